@@ -0,0 +1,44 @@
+//! Headless VM control for scripts: prints each command's result as JSON
+
+use quickemu_core::cli::{Cli, CliCommand, USAGE};
+use quickemu_core::models::config::AppConfig;
+use quickemu_core::VMManager;
+use serde_json::json;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if matches!(args.first().map(String::as_str), Some("-h" | "--help")) {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    let command = match CliCommand::parse(&args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let result = async {
+        let config = AppConfig::load()?;
+        let mut vm_manager = VMManager::new().await?;
+        vm_manager.set_shutdown_timeout(Duration::from_millis(config.shutdown_timeout_ms));
+        Cli::new(Arc::new(vm_manager), config).run(&command).await
+    }
+    .await;
+
+    match result {
+        Ok(value) => {
+            println!("{:#}", value);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("{:#}", json!({ "error": format!("{e:#}") }));
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,355 @@
+//! Headless control of VMs for scripting, with JSON output.
+//!
+//! Backs the `quickemu-manager-cli` binary; every command returns the JSON
+//! value the binary prints.
+
+use crate::models::config::AppConfig;
+use crate::models::{VMId, VMStatus, VMTemplate, VM};
+use crate::services::discovery::VMDiscovery;
+use crate::services::quickget::QuickgetService;
+use crate::services::vm_manager::{StopMode, VMManager};
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+pub const USAGE: &str = "\
+Usage: quickemu-manager-cli <command>
+
+Commands:
+  list                            List VMs in the configured directories
+  status <vm>                     Show a VM and whether it is running
+  start <vm>                      Start a VM
+  stop [--force] <vm>             Power a VM off, or kill it with --force
+  create <os> <release> [edition] [--dir <directory>]
+         [--rate-limit <rate>]    Create a VM with quickget, downloading at
+                                  most <rate> bytes per second (e.g. 2M)
+  systems                         List the systems quickget can create";
+
+/// A command given on the command line
+#[derive(Debug, Clone, PartialEq)]
+pub enum CliCommand {
+    List,
+    Status(String),
+    Start(String),
+    Stop {
+        vm: String,
+        force: bool,
+    },
+    Create {
+        template: VMTemplate,
+        directory: Option<PathBuf>,
+    },
+    Systems,
+}
+
+impl CliCommand {
+    /// Parse the arguments after the program name
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let (command, rest) = args
+            .split_first()
+            .ok_or_else(|| anyhow!("No command given"))?;
+        let mut flags = Vec::new();
+        let mut positional = Vec::new();
+        let mut rest = rest.iter();
+        while let Some(arg) = rest.next() {
+            match arg.as_str() {
+                "--force" => flags.push(("force", None)),
+                "--dir" | "--rate-limit" => {
+                    let value = rest
+                        .next()
+                        .ok_or_else(|| anyhow!("{} needs a value", arg))?;
+                    flags.push((&arg[2..], Some(value.clone())));
+                }
+                _ if arg.starts_with("--") => return Err(anyhow!("Unknown option {}", arg)),
+                _ => positional.push(arg.clone()),
+            }
+        }
+        let flag = |name: &str| {
+            flags
+                .iter()
+                .find(|(flag, _)| *flag == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        let command = match (command.as_str(), positional.as_slice()) {
+            ("list", []) => CliCommand::List,
+            ("systems", []) => CliCommand::Systems,
+            ("status", [vm]) => CliCommand::Status(vm.clone()),
+            ("start", [vm]) => CliCommand::Start(vm.clone()),
+            ("stop", [vm]) => CliCommand::Stop {
+                vm: vm.clone(),
+                force: flag("force").is_some(),
+            },
+            ("create", [os, version, edition @ ..]) if edition.len() <= 1 => CliCommand::Create {
+                template: VMTemplate {
+                    name: format!("{os}-{version}"),
+                    os: os.clone(),
+                    version: version.clone(),
+                    edition: edition.first().cloned(),
+                    ram: "4G".to_string(),
+                    disk_size: "64G".to_string(),
+                    cpu_cores: 2,
+                    download_rate_limit: flag("rate-limit").flatten(),
+                },
+                directory: flag("dir").flatten().map(PathBuf::from),
+            },
+            ("list" | "systems" | "status" | "start" | "stop" | "create", _) => {
+                return Err(anyhow!("Wrong arguments for {}", command))
+            }
+            _ => return Err(anyhow!("Unknown command {}", command)),
+        };
+        Ok(command)
+    }
+}
+
+/// Runs commands against the VMs in the app config's directories
+pub struct Cli {
+    vm_manager: Arc<VMManager>,
+    config: AppConfig,
+}
+
+impl Cli {
+    pub fn new(vm_manager: Arc<VMManager>, config: AppConfig) -> Self {
+        Self { vm_manager, config }
+    }
+
+    pub async fn run(&self, command: &CliCommand) -> Result<Value> {
+        match command {
+            CliCommand::List => self.list().await,
+            CliCommand::Status(vm) => self.status(vm).await,
+            CliCommand::Start(vm) => self.start(vm).await,
+            CliCommand::Stop { vm, force } => self.stop(vm, *force).await,
+            CliCommand::Create {
+                template,
+                directory,
+            } => self.create(template, directory.clone()).await,
+            CliCommand::Systems => self.systems().await,
+        }
+    }
+
+    /// Every VM found, sorted by id
+    pub async fn list(&self) -> Result<Value> {
+        let mut vms = self.discover().await?;
+        vms.sort_by(|a, b| a.id.0.cmp(&b.id.0));
+        Ok(Value::Array(vms.iter().map(vm_json).collect()))
+    }
+
+    pub async fn status(&self, vm: &str) -> Result<Value> {
+        Ok(vm_json(&self.find_vm(vm).await?))
+    }
+
+    /// Start a VM with the extra QEMU arguments the app keeps for it
+    pub async fn start(&self, vm: &str) -> Result<Value> {
+        let vm = self.find_vm(vm).await?;
+        let extra_args = self.config.get_extra_qemu_args(&vm.id);
+        self.vm_manager.start_vm_with_args(&vm, extra_args).await?;
+        self.status(&vm.id.0).await
+    }
+
+    /// Stop a VM, waiting up to the shutdown timeout before killing it
+    pub async fn stop(&self, vm: &str, force: bool) -> Result<Value> {
+        let vm = self.find_vm(vm).await?;
+        let mode = if force {
+            StopMode::Force
+        } else {
+            StopMode::GracefulThenForce(Duration::from_millis(self.config.shutdown_timeout_ms))
+        };
+        self.vm_manager.stop_vm(&vm.id, mode).await?;
+        self.status(&vm.id.0).await
+    }
+
+    /// Create a VM with quickget, in the primary VM directory unless another
+    /// is given
+    pub async fn create(&self, template: &VMTemplate, directory: Option<PathBuf>) -> Result<Value> {
+        let directory = directory.unwrap_or_else(|| self.config.get_primary_vm_directory());
+        std::fs::create_dir_all(&directory)?;
+        let config_path = self
+            .vm_manager
+            .create_vm_from_template(template, &directory)
+            .await?;
+        Ok(json!({ "config_path": config_path }))
+    }
+
+    /// The systems quickget can create, sorted by name
+    pub async fn systems(&self) -> Result<Value> {
+        let quickget_path = self
+            .vm_manager
+            .get_quickget_path()
+            .ok_or_else(|| anyhow!("quickget not available"))?;
+        let service = QuickgetService::new(quickget_path.to_path_buf())
+            .with_timeout(Duration::from_millis(self.config.quickget_timeout_ms));
+        let systems = service.get_supported_systems().await?;
+        Ok(serde_json::to_value(systems)?)
+    }
+
+    async fn discover(&self) -> Result<Vec<VM>> {
+        let (event_tx, _) = mpsc::unbounded_channel();
+        let mut discovery = VMDiscovery::with_vm_manager(event_tx, self.vm_manager.clone());
+        discovery.add_watch_directories(self.config.get_all_vm_directories().clone());
+        discovery.scan_all_directories().await
+    }
+
+    /// Find a VM by id or by its config file's path
+    async fn find_vm(&self, vm: &str) -> Result<VM> {
+        let vm_id = VMId(vm.to_string());
+        self.discover()
+            .await?
+            .into_iter()
+            .find(|found| found.id == vm_id || found.config_path == Path::new(vm))
+            .ok_or_else(|| anyhow!("No VM named {}", vm))
+    }
+}
+
+/// A VM as the CLI reports it
+pub fn vm_json(vm: &VM) -> Value {
+    let (status, pid) = match &vm.status {
+        VMStatus::Running { pid } => ("running", Some(*pid)),
+        VMStatus::Stopped => ("stopped", None),
+        VMStatus::Starting => ("starting", None),
+        VMStatus::Stopping => ("stopping", None),
+        VMStatus::Error(_) => ("error", None),
+    };
+    let error = match &vm.status {
+        VMStatus::Error(message) => Some(message.clone()),
+        _ => None,
+    };
+    json!({
+        "id": vm.id.0,
+        "name": vm.name,
+        "config_path": vm.config_path,
+        "status": status,
+        "pid": pid,
+        "error": error,
+        "guest_os": vm.config.guest_os,
+        "ram": vm.config.ram,
+        "cpu_cores": vm.config.cpu_cores,
+        "ssh_port": vm.config.ssh_port,
+        "tags": vm.config.tags,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn create_test_cli(temp_dir: &TempDir) -> Cli {
+        let vm_manager = VMManager::with_paths(
+            PathBuf::from("/usr/bin/echo"),
+            Some(PathBuf::from("/usr/bin/echo")),
+        );
+        let config = AppConfig {
+            vm_directories: vec![temp_dir.path().to_path_buf()],
+            ..AppConfig::default()
+        };
+        Cli::new(Arc::new(vm_manager), config)
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            CliCommand::parse(&args(&["list"])).unwrap(),
+            CliCommand::List
+        );
+        assert_eq!(
+            CliCommand::parse(&args(&["stop", "--force", "debian"])).unwrap(),
+            CliCommand::Stop {
+                vm: "debian".to_string(),
+                force: true
+            }
+        );
+        let CliCommand::Create {
+            template,
+            directory,
+        } = CliCommand::parse(&args(&["create", "ubuntu", "24.04"])).unwrap()
+        else {
+            panic!("expected a create command");
+        };
+        assert_eq!(template.os, "ubuntu");
+        assert_eq!(template.edition, None);
+        assert_eq!(template.download_rate_limit, None);
+        assert_eq!(directory, None);
+
+        let CliCommand::Create { template, .. } =
+            CliCommand::parse(&args(&["create", "debian", "12", "--rate-limit", "500K"])).unwrap()
+        else {
+            panic!("expected a create command");
+        };
+        assert_eq!(template.download_rate_limit.as_deref(), Some("500K"));
+
+        assert!(CliCommand::parse(&args(&[])).is_err());
+        assert!(CliCommand::parse(&args(&["status"])).is_err());
+        assert!(CliCommand::parse(&args(&["start", "a", "b"])).is_err());
+        assert!(CliCommand::parse(&args(&["create", "ubuntu", "--dir"])).is_err());
+        assert!(CliCommand::parse(&args(&["reboot", "a"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_reports_vms_as_json() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(
+            temp_dir.path().join("windows-11.conf"),
+            "guest_os=\"windows\"\nram=\"8G\"\ncpu_cores=4\ntags=\"work\"\n",
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.path().join("debian-12.conf"),
+            "guest_os=\"linux\"\nssh_port=22220\n",
+        )
+        .unwrap();
+
+        let cli = create_test_cli(&temp_dir);
+        let list = cli.run(&CliCommand::List).await.unwrap();
+
+        let vms = list.as_array().unwrap();
+        assert_eq!(vms.len(), 2);
+        assert_eq!(vms[0]["id"], "debian-12");
+        assert_eq!(vms[0]["ssh_port"], 22220);
+        assert_eq!(vms[1]["id"], "windows-11");
+        assert_eq!(vms[1]["status"], "stopped");
+        assert_eq!(vms[1]["pid"], Value::Null);
+        assert_eq!(vms[1]["guest_os"], "windows");
+        assert_eq!(vms[1]["ram"], "8G");
+        assert_eq!(vms[1]["cpu_cores"], 4);
+        assert_eq!(vms[1]["tags"], json!(["work"]));
+        assert_eq!(
+            vms[1]["config_path"],
+            json!(temp_dir.path().join("windows-11.conf"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_one_vm() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(
+            temp_dir.path().join("debian-12.conf"),
+            "guest_os=\"linux\"\n",
+        )
+        .unwrap();
+
+        let cli = create_test_cli(&temp_dir);
+        let status = cli
+            .run(&CliCommand::Status("debian-12".to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(status["id"], "debian-12");
+        assert_eq!(status["name"], "debian-12");
+        assert_eq!(status["status"], "stopped");
+        assert!(status.as_object().unwrap().contains_key("pid"));
+
+        let error = cli
+            .run(&CliCommand::Status("missing".to_string()))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("No VM named missing"));
+    }
+}
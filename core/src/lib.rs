@@ -1,3 +1,4 @@
+pub mod cli;
 pub mod models;
 pub mod services;
 
@@ -5,7 +6,21 @@ pub use models::*;
 pub use services::binary_discovery::BinaryDiscovery;
 pub use services::config_manager::ConfigManager;
 pub use services::discovery::{DiscoveryEvent, VMDiscovery};
+pub use services::disk_image::{parse_disk_size, QemuImg};
+#[cfg(target_os = "linux")]
+pub use services::gpu_passthrough::{GpuDevice, GpuPassthrough, PassthroughWarning};
+pub use services::hooks::HookKind;
+pub use services::notifier::{DesktopNotifier, NoopNotifier, Notification, Notifier};
 pub use services::parser::ConfigParser;
+pub use services::port_allocator::PortAllocator;
 pub use services::process_monitor::ProcessMonitor;
-pub use services::quickget::{OSInfo, QuickgetService};
-pub use services::vm_manager::VMManager;
+pub use services::quickget::{parse_download_rate, OSInfo, QuickgetService, QuickgetVersion};
+pub use services::resource_limits::SystemdRun;
+pub use services::status_events::{StatusEvents, VMStatusChanged};
+#[cfg(target_os = "linux")]
+pub use services::system_capabilities::{AccelerationStatus, SystemCapabilities};
+pub use services::vm_creation::{VmCreationEvent, VmCreationHandle};
+pub use services::vm_manager::{
+    validate_extra_qemu_args, ConsoleViewer, StopMode, StopProgress, VMManager,
+};
+pub use services::vm_registry::VmRegistry;
@@ -1,4 +1,6 @@
+use crate::models::VMId;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -9,6 +11,80 @@ pub struct AppConfig {
     pub auto_download_tools: bool,
     pub theme: Theme,
     pub update_interval_ms: u64,
+    /// Extra QEMU arguments per VM id, kept out of the quickemu .conf files
+    #[serde(default)]
+    pub vm_extra_qemu_args: HashMap<String, Vec<String>>,
+    /// Pause between starting autostart VMs at launch
+    #[serde(default = "default_autostart_delay_ms")]
+    pub autostart_delay_ms: u64,
+    /// How long a stopping VM gets to power off before it is killed
+    #[serde(default = "default_shutdown_timeout_ms")]
+    pub shutdown_timeout_ms: u64,
+    /// How long a quickget call may take before it's retried or given up on
+    #[serde(default = "default_quickget_timeout_ms")]
+    pub quickget_timeout_ms: u64,
+    /// Console window layout per VM id, kept out of the quickemu .conf files
+    #[serde(default)]
+    pub console_layouts: HashMap<String, ConsoleLayout>,
+}
+
+/// How a VM's console windows were placed, so they reopen the same way
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConsoleLayout {
+    /// One entry per guest display head that has had a window
+    #[serde(default)]
+    pub heads: Vec<HeadPlacement>,
+}
+
+/// Size and placement of the window showing one guest display head
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeadPlacement {
+    /// Index of the guest head
+    pub head: u32,
+    pub width: i32,
+    pub height: i32,
+    #[serde(default)]
+    pub maximized: bool,
+    #[serde(default)]
+    pub fullscreen: bool,
+    /// Connector of the physical monitor the window was on, e.g. `DP-1`
+    #[serde(default)]
+    pub monitor: Option<String>,
+}
+
+impl ConsoleLayout {
+    /// Placement saved for a guest head. Heads the VM didn't have last time
+    /// have none.
+    pub fn head(&self, head: u32) -> Option<&HeadPlacement> {
+        self.heads.iter().find(|placement| placement.head == head)
+    }
+
+    /// Record a head's placement, keeping those of heads the VM doesn't
+    /// have right now for when it has them again
+    pub fn set_head(&mut self, placement: HeadPlacement) {
+        self.heads.retain(|saved| saved.head != placement.head);
+        self.heads.push(placement);
+        self.heads.sort_by_key(|placement| placement.head);
+    }
+
+    /// The physical monitor to put a head back on, if it was on one that is
+    /// still among the `connected` ones
+    pub fn monitor_for_head(&self, head: u32, connected: &[&str]) -> Option<&str> {
+        let monitor = self.head(head)?.monitor.as_deref()?;
+        connected.contains(&monitor).then_some(monitor)
+    }
+}
+
+fn default_autostart_delay_ms() -> u64 {
+    crate::services::vm_manager::DEFAULT_AUTOSTART_DELAY.as_millis() as u64
+}
+
+fn default_shutdown_timeout_ms() -> u64 {
+    crate::services::vm_manager::DEFAULT_SHUTDOWN_TIMEOUT.as_millis() as u64
+}
+
+fn default_quickget_timeout_ms() -> u64 {
+    crate::services::quickget::DEFAULT_QUICKGET_TIMEOUT.as_millis() as u64
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -34,6 +110,11 @@ impl Default for AppConfig {
             auto_download_tools: true,
             theme: Theme::System,
             update_interval_ms: 1000,
+            vm_extra_qemu_args: HashMap::new(),
+            autostart_delay_ms: default_autostart_delay_ms(),
+            shutdown_timeout_ms: default_shutdown_timeout_ms(),
+            quickget_timeout_ms: default_quickget_timeout_ms(),
+            console_layouts: HashMap::new(),
         }
     }
 }
@@ -99,4 +180,91 @@ impl AppConfig {
     pub fn remove_vm_directory(&mut self, directory: &PathBuf) {
         self.vm_directories.retain(|d| d != directory);
     }
+
+    /// Get the extra QEMU arguments configured for a VM
+    pub fn get_extra_qemu_args(&self, vm_id: &VMId) -> &[String] {
+        self.vm_extra_qemu_args
+            .get(&vm_id.0)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Set the extra QEMU arguments for a VM (an empty list clears them)
+    pub fn set_extra_qemu_args(&mut self, vm_id: &VMId, args: Vec<String>) {
+        if args.is_empty() {
+            self.vm_extra_qemu_args.remove(&vm_id.0);
+        } else {
+            self.vm_extra_qemu_args.insert(vm_id.0.clone(), args);
+        }
+    }
+
+    /// Get the console layout saved for a VM
+    pub fn get_console_layout(&self, vm_id: &VMId) -> Option<&ConsoleLayout> {
+        self.console_layouts.get(&vm_id.0)
+    }
+
+    /// Save the console layout for a VM
+    pub fn set_console_layout(&mut self, vm_id: &VMId, layout: ConsoleLayout) {
+        self.console_layouts.insert(vm_id.0.clone(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placement(head: u32, monitor: Option<&str>) -> HeadPlacement {
+        HeadPlacement {
+            head,
+            width: 1280,
+            height: 800,
+            maximized: false,
+            fullscreen: head == 1,
+            monitor: monitor.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_console_layout_round_trip() {
+        let mut config = AppConfig::default();
+        let mut layout = ConsoleLayout::default();
+        layout.set_head(placement(1, Some("HDMI-A-1")));
+        layout.set_head(placement(0, Some("DP-1")));
+        layout.set_head(placement(0, None));
+        config.set_console_layout(&VMId("debian".to_string()), layout.clone());
+
+        let saved: AppConfig = toml::from_str(&toml::to_string_pretty(&config).unwrap()).unwrap();
+        assert_eq!(saved, config);
+        let saved = saved
+            .get_console_layout(&VMId("debian".to_string()))
+            .unwrap();
+        assert_eq!(
+            saved.heads,
+            vec![placement(0, None), placement(1, Some("HDMI-A-1"))]
+        );
+
+        // Configs from before layouts were saved still load
+        let old: AppConfig = toml::from_str(
+            "vm_directories = []\nauto_download_tools = true\ntheme = \"System\"\nupdate_interval_ms = 1000\n",
+        )
+        .unwrap();
+        assert!(old.console_layouts.is_empty());
+    }
+
+    #[test]
+    fn test_console_layout_follows_monitor_changes() {
+        let mut layout = ConsoleLayout::default();
+        layout.set_head(placement(0, Some("DP-1")));
+        layout.set_head(placement(1, Some("HDMI-A-1")));
+
+        assert_eq!(
+            layout.monitor_for_head(0, &["DP-1", "HDMI-A-1"]),
+            Some("DP-1")
+        );
+        // The second monitor was unplugged
+        assert_eq!(layout.monitor_for_head(1, &["DP-1"]), None);
+        // The guest gained a head
+        assert_eq!(layout.head(2), None);
+        assert_eq!(layout.monitor_for_head(2, &["DP-1"]), None);
+    }
 }
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::time::SystemTime;
 
@@ -22,6 +23,79 @@ pub enum DisplayProtocol {
     None,
 }
 
+/// Firmware a VM boots with, from the config's `boot`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Firmware {
+    Bios,
+    Uefi,
+}
+
+impl Firmware {
+    /// Value of quickemu's `boot` setting for this firmware
+    pub fn quickemu_value(self) -> &'static str {
+        match self {
+            Firmware::Bios => "legacy",
+            Firmware::Uefi => "efi",
+        }
+    }
+}
+
+/// Device a VM can boot from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BootDevice {
+    Disk,
+    Cdrom,
+    Network,
+}
+
+impl BootDevice {
+    /// Name used in the config's `boot_order`
+    pub fn config_name(self) -> &'static str {
+        match self {
+            BootDevice::Disk => "disk",
+            BootDevice::Cdrom => "cdrom",
+            BootDevice::Network => "network",
+        }
+    }
+
+    /// Drive letter for QEMU's `-boot order=`
+    pub fn qemu_drive(self) -> char {
+        match self {
+            BootDevice::Disk => 'c',
+            BootDevice::Cdrom => 'd',
+            BootDevice::Network => 'n',
+        }
+    }
+}
+
+/// A host directory shared with the guest over 9p, from a
+/// `share_TAG="/host/path"` config variable; a `:ro` suffix makes it
+/// read-only
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SharedFolder {
+    pub host_path: PathBuf,
+    /// Name the guest mounts the share by
+    pub tag: String,
+    #[serde(default)]
+    pub readonly: bool,
+}
+
+impl SharedFolder {
+    /// QEMU's `-virtfs` option for the share
+    pub fn qemu_virtfs(&self) -> String {
+        // QEMU options escape commas by doubling them
+        let path = self.host_path.to_string_lossy().replace(',', ",,");
+        let mut option = format!(
+            "local,path={path},mount_tag={},security_model=mapped-xattr",
+            self.tag
+        );
+        if self.readonly {
+            option.push_str(",readonly=on");
+        }
+        option
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VM {
     pub id: VMId,
@@ -42,6 +116,61 @@ pub struct VMConfig {
     pub disk_size: Option<String>,
     pub display: DisplayProtocol,
     pub ssh_port: Option<u16>,
+    /// Extra QEMU arguments from the config's `extra_args`
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+    /// Start this VM when the manager launches
+    #[serde(default)]
+    pub autostart: bool,
+    /// CPU time limit from the config's `cpu_quota_percent`, where 100 is
+    /// one full core
+    #[serde(default)]
+    pub cpu_quota_percent: Option<u32>,
+    /// Memory limit from the config's `mem_max`, in systemd's size format
+    /// (e.g. `4G`)
+    #[serde(default)]
+    pub mem_max: Option<String>,
+    /// Serve SPICE on a Unix socket instead of a TCP port, for consoles that
+    /// are only opened from this host (`spice_socket="on"`)
+    #[serde(default)]
+    pub spice_socket: bool,
+    /// SPICE ticket from the config's `spice_password`, for servers that
+    /// require one. quickemu itself serves SPICE without a ticket. VNC
+    /// consoles started here use it as their password
+    #[serde(default)]
+    pub spice_password: Option<String>,
+    /// Labels from the config's comma-separated `tags`, for grouping VMs
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Firmware from the config's `boot`; `None` leaves it to quickemu,
+    /// which defaults to UEFI
+    #[serde(default)]
+    pub firmware: Option<Firmware>,
+    /// Boot with UEFI secure boot (`secureboot="on"` or `boot="uefi-secure"`)
+    #[serde(default)]
+    pub secure_boot: bool,
+    /// Emulate a TPM 2.0 with swtpm (`tpm="on"`), as Windows 11 requires
+    #[serde(default)]
+    pub tpm: bool,
+    /// Devices to boot from, first to last, from the config's comma-separated
+    /// `boot_order`
+    #[serde(default)]
+    pub boot_order: Vec<BootDevice>,
+    /// Shell command run before quickemu starts, from the config's
+    /// `pre_start`; the VM isn't started if it fails
+    #[serde(default)]
+    pub pre_start: Option<String>,
+    /// Shell command run once the VM has stopped, from the config's
+    /// `post_stop`
+    #[serde(default)]
+    pub post_stop: Option<String>,
+    /// Environment for the hooks and quickemu, from `env_NAME="value"`
+    /// variables in the config
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Host directories shared with the guest, ordered by tag
+    #[serde(default)]
+    pub shared_folders: Vec<SharedFolder>,
     pub raw_config: String,
 }
 
@@ -54,6 +183,11 @@ pub struct VMTemplate {
     pub ram: String,
     pub disk_size: String,
     pub cpu_cores: u32,
+    /// Cap on quickget's download speed in bytes per second, with an
+    /// optional `K`, `M` or `G` suffix (e.g. `2M`); `None` downloads at
+    /// full speed
+    #[serde(default)]
+    pub download_rate_limit: Option<String>,
 }
 
 impl VM {
@@ -61,6 +195,10 @@ impl VM {
         matches!(self.status, VMStatus::Running { .. })
     }
 
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.config.tags.iter().any(|t| t == tag)
+    }
+
     pub fn get_display_url(&self) -> Option<String> {
         match &self.config.display {
             DisplayProtocol::Spice { port } => Some(format!("spice://localhost:{port}")),
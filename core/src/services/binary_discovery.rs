@@ -1,3 +1,5 @@
+#[cfg(target_os = "linux")]
+use crate::services::system_capabilities::{AccelerationStatus, SystemCapabilities};
 use anyhow::{anyhow, Result};
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -254,6 +256,12 @@ impl BinaryDiscovery {
         Ok(())
     }
 
+    /// Check whether VMs started with the discovered quickemu will get KVM acceleration
+    #[cfg(target_os = "linux")]
+    pub fn acceleration_status(&self) -> AccelerationStatus {
+        SystemCapabilities::new().acceleration_status()
+    }
+
     /// Get detailed discovery information for debugging
     pub fn discovery_info(&self) -> String {
         let mut info = String::new();
@@ -275,6 +283,9 @@ impl BinaryDiscovery {
             ));
         }
 
+        #[cfg(target_os = "linux")]
+        info.push_str(&SystemCapabilities::new().summary());
+
         info
     }
 }
@@ -345,6 +356,8 @@ mod tests {
         assert!(info.contains("quickget"));
         assert!(info.contains("✓"));
         assert!(info.contains("✗"));
+        #[cfg(target_os = "linux")]
+        assert!(info.contains("KVM"));
     }
 
     #[test]
@@ -1,4 +1,6 @@
-use crate::models::config::AppConfig;
+use crate::models::config::{AppConfig, ConsoleLayout};
+use crate::models::VMId;
+use crate::services::vm_manager::validate_extra_qemu_args;
 use anyhow::Result;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -61,6 +63,35 @@ impl ConfigManager {
         self.save().await
     }
 
+    /// Get the extra QEMU arguments configured for a VM
+    pub async fn get_extra_qemu_args(&self, vm_id: &VMId) -> Vec<String> {
+        self.config.read().await.get_extra_qemu_args(vm_id).to_vec()
+    }
+
+    /// Set the extra QEMU arguments for a VM after validating them
+    pub async fn set_extra_qemu_args(&self, vm_id: &VMId, args: Vec<String>) -> Result<()> {
+        validate_extra_qemu_args(&args)?;
+        {
+            let mut config = self.config.write().await;
+            config.set_extra_qemu_args(vm_id, args);
+        }
+        self.save().await
+    }
+
+    /// Get the console layout saved for a VM
+    pub async fn get_console_layout(&self, vm_id: &VMId) -> Option<ConsoleLayout> {
+        self.config.read().await.get_console_layout(vm_id).cloned()
+    }
+
+    /// Remember how a VM's console windows are laid out
+    pub async fn set_console_layout(&self, vm_id: &VMId, layout: ConsoleLayout) -> Result<()> {
+        {
+            let mut config = self.config.write().await;
+            config.set_console_layout(vm_id, layout);
+        }
+        self.save().await
+    }
+
     /// Update configuration settings
     pub async fn update_config<F>(&self, update_fn: F) -> Result<()>
     where
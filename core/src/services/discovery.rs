@@ -224,6 +224,17 @@ impl VMDiscovery {
         self.vms.read().await.get(id).cloned()
     }
 
+    /// Discovered VMs that have `tag`
+    pub async fn get_vms_with_tag(&self, tag: &str) -> Vec<VM> {
+        self.vms
+            .read()
+            .await
+            .values()
+            .filter(|vm| vm.has_tag(tag))
+            .cloned()
+            .collect()
+    }
+
     /// Add a directory to be watched for VM configs
     pub fn add_watch_directory(&mut self, directory: PathBuf) {
         if !self.watched_dirs.contains(&directory) {
@@ -0,0 +1,106 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Parse a disk size in the format quickemu and `qemu-img` use, e.g. `64G`.
+/// Suffixes are binary (`K` is 1024 bytes); a bare number is in bytes.
+pub fn parse_disk_size(size: &str) -> Result<u64> {
+    let size = size.trim().trim_matches('"');
+    let digits_end = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, suffix) = size.split_at(digits_end);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| anyhow!("Invalid disk size '{size}'"))?;
+
+    let shift = match suffix.to_ascii_uppercase().trim_end_matches('B') {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => bail!("Invalid disk size '{size}': unknown unit '{suffix}'"),
+    };
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| anyhow!("Disk size '{size}' is too large"))
+}
+
+/// Inspects and resizes disk images through `qemu-img`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QemuImg {
+    path: PathBuf,
+}
+
+impl QemuImg {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Find `qemu-img` on the PATH
+    pub fn detect() -> Option<Self> {
+        which::which("qemu-img").ok().map(Self::new)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Size of the disk as the guest sees it, in bytes
+    pub fn virtual_size(&self, image: &Path) -> Result<u64> {
+        let mut cmd = Command::new(&self.path);
+        cmd.args(["info", "--output=json"]).arg(image);
+        let output = self
+            .run(&mut cmd)
+            .with_context(|| format!("Failed to inspect {}", image.display()))?;
+        let info: serde_json::Value = serde_json::from_slice(&output)?;
+        info["virtual-size"]
+            .as_u64()
+            .ok_or_else(|| anyhow!("qemu-img didn't report a size for {}", image.display()))
+    }
+
+    /// Set the disk's virtual size. Shrinking drops whatever the guest kept
+    /// past the new end, so it has to be asked for with `shrink`.
+    pub fn resize(&self, image: &Path, size: u64, shrink: bool) -> Result<()> {
+        let mut cmd = Command::new(&self.path);
+        cmd.arg("resize");
+        if shrink {
+            cmd.arg("--shrink");
+        }
+        cmd.arg(image).arg(size.to_string());
+        self.run(&mut cmd)
+            .with_context(|| format!("Failed to resize {}", image.display()))?;
+        Ok(())
+    }
+
+    fn run(&self, cmd: &mut Command) -> Result<Vec<u8>> {
+        let output = cmd.output()?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(output.stdout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_disk_size() {
+        assert_eq!(parse_disk_size("512").unwrap(), 512);
+        assert_eq!(parse_disk_size("64K").unwrap(), 64 << 10);
+        assert_eq!(parse_disk_size("\"32G\"").unwrap(), 32 << 30);
+        assert_eq!(parse_disk_size("2t").unwrap(), 2 << 40);
+        assert_eq!(parse_disk_size("16GB").unwrap(), 16 << 30);
+    }
+
+    #[test]
+    fn test_parse_disk_size_rejects_garbage() {
+        assert!(parse_disk_size("").is_err());
+        assert!(parse_disk_size("G").is_err());
+        assert!(parse_disk_size("10X").is_err());
+        assert!(parse_disk_size("-5G").is_err());
+    }
+}
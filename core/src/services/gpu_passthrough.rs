@@ -0,0 +1,443 @@
+use crate::services::parser::ConfigParser;
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// PCI class code prefix for display controllers (VGA, 3D, ...)
+const DISPLAY_CLASS_PREFIX: &str = "0x03";
+
+/// A GPU found on the host that could be passed through to a VM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GpuDevice {
+    /// Full PCI address, e.g. `0000:01:00.0`
+    pub pci_address: String,
+    pub vendor_id: String,
+    pub device_id: String,
+    /// Kernel driver currently bound to the device
+    pub driver: Option<String>,
+    pub iommu_group: Option<u32>,
+    /// Whether the host firmware used this GPU to boot
+    pub boot_vga: bool,
+}
+
+impl GpuDevice {
+    pub fn is_bound_to_vfio(&self) -> bool {
+        self.driver.as_deref() == Some("vfio-pci")
+    }
+}
+
+/// Problems that may prevent GPU passthrough from working. These don't stop
+/// the configuration from being written; the UI shows them to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PassthroughWarning {
+    /// No IOMMU groups exist, so IOMMU is disabled in firmware or on the kernel command line
+    IommuDisabled,
+    /// The device is not bound to vfio-pci
+    NotBoundToVfio { driver: Option<String> },
+    /// Other devices share the IOMMU group and must be passed through (or unbound) too
+    SharedIommuGroup { devices: Vec<String> },
+    /// The GPU is the host's boot display, so its ROM may need to be supplied manually
+    BootGpu,
+}
+
+impl fmt::Display for PassthroughWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PassthroughWarning::IommuDisabled => write!(
+                f,
+                "IOMMU is not enabled; enable VT-d/AMD-Vi and add intel_iommu=on or amd_iommu=on to the kernel command line"
+            ),
+            PassthroughWarning::NotBoundToVfio { driver: Some(driver) } => write!(
+                f,
+                "Device is bound to '{driver}' instead of vfio-pci"
+            ),
+            PassthroughWarning::NotBoundToVfio { driver: None } => {
+                write!(f, "Device is not bound to vfio-pci")
+            }
+            PassthroughWarning::SharedIommuGroup { devices } => write!(
+                f,
+                "IOMMU group also contains {}; these devices must be passed through as well",
+                devices.join(", ")
+            ),
+            PassthroughWarning::BootGpu => write!(
+                f,
+                "This is the host's boot GPU; its ROM may be shadowed and need to be provided with romfile="
+            ),
+        }
+    }
+}
+
+/// Inspects host PCI devices through sysfs and writes passthrough settings
+/// into quickemu configs.
+pub struct GpuPassthrough {
+    sysfs_root: PathBuf,
+}
+
+impl Default for GpuPassthrough {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GpuPassthrough {
+    pub fn new() -> Self {
+        Self::with_sysfs_root(PathBuf::from("/sys"))
+    }
+
+    /// Use a different sysfs mount point (for tests)
+    pub fn with_sysfs_root(sysfs_root: PathBuf) -> Self {
+        Self { sysfs_root }
+    }
+
+    /// List the display controllers on the host
+    pub fn list_gpus(&self) -> Result<Vec<GpuDevice>> {
+        let mut gpus = Vec::new();
+
+        for entry in fs::read_dir(self.pci_devices_dir())? {
+            let address = entry?.file_name().to_string_lossy().to_string();
+            let class = self.read_attribute(&address, "class").unwrap_or_default();
+            if class.starts_with(DISPLAY_CLASS_PREFIX) {
+                gpus.push(self.get_device(&address)?);
+            }
+        }
+
+        gpus.sort_by(|a, b| a.pci_address.cmp(&b.pci_address));
+        Ok(gpus)
+    }
+
+    /// Look up a single PCI device by address (`01:00.0` or `0000:01:00.0`)
+    pub fn get_device(&self, pci_address: &str) -> Result<GpuDevice> {
+        let address = normalize_pci_address(pci_address)?;
+        let device_dir = self.pci_devices_dir().join(&address);
+        if !device_dir.exists() {
+            return Err(anyhow!("PCI device {} not found", address));
+        }
+
+        Ok(GpuDevice {
+            vendor_id: self.read_attribute(&address, "vendor").unwrap_or_default(),
+            device_id: self.read_attribute(&address, "device").unwrap_or_default(),
+            driver: link_name(&device_dir.join("driver")),
+            iommu_group: link_name(&device_dir.join("iommu_group")).and_then(|g| g.parse().ok()),
+            boot_vga: self.read_attribute(&address, "boot_vga").as_deref() == Some("1"),
+            pci_address: address,
+        })
+    }
+
+    /// Check a device for common passthrough problems
+    pub fn check_device(&self, device: &GpuDevice) -> Vec<PassthroughWarning> {
+        let mut warnings = Vec::new();
+
+        if !self.iommu_enabled() {
+            warnings.push(PassthroughWarning::IommuDisabled);
+        }
+
+        if !device.is_bound_to_vfio() {
+            warnings.push(PassthroughWarning::NotBoundToVfio {
+                driver: device.driver.clone(),
+            });
+        }
+
+        if let Some(group) = device.iommu_group {
+            let others: Vec<String> = self
+                .iommu_group_devices(group)
+                .into_iter()
+                .filter(|address| *address != device.pci_address)
+                .collect();
+            if !others.is_empty() {
+                warnings.push(PassthroughWarning::SharedIommuGroup { devices: others });
+            }
+        }
+
+        if device.boot_vga {
+            warnings.push(PassthroughWarning::BootGpu);
+        }
+
+        warnings
+    }
+
+    /// Add a vfio-pci device for the GPU to the config's `extra_args` and
+    /// return any warnings about the host setup.
+    pub fn configure(
+        &self,
+        config_path: &Path,
+        extra_args: &[String],
+        pci_address: &str,
+    ) -> Result<Vec<PassthroughWarning>> {
+        let device = self.get_device(pci_address)?;
+        let warnings = self.check_device(&device);
+
+        let device_arg = format!("vfio-pci,host={}", device.pci_address);
+        if !extra_args.contains(&device_arg) {
+            let mut args = extra_args.to_vec();
+            args.push("-device".to_string());
+            args.push(device_arg);
+            ConfigParser::set_variable(
+                config_path,
+                "extra_args",
+                &format!("\"{}\"", args.join(" ")),
+            )?;
+        }
+
+        for warning in &warnings {
+            println!(
+                "GPU passthrough warning for {}: {}",
+                device.pci_address, warning
+            );
+        }
+
+        Ok(warnings)
+    }
+
+    fn pci_devices_dir(&self) -> PathBuf {
+        self.sysfs_root.join("bus/pci/devices")
+    }
+
+    fn iommu_groups_dir(&self) -> PathBuf {
+        self.sysfs_root.join("kernel/iommu_groups")
+    }
+
+    fn iommu_enabled(&self) -> bool {
+        fs::read_dir(self.iommu_groups_dir())
+            .map(|mut entries| entries.next().is_some())
+            .unwrap_or(false)
+    }
+
+    fn iommu_group_devices(&self, group: u32) -> Vec<String> {
+        let devices_dir = self
+            .iommu_groups_dir()
+            .join(group.to_string())
+            .join("devices");
+        let mut devices: Vec<String> = fs::read_dir(devices_dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.file_name().to_string_lossy().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        devices.sort();
+        devices
+    }
+
+    fn read_attribute(&self, address: &str, attribute: &str) -> Option<String> {
+        fs::read_to_string(self.pci_devices_dir().join(address).join(attribute))
+            .ok()
+            .map(|value| value.trim().to_string())
+    }
+}
+
+/// Name of the file a sysfs symlink points to (e.g. the driver or group)
+fn link_name(path: &Path) -> Option<String> {
+    fs::read_link(path).ok().and_then(|target| {
+        target
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+    })
+}
+
+/// Turn `01:00.0` into `0000:01:00.0` and reject anything that isn't a PCI address
+pub fn normalize_pci_address(address: &str) -> Result<String> {
+    let address = address.trim().to_lowercase();
+    let full = if address.matches(':').count() == 1 {
+        format!("0000:{address}")
+    } else {
+        address
+    };
+
+    let valid = full.len() == 12
+        && full.char_indices().all(|(i, c)| match i {
+            4 | 7 => c == ':',
+            10 => c == '.',
+            11 => ('0'..='7').contains(&c),
+            _ => c.is_ascii_hexdigit(),
+        });
+
+    if valid {
+        Ok(full)
+    } else {
+        Err(anyhow!("Invalid PCI address '{}'", full))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    struct MockDevice<'a> {
+        address: &'a str,
+        class: &'a str,
+        driver: Option<&'a str>,
+        iommu_group: Option<u32>,
+        boot_vga: bool,
+    }
+
+    fn create_device(sysfs: &Path, device: &MockDevice) {
+        let dir = sysfs.join("bus/pci/devices").join(device.address);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("class"), format!("{}\n", device.class)).unwrap();
+        fs::write(dir.join("vendor"), "0x10de\n").unwrap();
+        fs::write(dir.join("device"), "0x1b80\n").unwrap();
+        fs::write(
+            dir.join("boot_vga"),
+            if device.boot_vga { "1\n" } else { "0\n" },
+        )
+        .unwrap();
+
+        if let Some(driver) = device.driver {
+            let driver_dir = sysfs.join("bus/pci/drivers").join(driver);
+            fs::create_dir_all(&driver_dir).unwrap();
+            symlink(&driver_dir, dir.join("driver")).unwrap();
+        }
+
+        if let Some(group) = device.iommu_group {
+            let group_dir = sysfs.join("kernel/iommu_groups").join(group.to_string());
+            fs::create_dir_all(group_dir.join("devices")).unwrap();
+            symlink(&group_dir, dir.join("iommu_group")).unwrap();
+            symlink(&dir, group_dir.join("devices").join(device.address)).unwrap();
+        }
+    }
+
+    /// A sysfs tree with the boot GPU and a vfio-bound GPU and its audio
+    /// function in one IOMMU group
+    pub(crate) fn mock_sysfs() -> TempDir {
+        let sysfs = TempDir::new().unwrap();
+        fs::create_dir_all(sysfs.path().join("bus/pci/devices")).unwrap();
+        create_device(
+            sysfs.path(),
+            &MockDevice {
+                address: "0000:00:02.0",
+                class: "0x030000",
+                driver: Some("i915"),
+                iommu_group: Some(0),
+                boot_vga: true,
+            },
+        );
+        create_device(
+            sysfs.path(),
+            &MockDevice {
+                address: "0000:01:00.0",
+                class: "0x030000",
+                driver: Some("vfio-pci"),
+                iommu_group: Some(1),
+                boot_vga: false,
+            },
+        );
+        create_device(
+            sysfs.path(),
+            &MockDevice {
+                address: "0000:01:00.1",
+                class: "0x040300",
+                driver: Some("vfio-pci"),
+                iommu_group: Some(1),
+                boot_vga: false,
+            },
+        );
+        sysfs
+    }
+
+    #[test]
+    fn test_normalize_pci_address() {
+        assert_eq!(normalize_pci_address("01:00.0").unwrap(), "0000:01:00.0");
+        assert_eq!(
+            normalize_pci_address("0000:0A:00.1").unwrap(),
+            "0000:0a:00.1"
+        );
+        assert!(normalize_pci_address("01:00").is_err());
+        assert!(normalize_pci_address("01:00.0;reboot").is_err());
+    }
+
+    #[test]
+    fn test_list_gpus() {
+        let sysfs = mock_sysfs();
+        let passthrough = GpuPassthrough::with_sysfs_root(sysfs.path().to_path_buf());
+
+        let gpus = passthrough.list_gpus().unwrap();
+        let addresses: Vec<&str> = gpus.iter().map(|g| g.pci_address.as_str()).collect();
+        assert_eq!(addresses, vec!["0000:00:02.0", "0000:01:00.0"]);
+
+        assert_eq!(gpus[0].driver.as_deref(), Some("i915"));
+        assert!(gpus[0].boot_vga);
+        assert!(gpus[1].is_bound_to_vfio());
+        assert_eq!(gpus[1].iommu_group, Some(1));
+    }
+
+    #[test]
+    fn test_check_device_warnings() {
+        let sysfs = mock_sysfs();
+        let passthrough = GpuPassthrough::with_sysfs_root(sysfs.path().to_path_buf());
+
+        let igpu = passthrough.get_device("00:02.0").unwrap();
+        assert_eq!(
+            passthrough.check_device(&igpu),
+            vec![
+                PassthroughWarning::NotBoundToVfio {
+                    driver: Some("i915".to_string())
+                },
+                PassthroughWarning::BootGpu,
+            ]
+        );
+
+        let dgpu = passthrough.get_device("01:00.0").unwrap();
+        assert_eq!(
+            passthrough.check_device(&dgpu),
+            vec![PassthroughWarning::SharedIommuGroup {
+                devices: vec!["0000:01:00.1".to_string()]
+            }]
+        );
+    }
+
+    #[test]
+    fn test_check_device_without_iommu() {
+        let sysfs = TempDir::new().unwrap();
+        create_device(
+            sysfs.path(),
+            &MockDevice {
+                address: "0000:01:00.0",
+                class: "0x030000",
+                driver: Some("vfio-pci"),
+                iommu_group: None,
+                boot_vga: false,
+            },
+        );
+        let passthrough = GpuPassthrough::with_sysfs_root(sysfs.path().to_path_buf());
+
+        let device = passthrough.get_device("01:00.0").unwrap();
+        assert_eq!(
+            passthrough.check_device(&device),
+            vec![PassthroughWarning::IommuDisabled]
+        );
+    }
+
+    #[test]
+    fn test_configure_writes_extra_args() -> Result<()> {
+        let sysfs = mock_sysfs();
+        let passthrough = GpuPassthrough::with_sysfs_root(sysfs.path().to_path_buf());
+
+        let config_dir = TempDir::new()?;
+        let config_path = config_dir.path().join("vm.conf");
+        fs::write(&config_path, "guest_os=\"windows\"\nextra_args=\"-m 8G\"\n")?;
+
+        let extra_args = vec!["-m".to_string(), "8G".to_string()];
+        passthrough.configure(&config_path, &extra_args, "01:00.0")?;
+
+        let config = ConfigParser::parse_quickemu_config(&config_path)?;
+        assert_eq!(
+            config.extra_args,
+            vec!["-m", "8G", "-device", "vfio-pci,host=0000:01:00.0"]
+        );
+
+        // Configuring the same device again doesn't duplicate it
+        passthrough.configure(&config_path, &config.extra_args, "0000:01:00.0")?;
+        let again = ConfigParser::parse_quickemu_config(&config_path)?;
+        assert_eq!(again.extra_args, config.extra_args);
+
+        assert!(passthrough
+            .configure(&config_path, &[], "0000:02:00.0")
+            .is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Variables passed through from the manager's own environment, so hooks can
+/// find their tools. Everything else is dropped.
+const INHERITED_ENV_VARS: &[&str] = &["PATH", "HOME", "USER", "LOGNAME", "LANG", "TMPDIR"];
+
+/// Variables that change how the shell or the dynamic linker behave, so a
+/// config can't set them
+const RESERVED_ENV_VARS: &[&str] = &[
+    "PATH",
+    "IFS",
+    "ENV",
+    "BASH_ENV",
+    "SHELLOPTS",
+    "BASHOPTS",
+    "PS4",
+    "PROMPT_COMMAND",
+];
+
+/// When a hook runs, relative to the VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookKind {
+    PreStart,
+    PostStop,
+}
+
+impl HookKind {
+    /// Name of the hook's config variable
+    pub fn config_name(&self) -> &'static str {
+        match self {
+            HookKind::PreStart => "pre_start",
+            HookKind::PostStop => "post_stop",
+        }
+    }
+}
+
+/// Check that a VM's environment can be handed to a hook or to quickemu.
+///
+/// Names must be plain shell identifiers and must not touch the loader or
+/// the shell's startup; values can't contain NUL or line breaks.
+pub fn validate_env(env: &BTreeMap<String, String>) -> Result<()> {
+    for (name, value) in env {
+        let mut chars = name.chars();
+        let valid_name = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_name {
+            bail!("Invalid environment variable name '{name}'");
+        }
+        if RESERVED_ENV_VARS.contains(&name.as_str())
+            || name.starts_with("LD_")
+            || name.starts_with("DYLD_")
+        {
+            bail!("Environment variable '{name}' can't be set from a VM config");
+        }
+        if value.contains(['\0', '\n', '\r']) {
+            bail!("Environment variable '{name}' contains a control character");
+        }
+    }
+    Ok(())
+}
+
+/// Run a hook command through the shell with only the VM's environment,
+/// appending what it prints to `log`.
+///
+/// The hook runs in `working_dir`, gets `QUICKEMU_VM` set to the VM's name
+/// and is killed if it takes longer than `timeout`. It fails if it exits
+/// with a non-zero status.
+pub async fn run_hook(
+    kind: HookKind,
+    command: &str,
+    vm_name: &str,
+    env: &BTreeMap<String, String>,
+    working_dir: &Path,
+    log: &Path,
+    timeout: Duration,
+) -> Result<()> {
+    validate_env(env)?;
+
+    let mut cmd = shell_command(command);
+    cmd.env_clear();
+    for name in INHERITED_ENV_VARS {
+        if let Some(value) = std::env::var_os(name) {
+            cmd.env(name, value);
+        }
+    }
+    cmd.envs(env)
+        .env("QUICKEMU_VM", vm_name)
+        .current_dir(working_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    let child = cmd
+        .spawn()
+        .with_context(|| format!("Failed to run {} hook", kind.config_name()))?;
+    let result = tokio::time::timeout(timeout, child.wait_with_output()).await;
+
+    let mut entry = format!("[{}] {}\n", kind.config_name(), command);
+    let outcome = match result {
+        Ok(Ok(output)) => {
+            entry.push_str(&String::from_utf8_lossy(&output.stdout));
+            entry.push_str(&String::from_utf8_lossy(&output.stderr));
+            if output.status.success() {
+                Ok(())
+            } else {
+                Err(anyhow!(
+                    "{} hook failed with {}",
+                    kind.config_name(),
+                    output.status
+                ))
+            }
+        }
+        Ok(Err(e)) => Err(anyhow!("{} hook failed: {}", kind.config_name(), e)),
+        Err(_) => Err(anyhow!(
+            "{} hook timed out after {:?}",
+            kind.config_name(),
+            timeout
+        )),
+    };
+    if !entry.ends_with('\n') {
+        entry.push('\n');
+    }
+    if let Err(e) = &outcome {
+        entry.push_str(&format!("[{}] {}\n", kind.config_name(), e));
+    }
+    append_log(log, &entry)
+        .with_context(|| format!("Failed to write hook output to {}", log.display()))?;
+
+    outcome
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+#[cfg(not(unix))]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+fn append_log(log: &Path, entry: &str) -> Result<()> {
+    if let Some(dir) = log.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)?;
+    file.write_all(entry.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn env(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_env() {
+        assert!(validate_env(&env(&[("BRIDGE", "br0"), ("_TAP_1", "")])).is_ok());
+        assert!(validate_env(&env(&[("1ABC", "x")])).is_err());
+        assert!(validate_env(&env(&[("A-B", "x")])).is_err());
+        assert!(validate_env(&env(&[("LD_PRELOAD", "/tmp/x.so")])).is_err());
+        assert!(validate_env(&env(&[("BASH_ENV", "/tmp/x")])).is_err());
+        assert!(validate_env(&env(&[("PATH", "/tmp")])).is_err());
+        assert!(validate_env(&env(&[("NAME", "a\nb")])).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_hook_sees_only_vm_env() {
+        let temp_dir = TempDir::new().unwrap();
+        let log = temp_dir.path().join("hooks.log");
+        std::env::set_var("QUICKEMU_HOOK_TEST_SECRET", "leaked");
+
+        run_hook(
+            HookKind::PreStart,
+            "echo \"$QUICKEMU_VM $BRIDGE [$QUICKEMU_HOOK_TEST_SECRET]\"",
+            "test-vm",
+            &env(&[("BRIDGE", "br0")]),
+            temp_dir.path(),
+            &log,
+            Duration::from_secs(10),
+        )
+        .await
+        .unwrap();
+
+        let output = std::fs::read_to_string(&log).unwrap();
+        assert!(output.contains("test-vm br0 []"), "{output}");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_hook_times_out() {
+        let temp_dir = TempDir::new().unwrap();
+        let log = temp_dir.path().join("hooks.log");
+
+        let result = run_hook(
+            HookKind::PostStop,
+            "sleep 10",
+            "test-vm",
+            &BTreeMap::new(),
+            temp_dir.path(),
+            &log,
+            Duration::from_millis(200),
+        )
+        .await;
+
+        assert!(result.unwrap_err().to_string().contains("timed out"));
+        let output = std::fs::read_to_string(&log).unwrap();
+        assert!(output.contains("[post_stop] sleep 10"));
+    }
+}
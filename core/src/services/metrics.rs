@@ -1,5 +1,7 @@
-use crate::models::{MetricsHistory, VMId, VMMetrics};
+use crate::models::{MetricsHistory, VMId, VMMetrics, VMStatus};
+use crate::services::vm_registry::VmRegistry;
 use std::collections::HashMap;
+use std::fmt::Write;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
@@ -41,4 +43,150 @@ impl MetricsService {
     pub async fn clear_all_histories(&self) {
         self.histories.write().await.clear();
     }
+
+    /// Render VM counts by status from `registry`, and the CPU and memory use
+    /// of all VMs from their latest samples, in the Prometheus text format
+    pub async fn prometheus_metrics(&self, registry: &VmRegistry) -> String {
+        let mut counts: [(&str, usize); 5] = [
+            ("running", 0),
+            ("starting", 0),
+            ("stopping", 0),
+            ("stopped", 0),
+            ("error", 0),
+        ];
+        for vm in registry.snapshot().await {
+            let index = match vm.status {
+                VMStatus::Running { .. } => 0,
+                VMStatus::Starting => 1,
+                VMStatus::Stopping => 2,
+                VMStatus::Stopped => 3,
+                VMStatus::Error(_) => 4,
+            };
+            counts[index].1 += 1;
+        }
+
+        let histories = self.histories.read().await;
+        let cpu_percent: f32 = histories.values().filter_map(|h| h.cpu.back()).sum();
+        let memory_percent: f32 = histories.values().filter_map(|h| h.memory.back()).sum();
+
+        let mut out = String::new();
+        out.push_str("# HELP quickemu_vms Number of known VMs by status\n");
+        out.push_str("# TYPE quickemu_vms gauge\n");
+        for (status, count) in counts {
+            let _ = writeln!(out, "quickemu_vms{{status=\"{status}\"}} {count}");
+        }
+        out.push_str("# HELP quickemu_vm_cpu_percent CPU use of all VMs, in percent of one core\n");
+        out.push_str("# TYPE quickemu_vm_cpu_percent gauge\n");
+        let _ = writeln!(out, "quickemu_vm_cpu_percent {cpu_percent}");
+        out.push_str("# HELP quickemu_vm_memory_percent Host memory used by all VMs, in percent\n");
+        out.push_str("# TYPE quickemu_vm_memory_percent gauge\n");
+        let _ = writeln!(out, "quickemu_vm_memory_percent {memory_percent}");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{DisplayProtocol, VMConfig, VM};
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use std::time::SystemTime;
+
+    fn test_vm(id: &str, status: VMStatus) -> VM {
+        VM {
+            id: VMId(id.to_string()),
+            name: id.to_string(),
+            config_path: PathBuf::from(format!("/tmp/{id}.conf")),
+            config: VMConfig {
+                guest_os: "linux".to_string(),
+                disk_img: None,
+                iso: None,
+                ram: "2G".to_string(),
+                cpu_cores: 2,
+                disk_size: None,
+                display: DisplayProtocol::Spice { port: 5930 },
+                ssh_port: None,
+                extra_args: Vec::new(),
+                autostart: false,
+                cpu_quota_percent: None,
+                mem_max: None,
+                spice_socket: false,
+                spice_password: None,
+                tags: Vec::new(),
+                firmware: None,
+                secure_boot: false,
+                tpm: false,
+                boot_order: Vec::new(),
+                pre_start: None,
+                post_stop: None,
+                env: BTreeMap::new(),
+                shared_folders: Vec::new(),
+                raw_config: String::new(),
+            },
+            status,
+            last_modified: SystemTime::now(),
+        }
+    }
+
+    fn sample(cpu_percent: f32, memory_percent: f32) -> VMMetrics {
+        VMMetrics {
+            cpu_percent,
+            memory_mb: 0,
+            memory_percent,
+            disk_read_bytes: 0,
+            disk_write_bytes: 0,
+            network_rx_bytes: 0,
+            network_tx_bytes: 0,
+        }
+    }
+
+    /// Parse the samples of a Prometheus text exposition, failing on any
+    /// malformed line
+    fn parse_samples(text: &str) -> HashMap<String, f64> {
+        text.lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let (name, value) = line.rsplit_once(' ').expect("sample without value");
+                (
+                    name.to_string(),
+                    value.parse().expect("invalid sample value"),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_prometheus_metrics_reflect_registry() {
+        let registry = VmRegistry::new();
+        registry
+            .upsert(test_vm("web", VMStatus::Running { pid: 100 }))
+            .await;
+        registry
+            .upsert(test_vm("db", VMStatus::Running { pid: 200 }))
+            .await;
+        registry.upsert(test_vm("old", VMStatus::Stopped)).await;
+
+        let metrics = MetricsService::new(10);
+        let web = VMId("web".to_string());
+        metrics
+            .record_metrics(web.clone(), sample(80.0, 10.0))
+            .await;
+        // Only the latest sample of a VM counts
+        metrics.record_metrics(web, sample(25.0, 12.5)).await;
+        metrics
+            .record_metrics(VMId("db".to_string()), sample(50.0, 20.0))
+            .await;
+
+        let samples = parse_samples(&metrics.prometheus_metrics(&registry).await);
+        assert_eq!(samples["quickemu_vms{status=\"running\"}"], 2.0);
+        assert_eq!(samples["quickemu_vms{status=\"stopped\"}"], 1.0);
+        assert_eq!(samples["quickemu_vms{status=\"error\"}"], 0.0);
+        assert_eq!(samples["quickemu_vm_cpu_percent"], 75.0);
+        assert_eq!(samples["quickemu_vm_memory_percent"], 32.5);
+
+        registry.remove(&VMId("old".to_string())).await;
+        let samples = parse_samples(&metrics.prometheus_metrics(&registry).await);
+        assert_eq!(samples["quickemu_vms{status=\"stopped\"}"], 0.0);
+    }
 }
@@ -1,9 +1,23 @@
 pub mod binary_discovery;
 pub mod config_manager;
 pub mod discovery;
+pub mod disk_image;
+#[cfg(target_os = "linux")]
+pub mod gpu_passthrough;
+pub mod hooks;
 pub mod metrics;
+pub mod notifier;
 pub mod parser;
+pub mod port_allocator;
 pub mod process_monitor;
+#[cfg(unix)]
+pub mod qmp;
 pub mod quickget;
+pub mod resource_limits;
+pub mod status_events;
+#[cfg(target_os = "linux")]
+pub mod system_capabilities;
+pub mod vm_creation;
 pub mod vm_manager;
+pub mod vm_registry;
 pub mod vnc_proxy;
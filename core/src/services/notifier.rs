@@ -0,0 +1,135 @@
+use crate::models::VMId;
+use std::path::PathBuf;
+use std::thread;
+
+/// Something worth telling the user about even when the app isn't in front
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    /// quickget created a VM; holds the path of the new config
+    VmCreated(PathBuf),
+    /// quickget failed to create a VM; holds the reason
+    VmCreationFailed(String),
+    /// A VM stopped without being asked to, e.g. because QEMU crashed
+    VmExitedUnexpectedly(VMId),
+}
+
+impl Notification {
+    /// Short title of the notification
+    pub fn summary(&self) -> &'static str {
+        match self {
+            Notification::VmCreated(_) => "VM created",
+            Notification::VmCreationFailed(_) => "VM creation failed",
+            Notification::VmExitedUnexpectedly(_) => "VM stopped unexpectedly",
+        }
+    }
+
+    /// Details shown below the summary
+    pub fn body(&self) -> String {
+        match self {
+            Notification::VmCreated(config_path) => {
+                let name = config_path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| config_path.display().to_string());
+                format!("{name} is ready to start")
+            }
+            Notification::VmCreationFailed(reason) => reason.clone(),
+            Notification::VmExitedUnexpectedly(vm_id) => {
+                format!("{} is no longer running", vm_id.0)
+            }
+        }
+    }
+}
+
+/// Delivers [`Notification`]s to the user.
+///
+/// Called from whichever thread noticed the event, so implementations
+/// shouldn't block for long.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, notification: &Notification);
+}
+
+/// Drops every notification; the default until a frontend sets another
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopNotifier;
+
+impl Notifier for NoopNotifier {
+    fn notify(&self, _notification: &Notification) {}
+}
+
+/// Shows notifications through the desktop's notification service
+#[derive(Debug, Clone)]
+pub struct DesktopNotifier {
+    app_name: String,
+}
+
+impl DesktopNotifier {
+    pub fn new(app_name: impl Into<String>) -> Self {
+        Self {
+            app_name: app_name.into(),
+        }
+    }
+}
+
+impl Notifier for DesktopNotifier {
+    fn notify(&self, notification: &Notification) {
+        let mut desktop_notification = notify_rust::Notification::new();
+        desktop_notification
+            .appname(&self.app_name)
+            .summary(notification.summary())
+            .body(&notification.body());
+
+        // Talking to the notification service can take a while, so don't
+        // hold up the caller
+        thread::spawn(move || {
+            if let Err(e) = desktop_notification.show() {
+                println!("Failed to show desktop notification: {e}");
+            }
+        });
+    }
+}
+
+/// Keeps every notification so tests can check what was sent
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct RecordingNotifier {
+    notifications: std::sync::Mutex<Vec<Notification>>,
+}
+
+#[cfg(test)]
+impl RecordingNotifier {
+    pub(crate) fn notifications(&self) -> Vec<Notification> {
+        self.notifications.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Notifier for RecordingNotifier {
+    fn notify(&self, notification: &Notification) {
+        self.notifications
+            .lock()
+            .unwrap()
+            .push(notification.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vm_created_names_the_vm() {
+        let notification = Notification::VmCreated(PathBuf::from("/vms/ubuntu-24.04.conf"));
+
+        assert_eq!(notification.summary(), "VM created");
+        assert_eq!(notification.body(), "ubuntu-24.04 is ready to start");
+    }
+
+    #[test]
+    fn test_unexpected_exit_names_the_vm() {
+        let notification = Notification::VmExitedUnexpectedly(VMId("windows-11".to_string()));
+
+        assert_eq!(notification.summary(), "VM stopped unexpectedly");
+        assert_eq!(notification.body(), "windows-11 is no longer running");
+    }
+}
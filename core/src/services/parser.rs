@@ -1,7 +1,44 @@
-use crate::models::{DisplayProtocol, VMConfig};
-use anyhow::Result;
-use std::collections::HashMap;
-use std::path::Path;
+use crate::models::{BootDevice, DisplayProtocol, Firmware, SharedFolder, VMConfig};
+use anyhow::{bail, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Prefix of the config variables that set the VM's environment
+const ENV_PREFIX: &str = "env_";
+
+/// Prefix of the config variables that share a host directory
+const SHARE_PREFIX: &str = "share_";
+
+/// Suffix of a shared directory's path that makes the share read-only
+const READONLY_SUFFIX: &str = ":ro";
+
+/// Variables paths in a config can use so the VM's files can be moved along
+/// with it. quickemu sources the config as bash, and the manager sets
+/// `VM_DIR` and `VM_NAME` in its environment.
+const VM_DIR_VAR: &str = "VM_DIR";
+const VM_NAME_VAR: &str = "VM_NAME";
+const HOME_VAR: &str = "HOME";
+
+/// Replace `${name}` and `$name` in `value` with `replacement`, as bash
+/// would for a variable set to it
+fn expand_var(value: &str, name: &str, replacement: &str) -> String {
+    let value = value.replace(&format!("${{{name}}}"), replacement);
+    let bare = format!("${name}");
+    let mut expanded = String::new();
+    let mut rest = value.as_str();
+    while let Some(pos) = rest.find(&bare) {
+        expanded.push_str(&rest[..pos]);
+        rest = &rest[pos + bare.len()..];
+        // `$VM_NAME_2` is a different variable
+        if rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+            expanded.push_str(&bare);
+        } else {
+            expanded.push_str(replacement);
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
 
 pub struct ConfigParser;
 
@@ -17,6 +54,21 @@ impl ConfigParser {
             disk_size: None,
             display: DisplayProtocol::Spice { port: 5930 },
             ssh_port: None,
+            extra_args: Vec::new(),
+            autostart: false,
+            cpu_quota_percent: None,
+            mem_max: None,
+            spice_socket: false,
+            spice_password: None,
+            tags: Vec::new(),
+            firmware: None,
+            secure_boot: false,
+            tpm: false,
+            boot_order: Vec::new(),
+            pre_start: None,
+            post_stop: None,
+            env: BTreeMap::new(),
+            shared_folders: Vec::new(),
             raw_config: content.clone(),
         };
 
@@ -27,11 +79,11 @@ impl ConfigParser {
         }
 
         if let Some(disk_img) = vars.get("disk_img") {
-            config.disk_img = Some(disk_img.trim_matches('"').into());
+            config.disk_img = Some(Self::expand_path(disk_img.trim_matches('"'), path));
         }
 
         if let Some(iso) = vars.get("iso") {
-            config.iso = Some(iso.trim_matches('"').into());
+            config.iso = Some(Self::expand_path(iso.trim_matches('"'), path));
         }
 
         if let Some(ram) = vars.get("ram") {
@@ -57,16 +109,132 @@ impl ConfigParser {
             };
         }
 
+        // Explicit console ports (assigned by the PortAllocator)
+        match &mut config.display {
+            DisplayProtocol::Spice { port } => {
+                if let Some(Ok(spice_port)) = vars.get("spice_port").map(|p| p.parse::<u16>()) {
+                    *port = spice_port;
+                }
+            }
+            DisplayProtocol::Vnc { port } => {
+                if let Some(Ok(vnc_port)) = vars.get("vnc_port").map(|p| p.parse::<u16>()) {
+                    *port = vnc_port;
+                }
+            }
+            _ => {}
+        }
+
         if let Some(ssh_port) = vars.get("ssh_port") {
             if let Ok(port) = ssh_port.parse::<u16>() {
                 config.ssh_port = Some(port);
             }
         }
 
+        if let Some(extra_args) = vars.get("extra_args") {
+            config.extra_args = extra_args
+                .trim_matches('"')
+                .split_whitespace()
+                .map(String::from)
+                .collect();
+        }
+
+        if let Some(autostart) = vars.get("autostart") {
+            config.autostart = Self::parse_bool(autostart);
+        }
+
+        if let Some(cpu_quota) = vars.get("cpu_quota_percent") {
+            config.cpu_quota_percent = cpu_quota
+                .trim_matches('"')
+                .parse::<u32>()
+                .ok()
+                .filter(|&percent| percent > 0);
+        }
+
+        if let Some(mem_max) = vars.get("mem_max") {
+            let mem_max = mem_max.trim_matches('"');
+            if !mem_max.is_empty() {
+                config.mem_max = Some(mem_max.to_string());
+            }
+        }
+
+        if let Some(spice_socket) = vars.get("spice_socket") {
+            config.spice_socket = Self::parse_bool(spice_socket);
+        }
+
+        if let Some(password) = vars.get("spice_password") {
+            let password = password.trim_matches('"');
+            if !password.is_empty() {
+                config.spice_password = Some(password.to_string());
+            }
+        }
+
+        if let Some(tags) = vars.get("tags") {
+            config.tags = Self::parse_tags(tags);
+        }
+
+        if let Some(secureboot) = vars.get("secureboot") {
+            config.secure_boot = Self::parse_bool(secureboot);
+        }
+
+        if let Some(tpm) = vars.get("tpm") {
+            config.tpm = Self::parse_bool(tpm);
+        }
+
+        // quickemu spells these `efi` and `legacy`; also accept the firmware names
+        if let Some(boot) = vars.get("boot") {
+            match boot.trim_matches('"').to_ascii_lowercase().as_str() {
+                "efi" | "uefi" => config.firmware = Some(Firmware::Uefi),
+                "uefi-secure" | "efi-secure" => {
+                    config.firmware = Some(Firmware::Uefi);
+                    config.secure_boot = true;
+                }
+                "legacy" | "bios" => config.firmware = Some(Firmware::Bios),
+                _ => {}
+            }
+        }
+
+        if let Some(boot_order) = vars.get("boot_order") {
+            config.boot_order = Self::parse_boot_order(boot_order);
+        }
+
+        if let Some(pre_start) = vars.get("pre_start") {
+            config.pre_start = Self::parse_command(pre_start);
+        }
+
+        if let Some(post_stop) = vars.get("post_stop") {
+            config.post_stop = Self::parse_command(post_stop);
+        }
+
+        for (key, value) in &vars {
+            if let Some(name) = key.strip_prefix(ENV_PREFIX) {
+                if !name.is_empty() {
+                    config
+                        .env
+                        .insert(name.to_string(), value.trim_matches('"').to_string());
+                }
+            }
+            if let Some(tag) = key.strip_prefix(SHARE_PREFIX) {
+                if !tag.is_empty() {
+                    let value = value.trim_matches('"');
+                    let (host_path, readonly) = match value.strip_suffix(READONLY_SUFFIX) {
+                        Some(host_path) => (host_path, true),
+                        None => (value, false),
+                    };
+                    config.shared_folders.push(SharedFolder {
+                        host_path: Self::expand_path(host_path, path),
+                        tag: tag.to_string(),
+                        readonly,
+                    });
+                }
+            }
+        }
+        config.shared_folders.sort_by(|a, b| a.tag.cmp(&b.tag));
+
         Ok(config)
     }
 
     pub fn save_config(path: &Path, config: &VMConfig) -> Result<()> {
+        let original = Self::extract_variables(&config.raw_config);
         let mut lines = Vec::new();
 
         // Basic config
@@ -75,11 +243,17 @@ impl ConfigParser {
         lines.push(format!("cpu_cores={}", config.cpu_cores));
 
         if let Some(disk_img) = &config.disk_img {
-            lines.push(format!("disk_img=\"{}\"", disk_img.display()));
+            lines.push(format!(
+                "disk_img=\"{}\"",
+                Self::path_value(&original, "disk_img", disk_img, path)
+            ));
         }
 
         if let Some(iso) = &config.iso {
-            lines.push(format!("iso=\"{}\"", iso.display()));
+            lines.push(format!(
+                "iso=\"{}\"",
+                Self::path_value(&original, "iso", iso, path)
+            ));
         }
 
         if let Some(disk_size) = &config.disk_size {
@@ -88,11 +262,13 @@ impl ConfigParser {
 
         // Display settings
         match &config.display {
-            DisplayProtocol::Spice { port: _ } => {
+            DisplayProtocol::Spice { port } => {
                 lines.push("display_server=\"spice\"".to_string());
+                lines.push(format!("spice_port={port}"));
             }
-            DisplayProtocol::Vnc { port: _ } => {
+            DisplayProtocol::Vnc { port } => {
                 lines.push("display_server=\"vnc\"".to_string());
+                lines.push(format!("vnc_port={port}"));
             }
             _ => {}
         }
@@ -101,12 +277,275 @@ impl ConfigParser {
             lines.push(format!("ssh_port={ssh_port}"));
         }
 
+        if !config.extra_args.is_empty() {
+            lines.push(format!("extra_args=\"{}\"", config.extra_args.join(" ")));
+        }
+
+        if config.autostart {
+            lines.push("autostart=\"on\"".to_string());
+        }
+
+        if let Some(cpu_quota) = config.cpu_quota_percent {
+            lines.push(format!("cpu_quota_percent={cpu_quota}"));
+        }
+
+        if let Some(mem_max) = &config.mem_max {
+            lines.push(format!("mem_max=\"{mem_max}\""));
+        }
+
+        if config.spice_socket {
+            lines.push("spice_socket=\"on\"".to_string());
+        }
+
+        if let Some(password) = &config.spice_password {
+            lines.push(format!("spice_password=\"{password}\""));
+        }
+
+        if !config.tags.is_empty() {
+            lines.push(format!("tags=\"{}\"", config.tags.join(",")));
+        }
+
+        if let Some(firmware) = config.firmware {
+            lines.push(format!("boot=\"{}\"", firmware.quickemu_value()));
+        }
+
+        if config.secure_boot {
+            lines.push("secureboot=\"on\"".to_string());
+        }
+
+        if config.tpm {
+            lines.push("tpm=\"on\"".to_string());
+        }
+
+        if !config.boot_order.is_empty() {
+            let devices: Vec<&str> = config.boot_order.iter().map(|d| d.config_name()).collect();
+            lines.push(format!("boot_order=\"{}\"", devices.join(",")));
+        }
+
+        if let Some(pre_start) = &config.pre_start {
+            lines.push(format!("pre_start=\"{pre_start}\""));
+        }
+
+        if let Some(post_stop) = &config.post_stop {
+            lines.push(format!("post_stop=\"{post_stop}\""));
+        }
+
+        for (name, value) in &config.env {
+            lines.push(format!("{ENV_PREFIX}{name}=\"{value}\""));
+        }
+
+        for share in &config.shared_folders {
+            let key = format!("{SHARE_PREFIX}{}", share.tag);
+            let suffix = if share.readonly { READONLY_SUFFIX } else { "" };
+            lines.push(format!(
+                "{key}=\"{}{suffix}\"",
+                Self::path_value(&original, &key, &share.host_path, path)
+            ));
+        }
+
         let content = lines.join("\n") + "\n";
         std::fs::write(path, content)?;
 
         Ok(())
     }
 
+    /// Set a single variable in a quickemu config file, keeping the rest of the
+    /// file untouched. The variable is appended if it isn't present yet.
+    pub fn set_variable(path: &Path, key: &str, value: &str) -> Result<()> {
+        let content = std::fs::read_to_string(path)?;
+        let mut found = false;
+
+        let mut lines: Vec<String> = content
+            .lines()
+            .map(|line| {
+                let trimmed = line.trim();
+                let is_key = !trimmed.starts_with('#')
+                    && trimmed
+                        .find('=')
+                        .is_some_and(|eq_pos| trimmed[..eq_pos].trim() == key);
+                if is_key {
+                    found = true;
+                    format!("{key}={value}")
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect();
+
+        if !found {
+            lines.push(format!("{key}={value}"));
+        }
+
+        std::fs::write(path, lines.join("\n") + "\n")?;
+        Ok(())
+    }
+
+    /// Persist whether the VM starts when the manager launches
+    pub fn set_autostart(path: &Path, autostart: bool) -> Result<()> {
+        let value = if autostart { "\"on\"" } else { "\"off\"" };
+        Self::set_variable(path, "autostart", value)
+    }
+
+    /// Replace the VM's tags, keeping the rest of the config untouched
+    pub fn set_tags(path: &Path, tags: &[String]) -> Result<()> {
+        for tag in tags {
+            Self::validate_tag(tag)?;
+        }
+        Self::set_variable(path, "tags", &format!("\"{}\"", tags.join(",")))
+    }
+
+    /// Add a tag to the VM; does nothing if it already has it
+    pub fn add_tag(path: &Path, tag: &str) -> Result<()> {
+        let tag = tag.trim();
+        Self::validate_tag(tag)?;
+        let mut tags = Self::parse_quickemu_config(path)?.tags;
+        if tags.iter().any(|t| t == tag) {
+            return Ok(());
+        }
+        tags.push(tag.to_string());
+        Self::set_tags(path, &tags)
+    }
+
+    /// Remove a tag from the VM; does nothing if it doesn't have it
+    pub fn remove_tag(path: &Path, tag: &str) -> Result<()> {
+        let tag = tag.trim();
+        let mut tags = Self::parse_quickemu_config(path)?.tags;
+        let count = tags.len();
+        tags.retain(|t| t != tag);
+        if tags.len() == count {
+            return Ok(());
+        }
+        Self::set_tags(path, &tags)
+    }
+
+    /// Write the firmware in quickemu's own `boot` and `secureboot` spelling,
+    /// keeping the rest of the config untouched
+    pub fn set_firmware(path: &Path, firmware: Option<Firmware>, secure_boot: bool) -> Result<()> {
+        if let Some(firmware) = firmware {
+            Self::set_variable(path, "boot", &format!("\"{}\"", firmware.quickemu_value()))?;
+        }
+        if secure_boot {
+            Self::set_variable(path, "secureboot", "\"on\"")?;
+        }
+        Ok(())
+    }
+
+    /// Turn the emulated TPM on or off, keeping the rest of the config
+    /// untouched
+    pub fn set_tpm(path: &Path, tpm: bool) -> Result<()> {
+        let value = if tpm { "\"on\"" } else { "\"off\"" };
+        Self::set_variable(path, "tpm", value)
+    }
+
+    fn validate_tag(tag: &str) -> Result<()> {
+        if tag.is_empty() {
+            bail!("Tag can't be empty");
+        }
+        if tag.contains([',', '"', '\n']) {
+            bail!("Tag '{tag}' can't contain commas, quotes or newlines");
+        }
+        Ok(())
+    }
+
+    /// Comma-separated tags, trimmed, without empty entries or duplicates
+    fn parse_tags(value: &str) -> Vec<String> {
+        let mut tags: Vec<String> = Vec::new();
+        for tag in value.trim_matches('"').split(',').map(str::trim) {
+            if !tag.is_empty() && !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_string());
+            }
+        }
+        tags
+    }
+
+    /// A hook command, or `None` if it's blank
+    fn parse_command(value: &str) -> Option<String> {
+        let command = value.trim_matches('"').trim();
+        (!command.is_empty()).then(|| command.to_string())
+    }
+
+    /// Comma-separated boot devices, skipping unknown names and duplicates
+    fn parse_boot_order(value: &str) -> Vec<BootDevice> {
+        let mut devices = Vec::new();
+        for name in value.trim_matches('"').split(',').map(str::trim) {
+            let device = match name.to_ascii_lowercase().as_str() {
+                "disk" | "hd" => BootDevice::Disk,
+                "cdrom" | "cd" => BootDevice::Cdrom,
+                "network" | "net" | "pxe" => BootDevice::Network,
+                _ => continue,
+            };
+            if !devices.contains(&device) {
+                devices.push(device);
+            }
+        }
+        devices
+    }
+
+    /// Directory of the VM whose config is at `config_path`, the value of
+    /// `${VM_DIR}`
+    pub fn vm_dir(config_path: &Path) -> PathBuf {
+        let dir = config_path.parent().unwrap_or(Path::new(""));
+        if dir.is_absolute() {
+            return dir.to_path_buf();
+        }
+        std::env::current_dir()
+            .map(|cwd| cwd.join(dir))
+            .unwrap_or_else(|_| dir.to_path_buf())
+    }
+
+    /// Name of the VM whose config is at `config_path`, the value of
+    /// `${VM_NAME}`
+    pub fn vm_name(config_path: &Path) -> String {
+        config_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Expand `${VM_DIR}`, `${VM_NAME}` and the home directory in a path
+    /// from the config at `config_path`. Anything else, relative paths
+    /// included, is left for quickemu, which runs in the config's directory.
+    fn expand_path(value: &str, config_path: &Path) -> PathBuf {
+        let vars = [
+            (VM_DIR_VAR, Some(Self::vm_dir(config_path))),
+            (VM_NAME_VAR, Some(PathBuf::from(Self::vm_name(config_path)))),
+            (HOME_VAR, dirs::home_dir()),
+        ];
+        let mut expanded = value.to_string();
+        for (name, replacement) in vars {
+            if let Some(replacement) = replacement {
+                expanded = expand_var(&expanded, name, &replacement.to_string_lossy());
+            }
+        }
+        PathBuf::from(expanded)
+    }
+
+    /// Text to write for a path the config at `config_path` set as `key`:
+    /// what the config had, if that still reads as `path`, so it's saved
+    /// exactly as written
+    fn path_value(
+        original: &HashMap<String, String>,
+        key: &str,
+        path: &Path,
+        config_path: &Path,
+    ) -> String {
+        let raw = original.get(key).map(|value| {
+            let value = value.trim_matches('"');
+            value.strip_suffix(READONLY_SUFFIX).unwrap_or(value)
+        });
+        match raw {
+            Some(raw) if Self::expand_path(raw, config_path) == path => raw.to_string(),
+            _ => path.display().to_string(),
+        }
+    }
+
+    fn parse_bool(value: &str) -> bool {
+        matches!(
+            value.trim_matches('"').to_ascii_lowercase().as_str(),
+            "on" | "true" | "yes" | "1"
+        )
+    }
+
     fn extract_variables(content: &str) -> HashMap<String, String> {
         let mut vars = HashMap::new();
 
@@ -243,6 +682,309 @@ cpu_cores=invalid
         Ok(())
     }
 
+    #[test]
+    fn test_parse_spice_port() -> Result<()> {
+        let content = r#"
+guest_os="ubuntu"
+display_server="spice"
+spice_port=5932
+        "#;
+
+        let temp_file = NamedTempFile::new()?;
+        fs::write(&temp_file, content)?;
+
+        let config = ConfigParser::parse_quickemu_config(temp_file.path())?;
+
+        assert_eq!(config.display, DisplayProtocol::Spice { port: 5932 });
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_variable() -> Result<()> {
+        let content = "# My VM\nguest_os=\"ubuntu\"\nspice_port=5930\n";
+
+        let temp_file = NamedTempFile::new()?;
+        fs::write(&temp_file, content)?;
+
+        ConfigParser::set_variable(temp_file.path(), "spice_port", "5931")?;
+        ConfigParser::set_variable(temp_file.path(), "ram", "\"4G\"")?;
+
+        let updated = fs::read_to_string(temp_file.path())?;
+        assert_eq!(
+            updated,
+            "# My VM\nguest_os=\"ubuntu\"\nspice_port=5931\nram=\"4G\"\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_extra_args() -> Result<()> {
+        let content = r#"
+guest_os="ubuntu"
+extra_args="-device virtio-rng-pci  -netdev user,id=net1"
+        "#;
+
+        let temp_file = NamedTempFile::new()?;
+        fs::write(&temp_file, content)?;
+
+        let config = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert_eq!(
+            config.extra_args,
+            vec!["-device", "virtio-rng-pci", "-netdev", "user,id=net1"]
+        );
+
+        ConfigParser::save_config(temp_file.path(), &config)?;
+        let saved = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert_eq!(saved.extra_args, config.extra_args);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_autostart_persisted() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        fs::write(&temp_file, "guest_os=\"ubuntu\"\n")?;
+
+        let config = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert!(!config.autostart);
+
+        ConfigParser::set_autostart(temp_file.path(), true)?;
+        let config = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert!(config.autostart);
+
+        ConfigParser::save_config(temp_file.path(), &config)?;
+        assert!(ConfigParser::parse_quickemu_config(temp_file.path())?.autostart);
+
+        ConfigParser::set_autostart(temp_file.path(), false)?;
+        assert!(!ConfigParser::parse_quickemu_config(temp_file.path())?.autostart);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_resource_limits() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        fs::write(
+            &temp_file,
+            "guest_os=\"ubuntu\"\ncpu_quota_percent=150\nmem_max=\"4G\"\n",
+        )?;
+
+        let config = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert_eq!(config.cpu_quota_percent, Some(150));
+        assert_eq!(config.mem_max.as_deref(), Some("4G"));
+
+        ConfigParser::save_config(temp_file.path(), &config)?;
+        let saved = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert_eq!(saved.cpu_quota_percent, Some(150));
+        assert_eq!(saved.mem_max.as_deref(), Some("4G"));
+
+        fs::write(&temp_file, "guest_os=\"ubuntu\"\ncpu_quota_percent=0\n")?;
+        let config = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert_eq!(config.cpu_quota_percent, None);
+        assert_eq!(config.mem_max, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tags_round_trip() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        fs::write(
+            &temp_file,
+            "guest_os=\"ubuntu\"\ntags=\"work, linux,,work\"\n",
+        )?;
+
+        let config = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert_eq!(config.tags, vec!["work", "linux"]);
+
+        ConfigParser::save_config(temp_file.path(), &config)?;
+        let saved = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert_eq!(saved.tags, config.tags);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_and_remove_tags() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        fs::write(&temp_file, "# My VM\nguest_os=\"ubuntu\"\nram=\"4G\"\n")?;
+
+        ConfigParser::add_tag(temp_file.path(), "work")?;
+        ConfigParser::add_tag(temp_file.path(), " dev ")?;
+        ConfigParser::add_tag(temp_file.path(), "work")?;
+        let config = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert_eq!(config.tags, vec!["work", "dev"]);
+        assert_eq!(config.ram, "4G");
+        assert!(fs::read_to_string(&temp_file)?.starts_with("# My VM\n"));
+
+        ConfigParser::remove_tag(temp_file.path(), "work")?;
+        ConfigParser::remove_tag(temp_file.path(), "missing")?;
+        let config = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert_eq!(config.tags, vec!["dev"]);
+
+        assert!(ConfigParser::add_tag(temp_file.path(), "a,b").is_err());
+        assert!(ConfigParser::add_tag(temp_file.path(), "  ").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hooks_and_env_round_trip() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        fs::write(
+            &temp_file,
+            "guest_os=\"ubuntu\"\npre_start=\"./tap-up.sh br0\"\npost_stop=\"\"\nenv_BRIDGE=\"br0\"\nenv_=\"x\"\n",
+        )?;
+
+        let config = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert_eq!(config.pre_start.as_deref(), Some("./tap-up.sh br0"));
+        assert_eq!(config.post_stop, None);
+        assert_eq!(config.env.len(), 1);
+        assert_eq!(config.env["BRIDGE"], "br0");
+
+        ConfigParser::save_config(temp_file.path(), &config)?;
+        let saved = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert_eq!(saved.pre_start, config.pre_start);
+        assert_eq!(saved.env, config.env);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_firmware_and_boot_order_round_trip() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        fs::write(
+            &temp_file,
+            "guest_os=\"windows\"\nboot=\"uefi-secure\"\nboot_order=\"cdrom, disk,floppy,cd\"\n",
+        )?;
+
+        let config = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert_eq!(config.firmware, Some(Firmware::Uefi));
+        assert!(config.secure_boot);
+        assert_eq!(config.boot_order, vec![BootDevice::Cdrom, BootDevice::Disk]);
+
+        ConfigParser::save_config(temp_file.path(), &config)?;
+        let saved = fs::read_to_string(temp_file.path())?;
+        assert!(saved.contains("boot=\"efi\"\n"));
+        assert!(saved.contains("secureboot=\"on\"\n"));
+        let saved = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert_eq!(saved.firmware, config.firmware);
+        assert!(saved.secure_boot);
+        assert_eq!(saved.boot_order, config.boot_order);
+
+        fs::write(&temp_file, "guest_os=\"debian\"\nboot=\"legacy\"\n")?;
+        let config = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert_eq!(config.firmware, Some(Firmware::Bios));
+        assert!(!config.secure_boot);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_folders_round_trip() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        fs::write(
+            &temp_file,
+            "guest_os=\"linux\"\nshare_src=\"/srv/src\"\nshare_docs=\"/srv/docs:ro\"\n",
+        )?;
+
+        let config = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert_eq!(
+            config.shared_folders,
+            vec![
+                SharedFolder {
+                    host_path: "/srv/docs".into(),
+                    tag: "docs".to_string(),
+                    readonly: true,
+                },
+                SharedFolder {
+                    host_path: "/srv/src".into(),
+                    tag: "src".to_string(),
+                    readonly: false,
+                },
+            ]
+        );
+
+        ConfigParser::save_config(temp_file.path(), &config)?;
+        let saved = ConfigParser::parse_quickemu_config(temp_file.path())?;
+        assert_eq!(saved.shared_folders, config.shared_folders);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_paths_are_saved_as_written() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let config_path = temp_dir.path().join("debian-12.conf");
+        let content = "guest_os=\"linux\"\n\
+            disk_img=\"debian-12/disk.qcow2\"\n\
+            iso=\"${HOME}/isos/debian.iso\"\n\
+            share_data=\"$HOME/data:ro\"\n";
+        fs::write(&config_path, content)?;
+
+        let mut config = ConfigParser::parse_quickemu_config(&config_path)?;
+        assert_eq!(config.disk_img, Some(PathBuf::from("debian-12/disk.qcow2")));
+        if let Some(home) = dirs::home_dir() {
+            assert_eq!(config.iso, Some(home.join("isos").join("debian.iso")));
+            assert_eq!(config.shared_folders[0].host_path, home.join("data"));
+        }
+
+        ConfigParser::save_config(&config_path, &config)?;
+        let saved = fs::read_to_string(&config_path)?;
+        assert!(
+            saved.contains("disk_img=\"debian-12/disk.qcow2\"\n"),
+            "{saved}"
+        );
+        assert!(
+            saved.contains("iso=\"${HOME}/isos/debian.iso\"\n"),
+            "{saved}"
+        );
+        assert!(saved.contains("share_data=\"$HOME/data:ro\"\n"), "{saved}");
+
+        // A changed path is written as it is now
+        config.raw_config = saved;
+        config.iso = Some(PathBuf::from("/srv/isos/debian.iso"));
+        ConfigParser::save_config(&config_path, &config)?;
+        let saved = fs::read_to_string(&config_path)?;
+        assert!(saved.contains("iso=\"/srv/isos/debian.iso\"\n"), "{saved}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_path_placeholders() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let config_path = temp_dir.path().join("debian-12.conf");
+        let content = "guest_os=\"linux\"\n\
+            disk_img=\"${VM_DIR}/disk.qcow2\"\n\
+            iso=\"$VM_DIR/${VM_NAME}.iso\"\n\
+            share_data=\"${VM_DIR}/$VM_NAME_data\"\n";
+        fs::write(&config_path, content)?;
+
+        let config = ConfigParser::parse_quickemu_config(&config_path)?;
+        assert_eq!(config.disk_img, Some(temp_dir.path().join("disk.qcow2")));
+        assert_eq!(config.iso, Some(temp_dir.path().join("debian-12.iso")));
+        assert_eq!(
+            config.shared_folders[0].host_path,
+            temp_dir.path().join("$VM_NAME_data")
+        );
+
+        ConfigParser::save_config(&config_path, &config)?;
+        let saved = fs::read_to_string(&config_path)?;
+        assert!(
+            saved.contains("disk_img=\"${VM_DIR}/disk.qcow2\"\n"),
+            "{saved}"
+        );
+        assert!(
+            saved.contains("iso=\"$VM_DIR/${VM_NAME}.iso\"\n"),
+            "{saved}"
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_parse_nonexistent_file() {
         let result = ConfigParser::parse_quickemu_config(Path::new("/nonexistent/file.conf"));
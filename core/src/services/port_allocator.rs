@@ -0,0 +1,250 @@
+use crate::models::VMId;
+use crate::services::vnc_proxy::ConsoleProtocol;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Ports QEMU uses for VNC displays (:0 - :29)
+pub const VNC_PORT_RANGE: RangeInclusive<u16> = 5900..=5929;
+/// Ports quickemu uses for SPICE servers
+pub const SPICE_PORT_RANGE: RangeInclusive<u16> = 5930..=5999;
+
+/// Hands out console ports to VMs so that VMs running side by side never
+/// end up with the same SPICE/VNC port.
+#[derive(Clone)]
+pub struct PortAllocator {
+    vnc_range: RangeInclusive<u16>,
+    spice_range: RangeInclusive<u16>,
+    allocations: Arc<RwLock<HashMap<VMId, (u16, ConsoleProtocol)>>>,
+}
+
+impl Default for PortAllocator {
+    fn default() -> Self {
+        Self::with_ranges(VNC_PORT_RANGE, SPICE_PORT_RANGE)
+    }
+}
+
+impl PortAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_ranges(vnc_range: RangeInclusive<u16>, spice_range: RangeInclusive<u16>) -> Self {
+        Self {
+            vnc_range,
+            spice_range,
+            allocations: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Allocate a console port for a VM.
+    ///
+    /// A VM that already holds a port keeps it. Otherwise `preferred` (usually
+    /// the port from the VM config) is used when it lies in the protocol's range
+    /// and is free, falling back to the first free port in the range.
+    pub async fn allocate(
+        &self,
+        vm_id: &VMId,
+        protocol: ConsoleProtocol,
+        preferred: Option<u16>,
+    ) -> Result<u16> {
+        let range = self.range_for(protocol);
+        let mut allocations = self.allocations.write().await;
+
+        if let Some(&(port, allocated_protocol)) = allocations.get(vm_id) {
+            if allocated_protocol == protocol {
+                return Ok(port);
+            }
+            allocations.remove(vm_id);
+        }
+
+        let is_free = |port: u16| {
+            !allocations.values().any(|&(p, _)| p == port) && Self::is_port_available(port)
+        };
+
+        let port = preferred
+            .filter(|port| range.contains(port) && is_free(*port))
+            .or_else(|| range.clone().find(|port| is_free(*port)))
+            .ok_or_else(|| {
+                anyhow!(
+                    "No free {:?} port available in range {}-{}",
+                    protocol,
+                    range.start(),
+                    range.end()
+                )
+            })?;
+
+        println!(
+            "PortAllocator: Assigned {:?} port {} to VM '{}'",
+            protocol, port, vm_id.0
+        );
+        allocations.insert(vm_id.clone(), (port, protocol));
+        Ok(port)
+    }
+
+    /// Record the port of a VM that is already running, e.g. one started
+    /// before this process, so it isn't handed out to another VM
+    pub async fn register(&self, vm_id: &VMId, protocol: ConsoleProtocol, port: u16) {
+        println!(
+            "PortAllocator: Registered {:?} port {} of running VM '{}'",
+            protocol, port, vm_id.0
+        );
+        self.allocations
+            .write()
+            .await
+            .insert(vm_id.clone(), (port, protocol));
+    }
+
+    /// Release the port held by a VM so it can be handed out again
+    pub async fn release(&self, vm_id: &VMId) -> Option<u16> {
+        let port = self
+            .allocations
+            .write()
+            .await
+            .remove(vm_id)
+            .map(|(port, _)| port);
+        if let Some(port) = port {
+            println!(
+                "PortAllocator: Released port {} from VM '{}'",
+                port, vm_id.0
+            );
+        }
+        port
+    }
+
+    /// Get the port currently allocated to a VM
+    pub async fn get_port(&self, vm_id: &VMId) -> Option<u16> {
+        self.get_allocation(vm_id).await.map(|(port, _)| port)
+    }
+
+    /// Get the port and protocol currently allocated to a VM
+    pub async fn get_allocation(&self, vm_id: &VMId) -> Option<(u16, ConsoleProtocol)> {
+        self.allocations.read().await.get(vm_id).copied()
+    }
+
+    fn range_for(&self, protocol: ConsoleProtocol) -> &RangeInclusive<u16> {
+        match protocol {
+            ConsoleProtocol::Vnc => &self.vnc_range,
+            ConsoleProtocol::Spice => &self.spice_range,
+        }
+    }
+
+    /// Check that nothing else on the host is listening on the port
+    fn is_port_available(port: u16) -> bool {
+        TcpListener::bind(("127.0.0.1", port)).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_allocator() -> PortAllocator {
+        PortAllocator::with_ranges(45900..=45909, 45930..=45939)
+    }
+
+    #[tokio::test]
+    async fn test_allocate_distinct_ports() {
+        let allocator = test_allocator();
+
+        let mut ports = Vec::new();
+        for i in 0..5 {
+            let vm_id = VMId(format!("vm-{i}"));
+            let port = allocator
+                .allocate(&vm_id, ConsoleProtocol::Spice, None)
+                .await
+                .unwrap();
+            assert!((45930..=45939).contains(&port));
+            ports.push(port);
+        }
+
+        let mut unique = ports.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), ports.len());
+    }
+
+    #[tokio::test]
+    async fn test_allocate_is_stable_per_vm() {
+        let allocator = test_allocator();
+        let vm_id = VMId("vm".to_string());
+
+        let first = allocator
+            .allocate(&vm_id, ConsoleProtocol::Vnc, None)
+            .await
+            .unwrap();
+        let second = allocator
+            .allocate(&vm_id, ConsoleProtocol::Vnc, None)
+            .await
+            .unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[tokio::test]
+    async fn test_preferred_port_not_shared() {
+        let allocator = test_allocator();
+        let vm_a = VMId("vm-a".to_string());
+        let vm_b = VMId("vm-b".to_string());
+
+        let port_a = allocator
+            .allocate(&vm_a, ConsoleProtocol::Spice, Some(45935))
+            .await
+            .unwrap();
+        let port_b = allocator
+            .allocate(&vm_b, ConsoleProtocol::Spice, Some(45935))
+            .await
+            .unwrap();
+
+        assert_eq!(port_a, 45935);
+        assert_ne!(port_b, 45935);
+    }
+
+    #[tokio::test]
+    async fn test_registered_port_not_shared() {
+        let allocator = test_allocator();
+        let running = VMId("running".to_string());
+        let vm = VMId("vm".to_string());
+
+        allocator
+            .register(&running, ConsoleProtocol::Vnc, 45903)
+            .await;
+        let port = allocator
+            .allocate(&vm, ConsoleProtocol::Vnc, Some(45903))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            allocator.get_allocation(&running).await,
+            Some((45903, ConsoleProtocol::Vnc))
+        );
+        assert_ne!(port, 45903);
+    }
+
+    #[tokio::test]
+    async fn test_release_makes_port_reusable() {
+        let allocator = PortAllocator::with_ranges(45900..=45900, 45930..=45930);
+        let vm_a = VMId("vm-a".to_string());
+        let vm_b = VMId("vm-b".to_string());
+
+        let port = allocator
+            .allocate(&vm_a, ConsoleProtocol::Spice, None)
+            .await
+            .unwrap();
+        assert!(allocator
+            .allocate(&vm_b, ConsoleProtocol::Spice, None)
+            .await
+            .is_err());
+
+        assert_eq!(allocator.release(&vm_a).await, Some(port));
+        assert_eq!(allocator.get_port(&vm_a).await, None);
+
+        let reused = allocator
+            .allocate(&vm_b, ConsoleProtocol::Spice, None)
+            .await
+            .unwrap();
+        assert_eq!(reused, port);
+    }
+}
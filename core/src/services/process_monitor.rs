@@ -1,23 +1,47 @@
-use crate::models::{VMId, VMMetrics};
+use crate::models::{VMId, VMMetrics, VMStatus};
+use crate::services::status_events::{StatusEvents, VMStatusChanged};
 use std::collections::HashMap;
-use std::sync::Arc;
-use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
-use tokio::sync::RwLock;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use sysinfo::{Pid, ProcessRefreshKind, ProcessStatus, ProcessesToUpdate, System};
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
 
 pub struct ProcessMonitor {
+    sampler: Sampler,
+    sampling_task: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// State shared between the monitor and its background sampling task
+#[derive(Clone)]
+struct Sampler {
     system: Arc<RwLock<System>>,
     vm_processes: Arc<RwLock<HashMap<VMId, u32>>>,
+    samples: Arc<RwLock<HashMap<VMId, VMMetrics>>>,
+    /// Told when a registered VM starts or its process exits
+    status_events: StatusEvents,
 }
 
 impl Default for ProcessMonitor {
     fn default() -> Self {
         Self {
-            system: Arc::new(RwLock::new(System::new_all())),
-            vm_processes: Arc::new(RwLock::new(HashMap::new())),
+            sampler: Sampler {
+                system: Arc::new(RwLock::new(System::new_all())),
+                vm_processes: Arc::new(RwLock::new(HashMap::new())),
+                samples: Arc::new(RwLock::new(HashMap::new())),
+                status_events: StatusEvents::new(),
+            },
+            sampling_task: Mutex::new(None),
         }
     }
 }
 
+impl Drop for ProcessMonitor {
+    fn drop(&mut self) {
+        self.stop_sampling();
+    }
+}
+
 impl ProcessMonitor {
     pub fn new() -> Self {
         Self::default()
@@ -25,31 +49,140 @@ impl ProcessMonitor {
 
     pub async fn register_vm_process(&self, vm_id: VMId, pid: u32) {
         println!("ProcessMonitor: Registering VM '{}' with PID {}", vm_id.0, pid);
-        self.vm_processes.write().await.insert(vm_id, pid);
+        self.sampler
+            .status_events
+            .record(&vm_id, VMStatus::Running { pid })
+            .await;
+        self.sampler.vm_processes.write().await.insert(vm_id, pid);
     }
 
+    /// Stop watching the VM's process. Says nothing about its status, since
+    /// the process may still be shutting down.
     pub async fn unregister_vm_process(&self, vm_id: &VMId) {
-        self.vm_processes.write().await.remove(vm_id);
+        self.sampler.vm_processes.write().await.remove(vm_id);
+        self.sampler.samples.write().await.remove(vm_id);
+    }
+
+    /// Status changes of registered VMs: one when a VM is registered and one
+    /// when a sample finds its process gone
+    pub fn subscribe_status_changes(&self) -> broadcast::Receiver<VMStatusChanged> {
+        self.sampler.status_events.subscribe()
+    }
+
+    /// Where status changes are recorded, for sharing with a `VMManager`
+    pub fn status_events(&self) -> &StatusEvents {
+        &self.sampler.status_events
+    }
+
+    /// Sample all registered VMs every `interval` on a background task, so
+    /// metric reads never wait on a process table refresh. Restarts the
+    /// task if it is already running. Must be called within a Tokio runtime.
+    pub fn start_sampling(&self, interval: Duration) {
+        let sampler = self.sampler.clone();
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                sampler.sample().await;
+            }
+        });
+
+        if let Some(previous) = self.sampling_task.lock().unwrap().replace(task) {
+            previous.abort();
+        }
+    }
+
+    /// Stop the background task started by `start_sampling`. The last
+    /// sample stays cached.
+    pub fn stop_sampling(&self) {
+        if let Some(task) = self.sampling_task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+
+    pub fn is_sampling(&self) -> bool {
+        self.sampling_task
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|task| !task.is_finished())
     }
 
+    /// Take a fresh sample now, independent of the background task
     pub async fn update_metrics(&self) {
+        self.sampler.sample().await;
+    }
+
+    /// Latest cached sample for the VM; never refreshes the process table
+    pub async fn get_vm_metrics(&self, vm_id: &VMId) -> Option<VMMetrics> {
+        self.sampler.samples.read().await.get(vm_id).cloned()
+    }
+
+    pub async fn get_all_metrics(&self) -> HashMap<VMId, VMMetrics> {
+        self.sampler.samples.read().await.clone()
+    }
+
+    pub async fn get_system_metrics(&self) -> (f32, f32) {
+        let system = self.sampler.system.read().await;
+        let cpu_usage = system.global_cpu_usage();
+        let memory_usage = (system.used_memory() as f32 / system.total_memory() as f32) * 100.0;
+
+        (cpu_usage, memory_usage)
+    }
+
+    pub async fn cleanup_stale_processes(&self) {
+        let system = self.sampler.system.read().await;
+        let mut vm_processes = self.sampler.vm_processes.write().await;
+
+        vm_processes.retain(|_, &mut pid| system.process(Pid::from(pid as usize)).is_some());
+        self.sampler
+            .samples
+            .write()
+            .await
+            .retain(|vm_id, _| vm_processes.contains_key(vm_id));
+    }
+}
+
+impl Sampler {
+    async fn sample(&self) {
         let mut system = self.system.write().await;
+        // Drop exited processes, so an exit is noticed below
         system.refresh_processes_specifics(
             ProcessesToUpdate::All,
-            false,
+            true,
             ProcessRefreshKind::everything(),
         );
         system.refresh_memory();
         system.refresh_cpu_usage();
-    }
 
-    pub async fn get_vm_metrics(&self, vm_id: &VMId) -> Option<VMMetrics> {
-        let processes = self.vm_processes.read().await;
-        let pid = processes.get(vm_id)?;
-        println!("ProcessMonitor: Getting metrics for VM '{}' with PID {}", vm_id.0, pid);
+        let vm_processes = self.vm_processes.read().await.clone();
+        let samples = vm_processes
+            .iter()
+            .filter_map(|(vm_id, pid)| {
+                Self::process_metrics(&system, vm_id, *pid).map(|metrics| (vm_id.clone(), metrics))
+            })
+            .collect();
+        *self.samples.write().await = samples;
+
+        let exited: Vec<VMId> = vm_processes
+            .into_iter()
+            .filter(|(_, pid)| match system.process(Pid::from(*pid as usize)) {
+                Some(process) => process.status() == ProcessStatus::Zombie,
+                None => true,
+            })
+            .map(|(vm_id, _)| vm_id)
+            .collect();
+        drop(system);
+        for vm_id in exited {
+            println!("ProcessMonitor: VM '{}' process exited", vm_id.0);
+            self.vm_processes.write().await.remove(&vm_id);
+            self.status_events.record(&vm_id, VMStatus::Stopped).await;
+        }
+    }
 
-        let system = self.system.read().await;
-        let process = system.process(Pid::from(*pid as usize))?;
+    fn process_metrics(system: &System, vm_id: &VMId, pid: u32) -> Option<VMMetrics> {
+        let process = system.process(Pid::from(pid as usize))?;
         
         // Log process details for debugging
         if let Some(cmd) = process.cmd().first() {
@@ -84,32 +217,100 @@ impl ProcessMonitor {
             network_tx_bytes: 0,
         })
     }
+}
 
-    pub async fn get_all_metrics(&self) -> HashMap<VMId, VMMetrics> {
-        let mut metrics = HashMap::new();
-        let vm_processes = self.vm_processes.read().await.clone();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        for (vm_id, _) in vm_processes {
-            if let Some(vm_metrics) = self.get_vm_metrics(&vm_id).await {
-                metrics.insert(vm_id, vm_metrics);
-            }
-        }
+    fn own_vm() -> (VMId, u32) {
+        (VMId("self".to_string()), std::process::id())
+    }
+
+    #[tokio::test]
+    async fn test_reads_return_cached_sample() {
+        let monitor = ProcessMonitor::new();
+        let (vm_id, pid) = own_vm();
+        monitor.register_vm_process(vm_id.clone(), pid).await;
+
+        // Nothing has been sampled yet, and reading must not sample
+        assert_eq!(monitor.get_vm_metrics(&vm_id).await, None);
+        assert!(monitor.get_all_metrics().await.is_empty());
 
-        metrics
+        monitor.update_metrics().await;
+        let sample = monitor.get_vm_metrics(&vm_id).await.expect("sampled");
+
+        // Later reads hand back the same sample until the next refresh
+        assert_eq!(monitor.get_vm_metrics(&vm_id).await, Some(sample.clone()));
+        assert_eq!(monitor.get_all_metrics().await.get(&vm_id), Some(&sample));
+
+        monitor.unregister_vm_process(&vm_id).await;
+        assert_eq!(monitor.get_vm_metrics(&vm_id).await, None);
     }
 
-    pub async fn get_system_metrics(&self) -> (f32, f32) {
-        let system = self.system.read().await;
-        let cpu_usage = system.global_cpu_usage();
-        let memory_usage = (system.used_memory() as f32 / system.total_memory() as f32) * 100.0;
+    #[tokio::test]
+    async fn test_background_sampling() {
+        let monitor = ProcessMonitor::new();
+        let (vm_id, pid) = own_vm();
+        monitor.register_vm_process(vm_id.clone(), pid).await;
 
-        (cpu_usage, memory_usage)
+        monitor.start_sampling(Duration::from_millis(10));
+        assert!(monitor.is_sampling());
+
+        let sampled = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if monitor.get_vm_metrics(&vm_id).await.is_some() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(sampled.is_ok(), "background task never sampled");
+
+        monitor.stop_sampling();
+        assert!(!monitor.is_sampling());
+        assert!(monitor.get_vm_metrics(&vm_id).await.is_some());
     }
 
-    pub async fn cleanup_stale_processes(&self) {
-        let system = self.system.read().await;
-        let mut vm_processes = self.vm_processes.write().await;
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_status_changes_follow_the_process() {
+        let monitor = ProcessMonitor::new();
+        let mut changes = monitor.subscribe_status_changes();
+        let vm_id = VMId("sleeper".to_string());
+        let mut process = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let pid = process.id();
 
-        vm_processes.retain(|_, &mut pid| system.process(Pid::from(pid as usize)).is_some());
+        monitor.register_vm_process(vm_id.clone(), pid).await;
+        monitor.update_metrics().await;
+        assert_eq!(
+            changes.try_recv().unwrap(),
+            VMStatusChanged {
+                vm_id: vm_id.clone(),
+                old: VMStatus::Stopped,
+                new: VMStatus::Running { pid },
+            }
+        );
+        assert!(changes.try_recv().is_err());
+
+        process.kill().unwrap();
+        process.wait().unwrap();
+        monitor.update_metrics().await;
+        assert_eq!(
+            changes.try_recv().unwrap(),
+            VMStatusChanged {
+                vm_id: vm_id.clone(),
+                old: VMStatus::Running { pid },
+                new: VMStatus::Stopped,
+            }
+        );
+
+        // The exit is reported once
+        monitor.update_metrics().await;
+        assert!(changes.try_recv().is_err());
     }
 }
@@ -0,0 +1,74 @@
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::UnixStream;
+
+/// Client for a QEMU Machine Protocol socket, enough to run commands on a
+/// running VM one at a time.
+pub struct QmpClient {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl QmpClient {
+    /// Connect to the QMP socket at `path` and leave capabilities
+    /// negotiation mode, so commands can be run
+    pub async fn connect(path: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(path)
+            .await
+            .map_err(|e| anyhow!("Failed to connect to QMP socket {}: {}", path.display(), e))?;
+        let (reader, writer) = stream.into_split();
+        let mut client = Self {
+            reader: BufReader::new(reader),
+            writer,
+        };
+
+        let greeting = client.read_message().await?;
+        if greeting.get("QMP").is_none() {
+            return Err(anyhow!("Unexpected QMP greeting: {}", greeting));
+        }
+        client.execute("qmp_capabilities", None).await?;
+        Ok(client)
+    }
+
+    /// Run `command` and return its `return` value. Events QEMU sends in
+    /// the meantime are skipped.
+    pub async fn execute(&mut self, command: &str, arguments: Option<Value>) -> Result<Value> {
+        let mut request = json!({ "execute": command });
+        if let Some(arguments) = arguments {
+            request["arguments"] = arguments;
+        }
+        let mut line = request.to_string();
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+        self.writer.flush().await?;
+
+        loop {
+            let mut response = self.read_message().await?;
+            if response.get("event").is_some() {
+                continue;
+            }
+            if let Some(error) = response.get("error") {
+                let desc = error
+                    .get("desc")
+                    .and_then(Value::as_str)
+                    .unwrap_or("unknown error");
+                return Err(anyhow!("QMP command '{}' failed: {}", command, desc));
+            }
+            return match response.get_mut("return") {
+                Some(value) => Ok(value.take()),
+                None => Err(anyhow!("Unexpected QMP response: {}", response)),
+            };
+        }
+    }
+
+    async fn read_message(&mut self) -> Result<Value> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err(anyhow!("QMP socket closed"));
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+}
@@ -2,12 +2,26 @@ use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::future::Future;
 use std::io::Write;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Output, Stdio};
 use std::time::{Duration, SystemTime};
+use tokio::process::Command;
 use tokio::sync::OnceCell;
 
+/// Default time a quickget call may take before it's given up on
+pub const DEFAULT_QUICKGET_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Extra attempts at a quickget query that timed out or failed
+const QUICKGET_RETRIES: u32 = 2;
+
+/// Pause before retrying a failed quickget query
+const QUICKGET_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// How long a cached OS list is used without asking quickget
+const CACHE_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OSInfo {
     pub name: String,
@@ -18,6 +32,113 @@ pub struct OSInfo {
     pub svg_icon: Option<String>,
 }
 
+/// Check a download rate limit and return it the way curl's `limit-rate`
+/// takes it: bytes per second, optionally followed by `K`, `M` or `G`
+pub fn parse_download_rate(rate: &str) -> Result<String> {
+    let rate = rate.trim();
+    let digits = rate.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let suffix = rate[digits.len()..].to_uppercase();
+    let valid = !digits.is_empty()
+        && digits.chars().all(|c| c.is_ascii_digit())
+        && digits.chars().any(|c| c != '0')
+        && matches!(suffix.as_str(), "" | "K" | "M" | "G");
+    if !valid {
+        return Err(anyhow!(
+            "Invalid download rate limit '{}' (expected e.g. 500K or 2M)",
+            rate
+        ));
+    }
+    Ok(format!("{digits}{suffix}"))
+}
+
+/// Environment that holds quickget's downloads to `rate`.
+///
+/// quickget downloads with curl, which takes no rate limit from the
+/// environment, so this writes a `.curlrc` to `config_dir` that adds
+/// `limit-rate` to the user's own curl settings and points `CURL_HOME` at it.
+pub fn download_rate_env(rate: &str, config_dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let rate = parse_download_rate(rate)?;
+    let user_curlrc = std::env::var_os("CURL_HOME")
+        .map(PathBuf::from)
+        .or_else(dirs::home_dir)
+        .map(|dir| dir.join(".curlrc"));
+    let mut curlrc = user_curlrc
+        .filter(|path| path.parent() != Some(config_dir))
+        .and_then(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default();
+    if !curlrc.is_empty() && !curlrc.ends_with('\n') {
+        curlrc.push('\n');
+    }
+    curlrc.push_str(&format!("limit-rate = {rate}\n"));
+
+    fs::create_dir_all(config_dir)?;
+    fs::write(config_dir.join(".curlrc"), curlrc)?;
+    Ok(vec![("CURL_HOME".to_string(), config_dir.to_path_buf())])
+}
+
+/// Oldest quickget whose output the service is known to parse
+pub const MIN_QUICKGET_VERSION: QuickgetVersion = QuickgetVersion::new(4, 9, 0);
+
+/// Version of quickget, as printed by `quickget --version`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct QuickgetVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl QuickgetVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Find the first `major.minor[.patch]` in `output`, allowing a leading
+    /// `v` and trailing build or pre-release labels
+    pub fn parse(output: &str) -> Option<Self> {
+        output.split_whitespace().find_map(|word| {
+            let word = word.trim_start_matches(['v', 'V']);
+            let core = word.split(['-', '+']).next()?;
+            let mut parts = core.split('.').map(|part| part.parse::<u32>().ok());
+            let major = parts.next()??;
+            let minor = parts.next()??;
+            let patch = match parts.next() {
+                Some(patch) => patch?,
+                None => 0,
+            };
+            parts
+                .next()
+                .is_none()
+                .then_some(Self::new(major, minor, patch))
+        })
+    }
+
+    /// Whether the service knows how to read this quickget's output. Newer
+    /// major versions may change it.
+    pub fn is_supported(&self) -> bool {
+        *self >= MIN_QUICKGET_VERSION && self.major == MIN_QUICKGET_VERSION.major
+    }
+
+    /// Argument that makes this quickget list its systems as JSON. Before
+    /// 4.9 quickget only took the bare `list_json` command.
+    fn list_json_arg(&self) -> &'static str {
+        if *self < MIN_QUICKGET_VERSION {
+            "list_json"
+        } else {
+            "--list-json"
+        }
+    }
+}
+
+impl std::fmt::Display for QuickgetVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct QuickgetCache {
     os_list: Vec<OSInfo>,
@@ -27,6 +148,12 @@ struct QuickgetCache {
 pub struct QuickgetService {
     quickget_path: PathBuf,
     os_cache: OnceCell<Vec<OSInfo>>,
+    /// Where the OS list is cached between runs
+    cache_path: PathBuf,
+    /// Time a single quickget call may take
+    timeout: Duration,
+    /// quickget's version, `None` if it couldn't be told
+    version: OnceCell<Option<QuickgetVersion>>,
 }
 
 impl QuickgetService {
@@ -34,9 +161,29 @@ impl QuickgetService {
         Self {
             quickget_path,
             os_cache: OnceCell::new(),
+            cache_path: Self::get_cache_path(),
+            timeout: DEFAULT_QUICKGET_TIMEOUT,
+            version: OnceCell::new(),
         }
     }
 
+    /// Set how long a single quickget call may take before it's retried or
+    /// reported as timed out
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Cache the OS list in `cache_path` instead of the user's cache directory
+    pub fn with_cache_path(mut self, cache_path: PathBuf) -> Self {
+        self.cache_path = cache_path;
+        self
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
     fn get_cache_path() -> PathBuf {
         let cache_dir = dirs::cache_dir()
             .unwrap_or_else(|| PathBuf::from("/tmp"))
@@ -48,35 +195,28 @@ impl QuickgetService {
         cache_dir.join("quickget_cache.json")
     }
 
-    fn load_cache() -> Option<QuickgetCache> {
-        let cache_path = Self::get_cache_path();
-
-        if cache_path.exists() {
-            if let Ok(contents) = fs::read_to_string(&cache_path) {
-                if let Ok(cache) = serde_json::from_str::<QuickgetCache>(&contents) {
-                    // Check if cache is less than 24 hours old
-                    if let Ok(elapsed) = cache.timestamp.elapsed() {
-                        if elapsed < Duration::from_secs(24 * 60 * 60) {
-                            return Some(cache);
-                        }
-                    }
-                }
+    /// The cached OS list, if there is one no older than `max_age`
+    fn load_cache(&self, max_age: Option<Duration>) -> Option<QuickgetCache> {
+        let contents = fs::read_to_string(&self.cache_path).ok()?;
+        let cache = serde_json::from_str::<QuickgetCache>(&contents).ok()?;
+        match max_age {
+            Some(max_age) => {
+                let elapsed = cache.timestamp.elapsed().ok()?;
+                (elapsed < max_age).then_some(cache)
             }
+            None => Some(cache),
         }
-
-        None
     }
 
-    fn save_cache(os_list: &[OSInfo]) -> Result<()> {
+    fn save_cache(&self, os_list: &[OSInfo]) -> Result<()> {
         let cache = QuickgetCache {
             os_list: os_list.to_vec(),
             timestamp: SystemTime::now(),
         };
 
-        let cache_path = Self::get_cache_path();
         let cache_json = serde_json::to_string_pretty(&cache)?;
 
-        let mut file = fs::File::create(cache_path)?;
+        let mut file = fs::File::create(&self.cache_path)?;
         file.write_all(cache_json.as_bytes())?;
 
         Ok(())
@@ -90,22 +230,42 @@ impl QuickgetService {
 
     async fn fetch_supported_systems(&self) -> Result<Vec<OSInfo>> {
         // Try to load from cache first
-        if let Some(cache) = Self::load_cache() {
+        if let Some(cache) = self.load_cache(Some(CACHE_MAX_AGE)) {
             log::info!("Loaded OS list from cache");
             return Ok(cache.os_list);
         }
 
         // Cache miss or expired, fetch from quickget
         log::info!("Fetching OS list from quickget...");
-        let output = Command::new(&self.quickget_path)
-            .arg("--list-json")
-            .output()?;
-
-        if !output.status.success() {
-            return Err(anyhow!("Failed to get OS list from quickget"));
+        match self.list_systems().await {
+            Ok(os_list) => Ok(os_list),
+            Err(e) => match self.load_cache(None) {
+                // An outdated list beats no list at all
+                Some(cache) => {
+                    log::warn!("{e:#}; using the cached OS list");
+                    Ok(cache.os_list)
+                }
+                None => Err(e),
+            },
         }
+    }
 
-        let json_str = String::from_utf8(output.stdout)?;
+    /// Ask quickget for the OS list and cache it. quickget is asked again
+    /// if it hangs or fails.
+    async fn list_systems(&self) -> Result<Vec<OSInfo>> {
+        let version = self.checked_version().await;
+        let list_arg = version.map_or("--list-json", |v| v.list_json_arg());
+        let version_label = version.map_or("of unknown version".to_string(), |v| v.to_string());
+
+        let json_str = self
+            .retry(|| async {
+                let output = self.run(&[list_arg]).await?;
+                if !output.status.success() {
+                    return Err(anyhow!("Failed to get OS list from quickget"));
+                }
+                Ok(String::from_utf8(output.stdout)?)
+            })
+            .await?;
 
         #[derive(serde::Deserialize)]
         struct QuickgetEntry {
@@ -123,8 +283,21 @@ impl QuickgetService {
             svg: Option<String>,
         }
 
-        let entries: Vec<QuickgetEntry> = serde_json::from_str(&json_str)
-            .map_err(|e| anyhow!("Failed to parse quickget JSON output: {}", e))?;
+        let entries: Vec<QuickgetEntry> = serde_json::from_str(&json_str).map_err(|e| {
+            anyhow!(
+                "Failed to parse the OS list of quickget {}, its output format may have changed: {}",
+                version_label,
+                e
+            )
+        })?;
+        // An empty list means the output changed shape, not that quickget
+        // supports nothing; don't cache it
+        if entries.is_empty() {
+            return Err(anyhow!(
+                "quickget {} listed no operating systems",
+                version_label
+            ));
+        }
 
         // Group by OS and collect versions, icons
         // Type alias for better readability
@@ -164,7 +337,7 @@ impl QuickgetService {
         os_list.sort_by(|a, b| a.name.cmp(&b.name));
 
         // Save to cache
-        if let Err(e) = Self::save_cache(&os_list) {
+        if let Err(e) = self.save_cache(&os_list) {
             log::warn!("Failed to save quickget cache: {e}");
         } else {
             log::info!("Saved OS list to cache");
@@ -201,11 +374,7 @@ impl QuickgetService {
     }
 
     pub async fn check_image_url(&self, os: &str, version: &str) -> Result<String> {
-        let output = Command::new(&self.quickget_path)
-            .arg("--url")
-            .arg(os)
-            .arg(version)
-            .output()?;
+        let output = self.query(&["--url", os, version]).await?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
@@ -217,7 +386,7 @@ impl QuickgetService {
     }
 
     pub async fn get_editions(&self, os: &str) -> Result<Vec<String>> {
-        let output = Command::new(&self.quickget_path).arg(os).output()?;
+        let output = self.query(&[os]).await?;
 
         let output_str = String::from_utf8(output.stdout)?;
 
@@ -239,10 +408,8 @@ impl QuickgetService {
     }
 
     pub async fn open_homepage(&self, os: &str) -> Result<()> {
-        let output = Command::new(&self.quickget_path)
-            .arg("--open-homepage")
-            .arg(os)
-            .output()?;
+        // Not retried, as a retry could open the page twice
+        let output = self.run(&["--open-homepage", os]).await?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
@@ -251,12 +418,230 @@ impl QuickgetService {
 
         Ok(())
     }
+
+    /// quickget's version, from `quickget --version`
+    pub async fn version(&self) -> Result<QuickgetVersion> {
+        let output = self.query(&["--version"]).await?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        QuickgetVersion::parse(&stdout)
+            .ok_or_else(|| anyhow!("Unrecognised quickget version '{}'", stdout.trim()))
+    }
+
+    /// quickget's version, asked for once and checked against the versions
+    /// the service can read. A mismatch is only warned about, as the output
+    /// often still parses.
+    async fn checked_version(&self) -> Option<QuickgetVersion> {
+        *self
+            .version
+            .get_or_init(|| async {
+                match self.version().await {
+                    Ok(version) if version.is_supported() => Some(version),
+                    Ok(version) => {
+                        log::warn!(
+                            "quickget {version} is untested; expected {}.x from {MIN_QUICKGET_VERSION}",
+                            MIN_QUICKGET_VERSION.major
+                        );
+                        Some(version)
+                    }
+                    Err(e) => {
+                        log::warn!("Could not tell the quickget version: {e:#}");
+                        None
+                    }
+                }
+            })
+            .await
+    }
+
+    /// Run a read-only quickget query, retrying when it times out
+    async fn query(&self, args: &[&str]) -> Result<Output> {
+        self.retry(|| self.run(args)).await
+    }
+
+    /// Call `attempt_fn` until it succeeds, at most `QUICKGET_RETRIES` extra
+    /// times, returning the last error
+    async fn retry<T, F, Fut>(&self, mut attempt_fn: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match attempt_fn().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < QUICKGET_RETRIES => {
+                    attempt += 1;
+                    log::warn!("{e:#}, retrying ({attempt}/{QUICKGET_RETRIES})");
+                    tokio::time::sleep(QUICKGET_RETRY_DELAY).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Run quickget once, killing it if it takes longer than the timeout
+    async fn run(&self, args: &[&str]) -> Result<Output> {
+        let child = Command::new(&self.quickget_path)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        match tokio::time::timeout(self.timeout, child.wait_with_output()).await {
+            Ok(output) => Ok(output?),
+            Err(_) => Err(anyhow!(
+                "quickget {} timed out after {:?}",
+                args.join(" "),
+                self.timeout
+            )),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn os_info(name: &str) -> OSInfo {
+        OSInfo {
+            name: name.to_string(),
+            versions: vec!["1".to_string()],
+            editions: None,
+            homepage: None,
+            png_icon: None,
+            svg_icon: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_download_rate() {
+        assert_eq!(parse_download_rate("2M").unwrap(), "2M");
+        assert_eq!(parse_download_rate(" 500k ").unwrap(), "500K");
+        assert_eq!(parse_download_rate("1048576").unwrap(), "1048576");
+        for invalid in ["", "0", "0K", "M", "2MB", "1.5M", "-1", "2M; rm"] {
+            assert!(parse_download_rate(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_download_rate_env_points_curl_at_limit() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config_dir = temp_dir.path().join("curl");
+
+        let env = download_rate_env("750k", &config_dir).unwrap();
+
+        assert_eq!(env, vec![("CURL_HOME".to_string(), config_dir.clone())]);
+        let curlrc = fs::read_to_string(config_dir.join(".curlrc")).unwrap();
+        assert!(curlrc.ends_with("limit-rate = 750K\n"), "{curlrc}");
+        assert!(download_rate_env("fast", &config_dir).is_err());
+    }
+
+    #[test]
+    fn test_parse_quickget_versions() {
+        assert_eq!(
+            QuickgetVersion::parse("4.9.8\n"),
+            Some(QuickgetVersion::new(4, 9, 8))
+        );
+        assert_eq!(
+            QuickgetVersion::parse("quickemu v4.9.6-dev"),
+            Some(QuickgetVersion::new(4, 9, 6))
+        );
+        assert_eq!(
+            QuickgetVersion::parse("4.8"),
+            Some(QuickgetVersion::new(4, 8, 0))
+        );
+        assert_eq!(QuickgetVersion::parse("ERROR! 4.x unknown"), None);
+        assert_eq!(QuickgetVersion::parse(""), None);
+    }
+
+    #[test]
+    fn test_quickget_version_compatibility() {
+        let current = QuickgetVersion::new(4, 9, 8);
+        assert!(current.is_supported());
+        assert_eq!(current.list_json_arg(), "--list-json");
+
+        let old = QuickgetVersion::new(4, 8, 0);
+        assert!(!old.is_supported());
+        assert_eq!(old.list_json_arg(), "list_json");
+
+        assert!(!QuickgetVersion::new(5, 0, 0).is_supported());
+        assert_eq!(current.to_string(), "4.9.8");
+    }
+
+    /// A stand-in for quickget that runs `script`
+    #[cfg(unix)]
+    fn fake_quickget(dir: &tempfile::TempDir, script: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.path().join("quickget");
+        fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_hanging_quickget_times_out() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let service = QuickgetService::new(fake_quickget(&temp_dir, "sleep 30"))
+            .with_cache_path(temp_dir.path().join("cache.json"))
+            .with_timeout(Duration::from_millis(200));
+
+        let started = std::time::Instant::now();
+        let error = service.get_supported_systems().await.unwrap_err();
+
+        assert!(error.to_string().contains("timed out"), "{error:#}");
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_failing_quickget_falls_back_to_stale_cache() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cache_path = temp_dir.path().join("cache.json");
+        let stale = QuickgetCache {
+            os_list: vec![os_info("ubuntu")],
+            timestamp: SystemTime::UNIX_EPOCH,
+        };
+        fs::write(&cache_path, serde_json::to_string(&stale).unwrap()).unwrap();
+
+        let service =
+            QuickgetService::new(fake_quickget(&temp_dir, "exit 1")).with_cache_path(cache_path);
+        let systems = service.get_supported_systems().await.unwrap();
+
+        assert_eq!(systems.len(), 1);
+        assert_eq!(systems[0].name, "ubuntu");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_version_from_quickget() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let service = QuickgetService::new(fake_quickget(&temp_dir, "echo 4.9.7"));
+
+        assert_eq!(
+            service.version().await.unwrap(),
+            QuickgetVersion::new(4, 9, 7)
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_empty_os_list_is_an_error() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let script = r#"case "$1" in --version) echo 4.9.8 ;; *) echo '[]' ;; esac"#;
+        let service = QuickgetService::new(fake_quickget(&temp_dir, script))
+            .with_cache_path(temp_dir.path().join("cache.json"));
+
+        let error = service.get_supported_systems().await.unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("quickget 4.9.8 listed no operating systems"));
+        assert!(!temp_dir.path().join("cache.json").exists());
+    }
+
     #[tokio::test]
     async fn test_quickget_service() {
         // This test requires quickget to be installed
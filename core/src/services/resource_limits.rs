@@ -0,0 +1,70 @@
+use crate::models::VMConfig;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Runs VMs in a transient systemd scope, so cgroup limits from the VM
+/// config keep a runaway guest from starving the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemdRun {
+    path: PathBuf,
+    /// Use the user's service manager instead of the system one
+    user: bool,
+}
+
+impl SystemdRun {
+    pub fn new(path: PathBuf, user: bool) -> Self {
+        Self { path, user }
+    }
+
+    /// Find `systemd-run`, or `None` if the host isn't running systemd
+    pub fn detect() -> Option<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            if !Path::new("/run/systemd/system").is_dir() {
+                return None;
+            }
+            let path = which::which("systemd-run").ok()?;
+            // /proc/self belongs to the effective user; only root uses the
+            // system manager, everyone else gets a scope in their session
+            let root = std::fs::metadata("/proc/self")
+                .map(|metadata| metadata.uid() == 0)
+                .unwrap_or(false);
+            Some(Self::new(path, !root))
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        None
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Command that runs `program` in a scope with the given `-p` properties
+    pub fn scope_command(&self, program: &Path, properties: &[String]) -> Command {
+        let mut cmd = Command::new(&self.path);
+        if self.user {
+            cmd.arg("--user");
+        }
+        cmd.args(["--scope", "--quiet", "--collect"]);
+        for property in properties {
+            cmd.arg("-p").arg(property);
+        }
+        cmd.arg("--").arg(program);
+        cmd
+    }
+}
+
+/// systemd properties for the limits set in `config`; empty if it sets none
+pub fn limit_properties(config: &VMConfig) -> Vec<String> {
+    let mut properties = Vec::new();
+    if let Some(percent) = config.cpu_quota_percent {
+        properties.push(format!("CPUQuota={percent}%"));
+    }
+    if let Some(mem_max) = &config.mem_max {
+        properties.push(format!("MemoryMax={mem_max}"));
+    }
+    properties
+}
@@ -0,0 +1,115 @@
+use crate::models::{VMId, VMStatus};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{broadcast, RwLock};
+
+/// Events a subscriber can fall behind by before it misses the oldest
+const STATUS_EVENT_CAPACITY: usize = 64;
+
+/// A VM's status changed from `old` to `new`
+#[derive(Debug, Clone, PartialEq)]
+pub struct VMStatusChanged {
+    pub vm_id: VMId,
+    pub old: VMStatus,
+    pub new: VMStatus,
+}
+
+/// Remembers the last status seen for each VM and broadcasts a
+/// [`VMStatusChanged`] whenever a different one is recorded.
+///
+/// Clones share the statuses and the subscribers. A VM never recorded counts
+/// as stopped.
+#[derive(Clone)]
+pub struct StatusEvents {
+    sender: broadcast::Sender<VMStatusChanged>,
+    statuses: Arc<RwLock<HashMap<VMId, VMStatus>>>,
+}
+
+impl Default for StatusEvents {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(STATUS_EVENT_CAPACITY).0,
+            statuses: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+impl StatusEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Receive every change recorded from now on. A receiver that falls
+    /// too far behind gets `RecvError::Lagged` and should re-read statuses.
+    pub fn subscribe(&self) -> broadcast::Receiver<VMStatusChanged> {
+        self.sender.subscribe()
+    }
+
+    /// Record the VM's current status, broadcasting a change if it differs
+    /// from the last one. Returns whether it changed.
+    pub async fn record(&self, vm_id: &VMId, status: VMStatus) -> bool {
+        let old = {
+            let mut statuses = self.statuses.write().await;
+            let old = if status == VMStatus::Stopped {
+                statuses.remove(vm_id)
+            } else {
+                statuses.insert(vm_id.clone(), status.clone())
+            }
+            .unwrap_or(VMStatus::Stopped);
+            if old == status {
+                return false;
+            }
+            old
+        };
+
+        // Nobody listening isn't an error
+        let _ = self.sender.send(VMStatusChanged {
+            vm_id: vm_id.clone(),
+            old,
+            new: status,
+        });
+        true
+    }
+
+    /// Last status recorded for the VM
+    pub async fn status(&self, vm_id: &VMId) -> VMStatus {
+        self.statuses
+            .read()
+            .await
+            .get(vm_id)
+            .cloned()
+            .unwrap_or(VMStatus::Stopped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_only_changes_are_broadcast() {
+        let events = StatusEvents::new();
+        let mut receiver = events.subscribe();
+        let vm_id = VMId("test-vm".to_string());
+
+        assert!(!events.record(&vm_id, VMStatus::Stopped).await);
+        assert!(events.record(&vm_id, VMStatus::Starting).await);
+        assert!(events.record(&vm_id, VMStatus::Running { pid: 42 }).await);
+        assert!(!events.record(&vm_id, VMStatus::Running { pid: 42 }).await);
+        assert!(events.record(&vm_id, VMStatus::Stopped).await);
+
+        let mut changes = Vec::new();
+        while let Ok(change) = receiver.try_recv() {
+            changes.push((change.old, change.new));
+        }
+        assert_eq!(
+            changes,
+            vec![
+                (VMStatus::Stopped, VMStatus::Starting),
+                (VMStatus::Starting, VMStatus::Running { pid: 42 }),
+                (VMStatus::Running { pid: 42 }, VMStatus::Stopped),
+            ]
+        );
+        assert_eq!(events.status(&vm_id).await, VMStatus::Stopped);
+    }
+}
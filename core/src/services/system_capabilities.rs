@@ -0,0 +1,396 @@
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+
+/// Whether VMs can use KVM hardware acceleration on this host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccelerationStatus {
+    /// `/dev/kvm` exists and the current user can open it
+    Available,
+    /// The CPU doesn't advertise VT-x/AMD-V, or it is disabled in firmware
+    Unsupported,
+    /// The CPU supports virtualization but the kvm module isn't loaded
+    ModuleNotLoaded,
+    /// `/dev/kvm` exists but the current user can't read and write it
+    PermissionDenied {
+        /// Group that owns `/dev/kvm`, usually `kvm`
+        group: Option<String>,
+        /// Whether the process already has that group
+        in_group: bool,
+    },
+}
+
+impl AccelerationStatus {
+    pub fn is_available(&self) -> bool {
+        matches!(self, AccelerationStatus::Available)
+    }
+}
+
+impl fmt::Display for AccelerationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccelerationStatus::Available => write!(f, "KVM acceleration available"),
+            AccelerationStatus::Unsupported => write!(
+                f,
+                "Running without KVM (slow): the CPU has no hardware virtualization support or it is disabled in firmware"
+            ),
+            AccelerationStatus::ModuleNotLoaded => write!(
+                f,
+                "Running without KVM (slow): load the kvm_intel or kvm_amd kernel module"
+            ),
+            AccelerationStatus::PermissionDenied {
+                group: Some(group),
+                in_group: false,
+            } => write!(
+                f,
+                "Running without KVM (slow): add your user to the '{group}' group and log in again"
+            ),
+            AccelerationStatus::PermissionDenied { .. } => write!(
+                f,
+                "Running without KVM (slow): /dev/kvm is not readable and writable by the current user"
+            ),
+        }
+    }
+}
+
+/// Probes the host for virtualization support through `/dev`, `/proc` and
+/// `/sys`.
+pub struct SystemCapabilities {
+    root: PathBuf,
+}
+
+impl Default for SystemCapabilities {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SystemCapabilities {
+    pub fn new() -> Self {
+        Self::with_root(PathBuf::from("/"))
+    }
+
+    /// Resolve `/dev`, `/proc`, `/sys` and `/etc` below a different root (for tests)
+    pub fn with_root(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    /// Check whether VMs will get KVM acceleration
+    pub fn acceleration_status(&self) -> AccelerationStatus {
+        let metadata = match fs::metadata(self.kvm_device()) {
+            Ok(metadata) => metadata,
+            Err(_) if self.cpu_supports_virtualization() => {
+                return AccelerationStatus::ModuleNotLoaded
+            }
+            Err(_) => return AccelerationStatus::Unsupported,
+        };
+
+        let Some((uid, groups)) = self.process_credentials() else {
+            // Without credentials to check against, trust that the device is usable
+            return AccelerationStatus::Available;
+        };
+
+        let mode = metadata.mode();
+        let in_group = groups.contains(&metadata.gid());
+        let allowed = if uid == 0 {
+            true
+        } else if uid == metadata.uid() {
+            mode & 0o600 == 0o600
+        } else if in_group {
+            mode & 0o060 == 0o060
+        } else {
+            mode & 0o006 == 0o006
+        };
+
+        if allowed {
+            AccelerationStatus::Available
+        } else {
+            AccelerationStatus::PermissionDenied {
+                group: self.group_name(metadata.gid()),
+                in_group,
+            }
+        }
+    }
+
+    /// Whether the loaded kvm module allows nested virtualization, or `None`
+    /// if no kvm vendor module is loaded
+    pub fn nested_virtualization(&self) -> Option<bool> {
+        ["kvm_intel", "kvm_amd"].iter().find_map(|module| {
+            fs::read_to_string(
+                self.root
+                    .join("sys/module")
+                    .join(module)
+                    .join("parameters/nested"),
+            )
+            .ok()
+            .map(|value| matches!(value.trim(), "Y" | "y" | "1"))
+        })
+    }
+
+    /// Whether the process belongs to the group that owns `/dev/kvm`
+    pub fn in_kvm_group(&self) -> bool {
+        let Ok(metadata) = fs::metadata(self.kvm_device()) else {
+            return false;
+        };
+        self.process_credentials()
+            .map(|(_, groups)| groups.contains(&metadata.gid()))
+            .unwrap_or(false)
+    }
+
+    /// Human readable summary for debugging output
+    pub fn summary(&self) -> String {
+        let mut info = String::new();
+
+        let status = self.acceleration_status();
+        if status.is_available() {
+            info.push_str("KVM: available ✓\n");
+        } else {
+            info.push_str(&format!("KVM: {status} ✗\n"));
+        }
+
+        match self.nested_virtualization() {
+            Some(true) => info.push_str("Nested virtualization: enabled\n"),
+            Some(false) => info.push_str("Nested virtualization: disabled\n"),
+            None => info.push_str("Nested virtualization: unknown\n"),
+        }
+
+        info
+    }
+
+    fn kvm_device(&self) -> PathBuf {
+        self.root.join("dev/kvm")
+    }
+
+    fn cpu_supports_virtualization(&self) -> bool {
+        fs::read_to_string(self.root.join("proc/cpuinfo"))
+            .map(|cpuinfo| {
+                cpuinfo
+                    .lines()
+                    .filter(|line| line.starts_with("flags"))
+                    .flat_map(|line| line.split_whitespace())
+                    .any(|flag| flag == "vmx" || flag == "svm")
+            })
+            .unwrap_or(false)
+    }
+
+    /// Effective uid and all group ids of the current process
+    fn process_credentials(&self) -> Option<(u32, Vec<u32>)> {
+        let status = fs::read_to_string(self.root.join("proc/self/status")).ok()?;
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .map(|rest| {
+                    rest.split_whitespace()
+                        .filter_map(|id| id.parse::<u32>().ok())
+                        .collect::<Vec<_>>()
+                })
+        };
+
+        // Uid and Gid list real, effective, saved and filesystem ids
+        let uid = *field("Uid:")?.get(1)?;
+        let mut groups = field("Groups:").unwrap_or_default();
+        if let Some(gid) = field("Gid:").and_then(|gids| gids.get(1).copied()) {
+            groups.push(gid);
+        }
+        Some((uid, groups))
+    }
+
+    fn group_name(&self, gid: u32) -> Option<String> {
+        let groups = fs::read_to_string(self.root.join("etc/group")).ok()?;
+        groups.lines().find_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let id = fields.nth(1)?.parse::<u32>().ok()?;
+            (id == gid).then(|| name.to_string())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    const VIRT_CPUINFO: &str = "processor\t: 0\nflags\t\t: fpu vme de pse vmx\n";
+    const PLAIN_CPUINFO: &str = "processor\t: 0\nflags\t\t: fpu vme de pse\n";
+
+    struct MockHost {
+        kvm_mode: Option<u32>,
+        cpuinfo: &'static str,
+        /// Pretend to be the owner of /dev/kvm instead of another user
+        owner: bool,
+        in_group: bool,
+    }
+
+    fn mock_root(host: &MockHost) -> TempDir {
+        let root = TempDir::new().unwrap();
+        let path = root.path();
+        fs::create_dir_all(path.join("dev")).unwrap();
+        fs::create_dir_all(path.join("proc/self")).unwrap();
+        fs::create_dir_all(path.join("etc")).unwrap();
+        fs::write(path.join("proc/cpuinfo"), host.cpuinfo).unwrap();
+
+        let (owner_uid, kvm_gid) = match host.kvm_mode {
+            Some(mode) => {
+                let kvm = path.join("dev/kvm");
+                fs::write(&kvm, "").unwrap();
+                fs::set_permissions(&kvm, fs::Permissions::from_mode(mode)).unwrap();
+                let metadata = fs::metadata(&kvm).unwrap();
+                (metadata.uid(), metadata.gid())
+            }
+            None => (0, 0),
+        };
+
+        // Never claim uid 0, which bypasses the permission bits
+        let uid = if host.owner {
+            owner_uid
+        } else {
+            owner_uid + 1000
+        };
+        let groups = if host.in_group {
+            format!("{} {}", kvm_gid + 1000, kvm_gid)
+        } else {
+            format!("{}", kvm_gid + 1000)
+        };
+        fs::write(
+            path.join("proc/self/status"),
+            format!(
+                "Name:\tcargo\nUid:\t{uid}\t{uid}\t{uid}\t{uid}\nGid:\t{0}\t{0}\t{0}\t{0}\nGroups:\t{groups}\n",
+                kvm_gid + 1000
+            ),
+        )
+        .unwrap();
+        fs::write(
+            path.join("etc/group"),
+            format!("users:x:{}:\nkvm:x:{kvm_gid}:\n", kvm_gid + 1000),
+        )
+        .unwrap();
+
+        root
+    }
+
+    fn set_nested(root: &Path, module: &str, value: &str) {
+        let dir = root.join("sys/module").join(module).join("parameters");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("nested"), value).unwrap();
+    }
+
+    #[test]
+    fn test_acceleration_status_matrix() {
+        let denied = AccelerationStatus::PermissionDenied {
+            group: Some("kvm".to_string()),
+            in_group: false,
+        };
+        let denied_in_group = AccelerationStatus::PermissionDenied {
+            group: Some("kvm".to_string()),
+            in_group: true,
+        };
+
+        let cases = [
+            (
+                None,
+                PLAIN_CPUINFO,
+                false,
+                false,
+                AccelerationStatus::Unsupported,
+            ),
+            (
+                None,
+                VIRT_CPUINFO,
+                false,
+                false,
+                AccelerationStatus::ModuleNotLoaded,
+            ),
+            (
+                Some(0o666),
+                VIRT_CPUINFO,
+                false,
+                false,
+                AccelerationStatus::Available,
+            ),
+            (
+                Some(0o660),
+                VIRT_CPUINFO,
+                false,
+                true,
+                AccelerationStatus::Available,
+            ),
+            (Some(0o660), VIRT_CPUINFO, false, false, denied.clone()),
+            (
+                Some(0o600),
+                VIRT_CPUINFO,
+                true,
+                false,
+                AccelerationStatus::Available,
+            ),
+            (Some(0o600), VIRT_CPUINFO, false, false, denied),
+            (Some(0o606), VIRT_CPUINFO, false, true, denied_in_group),
+        ];
+
+        for (kvm_mode, cpuinfo, owner, in_group, expected) in cases {
+            let root = mock_root(&MockHost {
+                kvm_mode,
+                cpuinfo,
+                owner,
+                in_group,
+            });
+            let capabilities = SystemCapabilities::with_root(root.path().to_path_buf());
+            assert_eq!(
+                capabilities.acceleration_status(),
+                expected,
+                "mode {kvm_mode:?}, owner {owner}, in_group {in_group}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_in_kvm_group() {
+        for in_group in [true, false] {
+            let root = mock_root(&MockHost {
+                kvm_mode: Some(0o660),
+                cpuinfo: VIRT_CPUINFO,
+                owner: false,
+                in_group,
+            });
+            let capabilities = SystemCapabilities::with_root(root.path().to_path_buf());
+            assert_eq!(capabilities.in_kvm_group(), in_group);
+        }
+    }
+
+    #[test]
+    fn test_nested_virtualization() {
+        let root = mock_root(&MockHost {
+            kvm_mode: Some(0o666),
+            cpuinfo: VIRT_CPUINFO,
+            owner: false,
+            in_group: false,
+        });
+        let capabilities = SystemCapabilities::with_root(root.path().to_path_buf());
+        assert_eq!(capabilities.nested_virtualization(), None);
+
+        set_nested(root.path(), "kvm_amd", "0\n");
+        assert_eq!(capabilities.nested_virtualization(), Some(false));
+
+        set_nested(root.path(), "kvm_intel", "Y\n");
+        assert_eq!(capabilities.nested_virtualization(), Some(true));
+    }
+
+    #[test]
+    fn test_summary_reports_missing_kvm() {
+        let root = mock_root(&MockHost {
+            kvm_mode: None,
+            cpuinfo: VIRT_CPUINFO,
+            owner: false,
+            in_group: false,
+        });
+        let capabilities = SystemCapabilities::with_root(root.path().to_path_buf());
+
+        let summary = capabilities.summary();
+        assert!(summary.contains("Running without KVM (slow)"));
+        assert!(summary.contains("✗"));
+    }
+}
@@ -0,0 +1,383 @@
+use crate::services::notifier::{Notification, Notifier};
+use anyhow::{anyhow, Result};
+use futures_util::Stream;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use tokio::sync::mpsc;
+
+/// Progress reported while quickget creates a VM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmCreationEvent {
+    Started,
+    /// A line quickget wrote to stdout
+    Output(String),
+    /// A line quickget wrote to stderr
+    Error(String),
+    /// quickget succeeded; holds the path of the new config
+    Finished(PathBuf),
+    Failed(String),
+    Cancelled,
+}
+
+impl VmCreationEvent {
+    /// Whether this is the last event of a creation
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            VmCreationEvent::Finished(_) | VmCreationEvent::Failed(_) | VmCreationEvent::Cancelled
+        )
+    }
+}
+
+impl fmt::Display for VmCreationEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmCreationEvent::Started => write!(f, "Quickget process started successfully"),
+            VmCreationEvent::Output(line) => write!(f, "STDOUT: {line}"),
+            VmCreationEvent::Error(line) => write!(f, "STDERR: {line}"),
+            VmCreationEvent::Finished(config_path) => {
+                write!(f, "VM created successfully: {}", config_path.display())
+            }
+            VmCreationEvent::Failed(reason) => write!(f, "{reason}"),
+            VmCreationEvent::Cancelled => write!(f, "VM creation cancelled"),
+        }
+    }
+}
+
+/// A running quickget process creating a VM.
+///
+/// Dropping the handle leaves quickget running; call [`cancel`](Self::cancel)
+/// to stop it.
+pub struct VmCreationHandle {
+    pid: u32,
+    cancelled: Arc<AtomicBool>,
+    events: mpsc::UnboundedReceiver<VmCreationEvent>,
+}
+
+impl VmCreationHandle {
+    /// Run quickget with `args` and the extra variables in `envs` in
+    /// `output_dir`, expecting it to write `config_path`. `notifier` hears
+    /// whether the creation succeeded.
+    pub(crate) fn spawn(
+        quickget_path: &Path,
+        args: &[String],
+        envs: &[(String, PathBuf)],
+        output_dir: &Path,
+        config_path: PathBuf,
+        notifier: Arc<dyn Notifier>,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(output_dir)
+            .map_err(|e| anyhow!("Failed to create directory {}: {}", output_dir.display(), e))?;
+
+        let mut cmd = Command::new(quickget_path);
+        cmd.args(args)
+            .envs(envs.iter().map(|(name, value)| (name, value)))
+            .current_dir(output_dir)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // Own process group, so cancelling also stops the downloads quickget starts
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+
+        let mut child = cmd.spawn().map_err(|e| {
+            anyhow!(
+                "Failed to start quickget: {} (working dir: {})",
+                e,
+                output_dir.display()
+            )
+        })?;
+
+        let (tx, events) = mpsc::unbounded_channel();
+        let _ = tx.send(VmCreationEvent::Started);
+
+        let readers = [
+            child
+                .stdout
+                .take()
+                .map(|stdout| forward_lines(stdout, tx.clone(), VmCreationEvent::Output)),
+            child
+                .stderr
+                .take()
+                .map(|stderr| forward_lines(stderr, tx.clone(), VmCreationEvent::Error)),
+        ];
+
+        let pid = child.id();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let cancelled_flag = cancelled.clone();
+
+        thread::spawn(move || {
+            let status = child.wait();
+            // Report all output before the result
+            for reader in readers.into_iter().flatten() {
+                let _ = reader.join();
+            }
+
+            let event = match status {
+                _ if cancelled_flag.load(Ordering::SeqCst) => VmCreationEvent::Cancelled,
+                Ok(status) if status.success() => VmCreationEvent::Finished(config_path),
+                Ok(status) => VmCreationEvent::Failed(format!(
+                    "VM creation failed with exit code: {}",
+                    status.code().unwrap_or(-1)
+                )),
+                Err(e) => VmCreationEvent::Failed(format!("Error waiting for process: {e}")),
+            };
+            match &event {
+                VmCreationEvent::Finished(config_path) => {
+                    notifier.notify(&Notification::VmCreated(config_path.clone()))
+                }
+                VmCreationEvent::Failed(reason) => {
+                    notifier.notify(&Notification::VmCreationFailed(reason.clone()))
+                }
+                _ => {}
+            }
+            let _ = tx.send(event);
+        });
+
+        Ok(Self {
+            pid,
+            cancelled,
+            events,
+        })
+    }
+
+    /// Process id of quickget
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Stop quickget and everything it started. The progress stream ends
+    /// with [`VmCreationEvent::Cancelled`] unless quickget already finished.
+    pub fn cancel(&self) -> Result<()> {
+        #[cfg(unix)]
+        {
+            use nix::errno::Errno;
+            use nix::sys::signal::{killpg, Signal};
+            use nix::unistd::Pid;
+
+            self.cancelled.store(true, Ordering::SeqCst);
+            match killpg(Pid::from_raw(self.pid as i32), Signal::SIGTERM) {
+                // quickget and its downloads are already gone
+                Ok(()) | Err(Errno::ESRCH) => Ok(()),
+                Err(e) => {
+                    self.cancelled.store(false, Ordering::SeqCst);
+                    Err(anyhow!("Failed to cancel VM creation: {}", e))
+                }
+            }
+        }
+
+        #[cfg(not(unix))]
+        Err(anyhow!("Cancelling VM creation is not supported here"))
+    }
+
+    /// Wait for the next progress event; `None` once the final event was read
+    pub async fn next_event(&mut self) -> Option<VmCreationEvent> {
+        self.events.recv().await
+    }
+
+    /// Blocking version of [`next_event`](Self::next_event) for use outside async code
+    pub fn blocking_next_event(&mut self) -> Option<VmCreationEvent> {
+        self.events.blocking_recv()
+    }
+
+    /// Progress events in the order they happened
+    pub fn progress(&mut self) -> impl Stream<Item = VmCreationEvent> + '_ {
+        futures_util::stream::poll_fn(move |cx| self.events.poll_recv(cx))
+    }
+}
+
+fn forward_lines<R, F>(
+    reader: R,
+    tx: mpsc::UnboundedSender<VmCreationEvent>,
+    event: F,
+) -> thread::JoinHandle<()>
+where
+    R: Read + Send + 'static,
+    F: Fn(String) -> VmCreationEvent + Send + 'static,
+{
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines().map_while(Result::ok) {
+            let _ = tx.send(event(line));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::notifier::{NoopNotifier, RecordingNotifier};
+    use futures_util::StreamExt;
+    use std::fs;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    /// Write a quickget stand-in and return the arguments that run it with sh.
+    /// Running the script through sh avoids ETXTBSY when another test thread
+    /// forks while the file is being written.
+    fn fake_quickget(dir: &Path, script: &str) -> Vec<String> {
+        let path = dir.join("quickget");
+        fs::write(&path, format!("{script}\n")).unwrap();
+        vec![path.to_string_lossy().to_string()]
+    }
+
+    fn spawn_fake(args: &[String], output_dir: &Path, config_path: PathBuf) -> VmCreationHandle {
+        spawn_fake_notifying(args, output_dir, config_path, Arc::new(NoopNotifier))
+    }
+
+    fn spawn_fake_notifying(
+        args: &[String],
+        output_dir: &Path,
+        config_path: PathBuf,
+        notifier: Arc<dyn Notifier>,
+    ) -> VmCreationHandle {
+        VmCreationHandle::spawn(
+            Path::new("/bin/sh"),
+            args,
+            &[],
+            output_dir,
+            config_path,
+            notifier,
+        )
+        .unwrap()
+    }
+
+    fn is_running(pid: u32) -> bool {
+        // Reaped processes disappear from /proc; zombies count as stopped
+        fs::read_to_string(format!("/proc/{pid}/stat"))
+            .map(|stat| !stat.contains(") Z "))
+            .unwrap_or(false)
+    }
+
+    #[tokio::test]
+    async fn test_progress_events_in_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let quickget = fake_quickget(
+            temp_dir.path(),
+            "echo one\necho two\necho oops >&2\necho three",
+        );
+        let output_dir = temp_dir.path().join("vms");
+        let config_path = output_dir.join("ubuntu-24.04.conf");
+
+        let mut handle = spawn_fake(&quickget, &output_dir, config_path.clone());
+        let events: Vec<VmCreationEvent> = handle.progress().collect().await;
+
+        // stdout and stderr are read separately, so only compare stdout order
+        let output: Vec<&VmCreationEvent> = events
+            .iter()
+            .filter(|event| matches!(event, VmCreationEvent::Output(_)))
+            .collect();
+        assert_eq!(
+            output,
+            vec![
+                &VmCreationEvent::Output("one".to_string()),
+                &VmCreationEvent::Output("two".to_string()),
+                &VmCreationEvent::Output("three".to_string()),
+            ]
+        );
+        assert!(events.contains(&VmCreationEvent::Error("oops".to_string())));
+        assert_eq!(events.first(), Some(&VmCreationEvent::Started));
+        assert_eq!(events.last(), Some(&VmCreationEvent::Finished(config_path)));
+    }
+
+    #[tokio::test]
+    async fn test_failed_exit_code_reported() {
+        let temp_dir = TempDir::new().unwrap();
+        let quickget = fake_quickget(temp_dir.path(), "exit 3");
+
+        let mut handle = spawn_fake(&quickget, temp_dir.path(), temp_dir.path().join("vm.conf"));
+        let events: Vec<VmCreationEvent> = handle.progress().collect().await;
+
+        assert_eq!(
+            events.last(),
+            Some(&VmCreationEvent::Failed(
+                "VM creation failed with exit code: 3".to_string()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_after_exit_succeeds() {
+        let temp_dir = TempDir::new().unwrap();
+        let quickget = fake_quickget(temp_dir.path(), "exit 3");
+
+        let mut handle = spawn_fake(&quickget, temp_dir.path(), temp_dir.path().join("vm.conf"));
+        let events: Vec<VmCreationEvent> = handle.progress().collect().await;
+        assert!(events.last().is_some_and(VmCreationEvent::is_final));
+
+        // Nothing is left to stop
+        handle.cancel().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_creation_result_is_notified() {
+        let temp_dir = TempDir::new().unwrap();
+        let quickget = fake_quickget(temp_dir.path(), "echo done");
+        let config_path = temp_dir.path().join("fedora-40.conf");
+        let notifier = Arc::new(RecordingNotifier::default());
+
+        let mut handle = spawn_fake_notifying(
+            &quickget,
+            temp_dir.path(),
+            config_path.clone(),
+            notifier.clone(),
+        );
+        let events: Vec<VmCreationEvent> = handle.progress().collect().await;
+
+        assert_eq!(
+            events.last(),
+            Some(&VmCreationEvent::Finished(config_path.clone()))
+        );
+        assert_eq!(
+            notifier.notifications(),
+            vec![Notification::VmCreated(config_path)]
+        );
+
+        let quickget = fake_quickget(temp_dir.path(), "exit 1");
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut handle = spawn_fake_notifying(
+            &quickget,
+            temp_dir.path(),
+            temp_dir.path().join("vm.conf"),
+            notifier.clone(),
+        );
+        handle.progress().collect::<Vec<_>>().await;
+
+        assert_eq!(
+            notifier.notifications(),
+            vec![Notification::VmCreationFailed(
+                "VM creation failed with exit code: 1".to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_kills_quickget() {
+        let temp_dir = TempDir::new().unwrap();
+        let quickget = fake_quickget(temp_dir.path(), "echo downloading\nsleep 30\necho done");
+
+        let mut handle = spawn_fake(&quickget, temp_dir.path(), temp_dir.path().join("vm.conf"));
+        assert_eq!(handle.next_event().await, Some(VmCreationEvent::Started));
+        assert_eq!(
+            handle.next_event().await,
+            Some(VmCreationEvent::Output("downloading".to_string()))
+        );
+        assert!(is_running(handle.pid()));
+
+        handle.cancel().unwrap();
+
+        // The sleep child holds the output pipe open, so this only finishes
+        // once the whole process group is gone
+        let events = tokio::time::timeout(
+            Duration::from_secs(5),
+            handle.progress().collect::<Vec<_>>(),
+        )
+        .await
+        .expect("cancelled creation did not finish");
+        assert_eq!(events, vec![VmCreationEvent::Cancelled]);
+        assert!(!is_running(handle.pid()));
+    }
+}
@@ -1,19 +1,447 @@
-use crate::models::{DisplayProtocol, VMId, VMStatus, VMTemplate, VM};
+use crate::models::{DisplayProtocol, Firmware, VMConfig, VMId, VMStatus, VMTemplate, VM};
 use crate::services::binary_discovery::BinaryDiscovery;
+use crate::services::disk_image::{parse_disk_size, QemuImg};
+#[cfg(target_os = "linux")]
+use crate::services::gpu_passthrough::{GpuPassthrough, PassthroughWarning};
+use crate::services::hooks::{run_hook, validate_env, HookKind};
+use crate::services::notifier::{NoopNotifier, Notification, Notifier};
+use crate::services::parser::ConfigParser;
+use crate::services::port_allocator::PortAllocator;
 use crate::services::process_monitor::ProcessMonitor;
+#[cfg(unix)]
+use crate::services::qmp::QmpClient;
+use crate::services::quickget::{download_rate_env, parse_download_rate};
+use crate::services::resource_limits::{limit_properties, SystemdRun};
+use crate::services::status_events::{StatusEvents, VMStatusChanged};
+use crate::services::vm_creation::{VmCreationEvent, VmCreationHandle};
 use crate::services::vnc_proxy::{ConsoleInfo, ConsoleProtocol, VncProxy};
-use anyhow::{anyhow, Result};
-use std::collections::HashMap;
-use std::io::{BufRead, BufReader};
+use anyhow::{anyhow, Context, Result};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+#[cfg(unix)]
+use serde_json::json;
+use spice_client::SpiceClient;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::TcpStream;
 use std::path::{Path, PathBuf};
-use std::process::{Child, Command, Stdio};
+use std::process::{Child, Command};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
-use sysinfo::{ProcessesToUpdate, System};
-use tokio::sync::RwLock;
+use sysinfo::{Pid, ProcessStatus, ProcessesToUpdate, Signal, System};
+use tokio::sync::{broadcast, RwLock};
+
+/// Default number of VMs `start_vms` launches at the same time
+pub const DEFAULT_START_CONCURRENCY: usize = 2;
+
+/// Default pause between starting autostart VMs, so they don't all boot at once
+pub const DEFAULT_AUTOSTART_DELAY: Duration = Duration::from_secs(10);
+
+/// Default time a guest gets to power off before a stop kills it
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Default time a `pre_start` or `post_stop` hook may run before it's killed
+pub const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often a stopping VM's process is checked for having exited
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long a killed QEMU process may take to go away
+const FORCE_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Environment variable that carries the manager's QEMU arguments to quickemu
+const QEMU_ARGS_ENV: &str = "QUICKEMU_MANAGER_QEMU_ARGS";
+
+/// quickemu sources the VM config after parsing its command line, so an
+/// `extra_args=` in the config replaces anything passed as `--extra_args`.
+/// Bash imports this function from the environment in place of the `source`
+/// builtin, and it adds the manager's arguments once the config is read.
+const SOURCE_FUNCTION_ENV: &str = "BASH_FUNC_source%%";
+const SOURCE_FUNCTION: &str = r#"() { builtin source "$@"; local status=$?; extra_args="${extra_args:+$extra_args }$QUICKEMU_MANAGER_QEMU_ARGS"; return $status; }"#;
+
+/// Characters that a shell would interpret. quickemu expands `--extra_args`
+/// unquoted, so these are rejected rather than passed through.
+const UNSAFE_QEMU_ARG_CHARS: &[char] = &[
+    ';', '&', '|', '$', '`', '<', '>', '(', ')', '{', '}', '[', ']', '*', '?', '!', '~', '\'', '"',
+    '\\', '#',
+];
+
+/// Check that extra QEMU arguments can be handed to quickemu's `--extra_args`
+/// unchanged.
+///
+/// quickemu splits `--extra_args` on whitespace, so a single argument can't
+/// contain spaces, and shell metacharacters are refused outright.
+pub fn validate_extra_qemu_args(args: &[String]) -> Result<()> {
+    for arg in args {
+        if arg.is_empty() {
+            return Err(anyhow!("Extra QEMU arguments must not be empty"));
+        }
+        if arg.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(anyhow!(
+                "Extra QEMU argument '{}' must not contain whitespace",
+                arg.escape_default()
+            ));
+        }
+        if let Some(c) = arg.chars().find(|c| UNSAFE_QEMU_ARG_CHARS.contains(c)) {
+            return Err(anyhow!(
+                "Extra QEMU argument '{}' contains unsupported character '{}'",
+                arg,
+                c
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Check that the firmware settings can boot: secure boot needs UEFI.
+pub fn validate_firmware(config: &VMConfig) -> Result<()> {
+    if config.secure_boot && config.firmware == Some(Firmware::Bios) {
+        return Err(anyhow!(
+            "Secure boot requires UEFI firmware, but the VM is set to boot with BIOS"
+        ));
+    }
+    Ok(())
+}
+
+/// Longest mount tag a 9p share can have
+const MAX_SHARE_TAG_LEN: usize = 31;
+
+/// Check that the shared folders can be exported: tags must be usable as
+/// config variable names and 9p mount tags, and host paths must be existing
+/// directories that survive quickemu's `--extra_args`.
+pub fn validate_shared_folders(config: &VMConfig) -> Result<()> {
+    for share in &config.shared_folders {
+        let valid_tag = !share.tag.is_empty()
+            && share.tag.len() <= MAX_SHARE_TAG_LEN
+            && share
+                .tag
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_tag {
+            return Err(anyhow!(
+                "Invalid shared folder tag '{}': use up to {} letters, digits or underscores",
+                share.tag,
+                MAX_SHARE_TAG_LEN
+            ));
+        }
+        if !share.host_path.is_dir() {
+            return Err(anyhow!(
+                "Shared folder '{}' does not exist: {}",
+                share.tag,
+                share.host_path.display()
+            ));
+        }
+        validate_extra_qemu_args(&[share.qemu_virtfs()])
+            .with_context(|| format!("Can't share {}", share.host_path.display()))?;
+    }
+    Ok(())
+}
+
+/// Why the guest won't see the shared folders without help, if it won't
+fn shared_folder_warning(config: &VMConfig) -> Option<String> {
+    if config.shared_folders.is_empty() {
+        return None;
+    }
+    let tags: Vec<&str> = config
+        .shared_folders
+        .iter()
+        .map(|share| share.tag.as_str())
+        .collect();
+    match config.guest_os.as_str() {
+        "windows" | "macos" => Some(format!(
+            "{} guests have no 9p driver, so shared folders {} won't be visible",
+            config.guest_os,
+            tags.join(", ")
+        )),
+        _ => Some(format!(
+            "shared folders aren't mounted automatically; in the guest run e.g. \
+             `mount -t 9p -o trans=virtio {} /mnt`",
+            tags[0]
+        )),
+    }
+}
+
+/// Arguments for creating `template` with quickget
+fn quickget_args(template: &VMTemplate) -> Vec<String> {
+    let mut args = Vec::new();
+    args.push(template.os.clone());
+    args.push(template.version.clone());
+    if let Some(ref edition) = template.edition {
+        args.push(edition.clone());
+    }
+    args
+}
+
+/// Extra environment for creating `template` with quickget, keeping any curl
+/// config it needs under `config_root`
+fn quickget_env(template: &VMTemplate, config_root: &Path) -> Result<Vec<(String, PathBuf)>> {
+    match template.download_rate_limit {
+        Some(ref rate) => {
+            let rate = parse_download_rate(rate)?;
+            download_rate_env(&rate, &config_root.join(format!("curl-{rate}")))
+        }
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Whether the guest quickget fetches for `template` refuses to install
+/// without a TPM and UEFI secure boot, as Windows 11 does
+fn requires_tpm(template: &VMTemplate) -> bool {
+    template.os == "windows" && template.version.starts_with("11")
+}
+
+/// Turn on the TPM and secure boot the guest of `template` needs in the
+/// config quickget wrote. Returns a warning if the TPM can't be emulated
+/// because `swtpm` isn't installed.
+fn apply_guest_requirements(
+    template: &VMTemplate,
+    config_path: &Path,
+    swtpm_path: Option<&Path>,
+) -> Result<Option<String>> {
+    if !requires_tpm(template) {
+        return Ok(None);
+    }
+
+    ConfigParser::set_firmware(config_path, Some(Firmware::Uefi), true)?;
+    ConfigParser::set_tpm(config_path, true)?;
+
+    Ok(swtpm_path.is_none().then(|| {
+        format!(
+            "{} needs a TPM, but swtpm isn't installed; install swtpm before starting the VM or the installer will refuse to run",
+            template.name
+        )
+    }))
+}
+
+fn format_ssh_command(port: u16, user: &str) -> String {
+    format!("ssh -p {port} {user}@localhost")
+}
+
+/// Unix socket QEMU serves SPICE on for `vm`, in the VM directory next to
+/// quickemu's monitor socket
+pub fn spice_socket_path(vm: &VM) -> PathBuf {
+    vm_socket_path(vm, "spice")
+}
+
+/// Unix socket QEMU serves QMP on for `vm`, next to its SPICE socket
+pub fn qmp_socket_path(vm: &VM) -> PathBuf {
+    vm_socket_path(vm, "qmp")
+}
+
+/// Log that the output of `vm`'s hooks is appended to, in the VM directory
+/// next to quickemu's own log
+pub fn hook_log_path(vm: &VM) -> PathBuf {
+    vm_dir_path(vm, "hooks.log")
+}
+
+fn vm_socket_path(vm: &VM, kind: &str) -> PathBuf {
+    vm_dir_path(vm, &format!("{kind}.socket"))
+}
+
+/// `<vm dir>/<vm name>-<suffix>`, the way quickemu names the files it keeps
+/// for a VM
+fn vm_dir_path(vm: &VM, suffix: &str) -> PathBuf {
+    let vm_dir = vm.config_path.with_extension("");
+    let name = vm_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| vm.id.0.clone());
+    vm_dir.join(format!("{name}-{suffix}"))
+}
+
+/// A running `qemu-system` process, as seen when reconciling VMs on startup
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct QemuProcess {
+    pid: u32,
+    cmd: Vec<String>,
+}
+
+/// List the `qemu-system` processes running on the host
+fn running_qemu_processes() -> Vec<QemuProcess> {
+    let mut system = System::new();
+    system.refresh_processes(ProcessesToUpdate::All, false);
+    system
+        .processes()
+        .values()
+        .filter(|process| {
+            process
+                .cmd()
+                .first()
+                .is_some_and(|cmd| cmd.to_string_lossy().contains("qemu-system"))
+        })
+        .map(|process| QemuProcess {
+            pid: process.pid().as_u32(),
+            cmd: process
+                .cmd()
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+        })
+        .collect()
+}
+
+/// Pair each VM with the QEMU process running it. quickemu keeps a VM's
+/// disks, pid file and sockets in the directory named after its config
+/// (`ubuntu.conf` → `ubuntu/`), so a process belongs to the VM whose
+/// directory its arguments point into. When directories nest, the deepest
+/// one wins.
+fn match_qemu_processes(vms: &[VM], processes: &[QemuProcess]) -> Vec<(VMId, u32)> {
+    let vm_dirs: Vec<(&VM, String)> = vms
+        .iter()
+        .map(|vm| {
+            let dir = vm.config_path.with_extension("");
+            (vm, format!("{}/", dir.display()))
+        })
+        .collect();
+
+    let mut matched: Vec<(VMId, u32)> = Vec::new();
+    for process in processes {
+        let owner = vm_dirs
+            .iter()
+            .filter(|(_, dir)| process.cmd.iter().any(|arg| arg.contains(dir.as_str())))
+            .max_by_key(|(_, dir)| dir.len());
+        if let Some((vm, _)) = owner {
+            if !matched.iter().any(|(id, _)| id == &vm.id) {
+                matched.push((vm.id.clone(), process.pid));
+            }
+        }
+    }
+    matched
+}
+
+/// PID of the QEMU process running the VM, if there is one
+fn find_qemu_pid(vm_id: &VMId) -> Option<u32> {
+    let mut system = System::new();
+    system.refresh_processes(ProcessesToUpdate::All, false);
+
+    for process in system.processes().values() {
+        if let Some(cmd) = process.cmd().first() {
+            // Look for qemu processes with our VM config
+            if cmd.to_string_lossy().contains("qemu-system")
+                && process.cmd().iter().any(|arg| {
+                    let arg_str = arg.to_string_lossy();
+                    arg_str.contains(&vm_id.0) || arg_str.contains(&format!("{}.conf", vm_id.0))
+                })
+            {
+                return Some(process.pid().as_u32());
+            }
+        }
+    }
+
+    // Fallback: sysinfo can miss command lines, so ask ps
+    let output = Command::new("ps").args(["aux"]).output().ok()?;
+    let ps_output = String::from_utf8_lossy(&output.stdout);
+    ps_output
+        .lines()
+        .filter(|line| line.contains("qemu-system") && line.contains(&vm_id.0))
+        // PID is the second column
+        .find_map(|line| line.split_whitespace().nth(1)?.parse().ok())
+}
+
+/// Ask the guest to power off: over QMP when the VM has a socket, otherwise
+/// by sending QEMU SIGTERM
+async fn request_powerdown(pid: u32, qmp_socket: Option<&Path>) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(path) = qmp_socket {
+        let result = async {
+            let mut qmp = QmpClient::connect(path).await?;
+            qmp.execute("system_powerdown", None).await
+        }
+        .await;
+        match result {
+            Ok(_) => return Ok(()),
+            Err(e) => println!("QMP powerdown failed, sending SIGTERM instead: {}", e),
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = qmp_socket;
+
+    if !signal_process(pid, Signal::Term) {
+        return Err(anyhow!("Failed to send SIGTERM to qemu process {}", pid));
+    }
+    Ok(())
+}
+
+/// Send `signal` to `pid`, falling back to the `kill` command when sysinfo
+/// can't see the process
+fn signal_process(pid: u32, signal: Signal) -> bool {
+    let pid = Pid::from_u32(pid);
+    let mut system = System::new();
+    system.refresh_processes(ProcessesToUpdate::Some(&[pid]), false);
+    if let Some(sent) = system.process(pid).and_then(|p| p.kill_with(signal)) {
+        return sent;
+    }
+
+    let name = match signal {
+        Signal::Kill => "KILL",
+        _ => "TERM",
+    };
+    Command::new("kill")
+        .args(["-s", name, &pid.to_string()])
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Wait up to `timeout` for `pid` to exit. Zombies count as exited, since
+/// reaping them is up to their parent.
+async fn wait_for_exit(pid: u32, timeout: Duration) -> bool {
+    let pid = Pid::from_u32(pid);
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let mut system = System::new();
+        system.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
+        let exited = system
+            .process(pid)
+            .map_or(true, |p| p.status() == ProcessStatus::Zombie);
+        if exited {
+            return true;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(STOP_POLL_INTERVAL).await;
+    }
+}
+
+/// How `VMManager::open_console` showed a VM's console
+pub enum ConsoleViewer {
+    /// Connected client for the in-app SPICE display
+    Embedded(Box<SpiceClient>),
+    /// The external viewer started by `launch_display`
+    External,
+}
+
+/// How `VMManager::stop_vm` stops a VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopMode {
+    /// Ask the guest to power off over QMP and return without waiting.
+    /// Without a QMP socket QEMU is sent SIGTERM instead, which exits it
+    /// cleanly but without the guest's involvement.
+    Graceful,
+    /// Like `Graceful`, then wait up to the timeout for the VM to exit and
+    /// kill it if it hasn't
+    GracefulThenForce(Duration),
+    /// Kill the QEMU process right away
+    Force,
+}
+
+/// Steps of a stop, reported by `VMManager::stop_vm_with_progress`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopProgress {
+    /// The guest was asked to power off
+    ShuttingDown,
+    /// The QEMU process is being killed
+    ForceStopping,
+    /// The QEMU process has exited
+    Stopped,
+}
+
+/// Where a VM being started serves its console
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ConsoleEndpoint {
+    Port(u16),
+    SpiceSocket(PathBuf),
+}
 
 #[derive(Clone)]
 pub struct VMManager {
@@ -22,6 +450,37 @@ pub struct VMManager {
     processes: Arc<RwLock<HashMap<VMId, Child>>>,
     process_monitor: Option<Arc<ProcessMonitor>>,
     vnc_proxy: Option<Arc<VncProxy>>,
+    port_allocator: PortAllocator,
+    /// Host ports forwarded to the guest's SSH server, for VMs started here
+    ssh_ports: Arc<RwLock<HashMap<VMId, u16>>>,
+    /// SPICE Unix sockets of VMs started here with `spice_socket`
+    console_sockets: Arc<RwLock<HashMap<VMId, PathBuf>>>,
+    /// QMP sockets of VMs started here with a SPICE or VNC console
+    qmp_sockets: Arc<RwLock<HashMap<VMId, PathBuf>>>,
+    /// VMs asked to power off that are still running
+    stopping: Arc<RwLock<HashSet<VMId>>>,
+    /// VMs last seen running, so their exit is noticed
+    running: Arc<RwLock<HashSet<VMId>>>,
+    /// VMs passed to `stop_vm` that haven't been seen stopped yet
+    stop_requested: Arc<RwLock<HashSet<VMId>>>,
+    /// Told about finished creations and unexpected exits
+    notifier: Arc<dyn Notifier>,
+    /// Pause between launches in `start_autostart_vms`
+    autostart_delay: Duration,
+    /// Time a guest gets to power off on a normal stop
+    shutdown_timeout: Duration,
+    /// Time a hook may run before it's killed
+    hook_timeout: Duration,
+    /// VMs started here with a `post_stop` hook that hasn't run yet
+    post_stop_hooks: Arc<RwLock<HashMap<VMId, VM>>>,
+    /// Applies the config's CPU and memory limits; `None` without systemd
+    systemd_run: Option<SystemdRun>,
+    /// Emulates the TPM of VMs with `tpm="on"`; `None` if it isn't installed
+    swtpm_path: Option<PathBuf>,
+    /// Broadcasts status changes; shared with the process monitor once set
+    status_events: StatusEvents,
+    /// Starts `start_vms` keeps in flight at once
+    start_concurrency: usize,
 }
 
 impl VMManager {
@@ -38,6 +497,22 @@ impl VMManager {
             processes: Arc::new(RwLock::new(HashMap::new())),
             process_monitor: None,
             vnc_proxy: None,
+            port_allocator: PortAllocator::new(),
+            ssh_ports: Arc::new(RwLock::new(HashMap::new())),
+            console_sockets: Arc::new(RwLock::new(HashMap::new())),
+            qmp_sockets: Arc::new(RwLock::new(HashMap::new())),
+            stopping: Arc::new(RwLock::new(HashSet::new())),
+            running: Arc::new(RwLock::new(HashSet::new())),
+            stop_requested: Arc::new(RwLock::new(HashSet::new())),
+            notifier: Arc::new(NoopNotifier),
+            autostart_delay: DEFAULT_AUTOSTART_DELAY,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            hook_timeout: DEFAULT_HOOK_TIMEOUT,
+            post_stop_hooks: Arc::new(RwLock::new(HashMap::new())),
+            systemd_run: SystemdRun::detect(),
+            swtpm_path: which::which("swtpm").ok(),
+            status_events: StatusEvents::new(),
+            start_concurrency: DEFAULT_START_CONCURRENCY,
         })
     }
 
@@ -48,6 +523,22 @@ impl VMManager {
             processes: Arc::new(RwLock::new(HashMap::new())),
             process_monitor: None,
             vnc_proxy: None,
+            port_allocator: PortAllocator::new(),
+            ssh_ports: Arc::new(RwLock::new(HashMap::new())),
+            console_sockets: Arc::new(RwLock::new(HashMap::new())),
+            qmp_sockets: Arc::new(RwLock::new(HashMap::new())),
+            stopping: Arc::new(RwLock::new(HashSet::new())),
+            running: Arc::new(RwLock::new(HashSet::new())),
+            stop_requested: Arc::new(RwLock::new(HashSet::new())),
+            notifier: Arc::new(NoopNotifier),
+            autostart_delay: DEFAULT_AUTOSTART_DELAY,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            hook_timeout: DEFAULT_HOOK_TIMEOUT,
+            post_stop_hooks: Arc::new(RwLock::new(HashMap::new())),
+            systemd_run: SystemdRun::detect(),
+            swtpm_path: which::which("swtpm").ok(),
+            status_events: StatusEvents::new(),
+            start_concurrency: DEFAULT_START_CONCURRENCY,
         }
     }
 
@@ -63,60 +554,238 @@ impl VMManager {
             processes: Arc::new(RwLock::new(HashMap::new())),
             process_monitor: None,
             vnc_proxy: None,
+            port_allocator: PortAllocator::new(),
+            ssh_ports: Arc::new(RwLock::new(HashMap::new())),
+            console_sockets: Arc::new(RwLock::new(HashMap::new())),
+            qmp_sockets: Arc::new(RwLock::new(HashMap::new())),
+            stopping: Arc::new(RwLock::new(HashSet::new())),
+            running: Arc::new(RwLock::new(HashSet::new())),
+            stop_requested: Arc::new(RwLock::new(HashSet::new())),
+            notifier: Arc::new(NoopNotifier),
+            autostart_delay: DEFAULT_AUTOSTART_DELAY,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            hook_timeout: DEFAULT_HOOK_TIMEOUT,
+            post_stop_hooks: Arc::new(RwLock::new(HashMap::new())),
+            systemd_run: SystemdRun::detect(),
+            swtpm_path: which::which("swtpm").ok(),
+            status_events: StatusEvents::new(),
+            start_concurrency: DEFAULT_START_CONCURRENCY,
         })
     }
 
+    /// Use `process_monitor` for metrics and share its status changes, so
+    /// exits it notices reach this manager's subscribers
     pub fn set_process_monitor(&mut self, process_monitor: Arc<ProcessMonitor>) {
+        self.status_events = process_monitor.status_events().clone();
         self.process_monitor = Some(process_monitor);
     }
 
+    /// Status changes of VMs as they start, stop or crash, so a UI can react
+    /// without polling `get_vm_status`.
+    ///
+    /// Changes are seen when this manager starts or stops a VM, when a status
+    /// is checked and, with a sampling process monitor, when a VM's process
+    /// exits. Subscribe after `set_process_monitor`, which replaces the
+    /// stream.
+    pub fn subscribe_status_changes(&self) -> broadcast::Receiver<VMStatusChanged> {
+        self.status_events.subscribe()
+    }
+
+    /// Share a port allocator between VM managers (e.g. across frontends)
+    pub fn set_port_allocator(&mut self, port_allocator: PortAllocator) {
+        self.port_allocator = port_allocator;
+    }
+
+    pub fn port_allocator(&self) -> &PortAllocator {
+        &self.port_allocator
+    }
+
+    /// Set the pause between VM launches in `start_autostart_vms`
+    pub fn set_autostart_delay(&mut self, delay: Duration) {
+        self.autostart_delay = delay;
+    }
+
+    pub fn autostart_delay(&self) -> Duration {
+        self.autostart_delay
+    }
+
+    /// Set where notifications about finished VM creations and unexpected
+    /// VM exits go; they are dropped by default
+    pub fn set_notifier(&mut self, notifier: Arc<dyn Notifier>) {
+        self.notifier = notifier;
+    }
+
+    /// Set how long a normal stop waits for the guest to power off before
+    /// killing it
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) {
+        self.shutdown_timeout = timeout;
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout
+    }
+
+    /// Set how long a `pre_start` or `post_stop` hook may run before it's
+    /// killed
+    pub fn set_hook_timeout(&mut self, timeout: Duration) {
+        self.hook_timeout = timeout;
+    }
+
+    pub fn hook_timeout(&self) -> Duration {
+        self.hook_timeout
+    }
+
+    /// The mode of a normal stop: power off, then kill after `shutdown_timeout`
+    pub fn default_stop_mode(&self) -> StopMode {
+        StopMode::GracefulThenForce(self.shutdown_timeout)
+    }
+
+    /// Set how VM resource limits are enforced; `None` starts VMs without them
+    pub fn set_systemd_run(&mut self, systemd_run: Option<SystemdRun>) {
+        self.systemd_run = systemd_run;
+    }
+
+    /// Set the `swtpm` binary quickemu emulates a TPM with; `None` if it
+    /// isn't installed
+    pub fn set_swtpm_path(&mut self, swtpm_path: Option<PathBuf>) {
+        self.swtpm_path = swtpm_path;
+    }
+
+    /// Set how many VMs `start_vms` launches at the same time; at least one
+    pub fn set_start_concurrency(&mut self, limit: usize) {
+        self.start_concurrency = limit.max(1);
+    }
+
+    pub fn start_concurrency(&self) -> usize {
+        self.start_concurrency
+    }
+
+    /// VMs marked for autostart that aren't already running
+    pub fn autostart_vms(vms: &[VM]) -> Vec<&VM> {
+        vms.iter()
+            .filter(|vm| vm.config.autostart && !vm.is_running())
+            .collect()
+    }
+
+    /// Start every VM marked for autostart, waiting `autostart_delay` between
+    /// launches. Meant to be called once when the app starts.
+    ///
+    /// A VM that fails to start is logged and skipped; the ids of the VMs
+    /// that did start are returned.
+    pub async fn start_autostart_vms(&self, vms: &[VM]) -> Vec<VMId> {
+        start_staggered(Self::autostart_vms(vms), self.autostart_delay, |vm| {
+            self.start_vm(vm)
+        })
+        .await
+    }
+
+    /// Start several VMs, at most `start_concurrency` at a time, e.g. for a
+    /// bulk action.
+    ///
+    /// Before each launch the VM's `ram` is checked against the host's free
+    /// memory, less the RAM of the VMs started before it that their QEMU
+    /// doesn't hold yet. A VM that doesn't fit waits for the starts in
+    /// flight to finish, and is refused if it still doesn't fit once nothing
+    /// else is starting. Returns each VM's result in the order given.
+    pub async fn start_vms(&self, vms: &[VM]) -> Vec<(VMId, Result<()>)> {
+        start_queued(
+            vms.iter().collect(),
+            self.start_concurrency,
+            available_memory,
+            resident_memory,
+            |vm| self.start_vm(vm),
+        )
+        .await
+    }
+
     pub async fn start_vm(&self, vm: &VM) -> Result<()> {
+        self.start_vm_with_args(vm, &[]).await
+    }
+
+    /// Start a VM, passing `extra_qemu_args` through to QEMU via quickemu's
+    /// `extra_args`
+    pub async fn start_vm_with_args(&self, vm: &VM, extra_qemu_args: &[String]) -> Result<()> {
         if vm.is_running() {
             return Err(anyhow!("VM is already running"));
         }
 
-        let config_dir = vm
-            .config_path
-            .parent()
-            .ok_or_else(|| anyhow!("Invalid config path"))?;
-
-        let mut cmd = Command::new(&self.quickemu_path);
-        cmd.arg("--vm").arg(&vm.config_path);
+        // Reserve the console port before building the command line
+        let console = match &vm.config.display {
+            DisplayProtocol::Spice { port } => Some(match Self::spice_socket(vm) {
+                Some(path) => ConsoleEndpoint::SpiceSocket(path),
+                None => ConsoleEndpoint::Port(
+                    self.allocate_console_port(vm, ConsoleProtocol::Spice, *port)
+                        .await?,
+                ),
+            }),
+            DisplayProtocol::Vnc { port } => Some(ConsoleEndpoint::Port(
+                self.allocate_console_port(vm, ConsoleProtocol::Vnc, *port)
+                    .await?,
+            )),
+            DisplayProtocol::Sdl | DisplayProtocol::None => None,
+        };
 
-        // Configure display and access based on the VM's display protocol
-        match &vm.config.display {
-            DisplayProtocol::Spice { .. } => {
-                cmd.arg("--display").arg("spice");
-                cmd.arg("--access").arg("remote");
-            }
-            DisplayProtocol::Vnc { port } => {
-                // Enable VNC using extra QEMU arguments
-                // Use none display to avoid conflicts, VNC will be the display
-                cmd.arg("--display").arg("none");
-                // Add VNC server on the specified port (or auto-assign if port is 0)
-                let vnc_arg = if *port > 0 {
-                    format!("-vnc :{}", port - 5900) // VNC uses display number, not port
-                } else {
-                    "-vnc :0".to_string() // Default to display :0 (port 5900)
-                };
-                println!("Enabling VNC with args: {vnc_arg}");
-                cmd.arg("--extra_args").arg(vnc_arg);
-            }
-            DisplayProtocol::Sdl => {
-                cmd.arg("--display").arg("sdl");
-            }
-            DisplayProtocol::None => {
-                cmd.arg("--display").arg("none");
+        let mut cmd = match self.build_start_command(vm, console.clone(), extra_qemu_args) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                self.port_allocator.release(&vm.id).await;
+                return Err(e);
             }
+        };
+
+        // quickemu reads the firmware from the config and has no option for
+        // it, so make sure the config uses quickemu's spelling
+        if let Err(e) =
+            ConfigParser::set_firmware(&vm.config_path, vm.config.firmware, vm.config.secure_boot)
+        {
+            self.port_allocator.release(&vm.id).await;
+            return Err(e);
         }
 
-        cmd.current_dir(config_dir);
+        // Set up whatever the VM needs on the host, e.g. a tap bridge
+        if let Some(pre_start) = &vm.config.pre_start {
+            if let Err(e) = self.run_vm_hook(vm, HookKind::PreStart, pre_start).await {
+                self.port_allocator.release(&vm.id).await;
+                return Err(e.context(format!("Not starting VM {}", vm.id.0)));
+            }
+        }
 
         // Log the full command for debugging
         println!("Starting VM {} with command: {:?}", vm.id.0, cmd);
 
-        let child = cmd.spawn()?;
+        let child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                self.port_allocator.release(&vm.id).await;
+                return Err(e.into());
+            }
+        };
         let wrapper_pid = child.id();
+        self.status_events.record(&vm.id, VMStatus::Starting).await;
+        // A stop asked for earlier says nothing about this run
+        self.stop_requested.write().await.remove(&vm.id);
+
+        if vm.config.post_stop.is_some() {
+            self.post_stop_hooks
+                .write()
+                .await
+                .insert(vm.id.clone(), vm.clone());
+        }
+
+        if let Some(ssh_port) = vm.config.ssh_port {
+            self.ssh_ports.write().await.insert(vm.id.clone(), ssh_port);
+        }
+        if console.is_some() {
+            if let Some(path) = Self::qmp_socket(vm) {
+                self.qmp_sockets.write().await.insert(vm.id.clone(), path);
+            }
+        }
+        if let Some(ConsoleEndpoint::SpiceSocket(path)) = console {
+            self.console_sockets
+                .write()
+                .await
+                .insert(vm.id.clone(), path);
+        }
 
         println!(
             "Starting VM {}: quickemu wrapper launched with PID {}",
@@ -129,6 +798,18 @@ impl VMManager {
         // Find the actual QEMU process PID
         let actual_status = self.get_vm_status(&vm.id).await;
 
+        // A VNC console refuses every client until its password is set
+        if let (DisplayProtocol::Vnc { .. }, Some(password)) =
+            (&vm.config.display, &vm.config.spice_password)
+        {
+            if let Err(e) = self.set_console_password(&vm.id, password, None).await {
+                println!(
+                    "Warning: Could not set VNC password for VM {}: {}",
+                    vm.id.0, e
+                );
+            }
+        }
+
         // Register with process monitor if available
         if let Some(monitor) = &self.process_monitor {
             if let VMStatus::Running { pid } = actual_status {
@@ -137,6 +818,7 @@ impl VMManager {
                     vm.id.0, pid
                 );
                 monitor.register_vm_process(vm.id.clone(), pid).await;
+                self.watch_monitored_exit(&vm.id);
             } else {
                 println!(
                     "Warning: Could not find running QEMU process for VM {}",
@@ -148,87 +830,518 @@ impl VMManager {
         Ok(())
     }
 
-    pub async fn stop_vm(&self, vm_id: &VMId) -> Result<()> {
-        // First try using sysinfo crate
-        let mut system = sysinfo::System::new();
-        system.refresh_processes(sysinfo::ProcessesToUpdate::All, false);
+    /// Build the quickemu command line used to start a VM.
+    ///
+    /// When the config sets `cpu_quota_percent` or `mem_max`, quickemu runs
+    /// inside a `systemd-run` scope with matching `CPUQuota`/`MemoryMax`.
+    ///
+    /// The VM config's `extra_args` come before `extra_qemu_args`, so settings
+    /// from the app can override those from the config file.
+    fn build_start_command(
+        &self,
+        vm: &VM,
+        console: Option<ConsoleEndpoint>,
+        extra_qemu_args: &[String],
+    ) -> Result<Command> {
+        let config_dir = vm
+            .config_path
+            .parent()
+            .ok_or_else(|| anyhow!("Invalid config path"))?;
 
-        for process in system.processes().values() {
-            if let Some(cmd) = process.cmd().first() {
-                let cmd_str = cmd.to_string_lossy();
-                // Look for qemu processes with our VM config
-                if cmd_str.contains("qemu-system")
-                    && process.cmd().iter().any(|arg| {
-                        let arg_str = arg.to_string_lossy();
-                        arg_str.contains(&vm_id.0) || arg_str.contains(&format!("{}.conf", vm_id.0))
-                    })
-                {
-                    println!(
-                        "Stopping VM {}: Killing qemu process PID {}",
-                        vm_id.0,
-                        process.pid().as_u32()
-                    );
-
-                    // Unregister from process monitor BEFORE killing if available
-                    if let Some(monitor) = &self.process_monitor {
-                        monitor.unregister_vm_process(vm_id).await;
-                    }
+        let properties = limit_properties(&vm.config);
+        let mut cmd = match &self.systemd_run {
+            _ if properties.is_empty() => Command::new(&self.quickemu_path),
+            Some(systemd_run) => systemd_run.scope_command(&self.quickemu_path, &properties),
+            None => {
+                println!(
+                    "Warning: systemd-run is not available, starting VM {} without resource limits",
+                    vm.id.0
+                );
+                Command::new(&self.quickemu_path)
+            }
+        };
+        cmd.arg("--vm").arg(&vm.config_path);
 
-                    process.kill();
+        validate_env(&vm.config.env)?;
+        cmd.envs(&vm.config.env);
+        // quickemu sources the config, so its path placeholders need these
+        cmd.env("VM_DIR", ConfigParser::vm_dir(&vm.config_path))
+            .env("VM_NAME", ConfigParser::vm_name(&vm.config_path));
 
-                    return Ok(());
+        // quickemu takes QEMU arguments as a single string, so collect them all
+        let mut qemu_args: Vec<String> = Vec::new();
+
+        // Consoles get a QMP socket so their password can be changed later
+        let qmp_socket = console.as_ref().and_then(|_| Self::qmp_socket(vm));
+
+        // Configure display and access based on the VM's display protocol
+        match (&vm.config.display, console) {
+            (DisplayProtocol::Spice { .. }, Some(ConsoleEndpoint::Port(port))) => {
+                cmd.arg("--display").arg("spice");
+                cmd.arg("--access").arg("remote");
+                cmd.arg("--spice-port").arg(port.to_string());
+            }
+            (DisplayProtocol::Spice { .. }, Some(ConsoleEndpoint::SpiceSocket(path))) => {
+                // quickemu can only serve SPICE over TCP, so QEMU gets its
+                // own -spice and quickemu none. The socket's permissions
+                // guard access, so no ticket is needed.
+                cmd.arg("--display").arg("none");
+                qemu_args.push("-spice".to_string());
+                qemu_args.push(format!(
+                    "unix=on,addr={},disable-ticketing=on",
+                    path.display()
+                ));
+            }
+            (DisplayProtocol::Vnc { .. }, Some(ConsoleEndpoint::Port(port))) => {
+                // Enable VNC using extra QEMU arguments
+                // Use none display to avoid conflicts, VNC will be the display
+                cmd.arg("--display").arg("none");
+                // VNC uses display number, not port
+                println!("Enabling VNC on display :{}", port - 5900);
+                qemu_args.push("-vnc".to_string());
+                // QEMU only takes a VNC password over QMP once it is enabled
+                // here; it is set right after the VM starts
+                if vm.config.spice_password.is_some() {
+                    qemu_args.push(format!(":{},password=on", port - 5900));
+                } else {
+                    qemu_args.push(format!(":{}", port - 5900));
                 }
             }
+            (DisplayProtocol::Spice { .. } | DisplayProtocol::Vnc { .. }, _) => {
+                return Err(anyhow!("No console port allocated for VM {}", vm.id.0));
+            }
+            (DisplayProtocol::Sdl, _) => {
+                cmd.arg("--display").arg("sdl");
+            }
+            (DisplayProtocol::None, _) => {
+                cmd.arg("--display").arg("none");
+            }
         }
 
-        // Fallback: Use ps and kill directly
-        if let Ok(output) = std::process::Command::new("ps").args(["aux"]).output() {
-            let ps_output = String::from_utf8_lossy(&output.stdout);
-            for line in ps_output.lines() {
-                if line.contains("qemu-system") && line.contains(&vm_id.0) {
-                    // Extract PID from ps output (second column)
-                    if let Some(pid_str) = line.split_whitespace().nth(1) {
-                        if let Ok(pid) = pid_str.parse::<u32>() {
-                            println!("Stopping VM {}: Killing qemu process PID {}", vm_id.0, pid);
+        if let Some(path) = qmp_socket {
+            qemu_args.push("-qmp".to_string());
+            qemu_args.push(format!("unix:{},server=on,wait=off", path.display()));
+        }
 
-                            // Use kill command
-                            if std::process::Command::new("kill")
-                                .arg(pid.to_string())
-                                .output()
-                                .is_ok()
-                            {
-                                // Unregister from process monitor if available
-                                if let Some(monitor) = &self.process_monitor {
-                                    monitor.unregister_vm_process(vm_id).await;
-                                }
-                                return Ok(());
-                            }
-                        }
-                    }
-                }
-            }
+        validate_shared_folders(&vm.config)?;
+        for share in &vm.config.shared_folders {
+            qemu_args.push("-virtfs".to_string());
+            qemu_args.push(share.qemu_virtfs());
+        }
+        if let Some(warning) = shared_folder_warning(&vm.config) {
+            println!("Warning: VM {} {}", vm.id.0, warning);
+        }
+
+        validate_firmware(&vm.config)?;
+        if !vm.config.boot_order.is_empty() {
+            let drives: String = vm
+                .config
+                .boot_order
+                .iter()
+                .map(|d| d.qemu_drive())
+                .collect();
+            qemu_args.push("-boot".to_string());
+            qemu_args.push(format!("order={drives}"));
+        }
+
+        // quickemu applies the config's own extra_args when it reads it
+        validate_extra_qemu_args(&vm.config.extra_args)?;
+        validate_extra_qemu_args(extra_qemu_args)?;
+        qemu_args.extend(extra_qemu_args.iter().cloned());
+        if !qemu_args.is_empty() {
+            cmd.env(QEMU_ARGS_ENV, qemu_args.join(" "));
+            cmd.env(SOURCE_FUNCTION_ENV, SOURCE_FUNCTION);
         }
 
-        Err(anyhow!("VM process not found"))
+        cmd.current_dir(config_dir);
+        Ok(cmd)
     }
 
-    pub async fn restart_vm(&self, vm: &VM) -> Result<()> {
-        self.stop_vm(&vm.id).await?;
-        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-        self.start_vm(vm).await
+    /// The SPICE socket to serve `vm`'s console on, if its config asks for
+    /// one. Falls back to TCP when the socket can't be used.
+    fn spice_socket(vm: &VM) -> Option<PathBuf> {
+        if !vm.config.spice_socket {
+            return None;
+        }
+        if !cfg!(unix) {
+            println!(
+                "Warning: Unix sockets are not supported here, serving SPICE for VM {} over TCP",
+                vm.id.0
+            );
+            return None;
+        }
+
+        let path = spice_socket_path(vm);
+        // The path goes through quickemu's extra_args like any other
+        if let Err(e) = validate_extra_qemu_args(&[path.display().to_string()]) {
+            println!(
+                "Warning: Can't serve SPICE for VM {} on {}: {}; using TCP",
+                vm.id.0,
+                path.display(),
+                e
+            );
+            return None;
+        }
+        Some(path)
     }
 
-    pub async fn get_vm_status(&self, vm_id: &VMId) -> VMStatus {
-        // Always use external process detection since quickemu wrapper exits quickly
-        self.check_vm_running_externally(vm_id).await
+    /// The QMP socket to open for `vm`, or `None` where QEMU can't serve one
+    /// quickemu will pass along
+    fn qmp_socket(vm: &VM) -> Option<PathBuf> {
+        if !cfg!(unix) {
+            return None;
+        }
+        let path = qmp_socket_path(vm);
+        validate_extra_qemu_args(&[path.display().to_string()])
+            .ok()
+            .map(|_| path)
     }
 
-    async fn check_vm_running_externally(&self, vm_id: &VMId) -> VMStatus {
-        // First try using sysinfo crate
-        let mut system = System::new();
-        system.refresh_processes(ProcessesToUpdate::All, false);
+    /// Reserve a console port for the VM and record it in its config file
+    async fn allocate_console_port(
+        &self,
+        vm: &VM,
+        protocol: ConsoleProtocol,
+        configured_port: u16,
+    ) -> Result<u16> {
+        let preferred = (configured_port > 0).then_some(configured_port);
+        let port = self
+            .port_allocator
+            .allocate(&vm.id, protocol, preferred)
+            .await?;
 
-        // First priority: Look for qemu-system processes directly
+        if preferred != Some(port) {
+            let key = match protocol {
+                ConsoleProtocol::Spice => "spice_port",
+                ConsoleProtocol::Vnc => "vnc_port",
+            };
+            if let Err(e) = ConfigParser::set_variable(&vm.config_path, key, &port.to_string()) {
+                println!(
+                    "Warning: Could not write {} to config for VM {}: {}",
+                    key, vm.id.0, e
+                );
+            }
+        }
+
+        Ok(port)
+    }
+
+    /// Stop a running VM the way `mode` says
+    pub async fn stop_vm(&self, vm_id: &VMId, mode: StopMode) -> Result<()> {
+        self.stop_vm_with_progress(vm_id, mode, |_| {}).await
+    }
+
+    /// Stop a running VM, calling `on_progress` as the stop goes on so a UI
+    /// can show that the guest is shutting down
+    pub async fn stop_vm_with_progress(
+        &self,
+        vm_id: &VMId,
+        mode: StopMode,
+        on_progress: impl FnMut(StopProgress),
+    ) -> Result<()> {
+        // Whatever happens next, the VM going away is expected
+        self.stop_requested.write().await.insert(vm_id.clone());
+
+        // Free the console port whatever happens to the process
+        self.port_allocator.release(vm_id).await;
+        self.ssh_ports.write().await.remove(vm_id);
+        self.console_sockets.write().await.remove(vm_id);
+        let qmp_socket = self.qmp_sockets.write().await.remove(vm_id);
+
+        let pid = find_qemu_pid(vm_id).ok_or_else(|| anyhow!("VM process not found"))?;
+
+        self.stop_process(vm_id, pid, qmp_socket.as_deref(), mode, on_progress)
+            .await?;
+
+        // A graceful stop returns while the guest is still shutting down; the
+        // process monitor keeps watching it so the exit is reported, and its
+        // hook runs once the VM is seen stopped
+        if !self.stopping.read().await.contains(vm_id) {
+            if let Some(monitor) = &self.process_monitor {
+                monitor.unregister_vm_process(vm_id).await;
+            }
+            self.run_post_stop_hook(vm_id).await;
+        }
+        Ok(())
+    }
+
+    /// Run one of `vm`'s hooks from its config directory, logging its output
+    /// to the VM's hook log
+    async fn run_vm_hook(&self, vm: &VM, kind: HookKind, command: &str) -> Result<()> {
+        let working_dir = vm
+            .config_path
+            .parent()
+            .ok_or_else(|| anyhow!("Invalid config path"))?;
+        println!(
+            "Running {} hook for VM {}: {}",
+            kind.config_name(),
+            vm.id.0,
+            command
+        );
+        run_hook(
+            kind,
+            command,
+            &vm.id.0,
+            &vm.config.env,
+            working_dir,
+            &hook_log_path(vm),
+            self.hook_timeout,
+        )
+        .await
+    }
+
+    /// Run the `post_stop` hook of a VM started here, once per start
+    async fn run_post_stop_hook(&self, vm_id: &VMId) {
+        let Some(vm) = self.post_stop_hooks.write().await.remove(vm_id) else {
+            return;
+        };
+        let Some(post_stop) = &vm.config.post_stop else {
+            return;
+        };
+        if let Err(e) = self.run_vm_hook(&vm, HookKind::PostStop, post_stop).await {
+            println!("Warning: {:#}", e);
+        }
+    }
+
+    async fn stop_process(
+        &self,
+        vm_id: &VMId,
+        pid: u32,
+        qmp_socket: Option<&Path>,
+        mode: StopMode,
+        mut on_progress: impl FnMut(StopProgress),
+    ) -> Result<()> {
+        if mode != StopMode::Force {
+            println!("Stopping VM {}: powering down PID {}", vm_id.0, pid);
+            request_powerdown(pid, qmp_socket).await?;
+            self.stopping.write().await.insert(vm_id.clone());
+            self.status_events.record(vm_id, VMStatus::Stopping).await;
+            on_progress(StopProgress::ShuttingDown);
+        }
+
+        match mode {
+            StopMode::Graceful => return Ok(()),
+            StopMode::GracefulThenForce(timeout) => {
+                if wait_for_exit(pid, timeout).await {
+                    self.stopping.write().await.remove(vm_id);
+                    self.status_events.record(vm_id, VMStatus::Stopped).await;
+                    on_progress(StopProgress::Stopped);
+                    return Ok(());
+                }
+                println!(
+                    "VM {} did not power off within {:?}, killing it",
+                    vm_id.0, timeout
+                );
+            }
+            StopMode::Force => {}
+        }
+
+        println!("Stopping VM {}: Killing qemu process PID {}", vm_id.0, pid);
+        on_progress(StopProgress::ForceStopping);
+        if !signal_process(pid, Signal::Kill) {
+            return Err(anyhow!("Failed to kill qemu process {}", pid));
+        }
+        let exited = wait_for_exit(pid, FORCE_STOP_TIMEOUT).await;
+        self.stopping.write().await.remove(vm_id);
+        if !exited {
+            return Err(anyhow!(
+                "qemu process {} did not exit after being killed",
+                pid
+            ));
+        }
+        self.status_events.record(vm_id, VMStatus::Stopped).await;
+        on_progress(StopProgress::Stopped);
+        Ok(())
+    }
+
+    pub async fn restart_vm(&self, vm: &VM) -> Result<()> {
+        self.stop_vm(&vm.id, self.default_stop_mode()).await?;
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        self.start_vm(vm).await
+    }
+
+    /// Host port forwarded to the guest's SSH server
+    pub async fn ssh_port(&self, vm_id: &VMId) -> Option<u16> {
+        self.ssh_ports.read().await.get(vm_id).copied()
+    }
+
+    /// The command to SSH into a running VM, e.g. `ssh -p 22220 user@localhost`.
+    ///
+    /// The guest user is assumed to match the host user. Returns `None` when
+    /// the VM isn't running or has no SSH port configured.
+    pub async fn ssh_command(&self, vm_id: &VMId) -> Option<String> {
+        if !self.is_vm_running(vm_id).await {
+            return None;
+        }
+
+        let port = self.ssh_port(vm_id).await?;
+        let user = std::env::var("USER").unwrap_or_else(|_| "user".to_string());
+        Some(format_ssh_command(port, &user))
+    }
+
+    /// Wait until the guest's SSH server answers on its forwarded port.
+    ///
+    /// QEMU accepts connections on forwarded ports before the guest is up, so
+    /// this waits for the SSH banner rather than an open port.
+    pub async fn wait_for_ssh(&self, vm_id: &VMId, timeout: Duration) -> Result<()> {
+        let port = self
+            .ssh_port(vm_id)
+            .await
+            .ok_or_else(|| anyhow!("VM '{}' has no SSH port configured", vm_id.0))?;
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            if remaining.is_zero() {
+                return Err(anyhow!(
+                    "Timed out waiting for SSH on port {} for VM '{}'",
+                    port,
+                    vm_id.0
+                ));
+            }
+
+            if tokio::time::timeout(remaining, Self::ssh_banner_received(port))
+                .await
+                .unwrap_or(false)
+            {
+                return Ok(());
+            }
+
+            tokio::time::sleep(remaining.min(Duration::from_millis(500))).await;
+        }
+    }
+
+    async fn ssh_banner_received(port: u16) -> bool {
+        use tokio::io::AsyncReadExt;
+
+        let Ok(mut stream) = tokio::net::TcpStream::connect(("127.0.0.1", port)).await else {
+            return false;
+        };
+        let mut banner = [0u8; 4];
+        stream.read_exact(&mut banner).await.is_ok() && &banner == b"SSH-"
+    }
+
+    /// Pass a host GPU through to the VM by adding a vfio-pci device to its
+    /// config. Host setup problems are returned as warnings for the UI.
+    #[cfg(target_os = "linux")]
+    pub fn configure_gpu_passthrough(
+        &self,
+        vm: &VM,
+        pci_address: &str,
+    ) -> Result<Vec<PassthroughWarning>> {
+        GpuPassthrough::new().configure(&vm.config_path, &vm.config.extra_args, pci_address)
+    }
+
+    /// Resize the VM's disk image to `new_size` (e.g. `64G`) and record the
+    /// new size in its config. The VM has to be stopped. Shrinking loses
+    /// whatever the guest kept past the new end, so it's refused unless
+    /// `force` is set.
+    pub async fn resize_disk(&self, vm: &VM, new_size: &str, force: bool) -> Result<()> {
+        if !matches!(self.get_vm_status(&vm.id).await, VMStatus::Stopped) {
+            return Err(anyhow!("Stop VM {} before resizing its disk", vm.id.0));
+        }
+        let disk_img = vm
+            .config
+            .disk_img
+            .as_ref()
+            .ok_or_else(|| anyhow!("VM {} has no disk_img in its config", vm.id.0))?;
+        // quickemu resolves disk_img from the config's directory
+        let disk_path = match vm.config_path.parent() {
+            Some(config_dir) => config_dir.join(disk_img),
+            None => disk_img.clone(),
+        };
+        let qemu_img = QemuImg::detect().ok_or_else(|| anyhow!("qemu-img is not installed"))?;
+
+        let size = parse_disk_size(new_size)?;
+        let current_size = qemu_img.virtual_size(&disk_path)?;
+        let shrink = size < current_size;
+        if shrink && !force {
+            return Err(anyhow!(
+                "Refusing to shrink the disk of VM {} from {} to {} bytes; data past the new end would be lost",
+                vm.id.0,
+                current_size,
+                size
+            ));
+        }
+
+        if size != current_size {
+            qemu_img.resize(&disk_path, size, shrink)?;
+        }
+        ConfigParser::set_variable(&vm.config_path, "disk_size", &format!("\"{new_size}\""))
+    }
+
+    pub async fn get_vm_status(&self, vm_id: &VMId) -> VMStatus {
+        // Always use external process detection since quickemu wrapper exits quickly
+        let status = self.check_vm_running_externally(vm_id).await;
+        self.track_exit(vm_id, &status).await;
+        let status = match status {
+            VMStatus::Running { .. } if self.stopping.read().await.contains(vm_id) => {
+                VMStatus::Stopping
+            }
+            VMStatus::Stopped => {
+                self.stopping.write().await.remove(vm_id);
+                status
+            }
+            _ => status,
+        };
+        self.status_events.record(vm_id, status.clone()).await;
+        status
+    }
+
+    /// Notify when a VM seen running has stopped without `stop_vm` being
+    /// called for it
+    async fn track_exit(&self, vm_id: &VMId, status: &VMStatus) {
+        match status {
+            VMStatus::Running { .. } => {
+                self.running.write().await.insert(vm_id.clone());
+            }
+            VMStatus::Stopped => {
+                let was_running = self.running.write().await.remove(vm_id);
+                let expected = self.stop_requested.write().await.remove(vm_id);
+                if was_running {
+                    // The VM is gone, so another one can have its console port
+                    self.port_allocator.release(vm_id).await;
+                }
+                if was_running && self.post_stop_hooks.read().await.contains_key(vm_id) {
+                    // Don't hold up status checks while the hook runs
+                    let manager = self.clone();
+                    let vm_id = vm_id.clone();
+                    tokio::spawn(async move { manager.run_post_stop_hook(&vm_id).await });
+                }
+                if was_running && !expected {
+                    println!("VM {} stopped unexpectedly", vm_id.0);
+                    self.notifier
+                        .notify(&Notification::VmExitedUnexpectedly(vm_id.clone()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle the exit of a VM as soon as the process monitor notices it,
+    /// rather than at the next status check
+    fn watch_monitored_exit(&self, vm_id: &VMId) {
+        let mut changes = self.status_events.subscribe();
+        let manager = self.clone();
+        let vm_id = vm_id.clone();
+        tokio::spawn(async move {
+            loop {
+                match changes.recv().await {
+                    Ok(change) if change.vm_id == vm_id && change.new == VMStatus::Stopped => break,
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+            manager.track_exit(&vm_id, &VMStatus::Stopped).await;
+        });
+    }
+
+    async fn check_vm_running_externally(&self, vm_id: &VMId) -> VMStatus {
+        // First try using sysinfo crate
+        let mut system = System::new();
+        system.refresh_processes(ProcessesToUpdate::All, false);
+
+        // First priority: Look for qemu-system processes directly
         for process in system.processes().values() {
             if let Some(cmd) = process.cmd().first() {
                 let cmd_str = cmd.to_string_lossy();
@@ -316,77 +1429,53 @@ impl VMManager {
         template: &VMTemplate,
         output_dir: &Path,
     ) -> Result<PathBuf> {
-        let quickget_path = self
-            .quickget_path
-            .as_ref()
-            .ok_or_else(|| anyhow!("quickget not available"))?;
-
-        let (tx, rx) = mpsc::channel();
-        let quickget_path = quickget_path.clone();
-        let template = template.clone();
-        let template_os = template.os.clone();
-        let template_version = template.version.clone();
-        let output_dir_original = output_dir.to_path_buf();
-        let output_dir = output_dir_original.clone();
-
-        // Run quickget in a separate thread to avoid blocking
-        thread::spawn(move || {
-            let mut cmd = Command::new(&quickget_path);
-            cmd.arg(&template.os)
-                .arg(&template.version)
-                .current_dir(&output_dir)
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped());
-
-            match cmd.spawn() {
-                Ok(mut child) => {
-                    if let Some(stdout) = child.stdout.take() {
-                        let reader = BufReader::new(stdout);
-                        for line in reader.lines().map_while(Result::ok) {
-                            let _ = tx.send(line);
-                        }
-                    }
+        let mut handle = self.create_vm(template, output_dir)?;
 
-                    match child.wait() {
-                        Ok(status) => {
-                            if status.success() {
-                                let config_path = output_dir
-                                    .join(format!("{}-{}.conf", template.os, template.version));
-                                let _ = tx.send(format!(
-                                    "VM created successfully: {}",
-                                    config_path.display()
-                                ));
-                            } else {
-                                let _ = tx.send("VM creation failed".to_string());
-                            }
-                        }
-                        Err(e) => {
-                            let _ = tx.send(format!("Error waiting for process: {e}"));
-                        }
+        let mut output_lines = Vec::new();
+        while let Some(event) = handle.next_event().await {
+            match event {
+                VmCreationEvent::Output(line) => output_lines.push(line),
+                VmCreationEvent::Finished(config_path) if config_path.exists() => {
+                    let swtpm_path = self.swtpm_path.as_deref();
+                    if let Some(warning) =
+                        apply_guest_requirements(template, &config_path, swtpm_path)?
+                    {
+                        println!("Warning: {warning}");
                     }
+                    return Ok(config_path);
                 }
-                Err(e) => {
-                    let _ = tx.send(format!("Failed to start quickget: {e}"));
-                }
-            }
-        });
-
-        // Wait for completion and return the config path
-        let mut output_lines = Vec::new();
-        while let Ok(line) = rx.recv() {
-            output_lines.push(line.clone());
-            if line.starts_with("VM created successfully:") || line.contains("failed") {
-                break;
+                event if event.is_final() => output_lines.push(event.to_string()),
+                _ => {}
             }
         }
 
-        let config_path =
-            output_dir_original.join(format!("{template_os}-{template_version}.conf"));
-        if config_path.exists() {
-            Ok(config_path)
-        } else {
-            Err(anyhow!("VM creation failed: {}", output_lines.join("\n")))
-        }
+        Err(anyhow!("VM creation failed: {}", output_lines.join("\n")))
+    }
+
+    /// Start creating a VM from a template with quickget in `output_dir`.
+    ///
+    /// Returns as soon as quickget is running; use the handle to follow its
+    /// progress or cancel it.
+    pub fn create_vm(&self, template: &VMTemplate, output_dir: &Path) -> Result<VmCreationHandle> {
+        let quickget_path = self
+            .quickget_path
+            .as_ref()
+            .ok_or_else(|| anyhow!("quickget not available"))?;
+
+        let config_root = dirs::cache_dir()
+            .unwrap_or_else(|| PathBuf::from("/tmp"))
+            .join("quickemu-manager");
+        let envs = quickget_env(template, &config_root)?;
+
+        let config_path = output_dir.join(format!("{}-{}.conf", template.os, template.version));
+        VmCreationHandle::spawn(
+            quickget_path,
+            &quickget_args(template),
+            &envs,
+            output_dir,
+            config_path,
+            self.notifier.clone(),
+        )
     }
 
     pub async fn is_vm_running(&self, vm_id: &VMId) -> bool {
@@ -414,114 +1503,106 @@ impl VMManager {
         vm.status = self.get_vm_status(&vm.id).await;
     }
 
+    /// Find VMs that are still running from before the manager started,
+    /// e.g. after a crash, and take them back under management: their QEMU
+    /// process is registered with the process monitor and their SSH port
+    /// and console sockets are recorded as if they had been started here.
+    /// Returns each adopted VM with the PID of its QEMU process.
+    pub async fn reconcile_running_vms(&self, vms: &[VM]) -> Vec<(VMId, u32)> {
+        self.adopt_vm_processes(vms, &running_qemu_processes())
+            .await
+    }
+
+    async fn adopt_vm_processes(&self, vms: &[VM], processes: &[QemuProcess]) -> Vec<(VMId, u32)> {
+        let adopted = match_qemu_processes(vms, processes);
+        for (vm_id, pid) in &adopted {
+            let Some(vm) = vms.iter().find(|vm| &vm.id == vm_id) else {
+                continue;
+            };
+            println!(
+                "Reconciling VM {}: found running QEMU process with PID {}",
+                vm_id.0, pid
+            );
+
+            // Seen running, so its exit is handled like that of a VM started here
+            self.running.write().await.insert(vm_id.clone());
+            if let Some(monitor) = &self.process_monitor {
+                monitor.register_vm_process(vm_id.clone(), *pid).await;
+                self.watch_monitored_exit(vm_id);
+            }
+            match vm.config.display {
+                DisplayProtocol::Spice { port } if port > 0 && Self::spice_socket(vm).is_none() => {
+                    self.port_allocator
+                        .register(vm_id, ConsoleProtocol::Spice, port)
+                        .await;
+                }
+                DisplayProtocol::Vnc { port } if port > 0 => {
+                    self.port_allocator
+                        .register(vm_id, ConsoleProtocol::Vnc, port)
+                        .await;
+                }
+                _ => {}
+            }
+            if let Some(ssh_port) = vm.config.ssh_port {
+                self.ssh_ports.write().await.insert(vm_id.clone(), ssh_port);
+            }
+            let qmp_socket = qmp_socket_path(vm);
+            if qmp_socket.exists() {
+                self.qmp_sockets
+                    .write()
+                    .await
+                    .insert(vm_id.clone(), qmp_socket);
+            }
+            let spice_socket = spice_socket_path(vm);
+            if vm.config.spice_socket && spice_socket.exists() {
+                self.console_sockets
+                    .write()
+                    .await
+                    .insert(vm_id.clone(), spice_socket);
+            }
+        }
+        adopted
+    }
+
+    /// Create a VM and report progress as text lines on a channel.
+    ///
+    /// Kept for callers that poll for output; new code should use
+    /// [`create_vm`](Self::create_vm), which can also cancel the creation.
     pub fn spawn_vm_creation_with_output(
         &self,
         template: VMTemplate,
         output_dir: PathBuf,
     ) -> Result<std::sync::mpsc::Receiver<String>> {
-        let quickget_path = self
-            .quickget_path
-            .as_ref()
-            .ok_or_else(|| anyhow!("quickget not available"))?;
+        if self.quickget_path.is_none() {
+            return Err(anyhow!("quickget not available"));
+        }
 
         let (tx, rx) = mpsc::channel();
-        let quickget_path = quickget_path.clone();
-        let template_os = template.os.clone();
-        let template_version = template.version.clone();
-
-        thread::spawn(move || {
-            println!("Attempting to run quickget at: {}", quickget_path.display());
-            println!("Working directory: {}", output_dir.display());
-            let cmd_str = if let Some(ref edition) = template.edition {
-                format!(
-                    "{} {} {} {}",
-                    quickget_path.display(),
-                    template_os,
-                    template_version,
-                    edition
-                )
-            } else {
-                format!(
-                    "{} {} {}",
-                    quickget_path.display(),
-                    template_os,
-                    template_version
-                )
-            };
-            println!("Command: {cmd_str}");
-
-            // Ensure output directory exists
-            if let Err(e) = std::fs::create_dir_all(&output_dir) {
-                let _ = tx.send(format!(
-                    "Failed to create directory {}: {}",
-                    output_dir.display(),
-                    e
-                ));
-                return;
-            }
-
-            let mut cmd = Command::new(&quickget_path);
-            cmd.arg(&template_os).arg(&template_version);
-
-            // Add edition if specified
-            if let Some(ref edition) = template.edition {
-                cmd.arg(edition);
-            }
-
-            cmd.current_dir(&output_dir)
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped());
-
-            match cmd.spawn() {
-                Ok(mut child) => {
-                    let _ = tx.send("Quickget process started successfully".to_string());
-
-                    // Handle stdout
-                    if let Some(stdout) = child.stdout.take() {
-                        let reader = BufReader::new(stdout);
-                        for line in reader.lines().map_while(Result::ok) {
-                            let _ = tx.send(format!("STDOUT: {line}"));
-                        }
-                    }
-
-                    // Handle stderr
-                    if let Some(stderr) = child.stderr.take() {
-                        let reader = BufReader::new(stderr);
-                        for line in reader.lines().map_while(Result::ok) {
-                            let _ = tx.send(format!("STDERR: {line}"));
-                        }
-                    }
-
-                    match child.wait() {
-                        Ok(status) => {
-                            if status.success() {
-                                let config_path = output_dir
-                                    .join(format!("{template_os}-{template_version}.conf"));
-                                let _ = tx.send(format!(
-                                    "VM created successfully: {}",
-                                    config_path.display()
-                                ));
-                            } else {
-                                let _ = tx.send(format!(
-                                    "VM creation failed with exit code: {}",
-                                    status.code().unwrap_or(-1)
-                                ));
+        match self.create_vm(&template, &output_dir) {
+            Ok(mut handle) => {
+                let swtpm_path = self.swtpm_path.clone();
+                thread::spawn(move || {
+                    while let Some(event) = handle.blocking_next_event() {
+                        if let VmCreationEvent::Finished(ref config_path) = event {
+                            let swtpm_path = swtpm_path.as_deref();
+                            match apply_guest_requirements(&template, config_path, swtpm_path) {
+                                Ok(Some(warning)) => {
+                                    let _ = tx.send(format!("Warning: {warning}"));
+                                }
+                                Ok(None) => {}
+                                Err(e) => {
+                                    let _ = tx.send(format!("Failed to enable the TPM: {e}"));
+                                }
                             }
                         }
-                        Err(e) => {
-                            let _ = tx.send(format!("Error waiting for process: {e}"));
-                        }
+                        let _ = tx.send(event.to_string());
                     }
-                }
-                Err(e) => {
-                    let _ = tx.send(format!(
-                        "Failed to start quickget: {} (working dir: {})",
-                        e,
-                        output_dir.display()
-                    ));
-                }
+                });
             }
-        });
+            Err(e) => {
+                let _ = tx.send(e.to_string());
+            }
+        }
 
         Ok(rx)
     }
@@ -556,6 +1637,83 @@ impl VMManager {
         Ok(())
     }
 
+    /// Connect the in-app SPICE client to a running VM's console, using its
+    /// SPICE socket or port and the config's `spice_password`
+    pub async fn open_embedded_console(&self, vm: &VM) -> Result<SpiceClient> {
+        if !matches!(vm.config.display, DisplayProtocol::Spice { .. }) {
+            return Err(anyhow!(
+                "VM '{}' does not use SPICE, so it has no embedded console",
+                vm.id.0
+            ));
+        }
+
+        let endpoint = match self.detect_console_socket(&vm.id).await? {
+            Some(path) => ConsoleEndpoint::SpiceSocket(path),
+            None => match self.detect_console_port(&vm.id).await? {
+                Some((port, ConsoleProtocol::Spice)) => ConsoleEndpoint::Port(port),
+                Some((port, protocol)) => {
+                    return Err(anyhow!(
+                        "VM '{}' serves {:?} on port {}, not SPICE",
+                        vm.id.0,
+                        protocol,
+                        port
+                    ));
+                }
+                None => {
+                    return Err(anyhow!(
+                        "VM '{}' does not have an active SPICE server",
+                        vm.id.0
+                    ));
+                }
+            },
+        };
+
+        println!(
+            "Opening embedded console for VM '{}' on {:?}",
+            vm.id.0, endpoint
+        );
+        Self::connect_spice(endpoint, vm.config.spice_password.clone()).await
+    }
+
+    /// Show a VM's console, in-app when the SPICE client connects and in
+    /// the external viewer from `launch_display` otherwise
+    pub async fn open_console(&self, vm: &VM) -> Result<ConsoleViewer> {
+        match self.open_embedded_console(vm).await {
+            Ok(client) => Ok(ConsoleViewer::Embedded(Box::new(client))),
+            Err(e) => {
+                println!(
+                    "Embedded console for VM '{}' failed ({}), using an external viewer",
+                    vm.id.0, e
+                );
+                self.launch_display(vm).await?;
+                Ok(ConsoleViewer::External)
+            }
+        }
+    }
+
+    async fn connect_spice(
+        endpoint: ConsoleEndpoint,
+        password: Option<String>,
+    ) -> Result<SpiceClient> {
+        let mut client = match endpoint {
+            ConsoleEndpoint::Port(port) => SpiceClient::new("127.0.0.1".to_string(), port),
+            #[cfg(unix)]
+            ConsoleEndpoint::SpiceSocket(path) => SpiceClient::new_unix(path),
+            #[cfg(not(unix))]
+            ConsoleEndpoint::SpiceSocket(path) => {
+                return Err(anyhow!(
+                    "SPICE socket {} is not supported here",
+                    path.display()
+                ));
+            }
+        };
+        if let Some(password) = password {
+            client.set_password(password);
+        }
+        client.connect().await?;
+        Ok(client)
+    }
+
     /// Set the VNC proxy service for this VM manager
     pub fn set_vnc_proxy(&mut self, vnc_proxy: Arc<VncProxy>) {
         self.vnc_proxy = Some(vnc_proxy);
@@ -578,6 +1736,17 @@ impl VMManager {
             return Ok(None);
         }
 
+        // A port we handed out ourselves is authoritative
+        if let Some((port, protocol)) = self.port_allocator.get_allocation(vm_id).await {
+            if self.is_port_open("127.0.0.1", port).await {
+                println!(
+                    "Using allocated {:?} port {} for VM '{}'",
+                    protocol, port, vm_id.0
+                );
+                return Ok(Some((port, protocol)));
+            }
+        }
+
         println!("VM '{}' is running, scanning for console ports", vm_id.0);
 
         // Try common VNC ports first - QEMU uses ports starting from 5900
@@ -625,6 +1794,38 @@ impl VMManager {
         Ok(None)
     }
 
+    /// Detect the Unix socket a running VM serves its SPICE console on.
+    ///
+    /// Only VMs started here with `spice_socket` have one; the others are
+    /// found with `detect_console_port`.
+    pub async fn detect_console_socket(&self, vm_id: &VMId) -> Result<Option<PathBuf>> {
+        let Some(path) = self.console_sockets.read().await.get(vm_id).cloned() else {
+            return Ok(None);
+        };
+
+        let status = self.get_vm_status(vm_id).await;
+        if !matches!(status, VMStatus::Running { .. }) {
+            println!(
+                "VM '{}' is not running, cannot detect console socket",
+                vm_id.0
+            );
+            return Ok(None);
+        }
+
+        #[cfg(unix)]
+        if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+            println!("Found SPICE socket {} for VM '{}'", path.display(), vm_id.0);
+            return Ok(Some(path));
+        }
+
+        println!(
+            "SPICE socket {} for VM '{}' is not accepting connections",
+            path.display(),
+            vm_id.0
+        );
+        Ok(None)
+    }
+
     /// Detect the actual VNC port being used by a running VM (backward compatibility)
     pub async fn detect_vnc_port(&self, vm_id: &VMId) -> Result<Option<u16>> {
         println!("Detecting VNC port for VM '{}'", vm_id.0);
@@ -713,6 +1914,26 @@ impl VMManager {
             .vnc_proxy
             .as_ref()
             .ok_or_else(|| anyhow!("Console proxy not initialized"))?;
+        let hostname = host_override.unwrap_or_else(|| "localhost".to_string());
+
+        // A local SPICE socket needs no TCP port on the console side
+        #[cfg(unix)]
+        if let Some(socket_path) = self.detect_console_socket(vm_id).await? {
+            println!(
+                "Creating Spice proxy connection to {} for VM '{}'",
+                socket_path.display(),
+                vm_id.0
+            );
+            let connection = vnc_proxy
+                .create_unix_connection(vm_id.0.clone(), socket_path)
+                .await?;
+            return Ok(ConsoleInfo {
+                websocket_url: format!("ws://{}:{}", hostname, connection.websocket_port),
+                auth_token: connection.auth_token,
+                connection_id: connection.id,
+                protocol: ConsoleProtocol::Spice,
+            });
+        }
 
         // Detect the console port and protocol
         println!("Detecting console port for VM '{}'", vm_id.0);
@@ -727,7 +1948,6 @@ impl VMManager {
         );
 
         // Create console connection
-        let hostname = host_override.unwrap_or_else(|| "localhost".to_string());
         println!(
             "Creating {:?} proxy connection to {}:{} for VM '{}'",
             protocol, hostname, console_port, vm_id.0
@@ -752,14 +1972,97 @@ impl VMManager {
         Ok(console_info)
     }
 
-    /// Remove a console session
-    pub async fn remove_console_session(&self, connection_id: &str) -> Result<()> {
-        let vnc_proxy = self
-            .vnc_proxy
-            .as_ref()
-            .ok_or_else(|| anyhow!("VNC proxy not initialized"))?;
-
-        vnc_proxy.stop_connection(connection_id).await
+    /// Change the password of a running VM's SPICE or VNC console over QMP.
+    ///
+    /// The password stops working after `expiry`, or never with `None`.
+    /// Open consoles stay connected; new connections need the new password.
+    pub async fn set_console_password(
+        &self,
+        vm_id: &VMId,
+        password: &str,
+        expiry: Option<Duration>,
+    ) -> Result<()> {
+        let protocol = self
+            .console_protocol(vm_id)
+            .await
+            .ok_or_else(|| anyhow!("VM '{}' does not have a SPICE or VNC display", vm_id.0))?;
+        let path = self
+            .qmp_sockets
+            .read()
+            .await
+            .get(vm_id)
+            .cloned()
+            .ok_or_else(|| anyhow!("VM '{}' has no QMP socket", vm_id.0))?;
+
+        #[cfg(unix)]
+        {
+            let protocol = match protocol {
+                ConsoleProtocol::Spice => "spice",
+                ConsoleProtocol::Vnc => "vnc",
+            };
+            let time = match expiry {
+                Some(expiry) => format!("+{}", expiry.as_secs()),
+                None => "never".to_string(),
+            };
+
+            println!(
+                "Setting {} console password for VM '{}' via {}",
+                protocol,
+                vm_id.0,
+                path.display()
+            );
+            let mut qmp = QmpClient::connect(&path).await?;
+            qmp.execute(
+                "set_password",
+                Some(json!({ "protocol": protocol, "password": password })),
+            )
+            .await?;
+            qmp.execute(
+                "expire_password",
+                Some(json!({ "protocol": protocol, "time": time })),
+            )
+            .await?;
+            Ok(())
+        }
+
+        #[cfg(not(unix))]
+        Err(anyhow!(
+            "QMP socket {} is not supported here",
+            path.display()
+        ))
+    }
+
+    /// Change a running VM's console password and create a console session
+    /// to reach it, so a client whose ticket expired can reconnect
+    pub async fn rotate_console_password(
+        &self,
+        vm_id: &VMId,
+        password: &str,
+        expiry: Option<Duration>,
+    ) -> Result<ConsoleInfo> {
+        self.set_console_password(vm_id, password, expiry).await?;
+        self.create_console_session(vm_id).await
+    }
+
+    /// Console protocol of a VM started here, if it has a console
+    async fn console_protocol(&self, vm_id: &VMId) -> Option<ConsoleProtocol> {
+        if self.console_sockets.read().await.contains_key(vm_id) {
+            return Some(ConsoleProtocol::Spice);
+        }
+        self.port_allocator
+            .get_allocation(vm_id)
+            .await
+            .map(|(_, protocol)| protocol)
+    }
+
+    /// Remove a console session
+    pub async fn remove_console_session(&self, connection_id: &str) -> Result<()> {
+        let vnc_proxy = self
+            .vnc_proxy
+            .as_ref()
+            .ok_or_else(|| anyhow!("VNC proxy not initialized"))?;
+
+        vnc_proxy.stop_connection(connection_id).await
     }
 
     /// Get console session status
@@ -792,6 +2095,15 @@ impl VMManager {
             return false;
         }
 
+        if let Ok(Some(path)) = self.detect_console_socket(&vm.id).await {
+            println!(
+                "VM '{}' has a SPICE console on {}, console access supported",
+                vm.id.0,
+                path.display()
+            );
+            return true;
+        }
+
         // Check if we can detect a console port (VNC or SPICE)
         match self.detect_console_port(&vm.id).await {
             Ok(Some((port, protocol))) => {
@@ -826,10 +2138,141 @@ impl Default for VMManager {
     }
 }
 
+/// Start `vms` one after another with `delay` between launches
+async fn start_staggered<'a, F, Fut>(vms: Vec<&'a VM>, delay: Duration, mut start: F) -> Vec<VMId>
+where
+    F: FnMut(&'a VM) -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    let mut started = Vec::new();
+
+    for (i, vm) in vms.into_iter().enumerate() {
+        if i > 0 && !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+
+        println!("Autostarting VM {}", vm.id.0);
+        match start(vm).await {
+            Ok(()) => started.push(vm.id.clone()),
+            Err(e) => println!("Warning: Failed to autostart VM {}: {}", vm.id.0, e),
+        }
+    }
+
+    started
+}
+
+/// Memory the host can hand out without swapping, or `None` if it's unknown
+fn available_memory() -> Option<u64> {
+    let mut system = System::new();
+    system.refresh_memory();
+    Some(system.available_memory()).filter(|&bytes| bytes > 0)
+}
+
+/// Memory the QEMU process of a VM holds, or `None` if it isn't running
+fn resident_memory(vm_id: &VMId) -> Option<u64> {
+    let pid = Pid::from_u32(find_qemu_pid(vm_id)?);
+    let mut system = System::new();
+    system.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
+    Some(system.process(pid).map_or(0, |process| process.memory()))
+}
+
+/// Parse a VM's `ram` the way QEMU's `-m` reads it: a bare number is in
+/// MiB, otherwise it takes a `K`, `M`, `G` or `T` suffix
+fn parse_ram_size(ram: &str) -> Result<u64> {
+    let ram = ram.trim().trim_matches('"');
+    if !ram.is_empty() && ram.chars().all(|c| c.is_ascii_digit()) {
+        return parse_disk_size(&format!("{ram}M"));
+    }
+    parse_disk_size(ram).map_err(|_| anyhow!("Invalid RAM size '{}'", ram))
+}
+
+/// Start `vms` with at most `limit` starts in flight, launching each only if
+/// its RAM fits in what `available_memory` reports less the RAM reserved for
+/// the VMs started so far.
+///
+/// QEMU only takes its guest's RAM as the guest touches it, so a started VM
+/// keeps its reservation, less what `resident_memory` reports its QEMU
+/// holds, until `resident_memory` says it has stopped. A VM that doesn't fit
+/// waits for a start to finish, and fails once nothing else is starting.
+async fn start_queued<'a, F, Fut>(
+    vms: Vec<&'a VM>,
+    limit: usize,
+    available_memory: impl Fn() -> Option<u64>,
+    resident_memory: impl Fn(&VMId) -> Option<u64>,
+    mut start: F,
+) -> Vec<(VMId, Result<()>)>
+where
+    F: FnMut(&'a VM) -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    let mut queue: VecDeque<(usize, &VM)> = vms.iter().copied().enumerate().collect();
+    let mut results: Vec<Option<Result<()>>> = vms.iter().map(|_| None).collect();
+    let mut in_flight = FuturesUnordered::new();
+    // RAM of the starts in flight, and of the VMs that started
+    let mut starting = 0u64;
+    let mut started: Vec<(&VMId, u64)> = Vec::new();
+
+    loop {
+        while in_flight.len() < limit.max(1) {
+            let Some(&(index, vm)) = queue.front() else {
+                break;
+            };
+            let needed = parse_ram_size(&vm.config.ram).unwrap_or(0);
+            if let Some(available) = available_memory() {
+                let mut reserved = starting;
+                started.retain(|(vm_id, ram)| match resident_memory(vm_id) {
+                    Some(resident) => {
+                        reserved = reserved.saturating_add(ram.saturating_sub(resident));
+                        true
+                    }
+                    None => false,
+                });
+                if needed.saturating_add(reserved) > available {
+                    if !in_flight.is_empty() {
+                        println!("Deferring start of VM {} until memory frees up", vm.id.0);
+                        break;
+                    }
+                    queue.pop_front();
+                    results[index] = Some(Err(anyhow!(
+                        "Not enough free memory to start VM {}: it needs {} MiB and {} MiB are available",
+                        vm.id.0,
+                        needed >> 20,
+                        available.saturating_sub(reserved) >> 20
+                    )));
+                    continue;
+                }
+            }
+
+            queue.pop_front();
+            starting += needed;
+            let start = start(vm);
+            in_flight.push(async move { (index, needed, start.await) });
+        }
+
+        let Some((index, needed, result)) = in_flight.next().await else {
+            break;
+        };
+        starting -= needed;
+        match &result {
+            Ok(()) => started.push((&vms[index].id, needed)),
+            Err(e) => println!("Warning: Failed to start VM {}: {}", vms[index].id.0, e),
+        }
+        results[index] = Some(result);
+    }
+
+    // Every VM leaves the queue with a result
+    vms.iter()
+        .map(|vm| vm.id.clone())
+        .zip(results.into_iter().flatten())
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::models::{DisplayProtocol, VMConfig};
+    use crate::models::{BootDevice, DisplayProtocol, SharedFolder, VMConfig};
+    use crate::services::notifier::RecordingNotifier;
+    use std::collections::BTreeMap;
     use std::fs;
     use std::time::SystemTime;
     use tempfile::TempDir;
@@ -842,6 +2285,14 @@ mod tests {
         )
     }
 
+    /// The QEMU arguments `cmd` hands to quickemu, if any
+    fn qemu_args(cmd: &Command) -> Option<String> {
+        cmd.get_envs()
+            .find(|(key, _)| *key == QEMU_ARGS_ENV)
+            .and_then(|(_, value)| value)
+            .map(|value| value.to_string_lossy().into_owned())
+    }
+
     fn create_test_vm(temp_dir: &TempDir) -> VM {
         let config_path = temp_dir.path().join("test-vm.conf");
         let config_content = r#"
@@ -865,6 +2316,21 @@ ram="2G"
                 disk_size: None,
                 display: DisplayProtocol::Spice { port: 5930 },
                 ssh_port: None,
+                extra_args: Vec::new(),
+                autostart: false,
+                cpu_quota_percent: None,
+                mem_max: None,
+                spice_socket: false,
+                spice_password: None,
+                tags: Vec::new(),
+                firmware: None,
+                secure_boot: false,
+                tpm: false,
+                boot_order: Vec::new(),
+                pre_start: None,
+                post_stop: None,
+                env: BTreeMap::new(),
+                shared_folders: Vec::new(),
                 raw_config: config_content.to_string(),
             },
             last_modified: SystemTime::now(),
@@ -906,7 +2372,7 @@ ram="2G"
         let vm_manager = create_test_vm_manager();
         let vm_id = VMId("non-existent-vm".to_string());
 
-        let result = vm_manager.stop_vm(&vm_id).await;
+        let result = vm_manager.stop_vm(&vm_id, StopMode::Force).await;
         assert!(result.is_err());
         assert!(result
             .unwrap_err()
@@ -914,6 +2380,335 @@ ram="2G"
             .contains("VM process not found"));
     }
 
+    #[cfg(unix)]
+    /// Accept QMP connections at `path`, recording the commands sent to it
+    fn spawn_qmp_recorder(path: &Path) -> Arc<std::sync::Mutex<Vec<String>>> {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::UnixListener::bind(path).unwrap();
+        let commands = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = commands.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                writer
+                    .write_all(b"{\"QMP\": {\"version\": {}, \"capabilities\": []}}\n")
+                    .await
+                    .unwrap();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let request: serde_json::Value = serde_json::from_str(&line).unwrap();
+                    let command = request["execute"].as_str().unwrap().to_string();
+                    recorded.lock().unwrap().push(command);
+                    writer.write_all(b"{\"return\": {}}\n").await.unwrap();
+                }
+            }
+        });
+        commands
+    }
+
+    #[cfg(unix)]
+    const SIGTERM: i32 = 15;
+    #[cfg(unix)]
+    const SIGKILL: i32 = 9;
+
+    #[cfg(unix)]
+    /// A process standing in for QEMU that ignores powerdown requests
+    fn spawn_stand_in_qemu() -> Child {
+        Command::new("sleep").arg("30").spawn().unwrap()
+    }
+
+    #[cfg(unix)]
+    fn exit_signal(child: &mut Child) -> Option<i32> {
+        use std::os::unix::process::ExitStatusExt;
+        child.wait().unwrap().signal()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_graceful_stop_powers_down_over_qmp() {
+        let temp_dir = TempDir::new().unwrap();
+        let qmp_path = temp_dir.path().join("qmp.socket");
+        let commands = spawn_qmp_recorder(&qmp_path);
+        let vm_manager = create_test_vm_manager();
+        let vm_id = VMId("stop-vm".to_string());
+        let mut qemu = spawn_stand_in_qemu();
+
+        let mut progress = Vec::new();
+        vm_manager
+            .stop_process(
+                &vm_id,
+                qemu.id(),
+                Some(&qmp_path),
+                StopMode::Graceful,
+                |p| progress.push(p),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *commands.lock().unwrap(),
+            vec!["qmp_capabilities", "system_powerdown"]
+        );
+        assert_eq!(progress, vec![StopProgress::ShuttingDown]);
+        // The guest decides when to go; nothing was killed
+        assert!(qemu.try_wait().unwrap().is_none());
+        assert!(vm_manager.stopping.read().await.contains(&vm_id));
+
+        qemu.kill().unwrap();
+        qemu.wait().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_graceful_stop_without_qmp_sends_sigterm() {
+        let vm_manager = create_test_vm_manager();
+        let vm_id = VMId("stop-vm".to_string());
+        let mut qemu = spawn_stand_in_qemu();
+
+        let mut progress = Vec::new();
+        vm_manager
+            .stop_process(&vm_id, qemu.id(), None, StopMode::Graceful, |p| {
+                progress.push(p)
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(exit_signal(&mut qemu), Some(SIGTERM));
+        assert_eq!(progress, vec![StopProgress::ShuttingDown]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_graceful_then_force_kills_after_timeout() {
+        let temp_dir = TempDir::new().unwrap();
+        let qmp_path = temp_dir.path().join("qmp.socket");
+        let commands = spawn_qmp_recorder(&qmp_path);
+        let vm_manager = create_test_vm_manager();
+        let vm_id = VMId("stop-vm".to_string());
+        let mut qemu = spawn_stand_in_qemu();
+
+        let mut progress = Vec::new();
+        let mode = StopMode::GracefulThenForce(Duration::from_millis(300));
+        vm_manager
+            .stop_process(&vm_id, qemu.id(), Some(&qmp_path), mode, |p| {
+                progress.push(p)
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *commands.lock().unwrap(),
+            vec!["qmp_capabilities", "system_powerdown"]
+        );
+        assert_eq!(
+            progress,
+            vec![
+                StopProgress::ShuttingDown,
+                StopProgress::ForceStopping,
+                StopProgress::Stopped
+            ]
+        );
+        assert_eq!(exit_signal(&mut qemu), Some(SIGKILL));
+        assert!(!vm_manager.stopping.read().await.contains(&vm_id));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_force_stop_kills_without_powerdown() {
+        let temp_dir = TempDir::new().unwrap();
+        let qmp_path = temp_dir.path().join("qmp.socket");
+        let commands = spawn_qmp_recorder(&qmp_path);
+        let vm_manager = create_test_vm_manager();
+        let vm_id = VMId("stop-vm".to_string());
+        let mut qemu = spawn_stand_in_qemu();
+
+        let mut progress = Vec::new();
+        vm_manager
+            .stop_process(&vm_id, qemu.id(), Some(&qmp_path), StopMode::Force, |p| {
+                progress.push(p)
+            })
+            .await
+            .unwrap();
+
+        assert!(commands.lock().unwrap().is_empty());
+        assert_eq!(
+            progress,
+            vec![StopProgress::ForceStopping, StopProgress::Stopped]
+        );
+        assert_eq!(exit_signal(&mut qemu), Some(SIGKILL));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stop_reports_status_changes() {
+        let monitor = Arc::new(ProcessMonitor::new());
+        let mut vm_manager = create_test_vm_manager();
+        vm_manager.set_process_monitor(monitor.clone());
+        let mut changes = vm_manager.subscribe_status_changes();
+        let vm_id = VMId("stop-vm".to_string());
+        let mut qemu = spawn_stand_in_qemu();
+        let pid = qemu.id();
+
+        monitor.register_vm_process(vm_id.clone(), pid).await;
+        // Without QMP the powerdown is a SIGTERM, which ends the stand-in
+        // like a guest powering off
+        vm_manager
+            .stop_process(&vm_id, pid, None, StopMode::Graceful, |_| {})
+            .await
+            .unwrap();
+        qemu.wait().unwrap();
+        monitor.update_metrics().await;
+
+        let mut transitions = Vec::new();
+        while let Ok(change) = changes.try_recv() {
+            assert_eq!(change.vm_id, vm_id);
+            transitions.push((change.old, change.new));
+        }
+        assert_eq!(
+            transitions,
+            vec![
+                (VMStatus::Stopped, VMStatus::Running { pid }),
+                (VMStatus::Running { pid }, VMStatus::Stopping),
+                (VMStatus::Stopping, VMStatus::Stopped),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unexpected_exit_is_notified() {
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut vm_manager = create_test_vm_manager();
+        vm_manager.set_notifier(notifier.clone());
+        let vm_id = VMId("crashing-vm".to_string());
+
+        vm_manager
+            .track_exit(&vm_id, &VMStatus::Running { pid: 4242 })
+            .await;
+        assert!(notifier.notifications().is_empty());
+
+        vm_manager.track_exit(&vm_id, &VMStatus::Stopped).await;
+        vm_manager.track_exit(&vm_id, &VMStatus::Stopped).await;
+
+        assert_eq!(
+            notifier.notifications(),
+            vec![Notification::VmExitedUnexpectedly(vm_id)]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_monitored_exit_releases_console_port() {
+        let monitor = Arc::new(ProcessMonitor::new());
+        let mut vm_manager = create_test_vm_manager();
+        vm_manager.set_process_monitor(monitor.clone());
+        vm_manager.set_port_allocator(PortAllocator::with_ranges(45910..=45919, 45940..=45949));
+        let vm_id = VMId("monitored-vm".to_string());
+        let mut qemu = spawn_stand_in_qemu();
+        let pid = qemu.id();
+
+        vm_manager
+            .port_allocator()
+            .allocate(&vm_id, ConsoleProtocol::Spice, None)
+            .await
+            .unwrap();
+        vm_manager
+            .track_exit(&vm_id, &VMStatus::Running { pid })
+            .await;
+        monitor.register_vm_process(vm_id.clone(), pid).await;
+        vm_manager.watch_monitored_exit(&vm_id);
+
+        qemu.kill().unwrap();
+        qemu.wait().unwrap();
+        monitor.update_metrics().await;
+
+        let released = async {
+            while vm_manager.port_allocator().get_port(&vm_id).await.is_some() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), released)
+            .await
+            .expect("console port was not released");
+    }
+
+    #[tokio::test]
+    async fn test_requested_stop_is_not_notified() {
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut vm_manager = create_test_vm_manager();
+        vm_manager.set_notifier(notifier.clone());
+        let vm_id = VMId("stopped-vm".to_string());
+
+        vm_manager
+            .track_exit(&vm_id, &VMStatus::Running { pid: 4242 })
+            .await;
+        // No such process, but the stop was still asked for
+        let _ = vm_manager.stop_vm(&vm_id, StopMode::Force).await;
+        vm_manager.track_exit(&vm_id, &VMStatus::Stopped).await;
+
+        assert!(notifier.notifications().is_empty());
+    }
+
+    /// Test VM with a fresh 1G qcow2 disk, or `None` without qemu-img
+    fn create_test_vm_with_disk(temp_dir: &TempDir) -> Option<VM> {
+        let qemu_img = QemuImg::detect()?;
+        let status = Command::new(qemu_img.path())
+            .args(["create", "-q", "-f", "qcow2"])
+            .arg(temp_dir.path().join("disk.qcow2"))
+            .arg("1G")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let mut vm = create_test_vm(temp_dir);
+        vm.config.disk_img = Some(PathBuf::from("disk.qcow2"));
+        Some(vm)
+    }
+
+    #[tokio::test]
+    async fn test_resize_disk_grows_image() {
+        let temp_dir = TempDir::new().unwrap();
+        let Some(vm) = create_test_vm_with_disk(&temp_dir) else {
+            println!("qemu-img not found, skipping");
+            return;
+        };
+        let vm_manager = create_test_vm_manager();
+
+        vm_manager.resize_disk(&vm, "2G", false).await.unwrap();
+
+        let disk_path = temp_dir.path().join("disk.qcow2");
+        let size = QemuImg::detect().unwrap().virtual_size(&disk_path).unwrap();
+        assert_eq!(size, 2 << 30);
+        let config = ConfigParser::parse_quickemu_config(&vm.config_path).unwrap();
+        assert_eq!(config.disk_size, Some("2G".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_resize_disk_refuses_to_shrink() {
+        let temp_dir = TempDir::new().unwrap();
+        let Some(vm) = create_test_vm_with_disk(&temp_dir) else {
+            println!("qemu-img not found, skipping");
+            return;
+        };
+        let vm_manager = create_test_vm_manager();
+
+        let error = vm_manager
+            .resize_disk(&vm, "512M", false)
+            .await
+            .unwrap_err();
+
+        assert!(error.to_string().contains("Refusing to shrink"));
+        let disk_path = temp_dir.path().join("disk.qcow2");
+        let size = QemuImg::detect().unwrap().virtual_size(&disk_path).unwrap();
+        assert_eq!(size, 1 << 30);
+        let config = ConfigParser::parse_quickemu_config(&vm.config_path).unwrap();
+        assert_eq!(config.disk_size, None);
+
+        vm_manager.resize_disk(&vm, "512M", true).await.unwrap();
+        let size = QemuImg::detect().unwrap().virtual_size(&disk_path).unwrap();
+        assert_eq!(size, 512 << 20);
+    }
+
     #[tokio::test]
     async fn test_start_already_running_vm() {
         let temp_dir = TempDir::new().unwrap();
@@ -927,6 +2722,750 @@ ram="2G"
         assert!(result.unwrap_err().to_string().contains("already running"));
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_failing_pre_start_hook_aborts_start() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        vm.config.pre_start = Some("echo no bridge >&2; exit 3".to_string());
+        vm.config.post_stop = Some("echo tearing down".to_string());
+
+        let vm_manager = create_test_vm_manager();
+        let error = vm_manager.start_vm(&vm).await.unwrap_err();
+
+        assert!(format!("{error:#}").contains("pre_start hook failed"));
+        assert_eq!(vm_manager.port_allocator().get_port(&vm.id).await, None);
+        assert!(!vm_manager.post_stop_hooks.read().await.contains_key(&vm.id));
+        let log = fs::read_to_string(hook_log_path(&vm)).unwrap();
+        assert!(log.contains("[pre_start] echo no bridge"));
+        assert!(log.contains("no bridge\n"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_hook_output_is_captured() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        vm.config.pre_start = Some("echo creating tap on $BRIDGE".to_string());
+        vm.config.post_stop = Some("echo removing tap from $BRIDGE".to_string());
+        vm.config
+            .env
+            .insert("BRIDGE".to_string(), "br0".to_string());
+
+        let vm_manager = create_test_vm_manager();
+        vm_manager.start_vm(&vm).await.unwrap();
+        let log = fs::read_to_string(hook_log_path(&vm)).unwrap();
+        assert!(log.contains("creating tap on br0\n"));
+
+        vm_manager.run_post_stop_hook(&vm.id).await;
+        vm_manager.run_post_stop_hook(&vm.id).await;
+        let log = fs::read_to_string(hook_log_path(&vm)).unwrap();
+        assert_eq!(log.matches("removing tap from br0\n").count(), 1);
+
+        let _ = vm_manager.stop_vm(&vm.id, StopMode::Force).await;
+    }
+
+    #[test]
+    fn test_unsafe_env_is_rejected() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        vm.config
+            .env
+            .insert("LD_PRELOAD".to_string(), "/tmp/evil.so".to_string());
+
+        let vm_manager = create_test_vm_manager();
+        assert!(vm_manager.build_start_command(&vm, None, &[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ssh_command_from_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        vm.config.ssh_port = Some(22220);
+
+        let vm_manager = create_test_vm_manager();
+        assert_eq!(vm_manager.ssh_port(&vm.id).await, None);
+
+        vm_manager.start_vm(&vm).await.unwrap();
+        assert_eq!(vm_manager.ssh_port(&vm.id).await, Some(22220));
+        assert_eq!(
+            format_ssh_command(22220, "alice"),
+            "ssh -p 22220 alice@localhost"
+        );
+
+        // The echo stand-in for quickemu exits immediately, so there's no guest
+        assert_eq!(vm_manager.ssh_command(&vm.id).await, None);
+
+        let _ = vm_manager.stop_vm(&vm.id, StopMode::Force).await;
+        assert_eq!(vm_manager.ssh_port(&vm.id).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_ssh_times_out_on_closed_port() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let vm_manager = create_test_vm_manager();
+        let vm_id = VMId("ssh-vm".to_string());
+        vm_manager
+            .ssh_ports
+            .write()
+            .await
+            .insert(vm_id.clone(), port);
+
+        let started = std::time::Instant::now();
+        let result = vm_manager
+            .wait_for_ssh(&vm_id, Duration::from_millis(300))
+            .await;
+        assert!(result.unwrap_err().to_string().contains("Timed out"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let unknown = VMId("no-ssh-vm".to_string());
+        assert!(vm_manager
+            .wait_for_ssh(&unknown, Duration::from_millis(300))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_ssh_waits_for_banner() {
+        use tokio::io::AsyncWriteExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let _ = socket.write_all(b"SSH-2.0-OpenSSH_9.6\r\n").await;
+            }
+        });
+
+        let vm_manager = create_test_vm_manager();
+        let vm_id = VMId("ssh-vm".to_string());
+        vm_manager
+            .ssh_ports
+            .write()
+            .await
+            .insert(vm_id.clone(), port);
+
+        vm_manager
+            .wait_for_ssh(&vm_id, Duration::from_secs(5))
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_extra_qemu_args_reach_command_in_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        vm.config.display = DisplayProtocol::Vnc { port: 5901 };
+
+        let extra_args: Vec<String> = ["-device", "virtio-rng-pci", "-netdev", "user,id=net1"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let vm_manager = create_test_vm_manager();
+        let cmd = vm_manager
+            .build_start_command(&vm, Some(ConsoleEndpoint::Port(5901)), &extra_args)
+            .unwrap();
+        let args: Vec<String> = cmd
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+
+        assert_eq!(
+            qemu_args(&cmd).unwrap(),
+            format!(
+                "-vnc :1 -qmp unix:{},server=on,wait=off -device virtio-rng-pci -netdev user,id=net1",
+                qmp_socket_path(&vm).display()
+            )
+        );
+        assert!(!args.iter().any(|a| a == "--extra_args"));
+    }
+
+    #[test]
+    fn test_config_extra_args_left_to_quickemu() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        vm.config.display = DisplayProtocol::None;
+        vm.config.extra_args = vec!["-device".to_string(), "vfio-pci,host=01:00.0".to_string()];
+
+        let vm_manager = create_test_vm_manager();
+        let cmd = vm_manager
+            .build_start_command(&vm, None, &["-m".to_string(), "8G".to_string()])
+            .unwrap();
+        assert_eq!(qemu_args(&cmd).unwrap(), "-m 8G");
+
+        let cmd = vm_manager.build_start_command(&vm, None, &[]).unwrap();
+        assert_eq!(qemu_args(&cmd), None);
+        assert!(!cmd.get_envs().any(|(key, _)| key == SOURCE_FUNCTION_ENV));
+    }
+
+    /// Read `vm`'s config the way quickemu does, with the environment `cmd`
+    /// gives it, and return the `extra_args` QEMU ends up with
+    #[cfg(unix)]
+    fn quickemu_extra_args(cmd: &Command, vm: &VM) -> String {
+        quickemu_variable(cmd, vm, "extra_args")
+    }
+
+    /// `name` as quickemu sees it once it has sourced the VM's config
+    #[cfg(unix)]
+    fn quickemu_variable(cmd: &Command, vm: &VM, name: &str) -> String {
+        let mut bash = Command::new("bash");
+        bash.arg("-c")
+            .arg(r#"extra_args="${extra_args:-}"; source "$1"; printf '%s' "${!2}""#)
+            .arg("quickemu")
+            .arg(&vm.config_path)
+            .arg(name)
+            .env_clear();
+        for (key, value) in cmd.get_envs() {
+            if let Some(value) = value {
+                bash.env(key, value);
+            }
+        }
+        let output = bash.output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_path_placeholders_set_for_quickemu() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        fs::write(
+            &vm.config_path,
+            "guest_os=\"linux\"\ndisk_img=\"${VM_DIR}/${VM_NAME}.qcow2\"\n",
+        )
+        .unwrap();
+        vm.config = ConfigParser::parse_quickemu_config(&vm.config_path).unwrap();
+        vm.config.display = DisplayProtocol::None;
+
+        let vm_manager = create_test_vm_manager();
+        let cmd = vm_manager.build_start_command(&vm, None, &[]).unwrap();
+        assert_eq!(
+            Some(PathBuf::from(quickemu_variable(&cmd, &vm, "disk_img"))),
+            vm.config.disk_img
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_config_extra_args_keep_manager_args() {
+        let temp_dir = TempDir::new().unwrap();
+        let share_dir = temp_dir.path().join("src");
+        fs::create_dir(&share_dir).unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        fs::write(
+            &vm.config_path,
+            "guest_os=\"linux\"\nextra_args=\"-device virtio-rng-pci\"\n",
+        )
+        .unwrap();
+        vm.config = ConfigParser::parse_quickemu_config(&vm.config_path).unwrap();
+        vm.config.display = DisplayProtocol::Vnc { port: 5901 };
+        vm.config.shared_folders = vec![SharedFolder {
+            host_path: share_dir.clone(),
+            tag: "src".to_string(),
+            readonly: false,
+        }];
+
+        let vm_manager = create_test_vm_manager();
+        let cmd = vm_manager
+            .build_start_command(&vm, Some(ConsoleEndpoint::Port(5901)), &[])
+            .unwrap();
+        assert_eq!(
+            quickemu_extra_args(&cmd, &vm),
+            format!(
+                "-device virtio-rng-pci -vnc :1 -qmp unix:{},server=on,wait=off \
+                 -virtfs local,path={},mount_tag=src,security_model=mapped-xattr",
+                qmp_socket_path(&vm).display(),
+                share_dir.display()
+            )
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_gpu_passthrough_keeps_manager_args() {
+        let temp_dir = TempDir::new().unwrap();
+        let sysfs = crate::services::gpu_passthrough::tests::mock_sysfs();
+        let mut vm = create_test_vm(&temp_dir);
+        GpuPassthrough::with_sysfs_root(sysfs.path().to_path_buf())
+            .configure(&vm.config_path, &vm.config.extra_args, "01:00.0")
+            .unwrap();
+        vm.config = ConfigParser::parse_quickemu_config(&vm.config_path).unwrap();
+        vm.config.display = DisplayProtocol::Vnc { port: 5901 };
+        vm.config.boot_order = vec![BootDevice::Cdrom, BootDevice::Disk];
+
+        let vm_manager = create_test_vm_manager();
+        let cmd = vm_manager
+            .build_start_command(&vm, Some(ConsoleEndpoint::Port(5901)), &[])
+            .unwrap();
+        assert_eq!(
+            quickemu_extra_args(&cmd, &vm),
+            format!(
+                "-device vfio-pci,host=0000:01:00.0 -vnc :1 \
+                 -qmp unix:{},server=on,wait=off -boot order=dc",
+                qmp_socket_path(&vm).display()
+            )
+        );
+    }
+
+    #[test]
+    fn test_config_extra_args_with_shell_metacharacters_rejected() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        vm.config.display = DisplayProtocol::None;
+        vm.config.extra_args = vec!["-name".to_string(), "vm;rm".to_string()];
+
+        let vm_manager = create_test_vm_manager();
+        let result = vm_manager.build_start_command(&vm, None, &[]);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("unsupported character ';'"));
+    }
+
+    #[test]
+    fn test_resource_limits_run_quickemu_in_systemd_scope() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        vm.config.display = DisplayProtocol::None;
+        vm.config.cpu_quota_percent = Some(150);
+        vm.config.mem_max = Some("4G".to_string());
+
+        let mut vm_manager = create_test_vm_manager();
+        vm_manager.set_systemd_run(Some(SystemdRun::new(
+            PathBuf::from("/usr/bin/systemd-run"),
+            true,
+        )));
+        let cmd = vm_manager.build_start_command(&vm, None, &[]).unwrap();
+        let args: Vec<String> = cmd
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+
+        assert_eq!(cmd.get_program(), "/usr/bin/systemd-run");
+        assert_eq!(
+            args[..11],
+            [
+                "--user",
+                "--scope",
+                "--quiet",
+                "--collect",
+                "-p",
+                "CPUQuota=150%",
+                "-p",
+                "MemoryMax=4G",
+                "--",
+                "/usr/bin/echo",
+                "--vm",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_uefi_config_requests_uefi_firmware() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        fs::write(
+            &vm.config_path,
+            "guest_os=\"windows\"\nboot=\"uefi\"\nsecureboot=\"on\"\nboot_order=\"cdrom,disk\"\n",
+        )
+        .unwrap();
+        vm.config = ConfigParser::parse_quickemu_config(&vm.config_path).unwrap();
+        vm.config.display = DisplayProtocol::None;
+
+        let vm_manager = create_test_vm_manager();
+        let cmd = vm_manager.build_start_command(&vm, None, &[]).unwrap();
+        assert_eq!(qemu_args(&cmd).unwrap(), "-boot order=dc");
+
+        // quickemu itself only understands `efi`
+        ConfigParser::set_firmware(&vm.config_path, vm.config.firmware, vm.config.secure_boot)
+            .unwrap();
+        let content = fs::read_to_string(&vm.config_path).unwrap();
+        assert!(content.contains("boot=\"efi\"\n"));
+        assert!(content.contains("secureboot=\"on\"\n"));
+
+        vm.config.firmware = Some(Firmware::Bios);
+        let err = vm_manager.build_start_command(&vm, None, &[]).unwrap_err();
+        assert!(err.to_string().contains("Secure boot requires UEFI"));
+    }
+
+    #[test]
+    fn test_shared_folders_become_virtfs_args() {
+        let temp_dir = TempDir::new().unwrap();
+        let share_dir = temp_dir.path().join("src");
+        fs::create_dir(&share_dir).unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        vm.config.display = DisplayProtocol::None;
+        vm.config.shared_folders = vec![
+            SharedFolder {
+                host_path: share_dir.clone(),
+                tag: "src".to_string(),
+                readonly: false,
+            },
+            SharedFolder {
+                host_path: temp_dir.path().to_path_buf(),
+                tag: "vm_dir".to_string(),
+                readonly: true,
+            },
+        ];
+
+        let vm_manager = create_test_vm_manager();
+        let cmd = vm_manager.build_start_command(&vm, None, &[]).unwrap();
+        let expected = format!(
+            "-virtfs local,path={},mount_tag=src,security_model=mapped-xattr \
+             -virtfs local,path={},mount_tag=vm_dir,security_model=mapped-xattr,readonly=on",
+            share_dir.display(),
+            temp_dir.path().display()
+        );
+        assert_eq!(qemu_args(&cmd).unwrap(), expected);
+        assert!(shared_folder_warning(&vm.config)
+            .unwrap()
+            .contains("mount -t 9p"));
+
+        // A missing host directory stops the start before quickemu runs
+        vm.config.shared_folders[0].host_path = temp_dir.path().join("missing");
+        let err = vm_manager.build_start_command(&vm, None, &[]).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Shared folder 'src' does not exist"));
+
+        vm.config.shared_folders[0].host_path = share_dir;
+        vm.config.shared_folders[0].tag = "my-src".to_string();
+        assert!(vm_manager.build_start_command(&vm, None, &[]).is_err());
+    }
+
+    #[test]
+    fn test_no_resource_limits_runs_quickemu_directly() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        vm.config.display = DisplayProtocol::None;
+
+        let mut vm_manager = create_test_vm_manager();
+        vm_manager.set_systemd_run(Some(SystemdRun::new(
+            PathBuf::from("/usr/bin/systemd-run"),
+            true,
+        )));
+        let cmd = vm_manager.build_start_command(&vm, None, &[]).unwrap();
+        assert_eq!(cmd.get_program(), "/usr/bin/echo");
+
+        // Without systemd the limits are skipped rather than failing the start
+        vm.config.mem_max = Some("4G".to_string());
+        vm_manager.set_systemd_run(None);
+        let cmd = vm_manager.build_start_command(&vm, None, &[]).unwrap();
+        assert_eq!(cmd.get_program(), "/usr/bin/echo");
+        assert_eq!(cmd.get_args().next().unwrap(), "--vm");
+    }
+
+    #[test]
+    fn test_spice_socket_replaces_tcp_port() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        let vm_manager = create_test_vm_manager();
+
+        // TCP unless the config asks for a socket
+        assert_eq!(VMManager::spice_socket(&vm), None);
+
+        vm.config.spice_socket = true;
+        let path = VMManager::spice_socket(&vm).unwrap();
+        assert_eq!(
+            path,
+            temp_dir.path().join("test-vm").join("test-vm-spice.socket")
+        );
+        assert_eq!(
+            qmp_socket_path(&vm),
+            temp_dir.path().join("test-vm").join("test-vm-qmp.socket")
+        );
+
+        let cmd = vm_manager
+            .build_start_command(&vm, Some(ConsoleEndpoint::SpiceSocket(path.clone())), &[])
+            .unwrap();
+        let args: Vec<String> = cmd
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        assert!(!args.iter().any(|arg| arg == "--spice-port"));
+        assert!(args.windows(2).any(|w| w == ["--display", "none"]));
+        assert_eq!(
+            qemu_args(&cmd).unwrap(),
+            format!(
+                "-spice unix=on,addr={},disable-ticketing=on -qmp unix:{},server=on,wait=off",
+                path.display(),
+                qmp_socket_path(&vm).display()
+            )
+        );
+
+        // A path quickemu would split up falls back to TCP
+        vm.config_path = PathBuf::from("/tmp/my vms/test-vm.conf");
+        assert_eq!(VMManager::spice_socket(&vm), None);
+    }
+
+    #[cfg(unix)]
+    /// Serve one QMP connection at `path` that, like QEMU, can only set the
+    /// password of the consoles in `password_protocols`. Returns the
+    /// commands it was sent.
+    fn spawn_qmp_password_server(
+        path: &Path,
+        password_protocols: &'static [&'static str],
+    ) -> tokio::task::JoinHandle<Vec<serde_json::Value>> {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::UnixListener::bind(path).unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            writer
+                .write_all(b"{\"QMP\": {\"version\": {}, \"capabilities\": []}}\n")
+                .await
+                .unwrap();
+            let mut commands = Vec::new();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let command: serde_json::Value = serde_json::from_str(&line).unwrap();
+                let reply = match command["execute"].as_str() {
+                    Some("qmp_capabilities") => json!({ "return": {} }),
+                    Some("set_password" | "expire_password") => {
+                        let protocol = command["arguments"]["protocol"].as_str().unwrap_or("");
+                        if password_protocols.contains(&protocol) {
+                            json!({ "return": {} })
+                        } else {
+                            json!({
+                                "error": { "class": "GenericError", "desc": "Could not set password" }
+                            })
+                        }
+                    }
+                    Some(other) => json!({
+                        "error": {
+                            "class": "CommandNotFound",
+                            "desc": format!("The command {other} has not been found")
+                        }
+                    }),
+                    None => json!({
+                        "error": { "class": "GenericError", "desc": "Invalid JSON syntax" }
+                    }),
+                };
+                commands.push(command);
+                // Events may arrive before a command's reply
+                writer
+                    .write_all(
+                        format!("{{\"event\": \"SPICE_DISCONNECTED\"}}\n{reply}\n").as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+            }
+            commands
+        })
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_set_console_password_over_qmp() {
+        let temp_dir = TempDir::new().unwrap();
+        let vm = create_test_vm(&temp_dir);
+        let vm_manager = create_test_vm_manager();
+
+        // Only VMs with a console can have their password changed
+        let err = vm_manager
+            .set_console_password(&vm.id, "s3cret", None)
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("does not have a SPICE or VNC display"));
+
+        let qmp_path = temp_dir.path().join("qmp.socket");
+        let server = spawn_qmp_password_server(&qmp_path, &["spice"]);
+
+        vm_manager
+            .console_sockets
+            .write()
+            .await
+            .insert(vm.id.clone(), spice_socket_path(&vm));
+        vm_manager
+            .qmp_sockets
+            .write()
+            .await
+            .insert(vm.id.clone(), qmp_path);
+        vm_manager
+            .set_console_password(&vm.id, "s3cret", Some(Duration::from_secs(300)))
+            .await
+            .unwrap();
+
+        let commands = server.await.unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                serde_json::json!({ "execute": "qmp_capabilities" }),
+                serde_json::json!({
+                    "execute": "set_password",
+                    "arguments": { "protocol": "spice", "password": "s3cret" }
+                }),
+                serde_json::json!({
+                    "execute": "expire_password",
+                    "arguments": { "protocol": "spice", "time": "+300" }
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_vnc_password_is_enabled_with_console_password() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        vm.config.display = DisplayProtocol::Vnc { port: 5901 };
+        let vm_manager = create_test_vm_manager();
+
+        let vnc_arg = |vm: &VM| {
+            let cmd = vm_manager
+                .build_start_command(vm, Some(ConsoleEndpoint::Port(5901)), &[])
+                .unwrap();
+            qemu_args(&cmd).unwrap().split(' ').nth(1).unwrap().to_string()
+        };
+
+        assert_eq!(vnc_arg(&vm), ":1");
+        vm.config.spice_password = Some("s3cret".to_string());
+        assert_eq!(vnc_arg(&vm), ":1,password=on");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_vnc_password_fails_without_password_enabled() {
+        let temp_dir = TempDir::new().unwrap();
+        let vm = create_test_vm(&temp_dir);
+        let vm_manager = create_test_vm_manager();
+
+        // A VNC console started without password=on
+        let qmp_path = temp_dir.path().join("qmp.socket");
+        let server = spawn_qmp_password_server(&qmp_path, &["spice"]);
+        vm_manager
+            .port_allocator()
+            .allocate(&vm.id, ConsoleProtocol::Vnc, None)
+            .await
+            .unwrap();
+        vm_manager
+            .qmp_sockets
+            .write()
+            .await
+            .insert(vm.id.clone(), qmp_path);
+
+        let err = vm_manager
+            .set_console_password(&vm.id, "s3cret", None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Could not set password"));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_matches_processes_by_config_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        vm.config.ssh_port = Some(2222);
+        let mut other = create_test_vm(&temp_dir);
+        other.id = VMId("test-vm-2".to_string());
+        other.config_path = temp_dir.path().join("test-vm-2.conf");
+        let unrelated = VMId("not-running".to_string());
+        let mut stopped = create_test_vm(&temp_dir);
+        stopped.id = unrelated.clone();
+        stopped.config_path = temp_dir.path().join("not-running.conf");
+
+        // QEMU serves QMP in the VM directory once it is running
+        let vm_dir = temp_dir.path().join("test-vm");
+        fs::create_dir(&vm_dir).unwrap();
+        fs::write(qmp_socket_path(&vm), "").unwrap();
+
+        let disk_arg = |dir: &str| {
+            format!(
+                "file={}/disk.qcow2,format=qcow2",
+                temp_dir.path().join(dir).display()
+            )
+        };
+        let own_pid = std::process::id();
+        let processes = vec![
+            QemuProcess {
+                pid: 4242,
+                cmd: vec![
+                    "qemu-system-x86_64".to_string(),
+                    "-drive".to_string(),
+                    disk_arg("test-vm-2"),
+                ],
+            },
+            QemuProcess {
+                pid: own_pid,
+                cmd: vec![
+                    "qemu-system-x86_64".to_string(),
+                    "-pidfile".to_string(),
+                    format!("{}/test-vm.pid", vm_dir.display()),
+                    "-drive".to_string(),
+                    disk_arg("test-vm"),
+                ],
+            },
+            QemuProcess {
+                pid: 99,
+                cmd: vec![
+                    "qemu-system-aarch64".to_string(),
+                    "-drive".to_string(),
+                    "file=/elsewhere/disk.qcow2".to_string(),
+                ],
+            },
+        ];
+
+        let monitor = Arc::new(ProcessMonitor::new());
+        let mut vm_manager = create_test_vm_manager();
+        vm_manager.set_process_monitor(monitor.clone());
+        let vms = vec![vm.clone(), other.clone(), stopped];
+        let adopted = vm_manager.adopt_vm_processes(&vms, &processes).await;
+
+        // `test-vm/` is not mistaken for a prefix of `test-vm-2/`
+        assert_eq!(
+            adopted,
+            vec![(other.id.clone(), 4242), (vm.id.clone(), own_pid)]
+        );
+        assert!(adopted.iter().all(|(id, _)| id != &unrelated));
+
+        assert_eq!(vm_manager.ssh_port(&vm.id).await, Some(2222));
+        // A VM started next can't take the adopted VM's console port
+        assert_eq!(
+            vm_manager.port_allocator().get_allocation(&vm.id).await,
+            Some((5930, ConsoleProtocol::Spice))
+        );
+        assert_eq!(
+            vm_manager.qmp_sockets.read().await.get(&vm.id),
+            Some(&qmp_socket_path(&vm))
+        );
+        assert!(!vm_manager.qmp_sockets.read().await.contains_key(&other.id));
+
+        monitor.update_metrics().await;
+        assert!(monitor.get_vm_metrics(&vm.id).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_no_console_socket_for_vm_not_started_here() {
+        let vm_manager = create_test_vm_manager();
+        let socket = vm_manager
+            .detect_console_socket(&VMId("unknown".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(socket, None);
+    }
+
+    #[test]
+    fn test_validate_extra_qemu_args() {
+        let ok: Vec<String> = vec!["-device".into(), "usb-host,vendorid=0x1234".into()];
+        assert!(validate_extra_qemu_args(&ok).is_ok());
+
+        for bad in ["-device foo", "$(reboot)", "a;b", "`id`", ""] {
+            assert!(
+                validate_extra_qemu_args(&[bad.to_string()]).is_err(),
+                "{bad:?} should be rejected"
+            );
+        }
+    }
+
     #[tokio::test]
     async fn test_is_vm_running() {
         let vm_manager = create_test_vm_manager();
@@ -946,6 +3485,7 @@ ram="2G"
             ram: "4G".to_string(),
             disk_size: "20G".to_string(),
             cpu_cores: 2,
+            download_rate_limit: None,
         };
 
         assert_eq!(template.os, "ubuntu");
@@ -953,6 +3493,362 @@ ram="2G"
         assert_eq!(template.name, "Ubuntu 22.04 Desktop".to_string());
     }
 
+    #[test]
+    fn test_quickget_env_applies_download_rate_limit() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut template = VMTemplate {
+            name: "debian-12".to_string(),
+            os: "debian".to_string(),
+            version: "12".to_string(),
+            edition: None,
+            ram: "4G".to_string(),
+            disk_size: "64G".to_string(),
+            cpu_cores: 2,
+            download_rate_limit: None,
+        };
+        assert!(quickget_env(&template, temp_dir.path()).unwrap().is_empty());
+        assert_eq!(quickget_args(&template), vec!["debian", "12"]);
+
+        template.download_rate_limit = Some("2m".to_string());
+        let curl_home = temp_dir.path().join("curl-2M");
+        assert_eq!(
+            quickget_env(&template, temp_dir.path()).unwrap(),
+            vec![("CURL_HOME".to_string(), curl_home.clone())]
+        );
+        let curlrc = fs::read_to_string(curl_home.join(".curlrc")).unwrap();
+        assert!(curlrc.ends_with("limit-rate = 2M\n"), "{curlrc}");
+        assert_eq!(quickget_args(&template), vec!["debian", "12"]);
+
+        template.download_rate_limit = Some("fast".to_string());
+        assert!(quickget_env(&template, temp_dir.path()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_windows_11_gets_tpm_and_secure_boot() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        // quickget writes the config into the directory it runs in
+        let quickget = temp_dir.path().join("quickget");
+        fs::write(
+            &quickget,
+            "#!/bin/sh\nprintf 'guest_os=\"windows\"\\nsecureboot=\"off\"\\n' > \"$1-$2.conf\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&quickget, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut vm_manager = VMManager::with_paths(PathBuf::from("/usr/bin/echo"), Some(quickget));
+        vm_manager.set_swtpm_path(Some(PathBuf::from("/usr/bin/swtpm")));
+        let mut template = VMTemplate {
+            name: "windows-11".to_string(),
+            os: "windows".to_string(),
+            version: "11".to_string(),
+            edition: None,
+            ram: "8G".to_string(),
+            disk_size: "64G".to_string(),
+            cpu_cores: 4,
+            download_rate_limit: None,
+        };
+
+        let config_path = vm_manager
+            .create_vm_from_template(&template, temp_dir.path())
+            .await
+            .unwrap();
+        let config = ConfigParser::parse_quickemu_config(&config_path).unwrap();
+        assert!(config.tpm);
+        assert!(config.secure_boot);
+        assert_eq!(config.firmware, Some(Firmware::Uefi));
+
+        // Without swtpm the config is still set up, with a warning
+        let warning = apply_guest_requirements(&template, &config_path, None)
+            .unwrap()
+            .unwrap();
+        assert!(warning.contains("swtpm isn't installed"), "{warning}");
+
+        template.version = "10".to_string();
+        let content = fs::read_to_string(&config_path).unwrap();
+        assert_eq!(
+            apply_guest_requirements(&template, &config_path, None).unwrap(),
+            None
+        );
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), content);
+    }
+
+    #[test]
+    fn test_autostart_vms_selects_flagged_vms() {
+        let temp_dir = TempDir::new().unwrap();
+        let plain = create_test_vm(&temp_dir);
+
+        let mut flagged = create_test_vm(&temp_dir);
+        flagged.id = VMId("flagged".to_string());
+        flagged.config.autostart = true;
+
+        let mut running = create_test_vm(&temp_dir);
+        running.id = VMId("running".to_string());
+        running.config.autostart = true;
+        running.status = VMStatus::Running { pid: 12345 };
+
+        let vms = vec![plain, flagged, running];
+        let selected: Vec<&VMId> = VMManager::autostart_vms(&vms)
+            .into_iter()
+            .map(|vm| &vm.id)
+            .collect();
+        assert_eq!(selected, vec![&VMId("flagged".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_autostart_staggers_launches() {
+        let temp_dir = TempDir::new().unwrap();
+        let vms: Vec<VM> = (0..3)
+            .map(|i| {
+                let mut vm = create_test_vm(&temp_dir);
+                vm.id = VMId(format!("vm-{i}"));
+                vm
+            })
+            .collect();
+
+        let delay = Duration::from_millis(50);
+        let mut launches = Vec::new();
+        let started = start_staggered(vms.iter().collect(), delay, |vm| {
+            launches.push((vm.id.clone(), std::time::Instant::now()));
+            let fail = launches.len() == 2;
+            async move {
+                if fail {
+                    Err(anyhow!("boom"))
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(
+            started,
+            vec![VMId("vm-0".to_string()), VMId("vm-2".to_string())]
+        );
+        assert_eq!(launches.len(), 3);
+        for pair in launches.windows(2) {
+            assert!(pair[1].1.duration_since(pair[0].1) >= delay);
+        }
+    }
+
+    fn queued_vms(temp_dir: &TempDir, count: usize, ram: &str) -> Vec<VM> {
+        (0..count)
+            .map(|i| {
+                let mut vm = create_test_vm(temp_dir);
+                vm.id = VMId(format!("vm-{i}"));
+                vm.config.ram = ram.to_string();
+                vm
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_start_queue_respects_concurrency_limit() {
+        let temp_dir = TempDir::new().unwrap();
+        let vms = queued_vms(&temp_dir, 5, "2G");
+        let active = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let peak = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        let results = start_queued(
+            vms.iter().collect(),
+            2,
+            || None,
+            |_| None,
+            |vm| {
+                let active = active.clone();
+                let peak = peak.clone();
+                let fail = vm.id.0 == "vm-3";
+                async move {
+                    let now = active.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, std::sync::atomic::Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    active.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+                    if fail {
+                        Err(anyhow!("boom"))
+                    } else {
+                        Ok(())
+                    }
+                }
+            },
+        )
+        .await;
+
+        assert_eq!(peak.load(std::sync::atomic::Ordering::SeqCst), 2);
+        let outcomes: Vec<(String, bool)> = results
+            .iter()
+            .map(|(id, result)| (id.0.clone(), result.is_ok()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                ("vm-0".to_string(), true),
+                ("vm-1".to_string(), true),
+                ("vm-2".to_string(), true),
+                ("vm-3".to_string(), false),
+                ("vm-4".to_string(), true),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_start_queue_defers_when_memory_is_low() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut vms = queued_vms(&temp_dir, 2, "2G");
+        vms.extend(queued_vms(&temp_dir, 1, "8G"));
+        vms[2].id = VMId("too-big".to_string());
+        let launches = std::sync::Mutex::new(Vec::new());
+        let record = &launches;
+
+        // Room for one 2G VM at a time, never for the 8G one. The VMs stop
+        // right after starting, giving their memory back.
+        let results = start_queued(
+            vms.iter().collect(),
+            3,
+            || Some(3 << 30),
+            |_| None,
+            move |vm| {
+                record.lock().unwrap().push(format!("start {}", vm.id.0));
+                async move {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    record.lock().unwrap().push(format!("done {}", vm.id.0));
+                    Ok(())
+                }
+            },
+        )
+        .await;
+
+        assert_eq!(
+            launches.into_inner().unwrap(),
+            vec!["start vm-0", "done vm-0", "start vm-1", "done vm-1"]
+        );
+        assert!(results[0].1.is_ok());
+        assert!(results[1].1.is_ok());
+        let refused = results[2].1.as_ref().unwrap_err().to_string();
+        assert!(refused.contains("Not enough free memory"), "{refused}");
+    }
+
+    #[tokio::test]
+    async fn test_start_queue_keeps_memory_of_started_vms() {
+        let temp_dir = TempDir::new().unwrap();
+        let vms = queued_vms(&temp_dir, 3, "2048");
+        let outcomes = |results: Vec<(VMId, Result<()>)>| -> Vec<bool> {
+            results.iter().map(|(_, result)| result.is_ok()).collect()
+        };
+
+        // Free memory doesn't drop until QEMU touches the guest's RAM, so the
+        // running VMs still count against it
+        let results = start_queued(
+            vms.iter().collect(),
+            1,
+            || Some(5 << 30),
+            |_| Some(0),
+            |_| async { Ok(()) },
+        )
+        .await;
+        assert_eq!(outcomes(results), vec![true, true, false]);
+
+        // Until their QEMU holds the memory itself
+        let results = start_queued(
+            vms.iter().collect(),
+            1,
+            || Some(5 << 30),
+            |_| Some(1 << 30),
+            |_| async { Ok(()) },
+        )
+        .await;
+        assert_eq!(outcomes(results), vec![true, true, true]);
+
+        // Or they stop
+        let results = start_queued(
+            vms.iter().collect(),
+            1,
+            || Some(5 << 30),
+            |vm_id| (vm_id.0 != "vm-0").then_some(0),
+            |_| async { Ok(()) },
+        )
+        .await;
+        assert_eq!(outcomes(results), vec![true, true, true]);
+    }
+
+    #[test]
+    fn test_parse_ram_size() {
+        assert_eq!(parse_ram_size("2048").unwrap(), 2 << 30);
+        assert_eq!(parse_ram_size("\"4G\"").unwrap(), 4 << 30);
+        assert_eq!(parse_ram_size("512M").unwrap(), 512 << 20);
+        assert!(parse_ram_size("").is_err());
+        assert!(parse_ram_size("lots").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_embedded_console_uses_config_password() {
+        use crate::services::port_allocator::VNC_PORT_RANGE;
+        use spice_client::test_utils::MockSpiceServer;
+        use std::os::unix::process::CommandExt;
+
+        // Pick a free port for the console and only let it be allocated
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut vm_manager = create_test_vm_manager();
+        vm_manager.set_port_allocator(PortAllocator::with_ranges(VNC_PORT_RANGE, port..=port));
+
+        let temp_dir = TempDir::new().unwrap();
+        let mut vm = create_test_vm(&temp_dir);
+        vm.id = VMId("embedded-console-test-vm".to_string());
+        vm.config_path = temp_dir.path().join("embedded-console-test-vm.conf");
+        fs::write(
+            &vm.config_path,
+            format!(
+                "guest_os=\"ubuntu\"\ndisplay_server=\"spice\"\nspice_port={port}\nspice_password=\"hunter2\"\n"
+            ),
+        )
+        .unwrap();
+        vm.config = ConfigParser::parse_quickemu_config(&vm.config_path).unwrap();
+        vm_manager
+            .port_allocator()
+            .allocate(&vm.id, ConsoleProtocol::Spice, Some(port))
+            .await
+            .unwrap();
+        let server = MockSpiceServer::with_password(&format!("127.0.0.1:{port}"), "hunter2")
+            .await
+            .unwrap();
+
+        // A process standing in for the VM's QEMU, so the VM counts as running
+        let mut qemu = Command::new("sh")
+            .arg0("qemu-system-x86_64")
+            .args(["-c", "sleep 30; :", "-name", &vm.id.0])
+            .spawn()
+            .unwrap();
+        let client = vm_manager.open_embedded_console(&vm).await;
+        qemu.kill().unwrap();
+        qemu.wait().unwrap();
+
+        let client = client.unwrap();
+        assert!(client.server_info().is_some());
+        assert_eq!(server.received_tickets().await, vec!["hunter2".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_embedded_console_requires_running_spice_vm() {
+        let temp_dir = TempDir::new().unwrap();
+        let vm_manager = create_test_vm_manager();
+        let mut vm = create_test_vm(&temp_dir);
+
+        let err = vm_manager.open_embedded_console(&vm).await.err().unwrap();
+        assert!(err
+            .to_string()
+            .contains("does not have an active SPICE server"));
+
+        vm.config.display = DisplayProtocol::Vnc { port: 5900 };
+        let err = vm_manager.open_embedded_console(&vm).await.err().unwrap();
+        assert!(err.to_string().contains("does not use SPICE"));
+    }
+
     #[tokio::test]
     async fn test_cleanup_finished_processes() {
         let vm_manager = create_test_vm_manager();
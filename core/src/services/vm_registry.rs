@@ -0,0 +1,267 @@
+use crate::models::{VMId, VMStatus, VM};
+use crate::services::discovery::DiscoveryEvent;
+use crate::services::vm_manager::VMManager;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use tokio::sync::{mpsc, RwLock};
+
+/// Shared, concurrency-safe cache of known VMs.
+///
+/// Frontends hold a clone of the registry instead of keeping their own
+/// cache; all clones see the same entries.
+#[derive(Clone, Default)]
+pub struct VmRegistry {
+    vms: Arc<RwLock<HashMap<VMId, VM>>>,
+}
+
+impl VmRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a VM or replace the existing entry with the same id
+    pub async fn upsert(&self, vm: VM) {
+        self.vms.write().await.insert(vm.id.clone(), vm);
+    }
+
+    pub async fn remove(&self, vm_id: &VMId) -> Option<VM> {
+        self.vms.write().await.remove(vm_id)
+    }
+
+    pub async fn get(&self, vm_id: &VMId) -> Option<VM> {
+        self.vms.read().await.get(vm_id).cloned()
+    }
+
+    pub async fn len(&self) -> usize {
+        self.vms.read().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.vms.read().await.is_empty()
+    }
+
+    /// All known VMs, sorted by name
+    pub async fn snapshot(&self) -> Vec<VM> {
+        let mut vms: Vec<VM> = self.vms.read().await.values().cloned().collect();
+        vms.sort_by(|a, b| a.name.cmp(&b.name));
+        vms
+    }
+
+    /// VMs that have `tag`, sorted by name
+    pub async fn with_tag(&self, tag: &str) -> Vec<VM> {
+        let mut vms = self.snapshot().await;
+        vms.retain(|vm| vm.has_tag(tag));
+        vms
+    }
+
+    /// Every tag used by a known VM, sorted
+    pub async fn tags(&self) -> Vec<String> {
+        let vms = self.vms.read().await;
+        let tags: BTreeSet<&String> = vms.values().flat_map(|vm| &vm.config.tags).collect();
+        tags.into_iter().cloned().collect()
+    }
+
+    /// VMs grouped by tag, each group sorted by name. A VM with several tags
+    /// is in each of their groups; untagged VMs are left out.
+    pub async fn group_by_tag(&self) -> BTreeMap<String, Vec<VM>> {
+        let mut groups: BTreeMap<String, Vec<VM>> = BTreeMap::new();
+        for vm in self.snapshot().await {
+            for tag in &vm.config.tags {
+                groups.entry(tag.clone()).or_default().push(vm.clone());
+            }
+        }
+        groups
+    }
+
+    /// Apply a discovery event to the registry
+    pub async fn apply_event(&self, event: DiscoveryEvent) {
+        match event {
+            DiscoveryEvent::VMAdded(vm) | DiscoveryEvent::VMUpdated(vm) => self.upsert(vm).await,
+            DiscoveryEvent::VMRemoved(id) => {
+                self.remove(&id).await;
+            }
+        }
+    }
+
+    /// Keep the registry current by applying every event from `event_rx`
+    pub fn subscribe(
+        &self,
+        mut event_rx: mpsc::UnboundedReceiver<DiscoveryEvent>,
+    ) -> tokio::task::JoinHandle<()> {
+        let registry = self.clone();
+        tokio::spawn(async move {
+            while let Some(event) = event_rx.recv().await {
+                registry.apply_event(event).await;
+            }
+        })
+    }
+
+    /// Refresh the status of every VM from the running processes.
+    ///
+    /// Statuses are looked up without holding the lock, so VMs added or
+    /// removed in the meantime are left alone rather than overwritten.
+    pub async fn update_statuses(&self, vm_manager: &VMManager) {
+        let ids: Vec<VMId> = self.vms.read().await.keys().cloned().collect();
+
+        let mut statuses = HashMap::with_capacity(ids.len());
+        for id in ids {
+            let status = vm_manager.get_vm_status(&id).await;
+            statuses.insert(id, status);
+        }
+
+        self.apply_statuses(statuses).await;
+    }
+
+    /// Set the status of the given VMs; ids no longer in the registry are ignored
+    pub async fn apply_statuses(&self, statuses: HashMap<VMId, VMStatus>) {
+        let mut vms = self.vms.write().await;
+        for (id, status) in statuses {
+            if let Some(vm) = vms.get_mut(&id) {
+                vm.status = status;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{DisplayProtocol, VMConfig};
+    use std::path::PathBuf;
+    use std::time::SystemTime;
+
+    fn tagged_vm(id: &str, tags: &[&str]) -> VM {
+        let mut vm = test_vm(id);
+        vm.config.tags = tags.iter().map(|tag| tag.to_string()).collect();
+        vm
+    }
+
+    fn test_vm(id: &str) -> VM {
+        VM {
+            id: VMId(id.to_string()),
+            name: id.to_string(),
+            config_path: PathBuf::from(format!("/tmp/{id}.conf")),
+            config: VMConfig {
+                guest_os: "linux".to_string(),
+                disk_img: None,
+                iso: None,
+                ram: "2G".to_string(),
+                cpu_cores: 2,
+                disk_size: None,
+                display: DisplayProtocol::Spice { port: 5930 },
+                ssh_port: None,
+                extra_args: Vec::new(),
+                autostart: false,
+                cpu_quota_percent: None,
+                mem_max: None,
+                spice_socket: false,
+                spice_password: None,
+                tags: Vec::new(),
+                firmware: None,
+                secure_boot: false,
+                tpm: false,
+                boot_order: Vec::new(),
+                pre_start: None,
+                post_stop: None,
+                env: BTreeMap::new(),
+                shared_folders: Vec::new(),
+                raw_config: String::new(),
+            },
+            status: VMStatus::Stopped,
+            last_modified: SystemTime::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_upsert_and_remove() {
+        let registry = VmRegistry::new();
+
+        let mut tasks = Vec::new();
+        for i in 0..50 {
+            let registry = registry.clone();
+            tasks.push(tokio::spawn(async move {
+                registry.upsert(test_vm(&format!("vm-{i}"))).await;
+                if i % 2 == 0 {
+                    registry.remove(&VMId(format!("vm-{i}"))).await;
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let vms = registry.snapshot().await;
+        assert_eq!(vms.len(), 25);
+        assert!(vms.iter().all(|vm| {
+            let n: u32 = vm.id.0.trim_start_matches("vm-").parse().unwrap();
+            n % 2 == 1
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_status_update_keeps_entries() {
+        let registry = VmRegistry::new();
+        registry.upsert(test_vm("a")).await;
+        registry.upsert(test_vm("b")).await;
+
+        let mut statuses = HashMap::new();
+        statuses.insert(VMId("a".to_string()), VMStatus::Running { pid: 42 });
+        statuses.insert(VMId("gone".to_string()), VMStatus::Stopped);
+        registry.apply_statuses(statuses).await;
+
+        assert_eq!(registry.len().await, 2);
+        assert_eq!(
+            registry.get(&VMId("a".to_string())).await.unwrap().status,
+            VMStatus::Running { pid: 42 }
+        );
+        assert_eq!(
+            registry.get(&VMId("b".to_string())).await.unwrap().status,
+            VMStatus::Stopped
+        );
+        assert!(registry.get(&VMId("gone".to_string())).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_applies_discovery_events() {
+        let registry = VmRegistry::new();
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let handle = registry.subscribe(event_rx);
+
+        event_tx
+            .send(DiscoveryEvent::VMAdded(test_vm("a")))
+            .unwrap();
+        event_tx
+            .send(DiscoveryEvent::VMAdded(test_vm("b")))
+            .unwrap();
+        event_tx
+            .send(DiscoveryEvent::VMRemoved(VMId("a".to_string())))
+            .unwrap();
+        drop(event_tx);
+        handle.await.unwrap();
+
+        let vms = registry.snapshot().await;
+        assert_eq!(vms.len(), 1);
+        assert_eq!(vms[0].id, VMId("b".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_filter_and_group_by_tag() {
+        let registry = VmRegistry::new();
+        registry.upsert(tagged_vm("c", &["work"])).await;
+        registry.upsert(tagged_vm("a", &["work", "linux"])).await;
+        registry.upsert(tagged_vm("b", &["games"])).await;
+        registry.upsert(test_vm("d")).await;
+
+        let names = |vms: Vec<VM>| vms.into_iter().map(|vm| vm.name).collect::<Vec<_>>();
+        assert_eq!(names(registry.with_tag("work").await), vec!["a", "c"]);
+        assert_eq!(names(registry.with_tag("linux").await), vec!["a"]);
+        assert!(registry.with_tag("missing").await.is_empty());
+
+        assert_eq!(registry.tags().await, vec!["games", "linux", "work"]);
+
+        let groups = registry.group_by_tag().await;
+        assert_eq!(groups.len(), 3);
+        assert_eq!(names(groups["work"].clone()), vec!["a", "c"]);
+        assert_eq!(names(groups["games"].clone()), vec!["b"]);
+    }
+}
@@ -3,8 +3,12 @@ use futures_util::{SinkExt, StreamExt};
 use hex;
 use rand::{thread_rng, Rng};
 use std::collections::HashMap;
+use std::fmt;
+#[cfg(unix)]
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{Mutex, RwLock};
 use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
@@ -15,12 +19,19 @@ pub struct VncConnection {
     pub vm_id: String,
     pub vnc_host: String,
     pub vnc_port: u16,
+    /// Unix socket of the console server, used instead of host and port
+    #[cfg(unix)]
+    pub vnc_socket: Option<PathBuf>,
     pub websocket_port: u16,
     pub auth_token: String,
     pub status: String,
+    /// Last time the client sent input through the proxy
+    pub last_activity: Instant,
+    /// Per-session override of the proxy-wide idle timeout
+    pub idle_timeout: Option<Duration>,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConsoleProtocol {
     Vnc,
     Spice,
@@ -42,9 +53,36 @@ pub enum ConnectionStatus {
     Error(String),
 }
 
+/// Where a session's console server listens
+#[derive(Debug, Clone)]
+enum ConsoleTarget {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl fmt::Display for ConsoleTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsoleTarget::Tcp(addr) => f.write_str(addr),
+            #[cfg(unix)]
+            ConsoleTarget::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Status reported while a session is about to be reaped for inactivity
+pub const STATUS_IDLE_WARNING: &str = "idle_warning";
+/// Status reported once a session has been disconnected for inactivity
+pub const STATUS_IDLE_TIMEOUT: &str = "idle_timeout";
+
+/// How long before the idle timeout the warning status is shown
+const IDLE_WARNING_WINDOW: Duration = Duration::from_secs(60);
+
 pub struct VncProxy {
     connections: Arc<RwLock<HashMap<String, VncConnection>>>,
     active_proxies: Arc<Mutex<HashMap<String, tokio::task::JoinHandle<()>>>>,
+    idle_timeout: Option<Duration>,
 }
 
 impl VncProxy {
@@ -52,16 +90,66 @@ impl VncProxy {
         Self {
             connections: Arc::new(RwLock::new(HashMap::new())),
             active_proxies: Arc::new(Mutex::new(HashMap::new())),
+            idle_timeout: None,
         }
     }
 
+    /// Disconnect sessions that see no client input for `timeout`
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Override the idle timeout for a single session. `None` falls back to
+    /// the proxy-wide setting.
+    pub async fn set_connection_idle_timeout(
+        &self,
+        connection_id: &str,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let mut connections = self.connections.write().await;
+        let conn = connections
+            .get_mut(connection_id)
+            .ok_or_else(|| anyhow!("Unknown connection: {connection_id}"))?;
+        conn.idle_timeout = timeout;
+        Ok(())
+    }
+
     pub async fn create_connection(
         &self,
         vm_id: String,
         vnc_host: String,
         vnc_port: u16,
     ) -> Result<VncConnection> {
-        log::info!("Creating VNC connection for VM '{vm_id}' at {vnc_host}:{vnc_port}");
+        let target = ConsoleTarget::Tcp(format!("{vnc_host}:{vnc_port}"));
+        self.start_connection(vm_id, vnc_host, vnc_port, target)
+            .await
+    }
+
+    /// Proxy a console server listening on a Unix socket, such as QEMU's
+    /// SPICE server with `-spice unix=on`, so it needs no TCP port at all
+    #[cfg(unix)]
+    pub async fn create_unix_connection(
+        &self,
+        vm_id: String,
+        socket_path: PathBuf,
+    ) -> Result<VncConnection> {
+        let target = ConsoleTarget::Unix(socket_path);
+        self.start_connection(vm_id, String::new(), 0, target).await
+    }
+
+    async fn start_connection(
+        &self,
+        vm_id: String,
+        vnc_host: String,
+        vnc_port: u16,
+        target: ConsoleTarget,
+    ) -> Result<VncConnection> {
+        log::info!("Creating VNC connection for VM '{vm_id}' at {target}");
 
         let connection_id = self.generate_connection_id();
         let auth_token = self.generate_auth_token();
@@ -77,9 +165,16 @@ impl VncProxy {
             vm_id: vm_id.clone(),
             vnc_host: vnc_host.clone(),
             vnc_port,
+            #[cfg(unix)]
+            vnc_socket: match &target {
+                ConsoleTarget::Unix(path) => Some(path.clone()),
+                ConsoleTarget::Tcp(_) => None,
+            },
             websocket_port,
             auth_token: auth_token.clone(),
             status: "connecting".to_string(),
+            last_activity: Instant::now(),
+            idle_timeout: None,
         };
 
         // Store the connection
@@ -95,7 +190,6 @@ impl VncProxy {
         // Start the WebSocket proxy
         let connections_clone = self.connections.clone();
         let connection_id_clone = connection_id.clone();
-        let vnc_addr = format!("{vnc_host}:{vnc_port}");
 
         log::info!(
             "Starting WebSocket proxy for connection {connection_id} on port {websocket_port}"
@@ -105,7 +199,7 @@ impl VncProxy {
             log::debug!("WebSocket proxy task started for connection {connection_id_clone}");
             if let Err(e) = Self::run_websocket_proxy(
                 websocket_port,
-                vnc_addr,
+                target,
                 auth_token,
                 connections_clone,
                 connection_id_clone,
@@ -129,7 +223,7 @@ impl VncProxy {
 
     async fn run_websocket_proxy(
         websocket_port: u16,
-        vnc_addr: String,
+        target: ConsoleTarget,
         expected_token: String,
         connections: Arc<RwLock<HashMap<String, VncConnection>>>,
         connection_id: String,
@@ -155,7 +249,7 @@ impl VncProxy {
         while let Ok((stream, addr)) = ws_listener.accept().await {
             log::info!("Accepted WebSocket connection from {addr} for connection {connection_id}");
 
-            let vnc_addr = vnc_addr.clone();
+            let target = target.clone();
             let expected_token = expected_token.clone();
             let connections = connections.clone();
             let connection_id = connection_id.clone();
@@ -164,7 +258,7 @@ impl VncProxy {
                 log::debug!("Spawned handler for WebSocket connection from {addr}");
                 if let Err(e) = Self::handle_websocket_connection(
                     stream,
-                    vnc_addr,
+                    target,
                     expected_token,
                     connections,
                     connection_id,
@@ -182,7 +276,7 @@ impl VncProxy {
 
     async fn handle_websocket_connection(
         stream: TcpStream,
-        vnc_addr: String,
+        target: ConsoleTarget,
         expected_token: String,
         connections: Arc<RwLock<HashMap<String, VncConnection>>>,
         connection_id: String,
@@ -222,47 +316,43 @@ impl VncProxy {
             let mut conns = connections.write().await;
             if let Some(conn) = conns.get_mut(&connection_id) {
                 conn.status = "connected".to_string();
+                conn.last_activity = Instant::now();
                 log::info!("Updated connection {connection_id} status to 'connected'");
             }
         }
 
         // Connect to VNC/SPICE server
         // Note: This proxy currently only works with VNC protocol
-        log::info!("Attempting to connect to console server at {vnc_addr}");
-        match TcpStream::connect(&vnc_addr).await {
-            Ok(mut vnc_stream) => {
-                log::info!("Successfully connected to console server at {vnc_addr}");
-                // TODO: Detect protocol and handle accordingly
-                // Currently only VNC protocol is supported
-                let (vnc_reader, vnc_writer) = vnc_stream.split();
-
-                // Create bidirectional proxy
-                let ws_to_vnc = Self::proxy_ws_to_vnc(ws_receiver, vnc_writer);
-                let vnc_to_ws = Self::proxy_vnc_to_ws(vnc_reader, ws_sender);
-
-                log::debug!("Starting bidirectional proxy for connection {connection_id}");
-
-                // Run both directions concurrently
-                tokio::select! {
-                    result = ws_to_vnc => {
-                        if let Err(e) = result {
-                            log::error!("WS to VNC proxy error for connection {connection_id}: {e}");
-                        } else {
-                            log::debug!("WS to VNC proxy ended normally for connection {connection_id}");
-                        }
-                    }
-                    result = vnc_to_ws => {
-                        if let Err(e) = result {
-                            log::error!("VNC to WS proxy error for connection {connection_id}: {e}");
-                        } else {
-                            log::debug!("VNC to WS proxy ended normally for connection {connection_id}");
-                        }
-                    }
-                }
+        log::info!("Attempting to connect to console server at {target}");
+        let connect_error = |e: std::io::Error| {
+            log::error!("Failed to connect to VNC server at {target}: {e}");
+            anyhow!("Failed to connect to VNC server: {e}")
+        };
+        match &target {
+            ConsoleTarget::Tcp(addr) => {
+                let vnc_stream = TcpStream::connect(addr).await.map_err(connect_error)?;
+                Self::relay(
+                    vnc_stream,
+                    ws_sender,
+                    ws_receiver,
+                    &connections,
+                    &connection_id,
+                )
+                .await;
             }
-            Err(e) => {
-                log::error!("Failed to connect to VNC server at {vnc_addr}: {e}");
-                return Err(anyhow!("Failed to connect to VNC server: {e}"));
+            #[cfg(unix)]
+            ConsoleTarget::Unix(path) => {
+                let vnc_stream = tokio::net::UnixStream::connect(path)
+                    .await
+                    .map_err(connect_error)?;
+                Self::relay(
+                    vnc_stream,
+                    ws_sender,
+                    ws_receiver,
+                    &connections,
+                    &connection_id,
+                )
+                .await;
             }
         }
 
@@ -279,14 +369,61 @@ impl VncProxy {
         Ok(())
     }
 
+    /// Copy data both ways between the client and the console server until
+    /// either side closes
+    async fn relay(
+        vnc_stream: impl AsyncRead + AsyncWrite + Unpin,
+        ws_sender: futures_util::stream::SplitSink<WebSocketStream<TcpStream>, Message>,
+        ws_receiver: futures_util::stream::SplitStream<WebSocketStream<TcpStream>>,
+        connections: &Arc<RwLock<HashMap<String, VncConnection>>>,
+        connection_id: &str,
+    ) {
+        log::info!("Successfully connected to console server for connection {connection_id}");
+        // TODO: Detect protocol and handle accordingly
+        // Currently only VNC protocol is supported
+        let (vnc_reader, vnc_writer) = tokio::io::split(vnc_stream);
+
+        // Create bidirectional proxy
+        let ws_to_vnc = Self::proxy_ws_to_vnc(
+            ws_receiver,
+            vnc_writer,
+            connections.clone(),
+            connection_id.to_string(),
+        );
+        let vnc_to_ws = Self::proxy_vnc_to_ws(vnc_reader, ws_sender);
+
+        log::debug!("Starting bidirectional proxy for connection {connection_id}");
+
+        // Run both directions concurrently
+        tokio::select! {
+            result = ws_to_vnc => {
+                if let Err(e) = result {
+                    log::error!("WS to VNC proxy error for connection {connection_id}: {e}");
+                } else {
+                    log::debug!("WS to VNC proxy ended normally for connection {connection_id}");
+                }
+            }
+            result = vnc_to_ws => {
+                if let Err(e) = result {
+                    log::error!("VNC to WS proxy error for connection {connection_id}: {e}");
+                } else {
+                    log::debug!("VNC to WS proxy ended normally for connection {connection_id}");
+                }
+            }
+        }
+    }
+
     async fn proxy_ws_to_vnc(
         mut ws_receiver: futures_util::stream::SplitStream<WebSocketStream<TcpStream>>,
-        mut vnc_writer: tokio::net::tcp::WriteHalf<'_>,
+        mut vnc_writer: impl AsyncWrite + Unpin,
+        connections: Arc<RwLock<HashMap<String, VncConnection>>>,
+        connection_id: String,
     ) -> Result<()> {
         while let Some(msg) = ws_receiver.next().await {
             match msg? {
                 Message::Binary(data) => {
                     vnc_writer.write_all(&data).await?;
+                    Self::touch(&connections, &connection_id).await;
                 }
                 Message::Close(_) => break,
                 _ => {}
@@ -296,7 +433,7 @@ impl VncProxy {
     }
 
     async fn proxy_vnc_to_ws(
-        mut vnc_reader: tokio::net::tcp::ReadHalf<'_>,
+        mut vnc_reader: impl AsyncRead + Unpin,
         mut ws_sender: futures_util::stream::SplitSink<WebSocketStream<TcpStream>, Message>,
     ) -> Result<()> {
         let mut buffer = vec![0u8; 65536];
@@ -312,6 +449,94 @@ impl VncProxy {
         Ok(())
     }
 
+    /// Record client activity, clearing any pending idle warning
+    async fn touch(connections: &RwLock<HashMap<String, VncConnection>>, connection_id: &str) {
+        let mut conns = connections.write().await;
+        if let Some(conn) = conns.get_mut(connection_id) {
+            conn.last_activity = Instant::now();
+            if conn.status == STATUS_IDLE_WARNING {
+                conn.status = "connected".to_string();
+            }
+        }
+    }
+
+    /// Disconnect sessions idle past their timeout and flag the ones about
+    /// to be. Returns the ids of the reaped connections.
+    pub async fn reap_idle_connections(&self) -> Vec<String> {
+        Self::reap_idle_at(
+            &self.connections,
+            &self.active_proxies,
+            self.idle_timeout,
+            Instant::now(),
+        )
+        .await
+    }
+
+    /// Periodically reap idle sessions in the background
+    pub fn start_idle_reaper(&self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let connections = self.connections.clone();
+        let active_proxies = self.active_proxies.clone();
+        let default_timeout = self.idle_timeout;
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                Self::reap_idle_at(
+                    &connections,
+                    &active_proxies,
+                    default_timeout,
+                    Instant::now(),
+                )
+                .await;
+            }
+        })
+    }
+
+    async fn reap_idle_at(
+        connections: &RwLock<HashMap<String, VncConnection>>,
+        active_proxies: &Mutex<HashMap<String, tokio::task::JoinHandle<()>>>,
+        default_timeout: Option<Duration>,
+        now: Instant,
+    ) -> Vec<String> {
+        let mut reaped = Vec::new();
+        {
+            let mut conns = connections.write().await;
+            for conn in conns.values_mut() {
+                if conn.status == STATUS_IDLE_TIMEOUT {
+                    continue;
+                }
+                let Some(timeout) = conn.idle_timeout.or(default_timeout) else {
+                    continue;
+                };
+
+                let idle = now.saturating_duration_since(conn.last_activity);
+                if idle >= timeout {
+                    log::info!(
+                        "Disconnecting console session {} after {}s of inactivity",
+                        conn.id,
+                        idle.as_secs()
+                    );
+                    conn.status = STATUS_IDLE_TIMEOUT.to_string();
+                    reaped.push(conn.id.clone());
+                } else if idle + IDLE_WARNING_WINDOW.min(timeout / 2) >= timeout {
+                    conn.status = STATUS_IDLE_WARNING.to_string();
+                }
+            }
+        }
+
+        // The connection entries stay around so the UI can show why the
+        // session ended; only the proxy tasks are torn down.
+        let mut proxies = active_proxies.lock().await;
+        for id in &reaped {
+            if let Some(task) = proxies.remove(id) {
+                task.abort();
+            }
+        }
+
+        reaped
+    }
+
     pub async fn stop_connection(&self, connection_id: &str) -> Result<()> {
         // Remove the connection
         {
@@ -369,3 +594,149 @@ impl Default for VncProxy {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn insert_connection(proxy: &VncProxy, id: &str, last_activity: Instant) {
+        let connection = VncConnection {
+            id: id.to_string(),
+            vm_id: "vm".to_string(),
+            vnc_host: "127.0.0.1".to_string(),
+            vnc_port: 5900,
+            #[cfg(unix)]
+            vnc_socket: None,
+            websocket_port: 6080,
+            auth_token: "token".to_string(),
+            status: "connected".to_string(),
+            last_activity,
+            idle_timeout: None,
+        };
+        proxy
+            .connections
+            .write()
+            .await
+            .insert(id.to_string(), connection);
+        proxy
+            .active_proxies
+            .lock()
+            .await
+            .insert(id.to_string(), tokio::spawn(std::future::pending()));
+    }
+
+    #[tokio::test]
+    async fn test_idle_session_is_reaped() {
+        let proxy = VncProxy::new().with_idle_timeout(Duration::from_secs(600));
+        let start = Instant::now();
+        insert_connection(&proxy, "idle", start).await;
+
+        // Inside the warning window the session is flagged but kept alive
+        let reaped = VncProxy::reap_idle_at(
+            &proxy.connections,
+            &proxy.active_proxies,
+            proxy.idle_timeout,
+            start + Duration::from_secs(570),
+        )
+        .await;
+        assert!(reaped.is_empty());
+        assert_eq!(
+            proxy.get_connection_status("idle").await.as_deref(),
+            Some(STATUS_IDLE_WARNING)
+        );
+
+        let reaped = VncProxy::reap_idle_at(
+            &proxy.connections,
+            &proxy.active_proxies,
+            proxy.idle_timeout,
+            start + Duration::from_secs(601),
+        )
+        .await;
+        assert_eq!(reaped, vec!["idle".to_string()]);
+        assert_eq!(
+            proxy.get_connection_status("idle").await.as_deref(),
+            Some(STATUS_IDLE_TIMEOUT)
+        );
+        assert!(proxy.active_proxies.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_per_session_idle_timeout_override() {
+        let proxy = VncProxy::new().with_idle_timeout(Duration::from_secs(600));
+        let start = Instant::now();
+        insert_connection(&proxy, "short", start).await;
+        insert_connection(&proxy, "default", start).await;
+        proxy
+            .set_connection_idle_timeout("short", Some(Duration::from_secs(60)))
+            .await
+            .unwrap();
+
+        let reaped = VncProxy::reap_idle_at(
+            &proxy.connections,
+            &proxy.active_proxies,
+            proxy.idle_timeout,
+            start + Duration::from_secs(120),
+        )
+        .await;
+        assert_eq!(reaped, vec!["short".to_string()]);
+        assert_eq!(
+            proxy.get_connection_status("default").await.as_deref(),
+            Some("connected")
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket_console_is_proxied() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let socket_path = dir.path().join("vm-spice.socket");
+        let listener = tokio::net::UnixListener::bind(&socket_path).unwrap();
+
+        // Stands in for the console server and echoes what it receives
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+        });
+
+        let proxy = VncProxy::new();
+        let connection = proxy
+            .create_unix_connection("vm".to_string(), socket_path.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            connection.vnc_socket.as_deref(),
+            Some(socket_path.as_path())
+        );
+
+        let start = Instant::now();
+        while proxy.get_connection_status(&connection.id).await.as_deref() != Some("ready") {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "proxy never listened"
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let url = format!("ws://127.0.0.1:{}", connection.websocket_port);
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        ws.send(Message::Text(connection.auth_token.clone().into()))
+            .await
+            .unwrap();
+        match ws.next().await {
+            Some(Ok(Message::Text(reply))) => assert_eq!(reply.as_str(), "authenticated"),
+            other => panic!("unexpected auth reply: {other:?}"),
+        }
+
+        ws.send(Message::Binary(b"REDQ".to_vec().into()))
+            .await
+            .unwrap();
+        match ws.next().await {
+            Some(Ok(Message::Binary(data))) => assert_eq!(&data[..], b"REDQ"),
+            other => panic!("unexpected console data: {other:?}"),
+        }
+
+        proxy.stop_connection(&connection.id).await.unwrap();
+    }
+}
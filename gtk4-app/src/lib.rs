@@ -1,5 +1,7 @@
 pub mod ui;
 pub mod utils;
+#[cfg(feature = "web-server")]
+pub mod web_server;
 
 use std::sync::Arc;
 
@@ -5,7 +5,10 @@ use gtk::{gdk, gio, glib, Application};
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use quickemu_core::{BinaryDiscovery, ConfigManager, ProcessMonitor, QuickgetService, VMManager};
+use quickemu_core::{
+    BinaryDiscovery, ConfigManager, DesktopNotifier, ProcessMonitor, QuickgetService,
+    VMDiscovery, VMManager,
+};
 use ui::MainWindow;
 
 // Import AppState from lib.rs instead of defining it here
@@ -68,12 +71,30 @@ fn build_ui(app: &Application) {
         
         // Initialize process monitor
         let process_monitor = Arc::new(ProcessMonitor::new());
+        process_monitor.start_sampling(std::time::Duration::from_secs(2));
         vm_manager.set_process_monitor(process_monitor.clone());
+        vm_manager.set_shutdown_timeout(std::time::Duration::from_millis(
+            config_manager.get_config().await.shutdown_timeout_ms,
+        ));
+        vm_manager.set_notifier(Arc::new(DesktopNotifier::new("Quickemu Manager")));
+
+        // Take back VMs left running by a previous session, e.g. after a crash
+        let (event_tx, _) = tokio::sync::mpsc::unbounded_channel();
+        let mut discovery = VMDiscovery::new(event_tx);
+        discovery.add_watch_directories(config_manager.get_all_vm_directories().await);
+        match discovery.scan_all_directories().await {
+            Ok(vms) => {
+                vm_manager.reconcile_running_vms(&vms).await;
+            }
+            Err(e) => println!("Failed to scan VMs to reconcile: {}", e),
+        }
 
         // Initialize quickget service if available
-        let quickget_service = binary_discovery
-            .quickget_path()
-            .map(|path| Arc::new(QuickgetService::new(path.to_path_buf())));
+        let quickget_timeout =
+            std::time::Duration::from_millis(config_manager.get_config().await.quickget_timeout_ms);
+        let quickget_service = binary_discovery.quickget_path().map(|path| {
+            Arc::new(QuickgetService::new(path.to_path_buf()).with_timeout(quickget_timeout))
+        });
 
         if quickget_service.is_some() {
             println!("✅ Quickget service initialized");
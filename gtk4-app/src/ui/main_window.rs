@@ -221,6 +221,7 @@ impl MainWindow {
 
         // Clean up console if it exists
         if let Some(console_widget) = imp.console_widget.borrow_mut().take() {
+            console_widget.disconnect();
             imp.console_container.remove(&console_widget);
         }
 
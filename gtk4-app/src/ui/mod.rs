@@ -11,7 +11,7 @@ mod vm_edit_dialog;
 
 pub use main_window::MainWindow;
 pub use settings_dialog::SettingsDialog;
-pub use spice_display::SpiceDisplay;
+pub use spice_display::{ConsoleState, SpiceDisplay};
 pub use vm_card::VMCard;
 pub use vm_console_window::VMConsoleWindow;
 pub use vm_create_dialog::VMCreateDialog;
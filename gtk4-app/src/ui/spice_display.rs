@@ -7,7 +7,16 @@ use std::sync::{Arc, Mutex};
 
 // For native builds, we need to use SpiceClientShared
 #[cfg(not(target_arch = "wasm32"))]
-use spice_client::SpiceClientShared;
+use spice_client::{MainEvent, SpiceClientShared};
+
+/// Connection state of the console, for status displays
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConsoleState {
+    Connecting,
+    Connected,
+    /// The connection failed or was lost, with the reason
+    Disconnected(String),
+}
 
 glib::wrapper! {
     pub struct SpiceDisplay(ObjectSubclass<imp::SpiceDisplay>)
@@ -23,15 +32,16 @@ impl SpiceDisplay {
     pub fn connect(&self, host: String, port: u16) {
         eprintln!("SpiceDisplay: Starting connection to {}:{}", host, port);
 
-        // Update placeholder text
-        self.imp().update_status("Connecting...");
+        *self.imp().target.borrow_mut() = Some((host.clone(), port));
+        self.imp().set_state(ConsoleState::Connecting);
 
         // Create a shared tokio runtime that we'll use throughout
         let runtime = match tokio::runtime::Runtime::new() {
             Ok(rt) => Arc::new(rt),
             Err(e) => {
                 eprintln!("Failed to create tokio runtime: {}", e);
-                self.imp().update_status(&format!("Runtime error: {}", e));
+                self.imp()
+                    .set_state(ConsoleState::Disconnected(format!("Runtime error: {}", e)));
                 return;
             }
         };
@@ -47,12 +57,43 @@ impl SpiceDisplay {
         glib::spawn_future_local(async move {
             eprintln!("SpiceDisplay: Connecting to {}:{}", host_clone, port_clone);
 
-            // Create the client in a tokio context
+            // Create the client in a tokio context, forwarding its events to
+            // this thread
+            let (event_tx, mut event_rx) = tokio::sync::mpsc::unbounded_channel();
             let client = runtime
-                .spawn(async move { SpiceClientShared::new(host_clone, port_clone) })
+                .spawn(async move {
+                    let client = SpiceClientShared::new(host_clone, port_clone);
+                    client
+                        .set_event_callback(move |event| {
+                            let _ = event_tx.send(event.clone());
+                        })
+                        .await;
+                    client
+                })
                 .await
                 .unwrap();
 
+            // Keep the client where closing the widget can disconnect it,
+            // even while it is still connecting
+            match widget_weak.upgrade() {
+                Some(widget) => *widget.imp().client.borrow_mut() = Some(client.clone()),
+                None => return,
+            }
+
+            let events_widget = widget_weak.clone();
+            glib::spawn_future_local(async move {
+                while let Some(event) = event_rx.recv().await {
+                    let MainEvent::Disconnected { reason } = event else {
+                        continue;
+                    };
+                    eprintln!("SpiceDisplay: Connection lost: {}", reason);
+                    if let Some(widget) = events_widget.upgrade() {
+                        widget.imp().stop_display_updates();
+                        widget.imp().set_state(ConsoleState::Disconnected(reason));
+                    }
+                }
+            });
+
             // Connect to the server
             eprintln!("SpiceDisplay: Calling client.connect()...");
             let client_for_connect = client.clone();
@@ -68,14 +109,26 @@ impl SpiceDisplay {
                 Err(e) => {
                     eprintln!("Failed to connect to SPICE server: {}", e);
                     if let Some(widget) = widget_weak.upgrade() {
-                        widget
-                            .imp()
-                            .update_status(&format!("Connection failed: {}", e));
+                        widget.imp().stop_display_updates();
+                        widget.imp().set_state(ConsoleState::Disconnected(format!(
+                            "Connection failed: {}",
+                            e
+                        )));
                     }
                     return;
                 }
             }
 
+            // The widget may have been closed while connecting, in which case
+            // it already asked the client to disconnect
+            let Some(widget) = widget_weak.upgrade() else {
+                return;
+            };
+            if widget.imp().client.borrow().is_none() {
+                return;
+            }
+            drop(widget);
+
             // Start the event loop
             eprintln!("SpiceDisplay: Starting event loop...");
             let client_clone = client.clone();
@@ -91,9 +144,11 @@ impl SpiceDisplay {
                 Err(e) => {
                     eprintln!("Failed to start SPICE event loop: {}", e);
                     if let Some(widget) = widget_weak.upgrade() {
-                        widget
-                            .imp()
-                            .update_status(&format!("Event loop failed: {}", e));
+                        widget.imp().stop_display_updates();
+                        widget.imp().set_state(ConsoleState::Disconnected(format!(
+                            "Event loop failed: {}",
+                            e
+                        )));
                     }
                     return;
                 }
@@ -101,7 +156,7 @@ impl SpiceDisplay {
 
             // Now set up the display
             if let Some(widget) = widget_weak.upgrade() {
-                widget.imp().update_status("Connected");
+                widget.imp().set_state(ConsoleState::Connected);
                 widget.imp().setup_display_with_runtime(client, runtime);
             }
         });
@@ -111,6 +166,25 @@ impl SpiceDisplay {
         let imp = self.imp();
         imp.stop_display_updates();
     }
+
+    /// Drop the current connection, if any, and connect to the same server
+    /// again
+    pub fn reconnect(&self) {
+        let Some((host, port)) = self.imp().target.borrow().clone() else {
+            return;
+        };
+        self.disconnect();
+        self.imp().reset_view();
+        self.connect(host, port);
+    }
+
+    /// Called whenever the connection state changes
+    pub fn connect_state_changed<F>(&self, callback: F)
+    where
+        F: Fn(&ConsoleState) + 'static,
+    {
+        *self.imp().state_callback.borrow_mut() = Some(Box::new(callback));
+    }
 }
 
 impl Default for SpiceDisplay {
@@ -134,6 +208,9 @@ mod imp {
         pub client: RefCell<Option<SpiceClientShared>>,
         pub status_label: RefCell<Option<gtk::Label>>,
         pub runtime: RefCell<Option<Arc<tokio::runtime::Runtime>>>,
+        pub target: RefCell<Option<(String, u16)>>,
+        pub drawing_area: RefCell<Option<gtk::DrawingArea>>,
+        pub state_callback: RefCell<Option<Box<dyn Fn(&ConsoleState)>>>,
     }
 
     impl Default for SpiceDisplay {
@@ -144,6 +221,9 @@ mod imp {
                 client: RefCell::new(None),
                 status_label: RefCell::new(None),
                 runtime: RefCell::new(None),
+                target: RefCell::new(None),
+                drawing_area: RefCell::new(None),
+                state_callback: RefCell::new(None),
             }
         }
     }
@@ -163,28 +243,52 @@ mod imp {
             obj.set_orientation(gtk::Orientation::Vertical);
             obj.set_visible(true);
 
-            // Add a status label
-            let status_label = gtk::Label::new(Some("Initializing SPICE display..."));
+            self.add_status_label("Initializing SPICE display...");
+        }
+    }
+
+    impl WidgetImpl for SpiceDisplay {}
+    impl BoxImpl for SpiceDisplay {}
+
+    impl SpiceDisplay {
+        fn add_status_label(&self, text: &str) {
+            let status_label = gtk::Label::new(Some(text));
             status_label.set_visible(true);
             status_label.set_vexpand(false);
             status_label.set_margin_top(10);
             status_label.set_margin_bottom(10);
-            obj.append(&status_label);
+            self.obj().append(&status_label);
 
             *self.status_label.borrow_mut() = Some(status_label);
         }
-    }
 
-    impl WidgetImpl for SpiceDisplay {}
-    impl BoxImpl for SpiceDisplay {}
-
-    impl SpiceDisplay {
         pub fn update_status(&self, text: &str) {
             if let Some(label) = self.status_label.borrow().as_ref() {
                 label.set_text(text);
             }
         }
 
+        pub fn set_state(&self, state: ConsoleState) {
+            match &state {
+                ConsoleState::Connecting => self.update_status("Connecting..."),
+                ConsoleState::Connected => self.update_status("Connected"),
+                ConsoleState::Disconnected(reason) => self.update_status(reason),
+            }
+            if let Some(callback) = self.state_callback.borrow().as_ref() {
+                callback(&state);
+            }
+        }
+
+        /// Go back to showing the status label, dropping the last frame
+        pub fn reset_view(&self) {
+            if let Some(drawing_area) = self.drawing_area.borrow_mut().take() {
+                self.obj().remove(&drawing_area);
+            }
+            if self.status_label.borrow().is_none() {
+                self.add_status_label("Connecting...");
+            }
+        }
+
         pub fn store_runtime(&self, runtime: Arc<tokio::runtime::Runtime>) {
             *self.runtime.borrow_mut() = Some(runtime);
         }
@@ -324,6 +428,7 @@ mod imp {
 
             obj.append(drawing_area);
             obj.set_visible(true);
+            *self.drawing_area.borrow_mut() = Some(drawing_area.clone());
 
             eprintln!("SpiceDisplay: Drawing area added and made visible");
 
@@ -366,10 +471,20 @@ mod imp {
                 let _ = tx.send(true);
             }
 
-            // Disconnect client if present
+            // Disconnect client if present. The runtime goes away with the
+            // widget, so keep it alive until the sockets are closed and the
+            // console proxy can free the session.
             if let Some(client) = self.client.borrow_mut().take() {
+                let runtime = self.runtime.borrow().clone();
                 glib::spawn_future_local(async move {
-                    client.disconnect().await;
+                    match runtime {
+                        Some(runtime) => {
+                            let _ = runtime
+                                .spawn(async move { client.disconnect().await })
+                                .await;
+                        }
+                        None => client.disconnect().await,
+                    }
                 });
             }
         }
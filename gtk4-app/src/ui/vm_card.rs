@@ -7,7 +7,7 @@ use std::sync::Arc;
 
 use super::{MainWindow, VMEditDialog};
 use crate::AppState;
-use quickemu_core::{DisplayProtocol, VMStatus, VM};
+use quickemu_core::{DisplayProtocol, StopMode, StopProgress, VMStatus, VM};
 
 mod imp {
     use super::*;
@@ -105,7 +105,15 @@ mod imp {
                     let obj_weak = self.obj().downgrade();
                     
                     glib::spawn_future_local(async move {
-                        if let Err(e) = app_state_clone.vm_manager.start_vm(&vm_clone).await {
+                        let extra_args = app_state_clone
+                            .config_manager
+                            .get_extra_qemu_args(&vm_clone.id)
+                            .await;
+                        if let Err(e) = app_state_clone
+                            .vm_manager
+                            .start_vm_with_args(&vm_clone, &extra_args)
+                            .await
+                        {
                             eprintln!("Failed to start VM: {}", e);
                         } else {
                             // Poll for VM to be running before opening console
@@ -165,6 +173,11 @@ mod imp {
             ops_section.append(Some("Export VM"), Some("vm.export"));
             menu.append_section(None, &ops_section);
             
+            // Power section
+            let power_section = gio::Menu::new();
+            power_section.append(Some("Force Stop"), Some("vm.force-stop"));
+            menu.append_section(None, &power_section);
+            
             let popover = gtk::PopoverMenu::from_model(Some(&menu));
             popover.set_parent(&*self.obj());
             popover.set_pointing_to(Some(&gtk::gdk::Rectangle::new(
@@ -177,16 +190,41 @@ mod imp {
         }
         
         fn handle_stop_button(&self) {
+            let mode = self
+                .app_state
+                .borrow()
+                .as_ref()
+                .map(|app_state| app_state.vm_manager.default_stop_mode());
+            if let Some(mode) = mode {
+                self.stop_vm(mode);
+            }
+        }
+
+        fn stop_vm(&self, mode: StopMode) {
             let vm_ref = self.vm.borrow();
             let app_state_ref = self.app_state.borrow();
             
             if let (Some(vm), Some(app_state)) = (vm_ref.as_ref(), app_state_ref.as_ref()) {
-                if matches!(vm.status, VMStatus::Running { .. }) {
+                if matches!(vm.status, VMStatus::Running { .. } | VMStatus::Stopping) {
                     let vm_id = vm.id.clone();
                     let app_state_clone = app_state.clone();
+                    let stop_button = self.stop_button.get();
                     
                     glib::spawn_future_local(async move {
-                        if let Err(e) = app_state_clone.vm_manager.stop_vm(&vm_id).await {
+                        let result = app_state_clone
+                            .vm_manager
+                            .stop_vm_with_progress(&vm_id, mode, |progress| match progress {
+                                StopProgress::ShuttingDown => {
+                                    stop_button.set_label("Shutting down...");
+                                    stop_button.set_sensitive(false);
+                                }
+                                StopProgress::ForceStopping => {
+                                    stop_button.set_label("Forcing stop...");
+                                }
+                                StopProgress::Stopped => {}
+                            })
+                            .await;
+                        if let Err(e) = result {
                             eprintln!("Failed to stop VM: {}", e);
                         }
                     });
@@ -248,12 +286,22 @@ mod imp {
                 }
             });
             
+            // Force stop action, for guests that don't shut down
+            let force_stop_action = gio::SimpleAction::new("force-stop", None);
+            let obj_weak = obj.downgrade();
+            force_stop_action.connect_activate(move |_, _| {
+                if let Some(obj) = obj_weak.upgrade() {
+                    obj.imp().stop_vm(StopMode::Force);
+                }
+            });
+            
             // Create action group
             let action_group = gio::SimpleActionGroup::new();
             action_group.add_action(&edit_action);
             action_group.add_action(&delete_action);
             action_group.add_action(&clone_action);
             action_group.add_action(&export_action);
+            action_group.add_action(&force_stop_action);
             
             self.obj().insert_action_group("vm", Some(&action_group));
         }
@@ -479,7 +527,7 @@ impl VMCard {
                 imp.console_button.set_visible(false);
                 imp.status_label.set_visible(true);
                 imp.control_area.set_visible(true);
-                imp.stop_button.set_label("Stopping...");
+                imp.stop_button.set_label("Shutting down...");
                 imp.stop_button.set_sensitive(false);
             },
             VMStatus::Error(_) => {
@@ -502,9 +550,7 @@ impl VMCard {
         
         if let Some(monitor) = imp.process_monitor.borrow().as_ref() {
             if let Some(vm) = imp.vm.borrow().as_ref() {
-                // Update metrics first
-                monitor.update_metrics().await;
-                
+                // Reads the latest background sample; never blocks on a refresh
                 if let Some(metrics) = monitor.get_vm_metrics(&vm.id).await {
                     // Update CPU
                     let cpu_text = format!("{:.0}%", metrics.cpu_percent);
@@ -1,6 +1,10 @@
-use crate::ui::SpiceDisplay;
+use crate::ui::{ConsoleState, SpiceDisplay};
 use adw::prelude::*;
-use gtk::{gio, glib};
+use gtk::{gdk, gio, glib};
+use quickemu_core::{ConfigManager, ConsoleLayout, HeadPlacement, VMId};
+
+/// Guest display head the console window shows
+const CONSOLE_HEAD: u32 = 0;
 
 pub struct VMConsoleWindow {
     window: adw::ApplicationWindow,
@@ -9,7 +13,12 @@ pub struct VMConsoleWindow {
 }
 
 impl VMConsoleWindow {
-    pub fn new(app: &gtk::Application, vm_name: String) -> Self {
+    pub fn new(
+        app: &gtk::Application,
+        config_manager: ConfigManager,
+        vm_id: VMId,
+        vm_name: String,
+    ) -> Self {
         let window = adw::ApplicationWindow::builder()
             .application(app)
             .title(format!("{} - Console", vm_name))
@@ -83,16 +92,95 @@ impl VMConsoleWindow {
 
         content_box.append(&scrolled_window);
 
+        // Status bar with the connection state, and a way back in when the
+        // connection is lost instead of a frozen frame
+        let status_bar = gtk::Box::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .spacing(6)
+            .margin_start(6)
+            .margin_end(6)
+            .margin_top(3)
+            .margin_bottom(3)
+            .build();
+
+        let status_label = gtk::Label::builder()
+            .label("Not connected")
+            .xalign(0.0)
+            .hexpand(true)
+            .ellipsize(gtk::pango::EllipsizeMode::End)
+            .build();
+        status_bar.append(&status_label);
+
+        let reconnect_button = gtk::Button::builder()
+            .label("Reconnect")
+            .tooltip_text("Connect to the VM console again")
+            .visible(false)
+            .build();
+        reconnect_button.connect_clicked(glib::clone!(
+            #[weak]
+            spice_display,
+            move |_| spice_display.reconnect()
+        ));
+        status_bar.append(&reconnect_button);
+
+        spice_display.connect_state_changed(glib::clone!(
+            #[weak]
+            status_label,
+            #[weak]
+            reconnect_button,
+            move |state| {
+                let text = match state {
+                    ConsoleState::Connecting => "Connecting...".to_string(),
+                    ConsoleState::Connected => "Connected".to_string(),
+                    ConsoleState::Disconnected(reason) => format!("Disconnected: {}", reason),
+                };
+                status_label.set_text(&text);
+                reconnect_button.set_visible(matches!(state, ConsoleState::Disconnected(_)));
+            }
+        ));
+
+        content_box.append(&status_bar);
+
         window.set_content(Some(&content_box));
 
+        // Reopen the window the way it was left
+        glib::spawn_future_local(glib::clone!(
+            #[weak]
+            window,
+            #[strong]
+            config_manager,
+            #[strong]
+            vm_id,
+            async move {
+                if let Some(layout) = config_manager.get_console_layout(&vm_id).await {
+                    restore_placement(&window, &layout, CONSOLE_HEAD);
+                }
+            }
+        ));
+
         // Handle window close
         window.connect_close_request(glib::clone!(
             #[weak]
             spice_display,
             #[upgrade_or]
             glib::Propagation::Proceed,
-            move |_| {
+            move |window| {
                 spice_display.disconnect();
+
+                let placement = current_placement(window, CONSOLE_HEAD);
+                let config_manager = config_manager.clone();
+                let vm_id = vm_id.clone();
+                glib::spawn_future_local(async move {
+                    let mut layout = config_manager
+                        .get_console_layout(&vm_id)
+                        .await
+                        .unwrap_or_default();
+                    layout.set_head(placement);
+                    if let Err(e) = config_manager.set_console_layout(&vm_id, layout).await {
+                        eprintln!("Failed to save console layout: {}", e);
+                    }
+                });
+
                 glib::Propagation::Proceed
             }
         ));
@@ -116,3 +204,55 @@ impl VMConsoleWindow {
         self.window.close();
     }
 }
+
+/// Monitors of `display` with their connector names
+fn connected_monitors(display: &gdk::Display) -> Vec<(String, gdk::Monitor)> {
+    let monitors = display.monitors();
+    (0..monitors.n_items())
+        .filter_map(|i| monitors.item(i).and_downcast::<gdk::Monitor>())
+        .filter_map(|monitor| Some((monitor.connector()?.to_string(), monitor)))
+        .collect()
+}
+
+/// Size the window like `head`'s was when it was last closed. Fullscreen
+/// windows go back to their monitor if it's still connected; windowed ones
+/// can't be positioned by the app on every backend, so only their size is
+/// kept.
+fn restore_placement(window: &adw::ApplicationWindow, layout: &ConsoleLayout, head: u32) {
+    let Some(placement) = layout.head(head) else {
+        return;
+    };
+    window.set_default_size(placement.width, placement.height);
+    if placement.maximized {
+        window.maximize();
+    }
+    if placement.fullscreen {
+        let monitors = connected_monitors(&window.display());
+        let connectors: Vec<&str> = monitors.iter().map(|(name, _)| name.as_str()).collect();
+        let monitor = layout
+            .monitor_for_head(head, &connectors)
+            .and_then(|name| monitors.iter().find(|(connector, _)| connector == name));
+        match monitor {
+            Some((_, monitor)) => window.fullscreen_on_monitor(monitor),
+            None => window.fullscreen(),
+        }
+    }
+}
+
+/// Where the window showing `head` is right now
+fn current_placement(window: &adw::ApplicationWindow, head: u32) -> HeadPlacement {
+    let (width, height) = window.default_size();
+    let monitor = window
+        .surface()
+        .and_then(|surface| window.display().monitor_at_surface(&surface))
+        .and_then(|monitor| monitor.connector())
+        .map(|connector| connector.to_string());
+    HeadPlacement {
+        head,
+        width,
+        height,
+        maximized: window.is_maximized(),
+        fullscreen: window.is_fullscreen(),
+        monitor,
+    }
+}
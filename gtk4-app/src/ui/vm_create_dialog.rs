@@ -46,6 +46,8 @@ mod imp {
         pub cpu_row: TemplateChild<SpinRow>,
         #[template_child]
         pub disk_row: TemplateChild<ComboRow>,
+        #[template_child]
+        pub rate_limit_entry: TemplateChild<EntryRow>,
 
         // Progress page elements
         #[template_child]
@@ -110,6 +112,7 @@ mod imp {
                 ram_row: TemplateChild::default(),
                 cpu_row: TemplateChild::default(),
                 disk_row: TemplateChild::default(),
+                rate_limit_entry: TemplateChild::default(),
                 progress_page: TemplateChild::default(),
                 progress_bar: TemplateChild::default(),
                 console_expander: TemplateChild::default(),
@@ -591,6 +594,19 @@ impl VMCreateDialog {
             .unwrap_or(&"64G")
             .to_string();
 
+        let rate_limit = imp.rate_limit_entry.text();
+        let download_rate_limit = if rate_limit.trim().is_empty() {
+            None
+        } else {
+            match quickemu_core::parse_download_rate(&rate_limit) {
+                Ok(rate) => Some(rate),
+                Err(e) => {
+                    eprintln!("Cannot create VM: {}", e);
+                    return;
+                }
+            }
+        };
+
         let template = VMTemplate {
             name: name.clone(),
             os: os.clone(),
@@ -599,6 +615,7 @@ impl VMCreateDialog {
             ram,
             disk_size,
             cpu_cores,
+            download_rate_limit,
         };
 
         // Store template for later reference
@@ -1,8 +1,9 @@
 use adw::prelude::*;
+use gtk::glib;
 use gtk::prelude::*;
 
 use crate::AppState;
-use quickemu_core::{ConfigParser, VM};
+use quickemu_core::{validate_extra_qemu_args, ConfigParser, VM};
 
 pub struct VMEditDialog {
     dialog: adw::Window,
@@ -13,6 +14,7 @@ pub struct VMEditDialog {
     ram_entry: gtk::Entry,
     cpu_spin: gtk::SpinButton,
     disk_size_entry: gtk::Entry,
+    extra_args_entry: gtk::Entry,
 }
 
 impl VMEditDialog {
@@ -123,6 +125,33 @@ impl VMEditDialog {
 
         content_box.append(&resources_group);
 
+        // Advanced Group
+        let advanced_group = adw::PreferencesGroup::builder().title("Advanced").build();
+
+        // Extra QEMU arguments row, stored in the app config rather than the .conf
+        let extra_args_entry = gtk::Entry::builder()
+            .placeholder_text("e.g., -device virtio-rng-pci")
+            .hexpand(true)
+            .build();
+
+        let extra_args_row = adw::ActionRow::builder()
+            .title("Extra QEMU Arguments")
+            .subtitle("Passed to QEMU when the VM starts, separated by spaces")
+            .build();
+        extra_args_row.add_suffix(&extra_args_entry);
+        advanced_group.add(&extra_args_row);
+
+        content_box.append(&advanced_group);
+
+        // Load the current extra arguments
+        let extra_args_entry_clone = extra_args_entry.clone();
+        let config_manager = app_state.config_manager.clone();
+        let vm_id = vm.id.clone();
+        glib::spawn_future_local(async move {
+            let args = config_manager.get_extra_qemu_args(&vm_id).await;
+            extra_args_entry_clone.set_text(&args.join(" "));
+        });
+
         // Wrap in scrolled window
         let scrolled = gtk::ScrolledWindow::builder()
             .hscrollbar_policy(gtk::PolicyType::Never)
@@ -141,6 +170,7 @@ impl VMEditDialog {
             ram_entry: ram_entry.clone(),
             cpu_spin: cpu_spin.clone(),
             disk_size_entry: disk_size_entry.clone(),
+            extra_args_entry: extra_args_entry.clone(),
         };
 
         // Connect cancel button
@@ -158,8 +188,20 @@ impl VMEditDialog {
         let ram_entry_clone = ram_entry.clone();
         let cpu_spin_clone = cpu_spin.clone();
         let disk_size_entry_clone = disk_size_entry.clone();
+        let extra_args_entry_clone = extra_args_entry.clone();
 
         save_button.connect_clicked(move |_| {
+            let extra_args: Vec<String> = extra_args_entry_clone
+                .text()
+                .split_whitespace()
+                .map(String::from)
+                .collect();
+            if let Err(e) = validate_extra_qemu_args(&extra_args) {
+                eprintln!("Invalid extra QEMU arguments: {}", e);
+                // TODO: Show error dialog
+                return;
+            }
+
             let mut updated_config = vm_clone.config.clone();
 
             // Update configuration values
@@ -176,6 +218,15 @@ impl VMEditDialog {
                 eprintln!("Failed to save VM configuration: {}", e);
                 // TODO: Show error dialog
             } else {
+                // Extra QEMU arguments live in the app config
+                let config_manager = app_state_clone.config_manager.clone();
+                let vm_id = vm_clone.id.clone();
+                glib::spawn_future_local(async move {
+                    if let Err(e) = config_manager.set_extra_qemu_args(&vm_id, extra_args).await {
+                        eprintln!("Failed to save extra QEMU arguments: {}", e);
+                    }
+                });
+
                 // Close dialog on successful save
                 if let Some(dialog) = dialog_weak.upgrade() {
                     dialog.close();
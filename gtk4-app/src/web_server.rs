@@ -0,0 +1,46 @@
+//! HTTP endpoints for monitoring the manager when it runs headless
+
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use quickemu_core::services::metrics::MetricsService;
+use quickemu_core::VmRegistry;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// What the endpoints report on
+#[derive(Clone)]
+pub struct WebServerState {
+    pub registry: VmRegistry,
+    pub metrics: Arc<MetricsService>,
+}
+
+/// `/healthz` answers as long as the manager is up; `/metrics` serves VM
+/// counts and resource use in the Prometheus text format
+pub fn router(state: WebServerState) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/metrics", get(metrics))
+        .with_state(state)
+}
+
+/// Serve the endpoints on `addr` until the server fails
+pub async fn serve(addr: SocketAddr, state: WebServerState) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    tracing::info!("Serving health and metrics endpoints on {}", addr);
+    axum::serve(listener, router(state)).await?;
+    Ok(())
+}
+
+async fn healthz() -> &'static str {
+    "ok\n"
+}
+
+async fn metrics(State(state): State<WebServerState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.prometheus_metrics(&state.registry).await,
+    )
+}
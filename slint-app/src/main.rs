@@ -1,6 +1,8 @@
 use anyhow::Result;
-use quickemu_core::{VMManager, VMStatus, VMDiscovery, VMId, ConfigManager};
+use quickemu_core::{VMManager, VMStatus, VMDiscovery, VMId, ConfigManager, StopMode, StopProgress, DesktopNotifier};
+use slint::Model;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{RwLock, mpsc};
 
 slint::include_modules!();
@@ -37,8 +39,13 @@ struct AppState {
 
 impl AppState {
     async fn new(ui: MainWindow) -> Result<Self> {
-        let vm_manager = Arc::new(VMManager::new().await?);
         let config_manager = Arc::new(ConfigManager::new().await?);
+        let mut vm_manager = VMManager::new().await?;
+        let config = config_manager.get_config().await;
+        vm_manager.set_autostart_delay(Duration::from_millis(config.autostart_delay_ms));
+        vm_manager.set_shutdown_timeout(Duration::from_millis(config.shutdown_timeout_ms));
+        vm_manager.set_notifier(Arc::new(DesktopNotifier::new("Quickemu Manager")));
+        let vm_manager = Arc::new(vm_manager);
         let (event_tx, _event_rx) = mpsc::unbounded_channel();
         let vm_discovery = Arc::new(RwLock::new(VMDiscovery::with_vm_manager(event_tx, vm_manager.clone())));
         
@@ -55,6 +62,16 @@ impl AppState {
             // Scan all directories
             discovery.scan_all_directories().await?;
         }
+
+        // Take back VMs left running by a previous session, e.g. after a crash
+        let vms = vm_discovery.read().await.get_all_vms().await;
+        vm_manager.reconcile_running_vms(&vms).await;
+
+        // Start autostart VMs in the background so the UI isn't held up
+        let autostart_manager = vm_manager.clone();
+        tokio::spawn(async move {
+            autostart_manager.start_autostart_vms(&vms).await;
+        });
         
         Ok(Self { vm_manager, vm_discovery, config_manager, ui })
     }
@@ -100,14 +117,33 @@ impl AppState {
         let discovery = self.vm_discovery.read().await;
         if let Some(vm) = discovery.get_vm(&VMId(vm_id.to_string())).await {
             drop(discovery);
-            self.vm_manager.start_vm(&vm).await?;
+            let extra_args = self.config_manager.get_extra_qemu_args(&vm.id).await;
+            self.vm_manager.start_vm_with_args(&vm, &extra_args).await?;
         }
         Ok(())
     }
 
-    async fn stop_vm(&self, vm_id: &str) -> Result<()> {
-        self.vm_manager.stop_vm(&VMId(vm_id.to_string())).await?;
-        Ok(())
+    async fn stop_vm(&self, vm_id: &str, mode: StopMode) -> Result<()> {
+        self.vm_manager
+            .stop_vm_with_progress(&VMId(vm_id.to_string()), mode, |progress| {
+                // Show the card as stopping while the guest powers off
+                if progress == StopProgress::ShuttingDown {
+                    self.set_vm_status(vm_id, "stopping");
+                }
+            })
+            .await
+    }
+
+    fn set_vm_status(&self, vm_id: &str, status: &str) {
+        let vms = self.ui.get_vms();
+        for row in 0..vms.row_count() {
+            if let Some(mut info) = vms.row_data(row) {
+                if info.id == vm_id {
+                    info.status = status.into();
+                    vms.set_row_data(row, info);
+                }
+            }
+        }
     }
 }
 
@@ -194,7 +230,8 @@ async fn main() -> Result<()> {
             let state = state.clone();
             let vm_id = vm_id.to_string();
             slint::spawn_local(async move {
-                if let Err(e) = state.stop_vm(&vm_id).await {
+                let mode = state.vm_manager.default_stop_mode();
+                if let Err(e) = state.stop_vm(&vm_id, mode).await {
                     eprintln!("Failed to stop VM: {}", e);
                 }
                 // Refresh after stopping
@@ -205,6 +242,22 @@ async fn main() -> Result<()> {
         });
     }
     
+    {
+        let state = app_state.clone();
+        ui.on_force_stop_vm(move |vm_id| {
+            let state = state.clone();
+            let vm_id = vm_id.to_string();
+            slint::spawn_local(async move {
+                if let Err(e) = state.stop_vm(&vm_id, StopMode::Force).await {
+                    eprintln!("Failed to force stop VM: {}", e);
+                }
+                if let Err(e) = state.refresh_vms().await {
+                    eprintln!("Failed to refresh VMs: {}", e);
+                }
+            }).unwrap();
+        });
+    }
+    
     ui.on_open_console(move |vm_id| {
         println!("Opening console for VM: {}", vm_id);
         // TODO: Implement console view
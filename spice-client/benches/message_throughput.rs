@@ -0,0 +1,44 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use spice_client::channels::ChannelConnection;
+use spice_client::protocol::*;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+
+const PAYLOAD_SIZE: usize = 64 * 1024;
+
+/// Message throughput with info-level logging enabled, as most users run it.
+/// Payloads used to be formatted into the log on every send.
+fn benchmark_send_message(c: &mut Criterion) {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_writer(std::io::sink)
+        .init();
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let mut channel = rt.block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 1 << 20];
+            while socket.read(&mut buf).await.unwrap_or(0) > 0 {}
+        });
+        ChannelConnection::new(&addr.ip().to_string(), addr.port(), ChannelType::Display, 0)
+            .await
+            .unwrap()
+    });
+
+    let payload = vec![0xabu8; PAYLOAD_SIZE];
+    let mut group = c.benchmark_group("send_message");
+    group.throughput(Throughput::Bytes(PAYLOAD_SIZE as u64));
+    group.bench_function("64KiB payload, info logging", |b| {
+        b.iter(|| {
+            rt.block_on(channel.send_message(SPICE_MSGC_ACK, &payload))
+                .unwrap()
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_send_message);
+criterion_main!(benches);
@@ -0,0 +1,67 @@
+//! Run the client over in-memory streams instead of a socket it dials
+//!
+//! An application embedding QEMU hands each channel's server end to QEMU,
+//! e.g. as a chardev backed by a pipe. This example stands in for that by
+//! pumping each server end to the SPICE unix socket of a running QEMU:
+//!
+//! ```sh
+//! qemu-system-x86_64 ... -spice unix=on,addr=/tmp/spice.sock,disable-ticketing=on
+//! SPICE_SOCKET=/tmp/spice.sock cargo run --example duplex_client
+//! ```
+
+use spice_client::protocol::ChannelType;
+use spice_client::{SpiceClient, SpiceError};
+use std::sync::Arc;
+use tokio::io::{duplex, DuplexStream};
+use tokio::net::UnixStream;
+
+/// Bytes buffered in each direction of a channel's stream
+const STREAM_BUFFER: usize = 256 * 1024;
+
+/// Stand-in for handing `server_end` to an in-process QEMU
+async fn attach_to_qemu(socket: &str, mut server_end: DuplexStream) -> std::io::Result<()> {
+    let mut qemu = UnixStream::connect(socket).await?;
+    tokio::io::copy_bidirectional(&mut server_end, &mut qemu).await?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), SpiceError> {
+    tracing_subscriber::fmt::init();
+
+    let socket = std::env::var("SPICE_SOCKET").unwrap_or_else(|_| "/tmp/spice.sock".to_string());
+
+    // SPICE opens a connection per channel, so the factory is asked for a
+    // new stream every time the client links one
+    let factory = move |channel_type: ChannelType, id: u8| {
+        let (client_end, server_end) = duplex(STREAM_BUFFER);
+        let socket = socket.clone();
+        tokio::spawn(async move {
+            if let Err(e) = attach_to_qemu(&socket, server_end).await {
+                eprintln!("{:?} channel {} closed: {}", channel_type, id, e);
+            }
+        });
+        async move { Ok::<_, std::io::Error>(client_end) }
+    };
+    let mut client = SpiceClient::with_connection_factory(Arc::new(factory));
+
+    client.connect().await?;
+    println!("Connected over in-memory streams");
+
+    let client_handle = tokio::spawn(async move {
+        if let Err(e) = client.start_event_loop().await {
+            eprintln!("Event loop error: {:?}", e);
+        }
+    });
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {
+            println!("\nShutting down...");
+        }
+        _ = client_handle => {
+            println!("Client disconnected");
+        }
+    }
+
+    Ok(())
+}
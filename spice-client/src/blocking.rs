@@ -0,0 +1,195 @@
+//! Synchronous client for simple tools
+//!
+//! [`SpiceClient`] wraps [`SpiceClientShared`] and a current-thread Tokio
+//! runtime of its own, so a CLI can connect and grab a screenshot without
+//! setting up async code. Every method runs the matching async one to
+//! completion.
+//!
+//! The channels only read from the server while one of these methods is
+//! running. Call [`SpiceClient::run_for`] or
+//! [`SpiceClient::wait_for_display_surface`] to let them catch up.
+//!
+//! This module is not for use inside an existing runtime: creating the
+//! client there fails, and blocking on it from async code would stall the
+//! runtime. Use [`SpiceClientShared`] directly instead.
+//!
+//! ```no_run
+//! use spice_client::blocking::SpiceClient;
+//! use spice_client::ChannelType;
+//! use std::time::Duration;
+//!
+//! # fn main() -> Result<(), spice_client::SpiceError> {
+//! let client = SpiceClient::new("localhost".to_string(), 5900)?;
+//! client.connect_with_channels(&[ChannelType::Display])?;
+//! client.start_event_loop()?;
+//! if let Some(surface) = client.wait_for_display_surface(0, Duration::from_secs(5)) {
+//!     println!("Display is {}x{}", surface.width, surface.height);
+//! }
+//! client.disconnect();
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::channels::{DisplaySurface, MonitorInfo, MouseButton, ServerInfo};
+use crate::client_shared::SpiceClientShared;
+use crate::error::{DisconnectInfo, Result, SpiceError};
+use crate::protocol::ChannelType;
+use std::future::Future;
+use std::time::Duration;
+use tokio::runtime::{Handle, Runtime};
+
+/// How often `wait_for_display_surface` checks for a surface
+const SURFACE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Blocking counterpart of [`SpiceClientShared`]
+pub struct SpiceClient {
+    client: SpiceClientShared,
+    runtime: Runtime,
+}
+
+impl SpiceClient {
+    /// Creates a client for the SPICE server at `host:port`.
+    ///
+    /// Fails when called from inside a Tokio runtime.
+    pub fn new(host: String, port: u16) -> Result<Self> {
+        if Handle::try_current().is_ok() {
+            return Err(SpiceError::Connection(
+                "the blocking client can't be used inside an async runtime; \
+                 use SpiceClientShared instead"
+                    .to_string(),
+            ));
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            client: SpiceClientShared::new(host, port),
+            runtime,
+        })
+    }
+
+    /// Sets the password used to authenticate on connect.
+    pub fn set_password(&mut self, password: String) {
+        self.runtime.block_on(self.client.set_password(password));
+    }
+
+    /// Connects to the server; see [`SpiceClientShared::connect`].
+    pub fn connect(&self) -> Result<()> {
+        self.block_on(self.client.connect())
+    }
+
+    /// Connects, opening only the secondary channels in `channels`; see
+    /// [`SpiceClientShared::connect_with_channels`].
+    pub fn connect_with_channels(&self, channels: &[ChannelType]) -> Result<()> {
+        self.block_on(self.client.connect_with_channels(channels))
+    }
+
+    /// Starts handling messages on the connected channels.
+    pub fn start_event_loop(&self) -> Result<()> {
+        self.block_on(self.client.start_event_loop())
+    }
+
+    /// Lets the channels handle messages for `duration`.
+    pub fn run_for(&self, duration: Duration) {
+        self.block_on(tokio::time::sleep(duration));
+    }
+
+    /// The primary surface of a display channel, if it has one yet.
+    pub fn get_display_surface(&self, channel_id: u8) -> Option<DisplaySurface> {
+        self.block_on(self.client.get_display_surface(channel_id))
+    }
+
+    /// The primary surface of the main monitor, if it has one yet.
+    pub fn primary_surface(&self) -> Option<DisplaySurface> {
+        self.block_on(self.client.primary_surface())
+    }
+
+    /// Asks the server to repaint a display channel in full.
+    pub fn request_refresh(&self, channel_id: u8) -> Result<()> {
+        self.block_on(self.client.request_refresh(channel_id))
+    }
+
+    /// Handles messages until a display channel has a primary surface, for
+    /// up to `timeout`.
+    pub fn wait_for_display_surface(
+        &self,
+        channel_id: u8,
+        timeout: Duration,
+    ) -> Option<DisplaySurface> {
+        self.block_on(async {
+            tokio::time::timeout(timeout, async {
+                loop {
+                    if let Some(surface) = self.client.get_display_surface(channel_id).await {
+                        return surface;
+                    }
+                    tokio::time::sleep(SURFACE_POLL_INTERVAL).await;
+                }
+            })
+            .await
+            .ok()
+        })
+    }
+
+    /// The guest's display heads and their resolutions.
+    pub fn guest_monitors(&self) -> Vec<MonitorInfo> {
+        self.block_on(self.client.guest_monitors())
+    }
+
+    /// Information the server sent when the main channel connected.
+    pub fn server_info(&self) -> Option<ServerInfo> {
+        self.block_on(self.client.server_info())
+    }
+
+    /// The channels that are connected, by type and id.
+    pub fn connected_channels(&self) -> Vec<(ChannelType, u8)> {
+        self.block_on(self.client.connected_channels())
+    }
+
+    /// Why the connection was lost, if it was.
+    pub fn get_disconnect_info(&self) -> Option<DisconnectInfo> {
+        self.block_on(self.client.get_disconnect_info())
+    }
+
+    /// Sends a key down event to the specified inputs channel.
+    pub fn send_key_down(&self, channel_id: u8, scancode: u32) -> Result<()> {
+        self.block_on(self.client.send_key_down(channel_id, scancode))
+    }
+
+    /// Sends a key up event to the specified inputs channel.
+    pub fn send_key_up(&self, channel_id: u8, scancode: u32) -> Result<()> {
+        self.block_on(self.client.send_key_up(channel_id, scancode))
+    }
+
+    /// Sends a mouse motion event to the specified inputs channel.
+    pub fn send_mouse_motion(&self, channel_id: u8, x: i32, y: i32) -> Result<()> {
+        self.block_on(self.client.send_mouse_motion(channel_id, x, y))
+    }
+
+    /// Sends a mouse button event to the specified inputs channel.
+    pub fn send_mouse_button(
+        &self,
+        channel_id: u8,
+        button: MouseButton,
+        pressed: bool,
+    ) -> Result<()> {
+        self.block_on(self.client.send_mouse_button(channel_id, button, pressed))
+    }
+
+    /// Disconnects from the server.
+    pub fn disconnect(&self) {
+        self.block_on(self.client.disconnect());
+    }
+
+    /// The async client underneath, for calls this module doesn't wrap.
+    /// Run them with [`block_on`](Self::block_on).
+    pub fn as_async(&self) -> &SpiceClientShared {
+        &self.client
+    }
+
+    /// Runs `future` on the client's runtime until it completes, letting
+    /// the channels handle messages meanwhile.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+}
@@ -0,0 +1,296 @@
+//! Capabilities exchanged when linking channels
+//!
+//! Capabilities travel as bitmaps of `u32` words. They are decoded into sets
+//! of capability numbers, the `SPICE_COMMON_CAP_*` and `SPICE_*_CAP_*`
+//! constants in [`crate::protocol`].
+
+use crate::protocol::{
+    ChannelType, SPICE_DISPLAY_CAP_CODEC_MJPEG, SPICE_DISPLAY_CAP_LZ4_COMPRESSION,
+    SPICE_DISPLAY_CAP_MULTI_CODEC, SPICE_DISPLAY_CAP_PREF_COMPRESSION_SETTING,
+    SPICE_DISPLAY_CAP_STREAM_REPORT, SPICE_MAIN_CAP_NAME_AND_UUID,
+};
+use std::collections::{BTreeSet, HashMap};
+
+/// Set of capability numbers of one kind
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CapabilitySet(BTreeSet<u32>);
+
+impl CapabilitySet {
+    /// Decode a capability bitmap, bit `n` of word `n / 32` standing for
+    /// capability `n`
+    pub fn from_words(words: &[u32]) -> Self {
+        let caps = words
+            .iter()
+            .enumerate()
+            .flat_map(|(index, word)| {
+                (0..32)
+                    .filter(move |bit| word & (1 << bit) != 0)
+                    .map(move |bit| index as u32 * 32 + bit)
+            })
+            .collect();
+        Self(caps)
+    }
+
+    pub fn contains(&self, cap: u32) -> bool {
+        self.0.contains(&cap)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Capability numbers in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+/// What the server supports, as advertised in the link replies of the
+/// channels connected so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerCapabilities {
+    common: CapabilitySet,
+    channels: HashMap<ChannelType, CapabilitySet>,
+}
+
+impl ServerCapabilities {
+    /// Record the capabilities from the link reply of a channel
+    pub(crate) fn record(
+        &mut self,
+        channel_type: ChannelType,
+        common: CapabilitySet,
+        channel: CapabilitySet,
+    ) {
+        self.common = common;
+        self.channels.insert(channel_type, channel);
+    }
+
+    /// `SPICE_COMMON_CAP_*` capabilities, shared by all channels
+    pub fn common(&self) -> &CapabilitySet {
+        &self.common
+    }
+
+    /// Capabilities of a channel type, if a channel of that type is linked
+    pub fn channel(&self, channel_type: ChannelType) -> Option<&CapabilitySet> {
+        self.channels.get(&channel_type)
+    }
+
+    pub fn has_common(&self, cap: u32) -> bool {
+        self.common.contains(cap)
+    }
+
+    pub fn has_channel_cap(&self, channel_type: ChannelType, cap: u32) -> bool {
+        self.channel(channel_type)
+            .is_some_and(|caps| caps.contains(cap))
+    }
+}
+
+/// Capabilities advertised whatever the features, for parts of the
+/// protocol the client always implements
+const BASE_CAPS: &[(ChannelType, u32)] = &[
+    // Ask the server to tell us the VM name and UUID
+    (ChannelType::Main, SPICE_MAIN_CAP_NAME_AND_UUID),
+    // Let the client pick the image compression, and report on video
+    // streams so the server can adapt their bitrate
+    (
+        ChannelType::Display,
+        SPICE_DISPLAY_CAP_PREF_COMPRESSION_SETTING,
+    ),
+    (ChannelType::Display, SPICE_DISPLAY_CAP_STREAM_REPORT),
+];
+
+/// A decoder or optional protocol feature that servers need to be told about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ClientFeature {
+    /// LZ4 compressed images
+    Lz4,
+    /// MJPEG video streams
+    Jpeg,
+}
+
+impl ClientFeature {
+    /// Every feature the client knows about
+    pub const ALL: [ClientFeature; 2] = [ClientFeature::Lz4, ClientFeature::Jpeg];
+
+    /// The channel capabilities announcing this feature
+    fn caps(self) -> &'static [(ChannelType, u32)] {
+        match self {
+            ClientFeature::Lz4 => &[(ChannelType::Display, SPICE_DISPLAY_CAP_LZ4_COMPRESSION)],
+            ClientFeature::Jpeg => &[
+                (ChannelType::Display, SPICE_DISPLAY_CAP_MULTI_CODEC),
+                (ChannelType::Display, SPICE_DISPLAY_CAP_CODEC_MJPEG),
+            ],
+        }
+    }
+}
+
+/// The features the client supports, which decide the capabilities it
+/// advertises unless they are overridden. All features are enabled by
+/// default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientFeatures(BTreeSet<ClientFeature>);
+
+impl Default for ClientFeatures {
+    fn default() -> Self {
+        Self(ClientFeature::ALL.into_iter().collect())
+    }
+}
+
+impl ClientFeatures {
+    /// No features, so only the capabilities of the basic protocol are
+    /// advertised
+    pub fn none() -> Self {
+        Self(BTreeSet::new())
+    }
+
+    pub fn enable(&mut self, feature: ClientFeature) {
+        self.0.insert(feature);
+    }
+
+    pub fn disable(&mut self, feature: ClientFeature) {
+        self.0.remove(&feature);
+    }
+
+    pub fn contains(&self, feature: ClientFeature) -> bool {
+        self.0.contains(&feature)
+    }
+
+    /// `SPICE_COMMON_CAP_*` capabilities to advertise; none of the features
+    /// need one
+    pub fn common_caps(&self) -> Vec<u32> {
+        Vec::new()
+    }
+
+    /// Capabilities to advertise for channels of `channel_type`, in
+    /// ascending order
+    pub fn channel_caps(&self, channel_type: ChannelType) -> Vec<u32> {
+        let feature_caps = self.0.iter().flat_map(|feature| feature.caps());
+        let caps: BTreeSet<u32> = BASE_CAPS
+            .iter()
+            .chain(feature_caps)
+            .filter(|(channel, _)| *channel == channel_type)
+            .map(|(_, cap)| *cap)
+            .collect();
+        caps.into_iter().collect()
+    }
+}
+
+/// Capabilities the client advertises in its link messages: those of its
+/// [`ClientFeatures`], replaced by explicit lists where set
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdvertisedCapabilities {
+    features: ClientFeatures,
+    common: Option<Vec<u32>>,
+    channels: HashMap<ChannelType, Vec<u32>>,
+}
+
+impl AdvertisedCapabilities {
+    /// Advertise `caps` as the `SPICE_COMMON_CAP_*` capabilities of every
+    /// channel
+    pub fn set_common(&mut self, caps: &[u32]) {
+        self.common = Some(caps.to_vec());
+    }
+
+    /// Advertise `caps` as the capabilities of channels of `channel_type`
+    pub fn set_channel(&mut self, channel_type: ChannelType, caps: &[u32]) {
+        self.channels.insert(channel_type, caps.to_vec());
+    }
+
+    /// Set the features whose capabilities are advertised where not
+    /// overridden
+    pub fn set_features(&mut self, features: ClientFeatures) {
+        self.features = features;
+    }
+
+    pub fn features(&self) -> &ClientFeatures {
+        &self.features
+    }
+
+    /// Common capabilities to advertise, if overridden
+    pub fn common(&self) -> Option<&[u32]> {
+        self.common.as_deref()
+    }
+
+    /// Capabilities to advertise for a channel type, if overridden
+    pub fn channel(&self, channel_type: ChannelType) -> Option<&[u32]> {
+        self.channels.get(&channel_type).map(Vec::as_slice)
+    }
+
+    /// Common capabilities to put in a link message
+    pub fn common_caps(&self) -> Vec<u32> {
+        self.common()
+            .map(<[u32]>::to_vec)
+            .unwrap_or_else(|| self.features.common_caps())
+    }
+
+    /// Capabilities to put in the link message of a `channel_type` channel
+    pub fn channel_caps(&self, channel_type: ChannelType) -> Vec<u32> {
+        self.channel(channel_type)
+            .map(<[u32]>::to_vec)
+            .unwrap_or_else(|| self.features.channel_caps(channel_type))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{SPICE_DISPLAY_CAP_CODEC_VP9, SPICE_DISPLAY_CAP_SIZED_STREAM};
+
+    #[test]
+    fn test_decodes_bitmap_words() {
+        let caps = CapabilitySet::from_words(&[
+            1 << SPICE_DISPLAY_CAP_PREF_COMPRESSION_SETTING | 1 << SPICE_DISPLAY_CAP_CODEC_VP9,
+            1 << 1,
+        ]);
+        assert_eq!(
+            caps.iter().collect::<Vec<_>>(),
+            vec![
+                SPICE_DISPLAY_CAP_PREF_COMPRESSION_SETTING,
+                SPICE_DISPLAY_CAP_CODEC_VP9,
+                33
+            ]
+        );
+        assert!(!caps.contains(SPICE_DISPLAY_CAP_LZ4_COMPRESSION));
+        assert!(CapabilitySet::from_words(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_enabling_jpeg_advertises_mjpeg() {
+        let mut features = ClientFeatures::none();
+        assert!(!features
+            .channel_caps(ChannelType::Display)
+            .contains(&SPICE_DISPLAY_CAP_CODEC_MJPEG));
+
+        features.enable(ClientFeature::Jpeg);
+
+        assert_eq!(
+            features.channel_caps(ChannelType::Display),
+            vec![
+                SPICE_DISPLAY_CAP_STREAM_REPORT,
+                SPICE_DISPLAY_CAP_PREF_COMPRESSION_SETTING,
+                SPICE_DISPLAY_CAP_MULTI_CODEC,
+                SPICE_DISPLAY_CAP_CODEC_MJPEG,
+            ]
+        );
+        // Other channels are unaffected
+        assert_eq!(
+            features.channel_caps(ChannelType::Main),
+            vec![SPICE_MAIN_CAP_NAME_AND_UUID]
+        );
+    }
+
+    #[test]
+    fn test_overrides_replace_feature_caps() {
+        let mut caps = AdvertisedCapabilities::default();
+        assert!(caps
+            .channel_caps(ChannelType::Display)
+            .contains(&SPICE_DISPLAY_CAP_LZ4_COMPRESSION));
+
+        caps.set_channel(ChannelType::Display, &[SPICE_DISPLAY_CAP_SIZED_STREAM]);
+
+        assert_eq!(
+            caps.channel_caps(ChannelType::Display),
+            vec![SPICE_DISPLAY_CAP_SIZED_STREAM]
+        );
+    }
+}
@@ -1,9 +1,14 @@
 //! Cursor channel implementation for hardware cursor support
 
+use crate::channels::display::unpack_rgb16;
+#[cfg(target_arch = "wasm32")]
+use crate::channels::ConnectOptions;
 use crate::channels::{Channel, ChannelConnection};
 use crate::error::Result;
 use crate::protocol::*;
+use binrw::BinRead;
 use std::collections::HashMap;
+use std::io::Cursor;
 use tracing::{debug, info, warn};
 
 /// Cursor shape data
@@ -13,10 +18,24 @@ pub struct CursorShape {
     pub height: u16,
     pub hot_spot_x: u16,
     pub hot_spot_y: u16,
+    /// RGBA pixels, row by row, whatever type the server sent
     pub data: Vec<u8>,
     pub mask: Option<Vec<u8>>,
 }
 
+/// Pointer changes the server reports on the cursor channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorEvent {
+    /// The server placed the pointer, in guest display coordinates. In
+    /// server mouse mode the guest owns the pointer, so the local one
+    /// should be drawn here.
+    Moved { x: i32, y: i32 },
+    /// The cursor became visible
+    Shown,
+    /// The cursor was hidden
+    Hidden,
+}
+
 /// Cursor channel for handling mouse cursor updates
 pub struct CursorChannel {
     pub(crate) connection: ChannelConnection,
@@ -24,6 +43,7 @@ pub struct CursorChannel {
     cursor_cache: HashMap<u64, CursorShape>,
     cursor_visible: bool,
     cursor_position: (i32, i32),
+    event_callback: Option<Box<dyn Fn(&CursorEvent) + Send + Sync>>,
 }
 
 impl CursorChannel {
@@ -37,8 +57,16 @@ impl CursorChannel {
         channel_id: u8,
         connection_id: Option<u32>,
     ) -> Result<Self> {
-        let mut connection =
+        let connection =
             ChannelConnection::new(host, port, ChannelType::Cursor, channel_id).await?;
+        Self::from_connection(connection, connection_id).await
+    }
+
+    /// Link an already opened `connection` as a cursor channel
+    pub(crate) async fn from_connection(
+        mut connection: ChannelConnection,
+        connection_id: Option<u32>,
+    ) -> Result<Self> {
         if let Some(conn_id) = connection_id {
             connection.set_connection_id(conn_id);
         }
@@ -50,6 +78,7 @@ impl CursorChannel {
             cursor_cache: HashMap::new(),
             cursor_visible: true,
             cursor_position: (0, 0),
+            event_callback: None,
         })
     }
 
@@ -64,8 +93,15 @@ impl CursorChannel {
         channel_id: u8,
         auth_token: Option<String>,
     ) -> Result<Self> {
-        Self::new_websocket_with_auth_and_session(websocket_url, channel_id, auth_token, None, None)
-            .await
+        Self::new_websocket_with_auth_and_session(
+            websocket_url,
+            channel_id,
+            auth_token,
+            None,
+            None,
+            ConnectOptions::default(),
+        )
+        .await
     }
 
     #[cfg(target_arch = "wasm32")]
@@ -75,12 +111,14 @@ impl CursorChannel {
         auth_token: Option<String>,
         password: Option<String>,
         connection_id: Option<u32>,
+        options: ConnectOptions,
     ) -> Result<Self> {
-        let mut connection = ChannelConnection::new_websocket_with_auth(
+        let mut connection = ChannelConnection::new_websocket_with_options(
             websocket_url,
             ChannelType::Cursor,
             channel_id,
             auth_token,
+            options,
         )
         .await?;
         if let Some(pwd) = password {
@@ -97,6 +135,7 @@ impl CursorChannel {
             cursor_cache: HashMap::new(),
             cursor_visible: true,
             cursor_position: (0, 0),
+            event_callback: None,
         })
     }
 
@@ -117,90 +156,152 @@ impl CursorChannel {
         self.cursor_position
     }
 
+    /// Calls `callback` when the server moves, shows or hides the cursor
+    pub fn set_event_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(&CursorEvent) + Send + Sync + 'static,
+    {
+        self.event_callback = Some(Box::new(callback));
+    }
+
+    fn notify_event(&self, event: CursorEvent) {
+        if let Some(ref callback) = self.event_callback {
+            callback(&event);
+        }
+    }
+
+    fn set_position(&mut self, x: i32, y: i32) {
+        self.cursor_position = (x, y);
+        self.notify_event(CursorEvent::Moved { x, y });
+    }
+
+    fn set_visible(&mut self, visible: bool) {
+        if self.cursor_visible == visible {
+            return;
+        }
+        self.cursor_visible = visible;
+        self.notify_event(if visible {
+            CursorEvent::Shown
+        } else {
+            CursorEvent::Hidden
+        });
+    }
+
+    /// Process a single message from the server
+    /// This is primarily for testing purposes
+    pub async fn process_next_message(&mut self) -> Result<()> {
+        let (header, data) = self.connection.read_message().await?;
+        let result = self.handle_message(&header, &data).await;
+        self.connection.check_message_result(&header, result)
+    }
+
     pub async fn run(&mut self) -> Result<()> {
         loop {
             let (header, data) = self.connection.read_message().await?;
-            self.handle_message(&header, &data).await?;
+            let result = self.handle_message(&header, &data).await;
+            self.connection.check_message_result(&header, result)?;
         }
     }
 
     async fn handle_cursor_init(&mut self, data: &[u8]) -> Result<()> {
-        if data.len() >= 8 {
-            let visible = u16::from_le_bytes([data[0], data[1]]) != 0;
-            let x = i16::from_le_bytes([data[2], data[3]]) as i32;
-            let y = i16::from_le_bytes([data[4], data[5]]) as i32;
-            let trail_len = u16::from_le_bytes([data[6], data[7]]);
+        let mut cursor = Cursor::new(data);
+        let init = SpiceMsgCursorInit::read(&mut cursor)?;
+        let shape = &data[cursor.position() as usize..];
+
+        info!(
+            "Cursor init - visible: {}, position: ({}, {}), trail: {}",
+            init.visible, init.position.x, init.position.y, init.trail_length
+        );
+        self.set_visible(init.visible != 0);
+        self.set_position(init.position.x as i32, init.position.y as i32);
+        self.read_cursor_shape(shape);
+        Ok(())
+    }
 
-            self.cursor_visible = visible;
-            self.cursor_position = (x, y);
+    async fn handle_cursor_set(&mut self, data: &[u8]) -> Result<()> {
+        let mut cursor = Cursor::new(data);
+        let set = SpiceMsgCursorSet::read(&mut cursor)?;
+        let shape = &data[cursor.position() as usize..];
 
-            info!(
-                "Cursor init - visible: {}, position: ({}, {}), trail: {}",
-                visible, x, y, trail_len
-            );
-        }
+        self.read_cursor_shape(shape);
+        self.set_visible(set.visible != 0);
+        self.set_position(set.position.x as i32, set.position.y as i32);
         Ok(())
     }
 
-    async fn handle_cursor_set(&mut self, data: &[u8]) -> Result<()> {
-        if data.len() >= 17 {
-            let cursor_header = SpiceCursorHeader {
-                unique: u64::from_le_bytes([
-                    data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
-                ]),
-                type_: data[8],
-                width: u16::from_le_bytes([data[9], data[10]]),
-                height: u16::from_le_bytes([data[11], data[12]]),
-                hot_spot_x: u16::from_le_bytes([data[13], data[14]]),
-                hot_spot_y: u16::from_le_bytes([data[15], data[16]]),
-            };
+    /// Reads the `SpiceCursor` at the end of an init or set message
+    fn read_cursor_shape(&mut self, data: &[u8]) {
+        if data.len() < 2 {
+            return;
+        }
+        let flags = u16::from_le_bytes([data[0], data[1]]);
+        if flags & SPICE_CURSOR_FLAGS_NONE != 0 {
+            return;
+        }
+
+        let data = &data[2..];
+        if data.len() < 17 {
+            warn!("Cursor shape too short: {} bytes", data.len());
+            return;
+        }
+        let cursor_header = SpiceCursorHeader {
+            unique: u64::from_le_bytes([
+                data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
+            ]),
+            type_: data[8],
+            width: u16::from_le_bytes([data[9], data[10]]),
+            height: u16::from_le_bytes([data[11], data[12]]),
+            hot_spot_x: u16::from_le_bytes([data[13], data[14]]),
+            hot_spot_y: u16::from_le_bytes([data[15], data[16]]),
+        };
 
-            let data_offset = 17;
-            let data_size = (cursor_header.width * cursor_header.height * 4) as usize;
-
-            if data.len() >= data_offset + data_size {
-                let cursor_data = data[data_offset..data_offset + data_size].to_vec();
-
-                let cursor_shape = CursorShape {
-                    width: cursor_header.width,
-                    height: cursor_header.height,
-                    hot_spot_x: cursor_header.hot_spot_x,
-                    hot_spot_y: cursor_header.hot_spot_y,
-                    data: cursor_data,
-                    mask: None,
-                };
-
-                // Cache the cursor
-                self.cursor_cache
-                    .insert(cursor_header.unique, cursor_shape.clone());
-                self.current_cursor = Some(cursor_shape);
-
-                info!(
-                    "Set cursor - {}x{}, hotspot: ({}, {})",
-                    cursor_header.width,
-                    cursor_header.height,
-                    cursor_header.hot_spot_x,
-                    cursor_header.hot_spot_y
-                );
+        if flags & SPICE_CURSOR_FLAGS_FROM_CACHE != 0 {
+            match self.cursor_cache.get(&cursor_header.unique) {
+                Some(shape) => self.current_cursor = Some(shape.clone()),
+                None => warn!("Cursor {} is not in the cache", cursor_header.unique),
             }
+            return;
         }
-        Ok(())
-    }
 
-    async fn handle_cursor_move(&mut self, data: &[u8]) -> Result<()> {
-        if data.len() >= 4 {
-            let x = i16::from_le_bytes([data[0], data[1]]) as i32;
-            let y = i16::from_le_bytes([data[2], data[3]]) as i32;
+        let Some(pixels) = decode_cursor_pixels(&cursor_header, &data[17..]) else {
+            return;
+        };
+        let cursor_shape = CursorShape {
+            width: cursor_header.width,
+            height: cursor_header.height,
+            hot_spot_x: cursor_header.hot_spot_x,
+            hot_spot_y: cursor_header.hot_spot_y,
+            data: pixels,
+            mask: None,
+        };
 
-            self.cursor_position = (x, y);
-            debug!("Cursor moved to ({}, {})", x, y);
+        if flags & SPICE_CURSOR_FLAGS_CACHE_ME != 0 {
+            self.cursor_cache
+                .insert(cursor_header.unique, cursor_shape.clone());
         }
+        self.current_cursor = Some(cursor_shape);
+
+        info!(
+            "Set cursor - type {}, {}x{}, hotspot: ({}, {})",
+            cursor_header.type_,
+            cursor_header.width,
+            cursor_header.height,
+            cursor_header.hot_spot_x,
+            cursor_header.hot_spot_y
+        );
+    }
+
+    async fn handle_cursor_move(&mut self, data: &[u8]) -> Result<()> {
+        let msg = SpiceMsgCursorMove::read(&mut Cursor::new(data))?;
+        let (x, y) = (msg.position.x as i32, msg.position.y as i32);
+        debug!("Cursor moved to ({}, {})", x, y);
+        self.set_position(x, y);
         Ok(())
     }
 
     async fn handle_cursor_hide(&mut self) -> Result<()> {
-        self.cursor_visible = false;
         debug!("Cursor hidden");
+        self.set_visible(false);
         Ok(())
     }
 
@@ -238,37 +339,44 @@ impl CursorChannel {
 
 impl Channel for CursorChannel {
     async fn handle_message(&mut self, header: &SpiceDataHeader, data: &[u8]) -> Result<()> {
-        match header.msg_type {
-            SPICE_MSG_CURSOR_INIT => {
+        let msg = match CursorChannelMessage::try_from(header.msg_type) {
+            Ok(msg) => msg,
+            Err(msg_type) => {
+                warn!("Unknown cursor message type: {}", msg_type);
+                return Ok(());
+            }
+        };
+        match msg {
+            CursorChannelMessage::Init => {
                 debug!("Received cursor init");
                 self.handle_cursor_init(data).await?;
             }
-            SPICE_MSG_CURSOR_SET => {
+            CursorChannelMessage::Set => {
                 debug!("Received cursor set");
                 self.handle_cursor_set(data).await?;
             }
-            SPICE_MSG_CURSOR_MOVE => {
+            CursorChannelMessage::Move => {
                 debug!("Received cursor move");
                 self.handle_cursor_move(data).await?;
             }
-            SPICE_MSG_CURSOR_HIDE => {
+            CursorChannelMessage::Hide => {
                 debug!("Received cursor hide");
                 self.handle_cursor_hide().await?;
             }
-            SPICE_MSG_CURSOR_TRAIL => {
+            CursorChannelMessage::Trail => {
                 debug!("Received cursor trail");
                 self.handle_cursor_trail(data).await?;
             }
-            SPICE_MSG_CURSOR_INVAL_ONE => {
+            CursorChannelMessage::InvalOne => {
                 debug!("Received cursor inval one");
                 self.handle_cursor_inval_one(data).await?;
             }
-            SPICE_MSG_CURSOR_INVAL_ALL => {
+            CursorChannelMessage::InvalAll => {
                 debug!("Received cursor inval all");
                 self.handle_cursor_inval_all().await?;
             }
-            _ => {
-                warn!("Unknown cursor message type: {}", header.msg_type);
+            other => {
+                debug!("Unhandled cursor message {:?}", other);
             }
         }
 
@@ -290,6 +398,126 @@ pub struct SpiceCursorHeader {
     pub hot_spot_y: u16,
 }
 
+/// Pixel of a masked cursor whose AND mask bit is set: the XOR value is
+/// applied to the screen. Zero leaves the screen alone; anything else
+/// inverts it, which RGBA can't express, so it's drawn opaque black.
+const INVERTED_PIXEL: [u8; 4] = [0, 0, 0, 255];
+const TRANSPARENT_PIXEL: [u8; 4] = [0, 0, 0, 0];
+
+/// Bytes per row of a 1-bit mask
+fn mask_stride(width: usize) -> usize {
+    (width + 7) / 8
+}
+
+/// Whether the bit for column `x` is set in a row of a 1-bit mask, most
+/// significant bit first
+fn mask_bit(row: &[u8], x: usize) -> bool {
+    row[x / 8] & (0x80 >> (x % 8)) != 0
+}
+
+/// Convert the shape data of a cursor to RGBA.
+///
+/// Returns `None` for unknown types and truncated data.
+pub(crate) fn decode_cursor_pixels(header: &SpiceCursorHeader, data: &[u8]) -> Option<Vec<u8>> {
+    let (width, height) = (header.width as usize, header.height as usize);
+    let mask_size = mask_stride(width) * height;
+
+    // Bytes of pixel data per row, and of palette entries after the pixels
+    let (row_size, palette_len) = match header.type_ {
+        SPICE_CURSOR_TYPE_ALPHA => (width * 4, 0),
+        SPICE_CURSOR_TYPE_MONO => (mask_stride(width), 0),
+        SPICE_CURSOR_TYPE_COLOR4 => ((width + 1) / 2, 16),
+        SPICE_CURSOR_TYPE_COLOR8 => (width, 256),
+        SPICE_CURSOR_TYPE_COLOR16 => (width * 2, 0),
+        SPICE_CURSOR_TYPE_COLOR24 => (width * 3, 0),
+        SPICE_CURSOR_TYPE_COLOR32 => (width * 4, 0),
+        other => {
+            warn!("Unsupported cursor type: {}", other);
+            return None;
+        }
+    };
+    let pixels_size = row_size * height;
+    let palette_size = palette_len * 4;
+    let expected = match header.type_ {
+        SPICE_CURSOR_TYPE_ALPHA => pixels_size,
+        // The XOR mask is the pixel data
+        SPICE_CURSOR_TYPE_MONO => mask_size + pixels_size,
+        _ => pixels_size + palette_size + mask_size,
+    };
+    if data.len() < expected {
+        warn!(
+            "Cursor data too short for type {}: {} < {}",
+            header.type_,
+            data.len(),
+            expected
+        );
+        return None;
+    }
+
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    if header.type_ == SPICE_CURSOR_TYPE_ALPHA {
+        // BGRA -> RGBA
+        for pixel in data[..pixels_size].chunks_exact(4) {
+            rgba.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+        }
+        return Some(rgba);
+    }
+
+    let (and_mask, pixels) = if header.type_ == SPICE_CURSOR_TYPE_MONO {
+        (
+            &data[..mask_size],
+            &data[mask_size..mask_size + pixels_size],
+        )
+    } else {
+        let mask_start = pixels_size + palette_size;
+        (
+            &data[mask_start..mask_start + mask_size],
+            &data[..pixels_size],
+        )
+    };
+    let palette: Vec<u32> = data[pixels_size..pixels_size + palette_size]
+        .chunks_exact(4)
+        .map(|entry| u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]))
+        .collect();
+    let from_palette = |index: usize| {
+        let color = palette.get(index).copied().unwrap_or(0);
+        [(color >> 16) as u8, (color >> 8) as u8, color as u8, 255]
+    };
+
+    for y in 0..height {
+        let and_row = &and_mask[y * mask_stride(width)..];
+        let row = &pixels[y * row_size..(y + 1) * row_size];
+        for x in 0..width {
+            let color = match header.type_ {
+                SPICE_CURSOR_TYPE_MONO if mask_bit(row, x) => [255, 255, 255, 255],
+                SPICE_CURSOR_TYPE_MONO => [0, 0, 0, 255],
+                SPICE_CURSOR_TYPE_COLOR4 => {
+                    let byte = row[x / 2];
+                    let index = if x % 2 == 0 { byte >> 4 } else { byte & 0x0F };
+                    from_palette(index as usize)
+                }
+                SPICE_CURSOR_TYPE_COLOR8 => from_palette(row[x] as usize),
+                SPICE_CURSOR_TYPE_COLOR16 => unpack_rgb16(
+                    u16::from_le_bytes([row[x * 2], row[x * 2 + 1]]),
+                    SurfaceFormat::Rgb555,
+                ),
+                SPICE_CURSOR_TYPE_COLOR24 => [row[x * 3 + 2], row[x * 3 + 1], row[x * 3], 255],
+                _ => [row[x * 4 + 2], row[x * 4 + 1], row[x * 4], 255],
+            };
+
+            let pixel = if !mask_bit(and_row, x) {
+                color
+            } else if color[..3] == [0, 0, 0] {
+                TRANSPARENT_PIXEL
+            } else {
+                INVERTED_PIXEL
+            };
+            rgba.extend_from_slice(&pixel);
+        }
+    }
+    Some(rgba)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -312,6 +540,71 @@ mod tests {
         assert_eq!(cursor.data.len(), 32 * 32 * 4);
     }
 
+    fn cursor_header(type_: u8, width: u16, height: u16) -> SpiceCursorHeader {
+        SpiceCursorHeader {
+            unique: 1,
+            type_,
+            width,
+            height,
+            hot_spot_x: 0,
+            hot_spot_y: 0,
+        }
+    }
+
+    #[test]
+    fn test_decode_mono_cursor() {
+        // Row 0 covers every AND/XOR combination, row 1 is transparent
+        let data = [
+            0b1100_0000, // AND row 0
+            0b1111_0000, // AND row 1
+            0b0101_0000, // XOR row 0
+            0b0000_0000, // XOR row 1
+        ];
+
+        let rgba =
+            decode_cursor_pixels(&cursor_header(SPICE_CURSOR_TYPE_MONO, 4, 2), &data).unwrap();
+
+        let pixels: Vec<&[u8]> = rgba.chunks(4).collect();
+        assert_eq!(pixels[0], TRANSPARENT_PIXEL); // AND 1, XOR 0
+        assert_eq!(pixels[1], INVERTED_PIXEL); // AND 1, XOR 1
+        assert_eq!(pixels[2], [0, 0, 0, 255]); // AND 0, XOR 0
+        assert_eq!(pixels[3], [255, 255, 255, 255]); // AND 0, XOR 1
+        assert!(pixels[4..].iter().all(|pixel| *pixel == TRANSPARENT_PIXEL));
+    }
+
+    #[test]
+    fn test_decode_color_cursor_applies_and_mask() {
+        // Two 8-bit pixels, palette entries 0 (black) and 1 (red), mask
+        // hiding the first pixel
+        let mut data = vec![0, 1];
+        data.extend(0u32.to_le_bytes());
+        data.extend(0x00FF_0000u32.to_le_bytes());
+        data.extend([0u8; 254 * 4]);
+        data.push(0b1000_0000);
+
+        let rgba =
+            decode_cursor_pixels(&cursor_header(SPICE_CURSOR_TYPE_COLOR8, 2, 1), &data).unwrap();
+
+        assert_eq!(rgba, [0, 0, 0, 0, 255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_decode_alpha_cursor_swaps_to_rgba() {
+        let data = [0x10, 0x20, 0x30, 0x80];
+
+        let rgba =
+            decode_cursor_pixels(&cursor_header(SPICE_CURSOR_TYPE_ALPHA, 1, 1), &data).unwrap();
+
+        assert_eq!(rgba, [0x30, 0x20, 0x10, 0x80]);
+    }
+
+    #[test]
+    fn test_decode_truncated_cursor() {
+        let header = cursor_header(SPICE_CURSOR_TYPE_MONO, 32, 32);
+
+        assert!(decode_cursor_pixels(&header, &[0; 64]).is_none());
+    }
+
     #[tokio::test]
     async fn test_cursor_position() {
         // Test cursor position parsing
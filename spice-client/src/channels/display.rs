@@ -1,9 +1,16 @@
-use crate::channels::{Channel, ChannelConnection};
+#[cfg(target_arch = "wasm32")]
+use crate::channels::ConnectOptions;
+use crate::channels::{Channel, ChannelConnection, MediaClock, QosGate};
 use crate::error::{Result, SpiceError};
 use crate::protocol::*;
 use binrw::BinRead;
+use instant::{Duration, Instant};
+use std::borrow::Cow;
 use std::collections::HashMap;
-use tracing::{debug, info, warn};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, Notify};
+use tracing::{debug, info, trace, warn};
 
 // Integration tests moved to tests/display_integration.rs
 
@@ -19,6 +26,8 @@ struct CachedImage {
     data: Vec<u8>,
     width: u32,
     height: u32,
+    // false when the entry came from a lossy encoding (JPEG)
+    lossless: bool,
 }
 
 impl ImageCache {
@@ -32,24 +41,736 @@ impl ImageCache {
         self.entries.get(&id)
     }
 
-    fn insert(&mut self, id: u64, data: Vec<u8>, width: u32, height: u32) {
+    /// Look up an entry for a FROM_CACHE / FROM_CACHE_LOSSLESS reference.
+    ///
+    /// A FROM_CACHE_LOSSLESS request must be served the lossless original,
+    /// so a lossy entry is treated as a miss.
+    fn lookup(&self, id: u64, image_type: u8) -> Option<&CachedImage> {
+        let cached = self.get(id)?;
+        if image_type == SPICE_IMAGE_TYPE_FROM_CACHE_LOSSLESS && !cached.lossless {
+            return None;
+        }
+        Some(cached)
+    }
+
+    fn insert(&mut self, id: u64, data: Vec<u8>, width: u32, height: u32, lossless: bool) {
         self.entries.insert(
             id,
             CachedImage {
                 data,
                 width,
                 height,
+                lossless,
             },
         );
     }
+
+    fn remove(&mut self, id: u64) -> bool {
+        self.entries.remove(&id).is_some()
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Drop the pixmaps named in an INVAL_LIST; other resource types are ignored
+    fn invalidate(&mut self, list: &SpiceResourceList) {
+        for resource in &list.resources {
+            if resource.type_ != SPICE_RES_TYPE_PIXMAP {
+                debug!(
+                    "Ignoring invalidation of resource type {} id {}",
+                    resource.type_, resource.id
+                );
+                continue;
+            }
+            if !self.remove(resource.id) {
+                trace!("Invalidated image {} was not cached", resource.id);
+            }
+        }
+    }
+
+    /// Whether images of this type are decoded to their exact original pixels
+    fn is_lossless_type(image_type: u8) -> bool {
+        !matches!(
+            image_type,
+            SPICE_IMAGE_TYPE_JPEG | SPICE_IMAGE_TYPE_JPEG_ALPHA
+        )
+    }
+}
+
+/// Byte order 32-bit surfaces are kept in, so renderers can upload them
+/// without converting
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// R, G, B, A bytes, as canvases and most GL paths take them
+    #[default]
+    Rgba8,
+    /// B, G, R, A bytes, as Cairo and GDK memory textures on little-endian
+    /// hosts take them
+    Bgra8,
+    /// R, G, B, A bytes with the colors multiplied by alpha
+    RgbaPremultiplied,
+}
+
+impl PixelFormat {
+    /// Convert an RGBA pixel to this format
+    fn encode(self, [r, g, b, a]: [u8; 4]) -> [u8; 4] {
+        match self {
+            PixelFormat::Rgba8 => [r, g, b, a],
+            PixelFormat::Bgra8 => [b, g, r, a],
+            PixelFormat::RgbaPremultiplied => {
+                let scale = |c: u8| ((c as u16 * a as u16 + 127) / 255) as u8;
+                [scale(r), scale(g), scale(b), a]
+            }
+        }
+    }
+
+    /// Convert a pixel in this format to RGBA
+    fn decode(self, [x, y, z, a]: [u8; 4]) -> [u8; 4] {
+        match self {
+            PixelFormat::Rgba8 => [x, y, z, a],
+            PixelFormat::Bgra8 => [z, y, x, a],
+            PixelFormat::RgbaPremultiplied => {
+                if a == 0 {
+                    return [0, 0, 0, 0];
+                }
+                let unscale = |c: u8| ((c as u16 * 255 + a as u16 / 2) / a as u16).min(255) as u8;
+                [unscale(x), unscale(y), unscale(z), a]
+            }
+        }
+    }
 }
 
+/// A server surface and its pixels.
+///
+/// `data` is laid out according to `format`: 32-bit surfaces hold four
+/// bytes per pixel in `pixel_format` order, 16-bit surfaces little-endian
+/// packed pixels, `A8` one alpha byte per pixel and `A1` one bit per pixel
+/// (least significant bit first).
 #[derive(Debug, Clone)]
 pub struct DisplaySurface {
     pub width: u32,
     pub height: u32,
-    pub format: u32,
+    pub format: SurfaceFormat,
     pub data: Vec<u8>,
+    /// Byte order of 32-bit pixels in `data`; other formats ignore it
+    pub pixel_format: PixelFormat,
+}
+
+impl DisplaySurface {
+    /// Create a cleared surface with a buffer sized for `format`
+    pub fn new(width: u32, height: u32, format: SurfaceFormat) -> Self {
+        Self::with_pixel_format(width, height, format, PixelFormat::default())
+    }
+
+    /// Like [`new`](Self::new), keeping 32-bit pixels in `pixel_format` order
+    pub fn with_pixel_format(
+        width: u32,
+        height: u32,
+        format: SurfaceFormat,
+        pixel_format: PixelFormat,
+    ) -> Self {
+        Self {
+            width,
+            height,
+            format,
+            data: vec![0; format.buffer_size(width, height)],
+            pixel_format,
+        }
+    }
+
+    /// Whether `data` holds 32-bit pixels in `pixel_format` order
+    fn is_32bit(&self) -> bool {
+        matches!(self.format, SurfaceFormat::Xrgb32 | SurfaceFormat::Argb32)
+    }
+
+    /// Rewrite 32-bit pixels in `pixel_format` order
+    pub fn convert_pixel_format(&mut self, pixel_format: PixelFormat) {
+        if self.pixel_format == pixel_format {
+            return;
+        }
+        if self.is_32bit() {
+            let from = self.pixel_format;
+            for pixel in self.data.chunks_exact_mut(4) {
+                let rgba = from.decode([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                pixel.copy_from_slice(&pixel_format.encode(rgba));
+            }
+        }
+        self.pixel_format = pixel_format;
+    }
+
+    /// Bytes per row
+    pub fn stride(&self) -> usize {
+        self.format.stride(self.width)
+    }
+
+    /// Read the pixel at (`x`, `y`) as RGBA
+    pub fn pixel(&self, x: usize, y: usize) -> Option<[u8; 4]> {
+        if x >= self.width as usize || y >= self.height as usize {
+            return None;
+        }
+        let row = y * self.stride();
+        match self.format {
+            SurfaceFormat::Xrgb32 | SurfaceFormat::Argb32 => {
+                let offset = row + x * 4;
+                let pixel = self.data.get(offset..offset + 4)?.try_into().ok()?;
+                Some(self.pixel_format.decode(pixel))
+            }
+            SurfaceFormat::Rgb555 | SurfaceFormat::Rgb565 => {
+                let offset = row + x * 2;
+                let bytes = self.data.get(offset..offset + 2)?;
+                let value = u16::from_le_bytes([bytes[0], bytes[1]]);
+                Some(unpack_rgb16(value, self.format))
+            }
+            SurfaceFormat::A8 => Some([0, 0, 0, *self.data.get(row + x)?]),
+            SurfaceFormat::A1 => {
+                let byte = *self.data.get(row + x / 8)?;
+                let alpha = if byte & (1 << (x % 8)) != 0 { 255 } else { 0 };
+                Some([0, 0, 0, alpha])
+            }
+        }
+    }
+
+    /// Write an RGBA pixel at (`x`, `y`), converting it to the surface format
+    fn set_pixel(&mut self, x: usize, y: usize, rgba: [u8; 4]) {
+        if x >= self.width as usize || y >= self.height as usize {
+            return;
+        }
+        let row = y * self.stride();
+        match self.format {
+            SurfaceFormat::Xrgb32 | SurfaceFormat::Argb32 => {
+                let offset = row + x * 4;
+                let pixel = self.pixel_format.encode(rgba);
+                if let Some(dest) = self.data.get_mut(offset..offset + 4) {
+                    dest.copy_from_slice(&pixel);
+                }
+            }
+            SurfaceFormat::Rgb555 | SurfaceFormat::Rgb565 => {
+                let offset = row + x * 2;
+                let value = pack_rgb16(rgba, self.format);
+                if let Some(dest) = self.data.get_mut(offset..offset + 2) {
+                    dest.copy_from_slice(&value.to_le_bytes());
+                }
+            }
+            SurfaceFormat::A8 => {
+                if let Some(dest) = self.data.get_mut(row + x) {
+                    *dest = rgba[3];
+                }
+            }
+            SurfaceFormat::A1 => {
+                if let Some(dest) = self.data.get_mut(row + x / 8) {
+                    let bit = 1 << (x % 8);
+                    if rgba[3] >= 128 {
+                        *dest |= bit;
+                    } else {
+                        *dest &= !bit;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The surface contents as tightly packed RGBA, whatever its format.
+    ///
+    /// 32-bit surfaces kept as [`PixelFormat::Rgba8`] are borrowed as-is;
+    /// anything else is converted.
+    pub fn to_rgba(&self) -> Cow<'_, [u8]> {
+        self.to_pixel_format(PixelFormat::Rgba8)
+    }
+
+    /// The surface contents as tightly packed pixels in `pixel_format`
+    /// order, whatever its format.
+    ///
+    /// 32-bit surfaces already kept in `pixel_format` are borrowed as-is;
+    /// anything else is converted.
+    pub fn to_pixel_format(&self, pixel_format: PixelFormat) -> Cow<'_, [u8]> {
+        if self.is_32bit() && self.pixel_format == pixel_format {
+            return Cow::Borrowed(&self.data);
+        }
+
+        let mut pixels = Vec::with_capacity(self.width as usize * self.height as usize * 4);
+        for y in 0..self.height as usize {
+            for x in 0..self.width as usize {
+                let rgba = self.pixel(x, y).unwrap_or_default();
+                pixels.extend_from_slice(&pixel_format.encode(rgba));
+            }
+        }
+        Cow::Owned(pixels)
+    }
+
+    /// Fill the part of `rect` inside the surface with a single RGBA color
+    fn fill_solid(&mut self, rect: &SpiceRect, rgba: [u8; 4]) {
+        for y in rect.top.max(0)..rect.bottom.min(self.height as i32) {
+            for x in rect.left.max(0)..rect.right.min(self.width as i32) {
+                self.set_pixel(x as usize, y as usize, rgba);
+            }
+        }
+    }
+
+    /// Copy `src_area` of an RGBA image to `dest`, clipped to both.
+    ///
+    /// With `opaque` set the source alpha is ignored and every copied pixel
+    /// is fully opaque.
+    fn blit_rgba(
+        &mut self,
+        image: &[u8],
+        img_width: u32,
+        img_height: u32,
+        src_area: &SpiceRect,
+        dest: &SpiceRect,
+        opaque: bool,
+    ) {
+        self.blit_pixels(image, img_width, img_height, src_area, dest, |_, _, src| {
+            let alpha = if opaque { 255 } else { src[3] };
+            Some([src[0], src[1], src[2], alpha])
+        });
+    }
+
+    /// Copy `src_area` of an RGBA image to `dest` as opaque pixels, leaving
+    /// out those of the 0xRRGGBB `key` color and, when `clip` is given,
+    /// those outside the union of its rectangles
+    fn blit_transparent(
+        &mut self,
+        image: &[u8],
+        img_width: u32,
+        img_height: u32,
+        src_area: &SpiceRect,
+        dest: &SpiceRect,
+        key: u32,
+        clip: Option<&[SpiceRect]>,
+    ) {
+        let key = [(key >> 16) as u8, (key >> 8) as u8, key as u8];
+        self.blit_pixels(image, img_width, img_height, src_area, dest, |x, y, src| {
+            let (x, y) = (x as i32, y as i32);
+            let inside_clip = clip.map_or(true, |rects| {
+                rects
+                    .iter()
+                    .any(|r| x >= r.left && x < r.right && y >= r.top && y < r.bottom)
+            });
+            (inside_clip && src[..3] != key).then(|| [src[0], src[1], src[2], 255])
+        });
+    }
+
+    /// Walk `src_area` of an RGBA image against `dest`, clipped to both, and
+    /// write whatever `pixel` returns for each destination pixel and source
+    /// RGBA value
+    fn blit_pixels(
+        &mut self,
+        image: &[u8],
+        img_width: u32,
+        img_height: u32,
+        src_area: &SpiceRect,
+        dest: &SpiceRect,
+        mut pixel: impl FnMut(usize, usize, &[u8]) -> Option<[u8; 4]>,
+    ) {
+        let image_stride = img_width as usize * 4;
+
+        // Calculate source rectangle bounds
+        let src_left = src_area.left.max(0) as usize;
+        let src_top = src_area.top.max(0) as usize;
+        let src_right = src_area.right.min(img_width as i32).max(0) as usize;
+        let src_bottom = src_area.bottom.min(img_height as i32).max(0) as usize;
+
+        // Calculate destination bounds
+        let dst_left = dest.left.max(0) as usize;
+        let dst_top = dest.top.max(0) as usize;
+        let dst_right = dest.right.min(self.width as i32).max(0) as usize;
+        let dst_bottom = dest.bottom.min(self.height as i32).max(0) as usize;
+
+        let copy_width = src_right
+            .saturating_sub(src_left)
+            .min(dst_right.saturating_sub(dst_left));
+        let copy_height = src_bottom
+            .saturating_sub(src_top)
+            .min(dst_bottom.saturating_sub(dst_top));
+
+        for y in 0..copy_height {
+            let src_row_offset = (src_top + y) * image_stride;
+            for x in 0..copy_width {
+                let src_offset = src_row_offset + (src_left + x) * 4;
+                let Some(src) = image.get(src_offset..src_offset + 4) else {
+                    continue;
+                };
+                let (dst_x, dst_y) = (dst_left + x, dst_top + y);
+                if let Some(rgba) = pixel(dst_x, dst_y, src) {
+                    self.set_pixel(dst_x, dst_y, rgba);
+                }
+            }
+        }
+    }
+
+    /// Fill `rect` with a 0xRRGGBB brush color using the given raster operation.
+    ///
+    /// The fill is limited to the surface bounds and, when `clip` is given, to
+    /// the union of the clip rectangles.
+    fn fill_rect(&mut self, rect: &SpiceRect, color: u32, rop: u16, clip: Option<&[SpiceRect]>) {
+        match clip {
+            Some(clip_rects) => {
+                for clip_rect in clip_rects {
+                    let area = SpiceRect {
+                        left: rect.left.max(clip_rect.left),
+                        top: rect.top.max(clip_rect.top),
+                        right: rect.right.min(clip_rect.right),
+                        bottom: rect.bottom.min(clip_rect.bottom),
+                    };
+                    self.fill_area(&area, color, rop);
+                }
+            }
+            None => self.fill_area(rect, color, rop),
+        }
+    }
+
+    fn fill_area(&mut self, rect: &SpiceRect, color: u32, rop: u16) {
+        for y in rect.top.max(0)..rect.bottom.min(self.height as i32) {
+            for x in rect.left.max(0)..rect.right.min(self.width as i32) {
+                self.apply_brush(x as usize, y as usize, color, rop);
+            }
+        }
+    }
+
+    /// Stroke every subpath of `polylines` with one-pixel lines of a
+    /// 0xRRGGBB pen color, limited to the union of `clip` when given
+    fn stroke_polylines(
+        &mut self,
+        polylines: &[Vec<(i32, i32)>],
+        color: u32,
+        rop: u16,
+        clip: Option<&[SpiceRect]>,
+    ) {
+        for polyline in polylines {
+            // A lone point is still drawn as a dot
+            if let [point] = polyline.as_slice() {
+                self.stroke_line(*point, *point, color, rop, clip);
+            }
+            for line in polyline.windows(2) {
+                self.stroke_line(line[0], line[1], color, rop, clip);
+            }
+        }
+    }
+
+    /// Draw a one-pixel line from `from` to `to`, both ends included, with
+    /// Bresenham's algorithm
+    fn stroke_line(
+        &mut self,
+        from: (i32, i32),
+        to: (i32, i32),
+        color: u32,
+        rop: u16,
+        clip: Option<&[SpiceRect]>,
+    ) {
+        let (mut x, mut y) = from;
+        let dx = (to.0 - x).abs();
+        let dy = -(to.1 - y).abs();
+        let step_x = if x < to.0 { 1 } else { -1 };
+        let step_y = if y < to.1 { 1 } else { -1 };
+        let mut error = dx + dy;
+
+        loop {
+            let inside_clip = clip.map_or(true, |rects| {
+                rects
+                    .iter()
+                    .any(|r| x >= r.left && x < r.right && y >= r.top && y < r.bottom)
+            });
+            if inside_clip && x >= 0 && y >= 0 {
+                self.apply_brush(x as usize, y as usize, color, rop);
+            }
+
+            if (x, y) == to {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Combine one pixel with a 0xRRGGBB brush color using a raster operation
+    fn apply_brush(&mut self, x: usize, y: usize, color: u32, rop: u16) {
+        let Some(dest) = self.pixel(x, y) else {
+            return;
+        };
+        let brush = [
+            ((color >> 16) & 0xFF) as u8,
+            ((color >> 8) & 0xFF) as u8,
+            (color & 0xFF) as u8,
+        ];
+        let mut result = [0, 0, 0, 255];
+        for (channel, &brush_value) in brush.iter().enumerate() {
+            result[channel] = apply_rop(dest[channel], brush_value, rop);
+        }
+        self.set_pixel(x, y, result);
+    }
+}
+
+/// Segments a cubic Bézier curve of a stroke is flattened into
+const BEZIER_STEPS: i32 = 16;
+
+/// Turn a SPICE path into polylines of whole-pixel points, one per subpath.
+///
+/// Points are rounded from 28.4 fixed point, Bézier segments are flattened
+/// and closed subpaths end back at their first point.
+pub(crate) fn path_polylines(path: &SpicePath) -> Vec<Vec<(i32, i32)>> {
+    let to_pixel = |v: Fixed28_4| (v + 8) >> 4;
+    let mut polylines: Vec<Vec<(i32, i32)>> = Vec::new();
+    let mut current: Vec<(i32, i32)> = Vec::new();
+    let mut fixed_current = (0, 0);
+
+    for segment in &path.segments {
+        let mut points = segment.points.as_slice();
+        if segment.flags & SPICE_PATH_BEGIN != 0 {
+            if !current.is_empty() {
+                polylines.push(std::mem::take(&mut current));
+            }
+            let Some((first, rest)) = points.split_first() else {
+                continue;
+            };
+            fixed_current = (first.x, first.y);
+            current.push((to_pixel(first.x), to_pixel(first.y)));
+            points = rest;
+        }
+
+        if segment.flags & SPICE_PATH_BEZIER != 0 {
+            for curve in points.chunks_exact(3) {
+                let (x0, y0) = (fixed_current.0 as i64, fixed_current.1 as i64);
+                let [c1, c2, end] =
+                    [curve[0], curve[1], curve[2]].map(|p| (p.x as i64, p.y as i64));
+                let n = BEZIER_STEPS as i64;
+                for step in 1..=n {
+                    let (t, u) = (step, n - step);
+                    // Cubic Bézier in integer arithmetic, scaled by n^3
+                    let point = |p0: i64, p1: i64, p2: i64, p3: i64| {
+                        (u * u * u * p0 + 3 * u * u * t * p1 + 3 * u * t * t * p2 + t * t * t * p3)
+                            / (n * n * n)
+                    };
+                    let x = point(x0, c1.0, c2.0, end.0) as Fixed28_4;
+                    let y = point(y0, c1.1, c2.1, end.1) as Fixed28_4;
+                    current.push((to_pixel(x), to_pixel(y)));
+                }
+                fixed_current = (curve[2].x, curve[2].y);
+            }
+        } else {
+            for point in points {
+                fixed_current = (point.x, point.y);
+                current.push((to_pixel(point.x), to_pixel(point.y)));
+            }
+        }
+
+        if segment.flags & SPICE_PATH_CLOSE != 0 {
+            if let Some(&first) = current.first() {
+                current.push(first);
+            }
+        }
+        if segment.flags & (SPICE_PATH_CLOSE | SPICE_PATH_END) != 0 && !current.is_empty() {
+            polylines.push(std::mem::take(&mut current));
+        }
+    }
+
+    if !current.is_empty() {
+        polylines.push(current);
+    }
+    polylines
+}
+
+/// Expand a packed 16-bit pixel to RGBA
+pub(crate) fn unpack_rgb16(value: u16, format: SurfaceFormat) -> [u8; 4] {
+    let (r, g, b) = if format == SurfaceFormat::Rgb565 {
+        let r = ((value >> 11) & 0x1F) as u8;
+        let g = ((value >> 5) & 0x3F) as u8;
+        let b = (value & 0x1F) as u8;
+        (
+            (r << 3) | (r >> 2),
+            (g << 2) | (g >> 4),
+            (b << 3) | (b >> 2),
+        )
+    } else {
+        let r = ((value >> 10) & 0x1F) as u8;
+        let g = ((value >> 5) & 0x1F) as u8;
+        let b = (value & 0x1F) as u8;
+        (
+            (r << 3) | (r >> 2),
+            (g << 3) | (g >> 2),
+            (b << 3) | (b >> 2),
+        )
+    };
+    [r, g, b, 255]
+}
+
+/// Pack an RGBA pixel into a 16-bit surface pixel
+fn pack_rgb16(rgba: [u8; 4], format: SurfaceFormat) -> u16 {
+    let [r, g, b, _] = rgba.map(u16::from);
+    if format == SurfaceFormat::Rgb565 {
+        ((r >> 3) << 11) | ((g >> 2) << 5) | (b >> 3)
+    } else {
+        ((r >> 3) << 10) | ((g >> 3) << 5) | (b >> 3)
+    }
+}
+
+/// Combine one destination channel with one brush channel according to a
+/// SPICE raster operation descriptor
+fn apply_rop(dest: u8, brush: u8, rop: u16) -> u8 {
+    let brush = if rop & SPICE_ROPD_INVERS_BRUSH != 0 {
+        !brush
+    } else {
+        brush
+    };
+    let dest = if rop & SPICE_ROPD_INVERS_DEST != 0 {
+        !dest
+    } else {
+        dest
+    };
+
+    let result = if rop & SPICE_ROPD_OP_BLACKNESS != 0 {
+        0x00
+    } else if rop & SPICE_ROPD_OP_WHITENESS != 0 {
+        0xFF
+    } else if rop & SPICE_ROPD_OP_INVERS != 0 {
+        !dest
+    } else if rop & SPICE_ROPD_OP_OR != 0 {
+        dest | brush
+    } else if rop & SPICE_ROPD_OP_AND != 0 {
+        dest & brush
+    } else if rop & SPICE_ROPD_OP_XOR != 0 {
+        dest ^ brush
+    } else {
+        // SPICE_ROPD_OP_PUT
+        brush
+    };
+
+    if rop & SPICE_ROPD_INVERS_RES != 0 {
+        !result
+    } else {
+        result
+    }
+}
+
+/// Most rectangles a surface's damage is reported as; past this the region
+/// collapses to its bounding box
+const MAX_DAMAGE_RECTS: usize = 8;
+
+/// Longest pending damage is held back while the server keeps sending
+const DAMAGE_FLUSH_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Paces update notifications so the data behind each one arrives no
+/// faster than a bandwidth target. Damage received in between coalesces
+/// into the next notification.
+#[derive(Debug, Default)]
+struct UpdateThrottle {
+    /// Bytes received since the last notification
+    pending_bytes: u64,
+    /// When the last notification went out and the bytes behind it
+    last_update: Option<(Instant, u64)>,
+}
+
+impl UpdateThrottle {
+    fn record(&mut self, bytes: usize) {
+        self.pending_bytes += bytes as u64;
+    }
+
+    /// How long after `now` the next notification has to wait to stay
+    /// within `kbps`; 0 means no limit
+    fn delay(&self, kbps: u32, now: Instant) -> Duration {
+        let Some((at, bytes)) = self.last_update else {
+            return Duration::ZERO;
+        };
+        if kbps == 0 {
+            return Duration::ZERO;
+        }
+        let cost = Duration::from_micros(bytes * 8_000 / kbps as u64);
+        cost.saturating_sub(now.saturating_duration_since(at))
+    }
+
+    fn updated(&mut self, now: Instant) {
+        self.last_update = Some((now, std::mem::take(&mut self.pending_bytes)));
+    }
+}
+
+/// Area of a surface drawn to since the last notification.
+///
+/// Overlapping and touching rectangles are merged as they are added, so a
+/// burst of small draws is reported as a few larger rectangles rather than
+/// one per draw.
+#[derive(Debug, Default)]
+struct DamageRegion {
+    rects: Vec<SpiceRect>,
+}
+
+impl DamageRegion {
+    fn add(&mut self, rect: SpiceRect) {
+        if rect.right <= rect.left || rect.bottom <= rect.top {
+            return;
+        }
+
+        // A merged rectangle can reach ones the original didn't touch, so
+        // keep absorbing until nothing else touches it
+        let mut merged = rect;
+        loop {
+            let before = self.rects.len();
+            self.rects.retain(|other| {
+                if rects_touch(other, &merged) {
+                    merged = rect_union(other, &merged);
+                    false
+                } else {
+                    true
+                }
+            });
+            if self.rects.len() == before {
+                break;
+            }
+        }
+        self.rects.push(merged);
+
+        if self.rects.len() > MAX_DAMAGE_RECTS {
+            let bounds = self.rects.drain(..).reduce(|a, b| rect_union(&a, &b));
+            self.rects.extend(bounds);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    fn rects(&self) -> &[SpiceRect] {
+        &self.rects
+    }
+}
+
+/// Whether two rectangles overlap or share an edge
+fn rects_touch(a: &SpiceRect, b: &SpiceRect) -> bool {
+    a.left <= b.right && b.left <= a.right && a.top <= b.bottom && b.top <= a.bottom
+}
+
+fn rect_union(a: &SpiceRect, b: &SpiceRect) -> SpiceRect {
+    SpiceRect {
+        left: a.left.min(b.left),
+        top: a.top.min(b.top),
+        right: a.right.max(b.right),
+        bottom: a.bottom.max(b.bottom),
+    }
+}
+
+/// Display state changes that consumers may need to react to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisplayEvent {
+    /// The server switched to GL scanout (virtio-gpu with GL). Frames are
+    /// shared as dmabufs, which this client can't import, so the regular
+    /// surfaces stop updating.
+    GlScanout(SpiceMsgDisplayGlScanoutUnix),
+    /// A region of the GL scanout was updated
+    GlDraw(SpiceMsgDisplayGlDraw),
+    /// Changes to a surface were passed to the update and damage callbacks.
+    /// `at` is when the latest of them was drawn, so renderers can repaint
+    /// in step with the content and skip frames while nothing changes.
+    SurfaceUpdated { surface_id: u32, at: Instant },
 }
 
 #[derive(Debug, Clone)]
@@ -59,15 +780,152 @@ pub struct StreamInfo {
     pub width: u32,
     pub height: u32,
     pub dest_rect: SpiceRect,
+    /// Frames are only drawn inside these rectangles; `None` means unclipped
+    pub clip: Option<Vec<SpiceRect>>,
+}
+
+/// Frames of a stream counted towards the next stream report
+#[derive(Debug, Clone)]
+struct StreamReportWindow {
+    request: SpiceStreamActivateReport,
+    /// When the first frame of the window arrived
+    started: Option<Instant>,
+    report: SpiceMsgcDisplayStreamReport,
+}
+
+impl StreamReportWindow {
+    fn new(request: SpiceStreamActivateReport) -> Self {
+        Self {
+            request,
+            started: None,
+            report: SpiceMsgcDisplayStreamReport {
+                stream_id: request.stream_id,
+                unique_id: request.unique_id,
+                start_frame_mm_time: 0,
+                end_frame_mm_time: 0,
+                num_frames: 0,
+                num_drops: 0,
+                last_frame_delay: 0,
+                audio_delay: 0,
+            },
+        }
+    }
+
+    /// Count a frame stamped with `mm_time` that arrived `delay` ms ahead of
+    /// it, and whether it was dropped. Returns the finished report once the
+    /// window is full or has timed out, and starts the next window.
+    fn record_frame(
+        &mut self,
+        mm_time: u32,
+        delay: i32,
+        dropped: bool,
+        now: Instant,
+    ) -> Option<SpiceMsgcDisplayStreamReport> {
+        let started = *self.started.get_or_insert(now);
+        if self.report.num_frames == 0 {
+            self.report.start_frame_mm_time = mm_time;
+        }
+        self.report.end_frame_mm_time = mm_time;
+        self.report.num_frames += 1;
+        if dropped {
+            self.report.num_drops += 1;
+        }
+        self.report.last_frame_delay = delay;
+
+        let timeout = Duration::from_millis(self.request.timeout_ms as u64);
+        if self.report.num_frames < self.request.max_window_size
+            && now.saturating_duration_since(started) < timeout
+        {
+            return None;
+        }
+
+        let report = self.report;
+        *self = Self::new(self.request);
+        Some(report)
+    }
 }
 
 pub struct DisplayChannel {
     pub(crate) connection: ChannelConnection,
     surfaces: HashMap<u32, DisplaySurface>,
+    /// `SPICE_SURFACE_FLAGS_*` each surface was created with
+    surface_flags: HashMap<u32, u32>,
+    /// Surface created with `SPICE_SURFACE_FLAGS_PRIMARY`
+    primary_surface_id: Option<u32>,
     monitors: Vec<SpiceHead>,
     active_streams: HashMap<u32, StreamInfo>,
+    /// Streams the server asked to report on
+    stream_reports: HashMap<u32, StreamReportWindow>,
+    /// Server multimedia time, to tell how early stream frames arrive
+    media_clock: Option<MediaClock>,
     update_callback: Option<Box<dyn Fn(&DisplaySurface) + Send + Sync>>,
+    damage_callback: Option<Box<dyn Fn(u32, &DisplaySurface, &SpiceRect) + Send + Sync>>,
+    event_callback: Option<Box<dyn Fn(&DisplayEvent) + Send + Sync>>,
     image_cache: ImageCache,
+    palette_cache: HashMap<u64, Vec<u32>>,
+    gl_scanout: Option<SpiceMsgDisplayGlScanoutUnix>,
+    has_drawn: bool,
+    /// Byte order new 32-bit surfaces are kept in
+    pixel_format: PixelFormat,
+    /// Damage of each surface not yet passed to the callbacks
+    damage: HashMap<u32, DamageRegion>,
+    /// When the oldest pending damage was added
+    damage_since: Option<Instant>,
+    /// When each surface last changed
+    updated_at: HashMap<u32, Instant>,
+    /// Bandwidth target in kbit/s, 0 for none; shared with the client so it
+    /// can be changed while the event loop runs
+    bandwidth_target: Arc<AtomicU32>,
+    throttle: UpdateThrottle,
+    /// Compression asked for with `set_preferred_compression`, sent again
+    /// when the channel relinks
+    requested_compression: Option<ImageCompression>,
+    /// Signalled by `RefreshHandle`s to repaint from the event loop
+    refresh: Arc<Notify>,
+    inspect_tx: mpsc::UnboundedSender<Inspection>,
+    /// Reads queued by `InspectHandle`s, answered by the event loop
+    inspect_rx: mpsc::UnboundedReceiver<Inspection>,
+}
+
+/// Asks a display channel for a full repaint, also while its event loop
+/// owns the channel.
+#[derive(Debug, Clone)]
+pub struct RefreshHandle {
+    refresh: Arc<Notify>,
+}
+
+impl RefreshHandle {
+    /// The event loop relinks the channel before handling the next message
+    pub fn request(&self) {
+        self.refresh.notify_one();
+    }
+}
+
+/// A read of a display channel's state, run by its event loop
+type Inspection = Box<dyn FnOnce(&DisplayChannel) + Send>;
+
+/// Reads a display channel's surfaces and state, also while its event loop
+/// owns the channel.
+#[derive(Clone)]
+pub(crate) struct InspectHandle {
+    tx: mpsc::UnboundedSender<Inspection>,
+}
+
+impl InspectHandle {
+    /// Run `read` on the channel between two messages. `None` once the
+    /// event loop has ended.
+    pub(crate) async fn read<T, F>(&self, read: F) -> Option<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&DisplayChannel) -> T + Send + 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel();
+        let inspection: Inspection = Box::new(move |channel| {
+            let _ = result_tx.send(read(channel));
+        });
+        self.tx.send(inspection).ok()?;
+        result_rx.await.ok()
+    }
 }
 
 impl DisplayChannel {
@@ -81,14 +939,66 @@ impl DisplayChannel {
         channel_id: u8,
         connection_id: Option<u32>,
     ) -> Result<Self> {
-        let mut connection =
+        let connection =
             ChannelConnection::new(host, port, ChannelType::Display, channel_id).await?;
+        Self::from_connection(connection, connection_id).await
+    }
+
+    /// Connect a display channel over a Unix domain socket
+    #[cfg(unix)]
+    pub async fn new_unix_with_connection_id(
+        path: impl AsRef<std::path::Path>,
+        channel_id: u8,
+        connection_id: Option<u32>,
+    ) -> Result<Self> {
+        let connection =
+            ChannelConnection::new_unix(path, ChannelType::Display, channel_id).await?;
+        Self::from_connection(connection, connection_id).await
+    }
+
+    /// Link `connection` and send the display init message
+    pub(crate) async fn from_connection(
+        mut connection: ChannelConnection,
+        connection_id: Option<u32>,
+    ) -> Result<Self> {
         if let Some(conn_id) = connection_id {
             connection.set_connection_id(conn_id);
         }
         connection.handshake().await?;
+        Self::send_init(&mut connection).await?;
+
+        let (inspect_tx, inspect_rx) = mpsc::unbounded_channel();
+        Ok(Self {
+            connection,
+            surfaces: HashMap::new(),
+            surface_flags: HashMap::new(),
+            primary_surface_id: None,
+            monitors: Vec::new(),
+            active_streams: HashMap::new(),
+            stream_reports: HashMap::new(),
+            media_clock: None,
+            update_callback: None,
+            damage_callback: None,
+            event_callback: None,
+            image_cache: ImageCache::new(),
+            palette_cache: HashMap::new(),
+            gl_scanout: None,
+            has_drawn: false,
+            pixel_format: PixelFormat::default(),
+            damage: HashMap::new(),
+            damage_since: None,
+            updated_at: HashMap::new(),
+            bandwidth_target: Arc::new(AtomicU32::new(0)),
+            throttle: UpdateThrottle::default(),
+            requested_compression: None,
+            refresh: Arc::new(Notify::new()),
+            inspect_tx,
+            inspect_rx,
+        })
+    }
 
-        // Send display init message after handshake
+    /// Send the display init message that follows the handshake
+    async fn send_init(connection: &mut ChannelConnection) -> Result<()> {
         info!("Sending SPICE_MSGC_DISPLAY_INIT");
 
         // Create the display init message
@@ -108,16 +1018,7 @@ impl DisplayChannel {
 
         connection
             .send_message(SPICE_MSGC_DISPLAY_INIT, &init_data)
-            .await?;
-
-        Ok(Self {
-            connection,
-            surfaces: HashMap::new(),
-            monitors: Vec::new(),
-            active_streams: HashMap::new(),
-            update_callback: None,
-            image_cache: ImageCache::new(),
-        })
+            .await
     }
 
     #[cfg(target_arch = "wasm32")]
@@ -139,36 +1040,35 @@ impl DisplayChannel {
         )
         .await?;
         connection.handshake().await?;
+        Self::send_init(&mut connection).await?;
 
-        // Send display init message after handshake
-        info!("Sending SPICE_MSGC_DISPLAY_INIT");
-
-        // Create the display init message
-        let display_init = SpiceMsgcDisplayInit {
-            cache_id: 0,
-            cache_size: 0, // No cache for now
-            glz_dict_id: 0,
-        };
-
-        // Serialize the message
-        use binrw::BinWrite;
-        let mut cursor = std::io::Cursor::new(Vec::new());
-        display_init
-            .write(&mut cursor)
-            .map_err(|e| SpiceError::Protocol(format!("Failed to write display init: {e}")))?;
-        let init_data = cursor.into_inner();
-
-        connection
-            .send_message(SPICE_MSGC_DISPLAY_INIT, &init_data)
-            .await?;
-
+        let (inspect_tx, inspect_rx) = mpsc::unbounded_channel();
         Ok(Self {
             connection,
             surfaces: HashMap::new(),
+            surface_flags: HashMap::new(),
+            primary_surface_id: None,
             monitors: Vec::new(),
             active_streams: HashMap::new(),
+            stream_reports: HashMap::new(),
+            media_clock: None,
             update_callback: None,
+            damage_callback: None,
+            event_callback: None,
             image_cache: ImageCache::new(),
+            palette_cache: HashMap::new(),
+            gl_scanout: None,
+            has_drawn: false,
+            pixel_format: PixelFormat::default(),
+            damage: HashMap::new(),
+            damage_since: None,
+            updated_at: HashMap::new(),
+            bandwidth_target: Arc::new(AtomicU32::new(0)),
+            throttle: UpdateThrottle::default(),
+            requested_compression: None,
+            refresh: Arc::new(Notify::new()),
+            inspect_tx,
+            inspect_rx,
         })
     }
 
@@ -179,12 +1079,14 @@ impl DisplayChannel {
         auth_token: Option<String>,
         password: Option<String>,
         connection_id: Option<u32>,
+        options: ConnectOptions,
     ) -> Result<Self> {
-        let mut connection = ChannelConnection::new_websocket_with_auth(
+        let mut connection = ChannelConnection::new_websocket_with_options(
             websocket_url,
             ChannelType::Display,
             channel_id,
             auth_token,
+            options,
         )
         .await?;
         if let Some(pwd) = password {
@@ -194,36 +1096,35 @@ impl DisplayChannel {
             connection.set_connection_id(conn_id);
         }
         connection.handshake().await?;
+        Self::send_init(&mut connection).await?;
 
-        // Send display init message after handshake
-        info!("Sending SPICE_MSGC_DISPLAY_INIT");
-
-        // Create the display init message
-        let display_init = SpiceMsgcDisplayInit {
-            cache_id: 0,
-            cache_size: 0, // No cache for now
-            glz_dict_id: 0,
-        };
-
-        // Serialize the message
-        use binrw::BinWrite;
-        let mut cursor = std::io::Cursor::new(Vec::new());
-        display_init
-            .write(&mut cursor)
-            .map_err(|e| SpiceError::Protocol(format!("Failed to write display init: {e}")))?;
-        let init_data = cursor.into_inner();
-
-        connection
-            .send_message(SPICE_MSGC_DISPLAY_INIT, &init_data)
-            .await?;
-
+        let (inspect_tx, inspect_rx) = mpsc::unbounded_channel();
         Ok(Self {
             connection,
             surfaces: HashMap::new(),
+            surface_flags: HashMap::new(),
+            primary_surface_id: None,
             monitors: Vec::new(),
             active_streams: HashMap::new(),
+            stream_reports: HashMap::new(),
+            media_clock: None,
             update_callback: None,
+            damage_callback: None,
+            event_callback: None,
             image_cache: ImageCache::new(),
+            palette_cache: HashMap::new(),
+            gl_scanout: None,
+            has_drawn: false,
+            pixel_format: PixelFormat::default(),
+            damage: HashMap::new(),
+            damage_since: None,
+            updated_at: HashMap::new(),
+            bandwidth_target: Arc::new(AtomicU32::new(0)),
+            throttle: UpdateThrottle::default(),
+            requested_compression: None,
+            refresh: Arc::new(Notify::new()),
+            inspect_tx,
+            inspect_rx,
         })
     }
 
@@ -241,10 +1142,21 @@ impl DisplayChannel {
         self.surfaces.get(&surface_id)
     }
 
+    /// Id of the surface that shows the guest's screen.
+    ///
+    /// This is the surface the server flagged as primary; servers that don't
+    /// flag it use surface 0.
+    pub fn primary_surface_id(&self) -> Option<u32> {
+        self.primary_surface_id
+            .or_else(|| self.surfaces.contains_key(&0).then_some(0))
+    }
+
     pub fn get_primary_surface(&self) -> Option<&DisplaySurface> {
-        let surface = self.surfaces.get(&0);
+        let surface = self
+            .primary_surface_id()
+            .and_then(|surface_id| self.surfaces.get(&surface_id));
         if surface.is_none() {
-            eprintln!(
+            debug!(
                 "DisplayChannel: No primary surface available. Total surfaces: {}",
                 self.surfaces.len()
             );
@@ -252,10 +1164,34 @@ impl DisplayChannel {
         surface
     }
 
+    /// `SPICE_SURFACE_FLAGS_*` the surface was created with
+    pub fn surface_flags(&self, surface_id: u32) -> Option<u32> {
+        self.surface_flags.get(&surface_id).copied()
+    }
+
+    /// Whether the server streams the surface's content as video
+    pub fn is_streaming_surface(&self, surface_id: u32) -> bool {
+        self.surface_flags(surface_id)
+            .is_some_and(|flags| flags & SPICE_SURFACE_FLAGS_STREAMING_MODE != 0)
+    }
+
     pub fn get_monitors(&self) -> &[SpiceHead] {
         &self.monitors
     }
 
+    /// Surface shown on the monitor at `index` of the last monitors config
+    pub fn surface_for_monitor(&self, index: usize) -> Option<u32> {
+        self.monitors.get(index).map(|head| head.surface_id)
+    }
+
+    /// Index of the first monitor of the last monitors config that shows
+    /// `surface_id`
+    pub fn monitor_for_surface(&self, surface_id: u32) -> Option<usize> {
+        self.monitors
+            .iter()
+            .position(|head| head.surface_id == surface_id)
+    }
+
     pub fn get_surfaces(&self) -> &HashMap<u32, DisplaySurface> {
         &self.surfaces
     }
@@ -267,21 +1203,373 @@ impl DisplayChannel {
         self.update_callback = Some(Box::new(callback));
     }
 
+    /// Like [`set_update_callback`](Self::set_update_callback), but also
+    /// passes the surface id and the area that changed, clipped to the
+    /// surface
+    pub fn set_damage_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(u32, &DisplaySurface, &SpiceRect) + Send + Sync + 'static,
+    {
+        self.damage_callback = Some(Box::new(callback));
+    }
+
+    /// Keep 32-bit surfaces in `pixel_format` byte order, so renderers that
+    /// want e.g. BGRA can use the surface data as-is. Surfaces that already
+    /// exist are converted.
+    pub fn set_pixel_format(&mut self, pixel_format: PixelFormat) {
+        self.pixel_format = pixel_format;
+        for surface in self.surfaces.values_mut() {
+            surface.convert_pixel_format(pixel_format);
+        }
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    /// Clock of the main channel, used to measure stream frame delays in
+    /// stream reports. Without one the delays are reported as 0.
+    pub fn set_media_clock(&mut self, clock: MediaClock) {
+        self.media_clock = Some(clock);
+    }
+
+    /// Priority gate shared with the client's other channels; this channel
+    /// holds off reading while they handle a message
+    pub fn set_qos_gate(&mut self, gate: Option<QosGate>) {
+        self.connection.set_qos_gate(gate);
+    }
+
+    /// Draw a decoded frame of a stream onto the primary surface.
+    ///
+    /// `frame` is RGBA at the stream's size. It is drawn unscaled at the
+    /// stream's destination, limited to the stream's clip region.
+    pub fn apply_stream_frame(&mut self, stream_id: u32, frame: &[u8]) -> Result<()> {
+        let stream = self
+            .active_streams
+            .get(&stream_id)
+            .ok_or_else(|| SpiceError::Channel(format!("Unknown stream {stream_id}")))?;
+        let surface_id = self
+            .primary_surface_id
+            .ok_or_else(|| SpiceError::Channel("No primary surface".to_string()))?;
+        let Some(surface) = self.surfaces.get_mut(&surface_id) else {
+            return Err(SpiceError::Channel("No primary surface".to_string()));
+        };
+
+        let dest = &stream.dest_rect;
+        let areas: Vec<SpiceRect> = match &stream.clip {
+            Some(clip_rects) => clip_rects
+                .iter()
+                .map(|clip_rect| SpiceRect {
+                    left: dest.left.max(clip_rect.left),
+                    top: dest.top.max(clip_rect.top),
+                    right: dest.right.min(clip_rect.right),
+                    bottom: dest.bottom.min(clip_rect.bottom),
+                })
+                .filter(|area| area.right > area.left && area.bottom > area.top)
+                .collect(),
+            None => vec![dest.clone()],
+        };
+
+        for area in &areas {
+            let src_area = SpiceRect {
+                left: area.left - dest.left,
+                top: area.top - dest.top,
+                right: area.right - dest.left,
+                bottom: area.bottom - dest.top,
+            };
+            surface.blit_rgba(frame, stream.width, stream.height, &src_area, area, true);
+        }
+        for area in &areas {
+            self.add_damage(surface_id, Some(area));
+        }
+        self.flush_damage();
+        Ok(())
+    }
+
+    pub fn set_event_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(&DisplayEvent) + Send + Sync + 'static,
+    {
+        self.event_callback = Some(Box::new(callback));
+    }
+
+    /// The active GL scanout, if the server is sending GL frames
+    pub fn gl_scanout(&self) -> Option<&SpiceMsgDisplayGlScanoutUnix> {
+        self.gl_scanout.as_ref()
+    }
+
+    /// Whether the server has drawn anything on this channel yet. Surfaces
+    /// start out blank, so until then they don't show the guest's screen.
+    pub fn has_drawn(&self) -> bool {
+        self.has_drawn
+    }
+
+    /// Whether the server is in GL mode, in which case the surfaces of this
+    /// channel won't be updated
+    pub fn is_gl_active(&self) -> bool {
+        self.gl_scanout.is_some()
+    }
+
+    /// The compression to ask for when the application hasn't chosen one,
+    /// derived from the capabilities negotiated on link.
+    ///
+    /// LZ4 if this client advertised it, otherwise uncompressed bitmaps: the
+    /// server's default picks GLZ and QUIC, which can't be decoded here.
+    /// `None` if the server doesn't take a preference.
+    pub fn preferred_compression(&self) -> Option<ImageCompression> {
+        if !self
+            .connection
+            .server_has_channel_cap(SPICE_DISPLAY_CAP_PREF_COMPRESSION_SETTING)
+        {
+            return None;
+        }
+        if self
+            .connection
+            .advertises_channel_cap(SPICE_DISPLAY_CAP_LZ4_COMPRESSION)
+        {
+            Some(ImageCompression::Lz4)
+        } else {
+            Some(ImageCompression::Off)
+        }
+    }
+
+    /// Ask the server to compress images with the given method.
+    ///
+    /// Fails if the server didn't advertise
+    /// `SPICE_DISPLAY_CAP_PREF_COMPRESSION_SETTING`.
+    pub async fn set_preferred_compression(&mut self, compression: ImageCompression) -> Result<()> {
+        if !self
+            .connection
+            .server_has_channel_cap(SPICE_DISPLAY_CAP_PREF_COMPRESSION_SETTING)
+        {
+            return Err(SpiceError::Channel(
+                "Server does not support setting the preferred compression".to_string(),
+            ));
+        }
+
+        info!(
+            "Requesting {:?} image compression on display channel {}",
+            compression, self.connection.channel_id
+        );
+        self.requested_compression = Some(compression);
+        self.connection
+            .send_message(
+                SPICE_MSGC_DISPLAY_PREFERRED_COMPRESSION,
+                &[compression as u8],
+            )
+            .await
+    }
+
+    /// Ask the server to repaint everything, e.g. when the surfaces may have
+    /// missed updates.
+    ///
+    /// SPICE has no message for this; the server sends the mode and draws
+    /// each surface in full whenever a display channel links, so the channel
+    /// is relinked. The current surfaces are kept until the server replaces
+    /// them, so frontends don't flash a blank screen.
+    pub async fn request_refresh(&mut self) -> Result<()> {
+        info!(
+            "Relinking display channel {} for a full repaint",
+            self.connection.channel_id
+        );
+        self.connection.relink().await?;
+        Self::send_init(&mut self.connection).await?;
+
+        // The server starts over with fresh caches and streams
+        self.image_cache = ImageCache::new();
+        self.palette_cache.clear();
+        self.active_streams.clear();
+        self.stream_reports.clear();
+        self.gl_scanout = None;
+
+        if let Some(compression) = self.requested_compression {
+            self.set_preferred_compression(compression).await?;
+        }
+        Ok(())
+    }
+
+    /// Handle for requesting a repaint while the event loop owns the channel
+    pub fn refresh_handle(&self) -> RefreshHandle {
+        RefreshHandle {
+            refresh: self.refresh.clone(),
+        }
+    }
+
+    /// Handle for reading the channel while the event loop owns it
+    pub(crate) fn inspect_handle(&self) -> InspectHandle {
+        InspectHandle {
+            tx: self.inspect_tx.clone(),
+        }
+    }
+
     /// Process a single message from the server
     /// This is primarily for testing purposes
     pub async fn process_next_message(&mut self) -> Result<()> {
         let (header, data) = self.connection.read_message().await?;
-        self.handle_message(&header, &data).await
+        self.throttle.record(data.len());
+        let result = self.handle_message(&header, &data).await;
+        if self.update_delay().is_zero() {
+            self.flush_damage();
+        }
+        self.connection.check_message_result(&header, result)
+    }
+
+    fn notify_event(&self, event: DisplayEvent) {
+        if let Some(ref callback) = self.event_callback {
+            callback(&event);
+        }
+    }
+
+    /// Record a change to `area` of a surface, or to all of it if `None`,
+    /// to be reported by the next [`flush_damage`](Self::flush_damage)
+    fn add_damage(&mut self, surface_id: u32, area: Option<&SpiceRect>) {
+        let Some(surface) = self.surfaces.get(&surface_id) else {
+            return;
+        };
+        let (width, height) = (surface.width as i32, surface.height as i32);
+        let rect = match area {
+            Some(area) => SpiceRect {
+                left: area.left.clamp(0, width),
+                top: area.top.clamp(0, height),
+                right: area.right.clamp(0, width),
+                bottom: area.bottom.clamp(0, height),
+            },
+            None => SpiceRect {
+                left: 0,
+                top: 0,
+                right: width,
+                bottom: height,
+            },
+        };
+        if rect.right <= rect.left || rect.bottom <= rect.top {
+            return;
+        }
+        let now = Instant::now();
+        self.damage.entry(surface_id).or_default().add(rect);
+        self.damage_since.get_or_insert(now);
+        self.updated_at.insert(surface_id, now);
+    }
+
+    /// Keep the display's updates within `kbps` kbit/s, or lift the limit
+    /// with `None`.
+    ///
+    /// After each update the next one is held back until the data behind it
+    /// would have taken that long to arrive at the target rate; the changes
+    /// in between are coalesced, and stream frames received meanwhile are
+    /// reported to the server as dropped so it lowers the stream's bitrate.
+    pub fn set_bandwidth_target(&self, kbps: Option<u32>) {
+        self.bandwidth_target
+            .store(kbps.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Returns the limit set with
+    /// [`set_bandwidth_target`](Self::set_bandwidth_target)
+    pub fn bandwidth_target(&self) -> Option<u32> {
+        Some(self.bandwidth_target.load(Ordering::Relaxed)).filter(|&kbps| kbps != 0)
+    }
+
+    /// Follow a bandwidth target the client can change while the event loop
+    /// owns the channel
+    pub(crate) fn share_bandwidth_target(&mut self, target: Arc<AtomicU32>) {
+        self.bandwidth_target = target;
     }
 
-    fn notify_update(&self, surface_id: u32) {
-        if let Some(ref callback) = self.update_callback {
-            if let Some(surface) = self.surfaces.get(&surface_id) {
-                callback(surface);
+    /// How long updates are held back to stay within the bandwidth target
+    fn update_delay(&self) -> Duration {
+        self.throttle.delay(
+            self.bandwidth_target.load(Ordering::Relaxed),
+            Instant::now(),
+        )
+    }
+
+    /// When a surface last changed, `None` if it hasn't been drawn yet
+    pub fn last_update_time(&self, surface_id: u32) -> Option<Instant> {
+        self.updated_at.get(&surface_id).copied()
+    }
+
+    /// Whether damage is waiting for [`flush_damage`](Self::flush_damage)
+    pub fn has_pending_damage(&self) -> bool {
+        self.damage.values().any(|region| !region.is_empty())
+    }
+
+    /// Report the damage accumulated since the last flush to the update and
+    /// damage callbacks, and start over. The event loop does this whenever
+    /// it has caught up with the server, and at least every 16 ms while the
+    /// server keeps drawing.
+    pub fn flush_damage(&mut self) {
+        if self.damage_since.take().is_some() {
+            self.throttle.updated(Instant::now());
+        }
+        for (surface_id, region) in std::mem::take(&mut self.damage) {
+            let Some(surface) = self.surfaces.get(&surface_id) else {
+                continue;
+            };
+            // Off-screen surfaces only become visible once copied to the primary
+            if self.primary_surface_id() == Some(surface_id) {
+                if let Some(ref callback) = self.update_callback {
+                    callback(surface);
+                }
+            }
+            if let Some(ref callback) = self.damage_callback {
+                for rect in region.rects() {
+                    callback(surface_id, surface, rect);
+                }
+            }
+            if let Some(&at) = self.updated_at.get(&surface_id) {
+                self.notify_event(DisplayEvent::SurfaceUpdated { surface_id, at });
             }
         }
     }
 
+    /// Flush the damage unless more messages are about to be handled and it
+    /// hasn't been held back for long, or the bandwidth target holds it
+    fn flush_damage_if_idle(&mut self) {
+        let Some(since) = self.damage_since else {
+            return;
+        };
+        if !self.update_delay().is_zero() {
+            return;
+        }
+        if !self.connection.has_pending_data() || since.elapsed() >= DAMAGE_FLUSH_INTERVAL {
+            self.flush_damage();
+        }
+    }
+
+    /// Read the clip rectangles of a draw command.
+    ///
+    /// Returns `None` when the command is not clipped. A clip list that cannot
+    /// be read yields an empty list so nothing outside the real clip is drawn.
+    fn read_clip_rects(&self, clip: &SpiceClip, data: &[u8]) -> Option<Vec<SpiceRect>> {
+        if clip.clip_type != ClipType::Rects as u8 {
+            return None;
+        }
+
+        let rects = self.resolve_address(clip.data, data).and_then(|clip_data| {
+            let mut cursor = std::io::Cursor::new(clip_data);
+            SpiceClipRects::read(&mut cursor).ok()
+        });
+
+        match rects {
+            Some(clip_rects) => Some(clip_rects.rects),
+            None => {
+                warn!("Failed to read clip rects at address 0x{:x}", clip.data);
+                Some(Vec::new())
+            }
+        }
+    }
+
+    /// Read the path a stroke draws
+    fn read_path(&self, address: SpiceAddress, data: &[u8]) -> Option<SpicePath> {
+        let path = self.resolve_address(address, data).and_then(|path_data| {
+            let mut cursor = std::io::Cursor::new(path_data);
+            SpicePath::read(&mut cursor).ok()
+        });
+        if path.is_none() {
+            warn!("Failed to read stroke path at address 0x{:x}", address);
+        }
+        path
+    }
+
     /// Resolve a SpiceAddress to get data from the message buffer
     /// SpiceAddress is an offset from the beginning of the message data
     fn resolve_address<'a>(&self, address: SpiceAddress, data: &'a [u8]) -> Option<&'a [u8]> {
@@ -378,7 +1666,18 @@ impl DisplayChannel {
                 }
 
                 let bitmap_data = &data[bitmap_data_offset..];
-                self.decode_bitmap(&bitmap, bitmap_data, descriptor.width, descriptor.height)
+                let palette = if bitmap.format == SPICE_BITMAP_FMT_8BIT {
+                    self.resolve_palette(&bitmap, data)
+                } else {
+                    None
+                };
+                Self::decode_bitmap(
+                    &bitmap,
+                    bitmap_data,
+                    descriptor.width,
+                    descriptor.height,
+                    palette.as_deref(),
+                )
             }
             SPICE_IMAGE_TYPE_LZ4 => {
                 // Decompress LZ4 data
@@ -402,12 +1701,24 @@ impl DisplayChannel {
             }
             SPICE_IMAGE_TYPE_FROM_CACHE | SPICE_IMAGE_TYPE_FROM_CACHE_LOSSLESS => {
                 // This is a cached image reference
-                debug!("FROM_CACHE image type, id: {}", descriptor.id);
+                debug!(
+                    "FROM_CACHE image type {}, id: {}",
+                    descriptor.type_, descriptor.id
+                );
 
                 // Try to get from cache
-                if let Some(cached) = self.image_cache.get(descriptor.id) {
+                if let Some(cached) = self.image_cache.lookup(descriptor.id, descriptor.type_) {
                     debug!("Found cached image: {}x{}", cached.width, cached.height);
                     Ok(Some((cached.data.clone(), cached.width, cached.height)))
+                } else if self.image_cache.get(descriptor.id).is_some() {
+                    // Only a lossy copy is cached. Drawing it would degrade the
+                    // area, so skip the draw and have the server repaint it.
+                    warn!(
+                        "Lossless cache request for image {} but only a lossy copy is cached, requesting a repaint",
+                        descriptor.id
+                    );
+                    self.refresh_handle().request();
+                    Ok(None)
                 } else {
                     warn!("Cached image with ID {} not found in cache", descriptor.id);
                     Ok(None)
@@ -428,7 +1739,11 @@ impl DisplayChannel {
                         "Found surface {}: {}x{}",
                         surface_id, surface.width, surface.height
                     );
-                    Ok(Some((surface.data.clone(), surface.width, surface.height)))
+                    Ok(Some((
+                        surface.to_rgba().into_owned(),
+                        surface.width,
+                        surface.height,
+                    )))
                 } else {
                     warn!("Surface {} not found", surface_id);
                     Ok(None)
@@ -446,22 +1761,61 @@ impl DisplayChannel {
                 && descriptor.type_ != SPICE_IMAGE_TYPE_FROM_CACHE_LOSSLESS
                 && descriptor.type_ != SPICE_IMAGE_TYPE_SURFACE
             {
-                debug!("Caching decoded image with id: {}", descriptor.id);
+                let lossless = ImageCache::is_lossless_type(descriptor.type_);
+                debug!(
+                    "Caching decoded image with id: {} (lossless: {})",
+                    descriptor.id, lossless
+                );
                 self.image_cache
-                    .insert(descriptor.id, data.clone(), width, height);
+                    .insert(descriptor.id, data.clone(), width, height, lossless);
             }
         }
 
         Ok(result)
     }
 
+    /// Get the palette of an indexed bitmap, either inline or from the palette cache
+    fn resolve_palette(&mut self, bitmap: &SpiceBitmap, data: &[u8]) -> Option<Vec<u32>> {
+        if bitmap.flags & SPICE_BITMAP_FLAGS_PAL_FROM_CACHE != 0 {
+            // The palette field holds the id of a previously cached palette
+            let palette = self.palette_cache.get(&bitmap.palette).cloned();
+            if palette.is_none() {
+                warn!("Palette {} not found in cache", bitmap.palette);
+            }
+            return palette;
+        }
+
+        let palette_data = self.resolve_address(bitmap.palette, data)?;
+        let mut cursor = std::io::Cursor::new(palette_data);
+        let palette = match SpicePalette::read(&mut cursor) {
+            Ok(palette) => palette,
+            Err(e) => {
+                warn!("Failed to parse bitmap palette: {}", e);
+                return None;
+            }
+        };
+
+        debug!(
+            "Bitmap palette {} with {} entries",
+            palette.unique, palette.num_ents
+        );
+        if bitmap.flags & SPICE_BITMAP_FLAGS_PAL_CACHE_ME != 0 {
+            self.palette_cache
+                .insert(palette.unique, palette.ents.clone());
+        }
+
+        Some(palette.ents)
+    }
+
     /// Decode a raw bitmap to RGBA format
+    ///
+    /// `palette` maps the indices of 8-bit indexed bitmaps to 0xXXRRGGBB colors.
     fn decode_bitmap(
-        &self,
         bitmap: &SpiceBitmap,
         data: &[u8],
         width: u32,
         height: u32,
+        palette: Option<&[u32]>,
     ) -> Result<Option<(Vec<u8>, u32, u32)>> {
         let bytes_per_pixel = match bitmap.format {
             SPICE_BITMAP_FMT_32BIT | SPICE_BITMAP_FMT_RGBA => 4,
@@ -507,6 +1861,15 @@ impl DisplayChannel {
                         rgba_data.push(data[pixel_offset]); // B
                         rgba_data.push(255); // A
                     }
+                    SPICE_BITMAP_FMT_8BIT => {
+                        // Palette index -> RGBA, unknown indices become black
+                        let index = data[pixel_offset] as usize;
+                        let color = palette.and_then(|p| p.get(index)).copied().unwrap_or(0);
+                        rgba_data.push(((color >> 16) & 0xFF) as u8); // R
+                        rgba_data.push(((color >> 8) & 0xFF) as u8); // G
+                        rgba_data.push((color & 0xFF) as u8); // B
+                        rgba_data.push(255); // A
+                    }
                     _ => {
                         // Fallback for other formats
                         rgba_data.extend_from_slice(&[0, 0, 0, 255]);
@@ -619,21 +1982,57 @@ impl DisplayChannel {
         Ok(Some((decompressed, width, height)))
     }
 
-    pub async fn run(&mut self) -> Result<()> {
+    pub async fn run(&mut self) -> Result<()> {
+        let result = self.run_loop().await;
+        // Reads sent from here on fail instead of waiting on a loop that is
+        // gone; answer the ones already queued
+        self.inspect_rx.close();
+        while let Ok(inspect) = self.inspect_rx.try_recv() {
+            inspect(self);
+        }
+        result
+    }
+
+    async fn run_loop(&mut self) -> Result<()> {
         info!(
             "DisplayChannel: Starting event loop for channel {}",
             self.connection.channel_id
         );
-        eprintln!("DisplayChannel: Entering message read loop");
+        let refresh = self.refresh.clone();
         loop {
-            eprintln!("DisplayChannel: Waiting for message...");
+            // Damage the bandwidth target holds back goes out once it's due
+            let held = self
+                .damage_since
+                .map(|_| self.update_delay())
+                .filter(|delay| !delay.is_zero());
+            tokio::select! {
+                biased;
+                _ = refresh.notified() => {
+                    self.request_refresh().await?;
+                    continue;
+                }
+                Some(inspect) = self.inspect_rx.recv() => {
+                    inspect(self);
+                    continue;
+                }
+                _ = crate::utils::sleep(held.unwrap_or_default()), if held.is_some() => {
+                    self.flush_damage();
+                    continue;
+                }
+                readable = self.connection.wait_readable() => readable?,
+            }
             match self.connection.read_message().await {
                 Ok((header, data)) => {
-                    eprintln!("DisplayChannel: Got message!");
-                    self.handle_message(&header, &data).await?;
+                    self.throttle.record(data.len());
+                    let result = self.handle_message(&header, &data).await;
+                    self.flush_damage_if_idle();
+                    self.connection.check_message_result(&header, result)?;
+                    // Don't hog a single-threaded executor while the
+                    // server floods draws; let input go out in between
+                    crate::utils::yield_now().await;
                 }
                 Err(e) => {
-                    eprintln!("DisplayChannel: Error reading message: {e}");
+                    debug!("DisplayChannel: Error reading message: {e}");
                     return Err(e);
                 }
             }
@@ -646,72 +2045,80 @@ impl DisplayChannel {
         if data.len() >= 12 {
             let width = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
             let height = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
-            let format = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
+            let bits = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
+            let format = SurfaceFormat::from_depth(bits);
 
-            info!("Display mode: {}x{}, format: {}", width, height, format);
+            info!(
+                "Display mode: {}x{}, {} bits ({:?})",
+                width, height, bits, format
+            );
 
             // Create primary surface (ID 0)
-            eprintln!(
-                "DisplayChannel: Creating primary surface {}x{} format {}",
+            debug!(
+                "DisplayChannel: Creating primary surface {}x{} format {:?}",
                 width, height, format
             );
             self.surfaces.insert(
                 0,
-                DisplaySurface {
-                    width,
-                    height,
-                    format,
-                    data: vec![0; (width * height * 4) as usize], // Assuming 32-bit RGBA
-                },
+                DisplaySurface::with_pixel_format(width, height, format, self.pixel_format),
             );
+            self.surface_flags.insert(0, SPICE_SURFACE_FLAGS_PRIMARY);
+            self.primary_surface_id = Some(0);
 
             // Notify about primary surface
-            self.notify_update(0);
+            self.add_damage(0, None);
         }
 
         Ok(())
     }
 
-    async fn handle_draw_message(&mut self, msg_type: u16, data: &[u8]) -> Result<()> {
-        match msg_type {
-            x if x == DisplayChannelMessage::DrawFill as u16 => {
+    async fn handle_draw_message(&mut self, msg: DisplayChannelMessage, data: &[u8]) -> Result<()> {
+        match msg {
+            DisplayChannelMessage::DrawFill => {
                 debug!("Handle draw fill");
 
-                // For now, just log that we received a DrawFill
-                // TODO: Implement proper parsing once SpiceDrawFill has binrw support
-                info!("Received DrawFill message ({} bytes)", data.len());
-
-                // Basic implementation: fill primary surface with a test color
-                if let Some(surface) = self.surfaces.get_mut(&0) {
-                    // Fill with a test pattern to show something is happening
-                    let bytes_per_pixel = 4;
-                    let stride = surface.width as usize * bytes_per_pixel;
-
-                    // Fill top 100 pixels with red to show we're receiving draw commands
-                    for y in 0..100.min(surface.height as usize) {
-                        for x in 0..surface.width as usize {
-                            let offset = y * stride + x * bytes_per_pixel;
-                            if offset + 4 <= surface.data.len() {
-                                surface.data[offset] = 255; // R
-                                surface.data[offset + 1] = 0; // G
-                                surface.data[offset + 2] = 0; // B
-                                surface.data[offset + 3] = 255; // A
-                            }
+                let mut cursor = std::io::Cursor::new(data);
+                if let Ok(draw_fill) = SpiceDrawFill::read(&mut cursor) {
+                    let surface_id = draw_fill.base.surface_id;
+                    let bbox = &draw_fill.base.box_;
+                    let brush = &draw_fill.data.brush;
+                    let rop = draw_fill.data.rop_descriptor;
+
+                    debug!(
+                        "DrawFill on surface {} - rect: ({},{}) to ({},{}) brush type: {} color: 0x{:06x} rop: 0x{:x}",
+                        surface_id, bbox.left, bbox.top, bbox.right, bbox.bottom,
+                        brush.brush_type, brush.color, rop
+                    );
+
+                    let color = match brush.brush_type {
+                        x if x == BrushType::Solid as u8 => Some(brush.color),
+                        // Blackness/whiteness/invert fills carry no brush
+                        x if x == BrushType::None as u8 => Some(0),
+                        _ => {
+                            warn!("DrawFill with pattern brush is not supported yet");
+                            None
                         }
-                    }
+                    };
 
-                    // Notify that the surface was updated
-                    self.notify_update(0);
+                    if let Some(color) = color {
+                        let clip = self.read_clip_rects(&draw_fill.base.clip, data);
+                        if let Some(surface) = self.surfaces.get_mut(&surface_id) {
+                            surface.fill_rect(bbox, color, rop, clip.as_deref());
+                            self.add_damage(surface_id, Some(bbox));
+                        }
+                    }
+                } else {
+                    warn!("Failed to parse DrawFill message");
                 }
             }
-            x if x == DisplayChannelMessage::DrawCopy as u16 => {
+            DisplayChannelMessage::DrawCopy => {
                 debug!("Handle draw copy");
 
                 // Log raw data for debugging
                 if data.len() < 100 {
-                    debug!("DrawCopy raw data (hex): {:02x?}", data);
+                    trace!("DrawCopy raw data (hex): {:02x?}", data);
                 } else {
-                    debug!(
+                    trace!(
                         "DrawCopy raw data first 100 bytes (hex): {:02x?}",
                         &data[..100]
                     );
@@ -724,7 +2131,7 @@ impl DisplayChannel {
                     let bbox = &draw_copy.base.box_;
                     let src_area = &draw_copy.data.src_area;
 
-                    info!("DrawCopy on surface {} - rect: ({},{}) to ({},{}) from src rect ({},{}) to ({},{}) src_image: 0x{:x}", 
+                    debug!("DrawCopy on surface {} - rect: ({},{}) to ({},{}) from src rect ({},{}) to ({},{}) src_image: 0x{:x}", 
                           surface_id, bbox.left, bbox.top, bbox.right, bbox.bottom,
                           src_area.left, src_area.top, src_area.right, src_area.bottom,
                           draw_copy.data.src_image);
@@ -736,90 +2143,26 @@ impl DisplayChannel {
                     if let Some(surface) = self.surfaces.get_mut(&surface_id) {
                         match decoded_image {
                             Some((image_data, img_width, img_height)) => {
-                                info!("Decoded image: {}x{}", img_width, img_height);
-
-                                // Copy the decoded image to the surface
-                                let bytes_per_pixel = 4;
-                                let surface_stride = surface.width as usize * bytes_per_pixel;
-                                let image_stride = img_width as usize * bytes_per_pixel;
-
-                                // Calculate source rectangle bounds
-                                let src_left = src_area.left.max(0) as usize;
-                                let src_top = src_area.top.max(0) as usize;
-                                let src_right = src_area.right.min(img_width as i32) as usize;
-                                let src_bottom = src_area.bottom.min(img_height as i32) as usize;
-
-                                // Calculate destination bounds
-                                let dst_left = bbox.left.max(0) as usize;
-                                let dst_top = bbox.top.max(0) as usize;
-                                let dst_right = bbox.right.min(surface.width as i32) as usize;
-                                let dst_bottom = bbox.bottom.min(surface.height as i32) as usize;
-
-                                // Copy pixels from source to destination
-                                let copy_width = (src_right - src_left).min(dst_right - dst_left);
-                                let copy_height = (src_bottom - src_top).min(dst_bottom - dst_top);
-
-                                for y in 0..copy_height {
-                                    let src_y = src_top + y;
-                                    let dst_y = dst_top + y;
-
-                                    if src_y < img_height as usize
-                                        && dst_y < surface.height as usize
-                                    {
-                                        let src_row_offset = src_y * image_stride;
-                                        let dst_row_offset = dst_y * surface_stride;
-
-                                        for x in 0..copy_width {
-                                            let src_x = src_left + x;
-                                            let dst_x = dst_left + x;
-
-                                            if src_x < img_width as usize
-                                                && dst_x < surface.width as usize
-                                            {
-                                                let src_offset =
-                                                    src_row_offset + src_x * bytes_per_pixel;
-                                                let dst_offset =
-                                                    dst_row_offset + dst_x * bytes_per_pixel;
-
-                                                if src_offset + 4 <= image_data.len()
-                                                    && dst_offset + 4 <= surface.data.len()
-                                                {
-                                                    // Copy RGBA pixels
-                                                    surface.data[dst_offset..dst_offset + 4]
-                                                        .copy_from_slice(
-                                                            &image_data[src_offset..src_offset + 4],
-                                                        );
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-
-                                self.notify_update(surface_id);
+                                debug!("Decoded image: {}x{}", img_width, img_height);
+
+                                surface.blit_rgba(
+                                    &image_data,
+                                    img_width,
+                                    img_height,
+                                    src_area,
+                                    bbox,
+                                    false,
+                                );
+
+                                self.add_damage(surface_id, Some(bbox));
                             }
                             None => {
                                 warn!("Failed to decode image at address 0x{:x}, using blue test pattern", draw_copy.data.src_image);
 
                                 // Fallback to blue test pattern
-                                let bytes_per_pixel = 4;
-                                let stride = surface.width as usize * bytes_per_pixel;
-
-                                for y in bbox.top.max(0)..bbox.bottom.min(surface.height as i32) {
-                                    let y = y as usize;
-                                    for x in bbox.left.max(0)..bbox.right.min(surface.width as i32)
-                                    {
-                                        let x = x as usize;
-                                        let offset = y * stride + x * bytes_per_pixel;
-                                        if offset + 4 <= surface.data.len() {
-                                            surface.data[offset] = 0; // R
-                                            surface.data[offset + 1] = 0; // G
-                                            surface.data[offset + 2] = 255; // B
-                                            surface.data[offset + 3] = 255; // A
-                                        }
-                                    }
-                                }
-
-                                self.notify_update(surface_id);
+                                surface.fill_solid(bbox, [0, 0, 255, 255]);
+
+                                self.add_damage(surface_id, Some(bbox));
                             }
                         }
                     }
@@ -827,7 +2170,7 @@ impl DisplayChannel {
                     warn!("Failed to parse DrawCopy message");
                 }
             }
-            x if x == DisplayChannelMessage::DrawOpaque as u16 => {
+            DisplayChannelMessage::DrawOpaque => {
                 debug!("Handle draw opaque");
 
                 // Parse the draw opaque message
@@ -838,7 +2181,7 @@ impl DisplayChannel {
                     let brush = &draw_opaque.data.brush;
                     let src_area = &draw_opaque.data.src_area;
 
-                    info!("DrawOpaque on surface {} - rect: ({},{}) to ({},{}) brush type: {} src_image: 0x{:x}", 
+                    debug!("DrawOpaque on surface {} - rect: ({},{}) to ({},{}) brush type: {} src_image: 0x{:x}", 
                           surface_id, bbox.left, bbox.top, bbox.right, bbox.bottom,
                           brush.brush_type, draw_opaque.data.src_image);
 
@@ -850,115 +2193,40 @@ impl DisplayChannel {
                     };
 
                     if let Some(surface) = self.surfaces.get_mut(&surface_id) {
-                        let bytes_per_pixel = 4;
-                        let stride = surface.width as usize * bytes_per_pixel;
-
                         // First, fill with brush background if it's a solid color
                         if brush.brush_type == 1 {
                             // SOLID brush
                             let r = ((brush.color >> 16) & 0xFF) as u8;
                             let g = ((brush.color >> 8) & 0xFF) as u8;
                             let b = (brush.color & 0xFF) as u8;
-
-                            // Fill the destination rectangle with brush color
-                            for y in bbox.top.max(0)..bbox.bottom.min(surface.height as i32) {
-                                let y = y as usize;
-                                for x in bbox.left.max(0)..bbox.right.min(surface.width as i32) {
-                                    let x = x as usize;
-                                    let offset = y * stride + x * bytes_per_pixel;
-                                    if offset + 4 <= surface.data.len() {
-                                        surface.data[offset] = r;
-                                        surface.data[offset + 1] = g;
-                                        surface.data[offset + 2] = b;
-                                        surface.data[offset + 3] = 255;
-                                    }
-                                }
-                            }
+                            surface.fill_solid(bbox, [r, g, b, 255]);
                         }
 
                         // If there's a source image, overlay it on top
                         if let Some((image_data, img_width, img_height)) = decoded_image {
-                            info!("Decoded opaque source image: {}x{}", img_width, img_height);
-
-                            // Copy the decoded image to the surface
-                            let image_stride = img_width as usize * bytes_per_pixel;
-
-                            // Calculate source rectangle bounds
-                            let src_left = src_area.left.max(0) as usize;
-                            let src_top = src_area.top.max(0) as usize;
-                            let src_right = src_area.right.min(img_width as i32) as usize;
-                            let src_bottom = src_area.bottom.min(img_height as i32) as usize;
-
-                            // Calculate destination bounds
-                            let dst_left = bbox.left.max(0) as usize;
-                            let dst_top = bbox.top.max(0) as usize;
-                            let dst_right = bbox.right.min(surface.width as i32) as usize;
-                            let dst_bottom = bbox.bottom.min(surface.height as i32) as usize;
-
-                            // Copy pixels from source to destination
-                            let copy_width = (src_right - src_left).min(dst_right - dst_left);
-                            let copy_height = (src_bottom - src_top).min(dst_bottom - dst_top);
-
-                            for y in 0..copy_height {
-                                let src_y = src_top + y;
-                                let dst_y = dst_top + y;
-
-                                if src_y < img_height as usize && dst_y < surface.height as usize {
-                                    let src_row_offset = src_y * image_stride;
-                                    let dst_row_offset = dst_y * stride;
-
-                                    for x in 0..copy_width {
-                                        let src_x = src_left + x;
-                                        let dst_x = dst_left + x;
-
-                                        if src_x < img_width as usize
-                                            && dst_x < surface.width as usize
-                                        {
-                                            let src_offset =
-                                                src_row_offset + src_x * bytes_per_pixel;
-                                            let dst_offset =
-                                                dst_row_offset + dst_x * bytes_per_pixel;
-
-                                            if src_offset + 4 <= image_data.len()
-                                                && dst_offset + 4 <= surface.data.len()
-                                            {
-                                                // Copy RGBA pixels (opaque means we ignore alpha from source)
-                                                surface.data[dst_offset] = image_data[src_offset];
-                                                surface.data[dst_offset + 1] =
-                                                    image_data[src_offset + 1];
-                                                surface.data[dst_offset + 2] =
-                                                    image_data[src_offset + 2];
-                                                surface.data[dst_offset + 3] = 255;
-                                                // Always opaque
-                                            }
-                                        }
-                                    }
-                                }
-                            }
+                            debug!("Decoded opaque source image: {}x{}", img_width, img_height);
+
+                            // Opaque means we ignore alpha from the source
+                            surface.blit_rgba(
+                                &image_data,
+                                img_width,
+                                img_height,
+                                src_area,
+                                bbox,
+                                true,
+                            );
                         } else if brush.brush_type != 1 {
                             // No image and no solid brush, use green test pattern
-                            for y in bbox.top.max(0)..bbox.bottom.min(surface.height as i32) {
-                                let y = y as usize;
-                                for x in bbox.left.max(0)..bbox.right.min(surface.width as i32) {
-                                    let x = x as usize;
-                                    let offset = y * stride + x * bytes_per_pixel;
-                                    if offset + 4 <= surface.data.len() {
-                                        surface.data[offset] = 0;
-                                        surface.data[offset + 1] = 255;
-                                        surface.data[offset + 2] = 0;
-                                        surface.data[offset + 3] = 255;
-                                    }
-                                }
-                            }
+                            surface.fill_solid(bbox, [0, 255, 0, 255]);
                         }
 
-                        self.notify_update(surface_id);
+                        self.add_damage(surface_id, Some(bbox));
                     }
                 } else {
                     warn!("Failed to parse DrawOpaque message");
                 }
             }
-            x if x == DisplayChannelMessage::DrawBlend as u16 => {
+            DisplayChannelMessage::DrawBlend => {
                 debug!("Handle draw blend");
 
                 // Parse the draw blend message
@@ -967,48 +2235,124 @@ impl DisplayChannel {
                     let surface_id = draw_blend.base.surface_id;
                     let bbox = &draw_blend.base.box_;
 
-                    info!(
+                    debug!(
                         "DrawBlend on surface {} - rect: ({},{}) to ({},{})",
                         surface_id, bbox.left, bbox.top, bbox.right, bbox.bottom
                     );
 
                     // For now, just fill with purple to show we're processing DrawBlend
                     if let Some(surface) = self.surfaces.get_mut(&surface_id) {
-                        let bytes_per_pixel = 4;
-                        let stride = surface.width as usize * bytes_per_pixel;
-
                         // Fill with purple for testing
-                        for y in bbox.top.max(0)..bbox.bottom.min(surface.height as i32) {
-                            let y = y as usize;
-                            for x in bbox.left.max(0)..bbox.right.min(surface.width as i32) {
-                                let x = x as usize;
-                                let offset = y * stride + x * bytes_per_pixel;
-                                if offset + 4 <= surface.data.len() {
-                                    surface.data[offset] = 128; // R
-                                    surface.data[offset + 1] = 0; // G
-                                    surface.data[offset + 2] = 128; // B
-                                    surface.data[offset + 3] = 255; // A
-                                }
+                        surface.fill_solid(bbox, [128, 0, 128, 255]);
+
+                        self.add_damage(surface_id, Some(bbox));
+                    }
+                } else {
+                    warn!("Failed to parse DrawBlend message");
+                }
+            }
+            DisplayChannelMessage::DrawTransparent => {
+                debug!("Handle draw transparent");
+
+                let mut cursor = std::io::Cursor::new(data);
+                if let Ok(draw_transparent) = SpiceDrawTransparent::read(&mut cursor) {
+                    let surface_id = draw_transparent.base.surface_id;
+                    let bbox = &draw_transparent.base.box_;
+                    let src_area = &draw_transparent.data.src_area;
+                    let key = draw_transparent.data.true_color;
+
+                    debug!(
+                        "DrawTransparent on surface {} - rect: ({},{}) to ({},{}) src_image: 0x{:x} key: 0x{:06x}",
+                        surface_id, bbox.left, bbox.top, bbox.right, bbox.bottom,
+                        draw_transparent.data.src_image, key
+                    );
+
+                    match self.decode_image(draw_transparent.data.src_image, data)? {
+                        Some((image_data, img_width, img_height)) => {
+                            let clip = self.read_clip_rects(&draw_transparent.base.clip, data);
+                            if let Some(surface) = self.surfaces.get_mut(&surface_id) {
+                                surface.blit_transparent(
+                                    &image_data,
+                                    img_width,
+                                    img_height,
+                                    src_area,
+                                    bbox,
+                                    key,
+                                    clip.as_deref(),
+                                );
+                                self.add_damage(surface_id, Some(bbox));
                             }
                         }
+                        None => warn!(
+                            "Failed to decode transparent image at address 0x{:x}",
+                            draw_transparent.data.src_image
+                        ),
+                    }
+                } else {
+                    warn!("Failed to parse DrawTransparent message");
+                }
+            }
+            DisplayChannelMessage::DrawStroke => {
+                debug!("Handle draw stroke");
+
+                let mut cursor = std::io::Cursor::new(data);
+                if let Ok(draw_stroke) = SpiceDrawStroke::read(&mut cursor) {
+                    let surface_id = draw_stroke.base.surface_id;
+                    let bbox = &draw_stroke.base.box_;
+                    let brush = &draw_stroke.data.brush;
+                    let rop = draw_stroke.data.fore_mode;
+
+                    debug!(
+                        "DrawStroke on surface {} - rect: ({},{}) to ({},{}) brush type: {} color: 0x{:06x} rop: 0x{:x}",
+                        surface_id, bbox.left, bbox.top, bbox.right, bbox.bottom,
+                        brush.brush_type, brush.color, rop
+                    );
+
+                    if draw_stroke.data.attr.flags & SPICE_LINE_FLAGS_STYLED != 0 {
+                        debug!("Styled DrawStroke is drawn as a solid line");
+                    }
+
+                    let color = match brush.brush_type {
+                        x if x == BrushType::Solid as u8 => Some(brush.color),
+                        x if x == BrushType::None as u8 => Some(0),
+                        _ => {
+                            warn!("DrawStroke with pattern brush is not supported yet");
+                            None
+                        }
+                    };
 
-                        self.notify_update(surface_id);
+                    let path = color.and_then(|_| self.read_path(draw_stroke.data.path, data));
+                    if let (Some(color), Some(path)) = (color, path) {
+                        let clip = self.read_clip_rects(&draw_stroke.base.clip, data);
+                        if let Some(surface) = self.surfaces.get_mut(&surface_id) {
+                            surface.stroke_polylines(
+                                &path_polylines(&path),
+                                color,
+                                rop,
+                                clip.as_deref(),
+                            );
+                            self.add_damage(surface_id, Some(bbox));
+                        }
                     }
                 } else {
-                    warn!("Failed to parse DrawBlend message");
+                    warn!("Failed to parse DrawStroke message");
                 }
             }
             _ => {
-                debug!("Unhandled draw message type: {}", msg_type);
+                debug!("Unhandled draw message {:?}", msg);
             }
         }
 
         Ok(())
     }
 
-    async fn handle_stream_message(&mut self, msg_type: u16, data: &[u8]) -> Result<()> {
-        match msg_type {
-            x if x == DisplayChannelMessage::StreamCreate as u16 => {
+    async fn handle_stream_message(
+        &mut self,
+        msg: DisplayChannelMessage,
+        data: &[u8],
+    ) -> Result<()> {
+        match msg {
+            DisplayChannelMessage::StreamCreate => {
                 debug!("Handle stream create");
                 let mut cursor = std::io::Cursor::new(data);
                 let stream_create = SpiceStreamCreate::read(&mut cursor).map_err(|e| {
@@ -1034,28 +2378,78 @@ impl DisplayChannel {
                         width: stream_create.stream_width,
                         height: stream_create.stream_height,
                         dest_rect: stream_create.dest.clone(),
+                        clip: self.read_clip_rects(&stream_create.clip, data),
                     },
                 );
             }
-            x if x == DisplayChannelMessage::StreamData as u16 => {
+            DisplayChannelMessage::StreamData | DisplayChannelMessage::StreamDataSized => {
                 debug!("Handle stream data");
                 let mut cursor = std::io::Cursor::new(data);
-                let stream_data = SpiceStreamData::read(&mut cursor).map_err(|e| {
-                    SpiceError::Protocol(format!("Failed to parse StreamData: {e}"))
-                })?;
+                let (stream_id, mm_time, data_size) =
+                    if msg == DisplayChannelMessage::StreamDataSized {
+                        let stream_data = SpiceStreamDataSized::read(&mut cursor).map_err(|e| {
+                            SpiceError::Protocol(format!("Failed to parse StreamDataSized: {e}"))
+                        })?;
+                        (
+                            stream_data.id,
+                            stream_data.multi_media_time,
+                            stream_data.data_size,
+                        )
+                    } else {
+                        let stream_data = SpiceStreamData::read(&mut cursor).map_err(|e| {
+                            SpiceError::Protocol(format!("Failed to parse StreamData: {e}"))
+                        })?;
+                        (
+                            stream_data.id,
+                            stream_data.multi_media_time,
+                            stream_data.data_size,
+                        )
+                    };
 
-                debug!(
-                    "Received {} bytes for stream {}",
-                    stream_data.data_size, stream_data.id
-                );
+                debug!("Received {} bytes for stream {}", data_size, stream_id);
+
+                // Frames that arrive while updates are held back aren't shown
+                let dropped = !self.update_delay().is_zero();
+                self.record_stream_frame(stream_id, mm_time, dropped)
+                    .await?;
 
                 // TODO: Decode stream data and apply to surface
                 // This would involve:
                 // 1. Finding the decoder for this stream ID
                 // 2. Decoding the data based on codec type
-                // 3. Applying decoded frame to the display surface
+                // 3. Applying the frame with `apply_stream_frame`
+            }
+            DisplayChannelMessage::StreamClip => {
+                debug!("Handle stream clip");
+                let mut cursor = std::io::Cursor::new(data);
+                let stream_clip = SpiceStreamClip::read(&mut cursor).map_err(|e| {
+                    SpiceError::Protocol(format!("Failed to parse StreamClip: {e}"))
+                })?;
+
+                let clip = self.read_clip_rects(&stream_clip.clip, data);
+                match self.active_streams.get_mut(&stream_clip.id) {
+                    Some(stream) => {
+                        debug!("Stream {} clipped to {:?}", stream_clip.id, clip);
+                        stream.clip = clip;
+                    }
+                    None => warn!("Clip for unknown stream {}", stream_clip.id),
+                }
             }
-            x if x == DisplayChannelMessage::StreamDestroy as u16 => {
+            DisplayChannelMessage::StreamActivateReport => {
+                debug!("Handle stream activate report");
+                let mut cursor = std::io::Cursor::new(data);
+                let request = SpiceStreamActivateReport::read(&mut cursor).map_err(|e| {
+                    SpiceError::Protocol(format!("Failed to parse StreamActivateReport: {e}"))
+                })?;
+
+                info!(
+                    "Reporting on stream {} every {} frames or {} ms",
+                    request.stream_id, request.max_window_size, request.timeout_ms
+                );
+                self.stream_reports
+                    .insert(request.stream_id, StreamReportWindow::new(request));
+            }
+            DisplayChannelMessage::StreamDestroy => {
                 debug!("Handle stream destroy");
                 let mut cursor = std::io::Cursor::new(data);
                 let stream_destroy = SpiceStreamDestroy::read(&mut cursor).map_err(|e| {
@@ -1066,9 +2460,74 @@ impl DisplayChannel {
 
                 // Clean up stream info
                 self.active_streams.remove(&stream_destroy.id);
+                self.stream_reports.remove(&stream_destroy.id);
+            }
+            DisplayChannelMessage::StreamDestroyAll => {
+                info!("Destroyed all streams");
+                self.active_streams.clear();
+                self.stream_reports.clear();
             }
             _ => {
-                debug!("Unhandled stream message type: {}", msg_type);
+                debug!("Unhandled stream message {:?}", msg);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Count a frame of a stream being reported on, and send the report when
+    /// its window is complete
+    async fn record_stream_frame(
+        &mut self,
+        stream_id: u32,
+        mm_time: u32,
+        dropped: bool,
+    ) -> Result<()> {
+        use binrw::BinWrite;
+
+        let Some(window) = self.stream_reports.get_mut(&stream_id) else {
+            return Ok(());
+        };
+        // Reinterpreting the wrapping difference keeps it right across wraps
+        let delay = match &self.media_clock {
+            Some(clock) if clock.is_set() => mm_time.wrapping_sub(clock.now()) as i32,
+            _ => 0,
+        };
+        let Some(report) = window.record_frame(mm_time, delay, dropped, Instant::now()) else {
+            return Ok(());
+        };
+        debug!(
+            "Sending report for stream {}: {} frames",
+            report.stream_id, report.num_frames
+        );
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        report
+            .write(&mut cursor)
+            .map_err(|e| SpiceError::Protocol(format!("Failed to write stream report: {e}")))?;
+        self.connection
+            .send_message(SPICE_MSGC_DISPLAY_STREAM_REPORT, &cursor.into_inner())
+            .await
+    }
+
+    async fn handle_common_message(&mut self, msg: CommonMessage, data: &[u8]) -> Result<()> {
+        match msg {
+            CommonMessage::SetAck => {
+                debug!("DisplayChannel: Received SET_ACK message");
+                // Parse the generation number
+                if data.len() >= 4 {
+                    let generation = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+                    debug!("DisplayChannel: SET_ACK generation: {generation}");
+
+                    // Send ACK_SYNC response
+                    let ack_data = generation.to_le_bytes();
+                    self.connection
+                        .send_message(SPICE_MSGC_ACK_SYNC, &ack_data)
+                        .await?;
+                    debug!("DisplayChannel: Sent ACK_SYNC response");
+                }
+            }
+            other => {
+                warn!("Unhandled common message on display channel: {:?}", other);
             }
         }
 
@@ -1078,92 +2537,129 @@ impl DisplayChannel {
 
 impl Channel for DisplayChannel {
     async fn handle_message(&mut self, header: &SpiceDataHeader, data: &[u8]) -> Result<()> {
-        eprintln!(
+        trace!(
             "DisplayChannel: Received message type {} with {} bytes",
             header.msg_type,
             data.len()
         );
 
-        // Log specific message types for debugging
-        match header.msg_type {
-            101 => eprintln!("  -> SPICE_MSG_DISPLAY_MODE"),
-            318 => eprintln!("  -> SPICE_MSG_DISPLAY_SURFACE_CREATE"),
-            319 => eprintln!("  -> SPICE_MSG_DISPLAY_SURFACE_DESTROY"),
-            122 => eprintln!("  -> SPICE_MSG_DISPLAY_STREAM_CREATE"),
-            123 => eprintln!("  -> SPICE_MSG_DISPLAY_STREAM_DATA"),
-            125 => eprintln!("  -> SPICE_MSG_DISPLAY_STREAM_DESTROY"),
-            302 => eprintln!("  -> SPICE_MSG_DISPLAY_DRAW_FILL"),
-            304 => eprintln!("  -> SPICE_MSG_DISPLAY_DRAW_COPY"),
-            _ => {}
-        }
-
-        match header.msg_type {
-            x if x == DisplayChannelMessage::Mode as u16 => {
+        if let Ok(common) = CommonMessage::try_from(header.msg_type) {
+            return self.handle_common_message(common, data).await;
+        }
+
+        let msg = match DisplayChannelMessage::try_from(header.msg_type) {
+            Ok(msg) => msg,
+            Err(msg_type) => {
+                warn!("Unknown display message type: {}", msg_type);
+                return Ok(());
+            }
+        };
+        trace!("  -> {msg:?}");
+
+        match msg {
+            DisplayChannelMessage::Mode => {
                 debug!("Received display mode");
                 self.handle_mode_message(data).await?;
             }
-            x if x == DisplayChannelMessage::Mark as u16 => {
+            DisplayChannelMessage::Mark => {
                 debug!("Received display mark");
                 // Handle mark message
             }
-            x if x == DisplayChannelMessage::Reset as u16 => {
+            DisplayChannelMessage::Reset => {
                 debug!("Received display reset");
                 // Reset display state - clear all surfaces and streams
                 self.surfaces.clear();
+                self.damage.clear();
+                self.updated_at.clear();
                 self.active_streams.clear();
+                self.stream_reports.clear();
                 self.monitors.clear();
+                self.palette_cache.clear();
+                self.gl_scanout = None;
+            }
+            DisplayChannelMessage::InvalList => {
+                let mut cursor = std::io::Cursor::new(data);
+                let list = SpiceResourceList::read(&mut cursor)
+                    .map_err(|e| SpiceError::Protocol(format!("Failed to parse InvalList: {e}")))?;
+                debug!("Received invalidation list of {} resources", list.count);
+                self.image_cache.invalidate(&list);
+            }
+            DisplayChannelMessage::InvalAllPixmaps => {
+                // The body is a wait-for-channels list; the cache is local to
+                // this channel, so there is nothing to wait for
+                debug!(
+                    "Received invalidate all pixmaps, dropping {} cached images",
+                    self.image_cache.len()
+                );
+                self.image_cache.clear();
             }
-            x if x == DisplayChannelMessage::InvalList as u16 => {
-                debug!("Received invalidation list");
-                // Handle invalidation list
+            DisplayChannelMessage::InvalPalette => {
+                let mut cursor = std::io::Cursor::new(data);
+                let inval = SpiceMsgDisplayInvalOne::read(&mut cursor).map_err(|e| {
+                    SpiceError::Protocol(format!("Failed to parse InvalPalette: {e}"))
+                })?;
+                debug!("Received invalidate palette {}", inval.id);
+                self.palette_cache.remove(&inval.id);
             }
-            x if x == DisplayChannelMessage::InvalAllPixmaps as u16 => {
-                debug!("Received invalidate all pixmaps");
-                // Handle pixmap invalidation
+            DisplayChannelMessage::InvalAllPalettes => {
+                debug!("Received invalidate all palettes");
+                self.palette_cache.clear();
             }
-            x if (DisplayChannelMessage::DrawFill as u16
-                ..=DisplayChannelMessage::DrawAlphaBlend as u16)
-                .contains(&x) =>
-            {
-                self.handle_draw_message(header.msg_type, data).await?;
+            msg if msg.is_draw() => {
+                self.handle_draw_message(msg, data).await?;
+                self.has_drawn = true;
             }
-            x if (DisplayChannelMessage::StreamCreate as u16
-                ..=DisplayChannelMessage::StreamDestroyAll as u16)
-                .contains(&x) =>
-            {
-                self.handle_stream_message(header.msg_type, data).await?;
+            msg if msg.is_stream() => {
+                self.handle_stream_message(msg, data).await?;
+                if matches!(
+                    msg,
+                    DisplayChannelMessage::StreamData | DisplayChannelMessage::StreamDataSized
+                ) {
+                    self.has_drawn = true;
+                }
             }
-            x if x == SPICE_MSG_DISPLAY_SURFACE_CREATE => {
+            DisplayChannelMessage::SurfaceCreate => {
                 debug!("Received surface create");
                 let mut cursor = std::io::Cursor::new(data);
                 let surface_create = SpiceMsgSurfaceCreate::read(&mut cursor).map_err(|e| {
                     SpiceError::Protocol(format!("Failed to parse SurfaceCreate: {e}"))
                 })?;
 
+                let format = SurfaceFormat::try_from(surface_create.format).unwrap_or_else(|f| {
+                    warn!("Unknown surface format {}, assuming 32-bit xRGB", f);
+                    SurfaceFormat::Xrgb32
+                });
                 info!(
-                    "Creating surface {} - {}x{} format: {}",
+                    "Creating surface {} - {}x{} format: {:?} flags: 0x{:x}",
                     surface_create.surface_id,
                     surface_create.width,
                     surface_create.height,
-                    surface_create.format
+                    format,
+                    surface_create.flags
                 );
 
                 // Create new surface
-                let data_size = (surface_create.width * surface_create.height * 4) as usize;
                 self.surfaces.insert(
                     surface_create.surface_id,
-                    DisplaySurface {
-                        width: surface_create.width,
-                        height: surface_create.height,
-                        format: surface_create.format,
-                        data: vec![0; data_size],
-                    },
+                    DisplaySurface::with_pixel_format(
+                        surface_create.width,
+                        surface_create.height,
+                        format,
+                        self.pixel_format,
+                    ),
                 );
+                self.surface_flags
+                    .insert(surface_create.surface_id, surface_create.flags);
+                if surface_create.is_primary() {
+                    self.primary_surface_id = Some(surface_create.surface_id);
+                } else if self.primary_surface_id == Some(surface_create.surface_id) {
+                    self.primary_surface_id = None;
+                }
 
                 // Notify about new surface
-                self.notify_update(surface_create.surface_id);
+                self.add_damage(surface_create.surface_id, None);
             }
-            x if x == SPICE_MSG_DISPLAY_SURFACE_DESTROY => {
+            DisplayChannelMessage::SurfaceDestroy => {
                 debug!("Received surface destroy");
                 let mut cursor = std::io::Cursor::new(data);
                 let surface_destroy = SpiceMsgSurfaceDestroy::read(&mut cursor).map_err(|e| {
@@ -1173,8 +2669,14 @@ impl Channel for DisplayChannel {
                 info!("Destroying surface {}", surface_destroy.surface_id);
 
                 self.surfaces.remove(&surface_destroy.surface_id);
+                self.surface_flags.remove(&surface_destroy.surface_id);
+                self.damage.remove(&surface_destroy.surface_id);
+                self.updated_at.remove(&surface_destroy.surface_id);
+                if self.primary_surface_id == Some(surface_destroy.surface_id) {
+                    self.primary_surface_id = None;
+                }
             }
-            x if x == SPICE_MSG_DISPLAY_MONITORS_CONFIG => {
+            DisplayChannelMessage::MonitorsConfig => {
                 debug!("Received monitors config");
                 let mut cursor = std::io::Cursor::new(data);
                 let monitors_config = SpiceMonitorsConfig::read(&mut cursor).map_err(|e| {
@@ -1196,22 +2698,39 @@ impl Channel for DisplayChannel {
                     );
                 }
             }
-            3 => {
-                // SPICE_MSG_SET_ACK
-                eprintln!("DisplayChannel: Received SET_ACK message");
-                // Parse the generation number
-                if data.len() >= 4 {
-                    let generation = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                    eprintln!("DisplayChannel: SET_ACK generation: {generation}");
+            DisplayChannelMessage::GlScanoutUnix => {
+                let mut cursor = std::io::Cursor::new(data);
+                let scanout = SpiceMsgDisplayGlScanoutUnix::read(&mut cursor).map_err(|e| {
+                    SpiceError::Protocol(format!("Failed to parse GlScanoutUnix: {e}"))
+                })?;
 
-                    // Send ACK_SYNC response
-                    let ack_data = generation.to_le_bytes();
-                    self.connection.send_message(1, &ack_data).await?; // SPICE_MSGC_ACK_SYNC
-                    eprintln!("DisplayChannel: Sent ACK_SYNC response");
+                if self.gl_scanout.is_none() {
+                    warn!(
+                        "Display channel {}: server switched to GL scanout ({}x{}, fourcc {:#x}); \
+                         dmabuf import is not supported, the display will not update",
+                        self.connection.channel_id,
+                        scanout.width,
+                        scanout.height,
+                        scanout.drm_fourcc_format
+                    );
                 }
+
+                self.gl_scanout = Some(scanout);
+                self.notify_event(DisplayEvent::GlScanout(scanout));
             }
-            _ => {
-                warn!("Unknown display message type: {}", header.msg_type);
+            DisplayChannelMessage::GlDraw => {
+                let mut cursor = std::io::Cursor::new(data);
+                let draw = SpiceMsgDisplayGlDraw::read(&mut cursor)
+                    .map_err(|e| SpiceError::Protocol(format!("Failed to parse GlDraw: {e}")))?;
+
+                debug!(
+                    "Received GL draw {}x{} at ({},{})",
+                    draw.w, draw.h, draw.x, draw.y
+                );
+                self.notify_event(DisplayEvent::GlDraw(draw));
+            }
+            other => {
+                debug!("Unhandled display message {:?}", other);
             }
         }
 
@@ -1222,3 +2741,568 @@ impl Channel for DisplayChannel {
         ChannelType::Display
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_cache_lossless_and_lossy_entries() {
+        let mut cache = ImageCache::new();
+        let lossy_type = SPICE_IMAGE_TYPE_JPEG;
+        let lossless_type = SPICE_IMAGE_TYPE_BITMAP;
+
+        cache.insert(
+            1,
+            vec![1; 16],
+            2,
+            2,
+            ImageCache::is_lossless_type(lossy_type),
+        );
+        cache.insert(
+            2,
+            vec![2; 16],
+            2,
+            2,
+            ImageCache::is_lossless_type(lossless_type),
+        );
+
+        // FROM_CACHE may be served from either entry
+        assert!(cache.lookup(1, SPICE_IMAGE_TYPE_FROM_CACHE).is_some());
+        assert!(cache.lookup(2, SPICE_IMAGE_TYPE_FROM_CACHE).is_some());
+
+        // FROM_CACHE_LOSSLESS must not be served from the lossy entry
+        assert!(cache
+            .lookup(1, SPICE_IMAGE_TYPE_FROM_CACHE_LOSSLESS)
+            .is_none());
+        assert_eq!(
+            cache
+                .lookup(2, SPICE_IMAGE_TYPE_FROM_CACHE_LOSSLESS)
+                .map(|c| c.data.clone()),
+            Some(vec![2; 16])
+        );
+
+        // Replacing the lossy entry with a lossless one makes it usable again
+        cache.insert(1, vec![3; 16], 2, 2, true);
+        assert_eq!(
+            cache
+                .lookup(1, SPICE_IMAGE_TYPE_FROM_CACHE_LOSSLESS)
+                .map(|c| c.data.clone()),
+            Some(vec![3; 16])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lossy_cache_hit_for_lossless_reference_requests_refresh() {
+        use crate::test_utils::MockLink;
+        use binrw::BinWrite;
+        use futures::FutureExt;
+
+        let (client_end, mut server_end) = tokio::io::duplex(64 * 1024);
+        let server_task = tokio::spawn(async move {
+            MockLink::default().serve(&mut server_end).await.unwrap();
+            server_end
+        });
+        let connection = ChannelConnection::from_stream(client_end, ChannelType::Display, 0);
+        let mut channel = DisplayChannel::from_connection(connection, Some(1))
+            .await
+            .unwrap();
+        let _server_end = server_task.await.unwrap();
+
+        channel.image_cache.insert(7, vec![1; 16], 2, 2, false);
+        let image = |type_| {
+            let descriptor = SpiceImageDescriptor {
+                id: 7,
+                type_,
+                flags: 0,
+                width: 2,
+                height: 2,
+            };
+            // Address 0 is null, so the image starts past a few bytes
+            let mut cursor = std::io::Cursor::new(vec![0; 4]);
+            cursor.set_position(4);
+            descriptor.write(&mut cursor).unwrap();
+            cursor.into_inner()
+        };
+
+        // The lossy copy serves a plain cache reference
+        let data = image(SPICE_IMAGE_TYPE_FROM_CACHE);
+        assert!(channel.decode_image(4, &data).unwrap().is_some());
+        assert!(channel.refresh.notified().now_or_never().is_none());
+
+        // A lossless reference isn't drawn from it; the area is repainted
+        let data = image(SPICE_IMAGE_TYPE_FROM_CACHE_LOSSLESS);
+        assert!(channel.decode_image(4, &data).unwrap().is_none());
+        assert!(channel.refresh.notified().now_or_never().is_some());
+    }
+
+    fn inval_list_body(resources: &[(u8, u64)]) -> Vec<u8> {
+        let mut body = (resources.len() as u16).to_le_bytes().to_vec();
+        for (type_, id) in resources {
+            body.push(*type_);
+            body.extend_from_slice(&id.to_le_bytes());
+        }
+        body
+    }
+
+    fn filled_cache(ids: &[u64]) -> ImageCache {
+        let mut cache = ImageCache::new();
+        for id in ids {
+            cache.insert(*id, vec![0; 4], 1, 1, true);
+        }
+        cache
+    }
+
+    #[test]
+    fn test_inval_list_removes_listed_images() {
+        let mut cache = filled_cache(&[1, 2, 3, 4]);
+        let body = inval_list_body(&[
+            (SPICE_RES_TYPE_PIXMAP, 2),
+            (SPICE_RES_TYPE_PIXMAP, 4),
+            (SPICE_RES_TYPE_PIXMAP, 99),
+            // Not a pixmap, so image 1 stays
+            (SPICE_RES_TYPE_INVALID, 1),
+        ]);
+
+        let list = SpiceResourceList::read(&mut std::io::Cursor::new(&body)).unwrap();
+        assert_eq!(list.resources.len(), 4);
+        cache.invalidate(&list);
+
+        let mut remaining: Vec<u64> = cache.entries.keys().copied().collect();
+        remaining.sort();
+        assert_eq!(remaining, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_inval_all_pixmaps_empties_cache() {
+        let mut cache = filled_cache(&[1, 2, 3]);
+        assert_eq!(cache.len(), 3);
+
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+        assert!(cache.lookup(1, SPICE_IMAGE_TYPE_FROM_CACHE).is_none());
+    }
+
+    fn test_surface(width: u32, height: u32) -> DisplaySurface {
+        DisplaySurface::new(width, height, SurfaceFormat::Xrgb32)
+    }
+
+    fn pixel(surface: &DisplaySurface, x: u32, y: u32) -> [u8; 4] {
+        let offset = ((y * surface.width + x) * 4) as usize;
+        surface.data[offset..offset + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_fill_rect_sets_exact_pixels() {
+        let mut surface = test_surface(8, 8);
+        let rect = SpiceRect {
+            left: 2,
+            top: 1,
+            right: 5,
+            bottom: 4,
+        };
+
+        surface.fill_rect(&rect, 0x00336699, SPICE_ROPD_OP_PUT, None);
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let inside = (2..5).contains(&x) && (1..4).contains(&y);
+                let expected = if inside {
+                    [0x33, 0x66, 0x99, 255]
+                } else {
+                    [0, 0, 0, 0]
+                };
+                assert_eq!(pixel(&surface, x, y), expected, "pixel ({x},{y})");
+            }
+        }
+    }
+
+    #[test]
+    fn test_fill_rect_honors_clip() {
+        let mut surface = test_surface(8, 8);
+        let rect = SpiceRect {
+            left: 0,
+            top: 0,
+            right: 8,
+            bottom: 8,
+        };
+        let clip = [SpiceRect {
+            left: 6,
+            top: 6,
+            right: 10,
+            bottom: 10,
+        }];
+
+        surface.fill_rect(&rect, 0x00FFFFFF, SPICE_ROPD_OP_PUT, Some(&clip));
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let inside = x >= 6 && y >= 6;
+                let expected = if inside {
+                    [255, 255, 255, 255]
+                } else {
+                    [0, 0, 0, 0]
+                };
+                assert_eq!(pixel(&surface, x, y), expected, "pixel ({x},{y})");
+            }
+        }
+    }
+
+    #[test]
+    fn test_blit_transparent_skips_key_color() {
+        let mut surface = test_surface(6, 6);
+        let full = SpiceRect {
+            left: 0,
+            top: 0,
+            right: 6,
+            bottom: 6,
+        };
+        surface.fill_rect(&full, 0x00112233, SPICE_ROPD_OP_PUT, None);
+
+        // A 4x4 sprite on a magenta background with a 2x2 yellow middle
+        let mut image = Vec::new();
+        for y in 0..4 {
+            for x in 0..4 {
+                let sprite = (1..3).contains(&x) && (1..3).contains(&y);
+                image.extend_from_slice(if sprite {
+                    &[255, 255, 0, 255]
+                } else {
+                    &[255, 0, 255, 255]
+                });
+            }
+        }
+        let src_area = SpiceRect {
+            left: 0,
+            top: 0,
+            right: 4,
+            bottom: 4,
+        };
+        let dest = SpiceRect {
+            left: 1,
+            top: 1,
+            right: 5,
+            bottom: 5,
+        };
+        // The clip leaves out the sprite's bottom row
+        let clip = [SpiceRect {
+            left: 0,
+            top: 0,
+            right: 6,
+            bottom: 3,
+        }];
+
+        surface.blit_transparent(&image, 4, 4, &src_area, &dest, 0x00FF00FF, Some(&clip));
+
+        for y in 0..6 {
+            for x in 0..6 {
+                let drawn = (2..4).contains(&x) && y == 2;
+                let expected = if drawn {
+                    [255, 255, 0, 255]
+                } else {
+                    [0x11, 0x22, 0x33, 255]
+                };
+                assert_eq!(pixel(&surface, x, y), expected, "pixel ({x},{y})");
+            }
+        }
+    }
+
+    fn fixed_point(x: i32, y: i32) -> SpicePointFix {
+        SpicePointFix {
+            x: x << 4,
+            y: y << 4,
+        }
+    }
+
+    #[test]
+    fn test_stroke_draws_diagonal_line() {
+        let mut surface = test_surface(8, 8);
+        let path = SpicePath {
+            num_segments: 1,
+            segments: vec![SpicePathSeg {
+                flags: SPICE_PATH_BEGIN | SPICE_PATH_END,
+                count: 2,
+                points: vec![fixed_point(1, 1), fixed_point(6, 6)],
+            }],
+        };
+
+        surface.stroke_polylines(&path_polylines(&path), 0x00FF8000, SPICE_ROPD_OP_PUT, None);
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let on_line = x == y && (1..=6).contains(&x);
+                let expected = if on_line {
+                    [0xFF, 0x80, 0x00, 255]
+                } else {
+                    [0, 0, 0, 0]
+                };
+                assert_eq!(pixel(&surface, x, y), expected, "pixel ({x},{y})");
+            }
+        }
+    }
+
+    #[test]
+    fn test_stroke_closes_path_and_honors_clip() {
+        let mut surface = test_surface(8, 8);
+        let path = SpicePath {
+            num_segments: 1,
+            segments: vec![SpicePathSeg {
+                flags: SPICE_PATH_BEGIN | SPICE_PATH_CLOSE,
+                count: 3,
+                points: vec![fixed_point(0, 0), fixed_point(7, 0), fixed_point(7, 7)],
+            }],
+        };
+        let polylines = path_polylines(&path);
+        assert_eq!(polylines, vec![vec![(0, 0), (7, 0), (7, 7), (0, 0)]]);
+
+        let clip = [rect(0, 0, 4, 8)];
+        surface.stroke_polylines(&polylines, 0x00FFFFFF, SPICE_ROPD_OP_PUT, Some(&clip));
+
+        let white = [255, 255, 255, 255];
+        assert_eq!(pixel(&surface, 3, 0), white);
+        assert_eq!(pixel(&surface, 2, 2), white);
+        assert_eq!(pixel(&surface, 4, 0), [0, 0, 0, 0]);
+        assert_eq!(pixel(&surface, 7, 3), [0, 0, 0, 0]);
+    }
+
+    fn rect(left: i32, top: i32, right: i32, bottom: i32) -> SpiceRect {
+        SpiceRect {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    fn damage_of(region: &DamageRegion) -> Vec<(i32, i32, i32, i32)> {
+        region
+            .rects()
+            .iter()
+            .map(|r| (r.left, r.top, r.right, r.bottom))
+            .collect()
+    }
+
+    #[test]
+    fn test_damage_region_merges_draws() {
+        let mut region = DamageRegion::default();
+        // Overlapping draws
+        region.add(rect(0, 0, 10, 10));
+        region.add(rect(5, 5, 20, 20));
+        // A draw sharing an edge with the first two
+        region.add(rect(20, 0, 30, 10));
+        // Apart from the others
+        region.add(rect(100, 100, 110, 110));
+        // Empty draws don't count
+        region.add(rect(50, 50, 50, 60));
+        assert_eq!(
+            damage_of(&region),
+            vec![(0, 0, 30, 20), (100, 100, 110, 110)]
+        );
+
+        // Bridging the gap pulls everything into one rectangle
+        region.add(rect(25, 15, 105, 105));
+        assert_eq!(damage_of(&region), vec![(0, 0, 110, 110)]);
+    }
+
+    #[test]
+    fn test_damage_region_collapses_many_small_draws() {
+        let mut region = DamageRegion::default();
+        // A dotted line of 1x1 draws, none touching the next
+        for x in 0..100 {
+            region.add(rect(x * 4, 7, x * 4 + 1, 8));
+        }
+        assert!(region.rects().len() <= MAX_DAMAGE_RECTS);
+
+        // The region still spans every draw
+        let bounds = region
+            .rects()
+            .iter()
+            .copied()
+            .reduce(|a, b| rect_union(&a, &b))
+            .unwrap();
+        assert_eq!(
+            (bounds.left, bounds.top, bounds.right, bounds.bottom),
+            (0, 7, 397, 8)
+        );
+    }
+
+    #[test]
+    fn test_update_throttle_paces_by_bytes() {
+        let mut throttle = UpdateThrottle::default();
+        let start = Instant::now();
+
+        // Nothing to pace before the first update
+        throttle.record(1_000);
+        assert_eq!(throttle.delay(8, start), Duration::ZERO);
+
+        // 1000 bytes take a second at 8 kbit/s
+        throttle.updated(start);
+        assert_eq!(throttle.delay(8, start), Duration::from_secs(1));
+        assert_eq!(
+            throttle.delay(8, start + Duration::from_millis(400)),
+            Duration::from_millis(600)
+        );
+        assert_eq!(
+            throttle.delay(8, start + Duration::from_secs(2)),
+            Duration::ZERO
+        );
+        assert_eq!(throttle.delay(0, start), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_stream_report_window() {
+        let mut window = StreamReportWindow::new(SpiceStreamActivateReport {
+            stream_id: 3,
+            unique_id: 9,
+            max_window_size: 3,
+            timeout_ms: 1_000,
+        });
+        let start = Instant::now();
+
+        // Full after three frames
+        assert!(window.record_frame(100, 5, false, start).is_none());
+        assert!(window.record_frame(140, 4, true, start).is_none());
+        let report = window.record_frame(180, -2, false, start).unwrap();
+        assert_eq!(
+            report,
+            SpiceMsgcDisplayStreamReport {
+                stream_id: 3,
+                unique_id: 9,
+                start_frame_mm_time: 100,
+                end_frame_mm_time: 180,
+                num_frames: 3,
+                num_drops: 1,
+                last_frame_delay: -2,
+                audio_delay: 0,
+            }
+        );
+
+        // The next window times out before it fills up
+        assert!(window.record_frame(220, 0, false, start).is_none());
+        let report = window
+            .record_frame(260, 0, false, start + Duration::from_millis(1_000))
+            .unwrap();
+        assert_eq!(report.start_frame_mm_time, 220);
+        assert_eq!(report.num_frames, 2);
+    }
+
+    #[test]
+    fn test_decode_8bit_bitmap_with_palette() {
+        use binrw::BinWrite;
+
+        let palette = SpicePalette {
+            unique: 7,
+            num_ents: 4,
+            ents: vec![0x00FF0000, 0x0000FF00, 0x000000FF, 0x00FFFFFF],
+        };
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        palette.write(&mut cursor).unwrap();
+        let parsed = SpicePalette::read(&mut std::io::Cursor::new(cursor.into_inner())).unwrap();
+        assert_eq!(parsed.ents, palette.ents);
+
+        let bitmap = SpiceBitmap {
+            format: SPICE_BITMAP_FMT_8BIT,
+            flags: SPICE_BITMAP_FLAGS_TOP_DOWN,
+            x: 2,
+            y: 2,
+            stride: 4, // rows are padded
+            palette: 0,
+            data: 0,
+        };
+        let pixels = [3, 0, 0xAA, 0xAA, 1, 2, 0xAA, 0xAA];
+
+        let (rgba, width, height) =
+            DisplayChannel::decode_bitmap(&bitmap, &pixels, 2, 2, Some(&parsed.ents))
+                .unwrap()
+                .unwrap();
+
+        assert_eq!((width, height), (2, 2));
+        assert_eq!(
+            rgba,
+            vec![
+                255, 255, 255, 255, // index 3: white
+                255, 0, 0, 255, // index 0: red
+                0, 255, 0, 255, // index 1: green
+                0, 0, 255, 255, // index 2: blue
+            ]
+        );
+    }
+
+    #[test]
+    fn test_surface_buffer_sized_for_format() {
+        let cases = [
+            (SurfaceFormat::Xrgb32, 10 * 4 * 3),
+            (SurfaceFormat::Argb32, 10 * 4 * 3),
+            (SurfaceFormat::Rgb565, 10 * 2 * 3),
+            (SurfaceFormat::Rgb555, 10 * 2 * 3),
+            (SurfaceFormat::A8, 10 * 3),
+            // 10 bits round up to 2 bytes per row
+            (SurfaceFormat::A1, 2 * 3),
+        ];
+
+        for (format, size) in cases {
+            let surface = DisplaySurface::new(10, 3, format);
+            assert_eq!(surface.data.len(), size, "{format:?}");
+            assert_eq!(surface.stride() * 3, size, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn test_fill_rect_on_16bpp_surface() {
+        let mut surface = DisplaySurface::new(4, 2, SurfaceFormat::Rgb565);
+        let rect = SpiceRect {
+            left: 1,
+            top: 0,
+            right: 3,
+            bottom: 1,
+        };
+
+        surface.fill_rect(&rect, 0x00FF0000, SPICE_ROPD_OP_PUT, None);
+
+        // Pure red packs to 0xF800 and expands back exactly
+        assert_eq!(&surface.data[2..4], &0xF800u16.to_le_bytes());
+        assert_eq!(surface.pixel(1, 0), Some([255, 0, 0, 255]));
+        assert_eq!(surface.pixel(0, 0), Some([0, 0, 0, 255]));
+        assert_eq!(surface.pixel(1, 1), Some([0, 0, 0, 255]));
+        assert_eq!(surface.pixel(4, 0), None);
+        assert_eq!(surface.to_rgba().len(), 4 * 2 * 4);
+    }
+
+    #[test]
+    fn test_blit_respects_surface_format() {
+        let image = [10, 20, 30, 40, 50, 60, 70, 80];
+        let area = SpiceRect {
+            left: 0,
+            top: 0,
+            right: 2,
+            bottom: 1,
+        };
+
+        let mut rgba = DisplaySurface::new(2, 1, SurfaceFormat::Argb32);
+        rgba.blit_rgba(&image, 2, 1, &area, &area, false);
+        assert_eq!(rgba.data, image);
+
+        let mut alpha = DisplaySurface::new(2, 1, SurfaceFormat::A8);
+        alpha.blit_rgba(&image, 2, 1, &area, &area, false);
+        assert_eq!(alpha.data, vec![40, 80]);
+    }
+
+    #[test]
+    fn test_to_pixel_format_from_same_surface() {
+        let rgba_pixels = [10, 20, 30, 255, 40, 50, 60, 128];
+        let mut surface = DisplaySurface::new(2, 1, SurfaceFormat::Argb32);
+        surface.data.copy_from_slice(&rgba_pixels);
+
+        let rgba = surface.to_pixel_format(PixelFormat::Rgba8);
+        assert!(matches!(rgba, Cow::Borrowed(_)));
+        assert_eq!(*rgba, rgba_pixels);
+        let bgra = surface.to_pixel_format(PixelFormat::Bgra8);
+        assert_eq!(*bgra, [30, 20, 10, 255, 60, 50, 40, 128]);
+
+        // Kept as BGRA, the surface hands out BGRA without converting
+        surface.convert_pixel_format(PixelFormat::Bgra8);
+        let bgra = surface.to_pixel_format(PixelFormat::Bgra8);
+        assert!(matches!(bgra, Cow::Borrowed(_)));
+        assert_eq!(*bgra, [30, 20, 10, 255, 60, 50, 40, 128]);
+        assert_eq!(*surface.to_rgba(), rgba_pixels);
+    }
+}
@@ -17,8 +17,17 @@ impl DisplayChannel {
 
     /// Renders the display to a canvas element
     pub async fn render_to_canvas(&self, canvas_manager: &mut CanvasManager) -> Result<()> {
-        // Render each surface to its corresponding canvas
-        for (surface_id, surface) in self.get_surfaces() {
+        // Render each visible surface to its corresponding canvas; off-screen
+        // surfaces only show up once the server copies them to one of these
+        let primary = self.primary_surface_id();
+        let visible = self.get_surfaces().iter().filter(|(surface_id, _)| {
+            primary == Some(**surface_id)
+                || self
+                    .get_monitors()
+                    .iter()
+                    .any(|head| head.surface_id == **surface_id)
+        });
+        for (surface_id, surface) in visible {
             canvas_manager.get_or_create_canvas(*surface_id, surface.width, surface.height)?;
             canvas_manager.update_canvas(*surface_id, surface)?;
         }
@@ -32,7 +41,7 @@ impl DisplayChannel {
     /// Renders a specific region to canvas (optimized for partial updates)
     pub async fn render_region_to_canvas(
         &self,
-        canvas_manager: &CanvasManager,
+        canvas_manager: &mut CanvasManager,
         surface_id: u32,
         rect: &SpiceRect,
     ) -> Result<()> {
@@ -67,6 +76,12 @@ impl WasmDisplayManager {
         }
     }
 
+    /// Draw with the 2D canvas API even when WebGL2 is available, for
+    /// debugging. Only affects canvases created after the call.
+    pub fn set_force_2d(&mut self, force_2d: bool) {
+        self.canvas_manager.set_force_2d(force_2d);
+    }
+
     /// Handles a display update from the channel
     pub async fn handle_display_update(&mut self, channel: &DisplayChannel) -> Result<()> {
         // Skip frame if performance is poor
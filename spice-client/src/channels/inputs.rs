@@ -1,8 +1,13 @@
 //! Inputs channel implementation for keyboard and mouse events
 
-use crate::channels::{Channel, ChannelConnection, InputEvent, KeyCode, MouseButton};
-use crate::error::Result;
+use crate::channels::keymap::{KeyboardLayout, KeymapProvider};
+#[cfg(target_arch = "wasm32")]
+use crate::channels::ConnectOptions;
+use crate::channels::{Channel, ChannelConnection, InputEvent, KeyCode, MouseButton, QosGate};
+use crate::error::{Result, SpiceError};
 use crate::protocol::*;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
 use tracing::{debug, info, warn};
 
 /// Mouse operation mode
@@ -17,6 +22,132 @@ pub struct InputsChannel {
     pub(crate) connection: ChannelConnection,
     mouse_mode: MouseMode,
     modifiers: KeyModifiers,
+    keyboard_layout: KeyboardLayout,
+    /// Used instead of `keyboard_layout` when set
+    keymap: Option<Arc<dyn KeymapProvider>>,
+    pointer_mapping: PointerMapping,
+    buttons_state: u32,
+    motion_count: u32,
+    pending_motion: Option<PendingMotion>,
+    input_tx: mpsc::UnboundedSender<InputCommand>,
+    input_rx: mpsc::UnboundedReceiver<InputCommand>,
+}
+
+/// Input to send on an inputs channel whose event loop is running.
+#[derive(Debug)]
+pub enum InputCommand {
+    Event(InputEvent),
+    KeyDown(u32),
+    KeyUp(u32),
+    MouseMotion {
+        x: i32,
+        y: i32,
+    },
+    MousePosition {
+        x: i32,
+        y: i32,
+    },
+    MouseButton {
+        button: MouseButton,
+        pressed: bool,
+    },
+    PointerSizes {
+        widget_size: (u32, u32),
+        display_size: (u32, u32),
+    },
+    /// Acknowledged once everything queued before it has been written
+    Flush(oneshot::Sender<()>),
+}
+
+/// Handle for queueing input on an [`InputsChannel`] while its event loop
+/// owns the channel.
+///
+/// Queued input is written in order, ahead of any server message the loop
+/// hasn't started reading yet.
+#[derive(Debug, Clone)]
+pub struct InputQueue {
+    channel_id: u8,
+    tx: mpsc::UnboundedSender<InputCommand>,
+}
+
+impl InputQueue {
+    /// Queue input for the event loop; fails once the loop has ended
+    pub fn send(&self, command: InputCommand) -> Result<()> {
+        self.tx.send(command).map_err(|_| self.closed())
+    }
+
+    /// Wait until all input queued so far has been written to the server
+    pub async fn flush(&self) -> Result<()> {
+        let (done_tx, done_rx) = oneshot::channel();
+        self.send(InputCommand::Flush(done_tx))?;
+        done_rx.await.map_err(|_| self.closed())
+    }
+
+    fn closed(&self) -> SpiceError {
+        SpiceError::Protocol(format!("Inputs channel {} is closed", self.channel_id))
+    }
+}
+
+/// Pointer update that later ones can be folded into before it's sent, as
+/// while waiting for a motion ack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PendingMotion {
+    /// Relative motion; deltas accumulate so no movement is lost
+    Motion { dx: i32, dy: i32 },
+    /// Absolute position in guest coordinates; only the latest is kept
+    Position { x: u32, y: u32 },
+}
+
+impl PendingMotion {
+    /// Fold `next` into this update, or `None` if they are different kinds
+    fn coalesce(self, next: PendingMotion) -> Option<PendingMotion> {
+        match (self, next) {
+            (
+                PendingMotion::Motion { dx, dy },
+                PendingMotion::Motion {
+                    dx: next_dx,
+                    dy: next_dy,
+                },
+            ) => Some(PendingMotion::Motion {
+                dx: dx.saturating_add(next_dx),
+                dy: dy.saturating_add(next_dy),
+            }),
+            (PendingMotion::Position { .. }, PendingMotion::Position { .. }) => Some(next),
+            _ => None,
+        }
+    }
+}
+
+/// Maps pointer coordinates from the frontend widget onto the guest display.
+///
+/// The widget showing the guest is usually scaled, so its coordinates have to
+/// be rescaled to the guest surface before being sent as absolute positions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PointerMapping {
+    /// Size of the widget the coordinates come from
+    pub widget_size: Option<(u32, u32)>,
+    /// Size of the guest's primary surface
+    pub display_size: Option<(u32, u32)>,
+}
+
+impl PointerMapping {
+    /// Scale widget coordinates to guest coordinates, clamped to the guest
+    /// surface. Coordinates pass through unscaled until both sizes are known.
+    pub fn map(&self, x: i32, y: i32) -> (u32, u32) {
+        let x = x.max(0) as u64;
+        let y = y.max(0) as u64;
+
+        match (self.widget_size, self.display_size) {
+            (Some((widget_w, widget_h)), Some((display_w, display_h)))
+                if widget_w > 0 && widget_h > 0 && display_w > 0 && display_h > 0 =>
+            {
+                let guest_x = (x * display_w as u64 / widget_w as u64).min(display_w as u64 - 1);
+                let guest_y = (y * display_h as u64 / widget_h as u64).min(display_h as u64 - 1);
+                (guest_x as u32, guest_y as u32)
+            }
+            _ => (x as u32, y as u32),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -38,18 +169,22 @@ impl InputsChannel {
         channel_id: u8,
         connection_id: Option<u32>,
     ) -> Result<Self> {
-        let mut connection =
+        let connection =
             ChannelConnection::new(host, port, ChannelType::Inputs, channel_id).await?;
+        Self::from_connection(connection, connection_id).await
+    }
+
+    /// Link an already opened `connection` as an inputs channel
+    pub(crate) async fn from_connection(
+        mut connection: ChannelConnection,
+        connection_id: Option<u32>,
+    ) -> Result<Self> {
         if let Some(conn_id) = connection_id {
             connection.set_connection_id(conn_id);
         }
         connection.handshake().await?;
 
-        Ok(Self {
-            connection,
-            mouse_mode: MouseMode::Server,
-            modifiers: KeyModifiers::default(),
-        })
+        Ok(Self::from_linked(connection))
     }
 
     #[cfg(target_arch = "wasm32")]
@@ -72,11 +207,7 @@ impl InputsChannel {
         .await?;
         connection.handshake().await?;
 
-        Ok(Self {
-            connection,
-            mouse_mode: MouseMode::Server,
-            modifiers: KeyModifiers::default(),
-        })
+        Ok(Self::from_linked(connection))
     }
 
     #[cfg(target_arch = "wasm32")]
@@ -86,12 +217,14 @@ impl InputsChannel {
         auth_token: Option<String>,
         password: Option<String>,
         connection_id: Option<u32>,
+        options: ConnectOptions,
     ) -> Result<Self> {
-        let mut connection = ChannelConnection::new_websocket_with_auth(
+        let mut connection = ChannelConnection::new_websocket_with_options(
             websocket_url,
             ChannelType::Inputs,
             channel_id,
             auth_token,
+            options,
         )
         .await?;
         if let Some(pwd) = password {
@@ -102,11 +235,24 @@ impl InputsChannel {
         }
         connection.handshake().await?;
 
-        Ok(Self {
+        Ok(Self::from_linked(connection))
+    }
+
+    fn from_linked(connection: ChannelConnection) -> Self {
+        let (input_tx, input_rx) = mpsc::unbounded_channel();
+        Self {
             connection,
             mouse_mode: MouseMode::Server,
             modifiers: KeyModifiers::default(),
-        })
+            keyboard_layout: KeyboardLayout::default(),
+            keymap: None,
+            pointer_mapping: PointerMapping::default(),
+            buttons_state: 0,
+            motion_count: 0,
+            pending_motion: None,
+            input_tx,
+            input_rx,
+        }
     }
 
     pub async fn initialize(&mut self) -> Result<()> {
@@ -122,20 +268,83 @@ impl InputsChannel {
         self.modifiers
     }
 
+    pub fn keyboard_layout(&self) -> KeyboardLayout {
+        self.keyboard_layout
+    }
+
+    /// Priority gate shared with the client's other channels, so acks are
+    /// handled ahead of display data
+    pub fn set_qos_gate(&mut self, gate: Option<QosGate>) {
+        self.connection.set_qos_gate(gate);
+    }
+
+    /// Set the guest's keyboard layout, used to map `KeyCode::Char` to scancodes
+    pub fn set_keyboard_layout(&mut self, layout: KeyboardLayout) {
+        self.keyboard_layout = layout;
+    }
+
+    /// Translate key events with `keymap` instead of the keyboard layout, or
+    /// go back to the layout with `None`
+    pub fn set_keymap_provider(&mut self, keymap: Option<Arc<dyn KeymapProvider>>) {
+        self.keymap = keymap;
+    }
+
+    /// What key events are translated with: the provider if one is set,
+    /// otherwise the keyboard layout
+    fn keymap(&self) -> &dyn KeymapProvider {
+        match &self.keymap {
+            Some(keymap) => keymap.as_ref(),
+            None => &self.keyboard_layout,
+        }
+    }
+
+    pub fn set_mouse_mode(&mut self, mode: MouseMode) {
+        self.mouse_mode = mode;
+    }
+
+    /// Set the size of the guest's primary surface, as reported by the
+    /// display channel. Absolute pointer positions are scaled to it.
+    pub fn set_display_size(&mut self, width: u32, height: u32) {
+        self.pointer_mapping.display_size = Some((width, height));
+    }
+
+    /// Set the size of the widget pointer coordinates are reported in
+    pub fn set_widget_size(&mut self, width: u32, height: u32) {
+        self.pointer_mapping.widget_size = Some((width, height));
+    }
+
+    pub fn pointer_mapping(&self) -> PointerMapping {
+        self.pointer_mapping
+    }
+
     /// Sends an input event to the server
     pub async fn send_event(&mut self, event: InputEvent) -> Result<()> {
         match event {
-            InputEvent::KeyDown(key) => {
-                let scancode = key_to_scancode(key);
-                self.update_modifiers(&key, true);
-                self.send_key_down(scancode).await?
-            }
-            InputEvent::KeyUp(key) => {
-                let scancode = key_to_scancode(key);
-                self.update_modifiers(&key, false);
-                self.send_key_up(scancode).await?
+            InputEvent::KeyDown(key) | InputEvent::KeyUp(key) => {
+                let pressed = matches!(event, InputEvent::KeyDown(_));
+                let scancode = key_to_scancode(key, self.keymap());
+                if scancode == 0 {
+                    if self.keymap.is_some() {
+                        warn!("No scancode for {:?} in keymap, dropping key event", key);
+                    } else {
+                        warn!(
+                            "No scancode for {:?} on {:?} layout, dropping key event",
+                            key, self.keyboard_layout
+                        );
+                    }
+                    return Ok(());
+                }
+                self.update_modifiers(&key, pressed);
+                if pressed {
+                    self.send_key_down(scancode).await?
+                } else {
+                    self.send_key_up(scancode).await?
+                }
             }
-            InputEvent::MouseMove { x, y } => self.send_mouse_motion(x, y).await?,
+            InputEvent::MouseMove { x, y } => match self.mouse_mode {
+                MouseMode::Client => self.send_mouse_position(x, y).await?,
+                MouseMode::Server => self.send_mouse_motion(x, y).await?,
+            },
             InputEvent::MouseButton { button, pressed } => {
                 self.send_mouse_button(button, pressed).await?
             }
@@ -167,8 +376,41 @@ impl InputsChannel {
         Ok(())
     }
 
+    /// Number of motion messages sent that the server hasn't acked yet
+    pub fn unacked_motion_count(&self) -> u32 {
+        self.motion_count
+    }
+
+    /// The server allows two bunches of motion messages in flight; past that
+    /// motion is held back until it acks.
+    fn motion_window_full(&self) -> bool {
+        self.motion_count >= SPICE_INPUT_MOTION_ACK_BUNCH * 2
+    }
+
     /// Sends a mouse motion event
     pub async fn send_mouse_motion(&mut self, x: i32, y: i32) -> Result<()> {
+        self.send_pointer_update(PendingMotion::Motion { dx: x, dy: y })
+            .await
+    }
+
+    /// Write a pointer update, or hold it back while the motion window is
+    /// full
+    async fn send_pointer_update(&mut self, update: PendingMotion) -> Result<()> {
+        if self.motion_window_full() {
+            self.pending_motion = Some(match self.pending_motion {
+                Some(held) => held.coalesce(update).unwrap_or(update),
+                None => update,
+            });
+            debug!("Motion window full, holding back {:?}", update);
+            return Ok(());
+        }
+        match update {
+            PendingMotion::Motion { dx, dy } => self.write_mouse_motion(dx, dy).await,
+            PendingMotion::Position { x, y } => self.write_mouse_position(x, y).await,
+        }
+    }
+
+    async fn write_mouse_motion(&mut self, x: i32, y: i32) -> Result<()> {
         let mut data = Vec::new();
         data.extend_from_slice(&x.to_le_bytes());
         data.extend_from_slice(&y.to_le_bytes());
@@ -177,10 +419,55 @@ impl InputsChannel {
         self.connection
             .send_message(SPICE_MSG_INPUTS_MOUSE_MOTION, &data)
             .await?;
+        self.motion_count += 1;
         debug!("Sent mouse motion: ({}, {})", x, y);
         Ok(())
     }
 
+    /// Sends an absolute pointer position given in widget coordinates,
+    /// scaled to the guest display
+    pub async fn send_mouse_position(&mut self, x: i32, y: i32) -> Result<()> {
+        let (guest_x, guest_y) = self.pointer_mapping.map(x, y);
+        debug!(
+            "Mouse position: ({}, {}) -> ({}, {})",
+            x, y, guest_x, guest_y
+        );
+        self.send_pointer_update(PendingMotion::Position {
+            x: guest_x,
+            y: guest_y,
+        })
+        .await
+    }
+
+    async fn write_mouse_position(&mut self, x: u32, y: u32) -> Result<()> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&x.to_le_bytes());
+        data.extend_from_slice(&y.to_le_bytes());
+        data.extend_from_slice(&self.buttons_state.to_le_bytes());
+        data.push(0); // display id of the primary display
+
+        self.connection
+            .send_message(SPICE_MSG_INPUTS_MOUSE_POSITION, &data)
+            .await?;
+        self.motion_count += 1;
+        debug!("Sent mouse position: ({}, {})", x, y);
+        Ok(())
+    }
+
+    /// The server processed a bunch of motion messages; release held-back
+    /// motion
+    async fn handle_motion_ack(&mut self) -> Result<()> {
+        self.motion_count = self
+            .motion_count
+            .saturating_sub(SPICE_INPUT_MOTION_ACK_BUNCH);
+
+        match self.pending_motion.take() {
+            Some(PendingMotion::Motion { dx, dy }) => self.write_mouse_motion(dx, dy).await,
+            Some(PendingMotion::Position { x, y }) => self.write_mouse_position(x, y).await,
+            None => Ok(()),
+        }
+    }
+
     /// Sends a mouse button event
     pub async fn send_mouse_button(&mut self, button: MouseButton, pressed: bool) -> Result<()> {
         let button_mask = match button {
@@ -197,6 +484,12 @@ impl InputsChannel {
             SPICE_MSG_INPUTS_MOUSE_RELEASE
         };
 
+        if pressed {
+            self.buttons_state |= button_mask;
+        } else {
+            self.buttons_state &= !button_mask;
+        }
+
         let mut data = Vec::new();
         data.extend_from_slice(&button_mask.to_le_bytes());
 
@@ -225,13 +518,135 @@ impl InputsChannel {
         }
     }
 
+    /// Handle for queueing input while [`run`](Self::run) owns the channel
+    pub fn input_queue(&self) -> InputQueue {
+        InputQueue {
+            channel_id: self.connection.channel_id,
+            tx: self.input_tx.clone(),
+        }
+    }
+
+    /// Write all queued input to the server now
+    pub async fn flush_input(&mut self) -> Result<()> {
+        let mut commands = Vec::new();
+        while let Ok(command) = self.input_rx.try_recv() {
+            commands.push(command);
+        }
+        self.apply_inputs(commands).await
+    }
+
+    /// Apply `commands` in order, sending pointer motion in a row as one
+    /// message: relative motion adds up and only the last position counts.
+    /// Anything else, such as a button press, ends the run, so nothing is
+    /// reordered around it.
+    async fn apply_inputs(&mut self, commands: Vec<InputCommand>) -> Result<()> {
+        let mut motion: Option<PendingMotion> = None;
+        for command in commands {
+            if let Some(update) = self.pointer_update(&command) {
+                motion = Some(match motion {
+                    Some(held) => match held.coalesce(update) {
+                        Some(merged) => merged,
+                        None => {
+                            self.send_pointer_update(held).await?;
+                            update
+                        }
+                    },
+                    None => update,
+                });
+                continue;
+            }
+            if let Some(held) = motion.take() {
+                self.send_pointer_update(held).await?;
+            }
+            self.apply_input(command).await?;
+        }
+        if let Some(held) = motion {
+            self.send_pointer_update(held).await?;
+        }
+        Ok(())
+    }
+
+    /// The pointer update `command` makes, if all it does is move the pointer
+    fn pointer_update(&self, command: &InputCommand) -> Option<PendingMotion> {
+        let (x, y, absolute) = match *command {
+            InputCommand::MouseMotion { x, y } => (x, y, false),
+            InputCommand::MousePosition { x, y } => (x, y, true),
+            InputCommand::Event(InputEvent::MouseMove { x, y }) => {
+                (x, y, self.mouse_mode == MouseMode::Client)
+            }
+            _ => return None,
+        };
+        Some(if absolute {
+            let (x, y) = self.pointer_mapping.map(x, y);
+            PendingMotion::Position { x, y }
+        } else {
+            PendingMotion::Motion { dx: x, dy: y }
+        })
+    }
+
+    pub(crate) async fn apply_input(&mut self, command: InputCommand) -> Result<()> {
+        match command {
+            InputCommand::Event(event) => self.send_event(event).await,
+            InputCommand::KeyDown(scancode) => self.send_key_down(scancode).await,
+            InputCommand::KeyUp(scancode) => self.send_key_up(scancode).await,
+            InputCommand::MouseMotion { x, y } => self.send_mouse_motion(x, y).await,
+            InputCommand::MousePosition { x, y } => self.send_mouse_position(x, y).await,
+            InputCommand::MouseButton { button, pressed } => {
+                self.send_mouse_button(button, pressed).await
+            }
+            InputCommand::PointerSizes {
+                widget_size,
+                display_size,
+            } => {
+                self.set_widget_size(widget_size.0, widget_size.1);
+                self.set_display_size(display_size.0, display_size.1);
+                Ok(())
+            }
+            InputCommand::Flush(done) => {
+                let _ = done.send(());
+                Ok(())
+            }
+        }
+    }
+
+    /// Run the channel until the connection fails.
+    ///
+    /// Queued input always goes first: the loop only starts reading a server
+    /// message when the input queue is empty, and a message is only read once
+    /// its first bytes have arrived, so input never waits on an idle server.
+    /// Pointer motion is collected for a turn of the event loop, or until
+    /// the next animation frame in the browser, and sent once.
     pub async fn run(&mut self) -> Result<()> {
         loop {
-            let (header, data) = self.connection.read_message().await?;
-            self.handle_message(&header, &data).await?;
+            self.flush_input().await?;
+            tokio::select! {
+                biased;
+                Some(command) = self.input_rx.recv() => {
+                    if self.pointer_update(&command).is_some() {
+                        crate::utils::next_frame().await;
+                    }
+                    let mut commands = vec![command];
+                    while let Ok(command) = self.input_rx.try_recv() {
+                        commands.push(command);
+                    }
+                    self.apply_inputs(commands).await?;
+                }
+                readable = self.connection.wait_readable() => {
+                    readable?;
+                    self.process_next_message().await?;
+                }
+            }
         }
     }
 
+    /// Process a single message from the server
+    /// This is primarily for testing purposes
+    pub async fn process_next_message(&mut self) -> Result<()> {
+        let (header, data) = self.connection.read_message().await?;
+        let result = self.handle_message(&header, &data).await;
+        self.connection.check_message_result(&header, result)
+    }
+
     async fn handle_init_message(&mut self, data: &[u8]) -> Result<()> {
         if data.len() >= 2 {
             let modifiers = u16::from_le_bytes([data[0], data[1]]);
@@ -260,17 +675,21 @@ impl InputsChannel {
 
 impl Channel for InputsChannel {
     async fn handle_message(&mut self, header: &SpiceDataHeader, data: &[u8]) -> Result<()> {
-        match header.msg_type {
-            SPICE_MSG_INPUTS_INIT => {
+        match InputsChannelMessage::try_from(header.msg_type) {
+            Ok(InputsChannelMessage::Init) => {
                 debug!("Received inputs init");
                 self.handle_init_message(data).await?;
             }
-            SPICE_MSG_INPUTS_KEY_MODIFIERS => {
+            Ok(InputsChannelMessage::KeyModifiers) => {
                 debug!("Received key modifiers");
                 self.handle_modifiers_message(data).await?;
             }
-            _ => {
-                warn!("Unknown inputs message type: {}", header.msg_type);
+            Ok(InputsChannelMessage::MouseMotionAck) => {
+                debug!("Received mouse motion ack");
+                self.handle_motion_ack().await?;
+            }
+            Err(msg_type) => {
+                warn!("Unknown inputs message type: {}", msg_type);
             }
         }
 
@@ -285,6 +704,10 @@ impl Channel for InputsChannel {
 // Inputs channel message types
 pub const SPICE_MSG_INPUTS_INIT: u16 = 101;
 pub const SPICE_MSG_INPUTS_KEY_MODIFIERS: u16 = 102;
+pub const SPICE_MSG_INPUTS_MOUSE_MOTION_ACK: u16 = 111;
+
+/// The server acks motion messages in bunches of this size
+pub const SPICE_INPUT_MOTION_ACK_BUNCH: u32 = 4;
 
 // Client to server messages
 pub const SPICE_MSG_INPUTS_KEY_DOWN: u16 = 103;
@@ -306,90 +729,9 @@ pub const SPICE_KEYBOARD_MODIFIER_SHIFT: u16 = 1 << 0;
 pub const SPICE_KEYBOARD_MODIFIER_CTRL: u16 = 1 << 1;
 pub const SPICE_KEYBOARD_MODIFIER_ALT: u16 = 1 << 2;
 
-/// Converts a KeyCode to a PC scancode
-fn key_to_scancode(key: KeyCode) -> u32 {
-    match key {
-        KeyCode::Escape => 0x01,
-        KeyCode::Enter => 0x1C,
-        KeyCode::Space => 0x39,
-        KeyCode::Tab => 0x0F,
-        KeyCode::Backspace => 0x0E,
-        KeyCode::Function(n) => match n {
-            1 => 0x3B,
-            2 => 0x3C,
-            3 => 0x3D,
-            4 => 0x3E,
-            5 => 0x3F,
-            6 => 0x40,
-            7 => 0x41,
-            8 => 0x42,
-            9 => 0x43,
-            10 => 0x44,
-            11 => 0x57,
-            12 => 0x58,
-            _ => 0x00,
-        },
-        KeyCode::ArrowUp => 0x48,
-        KeyCode::ArrowDown => 0x50,
-        KeyCode::ArrowLeft => 0x4B,
-        KeyCode::ArrowRight => 0x4D,
-        KeyCode::Char(c) => char_to_scancode(c),
-        KeyCode::Other(scancode) => scancode,
-    }
-}
-
-/// Converts a character to a PC scancode
-fn char_to_scancode(c: char) -> u32 {
-    match c.to_ascii_uppercase() {
-        'A' => 0x1E,
-        'B' => 0x30,
-        'C' => 0x2E,
-        'D' => 0x20,
-        'E' => 0x12,
-        'F' => 0x21,
-        'G' => 0x22,
-        'H' => 0x23,
-        'I' => 0x17,
-        'J' => 0x24,
-        'K' => 0x25,
-        'L' => 0x26,
-        'M' => 0x32,
-        'N' => 0x31,
-        'O' => 0x18,
-        'P' => 0x19,
-        'Q' => 0x10,
-        'R' => 0x13,
-        'S' => 0x1F,
-        'T' => 0x14,
-        'U' => 0x16,
-        'V' => 0x2F,
-        'W' => 0x11,
-        'X' => 0x2D,
-        'Y' => 0x15,
-        'Z' => 0x2C,
-        '1' => 0x02,
-        '2' => 0x03,
-        '3' => 0x04,
-        '4' => 0x05,
-        '5' => 0x06,
-        '6' => 0x07,
-        '7' => 0x08,
-        '8' => 0x09,
-        '9' => 0x0A,
-        '0' => 0x0B,
-        '-' => 0x0C,
-        '=' => 0x0D,
-        '[' => 0x1A,
-        ']' => 0x1B,
-        ';' => 0x27,
-        '\'' => 0x28,
-        ',' => 0x33,
-        '.' => 0x34,
-        '/' => 0x35,
-        '\\' => 0x2B,
-        '`' => 0x29,
-        _ => 0x00, // Unknown
-    }
+/// Converts a KeyCode to a PC scancode, or 0 if the keymap has no key for it
+fn key_to_scancode(key: KeyCode, keymap: &dyn KeymapProvider) -> u32 {
+    keymap.scancode(&key).unwrap_or(0)
 }
 
 #[cfg(test)]
@@ -398,12 +740,68 @@ mod tests {
 
     #[test]
     fn test_key_to_scancode() {
-        assert_eq!(key_to_scancode(KeyCode::Escape), 0x01);
-        assert_eq!(key_to_scancode(KeyCode::Enter), 0x1C);
-        assert_eq!(key_to_scancode(KeyCode::Space), 0x39);
-        assert_eq!(key_to_scancode(KeyCode::Char('A')), 0x1E);
-        assert_eq!(key_to_scancode(KeyCode::Char('a')), 0x1E);
-        assert_eq!(key_to_scancode(KeyCode::Other(0x42)), 0x42);
+        let us = &KeyboardLayout::Us;
+        assert_eq!(key_to_scancode(KeyCode::Escape, us), 0x01);
+        assert_eq!(key_to_scancode(KeyCode::Enter, us), 0x1C);
+        assert_eq!(key_to_scancode(KeyCode::Space, us), 0x39);
+        assert_eq!(key_to_scancode(KeyCode::Char('A'), us), 0x1E);
+        assert_eq!(key_to_scancode(KeyCode::Char('a'), us), 0x1E);
+        assert_eq!(key_to_scancode(KeyCode::Other(0x42), us), 0x42);
+    }
+
+    #[test]
+    fn test_key_to_scancode_follows_layout() {
+        assert_eq!(
+            key_to_scancode(KeyCode::Char('A'), &KeyboardLayout::Fr),
+            0x10
+        );
+        assert_eq!(
+            key_to_scancode(KeyCode::Char('Z'), &KeyboardLayout::De),
+            0x15
+        );
+        assert_eq!(key_to_scancode(KeyCode::Char('A'), &KeyboardLayout::Raw), 0);
+
+        // Keys with a fixed position are the same on every layout
+        for layout in [KeyboardLayout::Uk, KeyboardLayout::Raw] {
+            assert_eq!(key_to_scancode(KeyCode::Enter, &layout), 0x1C);
+            assert_eq!(key_to_scancode(KeyCode::Other(0x56), &layout), 0x56);
+        }
+    }
+
+    /// Dvorak, where 'q' is typed with the key QWERTY puts 'x' on
+    struct Dvorak;
+
+    impl KeymapProvider for Dvorak {
+        fn scancode(&self, key: &KeyCode) -> Option<u32> {
+            match key {
+                KeyCode::Char('q') => Some(0x2D),
+                KeyCode::Char(_) => None,
+                key => crate::channels::keymap::fixed_scancode(key),
+            }
+        }
+    }
+
+    #[test]
+    fn test_key_to_scancode_asks_keymap_provider() {
+        assert_eq!(key_to_scancode(KeyCode::Char('q'), &Dvorak), 0x2D);
+        assert_eq!(key_to_scancode(KeyCode::Char('w'), &Dvorak), 0);
+        assert_eq!(key_to_scancode(KeyCode::Tab, &Dvorak), 0x0F);
+        assert_eq!(key_to_scancode(KeyCode::Function(13), &Dvorak), 0);
+    }
+
+    #[test]
+    fn test_pointer_mapping_scales_to_guest() {
+        let mut mapping = PointerMapping::default();
+        assert_eq!(mapping.map(100, 50), (100, 50));
+
+        // A widget twice the size of the guest surface
+        mapping.widget_size = Some((2048, 1536));
+        mapping.display_size = Some((1024, 768));
+        assert_eq!(mapping.map(1024, 768), (512, 384));
+        assert_eq!(mapping.map(0, 0), (0, 0));
+
+        // Out-of-widget coordinates are clamped to the guest surface
+        assert_eq!(mapping.map(-10, 5000), (0, 767));
     }
 
     #[test]
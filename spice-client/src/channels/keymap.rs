@@ -0,0 +1,303 @@
+//! Character to scancode tables for the guest's keyboard layout
+//!
+//! SPICE sends PC/AT scancodes, i.e. physical key positions. To type a
+//! character the client has to know which key produces it on the guest's
+//! layout. Only the key is looked up; modifiers such as Shift or AltGr are
+//! left to the caller.
+//!
+//! Applications whose layouts aren't covered here, or that know which
+//! physical key was pressed (e.g. from a browser's `KeyboardEvent.code`),
+//! can supply their own [`KeymapProvider`].
+
+use crate::channels::KeyCode;
+
+/// Translates key events into the scancodes sent to the guest.
+///
+/// The inputs channel asks its provider for every key press and release.
+/// [`KeyboardLayout`] is the built-in provider, US unless configured
+/// otherwise.
+pub trait KeymapProvider: Send + Sync {
+    /// Scancode of the key that produces `key`, or `None` to drop the event
+    fn scancode(&self, key: &KeyCode) -> Option<u32>;
+}
+
+/// Scancode of keys that sit in the same place on every layout, i.e.
+/// everything but [`KeyCode::Char`], for providers to fall back on
+pub fn fixed_scancode(key: &KeyCode) -> Option<u32> {
+    let scancode = match key {
+        KeyCode::Escape => 0x01,
+        KeyCode::Enter => 0x1C,
+        KeyCode::Space => 0x39,
+        KeyCode::Tab => 0x0F,
+        KeyCode::Backspace => 0x0E,
+        KeyCode::Function(n) => match n {
+            1 => 0x3B,
+            2 => 0x3C,
+            3 => 0x3D,
+            4 => 0x3E,
+            5 => 0x3F,
+            6 => 0x40,
+            7 => 0x41,
+            8 => 0x42,
+            9 => 0x43,
+            10 => 0x44,
+            11 => 0x57,
+            12 => 0x58,
+            _ => return None,
+        },
+        KeyCode::ArrowUp => 0x48,
+        KeyCode::ArrowDown => 0x50,
+        KeyCode::ArrowLeft => 0x4B,
+        KeyCode::ArrowRight => 0x4D,
+        KeyCode::Char(_) => return None,
+        KeyCode::Other(scancode) => *scancode,
+    };
+    Some(scancode)
+}
+
+/// Keyboard layout configured in the guest, used to translate
+/// [`KeyCode::Char`](crate::channels::KeyCode::Char) into scancodes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyboardLayout {
+    /// US QWERTY
+    #[default]
+    Us,
+    /// UK QWERTY
+    Uk,
+    /// German QWERTZ
+    De,
+    /// French AZERTY
+    Fr,
+    /// No character translation. Only keys with a fixed scancode and
+    /// [`KeyCode::Other`](crate::channels::KeyCode::Other) are sent, for
+    /// frontends that forward hardware scancodes.
+    Raw,
+}
+
+impl KeyboardLayout {
+    /// Scancode of the key that produces `c` on this layout, if any
+    pub fn char_to_scancode(self, c: char) -> Option<u32> {
+        let c = c.to_lowercase().next().unwrap_or(c);
+        match self {
+            KeyboardLayout::Us => us_scancode(c),
+            KeyboardLayout::Uk => uk_scancode(c).or_else(|| us_scancode(c)),
+            KeyboardLayout::De => de_scancode(c).or_else(|| letter_or_digit_scancode(c)),
+            KeyboardLayout::Fr => fr_scancode(c).or_else(|| letter_or_digit_scancode(c)),
+            KeyboardLayout::Raw => None,
+        }
+    }
+}
+
+impl KeymapProvider for KeyboardLayout {
+    fn scancode(&self, key: &KeyCode) -> Option<u32> {
+        match key {
+            KeyCode::Char(c) => self.char_to_scancode(*c),
+            key => fixed_scancode(key),
+        }
+    }
+}
+
+/// Letters and digits by their position on a US keyboard
+fn letter_or_digit_scancode(c: char) -> Option<u32> {
+    let scancode = match c {
+        'a' => 0x1E,
+        'b' => 0x30,
+        'c' => 0x2E,
+        'd' => 0x20,
+        'e' => 0x12,
+        'f' => 0x21,
+        'g' => 0x22,
+        'h' => 0x23,
+        'i' => 0x17,
+        'j' => 0x24,
+        'k' => 0x25,
+        'l' => 0x26,
+        'm' => 0x32,
+        'n' => 0x31,
+        'o' => 0x18,
+        'p' => 0x19,
+        'q' => 0x10,
+        'r' => 0x13,
+        's' => 0x1F,
+        't' => 0x14,
+        'u' => 0x16,
+        'v' => 0x2F,
+        'w' => 0x11,
+        'x' => 0x2D,
+        'y' => 0x15,
+        'z' => 0x2C,
+        '1' => 0x02,
+        '2' => 0x03,
+        '3' => 0x04,
+        '4' => 0x05,
+        '5' => 0x06,
+        '6' => 0x07,
+        '7' => 0x08,
+        '8' => 0x09,
+        '9' => 0x0A,
+        '0' => 0x0B,
+        ' ' => 0x39,
+        _ => return None,
+    };
+    Some(scancode)
+}
+
+fn us_scancode(c: char) -> Option<u32> {
+    let scancode = match c {
+        '!' => 0x02,
+        '@' => 0x03,
+        '#' => 0x04,
+        '$' => 0x05,
+        '%' => 0x06,
+        '^' => 0x07,
+        '&' => 0x08,
+        '*' => 0x09,
+        '(' => 0x0A,
+        ')' => 0x0B,
+        '-' | '_' => 0x0C,
+        '=' | '+' => 0x0D,
+        '[' | '{' => 0x1A,
+        ']' | '}' => 0x1B,
+        ';' | ':' => 0x27,
+        '\'' | '"' => 0x28,
+        '`' | '~' => 0x29,
+        '\\' | '|' => 0x2B,
+        ',' | '<' => 0x33,
+        '.' | '>' => 0x34,
+        '/' | '?' => 0x35,
+        _ => return letter_or_digit_scancode(c),
+    };
+    Some(scancode)
+}
+
+/// Keys that differ from the US layout
+fn uk_scancode(c: char) -> Option<u32> {
+    let scancode = match c {
+        '"' => 0x03,
+        '£' => 0x04,
+        '@' => 0x28,
+        '#' | '~' => 0x2B,
+        '`' | '¬' => 0x29,
+        '\\' | '|' => 0x56,
+        _ => return None,
+    };
+    Some(scancode)
+}
+
+fn de_scancode(c: char) -> Option<u32> {
+    let scancode = match c {
+        'y' => 0x2C,
+        'z' => 0x15,
+        '!' => 0x02,
+        '"' => 0x03,
+        '§' => 0x04,
+        '$' => 0x05,
+        '%' => 0x06,
+        '&' => 0x07,
+        '/' | '{' => 0x08,
+        '(' | '[' => 0x09,
+        ')' | ']' => 0x0A,
+        '=' | '}' => 0x0B,
+        'ß' | '?' | '\\' => 0x0C,
+        '´' | '`' => 0x0D,
+        '@' => 0x10,
+        '€' => 0x12,
+        'ü' => 0x1A,
+        '+' | '*' | '~' => 0x1B,
+        'ö' => 0x27,
+        'ä' => 0x28,
+        '^' | '°' => 0x29,
+        '#' | '\'' => 0x2B,
+        ',' | ';' => 0x33,
+        '.' | ':' => 0x34,
+        '-' | '_' => 0x35,
+        '<' | '>' | '|' => 0x56,
+        _ => return None,
+    };
+    Some(scancode)
+}
+
+fn fr_scancode(c: char) -> Option<u32> {
+    let scancode = match c {
+        'a' => 0x10,
+        'z' => 0x11,
+        'q' => 0x1E,
+        'm' => 0x27,
+        'w' => 0x2C,
+        '&' => 0x02,
+        'é' | '~' => 0x03,
+        '"' | '#' => 0x04,
+        '\'' | '{' => 0x05,
+        '(' | '[' => 0x06,
+        '-' | '|' => 0x07,
+        'è' | '`' => 0x08,
+        '_' | '\\' => 0x09,
+        'ç' | '^' => 0x0A,
+        'à' | '@' => 0x0B,
+        ')' | '°' | ']' => 0x0C,
+        '=' | '+' | '}' => 0x0D,
+        '€' => 0x12,
+        '¨' => 0x1A,
+        '$' | '£' => 0x1B,
+        'ù' | '%' => 0x28,
+        '²' => 0x29,
+        '*' | 'µ' => 0x2B,
+        ',' | '?' => 0x32,
+        ';' | '.' => 0x33,
+        ':' | '/' => 0x34,
+        '!' | '§' => 0x35,
+        '<' | '>' => 0x56,
+        _ => return None,
+    };
+    Some(scancode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_us_layout() {
+        let us = KeyboardLayout::Us;
+        assert_eq!(us.char_to_scancode('A'), Some(0x1E));
+        assert_eq!(us.char_to_scancode('a'), Some(0x1E));
+        assert_eq!(us.char_to_scancode('@'), Some(0x03));
+        assert_eq!(us.char_to_scancode('\\'), Some(0x2B));
+        assert_eq!(us.char_to_scancode('é'), None);
+    }
+
+    #[test]
+    fn test_uk_layout_diverges_from_us() {
+        let uk = KeyboardLayout::Uk;
+        assert_eq!(uk.char_to_scancode('@'), Some(0x28));
+        assert_eq!(uk.char_to_scancode('"'), Some(0x03));
+        assert_eq!(uk.char_to_scancode('\\'), Some(0x56));
+        assert_eq!(uk.char_to_scancode('Q'), Some(0x10));
+    }
+
+    #[test]
+    fn test_de_layout_swaps_y_and_z() {
+        let de = KeyboardLayout::De;
+        assert_eq!(de.char_to_scancode('Z'), Some(0x15));
+        assert_eq!(de.char_to_scancode('y'), Some(0x2C));
+        assert_eq!(de.char_to_scancode('ß'), Some(0x0C));
+        assert_eq!(de.char_to_scancode('Ü'), Some(0x1A));
+        assert_eq!(de.char_to_scancode('-'), Some(0x35));
+    }
+
+    #[test]
+    fn test_fr_layout_is_azerty() {
+        let fr = KeyboardLayout::Fr;
+        assert_eq!(fr.char_to_scancode('A'), Some(0x10));
+        assert_eq!(fr.char_to_scancode('q'), Some(0x1E));
+        assert_eq!(fr.char_to_scancode('M'), Some(0x27));
+        assert_eq!(fr.char_to_scancode('é'), Some(0x03));
+        assert_eq!(fr.char_to_scancode('1'), Some(0x02));
+        assert_eq!(fr.char_to_scancode(','), Some(0x32));
+    }
+
+    #[test]
+    fn test_raw_layout_does_not_translate_characters() {
+        assert_eq!(KeyboardLayout::Raw.char_to_scancode('a'), None);
+    }
+}
@@ -1,24 +1,109 @@
-use crate::channels::{Channel, ChannelConnection};
+#[cfg(target_arch = "wasm32")]
+use crate::channels::ConnectOptions;
+use crate::channels::{Channel, ChannelConnection, MediaClock};
 use crate::error::{Result, SpiceError};
 use crate::protocol::*;
 use crate::utils::sleep;
 use binrw::BinRead;
 use instant::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
 use tracing::{debug, error, info, warn};
 
+/// Information about the SPICE server learned while connecting.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerInfo {
+    /// Protocol major version from the server's link reply
+    pub major_version: u32,
+    /// Protocol minor version from the server's link reply
+    pub minor_version: u32,
+    /// Session id assigned by the server in SPICE_MSG_MAIN_INIT
+    pub session_id: Option<u32>,
+    /// VM name from SPICE_MSG_MAIN_NAME, if the server sent one
+    pub name: Option<String>,
+    /// VM UUID from SPICE_MSG_MAIN_UUID, if the server sent one
+    pub uuid: Option<String>,
+}
+
+/// A guest display head, as the guest agent reports it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonitorInfo {
+    /// Head number; head `n` is shown by display channel `n`
+    pub index: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Position of the head on the guest desktop
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Main channel state changes that consumers may need to react to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MainEvent {
+    /// The guest agent connected; clipboard sharing and resizing are available
+    AgentConnected,
+    /// The guest agent went away
+    AgentDisconnected,
+    /// The guest agent reported its display heads
+    GuestMonitors(Vec<MonitorInfo>),
+    /// A channel listed by the server could not be connected; the session
+    /// continues without it
+    ChannelUnavailable {
+        channel_type: ChannelType,
+        channel_id: u8,
+    },
+    /// The server sent a notification (SPICE_MSG_NOTIFY) meant for the user,
+    /// such as a warning that the host disk is full
+    ServerNotify {
+        severity: NotifySeverity,
+        visibility: NotifyVisibility,
+        message: String,
+    },
+    /// The connection to the server was lost without `disconnect` being
+    /// called. The session is over until the client connects again.
+    Disconnected { reason: String },
+}
+
 pub struct MainChannel {
-    connection: ChannelConnection,
+    pub(crate) connection: ChannelConnection,
     session_id: Option<u32>,
+    server_name: Option<String>,
+    server_uuid: Option<[u8; 16]>,
+    agent_connected: Arc<AtomicBool>,
+    guest_monitors: Arc<RwLock<Vec<MonitorInfo>>>,
+    agent_messages: AgentMessageBuffer,
+    media_clock: MediaClock,
+    event_callback: Option<Box<dyn Fn(&MainEvent) + Send + Sync>>,
 }
 
 impl MainChannel {
     pub async fn new(host: &str, port: u16) -> Result<Self> {
-        let mut connection = ChannelConnection::new(host, port, ChannelType::Main, 0).await?;
+        let connection = ChannelConnection::new(host, port, ChannelType::Main, 0).await?;
+        Self::from_connection(connection).await
+    }
+
+    /// Connect the main channel over a Unix domain socket
+    #[cfg(unix)]
+    pub async fn new_unix(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let connection = ChannelConnection::new_unix(path, ChannelType::Main, 0).await?;
+        Self::from_connection(connection).await
+    }
+
+    /// Link an already opened `connection` as the main channel
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) async fn from_connection(mut connection: ChannelConnection) -> Result<Self> {
         connection.handshake().await?;
 
         Ok(Self {
             connection,
             session_id: None,
+            server_name: None,
+            server_uuid: None,
+            agent_connected: Arc::new(AtomicBool::new(false)),
+            guest_monitors: Arc::new(RwLock::new(Vec::new())),
+            agent_messages: AgentMessageBuffer::default(),
+            media_clock: MediaClock::new(),
+            event_callback: None,
         })
     }
 
@@ -44,6 +129,13 @@ impl MainChannel {
         Ok(Self {
             connection,
             session_id: None,
+            server_name: None,
+            server_uuid: None,
+            agent_connected: Arc::new(AtomicBool::new(false)),
+            guest_monitors: Arc::new(RwLock::new(Vec::new())),
+            agent_messages: AgentMessageBuffer::default(),
+            media_clock: MediaClock::new(),
+            event_callback: None,
         })
     }
 
@@ -52,12 +144,14 @@ impl MainChannel {
         websocket_url: &str,
         auth_token: Option<String>,
         password: Option<String>,
+        options: ConnectOptions,
     ) -> Result<Self> {
-        let mut connection = ChannelConnection::new_websocket_with_auth(
+        let mut connection = ChannelConnection::new_websocket_with_options(
             websocket_url,
             ChannelType::Main,
             0,
             auth_token,
+            options,
         )
         .await?;
         if let Some(password) = password {
@@ -68,6 +162,13 @@ impl MainChannel {
         Ok(Self {
             connection,
             session_id: None,
+            server_name: None,
+            server_uuid: None,
+            agent_connected: Arc::new(AtomicBool::new(false)),
+            guest_monitors: Arc::new(RwLock::new(Vec::new())),
+            agent_messages: AgentMessageBuffer::default(),
+            media_clock: MediaClock::new(),
+            event_callback: None,
         })
     }
 
@@ -75,6 +176,104 @@ impl MainChannel {
         self.session_id
     }
 
+    /// Negotiated protocol version and the server details received so far
+    pub fn server_info(&self) -> ServerInfo {
+        let (major_version, minor_version) = self.connection.server_version().unwrap_or_default();
+        ServerInfo {
+            major_version,
+            minor_version,
+            session_id: self.session_id,
+            name: self.server_name.clone(),
+            uuid: self.server_uuid.map(|uuid| format_uuid(&uuid)),
+        }
+    }
+
+    /// Whether the guest agent (spice-vdagent) is connected
+    pub fn is_agent_connected(&self) -> bool {
+        self.agent_connected.load(Ordering::SeqCst)
+    }
+
+    /// Shared agent state, so clients can check it while the channel runs
+    pub(crate) fn agent_connected_flag(&self) -> Arc<AtomicBool> {
+        self.agent_connected.clone()
+    }
+
+    /// Display heads the guest agent reported last; empty until it has
+    pub fn guest_monitors(&self) -> Vec<MonitorInfo> {
+        self.guest_monitors
+            .read()
+            .map(|monitors| monitors.clone())
+            .unwrap_or_default()
+    }
+
+    /// Shared guest monitors, so clients can read them while the channel runs
+    pub(crate) fn guest_monitors_handle(&self) -> Arc<RwLock<Vec<MonitorInfo>>> {
+        self.guest_monitors.clone()
+    }
+
+    /// The server's multimedia clock, for scheduling audio and video
+    pub fn media_clock(&self) -> MediaClock {
+        self.media_clock.clone()
+    }
+
+    /// Return errors from malformed messages instead of skipping them
+    pub fn set_strict(&mut self, strict: bool) {
+        self.connection.set_strict(strict);
+    }
+
+    pub fn set_event_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(&MainEvent) + Send + Sync + 'static,
+    {
+        self.event_callback = Some(Box::new(callback));
+    }
+
+    fn set_agent_connected(&mut self, connected: bool) {
+        if self.agent_connected.swap(connected, Ordering::SeqCst) == connected {
+            return;
+        }
+        // A restarted agent starts a fresh message stream
+        self.agent_messages.clear();
+
+        let event = if connected {
+            MainEvent::AgentConnected
+        } else {
+            MainEvent::AgentDisconnected
+        };
+        if let Some(ref callback) = self.event_callback {
+            callback(&event);
+        }
+    }
+
+    fn handle_agent_message(&mut self, agent_data: &SpiceMsgMainAgentData) -> Result<()> {
+        if agent_data.protocol != VD_AGENT_PROTOCOL {
+            warn!("Ignoring agent message of protocol {}", agent_data.protocol);
+            return Ok(());
+        }
+        if agent_data.type_ != VD_AGENT_MONITORS_CONFIG {
+            // TODO: Process other agent data (clipboard, file transfer, etc.)
+            return Ok(());
+        }
+
+        let monitors = parse_agent_monitors(&agent_data.data)?;
+        info!("Guest agent reported {} monitors", monitors.len());
+        if let Ok(mut slot) = self.guest_monitors.write() {
+            *slot = monitors.clone();
+        }
+        if let Some(ref callback) = self.event_callback {
+            callback(&MainEvent::GuestMonitors(monitors));
+        }
+        Ok(())
+    }
+
+    /// Process a single message from the server
+    /// This is primarily for testing purposes
+    pub async fn process_next_message(&mut self) -> Result<()> {
+        let (header, data) = self.connection.read_message().await?;
+        let result = self.handle_message(&header, &data).await;
+        self.connection.check_message_result(&header, result)
+    }
+
     pub async fn send_attach_channels(&mut self) -> Result<()> {
         // ATTACH_CHANNELS message has no data - it just tells the server
         // to start sending data on all connected channels
@@ -109,15 +308,17 @@ impl MainChannel {
                         "Received server message: type={}, size={}",
                         header.msg_type, header.msg_size
                     );
-                    if header.msg_type == MainChannelMessage::Init as u16 {
+                    if header.msg_type == u16::from(MainChannelMessage::Init) {
                         info!("Received SpiceMsgMainInit from server");
-                        self.handle_message(&header, &data).await?;
+                        let result = self.handle_message(&header, &data).await;
+                        self.connection.check_message_result(&header, result)?;
                     } else {
                         info!(
                             "Unexpected first message type: {}, handling anyway",
                             header.msg_type
                         );
-                        self.handle_message(&header, &data).await?;
+                        let result = self.handle_message(&header, &data).await;
+                        self.connection.check_message_result(&header, result)?;
                     }
                 }
                 Some(Err(e)) => {
@@ -148,15 +349,17 @@ impl MainChannel {
                         "Received server message: type={}, size={}",
                         header.msg_type, header.msg_size
                     );
-                    if header.msg_type == MainChannelMessage::Init as u16 {
+                    if header.msg_type == u16::from(MainChannelMessage::Init) {
                         info!("Received SpiceMsgMainInit from server");
-                        self.handle_message(&header, &data).await?;
+                        let result = self.handle_message(&header, &data).await;
+                        self.connection.check_message_result(&header, result)?;
                     } else {
                         info!(
                             "Unexpected first message type: {}, handling anyway",
                             header.msg_type
                         );
-                        self.handle_message(&header, &data).await?;
+                        let result = self.handle_message(&header, &data).await;
+                        self.connection.check_message_result(&header, result)?;
                     }
                 }
                 Ok(Err(e)) => {
@@ -195,10 +398,11 @@ impl MainChannel {
                     );
 
                     // Handle the message
-                    self.handle_message(&header, &data).await?;
+                    let result = self.handle_message(&header, &data).await;
+                    self.connection.check_message_result(&header, result)?;
 
                     // If it was the init message, we're done
-                    if header.msg_type == MainChannelMessage::Init as u16 {
+                    if header.msg_type == u16::from(MainChannelMessage::Init) {
                         info!("Successfully received SPICE_MSG_MAIN_INIT");
                         return Ok(());
                     }
@@ -228,7 +432,7 @@ impl MainChannel {
         while start_time.elapsed() < timeout {
             match self.connection.read_message().await {
                 Ok((header, data)) => {
-                    if header.msg_type == MainChannelMessage::ChannelsList as u16 {
+                    if header.msg_type == u16::from(MainChannelMessage::ChannelsList) {
                         info!("Received SPICE_MSG_MAIN_CHANNELS_LIST");
 
                         // Parse the channels list
@@ -255,7 +459,8 @@ impl MainChannel {
                         return Ok(channels);
                     } else {
                         // Handle other messages while waiting
-                        self.handle_message(&header, &data).await?;
+                        let result = self.handle_message(&header, &data).await;
+                        self.connection.check_message_result(&header, result)?;
                     }
                 }
                 Err(e) => {
@@ -280,7 +485,8 @@ impl MainChannel {
     pub async fn run(&mut self) -> Result<()> {
         loop {
             let (header, data) = self.connection.read_message().await?;
-            self.handle_message(&header, &data).await?;
+            let result = self.handle_message(&header, &data).await;
+            self.connection.check_message_result(&header, result)?;
         }
     }
 }
@@ -292,9 +498,9 @@ impl Channel for MainChannel {
             header.serial, header.msg_type, header.msg_size, header.sub_list
         );
         // Handle common messages first
-        if header.msg_type < 100 {
-            match header.msg_type {
-                x if x == crate::protocol::SPICE_MSG_PING => {
+        if let Ok(common) = CommonMessage::try_from(header.msg_type) {
+            match common {
+                CommonMessage::Ping => {
                     debug!(
                         "Received SPICE_MSG_PING with {} bytes, sending PONG",
                         data.len()
@@ -315,34 +521,62 @@ impl Channel for MainChannel {
                         data
                     };
                     self.connection
-                        .send_message(crate::protocol::SPICE_MSGC_PONG, pong_data)
+                        .send_message(SPICE_MSGC_PONG, pong_data)
                         .await?;
                     return Ok(());
                 }
-                x if x == crate::protocol::SPICE_MSG_SET_ACK => {
+                CommonMessage::SetAck => {
                     debug!("Received SPICE_MSG_SET_ACK");
                     // TODO: Implement acknowledgment flow control
                     return Ok(());
                 }
-                x if x == crate::protocol::SPICE_MSG_NOTIFY => {
-                    debug!("Received SPICE_MSG_NOTIFY");
-                    // TODO: Handle notification
+                CommonMessage::Notify => {
+                    let mut cursor = std::io::Cursor::new(data);
+                    let notify = SpiceMsgMainNotify::read(&mut cursor).map_err(|e| {
+                        SpiceError::Protocol(format!("Failed to parse Notify: {e}"))
+                    })?;
+                    let message = String::from_utf8_lossy(&notify.message).into_owned();
+                    let severity = NotifySeverity::try_from(notify.severity);
+                    match severity {
+                        Ok(NotifySeverity::Info) => info!("Server info: {}", message),
+                        Ok(NotifySeverity::Warn) => warn!("Server warning: {}", message),
+                        Ok(NotifySeverity::Error) => error!("Server error: {}", message),
+                        Err(_) => debug!(
+                            "Server notification (severity {}): {}",
+                            notify.severity, message
+                        ),
+                    }
+                    if let (Ok(severity), Some(callback)) = (severity, &self.event_callback) {
+                        callback(&MainEvent::ServerNotify {
+                            severity,
+                            visibility: NotifyVisibility::try_from(notify.visibility)
+                                .unwrap_or(NotifyVisibility::Low),
+                            message,
+                        });
+                    }
                     return Ok(());
                 }
-                x if x == crate::protocol::SPICE_MSG_DISCONNECTING => {
+                CommonMessage::Disconnecting => {
                     info!("Server sent SPICE_MSG_DISCONNECTING");
                     return Err(SpiceError::ConnectionClosed);
                 }
-                _ => {
-                    warn!("Unknown common message type: {}", header.msg_type);
+                other => {
+                    warn!("Unhandled common message: {:?}", other);
                     return Ok(());
                 }
             }
         }
 
         // Handle main channel specific messages
-        match header.msg_type {
-            x if x == MainChannelMessage::Init as u16 => {
+        let msg = match MainChannelMessage::try_from(header.msg_type) {
+            Ok(msg) => msg,
+            Err(msg_type) => {
+                warn!("Unknown message type: {}", msg_type);
+                return Ok(());
+            }
+        };
+        match msg {
+            MainChannelMessage::Init => {
                 // First log the raw data
                 info!(
                     "Raw SPICE_MSG_MAIN_INIT data ({} bytes): {:?}",
@@ -386,6 +620,8 @@ impl Channel for MainChannel {
 
                 // Store the session_id for use by other channels
                 self.session_id = Some(init_msg.session_id);
+                self.set_agent_connected(init_msg.agent_connected != 0);
+                self.media_clock.update(init_msg.multi_media_time);
 
                 // NOTE: The debug server rejects SPICE_MSGC_MAIN_CLIENT_INFO (type 101)
                 // with "invalid message type". This might be because:
@@ -400,52 +636,55 @@ impl Channel for MainChannel {
                 // TODO: Investigate why server rejects these messages
                 // Possibly the message type numbers are channel-specific offsets?
             }
-            x if x == MainChannelMessage::ChannelsList as u16 => {
+            MainChannelMessage::ChannelsList => {
                 debug!("Received channels list");
                 // Already handled in handle_channels_list_message
             }
-            x if x == MainChannelMessage::MouseMode as u16 => {
+            MainChannelMessage::MouseMode => {
                 let mut cursor = std::io::Cursor::new(data);
                 let mouse_mode = SpiceMsgMainMouseMode::read(&mut cursor)
                     .map_err(|e| SpiceError::Protocol(format!("Failed to parse MouseMode: {e}")))?;
                 info!("Mouse mode changed to: {}", mouse_mode.mode);
                 // TODO: Store mouse mode and notify input handling
             }
-            x if x == MainChannelMessage::MultiMediaTime as u16 => {
+            MainChannelMessage::MultiMediaTime => {
                 let mut cursor = std::io::Cursor::new(data);
                 let mm_time = SpiceMsgMainMultiMediaTime::read(&mut cursor).map_err(|e| {
                     SpiceError::Protocol(format!("Failed to parse MultiMediaTime: {e}"))
                 })?;
                 debug!("Multimedia time: {}", mm_time.time);
-                // TODO: Synchronize with multimedia time
+                self.media_clock.update(mm_time.time);
             }
-            x if x == MainChannelMessage::AgentConnected as u16 => {
-                let mut cursor = std::io::Cursor::new(data);
-                let agent_connected =
-                    SpiceMsgMainAgentConnected::read(&mut cursor).map_err(|e| {
-                        SpiceError::Protocol(format!("Failed to parse AgentConnected: {e}"))
-                    })?;
-                info!(
-                    "Agent connected with error code: {}",
-                    agent_connected.error_code
-                );
-                // TODO: Initialize agent communication
+            MainChannelMessage::AgentConnected => {
+                info!("Agent connected");
+                self.set_agent_connected(true);
             }
-            x if x == MainChannelMessage::AgentDisconnected as u16 => {
-                info!("Agent disconnected");
-                // TODO: Clean up agent state
+            MainChannelMessage::AgentConnectedTokens => {
+                let mut cursor = std::io::Cursor::new(data);
+                let agent_tokens = SpiceMsgMainAgentTokens::read(&mut cursor).map_err(|e| {
+                    SpiceError::Protocol(format!("Failed to parse AgentConnectedTokens: {e}"))
+                })?;
+                info!("Agent connected with {} tokens", agent_tokens.num_tokens);
+                self.set_agent_connected(true);
             }
-            x if x == MainChannelMessage::AgentData as u16 => {
+            MainChannelMessage::AgentDisconnected => {
                 let mut cursor = std::io::Cursor::new(data);
-                let agent_data = SpiceMsgMainAgentData::read(&mut cursor)
-                    .map_err(|e| SpiceError::Protocol(format!("Failed to parse AgentData: {e}")))?;
-                debug!(
-                    "Received agent data: protocol {}, type {}, size {}",
-                    agent_data.protocol, agent_data.type_, agent_data.size
-                );
-                // TODO: Process agent data (clipboard, file transfer, etc.)
+                match SpiceMsgMainAgentConnected::read(&mut cursor) {
+                    Ok(msg) => info!("Agent disconnected with error code: {}", msg.error_code),
+                    Err(_) => info!("Agent disconnected"),
+                }
+                self.set_agent_connected(false);
             }
-            x if x == MainChannelMessage::AgentToken as u16 => {
+            MainChannelMessage::AgentData => {
+                for agent_data in self.agent_messages.push(data)? {
+                    debug!(
+                        "Received agent data: protocol {}, type {}, size {}",
+                        agent_data.protocol, agent_data.type_, agent_data.size
+                    );
+                    self.handle_agent_message(&agent_data)?;
+                }
+            }
+            MainChannelMessage::AgentToken => {
                 let mut cursor = std::io::Cursor::new(data);
                 let agent_tokens = SpiceMsgMainAgentTokens::read(&mut cursor).map_err(|e| {
                     SpiceError::Protocol(format!("Failed to parse AgentTokens: {e}"))
@@ -453,27 +692,25 @@ impl Channel for MainChannel {
                 debug!("Agent tokens: {}", agent_tokens.num_tokens);
                 // TODO: Update agent token count for flow control
             }
-            x if x == SPICE_MSG_NOTIFY => {
+            MainChannelMessage::Name => {
                 let mut cursor = std::io::Cursor::new(data);
-                let notify = SpiceMsgMainNotify::read(&mut cursor)
-                    .map_err(|e| SpiceError::Protocol(format!("Failed to parse Notify: {e}")))?;
-                let message = String::from_utf8_lossy(&notify.message);
-                match notify.severity {
-                    0 => info!("Server info: {}", message),
-                    1 => warn!("Server warning: {}", message),
-                    2 => error!("Server error: {}", message),
-                    _ => debug!(
-                        "Server notification (severity {}): {}",
-                        notify.severity, message
-                    ),
-                }
+                let name = SpiceMsgMainName::read(&mut cursor)
+                    .map_err(|e| SpiceError::Protocol(format!("Failed to parse Name: {e}")))?;
+                let name = String::from_utf8_lossy(&name.name)
+                    .trim_end_matches('\0')
+                    .to_string();
+                info!("Server VM name: {}", name);
+                self.server_name = Some(name);
             }
-            x if x == SPICE_MSG_DISCONNECTING => {
-                info!("Server is disconnecting");
-                return Err(SpiceError::ConnectionClosed);
+            MainChannelMessage::Uuid => {
+                let mut cursor = std::io::Cursor::new(data);
+                let uuid = SpiceMsgMainUuid::read(&mut cursor)
+                    .map_err(|e| SpiceError::Protocol(format!("Failed to parse Uuid: {e}")))?;
+                info!("Server VM uuid: {}", format_uuid(&uuid.uuid));
+                self.server_uuid = Some(uuid.uuid);
             }
-            _ => {
-                warn!("Unknown message type: {}", header.msg_type);
+            other => {
+                debug!("Unhandled main message {:?}", other);
             }
         }
 
@@ -484,3 +721,212 @@ impl Channel for MainChannel {
         ChannelType::Main
     }
 }
+
+/// Size of the header at the start of each agent message
+const VD_AGENT_HEADER_SIZE: usize = 20;
+
+/// Largest agent message accepted, so a corrupt size can't make the buffer
+/// grow without bound
+const MAX_AGENT_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Reassembles agent messages from AgentData frames.
+///
+/// The server forwards the agent's byte stream in chunks. Only the first
+/// chunk of a message starts with its header, whose size covers the data in
+/// the chunks that follow; one frame can also end one message and start the
+/// next.
+#[derive(Debug, Default)]
+pub(crate) struct AgentMessageBuffer {
+    pending: Vec<u8>,
+}
+
+impl AgentMessageBuffer {
+    /// Add the body of an AgentData frame, returning the messages it completes
+    pub(crate) fn push(&mut self, frame: &[u8]) -> Result<Vec<SpiceMsgMainAgentData>> {
+        self.pending.extend_from_slice(frame);
+
+        let mut messages = Vec::new();
+        while self.pending.len() >= VD_AGENT_HEADER_SIZE {
+            let size = u32::from_le_bytes(self.pending[16..20].try_into().unwrap()) as usize;
+            if size > MAX_AGENT_MESSAGE_SIZE {
+                self.clear();
+                return Err(SpiceError::Protocol(format!(
+                    "Agent message of {size} bytes is too large"
+                )));
+            }
+            let message_len = VD_AGENT_HEADER_SIZE + size;
+            if self.pending.len() < message_len {
+                break;
+            }
+
+            let mut cursor = std::io::Cursor::new(&self.pending[..message_len]);
+            let message = SpiceMsgMainAgentData::read(&mut cursor)
+                .map_err(|e| SpiceError::Protocol(format!("Failed to parse AgentData: {e}")));
+            self.pending.drain(..message_len);
+            messages.push(message?);
+        }
+        Ok(messages)
+    }
+
+    /// Drop a partly received message
+    pub(crate) fn clear(&mut self) {
+        self.pending.clear();
+    }
+}
+
+/// Decode the body of a VD_AGENT_MONITORS_CONFIG agent message
+fn parse_agent_monitors(data: &[u8]) -> Result<Vec<MonitorInfo>> {
+    let mut cursor = std::io::Cursor::new(data);
+    let config = VDAgentMonitorsConfig::read(&mut cursor)
+        .map_err(|e| SpiceError::Protocol(format!("Failed to parse agent MonitorsConfig: {e}")))?;
+    Ok(config
+        .monitors
+        .iter()
+        .zip(0..)
+        .map(|(monitor, index)| MonitorInfo {
+            index,
+            width: monitor.width,
+            height: monitor.height,
+            x: monitor.x,
+            y: monitor.y,
+        })
+        .collect())
+}
+
+/// Add a head for each display channel the agent left out, sized like that
+/// channel's primary surface. `displays` holds the channel id, width and
+/// height of each display channel with a surface. Older agents only report
+/// the heads they manage, which can be fewer than the channels the server
+/// opened; their position on the guest desktop is unknown, so they are
+/// put at the origin.
+pub(crate) fn complete_guest_monitors(
+    mut monitors: Vec<MonitorInfo>,
+    displays: &[(u8, u32, u32)],
+) -> Vec<MonitorInfo> {
+    for &(channel_id, width, height) in displays {
+        let index = u32::from(channel_id);
+        if !monitors.iter().any(|monitor| monitor.index == index) {
+            monitors.push(MonitorInfo {
+                index,
+                width,
+                height,
+                x: 0,
+                y: 0,
+            });
+        }
+    }
+    monitors.sort_by_key(|monitor| monitor.index);
+    monitors
+}
+
+/// Format a UUID in the usual 8-4-4-4-12 hex form
+fn format_uuid(uuid: &[u8; 16]) -> String {
+    let hex: String = uuid.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(index: u32, width: u32, height: u32, x: i32) -> MonitorInfo {
+        MonitorInfo {
+            index,
+            width,
+            height,
+            x,
+            y: 0,
+        }
+    }
+
+    #[test]
+    fn test_missing_heads_come_from_display_channels() {
+        let reported = vec![monitor(0, 1920, 1080, 0)];
+        let displays = [(0, 1024, 768), (1, 1280, 1024), (2, 800, 600)];
+
+        let monitors = complete_guest_monitors(reported, &displays);
+
+        assert_eq!(
+            monitors,
+            vec![
+                monitor(0, 1920, 1080, 0),
+                monitor(1, 1280, 1024, 0),
+                monitor(2, 800, 600, 0),
+            ]
+        );
+    }
+
+    fn encode_agent_message(type_: u32, data: &[u8]) -> Vec<u8> {
+        use binrw::BinWrite;
+
+        let message = SpiceMsgMainAgentData {
+            protocol: VD_AGENT_PROTOCOL,
+            type_,
+            opaque: 0,
+            size: data.len() as u32,
+            data: data.to_vec(),
+        };
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        message.write(&mut bytes).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn test_agent_message_split_over_three_frames() {
+        let data: Vec<u8> = (0..100).collect();
+        let message = encode_agent_message(VD_AGENT_MONITORS_CONFIG, &data);
+        let mut buffer = AgentMessageBuffer::default();
+
+        // The header itself is split too
+        assert!(buffer.push(&message[..12]).unwrap().is_empty());
+        assert!(buffer.push(&message[12..70]).unwrap().is_empty());
+        let messages = buffer.push(&message[70..]).unwrap();
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].type_, VD_AGENT_MONITORS_CONFIG);
+        assert_eq!(messages[0].size, 100);
+        assert_eq!(messages[0].data, data);
+        assert!(buffer.pending.is_empty());
+    }
+
+    #[test]
+    fn test_agent_frame_ending_one_message_and_starting_another() {
+        let first = encode_agent_message(1, b"first");
+        let second = encode_agent_message(2, b"second message");
+        let stream = [first, second].concat();
+        let mut buffer = AgentMessageBuffer::default();
+
+        let messages = buffer.push(&stream[..30]).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data, b"first");
+
+        let messages = buffer.push(&stream[30..]).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].type_, 2);
+        assert_eq!(messages[0].data, b"second message");
+    }
+
+    #[test]
+    fn test_oversized_agent_message_is_rejected() {
+        let mut header = encode_agent_message(1, b"");
+        header[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut buffer = AgentMessageBuffer::default();
+
+        assert!(buffer.push(&header).is_err());
+        assert!(buffer.pending.is_empty());
+    }
+
+    #[test]
+    fn test_reported_heads_are_kept_without_display_channels() {
+        let reported = vec![monitor(0, 1920, 1080, 0), monitor(1, 1280, 1024, 1920)];
+
+        assert_eq!(complete_guest_monitors(reported.clone(), &[]), reported);
+    }
+}
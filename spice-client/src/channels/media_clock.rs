@@ -0,0 +1,102 @@
+//! Server multimedia clock
+//!
+//! The server stamps audio and video stream data with its multimedia time in
+//! milliseconds and periodically sends the current value on the main channel.
+//! Between those updates the clock runs on a local timer so presentation can
+//! be scheduled against it at any moment.
+
+use instant::Instant;
+use std::sync::{Arc, Mutex};
+
+/// Shared handle to the server's multimedia time. Clones see the same clock.
+#[derive(Debug, Clone, Default)]
+pub struct MediaClock {
+    base: Arc<Mutex<Option<(u32, Instant)>>>,
+}
+
+impl MediaClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the clock to a time just received from the server
+    pub fn update(&self, time: u32) {
+        self.update_at(time, Instant::now());
+    }
+
+    /// Current multimedia time in milliseconds, or 0 before the first update
+    pub fn now(&self) -> u32 {
+        self.time_at(Instant::now())
+    }
+
+    /// Whether the server has sent a time yet
+    pub fn is_set(&self) -> bool {
+        self.base.lock().unwrap().is_some()
+    }
+
+    fn update_at(&self, time: u32, at: Instant) {
+        *self.base.lock().unwrap() = Some((time, at));
+    }
+
+    /// Last server time plus the local time elapsed since it arrived. The
+    /// server clock is a wrapping `u32`, so this wraps the same way.
+    fn time_at(&self, at: Instant) -> u32 {
+        match *self.base.lock().unwrap() {
+            Some((time, received)) => {
+                let elapsed = at.saturating_duration_since(received).as_millis();
+                time.wrapping_add(elapsed as u32)
+            }
+            None => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use instant::Duration;
+
+    #[test]
+    fn test_interpolates_between_updates() {
+        let clock = MediaClock::new();
+        let start = Instant::now();
+        assert!(!clock.is_set());
+        assert_eq!(clock.time_at(start), 0);
+
+        clock.update_at(10_000, start);
+        assert_eq!(clock.time_at(start), 10_000);
+        assert_eq!(clock.time_at(start + Duration::from_millis(250)), 10_250);
+        // A read from before the update doesn't run the clock backwards
+        assert_eq!(clock.time_at(start - Duration::from_millis(5)), 10_000);
+
+        // The second update replaces the base; interpolation continues from it
+        let second = start + Duration::from_millis(1_000);
+        clock.update_at(11_020, second);
+        assert_eq!(clock.time_at(second), 11_020);
+        assert_eq!(clock.time_at(second + Duration::from_millis(40)), 11_060);
+
+        let mut last = 0;
+        for step in 0..10 {
+            let time = clock.time_at(second + Duration::from_millis(step * 16));
+            assert!(time >= last);
+            last = time;
+        }
+    }
+
+    #[test]
+    fn test_wraps_with_server_clock() {
+        let clock = MediaClock::new();
+        let start = Instant::now();
+        clock.update_at(u32::MAX - 9, start);
+        assert_eq!(clock.time_at(start + Duration::from_millis(20)), 10);
+    }
+
+    #[test]
+    fn test_clones_share_the_clock() {
+        let clock = MediaClock::new();
+        let handle = clock.clone();
+        clock.update(500);
+        assert!(handle.is_set());
+        assert!(handle.now() >= 500);
+    }
+}
@@ -1,37 +1,62 @@
+pub mod capabilities;
 pub mod connection;
 pub mod cursor;
 pub mod display;
 pub mod inputs;
+pub mod keymap;
 pub mod main;
+pub mod media_clock;
+pub mod qos;
+pub mod state;
 
 #[cfg(target_arch = "wasm32")]
 pub mod display_wasm;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) mod stream;
 
 // Integration tests moved to tests/channel_integration.rs
 
-use crate::error::{Result, SpiceError};
+use crate::error::{AuthFailureReason, Result, SpiceError};
 use crate::protocol::*;
+use instant::{Duration, Instant};
 use rand::rngs::OsRng;
+use rsa::pkcs1::DecodeRsaPublicKey;
 use rsa::pkcs8::DecodePublicKey;
-use rsa::{Oaep, RsaPublicKey};
+use rsa::sha2::Sha256;
+use rsa::{BigUint, Oaep, RsaPublicKey};
 use sha1::Sha1;
 
 #[cfg(not(target_arch = "wasm32"))]
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-#[cfg(not(target_arch = "wasm32"))]
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncWrite};
 
+use std::sync::Arc;
 #[cfg(target_arch = "wasm32")]
-use std::sync::{Arc, Mutex};
+use std::sync::Mutex;
 #[cfg(target_arch = "wasm32")]
 use web_sys::WebSocket;
 
-use tracing::{debug, info, warn};
+use tracing::{debug, info, trace, warn};
 
-pub use cursor::{CursorChannel, CursorShape};
-pub use display::{DisplayChannel, DisplaySurface};
-pub use inputs::{InputsChannel, KeyModifiers, MouseMode};
-pub use main::MainChannel;
+pub use capabilities::{
+    AdvertisedCapabilities, CapabilitySet, ClientFeature, ClientFeatures, ServerCapabilities,
+};
+pub use cursor::{CursorChannel, CursorEvent, CursorShape};
+pub use display::{DisplayChannel, DisplayEvent, DisplaySurface, PixelFormat, RefreshHandle};
+pub use inputs::{
+    InputCommand, InputQueue, InputsChannel, KeyModifiers, MouseMode, PointerMapping,
+};
+pub use keymap::{KeyboardLayout, KeymapProvider};
+pub use main::{MainChannel, MainEvent, MonitorInfo, ServerInfo};
+pub use media_clock::MediaClock;
+pub use qos::QosGate;
+pub use state::ChannelState;
+pub(crate) use state::ChannelStates;
+#[cfg(unix)]
+pub use stream::UnixConnectionFactory;
+#[cfg(not(target_arch = "wasm32"))]
+pub use stream::{
+    ConnectionFactory, IoStream, Stream, TcpConnectionFactory, WebSocketConnectionFactory,
+};
 
 /// Input event types for keyboard and mouse interactions.
 ///
@@ -114,7 +139,8 @@ pub enum KeyCode {
     Backspace,
     /// A character key (letters, digits, symbols).
     ///
-    /// The character should be uppercase for letters.
+    /// Mapped to the key that produces the character on the configured
+    /// [`KeyboardLayout`]. Letters may be given in either case.
     Char(char),
     /// Function keys (F1-F12).
     ///
@@ -150,48 +176,237 @@ pub trait Channel {
 
 pub struct ChannelConnection {
     #[cfg(not(target_arch = "wasm32"))]
-    stream: TcpStream,
+    stream: Stream,
+    /// Opens a fresh stream on reconnect; `None` for a caller-supplied one
+    #[cfg(not(target_arch = "wasm32"))]
+    factory: Option<Arc<dyn ConnectionFactory>>,
     #[cfg(target_arch = "wasm32")]
     websocket: Option<Arc<Mutex<WebSocket>>>,
     #[cfg(target_arch = "wasm32")]
     byte_buffer: Arc<Mutex<Vec<u8>>>,
+    #[cfg(target_arch = "wasm32")]
+    websocket_url: String,
+    #[cfg(target_arch = "wasm32")]
+    auth_token: Option<String>,
+    #[cfg(target_arch = "wasm32")]
+    binary_only: bool,
+    #[cfg(target_arch = "wasm32")]
+    connect_options: ConnectOptions,
     channel_type: ChannelType,
     pub channel_id: u8,
     password: Option<String>,
+    oaep_hash: OaepHash,
+    oaep_fallback: bool,
     connection_id: Option<u32>,
     next_serial: u64,
     handshake_complete: bool,
+    keepalive: Option<Duration>,
+    last_activity: Instant,
+    server_version: Option<(u32, u32)>,
+    server_common_caps: Vec<u32>,
+    server_channel_caps: Vec<u32>,
+    advertised_caps: AdvertisedCapabilities,
+    mini_header: bool,
+    received_serial: u64,
+    strict: bool,
+    trace_hook: Option<TraceHook>,
+    qos_gate: Option<QosGate>,
+    /// Held from reading an interactive message's header until it has been
+    /// handled
+    qos_guard: Option<qos::QosGuard>,
 }
 
-/// Encrypt a password using RSA-OAEP with SHA-1
-fn encrypt_password(password: &str, pub_key_der: &[u8]) -> Result<Vec<u8>> {
-    // The SPICE server sends the public key in SubjectPublicKeyInfo DER format
-    match RsaPublicKey::from_public_key_der(pub_key_der) {
-        Ok(public_key) => {
-            // SPICE uses RSA-OAEP with SHA-1
-            let padding = Oaep::new::<Sha1>();
-            match public_key.encrypt(&mut OsRng, padding, password.as_bytes()) {
-                Ok(encrypted) => Ok(encrypted),
-                Err(e) => Err(SpiceError::Protocol(format!(
-                    "Failed to encrypt password: {}",
-                    e
-                ))),
+/// Direction of bytes passed to a [`TraceHook`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Bytes written to the server.
+    Sent,
+    /// Bytes read from the server.
+    Received,
+}
+
+/// Callback that sees the raw bytes of a channel, for protocol tracing.
+///
+/// Called with the channel type and id, the direction, and the bytes exactly
+/// as they were written to or read from the transport.
+pub type TraceHook = Arc<dyn Fn(ChannelType, u8, Direction, &[u8]) + Send + Sync>;
+
+/// Stage of opening a channel, reported to [`ConnectOptions::progress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectPhase {
+    /// Waiting for the WebSocket to open.
+    Opening,
+    /// Waiting for the proxy to accept the auth token.
+    Authenticating,
+    /// Exchanging link messages with the SPICE server.
+    Linking,
+}
+
+/// Callback told when a channel, given by type and id, enters a new phase.
+pub type ConnectProgress = Arc<dyn Fn(ChannelType, u8, ConnectPhase) + Send + Sync>;
+
+/// Deadlines and progress reporting for opening WebSocket channels.
+#[derive(Clone)]
+pub struct ConnectOptions {
+    /// How long the WebSocket may take to open.
+    pub open_timeout: Duration,
+    /// How long the proxy may take to answer the auth token.
+    pub auth_timeout: Duration,
+    /// Told about each phase, e.g. to show what a spinner is waiting on.
+    pub progress: Option<ConnectProgress>,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            open_timeout: Duration::from_secs(5),
+            auth_timeout: Duration::from_secs(10),
+            progress: None,
+        }
+    }
+}
+
+impl ConnectOptions {
+    fn report(&self, channel_type: ChannelType, channel_id: u8, phase: ConnectPhase) {
+        debug!("{:?} channel {} {:?}", channel_type, channel_id, phase);
+        if let Some(progress) = &self.progress {
+            progress(channel_type, channel_id, phase);
+        }
+    }
+}
+
+/// Call `check` every 50ms until it returns true or `timeout` has passed.
+/// Returns whether it succeeded in time.
+#[cfg(target_arch = "wasm32")]
+async fn poll_until(timeout: Duration, mut check: impl FnMut() -> Result<bool>) -> Result<bool> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if check()? {
+            return Ok(true);
+        }
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        gloo_timers::future::TimeoutFuture::new(50).await;
+    }
+}
+
+/// Whether the proxy has accepted the auth token; an error if it refused it
+#[cfg(target_arch = "wasm32")]
+fn check_auth_response(auth_response: &Mutex<String>) -> Result<bool> {
+    let Ok(auth_buf) = auth_response.lock() else {
+        return Ok(false);
+    };
+    if auth_buf.is_empty() {
+        return Ok(false);
+    }
+    info!("Received auth response: '{}'", auth_buf);
+    if auth_buf.contains("OK") {
+        info!("Authentication successful");
+        Ok(true)
+    } else if auth_buf.contains("Authentication failed") {
+        Err(SpiceError::Protocol(
+            "WebSocket authentication failed".to_string(),
+        ))
+    } else {
+        info!("Unexpected auth response: '{}'", auth_buf);
+        Ok(false)
+    }
+}
+
+/// A message received from a WebSocket proxy
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum WebSocketFrame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl WebSocketFrame {
+    /// Append binary frames to `data`; text frames replace `auth_response`,
+    /// or are dropped when the proxy is `binary_only`
+    fn deliver(self, binary_only: bool, data: &mut Vec<u8>, auth_response: &mut String) {
+        match self {
+            WebSocketFrame::Binary(bytes) => data.extend_from_slice(&bytes),
+            WebSocketFrame::Text(text) if binary_only => {
+                debug!("Dropping {} byte text frame from proxy", text.len());
             }
+            WebSocketFrame::Text(text) => *auth_response = text,
         }
-        Err(e) => {
-            warn!(
-                "Failed to parse RSA public key: {}, trying raw modulus/exponent",
-                e
-            );
-            // The public key might be in a different format, let's try to parse it manually
-            // SPICE sends: error(4) + pubkey(162) + caps...
-            // The pubkey is in SubjectPublicKeyInfo format starting at offset 4
-            Err(SpiceError::Protocol(format!(
-                "Failed to parse RSA public key: {}",
-                e
-            )))
+    }
+}
+
+/// Hash function used for the RSA-OAEP padding of the SPICE ticket.
+///
+/// Upstream spice-server expects SHA-1, but some builds have moved to SHA-256.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OaepHash {
+    /// OAEP with SHA-1 (the SPICE protocol default).
+    #[default]
+    Sha1,
+    /// OAEP with SHA-256.
+    Sha256,
+}
+
+impl OaepHash {
+    /// The other supported hash, used when the server rejects the first attempt.
+    pub fn alternate(self) -> Self {
+        match self {
+            OaepHash::Sha1 => OaepHash::Sha256,
+            OaepHash::Sha256 => OaepHash::Sha1,
         }
     }
+
+    fn padding(self) -> Oaep {
+        match self {
+            OaepHash::Sha1 => Oaep::new::<Sha1>(),
+            OaepHash::Sha256 => Oaep::new::<Sha256>(),
+        }
+    }
+}
+
+/// Size in bytes of the modulus of the 1024-bit key SPICE sends in the link reply
+const RAW_MODULUS_SIZE: usize = 128;
+
+/// Parse the public key sent by the server in the link reply.
+///
+/// The key is normally in SubjectPublicKeyInfo DER format, but PKCS#1 DER and a
+/// raw big-endian modulus followed by the big-endian exponent are accepted too.
+fn parse_public_key(pub_key: &[u8]) -> Result<RsaPublicKey> {
+    let spki_err = match RsaPublicKey::from_public_key_der(pub_key) {
+        Ok(public_key) => return Ok(public_key),
+        Err(e) => e,
+    };
+
+    if let Ok(public_key) = RsaPublicKey::from_pkcs1_der(pub_key) {
+        debug!("Parsed RSA public key in PKCS#1 format");
+        return Ok(public_key);
+    }
+
+    warn!(
+        "Failed to parse RSA public key: {}, trying raw modulus/exponent",
+        spki_err
+    );
+    if pub_key.len() <= RAW_MODULUS_SIZE {
+        return Err(SpiceError::Protocol(format!(
+            "Failed to parse RSA public key: {}",
+            spki_err
+        )));
+    }
+
+    let (modulus, exponent) = pub_key.split_at(RAW_MODULUS_SIZE);
+    RsaPublicKey::new(
+        BigUint::from_bytes_be(modulus),
+        BigUint::from_bytes_be(exponent),
+    )
+    .map_err(|e| SpiceError::Protocol(format!("Failed to parse RSA public key: {}", e)))
+}
+
+/// Encrypt a password using RSA-OAEP with the given hash
+fn encrypt_password(password: &str, pub_key: &[u8], hash: OaepHash) -> Result<Vec<u8>> {
+    let public_key = parse_public_key(pub_key)?;
+    public_key
+        .encrypt(&mut OsRng, hash.padding(), password.as_bytes())
+        .map_err(|e| SpiceError::Protocol(format!("Failed to encrypt password: {}", e)))
 }
 
 impl ChannelConnection {
@@ -202,17 +417,82 @@ impl ChannelConnection {
         channel_type: ChannelType,
         channel_id: u8,
     ) -> Result<Self> {
-        let stream = TcpStream::connect((host, port)).await?;
+        let factory = Arc::new(TcpConnectionFactory::new(host, port));
+        Self::from_factory(factory, channel_type, channel_id).await
+    }
 
-        Ok(Self {
+    /// Connect to a server listening on a Unix domain socket, as QEMU does
+    /// with `-spice unix=on,addr=<path>`
+    #[cfg(unix)]
+    pub async fn new_unix(
+        path: impl AsRef<std::path::Path>,
+        channel_type: ChannelType,
+        channel_id: u8,
+    ) -> Result<Self> {
+        let factory = Arc::new(UnixConnectionFactory::new(path.as_ref()));
+        Self::from_factory(factory, channel_type, channel_id).await
+    }
+
+    /// Run the channel over a stream the caller has already opened, such as
+    /// a forwarded SSH channel or a socket from a proxy. The link handshake
+    /// still has to be done with `handshake`. Failed authentication can't be
+    /// retried, as that needs a fresh connection.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_stream<S>(stream: S, channel_type: ChannelType, channel_id: u8) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    {
+        Self::with_stream(Stream::custom(stream), None, channel_type, channel_id)
+    }
+
+    /// Run the channel over a stream opened by `factory`, which is asked
+    /// again for a fresh one if the channel has to reconnect
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn from_factory(
+        factory: Arc<dyn ConnectionFactory>,
+        channel_type: ChannelType,
+        channel_id: u8,
+    ) -> Result<Self> {
+        let stream = factory.connect(channel_type, channel_id).await?;
+        Ok(Self::with_stream(
+            stream,
+            Some(factory),
+            channel_type,
+            channel_id,
+        ))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn with_stream(
+        stream: Stream,
+        factory: Option<Arc<dyn ConnectionFactory>>,
+        channel_type: ChannelType,
+        channel_id: u8,
+    ) -> Self {
+        Self {
             stream,
+            factory,
             channel_type,
             channel_id,
             password: None,
+            oaep_hash: OaepHash::default(),
+            oaep_fallback: true,
             connection_id: None,
             next_serial: 1,
             handshake_complete: false,
-        })
+            keepalive: None,
+            last_activity: Instant::now(),
+            server_version: None,
+            server_common_caps: Vec::new(),
+            server_channel_caps: Vec::new(),
+            advertised_caps: AdvertisedCapabilities::default(),
+            mini_header: false,
+            received_serial: 0,
+            strict: false,
+            trace_hook: None,
+            qos_gate: None,
+            qos_guard: None,
+        }
     }
 
     #[cfg(target_arch = "wasm32")]
@@ -241,11 +521,75 @@ impl ChannelConnection {
         channel_type: ChannelType,
         channel_id: u8,
         auth_token: Option<String>,
+    ) -> Result<Self> {
+        Self::new_websocket_with_options(
+            websocket_url,
+            channel_type,
+            channel_id,
+            auth_token,
+            ConnectOptions::default(),
+        )
+        .await
+    }
+
+    /// Like `new_websocket_with_auth`, with custom deadlines and progress
+    /// reporting for the connection phases
+    #[cfg(target_arch = "wasm32")]
+    pub async fn new_websocket_with_options(
+        websocket_url: &str,
+        channel_type: ChannelType,
+        channel_id: u8,
+        auth_token: Option<String>,
+        options: ConnectOptions,
+    ) -> Result<Self> {
+        Self::open_websocket(
+            websocket_url,
+            channel_type,
+            channel_id,
+            auth_token,
+            false,
+            options,
+        )
+        .await
+    }
+
+    /// Connect through a WebSocket proxy that only sends binary frames.
+    ///
+    /// The bundled proxy answers the auth token with a text frame, so by
+    /// default text frames are set aside as auth responses. Here they are
+    /// dropped instead, so a proxy that sends stray text can't disturb the
+    /// SPICE stream. No auth token is sent.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn new_websocket_binary_only(
+        websocket_url: &str,
+        channel_type: ChannelType,
+        channel_id: u8,
+    ) -> Result<Self> {
+        Self::open_websocket(
+            websocket_url,
+            channel_type,
+            channel_id,
+            None,
+            true,
+            ConnectOptions::default(),
+        )
+        .await
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    async fn open_websocket(
+        websocket_url: &str,
+        channel_type: ChannelType,
+        channel_id: u8,
+        auth_token: Option<String>,
+        binary_only: bool,
+        options: ConnectOptions,
     ) -> Result<Self> {
         let window = web_sys::window()
             .ok_or_else(|| SpiceError::Protocol("No window object".to_string()))?;
         let websocket = WebSocket::new(websocket_url)
             .map_err(|e| SpiceError::Protocol(format!("Failed to create WebSocket: {:?}", e)))?;
+        let auth_token_for_reconnect = auth_token.clone();
 
         websocket.set_binary_type(web_sys::BinaryType::Arraybuffer);
 
@@ -254,85 +598,58 @@ impl ChannelConnection {
         let auth_response = Arc::new(Mutex::new(String::new()));
         let auth_response_clone = Arc::clone(&auth_response);
 
-        // Set up message handler - handle both text (auth) and binary (SPICE) messages
+        // Set up message handler - text frames carry the auth response,
+        // binary frames SPICE data
         let onmessage_callback = Closure::wrap(Box::new(move |e: MessageEvent| {
-            // Try text message first (for authentication response)
-            if let Ok(text) = e.data().dyn_into::<js_sys::JsString>() {
-                let text_str = text.as_string().unwrap_or_default();
-                if let Ok(mut auth_buf) = auth_response_clone.lock() {
-                    *auth_buf = text_str;
-                }
-            }
-            // Try binary message (for SPICE protocol data)
-            else if let Ok(arraybuffer) = e.data().dyn_into::<ArrayBuffer>() {
+            let frame = if let Ok(text) = e.data().dyn_into::<js_sys::JsString>() {
+                WebSocketFrame::Text(text.as_string().unwrap_or_default())
+            } else if let Ok(arraybuffer) = e.data().dyn_into::<ArrayBuffer>() {
                 let array = Uint8Array::new(&arraybuffer);
                 let mut bytes = vec![0u8; array.length() as usize];
                 array.copy_to(&mut bytes);
+                WebSocketFrame::Binary(bytes)
+            } else {
+                return;
+            };
 
-                if let Ok(mut buffer) = buffer_clone.lock() {
-                    buffer.extend_from_slice(&bytes);
-                }
+            if let (Ok(mut buffer), Ok(mut auth_buf)) =
+                (buffer_clone.lock(), auth_response_clone.lock())
+            {
+                frame.deliver(binary_only, &mut buffer, &mut auth_buf);
             }
         }) as Box<dyn FnMut(_)>);
 
         websocket.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
         onmessage_callback.forget();
 
-        // Wait for connection to open
-        let ready_state_check = || websocket.ready_state() == WebSocket::OPEN;
-
-        // Simple polling for connection open
-        let mut attempts = 0;
-        while !ready_state_check() && attempts < 100 {
-            gloo_timers::future::TimeoutFuture::new(50).await;
-            attempts += 1;
-        }
-
-        if !ready_state_check() {
-            return Err(SpiceError::Protocol(
-                "WebSocket connection timeout".to_string(),
-            ));
+        options.report(channel_type, channel_id, ConnectPhase::Opening);
+        let opened = poll_until(options.open_timeout, || {
+            Ok(websocket.ready_state() == WebSocket::OPEN)
+        })
+        .await?;
+        if !opened {
+            return Err(SpiceError::Protocol(format!(
+                "WebSocket connection timeout after {:?}",
+                options.open_timeout
+            )));
         }
 
         // Send authentication token if provided
         if let Some(token) = auth_token {
+            options.report(channel_type, channel_id, ConnectPhase::Authenticating);
             info!("Sending auth token: {}", token);
             let ws_clone = websocket.clone();
             ws_clone
                 .send_with_str(&token)
                 .map_err(|e| SpiceError::Protocol(format!("Failed to send auth token: {:?}", e)))?;
 
-            // Wait for authentication response
-            let mut attempts = 0;
-            let mut auth_success = false;
-
-            while attempts < 200 && !auth_success {
-                // Increased timeout
-                if let Ok(auth_buf) = auth_response.lock() {
-                    if !auth_buf.is_empty() {
-                        info!("Received auth response: '{}'", auth_buf);
-                        if auth_buf.contains("OK") {
-                            info!("Authentication successful");
-                            auth_success = true;
-                        } else if auth_buf.contains("Authentication failed") {
-                            return Err(SpiceError::Protocol(
-                                "WebSocket authentication failed".to_string(),
-                            ));
-                        } else {
-                            info!("Unexpected auth response: '{}'", auth_buf);
-                        }
-                    }
-                }
-                if !auth_success {
-                    gloo_timers::future::TimeoutFuture::new(50).await;
-                    attempts += 1;
-                }
-            }
-
-            if !auth_success {
-                return Err(SpiceError::Protocol(
-                    "WebSocket authentication timeout".to_string(),
-                ));
+            let authenticated =
+                poll_until(options.auth_timeout, || check_auth_response(&auth_response)).await?;
+            if !authenticated {
+                return Err(SpiceError::Protocol(format!(
+                    "WebSocket authentication timeout after {:?}",
+                    options.auth_timeout
+                )));
             }
 
             // Clear any residual data in the byte buffer after authentication
@@ -347,12 +664,30 @@ impl ChannelConnection {
         Ok(Self {
             websocket: Some(Arc::new(Mutex::new(websocket))),
             byte_buffer,
+            websocket_url: websocket_url.to_string(),
+            auth_token: auth_token_for_reconnect,
+            binary_only,
+            connect_options: options,
             channel_type,
             channel_id,
             password: None,
+            oaep_hash: OaepHash::default(),
+            oaep_fallback: true,
             connection_id: None,
             next_serial: 1,
             handshake_complete: false,
+            keepalive: None,
+            last_activity: Instant::now(),
+            server_version: None,
+            server_common_caps: Vec::new(),
+            server_channel_caps: Vec::new(),
+            advertised_caps: AdvertisedCapabilities::default(),
+            mini_header: false,
+            received_serial: 0,
+            strict: false,
+            trace_hook: None,
+            qos_gate: None,
+            qos_guard: None,
         })
     }
 
@@ -364,6 +699,213 @@ impl ChannelConnection {
         self.connection_id = Some(connection_id);
     }
 
+    /// Set the OAEP hash used to encrypt the ticket (SHA-1 by default).
+    pub fn set_oaep_hash(&mut self, hash: OaepHash) {
+        self.oaep_hash = hash;
+    }
+
+    /// The OAEP hash that will be (or was) used for ticket authentication.
+    pub fn oaep_hash(&self) -> OaepHash {
+        self.oaep_hash
+    }
+
+    /// Enable or disable retrying with the alternate OAEP hash when the server
+    /// answers the ticket with `SPICE_LINK_ERR_PERMISSION_DENIED`. Enabled by default.
+    pub fn set_oaep_fallback(&mut self, enabled: bool) {
+        self.oaep_fallback = enabled;
+    }
+
+    /// Override the capabilities advertised in the link message. Must be set
+    /// before `handshake()`.
+    pub fn set_advertised_caps(&mut self, caps: AdvertisedCapabilities) {
+        self.advertised_caps = caps;
+    }
+
+    /// Whether messages are framed with the 6-byte mini header, which both
+    /// sides must advertise `SPICE_COMMON_CAP_MINI_HEADER` for
+    pub fn uses_mini_header(&self) -> bool {
+        self.mini_header
+    }
+
+    /// Enable or disable strict message handling.
+    ///
+    /// By default a message that fails to parse is logged and skipped so one
+    /// malformed or unsupported message doesn't end the channel. In strict
+    /// mode the error is returned instead, which is useful when debugging.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Decide whether an error from handling one message ends the channel.
+    ///
+    /// The message body has already been read in full, so skipping a message
+    /// that failed to parse leaves the connection in sync with the server.
+    pub(crate) fn check_message_result(
+        &mut self,
+        header: &SpiceDataHeader,
+        result: Result<()>,
+    ) -> Result<()> {
+        // The message has been handled, so display data may go ahead again
+        self.qos_guard = None;
+        match result {
+            Err(e) if !self.strict && e.is_message_error() => {
+                warn!(
+                    "Skipping {:?} channel message type {} ({} bytes): {}",
+                    self.channel_type, header.msg_type, header.msg_size, e
+                );
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Set or clear the hook that sees every byte sent and received.
+    ///
+    /// Set it before `handshake()` to capture the link handshake too. When no
+    /// hook is set, tracing costs nothing.
+    pub fn set_trace_hook(&mut self, hook: Option<TraceHook>) {
+        self.trace_hook = hook;
+    }
+
+    /// Share a priority gate with the client's other channels.
+    ///
+    /// Display channels wait for the gate before reading each message, and
+    /// the other channels hold it while handling one, so input acks and
+    /// cursor updates aren't stuck behind a burst of draws.
+    pub fn set_qos_gate(&mut self, gate: Option<QosGate>) {
+        self.qos_gate = gate;
+        self.qos_guard = None;
+    }
+
+    /// Hold the gate while the message whose header was just read is
+    /// handled, unless this is bulk display data
+    fn hold_qos_gate(&mut self) {
+        if self.channel_type != ChannelType::Display {
+            self.qos_guard = self.qos_gate.as_ref().map(QosGate::hold);
+        }
+    }
+
+    fn trace(&self, direction: Direction, data: &[u8]) {
+        if let Some(ref hook) = self.trace_hook {
+            hook(self.channel_type, self.channel_id, direction, data);
+        }
+    }
+
+    /// Enable or disable the connection keepalive.
+    ///
+    /// With an interval set, idle connections are probed so that a silently
+    /// dropped connection (e.g. by a NAT or proxy idle timeout) is detected.
+    /// Native connections use TCP keepalive; WebSocket connections send a
+    /// harmless PONG whenever nothing has been sent or received for `interval`.
+    pub fn set_keepalive(&mut self, interval: Option<Duration>) -> Result<()> {
+        self.keepalive = interval;
+        self.apply_keepalive()
+    }
+
+    /// The configured keepalive interval, if any.
+    pub fn keepalive(&self) -> Option<Duration> {
+        self.keepalive
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn apply_keepalive(&self) -> Result<()> {
+        self.stream.set_keepalive(self.keepalive)?;
+        Ok(())
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn apply_keepalive(&self) -> Result<()> {
+        // Keepalive messages are sent from read_raw while waiting for data
+        Ok(())
+    }
+
+    /// Send a keepalive message if the connection has been idle for the
+    /// keepalive interval.
+    #[cfg(target_arch = "wasm32")]
+    async fn send_keepalive_if_idle(&mut self) -> Result<()> {
+        let Some(interval) = self.keepalive else {
+            return Ok(());
+        };
+        if !self.handshake_complete || self.last_activity.elapsed() < interval {
+            return Ok(());
+        }
+
+        // An unsolicited PONG (zero ping id and timestamp) is ignored by the
+        // server but keeps intermediaries from timing out the connection.
+        debug!("Sending keepalive on {:?} channel", self.channel_type);
+        self.send_message(SPICE_MSGC_PONG, &[0u8; 12]).await
+    }
+
+    /// The `(major, minor)` protocol version from the server's link reply,
+    /// available once the handshake has received it.
+    pub fn server_version(&self) -> Option<(u32, u32)> {
+        self.server_version
+    }
+
+    /// Whether `reconnect()` can open a fresh transport, which it can't for
+    /// a caller-supplied stream
+    #[cfg(not(target_arch = "wasm32"))]
+    fn can_reconnect(&self) -> bool {
+        self.factory.is_some()
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn can_reconnect(&self) -> bool {
+        true
+    }
+
+    /// Open a fresh transport to the same endpoint.
+    ///
+    /// The server closes the link after a failed authentication, so a retry
+    /// needs a new connection.
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn reconnect(&mut self) -> Result<()> {
+        let Some(factory) = self.factory.as_ref() else {
+            return Err(SpiceError::Connection(
+                "Cannot reopen a caller-supplied stream".to_string(),
+            ));
+        };
+        self.stream = factory.connect(self.channel_type, self.channel_id).await?;
+        self.handshake_complete = false;
+        self.last_activity = Instant::now();
+        self.apply_keepalive()
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    async fn reconnect(&mut self) -> Result<()> {
+        if let Some(ref ws) = self.websocket {
+            if let Ok(websocket) = ws.lock() {
+                let _ = websocket.close();
+            }
+        }
+        let fresh = Self::open_websocket(
+            &self.websocket_url,
+            self.channel_type,
+            self.channel_id,
+            self.auth_token.clone(),
+            self.binary_only,
+            self.connect_options.clone(),
+        )
+        .await?;
+        self.websocket = fresh.websocket;
+        self.byte_buffer = fresh.byte_buffer;
+        self.handshake_complete = false;
+        self.last_activity = Instant::now();
+        Ok(())
+    }
+
+    /// Link the channel again over a fresh transport, e.g. to have the
+    /// server resend its state. The server treats it as a new channel
+    /// connection of the same session.
+    pub(crate) async fn relink(&mut self) -> Result<()> {
+        self.reconnect().await?;
+        self.handshake().await
+    }
+
     /// Convert a list of capability bits into a capability bitmap array
     fn encode_capabilities(caps: &[u32]) -> Vec<u32> {
         if caps.is_empty() {
@@ -387,17 +929,94 @@ impl ChannelConnection {
 
     /// Get common capabilities supported by this client
     fn get_common_capabilities(&self) -> Vec<u32> {
-        // Don't advertise any capabilities for test-display-no-ssl
-        vec![]
+        self.advertised_caps.common_caps()
     }
 
     /// Get channel-specific capabilities
     fn get_channel_capabilities(&self) -> Vec<u32> {
-        // No channel-specific capabilities for now - focus on basic connectivity
-        vec![]
+        self.advertised_caps.channel_caps(self.channel_type)
+    }
+
+    /// Whether this client advertised the given channel capability bit
+    pub fn advertises_channel_cap(&self, cap: u32) -> bool {
+        self.get_channel_capabilities().contains(&cap)
+    }
+
+    /// Whether the server advertised the given channel capability bit
+    pub fn server_has_channel_cap(&self, cap: u32) -> bool {
+        self.server_channel_caps
+            .get((cap / 32) as usize)
+            .is_some_and(|word| word & (1 << (cap % 32)) != 0)
+    }
+
+    /// Common capabilities the server advertised in its link reply
+    pub fn server_common_caps(&self) -> CapabilitySet {
+        CapabilitySet::from_words(&self.server_common_caps)
+    }
+
+    /// Capabilities of this channel the server advertised in its link reply
+    pub fn server_channel_caps(&self) -> CapabilitySet {
+        CapabilitySet::from_words(&self.server_channel_caps)
+    }
+
+    /// Say what answered the link header instead of a SPICE server, from the
+    /// first bytes of its reply
+    fn describe_non_spice_reply(bytes: &[u8]) -> String {
+        if bytes.starts_with(b"RFB ") {
+            "the server speaks VNC".to_string()
+        } else if bytes.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+            format!(
+                "reply started with \"{}\"",
+                String::from_utf8_lossy(bytes).escape_default()
+            )
+        } else {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{b:02x}")).collect();
+            format!("reply started with bytes {}", hex.join(" "))
+        }
+    }
+
+    /// Extract `count` capability words starting at `start` in the link
+    /// reply data
+    fn parse_server_caps(link_data: &[u8], start: usize, count: u32) -> Vec<u32> {
+        (0..count as usize)
+            .map_while(|i| {
+                let offset = start + i * 4;
+                link_data
+                    .get(offset..offset + 4)
+                    .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            })
+            .collect()
     }
 
     pub async fn handshake(&mut self) -> Result<()> {
+        match self.link().await {
+            // The retry needs a new connection, so a caller-supplied stream
+            // gets the original error
+            Err(SpiceError::AuthenticationFailed { .. })
+                if self.oaep_fallback && self.can_reconnect() =>
+            {
+                let original = self.oaep_hash;
+                let alternate = original.alternate();
+                warn!(
+                    "Server rejected ticket encrypted with {:?} OAEP padding, retrying with {:?}",
+                    original, alternate
+                );
+                self.reconnect().await?;
+                self.oaep_hash = alternate;
+                let result = self.link().await;
+                if result.is_err() {
+                    self.oaep_hash = original;
+                }
+                result
+            }
+            result => result,
+        }
+    }
+
+    async fn link(&mut self) -> Result<()> {
+        #[cfg(target_arch = "wasm32")]
+        self.connect_options
+            .report(self.channel_type, self.channel_id, ConnectPhase::Linking);
         info!("=== SPICE Link Protocol Start ===");
         info!(
             "Channel type: {:?}, Channel ID: {}",
@@ -492,33 +1111,41 @@ impl ChannelConnection {
             .map_err(|e| SpiceError::Protocol(format!("Failed to write link header: {e}")))?;
         let header_bytes = header_cursor.into_inner();
 
-        info!("Sending SPICE link header: {:?}", header_bytes);
+        trace!("Sending SPICE link header: {:?}", header_bytes);
         self.send_raw(&header_bytes).await?;
 
-        info!(
+        trace!(
             "Sending SPICE link message ({} bytes): {:?}",
             mess_bytes.len(),
             mess_bytes
         );
         self.send_raw(&mess_bytes).await?;
 
-        // Read reply
-        let reply_bytes = self.read_raw(std::mem::size_of::<SpiceLinkReply>()).await?;
-
-        // Debug: log the raw bytes we received
-        info!("Received reply bytes: {:?}", reply_bytes);
-        if reply_bytes.len() >= 4 {
-            let magic_bytes = &reply_bytes[0..4];
-            let magic = u32::from_le_bytes([
-                magic_bytes[0],
-                magic_bytes[1],
-                magic_bytes[2],
-                magic_bytes[3],
-            ]);
-            info!(
-                "Magic in reply: 0x{:08x}, expected: 0x{:08x}",
-                magic, SPICE_MAGIC
-            );
+        // Read the magic on its own first: something that isn't a SPICE
+        // server, like a VNC server sending its 12-byte greeting, may never
+        // send a whole reply
+        let mut reply_bytes = self.read_raw(4).await?;
+        let magic = u32::from_le_bytes([
+            reply_bytes[0],
+            reply_bytes[1],
+            reply_bytes[2],
+            reply_bytes[3],
+        ]);
+        if magic != SPICE_MAGIC {
+            return Err(SpiceError::NotSpiceServer(Self::describe_non_spice_reply(
+                &reply_bytes,
+            )));
+        }
+        let rest = std::mem::size_of::<SpiceLinkReply>() - reply_bytes.len();
+        reply_bytes.extend(self.read_raw(rest).await?);
+        trace!("Received reply bytes: {:?}", reply_bytes);
+
+        // A real reply carries at least the public key, so it never matches
+        // the header we sent
+        if reply_bytes == header_bytes {
+            return Err(SpiceError::NotSpiceServer(
+                "the link header was echoed back".to_string(),
+            ));
         }
 
         // Use binrw for proper SPICE protocol deserialization
@@ -527,18 +1154,17 @@ impl ChannelConnection {
         let reply = SpiceLinkReply::read(&mut cursor)
             .map_err(|e| SpiceError::Protocol(format!("Failed to parse link reply: {e}")))?;
 
-        if reply.magic != SPICE_MAGIC {
-            return Err(SpiceError::Protocol(format!(
-                "Invalid magic in reply: got 0x{:08x}, expected 0x{:08x}",
-                reply.magic, SPICE_MAGIC
-            )));
-        }
+        info!(
+            "Server protocol version: {}.{}",
+            reply.major_version, reply.minor_version
+        );
+        self.server_version = Some((reply.major_version, reply.minor_version));
 
         // Read the link message data if size > 0
         if reply.size > 0 {
             info!("Reading {} bytes of link message data", reply.size);
             let link_data = self.read_raw(reply.size as usize).await?;
-            info!("Link message data: {:?}", link_data);
+            trace!("Link message data: {:?}", link_data);
 
             // Parse the link reply data using binrw
             use binrw::BinRead;
@@ -551,6 +1177,14 @@ impl ChannelConnection {
                 "Link reply: error={}, num_common_caps={}, num_channel_caps={}",
                 reply_data.error, reply_data.num_common_caps, reply_data.num_channel_caps
             );
+            let caps_offset = reply_data.caps_offset as usize;
+            self.server_common_caps =
+                Self::parse_server_caps(&link_data, caps_offset, reply_data.num_common_caps);
+            self.server_channel_caps = Self::parse_server_caps(
+                &link_data,
+                caps_offset + reply_data.num_common_caps as usize * 4,
+                reply_data.num_channel_caps,
+            );
 
             if reply_data.error == 0 {
                 // Server sent public key
@@ -592,7 +1226,7 @@ impl ChannelConnection {
                 };
 
                 // Encrypt the password (or empty string)
-                match encrypt_password(password_to_encrypt, pub_key_der) {
+                match encrypt_password(password_to_encrypt, pub_key_der, self.oaep_hash) {
                     Ok(encrypted_password) => {
                         info!(
                             "Successfully encrypted password, sending {} bytes",
@@ -620,6 +1254,24 @@ impl ChannelConnection {
                     link_result[3],
                 ]);
 
+                if auth_error == LinkError::PermissionDenied as u32 {
+                    warn!(
+                        "Authentication failed with error code: {} (SPICE_LINK_ERR_PERMISSION_DENIED)",
+                        auth_error
+                    );
+                    if self.password.as_deref().unwrap_or("").is_empty() {
+                        return Err(SpiceError::PasswordRequired);
+                    }
+                    return Err(SpiceError::AuthenticationFailed {
+                        reason: AuthFailureReason::BadPassword,
+                    });
+                }
+                if auth_error == LinkError::ChannelNotAvailable as u32 {
+                    return Err(SpiceError::ChannelNotAvailable(self.channel_type));
+                }
+                if let Some(error) = self.security_link_error(auth_error) {
+                    return Err(error);
+                }
                 if auth_error != 0 {
                     let error_name = match auth_error {
                         1 => "SPICE_LINK_ERR_ERROR",
@@ -639,6 +1291,11 @@ impl ChannelConnection {
                     )));
                 }
                 info!("✓ Authentication successful - Link result is 0 (SPICE_LINK_ERR_OK)");
+            } else if reply_data.error == LinkError::ChannelNotAvailable as u32 {
+                warn!("Server does not provide {:?} channel", self.channel_type);
+                return Err(SpiceError::ChannelNotAvailable(self.channel_type));
+            } else if let Some(error) = self.security_link_error(reply_data.error) {
+                return Err(error);
             } else {
                 // Handle link error
                 let error_name = match reply_data.error {
@@ -660,6 +1317,17 @@ impl ChannelConnection {
             }
         }
 
+        // Both sides must support the 6-byte mini header to switch to it
+        self.mini_header = self
+            .get_common_capabilities()
+            .contains(&SPICE_COMMON_CAP_MINI_HEADER)
+            && self
+                .server_common_caps()
+                .contains(SPICE_COMMON_CAP_MINI_HEADER);
+        if self.mini_header {
+            info!("Using mini data headers");
+        }
+
         // Mark handshake as complete
         self.handshake_complete = true;
         info!("=== SPICE Link Protocol Complete ===");
@@ -671,7 +1339,29 @@ impl ChannelConnection {
         Ok(())
     }
 
+    /// Map the link errors that say the channel is on the wrong port, TLS or
+    /// plaintext, to their own errors so callers can tell them apart
+    fn security_link_error(&self, error: u32) -> Option<SpiceError> {
+        if error == LinkError::NeedSecured as u32 {
+            warn!(
+                "Server requires TLS for {:?} channel, which is not supported",
+                self.channel_type
+            );
+            Some(SpiceError::NeedSecured(self.channel_type))
+        } else if error == LinkError::NeedUnsecured as u32 {
+            warn!(
+                "Server requires a plaintext connection for {:?} channel",
+                self.channel_type
+            );
+            Some(SpiceError::NeedUnsecured(self.channel_type))
+        } else {
+            None
+        }
+    }
+
     async fn send_raw(&mut self, data: &[u8]) -> Result<()> {
+        self.trace(Direction::Sent, data);
+
         #[cfg(not(target_arch = "wasm32"))]
         {
             self.stream.write_all(data).await?;
@@ -688,6 +1378,7 @@ impl ChannelConnection {
             }
         }
 
+        self.last_activity = Instant::now();
         Ok(())
     }
 
@@ -696,6 +1387,8 @@ impl ChannelConnection {
         {
             let mut data = vec![0u8; len];
             self.stream.read_exact(&mut data).await?;
+            self.last_activity = Instant::now();
+            self.trace(Direction::Received, &data);
             Ok(data)
         }
 
@@ -708,8 +1401,10 @@ impl ChannelConnection {
                 // Increased timeout for SPICE handshake
                 if let Ok(mut buffer) = self.byte_buffer.lock() {
                     if buffer.len() >= len {
-                        let data = buffer.drain(..len).collect();
-                        info!("Read {} bytes from WebSocket: {:?}", len, data);
+                        let data: Vec<u8> = buffer.drain(..len).collect();
+                        drop(buffer);
+                        self.last_activity = Instant::now();
+                        self.trace(Direction::Received, &data);
                         return Ok(data);
                     } else if !buffer.is_empty() {
                         if buffer.len() != last_buffer_size {
@@ -723,6 +1418,7 @@ impl ChannelConnection {
                         }
                     }
                 }
+                self.send_keepalive_if_idle().await?;
                 gloo_timers::future::TimeoutFuture::new(10).await;
                 attempts += 1;
 
@@ -741,18 +1437,79 @@ impl ChannelConnection {
         }
     }
 
+    /// Wait until the server has sent data, without consuming any.
+    ///
+    /// Unlike `read_message`, this can be cancelled at any point without
+    /// losing data, so it can be raced against other work.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn wait_readable(&mut self) -> Result<()> {
+        self.stream.readable().await?;
+        Ok(())
+    }
+
+    /// Wait until the server has sent data, without consuming any.
+    ///
+    /// Unlike `read_message`, this can be cancelled at any point without
+    /// losing data, so it can be raced against other work.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn wait_readable(&mut self) -> Result<()> {
+        loop {
+            if let Ok(buffer) = self.byte_buffer.lock() {
+                if !buffer.is_empty() {
+                    return Ok(());
+                }
+            }
+            self.send_keepalive_if_idle().await?;
+            gloo_timers::future::TimeoutFuture::new(10).await;
+        }
+    }
+
+    /// Whether data from the server is waiting to be read, so the next
+    /// `read_message` is unlikely to wait. Never blocks.
+    pub fn has_pending_data(&self) -> bool {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.stream.has_data()
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.byte_buffer
+                .lock()
+                .map(|buffer| !buffer.is_empty())
+                .unwrap_or(false)
+        }
+    }
+
     pub async fn read_message(&mut self) -> Result<(SpiceDataHeader, Vec<u8>)> {
+        // A message that was read but never handled doesn't keep the gate
+        self.qos_guard = None;
+        if self.channel_type == ChannelType::Display {
+            if let Some(ref gate) = self.qos_gate {
+                gate.wait_idle().await;
+            }
+        }
+
+        let result = if self.mini_header {
+            self.read_mini_message().await
+        } else {
+            self.read_full_message().await
+        };
+        if result.is_err() {
+            self.qos_guard = None;
+        }
+        result
+    }
+
+    /// Read a message framed with the 18-byte data header
+    async fn read_full_message(&mut self) -> Result<(SpiceDataHeader, Vec<u8>)> {
         // SPICE protocol specifies exact sizes on the wire:
         // serial: 8 bytes, msg_type: 2 bytes, msg_size: 4 bytes, sub_list: 4 bytes = 18 bytes total
         const SPICE_DATA_HEADER_SIZE: usize = 18;
 
         let header_bytes = self.read_raw(SPICE_DATA_HEADER_SIZE).await?;
 
-        debug!(
-            "Raw header bytes ({}): {:?}",
-            header_bytes.len(),
-            header_bytes
-        );
+        trace!("Raw header bytes: {:?}", header_bytes);
 
         use binrw::BinRead;
         let mut cursor = std::io::Cursor::new(&header_bytes);
@@ -763,6 +1520,37 @@ impl ChannelConnection {
             "Parsed header: serial={}, type={}, size={}, sub_list={}",
             header.serial, header.msg_type, header.msg_size, header.sub_list
         );
+        self.hold_qos_gate();
+
+        let data = self.read_raw(header.msg_size as usize).await?;
+
+        Ok((header, data))
+    }
+
+    /// Read a message framed with the 6-byte mini header, which carries no
+    /// serial, so one is counted locally
+    async fn read_mini_message(&mut self) -> Result<(SpiceDataHeader, Vec<u8>)> {
+        const SPICE_MINI_DATA_HEADER_SIZE: usize = 6;
+
+        let header_bytes = self.read_raw(SPICE_MINI_DATA_HEADER_SIZE).await?;
+
+        use binrw::BinRead;
+        let mut cursor = std::io::Cursor::new(&header_bytes);
+        let mini = SpiceMiniDataHeader::read(&mut cursor)
+            .map_err(|e| SpiceError::Protocol(format!("Failed to parse mini header: {e}")))?;
+
+        self.received_serial += 1;
+        let header = SpiceDataHeader {
+            serial: self.received_serial,
+            msg_type: mini.msg_type,
+            msg_size: mini.msg_size,
+            sub_list: 0,
+        };
+        debug!(
+            "Parsed mini header: type={}, size={}",
+            header.msg_type, header.msg_size
+        );
+        self.hold_qos_gate();
 
         let data = self.read_raw(header.msg_size as usize).await?;
 
@@ -781,33 +1569,139 @@ impl ChannelConnection {
             serial
         );
 
-        let header = SpiceDataHeader {
-            serial,
-            msg_type,
-            msg_size: data.len() as u32,
-            sub_list: 0,
-        };
-
         use binrw::BinWrite;
         let mut header_cursor = std::io::Cursor::new(Vec::new());
-        header
+        let written = if self.mini_header {
+            SpiceMiniDataHeader {
+                msg_type,
+                msg_size: data.len() as u32,
+            }
+            .write(&mut header_cursor)
+        } else {
+            SpiceDataHeader {
+                serial,
+                msg_type,
+                msg_size: data.len() as u32,
+                sub_list: 0,
+            }
             .write(&mut header_cursor)
-            .map_err(|e| SpiceError::Protocol(format!("Failed to write data header: {e}")))?;
+        };
+        written.map_err(|e| SpiceError::Protocol(format!("Failed to write data header: {e}")))?;
         let header_bytes = header_cursor.into_inner();
 
-        info!(
-            "Sending message: serial={}, type={}, size={}, header_size={}",
-            serial,
-            msg_type,
-            data.len(),
-            header_bytes.len()
-        );
         self.send_raw(&header_bytes).await?;
         if !data.is_empty() {
-            info!("Sending message data: {:?}", data);
             self.send_raw(data).await?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deliver_all(frames: Vec<WebSocketFrame>, binary_only: bool) -> (Vec<u8>, String) {
+        let mut data = Vec::new();
+        let mut auth_response = String::new();
+        for frame in frames {
+            frame.deliver(binary_only, &mut data, &mut auth_response);
+        }
+        (data, auth_response)
+    }
+
+    #[test]
+    fn test_binary_only_drops_stray_text_frame() {
+        // A link reply split around a text frame the proxy sent in between
+        let frames = vec![
+            WebSocketFrame::Binary(b"REDQ".to_vec()),
+            WebSocketFrame::Text("keepalive".to_string()),
+            WebSocketFrame::Binary(vec![2, 0, 0, 0]),
+        ];
+
+        let (data, auth_response) = deliver_all(frames.clone(), true);
+        assert_eq!(data, b"REDQ\x02\0\0\0");
+        assert!(auth_response.is_empty());
+
+        // By default the text is taken as the auth response
+        let (data, auth_response) = deliver_all(frames, false);
+        assert_eq!(data, b"REDQ\x02\0\0\0");
+        assert_eq!(auth_response, "keepalive");
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod wasm_tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_slow_proxy_connects_within_deadline() {
+        let phases = Arc::new(Mutex::new(Vec::new()));
+        let recorded = phases.clone();
+        let options = ConnectOptions {
+            open_timeout: Duration::from_secs(2),
+            auth_timeout: Duration::from_secs(2),
+            progress: Some(Arc::new(move |channel_type, channel_id, phase| {
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push((channel_type, channel_id, phase))
+            })),
+        };
+
+        // A socket that takes longer to open than a single poll
+        options.report(ChannelType::Main, 0, ConnectPhase::Opening);
+        let opens_at = Instant::now() + Duration::from_millis(300);
+        let opened = poll_until(options.open_timeout, || Ok(Instant::now() >= opens_at))
+            .await
+            .unwrap();
+        assert!(opened);
+
+        // A proxy that answers the auth token late
+        options.report(ChannelType::Main, 0, ConnectPhase::Authenticating);
+        let auth_response = Arc::new(Mutex::new(String::new()));
+        let response = auth_response.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            gloo_timers::future::TimeoutFuture::new(200).await;
+            *response.lock().unwrap() = "OK".to_string();
+        });
+        let authenticated =
+            poll_until(options.auth_timeout, || check_auth_response(&auth_response))
+                .await
+                .unwrap();
+        assert!(authenticated);
+
+        assert_eq!(
+            *phases.lock().unwrap(),
+            vec![
+                (ChannelType::Main, 0, ConnectPhase::Opening),
+                (ChannelType::Main, 0, ConnectPhase::Authenticating),
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_open_gives_up_at_deadline() {
+        let started = Instant::now();
+        let opened = poll_until(Duration::from_millis(200), || Ok(false))
+            .await
+            .unwrap();
+        assert!(!opened);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_refused_auth_token_fails_immediately() {
+        let auth_response = Mutex::new("Authentication failed".to_string());
+        let result = poll_until(Duration::from_secs(10), || {
+            check_auth_response(&auth_response)
+        })
+        .await;
+        assert!(matches!(result, Err(SpiceError::Protocol(_))));
+    }
+}
@@ -0,0 +1,96 @@
+//! Priority between channels
+//!
+//! Every channel reads on its own task, so a burst of display data can keep
+//! the executor busy while input acks and cursor moves wait behind it. The
+//! gate is shared by the channels of one client: while a main, inputs or
+//! cursor message is being handled, display channels hold off reading bulk
+//! data until it is done.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+#[derive(Debug, Default)]
+struct GateState {
+    /// Interactive messages being handled right now
+    busy: AtomicUsize,
+    idle: Notify,
+}
+
+/// Shared handle that lets interactive channels go ahead of display data.
+/// Clones see the same gate.
+#[derive(Debug, Clone, Default)]
+pub struct QosGate {
+    state: Arc<GateState>,
+}
+
+impl QosGate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether an interactive message is being handled
+    pub fn is_busy(&self) -> bool {
+        self.state.busy.load(Ordering::Acquire) > 0
+    }
+
+    /// Mark an interactive message as being handled until the guard drops
+    pub(crate) fn hold(&self) -> QosGuard {
+        self.state.busy.fetch_add(1, Ordering::AcqRel);
+        QosGuard { gate: self.clone() }
+    }
+
+    /// Wait until no interactive message is being handled
+    pub(crate) async fn wait_idle(&self) {
+        loop {
+            let idle = self.state.idle.notified();
+            tokio::pin!(idle);
+            idle.as_mut().enable();
+            if !self.is_busy() {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
+
+/// Keeps the gate busy while an interactive message is handled
+#[derive(Debug)]
+pub(crate) struct QosGuard {
+    gate: QosGate,
+}
+
+impl Drop for QosGuard {
+    fn drop(&mut self) {
+        if self.gate.state.busy.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.gate.state.idle.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wait_idle_until_last_guard_drops() {
+        let gate = QosGate::new();
+        gate.wait_idle().await;
+
+        let first = gate.hold();
+        let second = gate.hold();
+        let waiter = tokio::spawn({
+            let gate = gate.clone();
+            async move { gate.wait_idle().await }
+        });
+
+        drop(first);
+        tokio::task::yield_now().await;
+        assert!(gate.is_busy());
+        assert!(!waiter.is_finished());
+
+        drop(second);
+        waiter.await.unwrap();
+        assert!(!gate.is_busy());
+    }
+}
@@ -0,0 +1,89 @@
+//! Connection state of each channel, for diagnostics
+//!
+//! A client records every channel as it links and again when its task ends
+//! with an error, so a diagnostics view can tell which channel dropped and
+//! why while the others keep running.
+
+use crate::error::Result;
+use crate::protocol::ChannelType;
+use std::sync::{Arc, Mutex};
+
+/// Where a channel's connection stands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChannelState {
+    /// Opening or linking the connection
+    Connecting,
+    /// Linked and handling messages
+    Connected,
+    /// The connection was closed without an error
+    Closed,
+    /// The connection failed, with the last error seen
+    Failed(String),
+}
+
+/// States recorded by a client's channels. Clones share the states.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ChannelStates {
+    states: Arc<Mutex<Vec<(ChannelType, u8, ChannelState)>>>,
+}
+
+impl ChannelStates {
+    pub(crate) fn set(&self, channel_type: ChannelType, channel_id: u8, state: ChannelState) {
+        let mut states = self.states.lock().unwrap();
+        match states
+            .iter_mut()
+            .find(|(t, id, _)| *t == channel_type && *id == channel_id)
+        {
+            Some(entry) => entry.2 = state,
+            None => states.push((channel_type, channel_id, state)),
+        }
+    }
+
+    /// Record `result` of linking or running a channel if it failed
+    pub(crate) fn record_failure<T>(
+        &self,
+        channel_type: ChannelType,
+        channel_id: u8,
+        result: Result<T>,
+    ) -> Result<T> {
+        if let Err(ref e) = result {
+            self.set(
+                channel_type,
+                channel_id,
+                ChannelState::Failed(e.to_string()),
+            );
+        }
+        result
+    }
+
+    /// Record how a channel's task ended: closed, or failed with its error
+    pub(crate) fn record_exit(
+        &self,
+        channel_type: ChannelType,
+        channel_id: u8,
+        result: Result<()>,
+    ) -> Result<()> {
+        if result.is_ok() {
+            self.set(channel_type, channel_id, ChannelState::Closed);
+        }
+        self.record_failure(channel_type, channel_id, result)
+    }
+
+    /// Mark every channel that hasn't failed as closed
+    pub(crate) fn close_all(&self) {
+        for (_, _, state) in self.states.lock().unwrap().iter_mut() {
+            if !matches!(state, ChannelState::Failed(_)) {
+                *state = ChannelState::Closed;
+            }
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        self.states.lock().unwrap().clear();
+    }
+
+    /// Every recorded channel, in the order they were first seen
+    pub(crate) fn snapshot(&self) -> Vec<(ChannelType, u8, ChannelState)> {
+        self.states.lock().unwrap().clone()
+    }
+}
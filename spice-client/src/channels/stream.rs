@@ -0,0 +1,388 @@
+//! Native byte streams to a SPICE server
+
+use crate::error::{Result, SpiceError};
+use crate::protocol::ChannelType;
+use async_trait::async_trait;
+use bytes::{Buf, Bytes};
+use futures::{SinkExt, StreamExt};
+use instant::Duration;
+use socket2::SockRef;
+use std::future::Future;
+use std::io;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, Interest,
+    ReadBuf,
+};
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio_tungstenite::tungstenite::error::{Error as WsError, ProtocolError};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// Opens the transport for each channel of a SPICE session.
+///
+/// SPICE links every channel over a connection of its own, so this is asked
+/// once per channel, and again if a channel has to reconnect. There are
+/// factories for TCP and Unix sockets and for WebSocket proxies such as
+/// websockify; a caller that reaches the server some other way, such as
+/// over a tunnel or a QEMU chardev in the same process, can implement it,
+/// or use any `Fn(ChannelType, u8) -> impl Future<Output = io::Result<S>>`
+/// closure. WebAssembly builds open their WebSockets through the browser
+/// instead.
+#[async_trait]
+pub trait ConnectionFactory: Send + Sync {
+    /// Open a fresh stream for channel `channel_id` of `channel_type`
+    async fn connect(&self, channel_type: ChannelType, channel_id: u8) -> Result<Stream>;
+
+    /// Where the streams lead, for logging
+    fn describe(&self) -> String {
+        "caller-supplied streams".to_string()
+    }
+}
+
+/// Connects each channel to a TCP port
+pub struct TcpConnectionFactory {
+    host: String,
+    port: u16,
+}
+
+impl TcpConnectionFactory {
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+        }
+    }
+}
+
+#[async_trait]
+impl ConnectionFactory for TcpConnectionFactory {
+    async fn connect(&self, _channel_type: ChannelType, _channel_id: u8) -> Result<Stream> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        // Input goes out as small messages that mustn't wait on acks
+        stream.set_nodelay(true)?;
+        Ok(stream.into())
+    }
+
+    fn describe(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+/// Connects each channel to a Unix domain socket, as QEMU opens with
+/// `-spice unix=on,addr=<path>`
+#[cfg(unix)]
+pub struct UnixConnectionFactory {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixConnectionFactory {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[cfg(unix)]
+#[async_trait]
+impl ConnectionFactory for UnixConnectionFactory {
+    async fn connect(&self, _channel_type: ChannelType, _channel_id: u8) -> Result<Stream> {
+        let stream = UnixStream::connect(&self.path).await?;
+        Ok(stream.into())
+    }
+
+    fn describe(&self) -> String {
+        self.path.display().to_string()
+    }
+}
+
+/// Connects each channel through a WebSocket proxy that forwards binary
+/// messages to the server, as websockify and the quickemu-manager proxy do
+pub struct WebSocketConnectionFactory {
+    url: String,
+    auth_token: Option<String>,
+}
+
+impl WebSocketConnectionFactory {
+    /// Connect to the proxy at `url`, e.g. `ws://localhost:8080/spice`
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            auth_token: None,
+        }
+    }
+
+    /// Send `token` to the proxy as a text message before any SPICE data
+    /// and wait for it to answer `OK`
+    pub fn with_auth_token(mut self, token: impl Into<String>) -> Self {
+        self.auth_token = Some(token.into());
+        self
+    }
+
+    async fn authenticate(&self, socket: &mut WebSocket, token: &str) -> Result<()> {
+        socket
+            .send(Message::text(token))
+            .await
+            .map_err(|e| SpiceError::Connection(format!("Failed to send auth token: {e}")))?;
+        while let Some(message) = socket.next().await {
+            let message = message.map_err(|e| {
+                SpiceError::Connection(format!("WebSocket authentication failed: {e}"))
+            })?;
+            if let Message::Text(reply) = message {
+                if reply.contains("OK") {
+                    return Ok(());
+                }
+                if reply.contains("Authentication failed") {
+                    break;
+                }
+            }
+        }
+        Err(SpiceError::Protocol(
+            "WebSocket authentication failed".to_string(),
+        ))
+    }
+}
+
+#[async_trait]
+impl ConnectionFactory for WebSocketConnectionFactory {
+    async fn connect(&self, channel_type: ChannelType, channel_id: u8) -> Result<Stream> {
+        let (mut socket, _) = tokio_tungstenite::connect_async(self.url.as_str())
+            .await
+            .map_err(|e| {
+                SpiceError::Connection(format!(
+                    "Failed to open WebSocket {} for {:?} channel {}: {}",
+                    self.url, channel_type, channel_id, e
+                ))
+            })?;
+        if let Some(token) = &self.auth_token {
+            self.authenticate(&mut socket, token).await?;
+        }
+        Ok(Stream::custom(WebSocketIo::new(socket)))
+    }
+
+    fn describe(&self) -> String {
+        self.url.clone()
+    }
+}
+
+type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Byte stream over the binary messages of a WebSocket
+pub(crate) struct WebSocketIo<S> {
+    socket: WebSocketStream<S>,
+    /// Rest of the last binary message read
+    pending: Bytes,
+}
+
+impl<S> WebSocketIo<S> {
+    pub(crate) fn new(socket: WebSocketStream<S>) -> Self {
+        Self {
+            socket,
+            pending: Bytes::new(),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketIo<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.pending.is_empty() {
+            match ready!(self.socket.poll_next_unpin(cx)) {
+                Some(Ok(Message::Binary(data))) => self.pending = data,
+                // Pings are answered by the socket itself; text is only used
+                // for the proxy's auth handshake
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                // A peer that drops the socket without a close frame has
+                // still ended the stream, as closing a TCP socket would
+                Some(Err(WsError::Protocol(ProtocolError::ResetWithoutClosingHandshake))) => {
+                    return Poll::Ready(Ok(()))
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Poll::Ready(Err(io::Error::new(io::ErrorKind::Other, e))),
+            }
+        }
+        let len = self.pending.len().min(buf.remaining());
+        buf.put_slice(&self.pending[..len]);
+        self.pending.advance(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WebSocketIo<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(self.socket.poll_ready_unpin(cx)).map_err(websocket_error)?;
+        self.socket
+            .start_send_unpin(Message::binary(buf.to_vec()))
+            .map_err(websocket_error)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.socket.poll_flush_unpin(cx).map_err(websocket_error)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.socket.poll_close_unpin(cx).map_err(websocket_error)
+    }
+}
+
+fn websocket_error(e: WsError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+#[async_trait]
+impl<F, Fut, S> ConnectionFactory for F
+where
+    F: Fn(ChannelType, u8) -> Fut + Send + Sync,
+    Fut: Future<Output = io::Result<S>> + Send,
+    S: IoStream + 'static,
+{
+    async fn connect(&self, channel_type: ChannelType, channel_id: u8) -> Result<Stream> {
+        let stream = self(channel_type, channel_id).await.map_err(|e| {
+            SpiceError::Connection(format!(
+                "Failed to open stream for {:?} channel {}: {}",
+                channel_type, channel_id, e
+            ))
+        })?;
+        Ok(Stream::custom(stream))
+    }
+}
+
+/// Any byte stream a caller can supply in place of a socket
+pub trait IoStream: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync> IoStream for T {}
+
+/// Connection to one channel of the server
+pub struct Stream(Inner);
+
+enum Inner {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    /// Buffered, because a generic stream can only be polled for readiness
+    /// by reading from it
+    Supplied(BufReader<Box<dyn IoStream>>),
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        Stream(Inner::Tcp(stream))
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for Stream {
+    fn from(stream: UnixStream) -> Self {
+        Stream(Inner::Unix(stream))
+    }
+}
+
+impl Stream {
+    /// Wrap any other byte stream, e.g. one end of a `tokio::io::duplex`
+    pub fn custom(stream: impl IoStream + 'static) -> Self {
+        let stream: Box<dyn IoStream> = Box::new(stream);
+        Stream(Inner::Supplied(BufReader::new(stream)))
+    }
+
+    pub(crate) async fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        match &mut self.0 {
+            Inner::Tcp(stream) => stream.write_all(data).await,
+            #[cfg(unix)]
+            Inner::Unix(stream) => stream.write_all(data).await,
+            Inner::Supplied(stream) => {
+                stream.write_all(data).await?;
+                stream.flush().await
+            }
+        }
+    }
+
+    pub(crate) async fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            Inner::Tcp(stream) => stream.read_exact(buf).await,
+            #[cfg(unix)]
+            Inner::Unix(stream) => stream.read_exact(buf).await,
+            Inner::Supplied(stream) => stream.read_exact(buf).await,
+        }
+    }
+
+    /// Wait until there is data to read or the server has closed the
+    /// connection
+    pub(crate) async fn readable(&mut self) -> io::Result<()> {
+        // Socket readiness is only cleared by a read that would block, and
+        // messages are read exactly, so it can still be set once everything
+        // has been read. Peek to tell, clearing it if nothing is there.
+        match &mut self.0 {
+            Inner::Tcp(stream) => loop {
+                stream.readable().await?;
+                match stream.try_io(Interest::READABLE, || peek(SockRef::from(&*stream))) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    result => return result.map(|_| ()),
+                }
+            },
+            #[cfg(unix)]
+            Inner::Unix(stream) => loop {
+                stream.readable().await?;
+                match stream.try_io(Interest::READABLE, || peek(SockRef::from(&*stream))) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    result => return result.map(|_| ()),
+                }
+            },
+            // Data read ahead stays in the buffer, so this is cancel safe too
+            Inner::Supplied(stream) => stream.fill_buf().await.map(|_| ()),
+        }
+    }
+
+    /// Whether the server has sent data that hasn't been read yet. Never
+    /// waits; a supplied stream only reports what it has read ahead.
+    pub(crate) fn has_data(&self) -> bool {
+        // A closed connection peeks 0 bytes; the next read reports it
+        match &self.0 {
+            Inner::Tcp(stream) => matches!(peek(SockRef::from(stream)), Ok(n) if n > 0),
+            #[cfg(unix)]
+            Inner::Unix(stream) => matches!(peek(SockRef::from(stream)), Ok(n) if n > 0),
+            Inner::Supplied(stream) => !stream.buffer().is_empty(),
+        }
+    }
+
+    /// Turn TCP keepalive probes on or off. Unix sockets have no peer that
+    /// can silently disappear, and supplied streams are up to the caller, so
+    /// both are left alone.
+    pub(crate) fn set_keepalive(&self, interval: Option<Duration>) -> io::Result<()> {
+        match &self.0 {
+            Inner::Tcp(stream) => {
+                let socket = SockRef::from(stream);
+                match interval {
+                    Some(interval) => {
+                        let keepalive = socket2::TcpKeepalive::new()
+                            .with_time(interval)
+                            .with_interval(interval);
+                        socket.set_tcp_keepalive(&keepalive)
+                    }
+                    None => socket.set_keepalive(false),
+                }
+            }
+            #[cfg(unix)]
+            Inner::Unix(_) => Ok(()),
+            Inner::Supplied(_) => Ok(()),
+        }
+    }
+}
+
+/// Peek at the next byte of a socket without waiting for one
+fn peek(socket: SockRef<'_>) -> io::Result<usize> {
+    let mut byte = [std::mem::MaybeUninit::uninit()];
+    socket.peek(&mut byte)
+}
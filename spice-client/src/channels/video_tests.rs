@@ -24,14 +24,11 @@ pub enum VideoFormat {
 impl VideoFrame {
     pub fn from_display_surface(surface: &DisplaySurface, timestamp: u64) -> Result<Self> {
         let format = match surface.format {
-            0x0 => VideoFormat::Rgb32,
-            0x1 => VideoFormat::Rgba32,
-            0x2 => VideoFormat::Bgr32,
-            0x3 => VideoFormat::Bgra32,
-            _ => {
+            SurfaceFormat::Xrgb32 => VideoFormat::Rgb32,
+            SurfaceFormat::Argb32 => VideoFormat::Rgba32,
+            other => {
                 return Err(SpiceError::Protocol(format!(
-                    "Unsupported format: {}",
-                    surface.format
+                    "Unsupported format: {other:?}"
                 )))
             }
         };
@@ -114,8 +111,9 @@ mod tests {
         let surface = DisplaySurface {
             width: 100,
             height: 50,
-            format: 0x1,                   // RGBA32
+            format: SurfaceFormat::Argb32,
             data: vec![255; 100 * 50 * 4], // White image
+            pixel_format: PixelFormat::Rgba8,
         };
 
         let frame = VideoFrame::from_display_surface(&surface, 1000).unwrap();
@@ -131,8 +129,9 @@ mod tests {
         let surface = DisplaySurface {
             width: 100,
             height: 50,
-            format: 0xFF, // Invalid format
+            format: SurfaceFormat::A1, // Not a video format
             data: vec![0; 100 * 50 * 4],
+            pixel_format: PixelFormat::Rgba8,
         };
 
         let result = VideoFrame::from_display_surface(&surface, 1000);
@@ -144,13 +143,14 @@ mod tests {
         let surface = DisplaySurface {
             width: 2,
             height: 2,
-            format: 0x1, // RGBA32
+            format: SurfaceFormat::Argb32,
             data: vec![
                 255, 0, 0, 255, // Red
                 0, 255, 0, 255, // Green
                 0, 0, 255, 255, // Blue
                 255, 255, 255, 255, // White
             ],
+            pixel_format: PixelFormat::Rgba8,
         };
 
         let frame = VideoFrame::from_display_surface(&surface, 1000).unwrap();
@@ -165,8 +165,9 @@ mod tests {
         let surface = DisplaySurface {
             width: 2,
             height: 1,
-            format: 0x0,                      // RGB32
+            format: SurfaceFormat::Xrgb32,
             data: vec![255, 0, 0, 0, 255, 0], // Red, Green
+            pixel_format: PixelFormat::Rgba8,
         };
 
         let frame = VideoFrame::from_display_surface(&surface, 1000).unwrap();
@@ -183,14 +184,13 @@ mod tests {
 
     #[test]
     fn test_bgr_to_rgba_conversion() {
-        let surface = DisplaySurface {
+        let frame = VideoFrame {
             width: 2,
             height: 1,
-            format: 0x2,                      // BGR32
+            format: VideoFormat::Bgr32,
             data: vec![0, 0, 255, 0, 255, 0], // Red (BGR), Green (BGR)
+            timestamp: 1000,
         };
-
-        let frame = VideoFrame::from_display_surface(&surface, 1000).unwrap();
         let rgba_data = frame.convert_bgr_to_rgba();
 
         assert_eq!(
@@ -210,8 +210,9 @@ mod tests {
             let surface = DisplaySurface {
                 width,
                 height,
-                format: 0x1, // RGBA32
+                format: SurfaceFormat::Argb32,
                 data: vec![0; (width * height * 4) as usize],
+                pixel_format: PixelFormat::Rgba8,
             };
 
             let frame = VideoFrame::from_display_surface(&surface, 1000).unwrap();
@@ -297,8 +298,9 @@ mod buffer_tests {
         let surface = DisplaySurface {
             width: 100,
             height: 100,
-            format: 0x1,
+            format: SurfaceFormat::Argb32,
             data: vec![0; 100 * 100 * 4],
+            pixel_format: PixelFormat::Rgba8,
         };
 
         // First frame should be accepted
@@ -323,8 +325,9 @@ mod buffer_tests {
         let surface = DisplaySurface {
             width: 10,
             height: 10,
-            format: 0x1,
+            format: SurfaceFormat::Argb32,
             data: vec![0; 10 * 10 * 4],
+            pixel_format: PixelFormat::Rgba8,
         };
 
         // Add 4 frames, should only keep last 3
@@ -343,8 +346,9 @@ mod buffer_tests {
         let surface = DisplaySurface {
             width: 100,
             height: 100,
-            format: 0x1,
+            format: SurfaceFormat::Argb32,
             data: vec![0; 100 * 100 * 4],
+            pixel_format: PixelFormat::Rgba8,
         };
 
         let frame = VideoFrame::from_display_surface(&surface, 0).unwrap();
@@ -367,8 +371,9 @@ mod buffer_tests {
         let surface = DisplaySurface {
             width: 10,
             height: 10,
-            format: 0x1,
+            format: SurfaceFormat::Argb32,
             data: vec![0; 10 * 10 * 4],
+            pixel_format: PixelFormat::Rgba8,
         };
 
         let frame = VideoFrame::from_display_surface(&surface, 0).unwrap();
@@ -491,11 +496,10 @@ impl VideoStreamer {
     }
 
     pub fn adjust_quality_for_load(&mut self, target_encode_time_us: u64) {
-        let avg_encode_time = if self.frames_encoded > 0 {
-            self.total_encoding_time / self.frames_encoded
-        } else {
-            0
-        };
+        let avg_encode_time = self
+            .total_encoding_time
+            .checked_div(self.frames_encoded)
+            .unwrap_or(0);
 
         if avg_encode_time > target_encode_time_us && self.quality_level > 25 {
             self.quality_level = (self.quality_level - 5).max(25);
@@ -513,11 +517,10 @@ impl VideoStreamer {
             } else {
                 0.0
             },
-            avg_encoding_time_us: if self.frames_encoded > 0 {
-                self.total_encoding_time / self.frames_encoded
-            } else {
-                0
-            },
+            avg_encoding_time_us: self
+                .total_encoding_time
+                .checked_div(self.frames_encoded)
+                .unwrap_or(0),
             quality_level: self.quality_level,
             buffer_usage: self.buffer.get_frame_count(),
             memory_usage: self.buffer.get_memory_usage(),
@@ -615,8 +618,9 @@ mod streaming_tests {
         let surface = DisplaySurface {
             width: 640,
             height: 480,
-            format: 0x1,
+            format: SurfaceFormat::Argb32,
             data: vec![0; 640 * 480 * 4],
+            pixel_format: PixelFormat::Rgba8,
         };
 
         // Simulate continuous updates at 30 FPS (33.33ms intervals)
@@ -646,8 +650,9 @@ mod streaming_tests {
         let surface = DisplaySurface {
             width: 1920,
             height: 1080,
-            format: 0x1,
+            format: SurfaceFormat::Argb32,
             data: vec![0; 1920 * 1080 * 4],
+            pixel_format: PixelFormat::Rgba8,
         };
 
         // Simulate frames coming in too fast (every 10ms instead of 16.67ms)
@@ -679,8 +684,9 @@ mod streaming_tests {
         let surface = DisplaySurface {
             width: 3840,
             height: 2160,
-            format: 0x1,
+            format: SurfaceFormat::Argb32,
             data: vec![0; 3840 * 2160 * 4], // 4K resolution
+            pixel_format: PixelFormat::Rgba8,
         };
 
         // Process frames and simulate high encoding time
@@ -727,8 +733,9 @@ mod streaming_tests {
             let surface = DisplaySurface {
                 width,
                 height,
-                format: 0x1,
+                format: SurfaceFormat::Argb32,
                 data: vec![128; (width * height * 4) as usize],
+                pixel_format: PixelFormat::Rgba8,
             };
 
             streamer.reset_stats();
@@ -774,8 +781,9 @@ mod streaming_tests {
         let surface = DisplaySurface {
             width: 1920,
             height: 1080,
-            format: 0x1,
+            format: SurfaceFormat::Argb32,
             data: vec![0; 1920 * 1080 * 4],
+            pixel_format: PixelFormat::Rgba8,
         };
 
         // Fill buffer
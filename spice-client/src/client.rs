@@ -1,12 +1,27 @@
-use crate::channels::display::DisplayChannel;
-use crate::channels::main::MainChannel;
+use crate::channels::display::{DisplayChannel, PixelFormat};
+use crate::channels::main::{
+    complete_guest_monitors, MainChannel, MainEvent, MonitorInfo, ServerInfo,
+};
+#[cfg(target_arch = "wasm32")]
+use crate::channels::ConnectOptions;
+#[cfg(unix)]
+use crate::channels::UnixConnectionFactory;
+use crate::channels::{
+    AdvertisedCapabilities, ChannelConnection, ChannelState, ChannelStates, MediaClock, OaepHash,
+    QosGate, ServerCapabilities,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::channels::{ConnectionFactory, TcpConnectionFactory};
 use crate::error::{Result, SpiceError};
-use crate::protocol::ChannelType;
+use crate::protocol::{ChannelType, ImageCompression};
 use crate::video::{create_video_output, VideoOutput};
 
 use std::collections::HashMap;
-use std::sync::Arc;
-use tracing::{error, info};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
+use tracing::{error, info, warn};
 
 #[cfg(not(target_arch = "wasm32"))]
 use tokio::task::JoinHandle;
@@ -16,13 +31,41 @@ use tokio::task::JoinHandle;
 type TaskHandle = (); // Placeholder since we can't cancel wasm tasks easily
 
 pub struct SpiceClient {
+    /// Opens the connection of each channel
+    #[cfg(not(target_arch = "wasm32"))]
+    factory: Arc<dyn ConnectionFactory>,
+    #[cfg(target_arch = "wasm32")]
     host: String,
+    #[cfg(target_arch = "wasm32")]
     port: u16,
     #[cfg(target_arch = "wasm32")]
     websocket_url: Option<String>,
     #[cfg(target_arch = "wasm32")]
     auth_token: Option<String>,
+    #[cfg(target_arch = "wasm32")]
+    connect_options: ConnectOptions,
     password: Option<String>,
+    /// Whether the server accepted `password` on an earlier connect
+    ticket_accepted: bool,
+    oaep_hash: OaepHash,
+    oaep_fallback: bool,
+    preferred_compression: Option<ImageCompression>,
+    /// Byte order display surfaces are kept in
+    pixel_format: PixelFormat,
+    /// Display bandwidth target in kbit/s, 0 for none; shared with the
+    /// display channels
+    bandwidth_target: Arc<AtomicU32>,
+    server_info: Option<ServerInfo>,
+    server_capabilities: ServerCapabilities,
+    advertised_caps: AdvertisedCapabilities,
+    /// Lets main channel messages go ahead of display data
+    qos_gate: QosGate,
+    agent_connected: Arc<AtomicBool>,
+    guest_monitors: Arc<RwLock<Vec<MonitorInfo>>>,
+    media_clock: MediaClock,
+    /// Connection state of every channel seen since the last `connect`
+    channel_states: ChannelStates,
+    event_callback: Option<Arc<dyn Fn(&MainEvent) + Send + Sync>>,
     main_channel: Option<MainChannel>,
     display_channels: HashMap<u8, DisplayChannel>,
     #[cfg(not(target_arch = "wasm32"))]
@@ -35,13 +78,34 @@ pub struct SpiceClient {
 impl SpiceClient {
     pub fn new(host: String, port: u16) -> Self {
         Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            factory: Arc::new(TcpConnectionFactory::new(host, port)),
+            #[cfg(target_arch = "wasm32")]
             host,
+            #[cfg(target_arch = "wasm32")]
             port,
             #[cfg(target_arch = "wasm32")]
             websocket_url: None,
             #[cfg(target_arch = "wasm32")]
             auth_token: None,
+            #[cfg(target_arch = "wasm32")]
+            connect_options: ConnectOptions::default(),
             password: None,
+            ticket_accepted: false,
+            oaep_hash: OaepHash::default(),
+            oaep_fallback: true,
+            preferred_compression: None,
+            pixel_format: PixelFormat::default(),
+            bandwidth_target: Arc::new(AtomicU32::new(0)),
+            server_info: None,
+            server_capabilities: ServerCapabilities::default(),
+            advertised_caps: AdvertisedCapabilities::default(),
+            qos_gate: QosGate::new(),
+            agent_connected: Arc::new(AtomicBool::new(false)),
+            guest_monitors: Arc::new(RwLock::new(Vec::new())),
+            media_clock: MediaClock::new(),
+            channel_states: ChannelStates::default(),
+            event_callback: None,
             main_channel: None,
             display_channels: HashMap::new(),
             channel_tasks: Vec::new(),
@@ -49,6 +113,23 @@ impl SpiceClient {
         }
     }
 
+    /// Create a client for a server listening on a Unix domain socket, as
+    /// QEMU does with `-spice unix=on,addr=<path>`
+    #[cfg(unix)]
+    pub fn new_unix(path: impl Into<PathBuf>) -> Self {
+        Self::with_connection_factory(Arc::new(UnixConnectionFactory::new(path)))
+    }
+
+    /// Create a client that opens each channel's connection with `factory`,
+    /// e.g. over pipes to a QEMU chardev in the same process. See
+    /// [`ConnectionFactory`].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_connection_factory(factory: Arc<dyn ConnectionFactory>) -> Self {
+        let mut client = Self::new(String::new(), 0);
+        client.factory = factory;
+        client
+    }
+
     #[cfg(target_arch = "wasm32")]
     pub fn new_websocket(websocket_url: String) -> Self {
         Self::new_websocket_with_auth(websocket_url, None)
@@ -78,7 +159,23 @@ impl SpiceClient {
             port,
             websocket_url: Some(websocket_url),
             auth_token,
+            connect_options: ConnectOptions::default(),
             password: None,
+            ticket_accepted: false,
+            oaep_hash: OaepHash::default(),
+            oaep_fallback: true,
+            preferred_compression: None,
+            pixel_format: PixelFormat::default(),
+            bandwidth_target: Arc::new(AtomicU32::new(0)),
+            server_info: None,
+            server_capabilities: ServerCapabilities::default(),
+            advertised_caps: AdvertisedCapabilities::default(),
+            qos_gate: QosGate::new(),
+            agent_connected: Arc::new(AtomicBool::new(false)),
+            guest_monitors: Arc::new(RwLock::new(Vec::new())),
+            media_clock: MediaClock::new(),
+            channel_states: ChannelStates::default(),
+            event_callback: None,
             main_channel: None,
             display_channels: HashMap::new(),
             channel_tasks: Vec::new(),
@@ -86,23 +183,192 @@ impl SpiceClient {
         }
     }
 
+    /// Password for ticket authentication, used by the next `connect`
     pub fn set_password(&mut self, password: String) {
         self.password = Some(password);
+        self.ticket_accepted = false;
+    }
+
+    /// OAEP hash the ticket is encrypted with (SHA-1 by default). Takes
+    /// effect for channels linked after the call.
+    pub fn set_oaep_hash(&mut self, hash: OaepHash) {
+        self.oaep_hash = hash;
+    }
+
+    /// Whether a channel retries with the other OAEP hash when the server
+    /// rejects the ticket. Enabled by default.
+    pub fn set_oaep_fallback(&mut self, enabled: bool) {
+        self.oaep_fallback = enabled;
+    }
+
+    /// Override the capabilities advertised when linking channels. Takes
+    /// effect for channels linked after the call.
+    pub fn set_advertised_caps(&mut self, caps: AdvertisedCapabilities) {
+        self.advertised_caps = caps;
+    }
+
+    /// Deadlines and progress reporting for opening the WebSocket. Must be
+    /// set before `connect`.
+    #[cfg(target_arch = "wasm32")]
+    pub fn set_connect_options(&mut self, options: ConnectOptions) {
+        self.connect_options = options;
+    }
+
+    /// Ask the server to compress images with the given method. Only methods
+    /// this client can decode can be requested. Applies to display channels
+    /// that are connected and not yet running, and to future ones.
+    pub async fn set_preferred_compression(&mut self, compression: ImageCompression) -> Result<()> {
+        self.preferred_compression = Some(compression);
+        for display_channel in self.display_channels.values_mut() {
+            display_channel
+                .set_preferred_compression(compression)
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub fn preferred_compression(&self) -> Option<ImageCompression> {
+        self.preferred_compression
+    }
+
+    /// Keep display surfaces in `pixel_format` byte order, so a renderer
+    /// that wants e.g. BGRA can upload surface data without converting it.
+    /// Applies to connected and future display channels and to the frames
+    /// of the video output; RGBA by default.
+    pub fn set_pixel_format(&mut self, pixel_format: PixelFormat) {
+        self.pixel_format = pixel_format;
+        for display_channel in self.display_channels.values_mut() {
+            display_channel.set_pixel_format(pixel_format);
+        }
+        self.video_output.set_pixel_format(pixel_format);
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    /// Keep the display within `kbps` kbit/s, at the cost of frame rate, or
+    /// lift the limit with `None`. Applies to running and future display
+    /// channels; see [`DisplayChannel::set_bandwidth_target`].
+    pub fn set_bandwidth_target(&mut self, kbps: Option<u32>) {
+        self.bandwidth_target
+            .store(kbps.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn bandwidth_target(&self) -> Option<u32> {
+        Some(self.bandwidth_target.load(Ordering::Relaxed)).filter(|&kbps| kbps != 0)
+    }
+
+    /// Negotiated protocol version and server details, once connected
+    pub fn server_info(&self) -> Option<&ServerInfo> {
+        self.server_info.as_ref()
+    }
+
+    /// Capabilities the server advertised for the channels connected so far,
+    /// to check before using optional features such as LZ4 compression
+    pub fn server_capabilities(&self) -> &ServerCapabilities {
+        &self.server_capabilities
+    }
+
+    /// Whether the guest agent is connected. Clipboard sharing and automatic
+    /// resizing only work while it is.
+    pub fn is_agent_connected(&self) -> bool {
+        self.agent_connected.load(Ordering::SeqCst)
+    }
+
+    /// The guest's display heads and their resolutions, as the guest agent
+    /// reported them. Display channels the agent left out are added with
+    /// the size of their primary surface while this client still holds
+    /// them, i.e. before `start_event_loop`.
+    pub fn guest_monitors(&self) -> Vec<MonitorInfo> {
+        let reported = self
+            .guest_monitors
+            .read()
+            .map(|monitors| monitors.clone())
+            .unwrap_or_default();
+        let displays: Vec<(u8, u32, u32)> = self
+            .display_channels
+            .iter()
+            .filter_map(|(&channel_id, channel)| {
+                let surface = channel.get_primary_surface()?;
+                Some((channel_id, surface.width, surface.height))
+            })
+            .collect();
+        complete_guest_monitors(reported, &displays)
+    }
+
+    /// Server multimedia time in milliseconds, interpolated locally between
+    /// the server's updates. 0 until the main channel has received one.
+    pub fn media_time(&self) -> u32 {
+        self.media_clock.now()
+    }
+
+    /// Handle to the multimedia clock for playback and video stream timing
+    pub fn media_clock(&self) -> MediaClock {
+        self.media_clock.clone()
+    }
+
+    /// Called with main channel events such as the agent connecting or
+    /// disconnecting. Must be set before `connect`.
+    pub fn set_event_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(&MainEvent) + Send + Sync + 'static,
+    {
+        self.event_callback = Some(Arc::new(callback));
     }
 
+    fn record_capabilities(&mut self, channel_type: ChannelType, connection: &ChannelConnection) {
+        self.server_capabilities.record(
+            channel_type,
+            connection.server_common_caps(),
+            connection.server_channel_caps(),
+        );
+    }
+
+    /// Hook the main channel's agent state up to this client
+    fn track_main_channel(&mut self, main_channel: &mut MainChannel) {
+        self.record_capabilities(ChannelType::Main, &main_channel.connection);
+        self.agent_connected = main_channel.agent_connected_flag();
+        self.guest_monitors = main_channel.guest_monitors_handle();
+        self.media_clock = main_channel.media_clock();
+        if let Some(callback) = self.event_callback.clone() {
+            main_channel.set_event_callback(move |event| callback(event));
+        }
+    }
+
+    /// Connect to the server and link its channels.
+    ///
+    /// A refused password fails with [`SpiceError::AuthenticationFailed`],
+    /// or [`SpiceError::PasswordRequired`] when no password was set, and
+    /// closes every channel opened so far. Set the right password, or a
+    /// fresh ticket once an accepted one has expired, with
+    /// [`set_password`](Self::set_password) and call `connect` again; the
+    /// rest of the configuration is kept.
     pub async fn connect(&mut self) -> Result<()> {
         #[cfg(target_arch = "wasm32")]
         {
             if let Some(ref ws_url) = self.websocket_url {
                 info!("Connecting to SPICE server via WebSocket: {}", ws_url);
+                self.channel_states.clear();
+                self.channel_states
+                    .set(ChannelType::Main, 0, ChannelState::Connecting);
                 // Connect to main channel via WebSocket
-                let mut main_channel = MainChannel::new_websocket_with_password(
+                let connected = MainChannel::new_websocket_with_password(
                     ws_url,
                     self.auth_token.clone(),
                     self.password.clone(),
+                    self.connect_options.clone(),
                 )
-                .await?;
-                main_channel.initialize().await?;
+                .await;
+                let mut main_channel =
+                    self.channel_states
+                        .record_failure(ChannelType::Main, 0, connected)?;
+                self.track_main_channel(&mut main_channel);
+                let initialized = main_channel.initialize().await;
+                self.channel_states
+                    .record_failure(ChannelType::Main, 0, initialized)?;
+                self.channel_states
+                    .set(ChannelType::Main, 0, ChannelState::Connected);
 
                 // Get available channels
                 let channels = main_channel.get_channels_list().await?;
@@ -112,6 +378,7 @@ impl SpiceClient {
                 // The proxy creates one TCP connection per WebSocket, but SPICE expects multiple TCP connections
                 info!("Skipping additional channels - using main channel only for now");
 
+                self.server_info = Some(main_channel.server_info());
                 self.main_channel = Some(main_channel);
                 return Ok(());
             }
@@ -119,46 +386,17 @@ impl SpiceClient {
 
         #[cfg(not(target_arch = "wasm32"))]
         {
-            info!("Connecting to SPICE server at {}:{}", self.host, self.port);
-
-            // Connect to main channel first
-            info!("Creating main channel connection...");
-            let mut main_channel = MainChannel::new(&self.host, self.port).await?;
-            info!("Main channel created, initializing...");
-            main_channel.initialize().await?;
-            info!("Main channel initialized, getting channels list...");
-
-            // Get the session_id from main channel
-            let session_id = main_channel.get_session_id();
-            info!("Got session_id from main channel: {:?}", session_id);
-
-            // Get available channels
-            let channels = main_channel.get_channels_list().await?;
-            info!("Available channels: {:?}", channels);
-
-            // Connect to display channels
-            for (channel_type, channel_id) in channels {
-                match channel_type {
-                    ChannelType::Display => {
-                        let display_channel = DisplayChannel::new_with_connection_id(
-                            &self.host, self.port, channel_id, session_id,
-                        )
-                        .await?;
-                        self.display_channels.insert(channel_id, display_channel);
-                        info!(
-                            "Connected to display channel {} with connection_id = {}",
-                            channel_id,
-                            session_id.unwrap_or(0)
-                        );
-                    }
-                    _ => {
-                        info!("Ignoring channel type {:?} id {}", channel_type, channel_id);
-                    }
+            match self.connect_channels().await {
+                Ok(()) => {
+                    self.ticket_accepted = true;
+                    Ok(())
+                }
+                Err(e) => {
+                    // Close the channels that linked before the failure
+                    self.disconnect();
+                    Err(e.with_ticket_history(self.ticket_accepted))
                 }
             }
-
-            self.main_channel = Some(main_channel);
-            Ok(())
         }
 
         #[cfg(target_arch = "wasm32")]
@@ -167,6 +405,119 @@ impl SpiceClient {
         ))
     }
 
+    /// Link the main channel and every display channel it lists
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn connect_channels(&mut self) -> Result<()> {
+        // Connect to main channel first
+        info!("Creating main channel connection...");
+        self.channel_states.clear();
+        self.channel_states
+            .set(ChannelType::Main, 0, ChannelState::Connecting);
+        let connected = self.connect_main_channel().await;
+        let mut main_channel =
+            self.channel_states
+                .record_failure(ChannelType::Main, 0, connected)?;
+        self.track_main_channel(&mut main_channel);
+        info!("Main channel created, initializing...");
+        let initialized = main_channel.initialize().await;
+        self.channel_states
+            .record_failure(ChannelType::Main, 0, initialized)?;
+        self.channel_states
+            .set(ChannelType::Main, 0, ChannelState::Connected);
+        info!("Main channel initialized, getting channels list...");
+
+        // Get the session_id from main channel
+        let session_id = main_channel.get_session_id();
+        info!("Got session_id from main channel: {:?}", session_id);
+
+        // Get available channels
+        let channels = main_channel.get_channels_list().await?;
+        info!("Available channels: {:?}", channels);
+
+        // Connect to display channels
+        for (channel_type, channel_id) in channels {
+            match channel_type {
+                ChannelType::Display => {
+                    self.channel_states
+                        .set(channel_type, channel_id, ChannelState::Connecting);
+                    let connected = self.connect_display_channel(channel_id, session_id).await;
+                    let mut display_channel =
+                        self.channel_states
+                            .record_failure(channel_type, channel_id, connected)?;
+                    display_channel.set_media_clock(self.media_clock.clone());
+                    display_channel.set_pixel_format(self.pixel_format);
+                    display_channel.share_bandwidth_target(self.bandwidth_target.clone());
+                    self.record_capabilities(ChannelType::Display, &display_channel.connection);
+                    let compression = self
+                        .preferred_compression
+                        .or_else(|| display_channel.preferred_compression());
+                    if let Some(compression) = compression {
+                        if let Err(e) = display_channel.set_preferred_compression(compression).await
+                        {
+                            warn!(
+                                "Could not set preferred compression on display channel {}: {}",
+                                channel_id, e
+                            );
+                        }
+                    }
+                    self.display_channels.insert(channel_id, display_channel);
+                    self.channel_states
+                        .set(channel_type, channel_id, ChannelState::Connected);
+                    info!(
+                        "Connected to display channel {} with connection_id = {}",
+                        channel_id,
+                        session_id.unwrap_or(0)
+                    );
+                }
+                _ => {
+                    info!("Ignoring channel type {:?} id {}", channel_type, channel_id);
+                }
+            }
+        }
+
+        self.server_info = Some(main_channel.server_info());
+        self.main_channel = Some(main_channel);
+        Ok(())
+    }
+
+    /// Open an unlinked connection for a channel, carrying the ticket
+    /// settings and the capabilities to advertise
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn open_channel(
+        &self,
+        channel_type: ChannelType,
+        channel_id: u8,
+    ) -> Result<ChannelConnection> {
+        let mut connection =
+            ChannelConnection::from_factory(self.factory.clone(), channel_type, channel_id).await?;
+        if let Some(ref password) = self.password {
+            connection.set_password(password.clone());
+        }
+        connection.set_oaep_hash(self.oaep_hash);
+        connection.set_oaep_fallback(self.oaep_fallback);
+        connection.set_advertised_caps(self.advertised_caps.clone());
+        connection.set_qos_gate(Some(self.qos_gate.clone()));
+        Ok(connection)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn connect_main_channel(&self) -> Result<MainChannel> {
+        info!("Connecting to SPICE server at {}", self.factory.describe());
+
+        let connection = self.open_channel(ChannelType::Main, 0).await?;
+        MainChannel::from_connection(connection).await
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn connect_display_channel(
+        &self,
+        channel_id: u8,
+        session_id: Option<u32>,
+    ) -> Result<DisplayChannel> {
+        let connection = self.open_channel(ChannelType::Display, channel_id).await?;
+        DisplayChannel::from_connection(connection, session_id).await
+    }
+
     pub async fn start_event_loop(&mut self) -> Result<()> {
         if self.main_channel.is_none() {
             return Err(SpiceError::Protocol(
@@ -178,14 +529,22 @@ impl SpiceClient {
         {
             // Start main channel task
             if let Some(mut main_channel) = self.main_channel.take() {
-                let main_task = tokio::spawn(async move { main_channel.run().await });
+                let states = self.channel_states.clone();
+                let main_task = tokio::spawn(async move {
+                    let result = main_channel.run().await;
+                    states.record_exit(ChannelType::Main, 0, result)
+                });
                 self.channel_tasks.push(main_task);
             }
 
             // Start display channel tasks
             let display_channels = std::mem::take(&mut self.display_channels);
             for (channel_id, mut display_channel) in display_channels {
-                let display_task = tokio::spawn(async move { display_channel.run().await });
+                let states = self.channel_states.clone();
+                let display_task = tokio::spawn(async move {
+                    let result = display_channel.run().await;
+                    states.record_exit(ChannelType::Display, channel_id, result)
+                });
                 self.channel_tasks.push(display_task);
                 info!("Started event loop for display channel {}", channel_id);
             }
@@ -195,8 +554,10 @@ impl SpiceClient {
         {
             // In WASM, we use wasm_bindgen_futures::spawn_local for non-Send futures
             if let Some(mut main_channel) = self.main_channel.take() {
+                let states = self.channel_states.clone();
                 wasm_bindgen_futures::spawn_local(async move {
-                    if let Err(e) = main_channel.run().await {
+                    let result = main_channel.run().await;
+                    if let Err(e) = states.record_exit(ChannelType::Main, 0, result) {
                         error!("Main channel error: {}", e);
                     }
                 });
@@ -206,8 +567,10 @@ impl SpiceClient {
             // Start display channel tasks
             let display_channels = std::mem::take(&mut self.display_channels);
             for (channel_id, mut display_channel) in display_channels {
+                let states = self.channel_states.clone();
                 wasm_bindgen_futures::spawn_local(async move {
-                    if let Err(e) = display_channel.run().await {
+                    let result = display_channel.run().await;
+                    if let Err(e) = states.record_exit(ChannelType::Display, channel_id, result) {
                         error!("Display channel {} error: {}", channel_id, e);
                     }
                 });
@@ -219,6 +582,13 @@ impl SpiceClient {
         Ok(())
     }
 
+    /// Connection state of each channel linked since the last `connect`,
+    /// main first. A failed channel carries its last error, so a diagnostics
+    /// view can show e.g. a display that is up while another one dropped.
+    pub fn channel_states(&self) -> Vec<(ChannelType, u8, ChannelState)> {
+        self.channel_states.snapshot()
+    }
+
     pub async fn get_display_surface(
         &self,
         channel_id: u8,
@@ -228,6 +598,22 @@ impl SpiceClient {
             .get_primary_surface()
     }
 
+    /// Surface of a display channel shown on its monitor at `index`, while
+    /// this client still holds the channel, i.e. before `start_event_loop`
+    pub fn surface_for_monitor(&self, channel_id: u8, index: usize) -> Option<u32> {
+        self.display_channels
+            .get(&channel_id)?
+            .surface_for_monitor(index)
+    }
+
+    /// Monitor of a display channel that shows `surface_id`, while this
+    /// client still holds the channel, i.e. before `start_event_loop`
+    pub fn monitor_for_surface(&self, channel_id: u8, surface_id: u32) -> Option<usize> {
+        self.display_channels
+            .get(&channel_id)?
+            .monitor_for_surface(surface_id)
+    }
+
     pub fn get_video_output(&self) -> Arc<dyn VideoOutput> {
         self.video_output.clone()
     }
@@ -286,6 +672,7 @@ impl SpiceClient {
         // Clear channels and schedule video output clearing
         self.main_channel = None;
         self.display_channels.clear();
+        self.channel_states.close_all();
 
         // Video output will be cleared when new frames arrive
     }
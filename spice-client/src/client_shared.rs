@@ -1,19 +1,33 @@
-use crate::channels::cursor::{CursorChannel, CursorShape};
-use crate::channels::display::DisplayChannel;
-use crate::channels::inputs::InputsChannel;
-use crate::channels::main::MainChannel;
-use crate::channels::MouseButton;
-use crate::error::{Result, SpiceError};
-use crate::protocol::ChannelType;
+use crate::channels::cursor::{CursorChannel, CursorEvent, CursorShape};
+use crate::channels::display::{
+    DisplayChannel, DisplaySurface, InspectHandle, PixelFormat, RefreshHandle,
+};
+use crate::channels::inputs::{InputCommand, InputQueue, InputsChannel};
+use crate::channels::main::{
+    complete_guest_monitors, MainChannel, MainEvent, MonitorInfo, ServerInfo,
+};
+#[cfg(target_arch = "wasm32")]
+use crate::channels::ConnectOptions;
+#[cfg(unix)]
+use crate::channels::UnixConnectionFactory;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::channels::{
+    ChannelConnection, ConnectionFactory, TcpConnectionFactory, WebSocketConnectionFactory,
+};
+use crate::channels::{
+    Direction, InputEvent, KeyboardLayout, KeymapProvider, MouseButton, OaepHash, QosGate,
+    TraceHook,
+};
+use crate::error::{DisconnectInfo, Result, SpiceError};
+use crate::protocol::{ChannelType, ImageCompression, SpiceRect};
 use crate::utils::sleep;
-use crate::video::{create_video_output, VideoOutput};
-use instant::Duration;
+use crate::video::{create_video_output_with_format, VideoOutput};
+use instant::{Duration, Instant};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
 use tokio::sync::Mutex;
-#[cfg(target_arch = "wasm32")]
-use tracing::warn;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 #[cfg(not(target_arch = "wasm32"))]
 use tokio::task::JoinHandle;
@@ -21,25 +35,77 @@ use tokio::task::JoinHandle;
 #[cfg(target_arch = "wasm32")]
 type TaskHandle = ();
 
+/// Called for every display update with the display channel id, the surface
+/// id, the surface and the area that changed
+pub type FrameCallback = Arc<dyn Fn(u8, u32, &DisplaySurface, &SpiceRect) + Send + Sync>;
+
+/// Called with the cursor channel id when the server moves, shows or hides
+/// the cursor
+pub type CursorCallback = Arc<dyn Fn(u8, &CursorEvent) + Send + Sync>;
+
 pub struct SpiceClientInner {
+    /// Opens the connection of each channel
+    #[cfg(not(target_arch = "wasm32"))]
+    factory: Arc<dyn ConnectionFactory>,
+    #[cfg(target_arch = "wasm32")]
     host: String,
+    #[cfg(target_arch = "wasm32")]
     port: u16,
     #[cfg(target_arch = "wasm32")]
     websocket_url: Option<String>,
     #[cfg(target_arch = "wasm32")]
     auth_token: Option<String>,
+    #[cfg(target_arch = "wasm32")]
+    connect_options: ConnectOptions,
     password: Option<String>,
+    /// Whether the server accepted `password` on an earlier connect
+    ticket_accepted: bool,
+    oaep_hash: OaepHash,
+    oaep_fallback: bool,
+    keepalive: Option<Duration>,
+    strict_messages: bool,
+    trace_hook: Option<TraceHook>,
+    /// Lets main, inputs and cursor messages go ahead of display data
+    qos_gate: QosGate,
+    keyboard_layout: KeyboardLayout,
+    /// Overrides `keyboard_layout` for key events when set
+    keymap: Option<Arc<dyn KeymapProvider>>,
+    preferred_compression: Option<ImageCompression>,
+    /// Byte order display surfaces are kept in
+    pixel_format: PixelFormat,
+    /// Display bandwidth target in kbit/s, 0 for none; shared with the
+    /// display channels
+    bandwidth_target: Arc<AtomicU32>,
+    server_info: Option<ServerInfo>,
+    agent_connected: Arc<AtomicBool>,
+    /// Display heads the guest agent reported, shared with the main channel
+    guest_monitors: Arc<std::sync::RwLock<Vec<MonitorInfo>>>,
+    event_callback: Option<Arc<dyn Fn(&MainEvent) + Send + Sync>>,
+    /// Shared with the display channels, so it can change while they run
+    frame_callback: Arc<std::sync::RwLock<Option<FrameCallback>>>,
+    /// Shared with the cursor channels, so it can change while they run
+    cursor_callback: Arc<std::sync::RwLock<Option<CursorCallback>>>,
     main_channel: Option<Arc<Mutex<MainChannel>>>,
     display_channels: HashMap<u8, Arc<Mutex<DisplayChannel>>>,
+    /// Repaint requests for display channels whose event loop is running
+    refresh_handles: HashMap<u8, RefreshHandle>,
+    /// Reads of display channels whose event loop is running
+    inspect_handles: HashMap<u8, InspectHandle>,
     inputs_channels: HashMap<u8, Arc<Mutex<InputsChannel>>>,
+    /// Input queues of inputs channels whose event loop is running
+    input_queues: HashMap<u8, InputQueue>,
     cursor_channels: HashMap<u8, Arc<Mutex<CursorChannel>>>,
     #[cfg(not(target_arch = "wasm32"))]
     channel_tasks: Vec<JoinHandle<Result<()>>>,
     #[cfg(target_arch = "wasm32")]
     channel_tasks: Vec<TaskHandle>,
-    video_output: Arc<dyn VideoOutput>,
-    #[cfg(target_arch = "wasm32")]
-    error_state: Arc<std::sync::Mutex<Option<String>>>,
+    /// Video output of each display channel, kept across reconnects so
+    /// frontends can hold on to them
+    video_outputs: HashMap<u8, Arc<dyn VideoOutput>>,
+    /// Last primary surface of each display channel from before a reconnect
+    last_frames: HashMap<u8, DisplaySurface>,
+    /// Why the last channel event loop ended, if one has
+    error_state: Arc<std::sync::Mutex<Option<DisconnectInfo>>>,
 }
 
 /// Shared SPICE client implementation that works on both native and WebAssembly targets.
@@ -89,25 +155,89 @@ impl SpiceClientShared {
     pub fn new(host: String, port: u16) -> Self {
         Self {
             inner: Arc::new(Mutex::new(SpiceClientInner {
+                #[cfg(not(target_arch = "wasm32"))]
+                factory: Arc::new(TcpConnectionFactory::new(host, port)),
+                #[cfg(target_arch = "wasm32")]
                 host,
+                #[cfg(target_arch = "wasm32")]
                 port,
                 #[cfg(target_arch = "wasm32")]
                 websocket_url: None,
                 #[cfg(target_arch = "wasm32")]
                 auth_token: None,
+                #[cfg(target_arch = "wasm32")]
+                connect_options: ConnectOptions::default(),
                 password: None,
+                ticket_accepted: false,
+                oaep_hash: OaepHash::default(),
+                oaep_fallback: true,
+                keepalive: None,
+                strict_messages: false,
+                trace_hook: None,
+                qos_gate: QosGate::new(),
+                keyboard_layout: KeyboardLayout::default(),
+                keymap: None,
+                preferred_compression: None,
+                pixel_format: PixelFormat::default(),
+                bandwidth_target: Arc::new(AtomicU32::new(0)),
+                server_info: None,
+                agent_connected: Arc::new(AtomicBool::new(false)),
+                guest_monitors: Arc::new(std::sync::RwLock::new(Vec::new())),
+                event_callback: None,
+                frame_callback: Arc::new(std::sync::RwLock::new(None)),
+                cursor_callback: Arc::new(std::sync::RwLock::new(None)),
                 main_channel: None,
                 display_channels: HashMap::new(),
+                refresh_handles: HashMap::new(),
+                inspect_handles: HashMap::new(),
                 inputs_channels: HashMap::new(),
+                input_queues: HashMap::new(),
                 cursor_channels: HashMap::new(),
                 channel_tasks: Vec::new(),
-                video_output: create_video_output(),
-                #[cfg(target_arch = "wasm32")]
+                video_outputs: HashMap::new(),
+                last_frames: HashMap::new(),
                 error_state: Arc::new(std::sync::Mutex::new(None)),
             })),
         }
     }
 
+    /// Creates a SPICE client for a server listening on a Unix domain socket,
+    /// as QEMU does with `-spice unix=on,addr=<path>`
+    #[cfg(unix)]
+    pub fn new_unix(path: impl Into<std::path::PathBuf>) -> Self {
+        Self::with_connection_factory(Arc::new(UnixConnectionFactory::new(path)))
+    }
+
+    /// Creates a SPICE client that opens each channel's connection with
+    /// `factory`. See [`ConnectionFactory`].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_connection_factory(factory: Arc<dyn ConnectionFactory>) -> Self {
+        let mut client = Self::new(String::new(), 0);
+        if let Some(inner) = Arc::get_mut(&mut client.inner) {
+            inner.get_mut().factory = factory;
+        }
+        client
+    }
+
+    /// Creates a SPICE client that connects each channel through the
+    /// WebSocket proxy at `websocket_url`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new_websocket(websocket_url: String) -> Self {
+        Self::new_websocket_with_auth(websocket_url, None)
+    }
+
+    /// Creates a SPICE client that connects each channel through the
+    /// WebSocket proxy at `websocket_url`, sending `auth_token` to the proxy
+    /// first when there is one
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new_websocket_with_auth(websocket_url: String, auth_token: Option<String>) -> Self {
+        let mut factory = WebSocketConnectionFactory::new(websocket_url);
+        if let Some(token) = auth_token {
+            factory = factory.with_auth_token(token);
+        }
+        Self::with_connection_factory(Arc::new(factory))
+    }
+
     /// Creates a new SPICE client for WebAssembly using a WebSocket connection.
     ///
     /// This method is only available when compiling for WebAssembly targets.
@@ -175,14 +305,36 @@ impl SpiceClientShared {
                 port,
                 websocket_url: Some(websocket_url),
                 auth_token,
+                connect_options: ConnectOptions::default(),
                 password: None,
+                ticket_accepted: false,
+                oaep_hash: OaepHash::default(),
+                oaep_fallback: true,
+                keepalive: None,
+                strict_messages: false,
+                trace_hook: None,
+                qos_gate: QosGate::new(),
+                keyboard_layout: KeyboardLayout::default(),
+                keymap: None,
+                preferred_compression: None,
+                pixel_format: PixelFormat::default(),
+                bandwidth_target: Arc::new(AtomicU32::new(0)),
+                server_info: None,
+                agent_connected: Arc::new(AtomicBool::new(false)),
+                guest_monitors: Arc::new(std::sync::RwLock::new(Vec::new())),
+                event_callback: None,
+                frame_callback: Arc::new(std::sync::RwLock::new(None)),
+                cursor_callback: Arc::new(std::sync::RwLock::new(None)),
                 main_channel: None,
                 display_channels: HashMap::new(),
+                refresh_handles: HashMap::new(),
+                inspect_handles: HashMap::new(),
                 inputs_channels: HashMap::new(),
+                input_queues: HashMap::new(),
                 cursor_channels: HashMap::new(),
                 channel_tasks: Vec::new(),
-                video_output: create_video_output(),
-                #[cfg(target_arch = "wasm32")]
+                video_outputs: HashMap::new(),
+                last_frames: HashMap::new(),
                 error_state: Arc::new(std::sync::Mutex::new(None)),
             })),
         }
@@ -211,23 +363,558 @@ impl SpiceClientShared {
     pub async fn set_password(&mut self, password: String) {
         let mut inner = self.inner.lock().await;
         inner.password = Some(password);
+        inner.ticket_accepted = false;
+    }
+
+    /// Sets the OAEP hash the ticket is encrypted with on every channel
+    /// connection. SHA-1 by default. Must be set before calling `connect()`.
+    pub async fn set_oaep_hash(&mut self, hash: OaepHash) {
+        let mut inner = self.inner.lock().await;
+        inner.oaep_hash = hash;
+    }
+
+    /// Sets whether a channel retries with the other OAEP hash when the
+    /// server rejects the ticket. Enabled by default. Must be set before
+    /// calling `connect()`.
+    pub async fn set_oaep_fallback(&mut self, enabled: bool) {
+        let mut inner = self.inner.lock().await;
+        inner.oaep_fallback = enabled;
+    }
+
+    /// Sets the keepalive interval used on every channel connection.
+    ///
+    /// When set, idle connections are probed so that a connection silently
+    /// dropped by a NAT or proxy idle timeout is detected. Native connections
+    /// use TCP keepalive; WebSocket connections periodically send a small
+    /// message. Must be set before calling `connect()`. Disabled by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval` - Idle time before probing, or `None` to disable
+    pub async fn set_keepalive(&mut self, interval: Option<Duration>) {
+        let mut inner = self.inner.lock().await;
+        inner.keepalive = interval;
+    }
+
+    /// Sets how long each WebSocket channel may take to open and to
+    /// authenticate with the proxy, and a callback told as every channel
+    /// moves through the [`ConnectPhase`](crate::channels::ConnectPhase)s.
+    /// Must be set before calling `connect()`.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn set_connect_options(&mut self, options: ConnectOptions) {
+        let mut inner = self.inner.lock().await;
+        inner.connect_options = options;
+    }
+
+    /// Sets whether a malformed message ends the channel.
+    ///
+    /// By default messages that fail to parse are logged and skipped, so a
+    /// server sending newer or unknown message variants doesn't drop the
+    /// session. Enable strict mode to surface these errors while debugging.
+    /// Must be set before calling `connect()`.
+    pub async fn set_strict_message_parsing(&mut self, strict: bool) {
+        let mut inner = self.inner.lock().await;
+        inner.strict_messages = strict;
+    }
+
+    /// Sets a hook that sees the raw bytes of every channel, for dumping a
+    /// protocol trace while debugging interoperability problems.
+    ///
+    /// The hook gets the channel type and id, the direction, and the bytes as
+    /// they went over the wire. Applies to connected and future channels,
+    /// starting after each channel's link handshake; use
+    /// [`ChannelConnection::set_trace_hook`](crate::channels::ChannelConnection::set_trace_hook)
+    /// to capture a handshake.
+    pub async fn set_trace_hook<F>(&self, hook: F)
+    where
+        F: Fn(ChannelType, u8, Direction, &[u8]) + Send + Sync + 'static,
+    {
+        let mut inner = self.inner.lock().await;
+        inner.trace_hook = Some(Arc::new(hook));
+        Self::apply_trace_hook(&inner).await;
+    }
+
+    /// Removes the hook set with [`set_trace_hook`](Self::set_trace_hook).
+    pub async fn clear_trace_hook(&self) {
+        let mut inner = self.inner.lock().await;
+        inner.trace_hook = None;
+        Self::apply_trace_hook(&inner).await;
+    }
+
+    /// Returns the negotiated protocol version and server details.
+    ///
+    /// Useful for diagnosing interoperability problems between QEMU versions.
+    /// Returns `None` until `connect()` has succeeded.
+    pub async fn server_info(&self) -> Option<ServerInfo> {
+        self.inner.lock().await.server_info.clone()
+    }
+
+    /// Returns whether the guest agent is connected.
+    ///
+    /// Clipboard sharing and automatic resizing need the agent, so frontends
+    /// should disable them while this is `false`.
+    pub async fn is_agent_connected(&self) -> bool {
+        self.inner
+            .lock()
+            .await
+            .agent_connected
+            .load(Ordering::SeqCst)
+    }
+
+    /// Returns the guest's display heads and their resolutions, so a
+    /// frontend can show them or pick the one to fit its window to.
+    ///
+    /// The heads come from the guest agent. Display channels the agent
+    /// didn't report are added with the size of their primary surface, so
+    /// without an agent this lists one head per display channel.
+    pub async fn guest_monitors(&self) -> Vec<MonitorInfo> {
+        let (reported, channel_ids) = {
+            let inner = self.inner.lock().await;
+            let reported = inner
+                .guest_monitors
+                .read()
+                .map(|monitors| monitors.clone())
+                .unwrap_or_default();
+            let channel_ids: Vec<u8> = inner.display_channels.keys().copied().collect();
+            (reported, channel_ids)
+        };
+
+        let mut displays = Vec::new();
+        for channel_id in channel_ids {
+            if let Some(surface) = self.get_display_surface(channel_id).await {
+                displays.push((channel_id, surface.width, surface.height));
+            }
+        }
+        complete_guest_monitors(reported, &displays)
+    }
+
+    /// Sets a callback for main channel events, such as the guest agent
+    /// connecting or disconnecting.
+    ///
+    /// Must be called before `connect()` to see the agent state reported
+    /// during the initial handshake.
+    pub async fn set_event_callback<F>(&self, callback: F)
+    where
+        F: Fn(&MainEvent) + Send + Sync + 'static,
+    {
+        self.inner.lock().await.event_callback = Some(Arc::new(callback));
+    }
+
+    /// Sets a callback that sees every display update, for frontends that
+    /// render the frames themselves.
+    ///
+    /// The callback gets the display channel id, the surface id, the surface
+    /// and the changed area, clipped to the surface. It runs on the display
+    /// channel's event loop while the surface is borrowed, so it should copy
+    /// what it needs and return quickly. Can be changed at any time, also
+    /// while the event loop is running.
+    pub async fn set_frame_callback<F>(&self, callback: F)
+    where
+        F: Fn(u8, u32, &DisplaySurface, &SpiceRect) + Send + Sync + 'static,
+    {
+        let inner = self.inner.lock().await;
+        let slot = inner.frame_callback.write();
+        if let Ok(mut slot) = slot {
+            *slot = Some(Arc::new(callback));
+        }
+    }
+
+    /// Removes the callback set with [`set_frame_callback`](Self::set_frame_callback).
+    pub async fn clear_frame_callback(&self) {
+        let inner = self.inner.lock().await;
+        let slot = inner.frame_callback.write();
+        if let Ok(mut slot) = slot {
+            *slot = None;
+        }
+    }
+
+    /// Sets a callback that sees where the server puts the cursor and when
+    /// it shows or hides it, so a frontend can draw the pointer where the
+    /// guest has it in server mouse mode.
+    ///
+    /// The callback gets the cursor channel id and runs on the cursor
+    /// channel's event loop.
+    pub async fn set_cursor_callback<F>(&self, callback: F)
+    where
+        F: Fn(u8, &CursorEvent) + Send + Sync + 'static,
+    {
+        let inner = self.inner.lock().await;
+        let slot = inner.cursor_callback.write();
+        if let Ok(mut slot) = slot {
+            *slot = Some(Arc::new(callback));
+        }
+    }
+
+    /// Removes the callback set with [`set_cursor_callback`](Self::set_cursor_callback).
+    pub async fn clear_cursor_callback(&self) {
+        let inner = self.inner.lock().await;
+        let slot = inner.cursor_callback.write();
+        if let Ok(mut slot) = slot {
+            *slot = None;
+        }
+    }
+
+    /// Forwards a display channel's updates to the frame callback.
+    fn track_display_channel(
+        inner: &SpiceClientInner,
+        channel_id: u8,
+        display_channel: &mut DisplayChannel,
+    ) {
+        display_channel.set_pixel_format(inner.pixel_format);
+        display_channel.share_bandwidth_target(inner.bandwidth_target.clone());
+        let frame_callback = inner.frame_callback.clone();
+        display_channel.set_damage_callback(move |surface_id, surface, rect| {
+            let callback = frame_callback.read().ok().and_then(|slot| slot.clone());
+            if let Some(callback) = callback {
+                callback(channel_id, surface_id, surface, rect);
+            }
+        });
+    }
+
+    /// Forwards a cursor channel's events to the cursor callback.
+    fn track_cursor_channel(
+        inner: &SpiceClientInner,
+        channel_id: u8,
+        cursor_channel: &mut CursorChannel,
+    ) {
+        let cursor_callback = inner.cursor_callback.clone();
+        cursor_channel.set_event_callback(move |event| {
+            let callback = cursor_callback.read().ok().and_then(|slot| slot.clone());
+            if let Some(callback) = callback {
+                callback(channel_id, event);
+            }
+        });
+    }
+
+    /// The video output of a display channel, created on first use
+    fn channel_video_output(inner: &mut SpiceClientInner, channel_id: u8) -> Arc<dyn VideoOutput> {
+        let pixel_format = inner.pixel_format;
+        inner
+            .video_outputs
+            .entry(channel_id)
+            .or_insert_with(|| create_video_output_with_format(pixel_format))
+            .clone()
+    }
+
+    /// Hooks the main channel's agent state up to this client.
+    fn track_main_channel(inner: &mut SpiceClientInner, main_channel: &mut MainChannel) {
+        inner.agent_connected = main_channel.agent_connected_flag();
+        inner.guest_monitors = main_channel.guest_monitors_handle();
+        if let Some(callback) = inner.event_callback.clone() {
+            main_channel.set_event_callback(move |event| callback(event));
+        }
+    }
+
+    /// Reports a secondary channel that could not be connected.
+    ///
+    /// A missing channel doesn't fail the connection, so the session keeps
+    /// whatever channels the server did provide.
+    fn report_channel_failure(
+        inner: &SpiceClientInner,
+        channel_type: ChannelType,
+        channel_id: u8,
+        error: &SpiceError,
+    ) {
+        warn!(
+            "Continuing without {:?} channel {}: {}",
+            channel_type, channel_id, error
+        );
+        if let Some(ref callback) = inner.event_callback {
+            callback(&MainEvent::ChannelUnavailable {
+                channel_type,
+                channel_id,
+            });
+        }
+    }
+
+    fn is_channel_wanted(wanted: Option<&[ChannelType]>, channel_type: ChannelType) -> bool {
+        wanted.map_or(true, |wanted| wanted.contains(&channel_type))
+    }
+
+    /// Returns the secondary channels that are connected.
+    pub async fn connected_channels(&self) -> Vec<(ChannelType, u8)> {
+        let inner = self.inner.lock().await;
+        let mut channels = Vec::new();
+        for id in inner.display_channels.keys() {
+            channels.push((ChannelType::Display, *id));
+        }
+        for id in inner.inputs_channels.keys() {
+            channels.push((ChannelType::Inputs, *id));
+        }
+        for id in inner.cursor_channels.keys() {
+            channels.push((ChannelType::Cursor, *id));
+        }
+        channels.sort_by_key(|&(channel_type, id)| (channel_type as u8, id));
+        channels
+    }
+
+    /// Sets the guest's keyboard layout.
+    ///
+    /// The layout decides which scancode is sent for `KeyCode::Char` keys in
+    /// [`send_input_event`](Self::send_input_event), so characters type
+    /// correctly on non-US guests. Applies to connected and future inputs
+    /// channels.
+    pub async fn set_keyboard_layout(&self, layout: KeyboardLayout) {
+        let mut inner = self.inner.lock().await;
+        inner.keyboard_layout = layout;
+        for channel in inner.inputs_channels.values() {
+            channel.lock().await.set_keyboard_layout(layout);
+        }
     }
 
-    /// Gets the current error state of the client (WebAssembly only).
+    /// Translates key events with `keymap` instead of the keyboard layout,
+    /// e.g. one that knows the user's layout; `None` goes back to the
+    /// layout. Applies to connected and future inputs channels.
+    pub async fn set_keymap_provider(&self, keymap: Option<Arc<dyn KeymapProvider>>) {
+        let mut inner = self.inner.lock().await;
+        inner.keymap = keymap.clone();
+        for channel in inner.inputs_channels.values() {
+            channel.lock().await.set_keymap_provider(keymap.clone());
+        }
+    }
+
+    /// Asks the server to use the given image compression.
+    ///
+    /// Only methods this client can decode can be requested. Applies to
+    /// connected and future display channels.
+    ///
+    /// # Errors
     ///
-    /// This method is only available on WebAssembly targets and returns any
-    /// error message that occurred during connection or operation. This is useful
-    /// for debugging connection issues in web browsers.
+    /// Returns an error if a connected server doesn't support setting the
+    /// preferred compression.
+    pub async fn set_preferred_compression(&self, compression: ImageCompression) -> Result<()> {
+        let mut inner = self.inner.lock().await;
+        inner.preferred_compression = Some(compression);
+        for channel in inner.display_channels.values() {
+            channel
+                .lock()
+                .await
+                .set_preferred_compression(compression)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the image compression requested with
+    /// [`set_preferred_compression`](Self::set_preferred_compression).
+    pub async fn preferred_compression(&self) -> Option<ImageCompression> {
+        self.inner.lock().await.preferred_compression
+    }
+
+    /// Keeps display surfaces in `pixel_format` byte order, so a renderer
+    /// that wants e.g. BGRA can upload surface data without converting it.
+    ///
+    /// Applies to future display channels and to connected ones whose event
+    /// loop hasn't started; running channels pick it up when they reconnect.
+    /// The video outputs emit their frames in the same order, so neither
+    /// side swaps bytes. Surfaces are kept as RGBA unless this is called.
+    pub async fn set_pixel_format(&self, pixel_format: PixelFormat) {
+        let mut inner = self.inner.lock().await;
+        inner.pixel_format = pixel_format;
+        for channel in inner.display_channels.values() {
+            // A running event loop holds the channel for as long as it runs
+            if let Ok(mut channel) = channel.try_lock() {
+                channel.set_pixel_format(pixel_format);
+            }
+        }
+        for output in inner.video_outputs.values() {
+            output.set_pixel_format(pixel_format);
+        }
+    }
+
+    /// Returns the byte order set with
+    /// [`set_pixel_format`](Self::set_pixel_format).
+    pub async fn pixel_format(&self) -> PixelFormat {
+        self.inner.lock().await.pixel_format
+    }
+
+    /// Keeps the display within `kbps` kbit/s on slow or metered links, at
+    /// the cost of frame rate, or lifts the limit with `None`.
+    ///
+    /// Updates are held back and coalesced so the data behind them arrives
+    /// no faster than the target, and stream frames that arrive meanwhile
+    /// are reported to the server as dropped so it lowers their bitrate;
+    /// see [`DisplayChannel::set_bandwidth_target`]. Applies to running and
+    /// future display channels.
+    pub async fn set_bandwidth_target(&self, kbps: Option<u32>) {
+        let inner = self.inner.lock().await;
+        inner
+            .bandwidth_target
+            .store(kbps.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Returns the limit set with
+    /// [`set_bandwidth_target`](Self::set_bandwidth_target).
+    pub async fn bandwidth_target(&self) -> Option<u32> {
+        let inner = self.inner.lock().await;
+        Some(inner.bandwidth_target.load(Ordering::Relaxed)).filter(|&kbps| kbps != 0)
+    }
+
+    /// Sends the stored compression preference on a newly connected display
+    /// channel, or the one its decoders favor if none was set. A server that
+    /// can't honor it is not an error.
+    async fn apply_preferred_compression(
+        inner: &SpiceClientInner,
+        display_channel: &mut DisplayChannel,
+    ) {
+        let compression = inner
+            .preferred_compression
+            .or_else(|| display_channel.preferred_compression());
+        if let Some(compression) = compression {
+            if let Err(e) = display_channel.set_preferred_compression(compression).await {
+                warn!(
+                    "Could not set preferred compression on display channel {}: {}",
+                    display_channel.connection.channel_id, e
+                );
+            }
+        }
+    }
+
+    /// Returns the guest keyboard layout used for character keys.
+    pub async fn keyboard_layout(&self) -> KeyboardLayout {
+        self.inner.lock().await.keyboard_layout
+    }
+
+    /// Applies the configured message strictness to all connected channels.
+    async fn apply_strict_messages(inner: &SpiceClientInner) {
+        if let Some(ref main_channel) = inner.main_channel {
+            main_channel
+                .lock()
+                .await
+                .connection
+                .set_strict(inner.strict_messages);
+        }
+        for channel in inner.display_channels.values() {
+            channel
+                .lock()
+                .await
+                .connection
+                .set_strict(inner.strict_messages);
+        }
+        for channel in inner.inputs_channels.values() {
+            channel
+                .lock()
+                .await
+                .connection
+                .set_strict(inner.strict_messages);
+        }
+        for channel in inner.cursor_channels.values() {
+            channel
+                .lock()
+                .await
+                .connection
+                .set_strict(inner.strict_messages);
+        }
+    }
+
+    /// Applies the configured trace hook to all connected channels.
+    async fn apply_trace_hook(inner: &SpiceClientInner) {
+        if let Some(ref main_channel) = inner.main_channel {
+            main_channel
+                .lock()
+                .await
+                .connection
+                .set_trace_hook(inner.trace_hook.clone());
+        }
+        for channel in inner.display_channels.values() {
+            channel
+                .lock()
+                .await
+                .connection
+                .set_trace_hook(inner.trace_hook.clone());
+        }
+        for channel in inner.inputs_channels.values() {
+            channel
+                .lock()
+                .await
+                .connection
+                .set_trace_hook(inner.trace_hook.clone());
+        }
+        for channel in inner.cursor_channels.values() {
+            channel
+                .lock()
+                .await
+                .connection
+                .set_trace_hook(inner.trace_hook.clone());
+        }
+    }
+
+    /// Shares the client's priority gate with all connected channels.
+    async fn apply_qos_gate(inner: &SpiceClientInner) {
+        let gate = Some(inner.qos_gate.clone());
+        if let Some(ref main_channel) = inner.main_channel {
+            main_channel
+                .lock()
+                .await
+                .connection
+                .set_qos_gate(gate.clone());
+        }
+        for channel in inner.display_channels.values() {
+            channel.lock().await.connection.set_qos_gate(gate.clone());
+        }
+        for channel in inner.inputs_channels.values() {
+            channel.lock().await.connection.set_qos_gate(gate.clone());
+        }
+        for channel in inner.cursor_channels.values() {
+            channel.lock().await.connection.set_qos_gate(gate.clone());
+        }
+    }
+
+    /// Applies the configured keepalive to all connected channels.
+    async fn apply_keepalive(inner: &SpiceClientInner) -> Result<()> {
+        if inner.keepalive.is_none() {
+            return Ok(());
+        }
+
+        if let Some(ref main_channel) = inner.main_channel {
+            main_channel
+                .lock()
+                .await
+                .connection
+                .set_keepalive(inner.keepalive)?;
+        }
+        for channel in inner.display_channels.values() {
+            channel
+                .lock()
+                .await
+                .connection
+                .set_keepalive(inner.keepalive)?;
+        }
+        for channel in inner.inputs_channels.values() {
+            channel
+                .lock()
+                .await
+                .connection
+                .set_keepalive(inner.keepalive)?;
+        }
+        for channel in inner.cursor_channels.values() {
+            channel
+                .lock()
+                .await
+                .connection
+                .set_keepalive(inner.keepalive)?;
+        }
+        Ok(())
+    }
+
+    /// Gets the current error state of the client.
+    ///
+    /// Returns the message of [`get_disconnect_info`](Self::get_disconnect_info),
+    /// for callers that only show the error. This is useful for debugging
+    /// connection issues in web browsers.
     ///
     /// # Returns
     ///
     /// * `Some(String)` - The error message if an error occurred
     /// * `None` - If no error has occurred
-    #[cfg(target_arch = "wasm32")]
     pub async fn get_error_state(&self) -> Option<String> {
-        let inner = self.inner.lock().await;
-        let error_state = inner.error_state.clone();
-        drop(inner);
+        self.get_disconnect_info().await.map(|info| info.message)
+    }
+
+    /// Gets why a channel's event loop ended, if one has since
+    /// [`start_event_loop`](Self::start_event_loop).
+    pub async fn get_disconnect_info(&self) -> Option<DisconnectInfo> {
+        let error_state = self.inner.lock().await.error_state.clone();
         let result = error_state.lock().unwrap().clone();
         result
     }
@@ -261,6 +948,67 @@ impl SpiceClientShared {
     /// # }
     /// ```
     pub async fn connect(&self) -> Result<()> {
+        self.connect_channels(None).await
+    }
+
+    /// Connects like [`connect`](Self::connect), but only opens the
+    /// secondary channels whose type is in `channels`.
+    ///
+    /// The main channel is always opened, whether or not it is listed.
+    /// Channels the server advertises that aren't in `channels` are skipped,
+    /// so a screenshot tool can ask for just [`ChannelType::Display`] and
+    /// leave inputs and cursor unconnected.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spice_client::{ChannelType, SpiceClientShared};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = SpiceClientShared::new("localhost".to_string(), 5900);
+    /// client.connect_with_channels(&[ChannelType::Display]).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_with_channels(&self, channels: &[ChannelType]) -> Result<()> {
+        self.connect_channels(Some(channels)).await
+    }
+
+    /// Opens the channels like `open_channels`, reporting a refused ticket
+    /// that worked on an earlier connect as expired
+    async fn connect_channels(&self, wanted: Option<&[ChannelType]>) -> Result<()> {
+        let result = self.open_channels(wanted).await;
+        let mut inner = self.inner.lock().await;
+        match result {
+            Ok(()) => {
+                inner.ticket_accepted = true;
+                Ok(())
+            }
+            Err(e) => Err(e.with_ticket_history(inner.ticket_accepted)),
+        }
+    }
+
+    /// Opens an unlinked connection for a channel through the factory,
+    /// carrying the password and the OAEP settings
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn open_channel(
+        inner: &SpiceClientInner,
+        channel_type: ChannelType,
+        channel_id: u8,
+    ) -> Result<ChannelConnection> {
+        let mut connection =
+            ChannelConnection::from_factory(inner.factory.clone(), channel_type, channel_id)
+                .await?;
+        if let Some(ref password) = inner.password {
+            connection.set_password(password.clone());
+        }
+        connection.set_oaep_hash(inner.oaep_hash);
+        connection.set_oaep_fallback(inner.oaep_fallback);
+        Ok(connection)
+    }
+
+    /// Opens the main channel and the advertised secondary channels in
+    /// `wanted`, or all of them when `wanted` is `None`
+    async fn open_channels(&self, wanted: Option<&[ChannelType]>) -> Result<()> {
         let mut inner = self.inner.lock().await;
 
         #[cfg(target_arch = "wasm32")]
@@ -271,8 +1019,10 @@ impl SpiceClientShared {
                     &ws_url,
                     inner.auth_token.clone(),
                     inner.password.clone(),
+                    inner.connect_options.clone(),
                 )
                 .await?;
+                Self::track_main_channel(&mut inner, &mut main_channel);
                 main_channel.initialize().await?;
 
                 let channels = main_channel.get_channels_list().await?;
@@ -284,6 +1034,13 @@ impl SpiceClientShared {
 
                 // Connect to Display channel as it might be required
                 for (channel_type, channel_id) in &channels {
+                    if !Self::is_channel_wanted(wanted, *channel_type) {
+                        info!(
+                            "Skipping unrequested channel type {:?} id {}",
+                            channel_type, channel_id
+                        );
+                        continue;
+                    }
                     match channel_type {
                         ChannelType::Display => {
                             info!("Connecting to display channel {} via WebSocket", channel_id);
@@ -294,19 +1051,29 @@ impl SpiceClientShared {
                                 inner.auth_token.clone(),
                                 inner.password.clone(),
                                 Some(0),
+                                inner.connect_options.clone(),
                             )
                             .await
                             {
-                                Ok(display_channel) => {
+                                Ok(mut display_channel) => {
+                                    Self::apply_preferred_compression(&inner, &mut display_channel)
+                                        .await;
+                                    Self::track_display_channel(
+                                        &inner,
+                                        *channel_id,
+                                        &mut display_channel,
+                                    );
                                     inner
                                         .display_channels
                                         .insert(*channel_id, Arc::new(Mutex::new(display_channel)));
                                     info!("Connected to display channel {}", channel_id);
                                 }
                                 Err(e) => {
-                                    warn!(
-                                        "Failed to connect to display channel {}: {}",
-                                        channel_id, e
+                                    Self::report_channel_failure(
+                                        &inner,
+                                        ChannelType::Display,
+                                        *channel_id,
+                                        &e,
                                     );
                                 }
                             }
@@ -320,19 +1087,24 @@ impl SpiceClientShared {
                                 inner.auth_token.clone(),
                                 inner.password.clone(),
                                 Some(0),
+                                inner.connect_options.clone(),
                             )
                             .await
                             {
-                                Ok(inputs_channel) => {
+                                Ok(mut inputs_channel) => {
+                                    inputs_channel.set_keyboard_layout(inner.keyboard_layout);
+                                    inputs_channel.set_keymap_provider(inner.keymap.clone());
                                     inner
                                         .inputs_channels
                                         .insert(*channel_id, Arc::new(Mutex::new(inputs_channel)));
                                     info!("Connected to inputs channel {}", channel_id);
                                 }
                                 Err(e) => {
-                                    warn!(
-                                        "Failed to connect to inputs channel {}: {}",
-                                        channel_id, e
+                                    Self::report_channel_failure(
+                                        &inner,
+                                        ChannelType::Inputs,
+                                        *channel_id,
+                                        &e,
                                     );
                                 }
                             }
@@ -346,19 +1118,27 @@ impl SpiceClientShared {
                                 inner.auth_token.clone(),
                                 inner.password.clone(),
                                 Some(0),
+                                inner.connect_options.clone(),
                             )
                             .await
                             {
-                                Ok(cursor_channel) => {
+                                Ok(mut cursor_channel) => {
+                                    Self::track_cursor_channel(
+                                        &inner,
+                                        *channel_id,
+                                        &mut cursor_channel,
+                                    );
                                     inner
                                         .cursor_channels
                                         .insert(*channel_id, Arc::new(Mutex::new(cursor_channel)));
                                     info!("Connected to cursor channel {}", channel_id);
                                 }
                                 Err(e) => {
-                                    warn!(
-                                        "Failed to connect to cursor channel {}: {}",
-                                        channel_id, e
+                                    Self::report_channel_failure(
+                                        &inner,
+                                        ChannelType::Cursor,
+                                        *channel_id,
+                                        &e,
                                     );
                                 }
                             }
@@ -372,19 +1152,23 @@ impl SpiceClientShared {
                     }
                 }
 
+                inner.server_info = Some(main_channel.server_info());
                 inner.main_channel = Some(Arc::new(Mutex::new(main_channel)));
+                Self::apply_strict_messages(&inner).await;
+                Self::apply_trace_hook(&inner).await;
+                Self::apply_qos_gate(&inner).await;
+                Self::apply_keepalive(&inner).await?;
                 return Ok(());
             }
         }
 
         #[cfg(not(target_arch = "wasm32"))]
         {
-            info!(
-                "Connecting to SPICE server at {}:{}",
-                inner.host, inner.port
-            );
+            info!("Connecting to SPICE server at {}", inner.factory.describe());
 
-            let mut main_channel = MainChannel::new(&inner.host, inner.port).await?;
+            let connection = Self::open_channel(&inner, ChannelType::Main, 0).await?;
+            let mut main_channel = MainChannel::from_connection(connection).await?;
+            Self::track_main_channel(&mut inner, &mut main_channel);
             main_channel.initialize().await?;
 
             // Get the session_id from main channel
@@ -413,6 +1197,13 @@ impl SpiceClientShared {
             sleep(Duration::from_secs(1)).await;
 
             for (channel_type, channel_id) in channels {
+                if !Self::is_channel_wanted(wanted, channel_type) {
+                    info!(
+                        "Skipping unrequested channel type {:?} id {}",
+                        channel_type, channel_id
+                    );
+                    continue;
+                }
                 match channel_type {
                     ChannelType::Display => {
                         info!(
@@ -421,13 +1212,28 @@ impl SpiceClientShared {
                         );
 
                         // According to SPICE protocol: non-main channels use session_id as connection_id
-                        let display_channel = DisplayChannel::new_with_connection_id(
-                            &inner.host,
-                            inner.port,
-                            channel_id,
-                            session_id,
-                        )
-                        .await?;
+                        let connection =
+                            Self::open_channel(&inner, ChannelType::Display, channel_id).await;
+                        let display_channel = match connection {
+                            Ok(connection) => {
+                                DisplayChannel::from_connection(connection, session_id).await
+                            }
+                            Err(e) => Err(e),
+                        };
+                        let mut display_channel = match display_channel {
+                            Ok(channel) => channel,
+                            Err(e) => {
+                                Self::report_channel_failure(
+                                    &inner,
+                                    ChannelType::Display,
+                                    channel_id,
+                                    &e,
+                                );
+                                continue;
+                            }
+                        };
+                        Self::apply_preferred_compression(&inner, &mut display_channel).await;
+                        Self::track_display_channel(&inner, channel_id, &mut display_channel);
                         inner
                             .display_channels
                             .insert(channel_id, Arc::new(Mutex::new(display_channel)));
@@ -444,13 +1250,28 @@ impl SpiceClientShared {
                         );
 
                         // According to SPICE protocol: non-main channels use session_id as connection_id
-                        let inputs_channel = InputsChannel::new_with_connection_id(
-                            &inner.host,
-                            inner.port,
-                            channel_id,
-                            session_id,
-                        )
-                        .await?;
+                        let connection =
+                            Self::open_channel(&inner, ChannelType::Inputs, channel_id).await;
+                        let inputs_channel = match connection {
+                            Ok(connection) => {
+                                InputsChannel::from_connection(connection, session_id).await
+                            }
+                            Err(e) => Err(e),
+                        };
+                        let mut inputs_channel = match inputs_channel {
+                            Ok(channel) => channel,
+                            Err(e) => {
+                                Self::report_channel_failure(
+                                    &inner,
+                                    ChannelType::Inputs,
+                                    channel_id,
+                                    &e,
+                                );
+                                continue;
+                            }
+                        };
+                        inputs_channel.set_keyboard_layout(inner.keyboard_layout);
+                        inputs_channel.set_keymap_provider(inner.keymap.clone());
                         inner
                             .inputs_channels
                             .insert(channel_id, Arc::new(Mutex::new(inputs_channel)));
@@ -467,13 +1288,27 @@ impl SpiceClientShared {
                         );
 
                         // According to SPICE protocol: non-main channels use session_id as connection_id
-                        let cursor_channel = CursorChannel::new_with_connection_id(
-                            &inner.host,
-                            inner.port,
-                            channel_id,
-                            session_id,
-                        )
-                        .await?;
+                        let connection =
+                            Self::open_channel(&inner, ChannelType::Cursor, channel_id).await;
+                        let cursor_channel = match connection {
+                            Ok(connection) => {
+                                CursorChannel::from_connection(connection, session_id).await
+                            }
+                            Err(e) => Err(e),
+                        };
+                        let mut cursor_channel = match cursor_channel {
+                            Ok(channel) => channel,
+                            Err(e) => {
+                                Self::report_channel_failure(
+                                    &inner,
+                                    ChannelType::Cursor,
+                                    channel_id,
+                                    &e,
+                                );
+                                continue;
+                            }
+                        };
+                        Self::track_cursor_channel(&inner, channel_id, &mut cursor_channel);
                         inner
                             .cursor_channels
                             .insert(channel_id, Arc::new(Mutex::new(cursor_channel)));
@@ -489,8 +1324,12 @@ impl SpiceClientShared {
                 }
             }
 
+            inner.server_info = Some(main_channel.server_info());
             inner.main_channel = Some(Arc::new(Mutex::new(main_channel)));
-            Ok(())
+            Self::apply_strict_messages(&inner).await;
+            Self::apply_trace_hook(&inner).await;
+            Self::apply_qos_gate(&inner).await;
+            Self::apply_keepalive(&inner).await
         }
 
         #[cfg(target_arch = "wasm32")]
@@ -509,6 +1348,16 @@ impl SpiceClientShared {
     /// On WebAssembly, this uses `spawn_local` to run tasks in the browser's
     /// event loop.
     ///
+    /// # Scheduling
+    ///
+    /// Input sent once the loops are running is queued for the inputs
+    /// channel's loop, which writes queued input before reading any further
+    /// server message and never waits on the server while input is pending.
+    /// Display loops yield after every message, so on WebAssembly's single
+    /// thread input goes out between draws instead of after a whole burst.
+    /// Input is written in the order it was sent; use
+    /// [`flush_input`](Self::flush_input) to wait until it has been written.
+    ///
     /// # Errors
     ///
     /// Returns a `SpiceError` if the client is not connected.
@@ -533,12 +1382,28 @@ impl SpiceClientShared {
             ));
         }
 
+        let error_state = inner.error_state.clone();
+        *error_state.lock().unwrap() = None;
+
         #[cfg(not(target_arch = "wasm32"))]
         {
             if let Some(main_channel_arc) = inner.main_channel.clone() {
+                let event_callback = inner.event_callback.clone();
+                let error_state = error_state.clone();
                 let main_task = tokio::spawn(async move {
                     let mut main_channel = main_channel_arc.lock().await;
-                    main_channel.run().await
+                    let result = main_channel.run().await;
+                    // `disconnect` aborts the task, so an error here means the
+                    // connection ended on its own
+                    if let Err(e) = &result {
+                        record_disconnect(&error_state, "Main channel", e);
+                        if let Some(callback) = event_callback {
+                            callback(&MainEvent::Disconnected {
+                                reason: e.to_string(),
+                            });
+                        }
+                    }
+                    result
                 });
                 inner.channel_tasks.push(main_task);
             }
@@ -549,9 +1414,21 @@ impl SpiceClientShared {
                 .map(|(id, ch)| (*id, ch.clone()))
                 .collect();
             for (channel_id, display_channel_arc) in display_channels {
+                let (refresh, inspect) = {
+                    let channel = display_channel_arc.lock().await;
+                    (channel.refresh_handle(), channel.inspect_handle())
+                };
+                inner.refresh_handles.insert(channel_id, refresh);
+                inner.inspect_handles.insert(channel_id, inspect);
+                let error_state = error_state.clone();
                 let display_task = tokio::spawn(async move {
                     let mut display_channel = display_channel_arc.lock().await;
-                    display_channel.run().await
+                    let result = display_channel.run().await;
+                    if let Err(e) = &result {
+                        let context = format!("Display channel {channel_id}");
+                        record_disconnect(&error_state, &context, e);
+                    }
+                    result
                 });
                 inner.channel_tasks.push(display_task);
                 info!("Started event loop for display channel {}", channel_id);
@@ -563,9 +1440,17 @@ impl SpiceClientShared {
                 .map(|(id, ch)| (*id, ch.clone()))
                 .collect();
             for (channel_id, inputs_channel_arc) in inputs_channels {
+                let queue = inputs_channel_arc.lock().await.input_queue();
+                inner.input_queues.insert(channel_id, queue);
+                let error_state = error_state.clone();
                 let inputs_task = tokio::spawn(async move {
                     let mut inputs_channel = inputs_channel_arc.lock().await;
-                    inputs_channel.run().await
+                    let result = inputs_channel.run().await;
+                    if let Err(e) = &result {
+                        let context = format!("Inputs channel {channel_id}");
+                        record_disconnect(&error_state, &context, e);
+                    }
+                    result
                 });
                 inner.channel_tasks.push(inputs_task);
                 info!("Started event loop for inputs channel {}", channel_id);
@@ -577,9 +1462,15 @@ impl SpiceClientShared {
                 .map(|(id, ch)| (*id, ch.clone()))
                 .collect();
             for (channel_id, cursor_channel_arc) in cursor_channels {
+                let error_state = error_state.clone();
                 let cursor_task = tokio::spawn(async move {
                     let mut cursor_channel = cursor_channel_arc.lock().await;
-                    cursor_channel.run().await
+                    let result = cursor_channel.run().await;
+                    if let Err(e) = &result {
+                        let context = format!("Cursor channel {channel_id}");
+                        record_disconnect(&error_state, &context, e);
+                    }
+                    result
                 });
                 inner.channel_tasks.push(cursor_task);
                 info!("Started event loop for cursor channel {}", channel_id);
@@ -588,17 +1479,13 @@ impl SpiceClientShared {
 
         #[cfg(target_arch = "wasm32")]
         {
-            let error_state = inner.error_state.clone();
-
             if let Some(main_channel_arc) = inner.main_channel.clone() {
                 let error_state_clone = error_state.clone();
                 wasm_bindgen_futures::spawn_local(async move {
                     let mut main_channel = main_channel_arc.lock().await;
                     if let Err(e) = main_channel.run().await {
-                        error!("Main channel error: {}", e);
                         // Set error state to stop other operations
-                        *error_state_clone.lock().unwrap() =
-                            Some(format!("Main channel error: {e}"));
+                        record_disconnect(&error_state_clone, "Main channel", &e);
                     }
                 });
                 inner.channel_tasks.push(());
@@ -610,14 +1497,19 @@ impl SpiceClientShared {
                 .map(|(id, ch)| (*id, ch.clone()))
                 .collect();
             for (channel_id, display_channel_arc) in display_channels {
+                let (refresh, inspect) = {
+                    let channel = display_channel_arc.lock().await;
+                    (channel.refresh_handle(), channel.inspect_handle())
+                };
+                inner.refresh_handles.insert(channel_id, refresh);
+                inner.inspect_handles.insert(channel_id, inspect);
                 let error_state_clone = error_state.clone();
                 wasm_bindgen_futures::spawn_local(async move {
                     let mut display_channel = display_channel_arc.lock().await;
                     if let Err(e) = display_channel.run().await {
-                        error!("Display channel {} error: {}", channel_id, e);
                         // Set error state to stop other operations
-                        *error_state_clone.lock().unwrap() =
-                            Some(format!("Display channel {channel_id} error: {e}"));
+                        let context = format!("Display channel {channel_id}");
+                        record_disconnect(&error_state_clone, &context, &e);
                     }
                 });
                 inner.channel_tasks.push(());
@@ -630,14 +1522,15 @@ impl SpiceClientShared {
                 .map(|(id, ch)| (*id, ch.clone()))
                 .collect();
             for (channel_id, inputs_channel_arc) in inputs_channels {
+                let queue = inputs_channel_arc.lock().await.input_queue();
+                inner.input_queues.insert(channel_id, queue);
                 let error_state_clone = error_state.clone();
                 wasm_bindgen_futures::spawn_local(async move {
                     let mut inputs_channel = inputs_channel_arc.lock().await;
                     if let Err(e) = inputs_channel.run().await {
-                        error!("Inputs channel {} error: {}", channel_id, e);
                         // Set error state to stop other operations
-                        *error_state_clone.lock().unwrap() =
-                            Some(format!("Inputs channel {channel_id} error: {e}"));
+                        let context = format!("Inputs channel {channel_id}");
+                        record_disconnect(&error_state_clone, &context, &e);
                     }
                 });
                 inner.channel_tasks.push(());
@@ -654,10 +1547,9 @@ impl SpiceClientShared {
                 wasm_bindgen_futures::spawn_local(async move {
                     let mut cursor_channel = cursor_channel_arc.lock().await;
                     if let Err(e) = cursor_channel.run().await {
-                        error!("Cursor channel {} error: {}", channel_id, e);
                         // Set error state to stop other operations
-                        *error_state_clone.lock().unwrap() =
-                            Some(format!("Cursor channel {channel_id} error: {e}"));
+                        let context = format!("Cursor channel {channel_id}");
+                        record_disconnect(&error_state_clone, &context, &e);
                     }
                 });
                 inner.channel_tasks.push(());
@@ -722,33 +1614,185 @@ impl SpiceClientShared {
         &self,
         channel_id: u8,
     ) -> Option<crate::channels::display::DisplaySurface> {
+        self.current_display_frame(channel_id)
+            .await
+            .map(|(surface, _)| surface)
+    }
+
+    /// Returns the primary surface of the main monitor, the display channel
+    /// with the lowest id.
+    ///
+    /// Use [`get_display_surface`](Self::get_display_surface) for the other
+    /// monitors.
+    pub async fn primary_surface(&self) -> Option<DisplaySurface> {
+        let channel_id = {
+            let inner = self.inner.lock().await;
+            inner.display_channels.keys().min().copied()?
+        };
+        self.get_display_surface(channel_id).await
+    }
+
+    /// Returns a surface of a display channel.
+    ///
+    /// Each display channel numbers its surfaces on its own, so with several
+    /// channels a surface is only identified by both ids.
+    pub async fn get_surface(&self, channel_id: u8, surface_id: u32) -> Option<DisplaySurface> {
+        self.read_display_channel(channel_id, move |channel| {
+            channel.get_surface(surface_id).cloned()
+        })
+        .await
+        .flatten()
+    }
+
+    /// Returns the surface a display channel shows on its monitor at
+    /// `index`, following the server's latest monitors config.
+    ///
+    /// Renderers of several monitors can route surface updates with this and
+    /// [`monitor_for_surface`](Self::monitor_for_surface).
+    pub async fn surface_for_monitor(&self, channel_id: u8, index: usize) -> Option<u32> {
+        self.read_display_channel(channel_id, move |channel| {
+            channel.surface_for_monitor(index)
+        })
+        .await
+        .flatten()
+    }
+
+    /// Returns the index of the first monitor of a display channel that
+    /// shows `surface_id`, following the server's latest monitors config.
+    pub async fn monitor_for_surface(&self, channel_id: u8, surface_id: u32) -> Option<usize> {
+        self.read_display_channel(channel_id, move |channel| {
+            channel.monitor_for_surface(surface_id)
+        })
+        .await
+        .flatten()
+    }
+
+    /// Returns when a surface of a display channel last changed, so a
+    /// renderer can skip repainting while it stays the same.
+    ///
+    /// [`DisplayEvent::SurfaceUpdated`](crate::DisplayEvent::SurfaceUpdated)
+    /// carries the same time as changes are reported.
+    pub async fn last_update_time(&self, channel_id: u8, surface_id: u32) -> Option<Instant> {
+        self.read_display_channel(channel_id, move |channel| {
+            channel.last_update_time(surface_id)
+        })
+        .await
+        .flatten()
+    }
+
+    /// Asks the server to repaint a display channel in full, for when its
+    /// surfaces may have missed updates.
+    ///
+    /// The channel is relinked, which is what makes the server resend the
+    /// mode and draw everything; see
+    /// [`DisplayChannel::request_refresh`]. With the event loop running the
+    /// request is handed to the loop and this returns right away.
+    pub async fn request_refresh(&self, channel_id: u8) -> Result<()> {
         let inner = self.inner.lock().await;
-        if let Some(channel_arc) = inner.display_channels.get(&channel_id) {
-            let channel = channel_arc.lock().await;
-            channel.get_primary_surface().cloned()
+
+        if let Some(handle) = inner.refresh_handles.get(&channel_id) {
+            handle.request();
+            return Ok(());
+        }
+        if let Some(display_channel_arc) = inner.display_channels.get(&channel_id) {
+            display_channel_arc.lock().await.request_refresh().await
         } else {
-            None
+            Err(SpiceError::Protocol(format!(
+                "Display channel {} not connected",
+                channel_id
+            )))
         }
     }
 
-    /// Gets the video output handler for this client.
+    /// Returns whether a display channel is showing its last frame from
+    /// before a reconnect because the server hasn't drawn anything since.
+    ///
+    /// Frontends can dim the display or show a "reconnecting" overlay while
+    /// this is `true`.
+    pub async fn is_display_stale(&self, channel_id: u8) -> bool {
+        self.current_display_frame(channel_id)
+            .await
+            .is_some_and(|(_, stale)| stale)
+    }
+
+    /// The surface to show for a display channel and whether it is stale.
+    async fn current_display_frame(&self, channel_id: u8) -> Option<(DisplaySurface, bool)> {
+        let live = self
+            .read_display_channel(channel_id, |channel| {
+                channel
+                    .get_primary_surface()
+                    .cloned()
+                    .map(|surface| (surface, channel.has_drawn()))
+            })
+            .await
+            .flatten();
+        let mut inner = self.inner.lock().await;
+        pick_display_frame(&mut inner.last_frames, channel_id, live)
+    }
+
+    /// Runs `read` on a display channel, or `None` if it isn't connected.
+    ///
+    /// A running event loop holds the channel for as long as it runs, so the
+    /// read is handed to the loop, which answers between two messages.
+    async fn read_display_channel<T, F>(&self, channel_id: u8, read: F) -> Option<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&DisplayChannel) -> T + Send + 'static,
+    {
+        let inner = self.inner.lock().await;
+        let channel_arc = inner.display_channels.get(&channel_id)?.clone();
+        let handle = inner.inspect_handles.get(&channel_id).cloned();
+        drop(inner);
+
+        if let Ok(channel) = channel_arc.try_lock() {
+            return Some(read(&channel));
+        }
+        match handle {
+            Some(handle) => handle.read(read).await,
+            None => Some(read(&*channel_arc.lock().await)),
+        }
+    }
+
+    /// Returns whether the server has switched a display channel to GL
+    /// scanout.
+    ///
+    /// GL frames are shared as dmabufs, which this client can't display, so
+    /// while this is `true` the channel's surface stops updating. Frontends
+    /// should tell the user to disable GL for the VM's display.
+    pub async fn is_display_gl_active(&self, channel_id: u8) -> bool {
+        self.read_display_channel(channel_id, |channel| channel.is_gl_active())
+            .await
+            .unwrap_or(false)
+    }
+
+    /// Gets the video output handler for display channel 0.
     ///
     /// Returns the video output implementation that processes and renders
     /// display updates. This can be used to integrate with custom rendering
-    /// systems or to access video frames directly.
+    /// systems or to access video frames directly. Guests with several
+    /// displays have one output per display channel, see
+    /// [`get_channel_video_output`](Self::get_channel_video_output).
     ///
     /// # Returns
     ///
     /// An `Arc` to the video output implementation.
     pub async fn get_video_output(&self) -> Arc<dyn VideoOutput> {
-        let inner = self.inner.lock().await;
-        inner.video_output.clone()
+        self.get_channel_video_output(0).await
+    }
+
+    /// Gets the video output handler of a display channel.
+    ///
+    /// The output exists even before the channel connects and stays the same
+    /// across reconnects.
+    pub async fn get_channel_video_output(&self, channel_id: u8) -> Arc<dyn VideoOutput> {
+        let mut inner = self.inner.lock().await;
+        Self::channel_video_output(&mut inner, channel_id)
     }
 
     /// Updates the video output with the latest display surface data.
     ///
     /// This method retrieves the current display surface from the specified
-    /// channel and updates the video output handler with the new frame data.
+    /// channel and updates that channel's video output with the new frame data.
     /// This is typically called automatically but can be used for manual updates.
     ///
     /// # Arguments
@@ -759,9 +1803,12 @@ impl SpiceClientShared {
     ///
     /// Returns `Ok(())` even if no surface is available (no-op in that case).
     pub async fn update_video_from_display(&self, channel_id: u8) -> Result<()> {
-        if let Some(surface) = self.get_display_surface(channel_id).await {
-            let inner = self.inner.lock().await;
-            inner.video_output.update_frame(&surface).await;
+        if let Some((surface, stale)) = self.current_display_frame(channel_id).await {
+            let output = self.get_channel_video_output(channel_id).await;
+            output.update_frame(&surface).await;
+            if stale {
+                output.mark_stale().await;
+            }
         }
         Ok(())
     }
@@ -800,6 +1847,11 @@ impl SpiceClientShared {
         Ok(())
     }
 
+    /// Disconnects from the server.
+    ///
+    /// The last frame of each display channel is kept and shown, flagged as
+    /// stale, until the server draws again after the next `connect()`, so a
+    /// reconnect doesn't flash a black screen.
     pub async fn disconnect(&self) {
         let mut inner = self.inner.lock().await;
         info!("Disconnecting from SPICE server");
@@ -808,6 +1860,8 @@ impl SpiceClientShared {
         {
             for task in inner.channel_tasks.drain(..) {
                 task.abort();
+                // Wait for the task to drop so its channel is unlocked
+                let _ = task.await;
             }
         }
 
@@ -816,20 +1870,50 @@ impl SpiceClientShared {
             inner.channel_tasks.clear();
         }
 
+        let display_channels: Vec<(u8, Arc<Mutex<DisplayChannel>>)> = inner
+            .display_channels
+            .iter()
+            .map(|(id, ch)| (*id, ch.clone()))
+            .collect();
+        for (channel_id, channel_arc) in display_channels {
+            // A WASM event loop can't be stopped and may still hold the channel
+            let Ok(channel) = channel_arc.try_lock() else {
+                continue;
+            };
+            if !channel.has_drawn() {
+                continue;
+            }
+            if let Some(surface) = channel.get_primary_surface() {
+                inner.last_frames.insert(channel_id, surface.clone());
+            }
+        }
+        for output in inner.video_outputs.values() {
+            output.mark_stale().await;
+        }
+
         inner.main_channel = None;
         inner.display_channels.clear();
+        inner.refresh_handles.clear();
+        inner.inspect_handles.clear();
         inner.inputs_channels.clear();
+        inner.input_queues.clear();
     }
 
     // Input forwarding methods
 
-    /// Sends a key down event to the specified inputs channel.
-    pub async fn send_key_down(&self, channel_id: u8, scancode: u32) -> Result<()> {
+    /// Sends input on an inputs channel.
+    ///
+    /// Once the event loop is running it owns the channel, so the input is
+    /// queued for the loop, which writes it ahead of reading server messages.
+    async fn send_input(&self, channel_id: u8, command: InputCommand) -> Result<()> {
         let inner = self.inner.lock().await;
 
+        if let Some(queue) = inner.input_queues.get(&channel_id) {
+            return queue.send(command);
+        }
         if let Some(inputs_channel_arc) = inner.inputs_channels.get(&channel_id) {
             let mut inputs_channel = inputs_channel_arc.lock().await;
-            inputs_channel.send_key_down(scancode).await
+            inputs_channel.apply_input(command).await
         } else {
             Err(SpiceError::Protocol(format!(
                 "Inputs channel {} not connected",
@@ -838,13 +1922,21 @@ impl SpiceClientShared {
         }
     }
 
-    /// Sends a key up event to the specified inputs channel.
-    pub async fn send_key_up(&self, channel_id: u8, scancode: u32) -> Result<()> {
+    /// Waits until all input sent so far on an inputs channel has been
+    /// written to the server.
+    ///
+    /// Input is normally written as soon as the channel's event loop gets to
+    /// run; call this when the next step depends on the server having it,
+    /// such as before disconnecting.
+    pub async fn flush_input(&self, channel_id: u8) -> Result<()> {
         let inner = self.inner.lock().await;
 
+        if let Some(queue) = inner.input_queues.get(&channel_id).cloned() {
+            drop(inner);
+            return queue.flush().await;
+        }
         if let Some(inputs_channel_arc) = inner.inputs_channels.get(&channel_id) {
-            let mut inputs_channel = inputs_channel_arc.lock().await;
-            inputs_channel.send_key_up(scancode).await
+            inputs_channel_arc.lock().await.flush_input().await
         } else {
             Err(SpiceError::Protocol(format!(
                 "Inputs channel {} not connected",
@@ -853,19 +1945,55 @@ impl SpiceClientShared {
         }
     }
 
+    /// Sends an input event to the specified inputs channel, mapping
+    /// character keys through the configured keyboard layout.
+    pub async fn send_input_event(&self, channel_id: u8, event: InputEvent) -> Result<()> {
+        self.send_input(channel_id, InputCommand::Event(event))
+            .await
+    }
+
+    /// Sends a key down event to the specified inputs channel.
+    pub async fn send_key_down(&self, channel_id: u8, scancode: u32) -> Result<()> {
+        self.send_input(channel_id, InputCommand::KeyDown(scancode))
+            .await
+    }
+
+    /// Sends a key up event to the specified inputs channel.
+    pub async fn send_key_up(&self, channel_id: u8, scancode: u32) -> Result<()> {
+        self.send_input(channel_id, InputCommand::KeyUp(scancode))
+            .await
+    }
+
     /// Sends a mouse motion event to the specified inputs channel.
     pub async fn send_mouse_motion(&self, channel_id: u8, x: i32, y: i32) -> Result<()> {
-        let inner = self.inner.lock().await;
+        self.send_input(channel_id, InputCommand::MouseMotion { x, y })
+            .await
+    }
 
-        if let Some(inputs_channel_arc) = inner.inputs_channels.get(&channel_id) {
-            let mut inputs_channel = inputs_channel_arc.lock().await;
-            inputs_channel.send_mouse_motion(x, y).await
-        } else {
-            Err(SpiceError::Protocol(format!(
-                "Inputs channel {} not connected",
-                channel_id
-            )))
-        }
+    /// Sends an absolute pointer position in widget coordinates. The
+    /// position is scaled to the guest display using the sizes given to
+    /// [`set_pointer_sizes`](Self::set_pointer_sizes).
+    pub async fn send_mouse_position(&self, channel_id: u8, x: i32, y: i32) -> Result<()> {
+        self.send_input(channel_id, InputCommand::MousePosition { x, y })
+            .await
+    }
+
+    /// Sets the widget size pointer coordinates are reported in and the
+    /// guest display size they are scaled to.
+    pub async fn set_pointer_sizes(
+        &self,
+        channel_id: u8,
+        widget_size: (u32, u32),
+        display_size: (u32, u32),
+    ) -> Result<()> {
+        self.send_input(
+            channel_id,
+            InputCommand::PointerSizes {
+                widget_size,
+                display_size,
+            },
+        )
+        .await
     }
 
     /// Sends a mouse button event to the specified inputs channel.
@@ -875,17 +2003,8 @@ impl SpiceClientShared {
         button: MouseButton,
         pressed: bool,
     ) -> Result<()> {
-        let inner = self.inner.lock().await;
-
-        if let Some(inputs_channel_arc) = inner.inputs_channels.get(&channel_id) {
-            let mut inputs_channel = inputs_channel_arc.lock().await;
-            inputs_channel.send_mouse_button(button, pressed).await
-        } else {
-            Err(SpiceError::Protocol(format!(
-                "Inputs channel {} not connected",
-                channel_id
-            )))
-        }
+        self.send_input(channel_id, InputCommand::MouseButton { button, pressed })
+            .await
     }
 
     /// Sends a mouse wheel event to the specified inputs channel.
@@ -895,34 +2014,14 @@ impl SpiceClientShared {
         _delta_x: i32,
         delta_y: i32,
     ) -> Result<()> {
-        let inner = self.inner.lock().await;
-
-        if let Some(inputs_channel_arc) = inner.inputs_channels.get(&channel_id) {
-            let mut inputs_channel = inputs_channel_arc.lock().await;
-            // Convert wheel deltas to button presses (SPICE protocol uses button events for wheel)
-            if delta_y > 0 {
-                inputs_channel
-                    .send_mouse_button(MouseButton::WheelUp, true)
-                    .await?;
-                inputs_channel
-                    .send_mouse_button(MouseButton::WheelUp, false)
-                    .await
-            } else if delta_y < 0 {
-                inputs_channel
-                    .send_mouse_button(MouseButton::WheelDown, true)
-                    .await?;
-                inputs_channel
-                    .send_mouse_button(MouseButton::WheelDown, false)
-                    .await
-            } else {
-                Ok(())
-            }
-        } else {
-            Err(SpiceError::Protocol(format!(
-                "Inputs channel {} not connected",
-                channel_id
-            )))
-        }
+        // Convert wheel deltas to button presses (SPICE protocol uses button events for wheel)
+        let button = match delta_y.cmp(&0) {
+            std::cmp::Ordering::Greater => MouseButton::WheelUp,
+            std::cmp::Ordering::Less => MouseButton::WheelDown,
+            std::cmp::Ordering::Equal => return Ok(()),
+        };
+        self.send_mouse_button(channel_id, button, true).await?;
+        self.send_mouse_button(channel_id, button, false).await
     }
 
     /// Sets a callback that will be called whenever a display surface is updated.
@@ -945,3 +2044,199 @@ impl SpiceClientShared {
         }
     }
 }
+
+/// Choose between a display channel's live surface and the frame kept from
+/// before a reconnect. The kept frame is shown, flagged stale, until the
+/// server has drawn on the live surface, and is dropped after that.
+fn pick_display_frame(
+    last_frames: &mut HashMap<u8, DisplaySurface>,
+    channel_id: u8,
+    live: Option<(DisplaySurface, bool)>,
+) -> Option<(DisplaySurface, bool)> {
+    match live {
+        Some((surface, true)) => {
+            last_frames.remove(&channel_id);
+            Some((surface, false))
+        }
+        live => match last_frames.get(&channel_id) {
+            Some(last) => Some((last.clone(), true)),
+            None => live.map(|(surface, _)| (surface, false)),
+        },
+    }
+}
+
+/// Log the error that ended a channel's event loop and keep it as the
+/// client's error state. The first one is kept: once one channel fails the
+/// others usually fail because of it.
+fn record_disconnect(
+    error_state: &std::sync::Mutex<Option<DisconnectInfo>>,
+    context: &str,
+    error: &SpiceError,
+) {
+    error!("{} error: {}", context, error);
+    let mut state = error_state.lock().unwrap();
+    if state.is_none() {
+        *state = Some(DisconnectInfo::from_error(context, error));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::SurfaceFormat;
+    use crate::video::{create_video_output, VideoFrame};
+    use base64::{engine::general_purpose, Engine as _};
+
+    fn surface(fill: u8) -> DisplaySurface {
+        let mut surface = DisplaySurface::new(2, 2, SurfaceFormat::Xrgb32);
+        surface.data.fill(fill);
+        surface
+    }
+
+    #[test]
+    fn test_last_frame_shown_until_server_draws() {
+        let mut last_frames = HashMap::new();
+        last_frames.insert(0, surface(7));
+
+        // Disconnected, or reconnected with a blank surface: keep the old frame
+        let (shown, stale) = pick_display_frame(&mut last_frames, 0, None).unwrap();
+        assert!(stale);
+        assert_eq!(shown.data, surface(7).data);
+
+        let (shown, stale) =
+            pick_display_frame(&mut last_frames, 0, Some((surface(0), false))).unwrap();
+        assert!(stale);
+        assert_eq!(shown.data, surface(7).data);
+
+        // The first draw replaces it for good
+        let (shown, stale) =
+            pick_display_frame(&mut last_frames, 0, Some((surface(9), true))).unwrap();
+        assert!(!stale);
+        assert_eq!(shown.data, surface(9).data);
+        assert!(last_frames.is_empty());
+    }
+
+    #[test]
+    fn test_live_surface_used_without_last_frame() {
+        let mut last_frames = HashMap::new();
+
+        let (shown, stale) =
+            pick_display_frame(&mut last_frames, 0, Some((surface(3), false))).unwrap();
+        assert!(!stale);
+        assert_eq!(shown.data, surface(3).data);
+        assert!(pick_display_frame(&mut last_frames, 1, None).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_video_output_marks_frame_stale() {
+        let output = create_video_output();
+        output.mark_stale().await;
+        assert!(output.get_current_frame().await.is_none());
+
+        output.update_frame(&surface(5)).await;
+        assert!(!output.get_current_frame().await.unwrap().stale);
+
+        output.mark_stale().await;
+        let frame = output.get_current_frame().await.unwrap();
+        assert!(frame.stale);
+        assert_eq!(frame.width, 2);
+
+        // A fresh frame clears the flag
+        output.update_frame(&surface(6)).await;
+        assert!(!output.get_current_frame().await.unwrap().stale);
+    }
+
+    fn frame_pixels(frame: &VideoFrame) -> Vec<u8> {
+        let (_, encoded) = frame.data_url.split_once(";base64,").unwrap();
+        general_purpose::STANDARD.decode(encoded).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_video_output_emits_its_pixel_format() {
+        let mut source = DisplaySurface::new(1, 1, SurfaceFormat::Xrgb32);
+        source.data.copy_from_slice(&[10, 20, 30, 255]);
+
+        let rgba_output = create_video_output();
+        rgba_output.update_frame(&source).await;
+        let frame = rgba_output.get_current_frame().await.unwrap();
+        assert_eq!(frame.pixel_format, PixelFormat::Rgba8);
+        assert!(frame.data_url.starts_with("data:image/rgba;"));
+        assert_eq!(frame_pixels(&frame), [10, 20, 30, 255]);
+
+        let bgra_output = create_video_output_with_format(PixelFormat::Bgra8);
+        bgra_output.update_frame(&source).await;
+        let frame = bgra_output.get_current_frame().await.unwrap();
+        assert_eq!(frame.pixel_format, PixelFormat::Bgra8);
+        assert!(frame.data_url.starts_with("data:image/bgra;"));
+        assert_eq!(frame_pixels(&frame), [30, 20, 10, 255]);
+    }
+
+    #[tokio::test]
+    async fn test_set_pixel_format_applies_to_video_outputs() {
+        let client = SpiceClientShared::new("localhost".to_string(), 5900);
+        let existing = client.get_channel_video_output(0).await;
+
+        client.set_pixel_format(PixelFormat::Bgra8).await;
+
+        assert_eq!(existing.pixel_format(), PixelFormat::Bgra8);
+        let created = client.get_channel_video_output(1).await;
+        assert_eq!(created.pixel_format(), PixelFormat::Bgra8);
+    }
+
+    #[tokio::test]
+    async fn test_websocket_client_opens_every_channel_through_proxy() {
+        use crate::channels::stream::WebSocketIo;
+        use crate::test_utils::serve_mock_channel;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (served_tx, mut served_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let served_tx = served_tx.clone();
+                tokio::spawn(async move {
+                    let socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+                    let channels = [(ChannelType::Display, 0), (ChannelType::Inputs, 0)];
+                    let served =
+                        serve_mock_channel(WebSocketIo::new(socket), "secret", &channels).await;
+                    let _ = served_tx.send(served.unwrap());
+                });
+            }
+        });
+
+        let mut client = SpiceClientShared::new_websocket(format!("ws://{addr}"));
+        client.set_password("secret".to_string()).await;
+        tokio::time::timeout(Duration::from_secs(10), client.connect())
+            .await
+            .expect("connect timed out")
+            .unwrap();
+
+        {
+            let inner = client.inner.lock().await;
+            assert!(inner.main_channel.is_some());
+            assert!(inner.display_channels.contains_key(&0));
+            assert!(inner.inputs_channels.contains_key(&0));
+        }
+
+        // Each channel's proxy end only finishes once the client lets go
+        client.disconnect().await;
+        drop(client);
+        let mut served = Vec::new();
+        while served.len() < 3 {
+            let channel = tokio::time::timeout(Duration::from_secs(5), served_rx.recv())
+                .await
+                .expect("proxy ends were not released")
+                .unwrap();
+            served.push(channel);
+        }
+        served.sort();
+        assert_eq!(
+            served,
+            vec![
+                ChannelType::Main as u8,
+                ChannelType::Display as u8,
+                ChannelType::Inputs as u8
+            ]
+        );
+    }
+}
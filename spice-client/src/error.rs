@@ -1,5 +1,6 @@
 //! Error types for the SPICE client library.
 
+use crate::protocol::ChannelType;
 use thiserror::Error;
 
 /// Errors that can occur when using the SPICE client.
@@ -43,12 +44,59 @@ pub enum SpiceError {
     #[error("Channel error: {0}")]
     Channel(String),
 
+    /// The server does not provide the requested channel.
+    ///
+    /// Returned when the link is refused with
+    /// `SPICE_LINK_ERR_CHANNEL_NOT_AVAILABLE`, for example when connecting a
+    /// channel type the VM was started without.
+    #[error("Channel not available: {0:?}")]
+    ChannelNotAvailable(ChannelType),
+
+    /// The server only accepts this channel over TLS.
+    ///
+    /// Returned for `SPICE_LINK_ERR_NEED_SECURED`, when the channel has to be
+    /// opened on the server's TLS port. This client has no TLS support, so
+    /// the connection can't be retried there.
+    #[error("Channel {0:?} requires a TLS connection")]
+    NeedSecured(ChannelType),
+
+    /// The server only accepts this channel without TLS.
+    ///
+    /// Returned for `SPICE_LINK_ERR_NEED_UNSECURED`; the channel has to be
+    /// opened on the server's plaintext port.
+    #[error("Channel {0:?} requires a plaintext connection")]
+    NeedUnsecured(ChannelType),
+
+    /// The server didn't answer the link header the way a SPICE server does.
+    ///
+    /// Returned as soon as the reply is seen not to start with the SPICE
+    /// magic, for example from a VNC server on the port, or when a proxy
+    /// echoes the link header back.
+    #[error("Not a SPICE server on this port: {0}")]
+    NotSpiceServer(String),
+
     /// Authentication with the SPICE server failed.
     ///
-    /// This occurs when the provided password or ticket is incorrect,
-    /// or when the authentication method is not supported.
-    #[error("Authentication failed")]
-    AuthenticationFailed,
+    /// Returned when the server refuses the ticket with
+    /// `SPICE_LINK_ERR_PERMISSION_DENIED`. The server gives the same error
+    /// for a wrong password and an expired one, so `reason` is only
+    /// [`AuthFailureReason::TicketExpired`] when the client linked with the
+    /// same password before. Either way the caller needs a fresh ticket:
+    /// for QEMU, set one with the QMP `set_password` command, pass it to
+    /// `set_password` and connect again.
+    #[error("Authentication failed: {reason}")]
+    AuthenticationFailed {
+        /// Why the server most likely refused the ticket.
+        reason: AuthFailureReason,
+    },
+
+    /// The server requires a password and none was set.
+    ///
+    /// Returned instead of [`SpiceError::AuthenticationFailed`] when the
+    /// server refuses the ticket of a client without a password, so a UI
+    /// can ask for one, pass it to `set_password` and connect again.
+    #[error("The server requires a password")]
+    PasswordRequired,
 
     /// The connection to the SPICE server was closed.
     ///
@@ -66,6 +114,174 @@ pub enum SpiceError {
     BinRw(#[from] binrw::Error),
 }
 
+/// Why the server refused a ticket, as far as the client can tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthFailureReason {
+    /// The password was accepted on an earlier connect, so its time limit
+    /// has most likely run out.
+    TicketExpired,
+    /// The password isn't known to have worked before, so it is most
+    /// likely wrong.
+    BadPassword,
+}
+
+impl std::fmt::Display for AuthFailureReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthFailureReason::TicketExpired => write!(f, "ticket expired"),
+            AuthFailureReason::BadPassword => write!(f, "wrong password"),
+        }
+    }
+}
+
+impl SpiceError {
+    /// Whether the error only concerns the message being handled, leaving
+    /// the connection itself usable.
+    pub fn is_message_error(&self) -> bool {
+        matches!(
+            self,
+            SpiceError::Protocol(_) | SpiceError::Serialization(_) | SpiceError::BinRw(_)
+        )
+    }
+
+    /// Report a refused ticket as expired when the server accepted the same
+    /// password before
+    pub(crate) fn with_ticket_history(self, accepted_before: bool) -> Self {
+        match self {
+            SpiceError::AuthenticationFailed {
+                reason: AuthFailureReason::BadPassword,
+            } if accepted_before => SpiceError::AuthenticationFailed {
+                reason: AuthFailureReason::TicketExpired,
+            },
+            error => error,
+        }
+    }
+}
+
+/// Why a connection ended, in a form frontends can act on without parsing
+/// error messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The server closed the connection.
+    ConnectionClosed,
+    /// The socket failed or the server couldn't be reached.
+    Network,
+    /// The server refused the password.
+    AuthenticationFailed,
+    /// The server wants a password and none was given.
+    PasswordRequired,
+    /// The server refused a password it accepted before.
+    TicketExpired,
+    /// The server doesn't provide the channel.
+    ChannelNotAvailable,
+    /// The channel has to be opened with or without TLS on another port.
+    SecurityMismatch,
+    /// Something other than a SPICE server answered.
+    NotSpiceServer,
+    /// The server speaks an incompatible protocol version.
+    VersionMismatch,
+    /// The server sent data the client couldn't handle.
+    Protocol,
+}
+
+impl DisconnectReason {
+    /// Stable name of the reason, as handed to JavaScript
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DisconnectReason::ConnectionClosed => "connection-closed",
+            DisconnectReason::Network => "network",
+            DisconnectReason::AuthenticationFailed => "authentication-failed",
+            DisconnectReason::PasswordRequired => "password-required",
+            DisconnectReason::TicketExpired => "ticket-expired",
+            DisconnectReason::ChannelNotAvailable => "channel-not-available",
+            DisconnectReason::SecurityMismatch => "security-mismatch",
+            DisconnectReason::NotSpiceServer => "not-spice-server",
+            DisconnectReason::VersionMismatch => "version-mismatch",
+            DisconnectReason::Protocol => "protocol",
+        }
+    }
+}
+
+impl std::fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What is known about the error that ended a channel's event loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisconnectInfo {
+    /// Why the connection ended.
+    pub reason: DisconnectReason,
+    /// The `SPICE_LINK_ERR_*` code the server refused the link with, if any.
+    pub code: Option<u32>,
+    /// Whether connecting again as-is may work. False when the user has to
+    /// change something first, such as the password.
+    pub recoverable: bool,
+    /// Human-readable description, naming the channel that failed.
+    pub message: String,
+}
+
+impl DisconnectInfo {
+    /// Describe `error`, which ended the channel named by `context`, e.g.
+    /// `"Main channel"`.
+    pub fn from_error(context: &str, error: &SpiceError) -> Self {
+        use crate::protocol::LinkError;
+
+        let (reason, code, recoverable) = match error {
+            SpiceError::Io(_) | SpiceError::Connection(_) => {
+                (DisconnectReason::Network, None, true)
+            }
+            SpiceError::ConnectionClosed => (DisconnectReason::ConnectionClosed, None, true),
+            SpiceError::AuthenticationFailed { reason } => (
+                match reason {
+                    AuthFailureReason::TicketExpired => DisconnectReason::TicketExpired,
+                    AuthFailureReason::BadPassword => DisconnectReason::AuthenticationFailed,
+                },
+                Some(LinkError::PermissionDenied as u32),
+                false,
+            ),
+            SpiceError::PasswordRequired => (
+                DisconnectReason::PasswordRequired,
+                Some(LinkError::PermissionDenied as u32),
+                false,
+            ),
+            SpiceError::ChannelNotAvailable(_) => (
+                DisconnectReason::ChannelNotAvailable,
+                Some(LinkError::ChannelNotAvailable as u32),
+                false,
+            ),
+            SpiceError::NeedSecured(_) => (
+                DisconnectReason::SecurityMismatch,
+                Some(LinkError::NeedSecured as u32),
+                false,
+            ),
+            SpiceError::NeedUnsecured(_) => (
+                DisconnectReason::SecurityMismatch,
+                Some(LinkError::NeedUnsecured as u32),
+                false,
+            ),
+            SpiceError::NotSpiceServer(_) => (DisconnectReason::NotSpiceServer, None, false),
+            SpiceError::VersionMismatch { .. } => (
+                DisconnectReason::VersionMismatch,
+                Some(LinkError::VersionMismatch as u32),
+                false,
+            ),
+            SpiceError::Protocol(_)
+            | SpiceError::Channel(_)
+            | SpiceError::Serialization(_)
+            | SpiceError::BinRw(_) => (DisconnectReason::Protocol, None, true),
+        };
+
+        Self {
+            reason,
+            code,
+            recoverable,
+            message: format!("{context} error: {error}"),
+        }
+    }
+}
+
 /// A type alias for `Result<T, SpiceError>`.
 ///
 /// This is the standard result type used throughout the SPICE client library.
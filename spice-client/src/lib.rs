@@ -46,6 +46,13 @@
 //! }
 //! ```
 //!
+//! ## Blocking API
+//!
+//! Simple tools that don't want to manage a runtime can enable the
+//! `blocking` feature and use [`blocking::SpiceClient`](crate::blocking),
+//! which runs the async client on a runtime of its own. It must not be used
+//! from inside an existing Tokio runtime.
+//!
 //! ## WebAssembly Example
 //!
 //! ```ignore
@@ -74,6 +81,7 @@
 //! - **`protocol`** - SPICE protocol message definitions and serialization
 //! - **`client`** - Native client implementation using Tokio
 //! - **`wasm_bindings`** - WebAssembly client using browser APIs
+//! - **`blocking`** - Synchronous client for native tools (`blocking` feature)
 //! - **`channels`** - Individual channel implementations (Main, Display, Inputs, Cursor)
 //! - **`error`** - Error types and result definitions
 //!
@@ -92,7 +100,8 @@
 //! - **No USB redirection** - USB channel not implemented  
 //! - **No clipboard sharing** - Agent clipboard integration not implemented
 //! - **Limited compression** - Only ZLIB compression supported (no LZ4)
-//! - **No TLS encryption** - Only unencrypted connections supported
+//! - **No TLS encryption** - Only unencrypted connections supported; servers
+//!   that require TLS fail the link with [`SpiceError::NeedSecured`]
 //! - **WebSocket proxy required for WASM** - Cannot connect directly to SPICE TCP ports from browsers
 //! - **Partial drawing commands** - Some complex QXL drawing operations not fully implemented
 //!
@@ -143,6 +152,9 @@ pub mod wasm_bindings;
 #[cfg(any(test, feature = "test-utils"))]
 pub mod test_utils;
 
+#[cfg(all(feature = "blocking", not(target_arch = "wasm32")))]
+pub mod blocking;
+
 // For non-WASM builds, export the native client
 #[cfg(not(target_arch = "wasm32"))]
 pub use client::SpiceClient;
@@ -158,7 +170,12 @@ pub type Client = SpiceClient;
 pub struct ClientBuilder {
     host: String,
     port: u16,
+    #[cfg(not(target_arch = "wasm32"))]
+    factory: Option<std::sync::Arc<dyn channels::ConnectionFactory>>,
     password: Option<String>,
+    oaep_hash: OaepHash,
+    oaep_fallback: bool,
+    advertised_caps: channels::AdvertisedCapabilities,
 }
 
 impl ClientBuilder {
@@ -176,7 +193,36 @@ impl ClientBuilder {
         Self {
             host,
             port,
+            #[cfg(not(target_arch = "wasm32"))]
+            factory: None,
             password: None,
+            oaep_hash: OaepHash::default(),
+            oaep_fallback: true,
+            advertised_caps: channels::AdvertisedCapabilities::default(),
+        }
+    }
+
+    /// Create a client builder for a server listening on a Unix domain
+    /// socket, e.g. one started with `-spice unix=on,addr=<path>`
+    #[cfg(unix)]
+    pub fn from_unix_socket(path: impl Into<std::path::PathBuf>) -> Self {
+        let factory = channels::UnixConnectionFactory::new(path);
+        Self::from_connection_factory(std::sync::Arc::new(factory))
+    }
+
+    /// Create a client builder whose channels connect through `factory`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_connection_factory(
+        factory: std::sync::Arc<dyn channels::ConnectionFactory>,
+    ) -> Self {
+        Self {
+            host: String::new(),
+            port: 0,
+            factory: Some(factory),
+            password: None,
+            oaep_hash: OaepHash::default(),
+            oaep_fallback: true,
+            advertised_caps: channels::AdvertisedCapabilities::default(),
         }
     }
 
@@ -186,14 +232,54 @@ impl ClientBuilder {
         self
     }
 
+    /// Encrypt the ticket with `hash` OAEP padding instead of SHA-1
+    pub fn with_oaep_hash(mut self, hash: OaepHash) -> Self {
+        self.oaep_hash = hash;
+        self
+    }
+
+    /// Whether to retry with the other OAEP hash when the server rejects the
+    /// ticket. Enabled by default.
+    pub fn with_oaep_fallback(mut self, enabled: bool) -> Self {
+        self.oaep_fallback = enabled;
+        self
+    }
+
+    /// Advertise the capabilities of `features` instead of those of every
+    /// feature the client supports
+    pub fn with_features(mut self, features: ClientFeatures) -> Self {
+        self.advertised_caps.set_features(features);
+        self
+    }
+
+    /// Advertise `caps` as the `SPICE_COMMON_CAP_*` capabilities of every
+    /// channel instead of the defaults
+    pub fn advertise_common_caps(mut self, caps: &[u32]) -> Self {
+        self.advertised_caps.set_common(caps);
+        self
+    }
+
+    /// Advertise `caps` as the capabilities of channels of `channel_type`
+    /// instead of the defaults
+    pub fn advertise_channel_caps(mut self, channel_type: ChannelType, caps: &[u32]) -> Self {
+        self.advertised_caps.set_channel(channel_type, caps);
+        self
+    }
+
     /// Build the client
     pub fn build(self) -> Result<SpiceClient> {
         #[cfg(not(target_arch = "wasm32"))]
         {
-            let mut client = SpiceClient::new(self.host, self.port);
+            let mut client = match self.factory {
+                Some(factory) => SpiceClient::with_connection_factory(factory),
+                None => SpiceClient::new(self.host, self.port),
+            };
             if let Some(password) = self.password {
                 client.set_password(password);
             }
+            client.set_oaep_hash(self.oaep_hash);
+            client.set_oaep_fallback(self.oaep_fallback);
+            client.set_advertised_caps(self.advertised_caps);
             Ok(client)
         }
         #[cfg(target_arch = "wasm32")]
@@ -207,10 +293,20 @@ impl ClientBuilder {
     }
 }
 
-pub use client_shared::SpiceClientShared;
-pub use error::{Result, SpiceError};
+pub use client_shared::{CursorCallback, FrameCallback, SpiceClientShared};
+pub use error::{AuthFailureReason, DisconnectInfo, DisconnectReason, Result, SpiceError};
 pub use protocol::*;
 pub use video::{VideoFrame, VideoOutput};
 
 // Re-export commonly used types
-pub use channels::{DisplaySurface, InputEvent, KeyCode, MouseButton};
+#[cfg(unix)]
+pub use channels::UnixConnectionFactory;
+pub use channels::{
+    AdvertisedCapabilities, CapabilitySet, ChannelState, ClientFeature, ClientFeatures,
+    ConnectOptions, ConnectPhase, ConnectProgress, CursorEvent, Direction, DisplayEvent,
+    DisplaySurface, InputEvent, KeyCode, KeyboardLayout, KeymapProvider, MainEvent, MediaClock,
+    MonitorInfo, MouseButton, OaepHash, PixelFormat, QosGate, ServerCapabilities, ServerInfo,
+    TraceHook,
+};
+#[cfg(not(target_arch = "wasm32"))]
+pub use channels::{ConnectionFactory, TcpConnectionFactory, WebSocketConnectionFactory};
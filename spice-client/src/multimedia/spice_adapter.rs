@@ -4,11 +4,13 @@ use super::{
     Result,
 };
 use crate::channels::cursor::CursorShape;
+use crate::channels::display::{DisplaySurface, PixelFormat as SurfacePixelFormat};
 use crate::channels::MouseButton as SpiceMouseButton;
+use crate::protocol::SurfaceFormat;
 use crate::SpiceClientShared;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use tracing::{debug, warn};
+use tracing::debug;
 
 /// Adapter that connects SPICE display channel to multimedia display backend
 pub struct SpiceDisplayAdapter {
@@ -77,22 +79,12 @@ impl SpiceDisplayAdapter {
                     *current_dims = (surface.width, surface.height);
                 }
 
-                // Convert SPICE pixel format to our PixelFormat
-                let pixel_format = match surface.format {
-                    1 => PixelFormat::Rgba8888, // SPICE_SURFACE_FMT_32_xRGB
-                    8 => PixelFormat::Rgba8888, // SPICE_SURFACE_FMT_32_ARGB
-                    _ => {
-                        warn!(
-                            "Unknown SPICE surface format: {}, assuming RGBA",
-                            surface.format
-                        );
-                        PixelFormat::Rgba8888
-                    }
-                };
-
-                // Present the frame
+                // Present the frame, converting formats the backend can't take
                 let mut display = self.backend_display.lock().await;
-                display.present_frame(&surface.data, pixel_format)?;
+                match convert_spice_format(&surface) {
+                    Some(pixel_format) => display.present_frame(&surface.data, pixel_format)?,
+                    None => display.present_frame(&surface.to_rgba(), PixelFormat::Rgba8888)?,
+                }
             }
         } else {
             eprintln!("SpiceDisplayAdapter: No surface available from SPICE client");
@@ -135,20 +127,18 @@ impl SpiceDisplayAdapter {
     }
 }
 
-/// Converts SPICE display surface to multimedia pixel format
-#[allow(dead_code)]
-fn convert_spice_format(spice_format: u32) -> PixelFormat {
-    match spice_format {
-        1 => PixelFormat::Rgba8888, // SPICE_SURFACE_FMT_32_xRGB
-        8 => PixelFormat::Rgba8888, // SPICE_SURFACE_FMT_32_ARGB
-        16 => PixelFormat::Rgb565,  // SPICE_SURFACE_FMT_16_565
-        _ => {
-            warn!(
-                "Unknown SPICE surface format: {}, defaulting to RGBA",
-                spice_format
-            );
-            PixelFormat::Rgba8888
-        }
+/// The multimedia pixel format a surface's data is already in, if there is
+/// one
+fn convert_spice_format(surface: &DisplaySurface) -> Option<PixelFormat> {
+    match surface.format {
+        // 32-bit surfaces are kept in the byte order the client was set to
+        SurfaceFormat::Xrgb32 | SurfaceFormat::Argb32 => match surface.pixel_format {
+            SurfacePixelFormat::Rgba8 => Some(PixelFormat::Rgba8888),
+            SurfacePixelFormat::Bgra8 => Some(PixelFormat::Bgra8888),
+            SurfacePixelFormat::RgbaPremultiplied => None,
+        },
+        SurfaceFormat::Rgb565 => Some(PixelFormat::Rgb565),
+        SurfaceFormat::Rgb555 | SurfaceFormat::A8 | SurfaceFormat::A1 => None,
     }
 }
 
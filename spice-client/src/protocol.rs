@@ -39,6 +39,32 @@ pub enum NotifyVisibility {
     High = 2,
 }
 
+impl TryFrom<u32> for NotifySeverity {
+    type Error = u32;
+
+    fn try_from(value: u32) -> std::result::Result<Self, u32> {
+        match value {
+            0 => Ok(NotifySeverity::Info),
+            1 => Ok(NotifySeverity::Warn),
+            2 => Ok(NotifySeverity::Error),
+            other => Err(other),
+        }
+    }
+}
+
+impl TryFrom<u32> for NotifyVisibility {
+    type Error = u32;
+
+    fn try_from(value: u32) -> std::result::Result<Self, u32> {
+        match value {
+            0 => Ok(NotifyVisibility::Low),
+            1 => Ok(NotifyVisibility::Medium),
+            2 => Ok(NotifyVisibility::High),
+            other => Err(other),
+        }
+    }
+}
+
 // Clip type
 #[binrw]
 #[brw(repr = u8)]
@@ -60,7 +86,7 @@ pub enum BrushType {
     Pattern = 2,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u8)]
 pub enum ChannelType {
     Main = 1,
@@ -168,58 +194,181 @@ pub struct SpiceMiniDataHeader {
     pub msg_size: u32,
 }
 
-// Main channel messages
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u16)]
-pub enum MainChannelMessage {
-    MigrateBegin = 101,
-    MigrateCancel = 102,
-    Init = 103,
-    ChannelsList = 104,
-    MouseMode = 105,
-    MultiMediaTime = 106,
-    AgentConnected = 107,
-    AgentDisconnected = 108,
-    AgentData = 109,
-    AgentToken = 110,
-    MigrateSwitchHost = 111,
-    MigrateEnd = 112,
-    Name = 113,
-    Uuid = 114,
-    AgentConnectedTokens = 115,
-    MigrateBeginSeamless = 116,
-    MigrateDstSeamlessAck = 117,
-    MigrateDstSeamlessNack = 118,
-}
-
-// Display channel messages
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u16)]
-pub enum DisplayChannelMessage {
-    Mode = 101,
-    Mark = 102,
-    Reset = 103,
-    CopyBits = 104,
-    InvalList = 105,
-    InvalAllPixmaps = 106,
-    InvalAllPalettes = 107,
-    StreamCreate = 122,
-    StreamData = 123,
-    StreamClip = 124,
-    StreamDestroy = 125,
-    StreamDestroyAll = 126,
-    DrawFill = 302,
-    DrawOpaque = 303,
-    DrawCopy = 304,
-    DrawBlend = 305,
-    DrawBlackness = 306,
-    DrawWhiteness = 307,
-    DrawInvers = 308,
-    DrawRop3 = 309,
-    DrawStroke = 310,
-    DrawText = 311,
-    DrawTransparent = 312,
-    DrawAlphaBlend = 317,
+/// Declares a `#[repr(u16)]` message type enum together with a
+/// `TryFrom<u16>` conversion and an `ALL` list of its variants.
+///
+/// The conversion returns the unrecognized value as the error.
+macro_rules! message_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $($variant:ident = $value:literal,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[repr(u16)]
+        pub enum $name {
+            $($variant = $value,)*
+        }
+
+        impl $name {
+            /// Every message type of this kind, in protocol order
+            pub const ALL: &'static [$name] = &[$($name::$variant,)*];
+        }
+
+        impl From<$name> for u16 {
+            fn from(msg: $name) -> u16 {
+                msg as u16
+            }
+        }
+
+        impl TryFrom<u16> for $name {
+            type Error = u16;
+
+            fn try_from(value: u16) -> std::result::Result<Self, u16> {
+                match value {
+                    $($value => Ok($name::$variant),)*
+                    other => Err(other),
+                }
+            }
+        }
+    };
+}
+
+message_enum! {
+    /// Server messages shared by every channel
+    pub enum CommonMessage {
+        Migrate = 1,
+        MigrateData = 2,
+        SetAck = 3,
+        Ping = 4,
+        WaitForChannels = 5,
+        Disconnecting = 6,
+        Notify = 7,
+    }
+}
+
+message_enum! {
+    /// Main channel server messages
+    pub enum MainChannelMessage {
+        MigrateBegin = 101,
+        MigrateCancel = 102,
+        Init = 103,
+        ChannelsList = 104,
+        MouseMode = 105,
+        MultiMediaTime = 106,
+        AgentConnected = 107,
+        AgentDisconnected = 108,
+        AgentData = 109,
+        AgentToken = 110,
+        MigrateSwitchHost = 111,
+        MigrateEnd = 112,
+        Name = 113,
+        Uuid = 114,
+        AgentConnectedTokens = 115,
+        MigrateBeginSeamless = 116,
+        MigrateDstSeamlessAck = 117,
+        MigrateDstSeamlessNack = 118,
+    }
+}
+
+message_enum! {
+    /// Display channel server messages
+    pub enum DisplayChannelMessage {
+        Mode = 101,
+        Mark = 102,
+        Reset = 103,
+        CopyBits = 104,
+        InvalList = 105,
+        InvalAllPixmaps = 106,
+        InvalPalette = 107,
+        InvalAllPalettes = 108,
+        StreamCreate = 122,
+        StreamData = 123,
+        StreamClip = 124,
+        StreamDestroy = 125,
+        StreamDestroyAll = 126,
+        DrawFill = 302,
+        DrawOpaque = 303,
+        DrawCopy = 304,
+        DrawBlend = 305,
+        DrawBlackness = 306,
+        DrawWhiteness = 307,
+        DrawInvers = 308,
+        DrawRop3 = 309,
+        DrawStroke = 310,
+        DrawText = 311,
+        DrawTransparent = 312,
+        DrawAlphaBlend = 313,
+        SurfaceCreate = 314,
+        SurfaceDestroy = 315,
+        StreamDataSized = 316,
+        MonitorsConfig = 317,
+        DrawComposite = 318,
+        StreamActivateReport = 319,
+        GlScanoutUnix = 320,
+        GlDraw = 321,
+    }
+}
+
+impl DisplayChannelMessage {
+    /// Whether this is one of the `DRAW_*` drawing commands
+    pub fn is_draw(self) -> bool {
+        matches!(
+            self,
+            DisplayChannelMessage::DrawFill
+                | DisplayChannelMessage::DrawOpaque
+                | DisplayChannelMessage::DrawCopy
+                | DisplayChannelMessage::DrawBlend
+                | DisplayChannelMessage::DrawBlackness
+                | DisplayChannelMessage::DrawWhiteness
+                | DisplayChannelMessage::DrawInvers
+                | DisplayChannelMessage::DrawRop3
+                | DisplayChannelMessage::DrawStroke
+                | DisplayChannelMessage::DrawText
+                | DisplayChannelMessage::DrawTransparent
+                | DisplayChannelMessage::DrawAlphaBlend
+                | DisplayChannelMessage::DrawComposite
+        )
+    }
+
+    /// Whether this is one of the `STREAM_*` video stream messages
+    pub fn is_stream(self) -> bool {
+        matches!(
+            self,
+            DisplayChannelMessage::StreamCreate
+                | DisplayChannelMessage::StreamData
+                | DisplayChannelMessage::StreamDataSized
+                | DisplayChannelMessage::StreamClip
+                | DisplayChannelMessage::StreamDestroy
+                | DisplayChannelMessage::StreamDestroyAll
+                | DisplayChannelMessage::StreamActivateReport
+        )
+    }
+}
+
+message_enum! {
+    /// Inputs channel server messages
+    pub enum InputsChannelMessage {
+        Init = 101,
+        KeyModifiers = 102,
+        MouseMotionAck = 111,
+    }
+}
+
+message_enum! {
+    /// Cursor channel server messages
+    pub enum CursorChannelMessage {
+        Init = 101,
+        Reset = 102,
+        Set = 103,
+        Move = 104,
+        Hide = 105,
+        Trail = 106,
+        InvalOne = 107,
+        InvalAll = 108,
+    }
 }
 
 #[binrw]
@@ -333,6 +482,24 @@ pub const SPICE_MAIN_CAP_SEAMLESS_MIGRATE: u32 = 3;
 
 // Client to server display channel messages
 pub const SPICE_MSGC_DISPLAY_INIT: u16 = 101;
+pub const SPICE_MSGC_DISPLAY_STREAM_REPORT: u16 = 102;
+pub const SPICE_MSGC_DISPLAY_PREFERRED_COMPRESSION: u16 = 103;
+
+/// Image compression the client can ask the display channel to use
+/// (`SPICE_IMAGE_COMPRESSION_*`).
+///
+/// Only the methods this client can decode are listed, so a preference the
+/// client can't handle can't be requested. QUIC and GLZ, and the AUTO modes
+/// that may pick them, are left out until decoders exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ImageCompression {
+    /// Send uncompressed bitmaps; least CPU, most bandwidth
+    Off = 1,
+    Lz = 6,
+    /// Fast compression, a good default for low-CPU clients
+    Lz4 = 7,
+}
 
 // Display init message structure
 // Based on spice-protocol/spice/protocol.h
@@ -391,11 +558,46 @@ pub const SPICE_MSG_DISPLAY_DRAW_ROP3: u16 = 309;
 pub const SPICE_MSG_DISPLAY_DRAW_STROKE: u16 = 310;
 pub const SPICE_MSG_DISPLAY_DRAW_TEXT: u16 = 311;
 pub const SPICE_MSG_DISPLAY_DRAW_TRANSPARENT: u16 = 312;
-pub const SPICE_MSG_DISPLAY_DRAW_ALPHA_BLEND: u16 = 317;
-pub const SPICE_MSG_DISPLAY_SURFACE_CREATE: u16 = 318;
-pub const SPICE_MSG_DISPLAY_SURFACE_DESTROY: u16 = 319;
-pub const SPICE_MSG_DISPLAY_MONITORS_CONFIG: u16 = 320;
-pub const SPICE_MSG_DISPLAY_DRAW_COMPOSITE: u16 = 321;
+pub const SPICE_MSG_DISPLAY_DRAW_ALPHA_BLEND: u16 = 313;
+pub const SPICE_MSG_DISPLAY_SURFACE_CREATE: u16 = 314;
+pub const SPICE_MSG_DISPLAY_SURFACE_DESTROY: u16 = 315;
+pub const SPICE_MSG_DISPLAY_STREAM_DATA_SIZED: u16 = 316;
+pub const SPICE_MSG_DISPLAY_MONITORS_CONFIG: u16 = 317;
+pub const SPICE_MSG_DISPLAY_DRAW_COMPOSITE: u16 = 318;
+pub const SPICE_MSG_DISPLAY_STREAM_ACTIVATE_REPORT: u16 = 319;
+pub const SPICE_MSG_DISPLAY_GL_SCANOUT_UNIX: u16 = 320;
+pub const SPICE_MSG_DISPLAY_GL_DRAW: u16 = 321;
+
+// GL scanout flags
+pub const SPICE_GL_SCANOUT_FLAGS_Y0TOP: u32 = 1 << 0;
+
+// Raster operation descriptor flags
+pub const SPICE_ROPD_INVERS_SRC: u16 = 1 << 0;
+pub const SPICE_ROPD_INVERS_BRUSH: u16 = 1 << 1;
+pub const SPICE_ROPD_INVERS_DEST: u16 = 1 << 2;
+pub const SPICE_ROPD_OP_PUT: u16 = 1 << 3;
+pub const SPICE_ROPD_OP_OR: u16 = 1 << 4;
+pub const SPICE_ROPD_OP_AND: u16 = 1 << 5;
+pub const SPICE_ROPD_OP_XOR: u16 = 1 << 6;
+pub const SPICE_ROPD_OP_BLACKNESS: u16 = 1 << 7;
+pub const SPICE_ROPD_OP_WHITENESS: u16 = 1 << 8;
+pub const SPICE_ROPD_OP_INVERS: u16 = 1 << 9;
+pub const SPICE_ROPD_INVERS_RES: u16 = 1 << 10;
+
+// Path segment flags
+/// The segment's first point starts a new subpath
+pub const SPICE_PATH_BEGIN: u8 = 1 << 0;
+/// The segment ends the subpath
+pub const SPICE_PATH_END: u8 = 1 << 1;
+/// The subpath is closed back to its first point after this segment
+pub const SPICE_PATH_CLOSE: u8 = 1 << 3;
+/// The segment's points are cubic Bézier control points, three per curve
+pub const SPICE_PATH_BEZIER: u8 = 1 << 4;
+
+// Line attribute flags
+pub const SPICE_LINE_FLAGS_START_WITH_GAP: u8 = 1 << 2;
+/// The line is dashed according to the attribute's style
+pub const SPICE_LINE_FLAGS_STYLED: u8 = 1 << 3;
 
 // Cursor channel messages
 pub const SPICE_MSG_CURSOR_INIT: u16 = 101;
@@ -407,6 +609,57 @@ pub const SPICE_MSG_CURSOR_TRAIL: u16 = 106;
 pub const SPICE_MSG_CURSOR_INVAL_ONE: u16 = 107;
 pub const SPICE_MSG_CURSOR_INVAL_ALL: u16 = 108;
 
+// Cursor flags
+/// The message carries no cursor shape
+pub const SPICE_CURSOR_FLAGS_NONE: u16 = 1 << 0;
+/// The client should cache the shape under its unique id
+pub const SPICE_CURSOR_FLAGS_CACHE_ME: u16 = 1 << 1;
+/// The shape is the cached one with the header's unique id
+pub const SPICE_CURSOR_FLAGS_FROM_CACHE: u16 = 1 << 2;
+
+// Cursor shape types
+/// 32-bit BGRA pixels
+pub const SPICE_CURSOR_TYPE_ALPHA: u8 = 0;
+/// 1-bit AND mask followed by a 1-bit XOR mask
+pub const SPICE_CURSOR_TYPE_MONO: u8 = 1;
+/// 4-bit palette indices, a 16-entry palette, then a 1-bit AND mask
+pub const SPICE_CURSOR_TYPE_COLOR4: u8 = 2;
+/// 8-bit palette indices, a 256-entry palette, then a 1-bit AND mask
+pub const SPICE_CURSOR_TYPE_COLOR8: u8 = 3;
+/// 16-bit RGB555 pixels, then a 1-bit AND mask
+pub const SPICE_CURSOR_TYPE_COLOR16: u8 = 4;
+/// 24-bit BGR pixels, then a 1-bit AND mask
+pub const SPICE_CURSOR_TYPE_COLOR24: u8 = 5;
+/// 32-bit BGRX pixels, then a 1-bit AND mask
+pub const SPICE_CURSOR_TYPE_COLOR32: u8 = 6;
+
+/// Fixed part of SPICE_MSG_CURSOR_INIT; the cursor shape follows
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SpiceMsgCursorInit {
+    pub position: SpicePoint16,
+    pub trail_length: u16,
+    pub trail_frequency: u16,
+    pub visible: u8,
+}
+
+/// Fixed part of SPICE_MSG_CURSOR_SET; the cursor shape follows
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SpiceMsgCursorSet {
+    pub position: SpicePoint16,
+    pub visible: u8,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SpiceMsgCursorMove {
+    pub position: SpicePoint16,
+}
+
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -464,6 +717,22 @@ pub struct SpiceMsgMainAgentData {
     pub data: Vec<u8>,
 }
 
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpiceMsgMainName {
+    pub name_len: u32,
+    #[br(count = name_len)]
+    pub name: Vec<u8>, // NUL-terminated
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SpiceMsgMainUuid {
+    pub uuid: [u8; 16],
+}
+
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -509,6 +778,16 @@ pub struct SpiceClip {
 // SPICE_ADDRESS is a 64-bit offset from the beginning of the message body
 pub type SpiceAddress = u64;
 
+/// Rectangle list referenced by a `ClipType::Rects` clip
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpiceClipRects {
+    pub num_rects: u32,
+    #[br(count = num_rects)]
+    pub rects: Vec<SpiceRect>,
+}
+
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -610,6 +889,77 @@ pub struct SpiceDrawBlendData {
     pub mask: SpiceQMask,
 }
 
+/// Copy of an image that leaves out the pixels of one color
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpiceDrawTransparent {
+    pub base: SpiceDrawBase,
+    pub data: SpiceDrawTransparentData,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpiceDrawTransparentData {
+    pub src_image: SpiceAddress,
+    pub src_area: SpiceRect,
+    pub src_color: u32,  // Transparent color in the source image's format
+    pub true_color: u32, // The same color as 0xRRGGBB
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpiceDrawStroke {
+    pub base: SpiceDrawBase,
+    pub data: SpiceDrawStrokeData,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpiceDrawStrokeData {
+    pub path: SpiceAddress, // Address to the SpicePath
+    pub attr: SpiceLineAttr,
+    pub brush: SpiceBrush,
+    pub fore_mode: u16, // Raster operation for the line
+    pub back_mode: u16, // Raster operation for the gaps of a styled line
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpiceLineAttr {
+    pub flags: u8,
+    pub style_nseg: u8,
+    #[br(pad_before = 6)] // 6 bytes padding for alignment before u64
+    #[bw(pad_before = 6)]
+    pub style: SpiceAddress, // Address to style_nseg Fixed28_4 dash lengths
+}
+
+/// Path referenced by a stroke, made of one or more segments
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpicePath {
+    pub num_segments: u32,
+    #[br(count = num_segments)]
+    pub segments: Vec<SpicePathSeg>,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpicePathSeg {
+    pub flags: u8, // SPICE_PATH_* flags
+    #[br(pad_before = 3)] // 3 bytes padding for alignment
+    #[bw(pad_before = 3)]
+    pub count: u32,
+    #[br(count = count)]
+    pub points: Vec<SpicePointFix>,
+}
+
 // Stream structures
 #[binrw]
 #[brw(little)]
@@ -640,6 +990,22 @@ pub struct SpiceStreamData {
     pub data: Vec<u8>,
 }
 
+/// Stream frame with its own size and position, sent instead of
+/// `SpiceStreamData` to clients with `SPICE_DISPLAY_CAP_SIZED_STREAM`
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpiceStreamDataSized {
+    pub id: u32,
+    pub multi_media_time: u32,
+    pub width: u32,
+    pub height: u32,
+    pub dest: SpiceRect,
+    pub data_size: u32,
+    #[br(count = data_size)]
+    pub data: Vec<u8>,
+}
+
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -647,6 +1013,46 @@ pub struct SpiceStreamDestroy {
     pub id: u32,
 }
 
+/// New clip region of a stream; frames are only drawn inside it
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpiceStreamClip {
+    pub id: u32,
+    pub clip: SpiceClip,
+}
+
+/// Server request to report playback statistics of a stream
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SpiceStreamActivateReport {
+    pub stream_id: u32,
+    pub unique_id: u32,
+    /// Frames after which to send a report
+    pub max_window_size: u32,
+    /// Milliseconds after which to send a report, even with fewer frames
+    pub timeout_ms: u32,
+}
+
+/// Playback statistics of a stream, sent as `SPICE_MSGC_DISPLAY_STREAM_REPORT`
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpiceMsgcDisplayStreamReport {
+    pub stream_id: u32,
+    /// Echoes `unique_id` of the activate report request
+    pub unique_id: u32,
+    pub start_frame_mm_time: u32,
+    pub end_frame_mm_time: u32,
+    pub num_frames: u32,
+    pub num_drops: u32,
+    /// Milliseconds the last frame arrived ahead of its multimedia time;
+    /// negative when it was late
+    pub last_frame_delay: i32,
+    pub audio_delay: u32,
+}
+
 // Image structures
 #[binrw]
 #[brw(little)]
@@ -686,6 +1092,11 @@ pub const SPICE_BITMAP_FMT_32BIT: u8 = 8;
 pub const SPICE_BITMAP_FMT_RGBA: u8 = 9;
 pub const SPICE_BITMAP_FMT_8BIT_A: u8 = 10;
 
+// Bitmap flags
+pub const SPICE_BITMAP_FLAGS_PAL_CACHE_ME: u8 = 1 << 0;
+pub const SPICE_BITMAP_FLAGS_PAL_FROM_CACHE: u8 = 1 << 1;
+pub const SPICE_BITMAP_FLAGS_TOP_DOWN: u8 = 1 << 2;
+
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -709,6 +1120,68 @@ pub struct SpiceBitmap {
     pub data: SpiceAddress,    // Address to bitmap data
 }
 
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpicePalette {
+    pub unique: u64,
+    pub num_ents: u16,
+    #[br(count = num_ents)]
+    pub ents: Vec<u32>, // 0xXXRRGGBB colors
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SpiceMsgDisplayInvalOne {
+    pub id: u64,
+}
+
+// Resource types in SPICE_MSG_DISPLAY_INVAL_LIST
+pub const SPICE_RES_TYPE_INVALID: u8 = 0;
+pub const SPICE_RES_TYPE_PIXMAP: u8 = 1;
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpiceResourceId {
+    pub type_: u8,
+    pub id: u64,
+}
+
+/// Body of SPICE_MSG_DISPLAY_INVAL_LIST
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpiceResourceList {
+    pub count: u16,
+    #[br(count = count)]
+    pub resources: Vec<SpiceResourceId>,
+}
+
+/// GL scanout of a guest framebuffer. The dmabuf file descriptor is passed
+/// out of band over the unix socket and is not part of the message body.
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpiceMsgDisplayGlScanoutUnix {
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+    pub drm_fourcc_format: u32,
+    pub flags: u32,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpiceMsgDisplayGlDraw {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
 // Surface structures
 #[binrw]
 #[brw(little)]
@@ -721,6 +1194,91 @@ pub struct SpiceMsgSurfaceCreate {
     pub flags: u32,
 }
 
+/// The surface is the visible screen rather than an off-screen buffer
+pub const SPICE_SURFACE_FLAGS_PRIMARY: u32 = 1 << 0;
+/// The server streams the surface's content as video
+pub const SPICE_SURFACE_FLAGS_STREAMING_MODE: u32 = 1 << 1;
+
+impl SpiceMsgSurfaceCreate {
+    pub fn is_primary(&self) -> bool {
+        self.flags & SPICE_SURFACE_FLAGS_PRIMARY != 0
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.flags & SPICE_SURFACE_FLAGS_STREAMING_MODE != 0
+    }
+}
+
+/// Pixel format of a display surface (`SPICE_SURFACE_FMT_*`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum SurfaceFormat {
+    A1 = 1,
+    A8 = 8,
+    Rgb555 = 16,
+    Xrgb32 = 32,
+    Rgb565 = 80,
+    Argb32 = 96,
+}
+
+impl SurfaceFormat {
+    pub fn bits_per_pixel(self) -> u32 {
+        match self {
+            SurfaceFormat::A1 => 1,
+            SurfaceFormat::A8 => 8,
+            SurfaceFormat::Rgb555 | SurfaceFormat::Rgb565 => 16,
+            SurfaceFormat::Xrgb32 | SurfaceFormat::Argb32 => 32,
+        }
+    }
+
+    /// Bytes per row of a surface `width` pixels wide
+    pub fn stride(self, width: u32) -> usize {
+        (width as usize * self.bits_per_pixel() as usize + 7) / 8
+    }
+
+    /// Size of the pixel buffer for a `width` x `height` surface
+    pub fn buffer_size(self, width: u32, height: u32) -> usize {
+        self.stride(width) * height as usize
+    }
+
+    pub fn has_alpha(self) -> bool {
+        matches!(
+            self,
+            SurfaceFormat::A1 | SurfaceFormat::A8 | SurfaceFormat::Argb32
+        )
+    }
+
+    /// Surface format for a legacy `DISPLAY_MODE` color depth
+    pub fn from_depth(bits: u32) -> Self {
+        match bits {
+            16 => SurfaceFormat::Rgb555,
+            _ => SurfaceFormat::Xrgb32,
+        }
+    }
+}
+
+impl From<SurfaceFormat> for u32 {
+    fn from(format: SurfaceFormat) -> u32 {
+        format as u32
+    }
+}
+
+impl TryFrom<u32> for SurfaceFormat {
+    type Error = u32;
+
+    fn try_from(value: u32) -> std::result::Result<Self, u32> {
+        match value {
+            1 => Ok(SurfaceFormat::A1),
+            8 => Ok(SurfaceFormat::A8),
+            16 => Ok(SurfaceFormat::Rgb555),
+            32 => Ok(SurfaceFormat::Xrgb32),
+            80 => Ok(SurfaceFormat::Rgb565),
+            96 => Ok(SurfaceFormat::Argb32),
+            other => Err(other),
+        }
+    }
+}
+
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -756,5 +1314,33 @@ pub struct SpiceMonitorsConfig {
 pub const SPICE_HEAD_FLAGS_NONE: u32 = 0;
 pub const SPICE_HEAD_FLAGS_PRIMARY: u32 = 1 << 0;
 
+// Guest agent (spice-vdagent) messages, carried in SPICE_MSG_MAIN_AGENT_DATA
+pub const VD_AGENT_PROTOCOL: u32 = 1;
+pub const VD_AGENT_MONITORS_CONFIG: u32 = 2;
+
+// Monitors config flags
+pub const VD_AGENT_CONFIG_MONITORS_FLAG_USE_POS: u32 = 1 << 0;
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct VDAgentMonConfig {
+    pub height: u32,
+    pub width: u32,
+    pub depth: u32,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VDAgentMonitorsConfig {
+    pub num_of_monitors: u32,
+    pub flags: u32,
+    #[br(count = num_of_monitors)]
+    pub monitors: Vec<VDAgentMonConfig>,
+}
+
 #[cfg(test)]
 mod tests;
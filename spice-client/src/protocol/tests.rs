@@ -182,6 +182,99 @@ fn test_display_channel_message_types() {
     assert_eq!(DisplayChannelMessage::CopyBits as u16, 104);
 }
 
+#[test]
+fn test_display_message_ids_match_spice_protocol() {
+    // SPICE_MSG_DISPLAY_* from spice-protocol's enums.h
+    let expected = [
+        (DisplayChannelMessage::Mode, 101),
+        (DisplayChannelMessage::Mark, 102),
+        (DisplayChannelMessage::Reset, 103),
+        (DisplayChannelMessage::CopyBits, 104),
+        (DisplayChannelMessage::InvalList, 105),
+        (DisplayChannelMessage::InvalAllPixmaps, 106),
+        (DisplayChannelMessage::InvalPalette, 107),
+        (DisplayChannelMessage::InvalAllPalettes, 108),
+        (DisplayChannelMessage::StreamCreate, 122),
+        (DisplayChannelMessage::StreamData, 123),
+        (DisplayChannelMessage::StreamClip, 124),
+        (DisplayChannelMessage::StreamDestroy, 125),
+        (DisplayChannelMessage::StreamDestroyAll, 126),
+        (DisplayChannelMessage::DrawFill, 302),
+        (DisplayChannelMessage::DrawOpaque, 303),
+        (DisplayChannelMessage::DrawCopy, 304),
+        (DisplayChannelMessage::DrawBlend, 305),
+        (DisplayChannelMessage::DrawBlackness, 306),
+        (DisplayChannelMessage::DrawWhiteness, 307),
+        (DisplayChannelMessage::DrawInvers, 308),
+        (DisplayChannelMessage::DrawRop3, 309),
+        (DisplayChannelMessage::DrawStroke, 310),
+        (DisplayChannelMessage::DrawText, 311),
+        (DisplayChannelMessage::DrawTransparent, 312),
+        (DisplayChannelMessage::DrawAlphaBlend, 313),
+        (DisplayChannelMessage::SurfaceCreate, 314),
+        (DisplayChannelMessage::SurfaceDestroy, 315),
+        (DisplayChannelMessage::StreamDataSized, 316),
+        (DisplayChannelMessage::MonitorsConfig, 317),
+        (DisplayChannelMessage::DrawComposite, 318),
+        (DisplayChannelMessage::StreamActivateReport, 319),
+        (DisplayChannelMessage::GlScanoutUnix, 320),
+        (DisplayChannelMessage::GlDraw, 321),
+    ];
+    for (msg, id) in expected {
+        assert_eq!(u16::from(msg), id, "{msg:?}");
+    }
+}
+
+fn assert_round_trips<T>(all: &[T])
+where
+    T: Copy + PartialEq + std::fmt::Debug + Into<u16> + TryFrom<u16, Error = u16>,
+{
+    for &msg in all {
+        let value: u16 = msg.into();
+        assert_eq!(T::try_from(value), Ok(msg));
+    }
+}
+
+#[test]
+fn test_message_enums_round_trip() {
+    assert_round_trips(CommonMessage::ALL);
+    assert_round_trips(MainChannelMessage::ALL);
+    assert_round_trips(DisplayChannelMessage::ALL);
+    assert_round_trips(InputsChannelMessage::ALL);
+    assert_round_trips(CursorChannelMessage::ALL);
+}
+
+#[test]
+fn test_message_enums_match_constants() {
+    assert_eq!(u16::from(CommonMessage::SetAck), SPICE_MSG_SET_ACK);
+    assert_eq!(u16::from(CommonMessage::Notify), SPICE_MSG_NOTIFY);
+    assert_eq!(
+        u16::from(MainChannelMessage::AgentConnectedTokens),
+        SPICE_MSG_MAIN_AGENT_CONNECTED_TOKENS
+    );
+    assert_eq!(
+        u16::from(DisplayChannelMessage::SurfaceCreate),
+        SPICE_MSG_DISPLAY_SURFACE_CREATE
+    );
+    assert_eq!(
+        u16::from(DisplayChannelMessage::GlDraw),
+        SPICE_MSG_DISPLAY_GL_DRAW
+    );
+    assert_eq!(
+        u16::from(CursorChannelMessage::InvalAll),
+        SPICE_MSG_CURSOR_INVAL_ALL
+    );
+}
+
+#[test]
+fn test_unknown_message_type_rejected() {
+    assert_eq!(CommonMessage::try_from(0), Err(0));
+    assert_eq!(MainChannelMessage::try_from(119), Err(119));
+    assert_eq!(DisplayChannelMessage::try_from(109), Err(109));
+    assert_eq!(InputsChannelMessage::try_from(103), Err(103));
+    assert_eq!(CursorChannelMessage::try_from(109), Err(109));
+}
+
 #[test]
 fn test_struct_sizes() {
     // Ensure structs have expected sizes for protocol compatibility
@@ -404,5 +497,5 @@ fn test_message_type_enum_conversion() {
     // Test DisplayChannelMessage enum conversions
     assert_eq!(DisplayChannelMessage::Mode as u16, 101);
     assert_eq!(DisplayChannelMessage::DrawCopy as u16, 304);
-    assert_eq!(DisplayChannelMessage::DrawAlphaBlend as u16, 317);
+    assert_eq!(DisplayChannelMessage::DrawAlphaBlend as u16, 313);
 }
@@ -1,21 +1,37 @@
 //! Test utilities for SPICE client
 
-use crate::error::Result;
+use crate::channels::OaepHash;
+use crate::error::{Result, SpiceError};
 use crate::protocol::*;
 use binrw::io::Cursor;
 use binrw::{BinRead, BinWrite};
+use rsa::pkcs8::EncodePublicKey;
+use rsa::traits::PublicKeyParts;
+use rsa::{Oaep, RsaPrivateKey};
+use sha1::Sha1;
 use std::collections::HashMap;
+use std::future::Future;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// Compose file with the QEMU-based SPICE server
+const DOCKER_COMPOSE_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/docker/docker-compose.yml");
+const DOCKER_PROFILE: &str = "server-qemu";
+const DOCKER_SERVICE: &str = "spice-qemu-server";
+/// Port the compose file publishes the server's SPICE port on
+const DOCKER_SPICE_PORT: u16 = 5900;
 
 /// Mock SPICE server for testing
 #[derive(Clone)]
 pub struct MockSpiceServer {
     addr: SocketAddr,
     connections: Arc<Mutex<HashMap<u8, TcpStream>>>,
+    tickets: Arc<Mutex<Vec<String>>>,
 }
 
 impl MockSpiceServer {
@@ -44,13 +60,63 @@ impl MockSpiceServer {
             }
         });
 
-        Ok(Self { addr, connections })
+        Ok(Self {
+            addr,
+            connections,
+            tickets: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    /// Start a mock server that requires `password` as the ticket and serves
+    /// a main channel announcing no other channels, enough for
+    /// `SpiceClient::connect` to succeed
+    pub async fn with_password(bind_addr: &str, password: &str) -> Result<Self> {
+        let listener = TcpListener::bind(bind_addr).await?;
+        let addr = listener.local_addr()?;
+        let password = password.to_string();
+
+        let connections = Arc::new(Mutex::new(HashMap::new()));
+        let tickets = Arc::new(Mutex::new(Vec::new()));
+        let tickets_clone = tickets.clone();
+
+        tokio::spawn(async move {
+            loop {
+                if let Ok((mut stream, _)) = listener.accept().await {
+                    let password = password.clone();
+                    let tickets = tickets_clone.clone();
+                    tokio::spawn(async move {
+                        if let Ok((ticket, _)) = handle_ticket_handshake(&mut stream).await {
+                            let accepted = ticket == password;
+                            tickets.lock().await.push(ticket);
+                            if accepted {
+                                let _ = serve_main_channel(&mut stream, &[]).await;
+                            } else {
+                                let result = LinkError::PermissionDenied as u32;
+                                let _ = stream.write_all(&result.to_le_bytes()).await;
+                            }
+                        }
+                    });
+                }
+            }
+        });
+
+        Ok(Self {
+            addr,
+            connections,
+            tickets,
+        })
     }
 
     pub fn local_addr(&self) -> SocketAddr {
         self.addr
     }
 
+    /// Tickets received by a server started with `with_password`, in the
+    /// order the clients sent them
+    pub async fn received_tickets(&self) -> Vec<String> {
+        self.tickets.lock().await.clone()
+    }
+
     pub async fn send_display_message(&self, msg_type: u16, data_bytes: Vec<u8>) -> Result<()> {
         self.send_message_to_channel(0, msg_type, data_bytes).await
     }
@@ -96,6 +162,115 @@ impl MockSpiceServer {
     }
 }
 
+/// A real SPICE server for tests that need more than the mock: QEMU in the
+/// `spice-qemu-server` service of `docker/docker-compose.yml`.
+///
+/// With `SPICE_TEST_HOST` set, the server at that host and `SPICE_TEST_PORT`
+/// (5900 by default) is used instead and no container is started, e.g. for
+/// a QEMU run by hand. A started container is taken down on drop.
+pub struct DockerSpiceServer {
+    host: String,
+    port: u16,
+    started_container: bool,
+}
+
+impl DockerSpiceServer {
+    /// Start the container, or use the server from the environment, and wait
+    /// up to `ready_timeout` for it to pass its health check. Building the
+    /// image the first time takes a few minutes.
+    pub async fn start(ready_timeout: Duration) -> Result<Self> {
+        if let Ok(host) = std::env::var("SPICE_TEST_HOST") {
+            let port = std::env::var("SPICE_TEST_PORT")
+                .ok()
+                .and_then(|port| port.parse().ok())
+                .unwrap_or(DOCKER_SPICE_PORT);
+            return Ok(Self {
+                host,
+                port,
+                started_container: false,
+            });
+        }
+
+        docker_compose(&["up", "--detach", "--build", DOCKER_SERVICE]).await?;
+        let server = Self {
+            host: "127.0.0.1".to_string(),
+            port: DOCKER_SPICE_PORT,
+            started_container: true,
+        };
+
+        // Docker accepts connections on the published port before QEMU
+        // listens, so wait for the health check instead
+        let container = docker_compose(&["ps", "--quiet", DOCKER_SERVICE]).await?;
+        let container = container.trim();
+        let deadline = tokio::time::Instant::now() + ready_timeout;
+        loop {
+            let health =
+                docker(&["inspect", "--format", "{{.State.Health.Status}}", container]).await?;
+            if health.trim() == "healthy" {
+                return Ok(server);
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(SpiceError::Connection(format!(
+                    "{DOCKER_SERVICE} is not healthy after {ready_timeout:?}: {}",
+                    health.trim()
+                )));
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Drop for DockerSpiceServer {
+    fn drop(&mut self) {
+        if self.started_container {
+            let _ = std::process::Command::new("docker")
+                .args(compose_args(&["down"]))
+                .output();
+        }
+    }
+}
+
+fn compose_args<'a>(args: &[&'a str]) -> Vec<&'a str> {
+    let mut all = vec![
+        "compose",
+        "-f",
+        DOCKER_COMPOSE_FILE,
+        "--profile",
+        DOCKER_PROFILE,
+    ];
+    all.extend_from_slice(args);
+    all
+}
+
+async fn docker_compose(args: &[&str]) -> Result<String> {
+    docker(&compose_args(args)).await
+}
+
+/// Run docker and return its output, failing if it exits unsuccessfully
+async fn docker(args: &[&str]) -> Result<String> {
+    let output = tokio::process::Command::new("docker")
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| SpiceError::Connection(format!("Failed to run docker: {e}")))?;
+    if !output.status.success() {
+        return Err(SpiceError::Connection(format!(
+            "docker {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
 async fn handle_handshake(stream: &mut TcpStream) -> Result<()> {
     // Read link header
     let mut header_buf = vec![0u8; std::mem::size_of::<SpiceLinkHeader>()];
@@ -122,3 +297,272 @@ async fn handle_handshake(stream: &mut TcpStream) -> Result<()> {
 
     Ok(())
 }
+
+/// RSA key the mock servers link with. Generating one is slow, so every
+/// server of a test run shares it.
+pub fn mock_key() -> &'static RsaPrivateKey {
+    static KEY: OnceLock<RsaPrivateKey> = OnceLock::new();
+    KEY.get_or_init(|| RsaPrivateKey::new(&mut rand::rngs::OsRng, 1024).unwrap())
+}
+
+/// Key material a mock server hands out in its link reply
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MockKeyFormat {
+    /// A DER SubjectPublicKeyInfo, as servers send it
+    #[default]
+    Spki,
+    /// The modulus and exponent laid out raw, as some older servers do
+    RawModulusExponent,
+}
+
+/// Encode the public half of `key` for the 162-byte field of a link reply
+pub fn encode_mock_public_key(key: &RsaPrivateKey, format: MockKeyFormat) -> [u8; 162] {
+    let mut pub_key = [0u8; 162];
+    match format {
+        MockKeyFormat::Spki => {
+            let der = key.to_public_key().to_public_key_der().unwrap();
+            pub_key.copy_from_slice(der.as_bytes());
+        }
+        MockKeyFormat::RawModulusExponent => {
+            let modulus = key.n().to_bytes_be();
+            let exponent = key.e().to_bytes_be();
+            pub_key[128 - modulus.len()..128].copy_from_slice(&modulus);
+            pub_key[162 - exponent.len()..].copy_from_slice(&exponent);
+        }
+    }
+    pub_key
+}
+
+/// How a mock server answers a channel link. The default links any client
+/// without a password.
+#[derive(Debug, Clone)]
+pub struct MockLink {
+    pub key_format: MockKeyFormat,
+    /// OAEP padding the ticket has to be encrypted with
+    pub oaep_hash: OaepHash,
+    pub password: String,
+    pub version: (u32, u32),
+    pub channel_caps: Vec<u32>,
+}
+
+impl Default for MockLink {
+    fn default() -> Self {
+        Self {
+            key_format: MockKeyFormat::Spki,
+            oaep_hash: OaepHash::Sha1,
+            password: String::new(),
+            version: (SPICE_VERSION_MAJOR, SPICE_VERSION_MINOR),
+            channel_caps: Vec::new(),
+        }
+    }
+}
+
+impl MockLink {
+    /// Run one link exchange on `stream`, signing the [`mock_key`], and
+    /// return whether the ticket was accepted
+    pub async fn serve<S>(&self, stream: &mut S) -> Result<bool>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut header_buf = vec![0u8; std::mem::size_of::<SpiceLinkHeader>()];
+        stream.read_exact(&mut header_buf).await?;
+        let header = SpiceLinkHeader::read_le(&mut Cursor::new(&header_buf))?;
+        let mut mess_buf = vec![0u8; header.size as usize];
+        stream.read_exact(&mut mess_buf).await?;
+
+        let key = mock_key();
+        let reply = SpiceLinkReply {
+            magic: SPICE_MAGIC,
+            major_version: self.version.0,
+            minor_version: self.version.1,
+            size: 178 + self.channel_caps.len() as u32 * 4,
+        };
+        let reply_data = SpiceLinkReplyData {
+            error: 0,
+            pub_key: encode_mock_public_key(key, self.key_format),
+            num_common_caps: 0,
+            num_channel_caps: self.channel_caps.len() as u32,
+            caps_offset: 178,
+        };
+        let mut reply_bytes = Cursor::new(Vec::new());
+        reply.write_le(&mut reply_bytes)?;
+        reply_data.write_le(&mut reply_bytes)?;
+        for cap in &self.channel_caps {
+            cap.write_le(&mut reply_bytes)?;
+        }
+        stream.write_all(&reply_bytes.into_inner()).await?;
+
+        let mut encrypted = [0u8; 128];
+        stream.read_exact(&mut encrypted).await?;
+        let decrypted = match self.oaep_hash {
+            OaepHash::Sha1 => key.decrypt(Oaep::new::<Sha1>(), &encrypted),
+            OaepHash::Sha256 => key.decrypt(Oaep::new::<rsa::sha2::Sha256>(), &encrypted),
+        };
+        let accepted = decrypted.is_ok_and(|plain| plain == self.password.as_bytes());
+
+        let result = if accepted {
+            0
+        } else {
+            LinkError::PermissionDenied as u32
+        };
+        stream.write_all(&result.to_le_bytes()).await?;
+        stream.flush().await?;
+        Ok(accepted)
+    }
+
+    /// Start a server for one connection on a free local port: the link is
+    /// served and, if the ticket was accepted, `serve` is handed the socket.
+    ///
+    /// Returns the address to connect to and the server task, which yields
+    /// what `serve` returns.
+    pub async fn spawn<F, Fut>(self, serve: F) -> (SocketAddr, JoinHandle<Fut::Output>)
+    where
+        F: FnOnce(TcpStream) -> Fut + Send + 'static,
+        Fut: Future + Send,
+        Fut::Output: Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let task = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            assert!(self.serve(&mut socket).await.unwrap(), "link refused");
+            serve(socket).await
+        });
+        (addr, task)
+    }
+}
+
+/// Serve one channel connection of a mock server requiring `password` over
+/// any stream, e.g. one end of a `tokio::io::duplex` handed out by a
+/// [`ConnectionFactory`](crate::channels::ConnectionFactory).
+///
+/// The main channel announces `channels`; any other channel is linked and
+/// held open until the client goes away. Returns the type of the channel
+/// served.
+pub async fn serve_mock_channel<S>(
+    mut stream: S,
+    password: &str,
+    channels: &[(ChannelType, u8)],
+) -> Result<u8>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (ticket, channel_type) = handle_ticket_handshake(&mut stream).await?;
+    if ticket != password {
+        let result = LinkError::PermissionDenied as u32;
+        stream.write_all(&result.to_le_bytes()).await?;
+        return Ok(channel_type);
+    }
+
+    if channel_type == ChannelType::Main as u8 {
+        serve_main_channel(&mut stream, channels).await?;
+    } else {
+        stream.write_all(&0u32.to_le_bytes()).await?;
+        stream.flush().await?;
+        hold_open(&mut stream).await?;
+    }
+    Ok(channel_type)
+}
+
+/// Serve a link reply advertising the [`mock_key`] and return the decrypted
+/// ticket and the type of channel being linked, leaving the link result to
+/// the caller
+async fn handle_ticket_handshake<S>(stream: &mut S) -> Result<(String, u8)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut header_buf = vec![0u8; std::mem::size_of::<SpiceLinkHeader>()];
+    stream.read_exact(&mut header_buf).await?;
+    let header = SpiceLinkHeader::read_le(&mut Cursor::new(&header_buf))?;
+    let mut mess_buf = vec![0u8; header.size as usize];
+    stream.read_exact(&mut mess_buf).await?;
+    let mess = SpiceLinkMess::read_le(&mut Cursor::new(&mess_buf))?;
+
+    let key = mock_key();
+    let reply = SpiceLinkReply {
+        magic: SPICE_MAGIC,
+        major_version: SPICE_VERSION_MAJOR,
+        minor_version: SPICE_VERSION_MINOR,
+        size: 178,
+    };
+    let reply_data = SpiceLinkReplyData {
+        error: 0,
+        pub_key: encode_mock_public_key(key, MockKeyFormat::Spki),
+        num_common_caps: 0,
+        num_channel_caps: 0,
+        caps_offset: 178,
+    };
+    let mut reply_bytes = Cursor::new(Vec::new());
+    reply.write_le(&mut reply_bytes)?;
+    reply_data.write_le(&mut reply_bytes)?;
+    stream.write_all(&reply_bytes.into_inner()).await?;
+    stream.flush().await?;
+
+    let mut encrypted = [0u8; 128];
+    stream.read_exact(&mut encrypted).await?;
+    let ticket = key
+        .decrypt(Oaep::new::<Sha1>(), &encrypted)
+        .map(|plain| String::from_utf8_lossy(&plain).into_owned())
+        .unwrap_or_default();
+    Ok((ticket, mess.channel_type))
+}
+
+/// Accept the ticket and send the main channel init and a list of
+/// `channels`, then hold the stream open until the client goes away
+async fn serve_main_channel<S>(stream: &mut S, channels: &[(ChannelType, u8)]) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(&0u32.to_le_bytes()).await?;
+
+    let mut init = Cursor::new(Vec::new());
+    SpiceMsgMainInit {
+        session_id: 1,
+        display_channels_hint: 0,
+        supported_mouse_modes: 0,
+        current_mouse_mode: 0,
+        agent_connected: 0,
+        agent_tokens: 0,
+        multi_media_time: 0,
+        ram_hint: 0,
+    }
+    .write_le(&mut init)?;
+    let mut channels_list = (channels.len() as u32).to_le_bytes().to_vec();
+    for (channel_type, channel_id) in channels {
+        channels_list.extend([*channel_type as u8, *channel_id]);
+    }
+    let messages = [
+        (MainChannelMessage::Init as u16, init.into_inner()),
+        (MainChannelMessage::ChannelsList as u16, channels_list),
+    ];
+    for (serial, (msg_type, body)) in messages.iter().enumerate() {
+        let header = SpiceDataHeader {
+            serial: serial as u64 + 1,
+            msg_type: *msg_type,
+            msg_size: body.len() as u32,
+            sub_list: 0,
+        };
+        let mut header_bytes = Vec::new();
+        header.write_le(&mut Cursor::new(&mut header_bytes))?;
+        stream.write_all(&header_bytes).await?;
+        stream.write_all(body).await?;
+    }
+    stream.flush().await?;
+
+    hold_open(stream).await
+}
+
+/// Read and drop whatever the client sends until it goes away, so a test
+/// server stays up for as long as the client needs it
+pub async fn hold_open<S: AsyncRead + Unpin>(stream: &mut S) -> Result<()> {
+    let mut buf = [0u8; 1024];
+    loop {
+        match stream.read(&mut buf).await {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            // A client dropped with data still unread resets the connection
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionReset => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
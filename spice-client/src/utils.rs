@@ -9,6 +9,41 @@ pub async fn sleep(duration: Duration) {
     gloo_timers::future::sleep(duration).await;
 }
 
+/// Cross-platform yield to the scheduler.
+///
+/// In the browser this goes back to the event loop, so other channel tasks
+/// and input handlers get to run between messages.
+pub async fn yield_now() {
+    #[cfg(not(target_arch = "wasm32"))]
+    tokio::task::yield_now().await;
+
+    #[cfg(target_arch = "wasm32")]
+    gloo_timers::future::TimeoutFuture::new(0).await;
+}
+
+/// Wait until the next frame: a turn of the scheduler natively, the next
+/// animation frame in the browser.
+///
+/// Input that arrives in the meantime can be sent together.
+pub async fn next_frame() {
+    #[cfg(not(target_arch = "wasm32"))]
+    tokio::task::yield_now().await;
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        let promise = js_sys::Promise::new(&mut |resolve, _| {
+            let scheduled = web_sys::window()
+                .map(|window| window.request_animation_frame(&resolve).is_ok())
+                .unwrap_or(false);
+            // Workers have no animation frames
+            if !scheduled {
+                let _ = resolve.call0(&wasm_bindgen::JsValue::NULL);
+            }
+        });
+        let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+    }
+}
+
 /// Task handle for cross-platform compatibility
 #[cfg(not(target_arch = "wasm32"))]
 pub type TaskHandle<T> = tokio::task::JoinHandle<T>;
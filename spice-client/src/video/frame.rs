@@ -1,4 +1,4 @@
-use crate::channels::display::DisplaySurface;
+use crate::channels::display::{DisplaySurface, PixelFormat};
 use base64::{engine::general_purpose, Engine as _};
 use instant::Instant;
 
@@ -7,67 +7,70 @@ pub struct VideoFrame {
     pub width: u32,
     pub height: u32,
     pub data_url: String,
+    /// Byte order of the pixels in `data_url`
+    pub pixel_format: PixelFormat,
     pub timestamp: Instant,
+    /// The last frame from before a reconnect, shown until the server draws
+    /// again. Renderers should make it look different, e.g. dimmed with a
+    /// "reconnecting" overlay.
+    pub stale: bool,
 }
 
 impl PartialEq for VideoFrame {
     fn eq(&self, other: &Self) -> bool {
-        self.width == other.width && self.height == other.height && self.data_url == other.data_url
+        self.width == other.width
+            && self.height == other.height
+            && self.data_url == other.data_url
+            && self.pixel_format == other.pixel_format
+            && self.stale == other.stale
         // Note: We exclude timestamp from equality comparison
     }
 }
 
 impl VideoFrame {
     pub fn from_surface(surface: &DisplaySurface) -> Self {
-        let data_url =
-            Self::create_data_url(surface.width, surface.height, &surface.data, surface.format);
+        Self::from_surface_with_format(surface, PixelFormat::Rgba8)
+    }
+
+    /// Like [`from_surface`](Self::from_surface), with the pixels in
+    /// `pixel_format` order. Surfaces already kept in that order are
+    /// encoded without converting.
+    pub fn from_surface_with_format(surface: &DisplaySurface, pixel_format: PixelFormat) -> Self {
+        let data_url = Self::pixels_to_data_url(
+            surface.width,
+            surface.height,
+            &surface.to_pixel_format(pixel_format),
+            pixel_format,
+        );
 
         Self {
             width: surface.width,
             height: surface.height,
             data_url,
+            pixel_format,
             timestamp: Instant::now(),
+            stale: false,
         }
     }
 
-    fn create_data_url(width: u32, height: u32, data: &[u8], format: u32) -> String {
-        // Convert raw pixel data to a web-compatible format
-        match format {
-            32 => Self::rgba_to_data_url(width, height, data),
-            24 => Self::rgb_to_data_url(width, height, data),
-            _ => Self::create_placeholder_svg(width, height),
-        }
-    }
-
-    fn rgba_to_data_url(width: u32, height: u32, data: &[u8]) -> String {
-        // Create a simple bitmap for RGBA data
+    fn pixels_to_data_url(
+        width: u32,
+        height: u32,
+        data: &[u8],
+        pixel_format: PixelFormat,
+    ) -> String {
+        // Create a simple bitmap for the raw pixels
         // In a real implementation, you'd convert to PNG or JPEG
         if data.len() >= (width * height * 4) as usize {
             // For now, create a canvas-compatible data URL
             // This is a simplified approach - in production you'd use proper image encoding
+            let mime = match pixel_format {
+                PixelFormat::Rgba8 => "image/rgba",
+                PixelFormat::Bgra8 => "image/bgra",
+                PixelFormat::RgbaPremultiplied => "image/rgba-premultiplied",
+            };
             let encoded = general_purpose::STANDARD.encode(data);
-            format!("data:image/rgba;base64,{}", encoded)
-        } else {
-            Self::create_placeholder_svg(width, height)
-        }
-    }
-
-    fn rgb_to_data_url(width: u32, height: u32, data: &[u8]) -> String {
-        // Convert RGB to RGBA for web compatibility
-        if data.len() >= (width * height * 3) as usize {
-            let mut rgba_data = Vec::with_capacity((width * height * 4) as usize);
-
-            for i in (0..data.len()).step_by(3) {
-                if i + 2 < data.len() {
-                    rgba_data.push(data[i]); // R
-                    rgba_data.push(data[i + 1]); // G
-                    rgba_data.push(data[i + 2]); // B
-                    rgba_data.push(255); // A (opaque)
-                }
-            }
-
-            let encoded = general_purpose::STANDARD.encode(&rgba_data);
-            format!("data:image/rgba;base64,{}", encoded)
+            format!("data:{};base64,{}", mime, encoded)
         } else {
             Self::create_placeholder_svg(width, height)
         }
@@ -2,7 +2,7 @@ mod frame;
 mod output;
 
 pub use frame::VideoFrame;
-pub use output::{create_video_output, VideoOutput};
+pub use output::{create_video_output, create_video_output_with_format, VideoOutput};
 
 #[cfg(not(target_arch = "wasm32"))]
 mod native;
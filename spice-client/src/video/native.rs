@@ -1,5 +1,5 @@
 use super::{VideoFrame, VideoOutput};
-use crate::channels::display::DisplaySurface;
+use crate::channels::display::{DisplaySurface, PixelFormat};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
@@ -7,13 +7,19 @@ use tokio::sync::RwLock;
 pub struct NativeVideoOutput {
     current_frame: Arc<RwLock<Option<VideoFrame>>>,
     frame_count: Arc<RwLock<u64>>,
+    pixel_format: Arc<std::sync::RwLock<PixelFormat>>,
 }
 
 impl NativeVideoOutput {
     pub fn new() -> Self {
+        Self::with_pixel_format(PixelFormat::default())
+    }
+
+    pub fn with_pixel_format(pixel_format: PixelFormat) -> Self {
         Self {
             current_frame: Arc::new(RwLock::new(None)),
             frame_count: Arc::new(RwLock::new(0)),
+            pixel_format: Arc::new(std::sync::RwLock::new(pixel_format)),
         }
     }
 }
@@ -21,7 +27,7 @@ impl NativeVideoOutput {
 #[async_trait::async_trait]
 impl VideoOutput for NativeVideoOutput {
     async fn update_frame(&self, surface: &DisplaySurface) {
-        let frame = VideoFrame::from_surface(surface);
+        let frame = VideoFrame::from_surface_with_format(surface, self.pixel_format());
         *self.current_frame.write().await = Some(frame);
         *self.frame_count.write().await += 1;
     }
@@ -33,4 +39,23 @@ impl VideoOutput for NativeVideoOutput {
     async fn get_frame_count(&self) -> u64 {
         *self.frame_count.read().await
     }
+
+    async fn mark_stale(&self) {
+        if let Some(frame) = self.current_frame.write().await.as_mut() {
+            frame.stale = true;
+        }
+    }
+
+    fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+            .read()
+            .map(|format| *format)
+            .unwrap_or_default()
+    }
+
+    fn set_pixel_format(&self, pixel_format: PixelFormat) {
+        if let Ok(mut format) = self.pixel_format.write() {
+            *format = pixel_format;
+        }
+    }
 }
@@ -1,5 +1,5 @@
 use super::VideoFrame;
-use crate::channels::display::DisplaySurface;
+use crate::channels::display::{DisplaySurface, PixelFormat};
 use std::sync::Arc;
 
 /// Trait for video output handling
@@ -15,6 +15,20 @@ pub trait VideoOutput: Send + Sync {
 
     /// Get the total frame count
     async fn get_frame_count(&self) -> u64;
+
+    /// Flag the current frame as stale, keeping it on screen while the
+    /// client reconnects
+    async fn mark_stale(&self);
+
+    /// Byte order frames are emitted in. RGBA unless the output supports
+    /// other orders.
+    fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::Rgba8
+    }
+
+    /// Emit later frames in `pixel_format` order, e.g. BGRA for a renderer
+    /// uploading to a BGRA texture. Outputs that only emit RGBA ignore it.
+    fn set_pixel_format(&self, _pixel_format: PixelFormat) {}
 }
 
 /// Trait for video output handling - WASM version
@@ -30,17 +44,40 @@ pub trait VideoOutput {
 
     /// Get the total frame count
     async fn get_frame_count(&self) -> u64;
+
+    /// Flag the current frame as stale, keeping it on screen while the
+    /// client reconnects
+    async fn mark_stale(&self);
+
+    /// Byte order frames are emitted in. RGBA unless the output supports
+    /// other orders.
+    fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::Rgba8
+    }
+
+    /// Emit later frames in `pixel_format` order, e.g. BGRA for a renderer
+    /// uploading to a BGRA texture. Outputs that only emit RGBA ignore it.
+    fn set_pixel_format(&self, _pixel_format: PixelFormat) {}
 }
 
 /// Create a platform-specific VideoOutput implementation
 pub fn create_video_output() -> Arc<dyn VideoOutput> {
+    create_video_output_with_format(PixelFormat::default())
+}
+
+/// Create a platform-specific VideoOutput emitting frames in `pixel_format`
+pub fn create_video_output_with_format(pixel_format: PixelFormat) -> Arc<dyn VideoOutput> {
     #[cfg(not(target_arch = "wasm32"))]
     {
-        Arc::new(super::native::NativeVideoOutput::new())
+        Arc::new(super::native::NativeVideoOutput::with_pixel_format(
+            pixel_format,
+        ))
     }
 
     #[cfg(target_arch = "wasm32")]
     {
-        Arc::new(super::wasm::WasmVideoOutput::new())
+        Arc::new(super::wasm::WasmVideoOutput::with_pixel_format(
+            pixel_format,
+        ))
     }
 }
@@ -1,17 +1,23 @@
 use super::{VideoFrame, VideoOutput};
-use crate::channels::display::DisplaySurface;
+use crate::channels::display::{DisplaySurface, PixelFormat};
 use std::sync::{Arc, Mutex};
 
 pub struct WasmVideoOutput {
     current_frame: Arc<Mutex<Option<VideoFrame>>>,
     frame_count: Arc<Mutex<u64>>,
+    pixel_format: Arc<Mutex<PixelFormat>>,
 }
 
 impl WasmVideoOutput {
     pub fn new() -> Self {
+        Self::with_pixel_format(PixelFormat::default())
+    }
+
+    pub fn with_pixel_format(pixel_format: PixelFormat) -> Self {
         Self {
             current_frame: Arc::new(Mutex::new(None)),
             frame_count: Arc::new(Mutex::new(0)),
+            pixel_format: Arc::new(Mutex::new(pixel_format)),
         }
     }
 }
@@ -19,7 +25,7 @@ impl WasmVideoOutput {
 #[async_trait::async_trait(?Send)]
 impl VideoOutput for WasmVideoOutput {
     async fn update_frame(&self, surface: &DisplaySurface) {
-        let frame = VideoFrame::from_surface(surface);
+        let frame = VideoFrame::from_surface_with_format(surface, self.pixel_format());
         if let Ok(mut current) = self.current_frame.lock() {
             *current = Some(frame);
         }
@@ -35,4 +41,25 @@ impl VideoOutput for WasmVideoOutput {
     async fn get_frame_count(&self) -> u64 {
         self.frame_count.lock().ok().map(|c| *c).unwrap_or(0)
     }
+
+    async fn mark_stale(&self) {
+        if let Ok(mut current) = self.current_frame.lock() {
+            if let Some(frame) = current.as_mut() {
+                frame.stale = true;
+            }
+        }
+    }
+
+    fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+            .lock()
+            .map(|format| *format)
+            .unwrap_or_default()
+    }
+
+    fn set_pixel_format(&self, pixel_format: PixelFormat) {
+        if let Ok(mut format) = self.pixel_format.lock() {
+            *format = pixel_format;
+        }
+    }
 }
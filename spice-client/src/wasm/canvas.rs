@@ -2,6 +2,7 @@
 
 use crate::channels::display::DisplaySurface;
 use crate::error::{Result, SpiceError};
+use crate::wasm::webgl::WebGlRenderer;
 use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
@@ -10,22 +11,44 @@ use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, ImageData};
 /// Manages HTML5 Canvas elements for rendering SPICE displays
 pub struct CanvasManager {
     canvases: HashMap<u32, CanvasDisplay>,
+    force_2d: bool,
 }
 
 struct CanvasDisplay {
     canvas: HtmlCanvasElement,
-    context: CanvasRenderingContext2d,
+    backend: CanvasBackend,
     surface_id: u32,
 }
 
+/// How a canvas is drawn to
+enum CanvasBackend {
+    WebGl(WebGlRenderer),
+    Canvas2d(CanvasRenderingContext2d),
+}
+
 impl CanvasManager {
     /// Creates a new canvas manager
     pub fn new() -> Self {
         Self {
             canvases: HashMap::new(),
+            force_2d: false,
         }
     }
 
+    /// Use the 2D canvas path even when WebGL2 is available (for debugging).
+    /// Only applies to canvases created afterwards.
+    pub fn set_force_2d(&mut self, force_2d: bool) {
+        self.force_2d = force_2d;
+    }
+
+    /// Whether a surface's canvas is drawn with WebGL2
+    pub fn is_webgl(&self, surface_id: u32) -> bool {
+        matches!(
+            self.canvases.get(&surface_id).map(|d| &d.backend),
+            Some(CanvasBackend::WebGl(_))
+        )
+    }
+
     /// Creates or gets a canvas for a specific display
     pub fn get_or_create_canvas(
         &mut self,
@@ -62,6 +85,31 @@ impl CanvasManager {
         // Add CSS class for styling
         canvas.set_class_name("spice-display-canvas");
 
+        // A canvas keeps the first context type it hands out, so WebGL2 has to
+        // be tried before falling back to 2D
+        let webgl = if self.force_2d {
+            None
+        } else {
+            WebGlRenderer::new(&canvas)?
+        };
+        let backend = match webgl {
+            Some(renderer) => CanvasBackend::WebGl(renderer),
+            None => CanvasBackend::Canvas2d(Self::create_2d_context(&canvas)?),
+        };
+
+        self.canvases.insert(
+            surface_id,
+            CanvasDisplay {
+                canvas,
+                backend,
+                surface_id,
+            },
+        );
+
+        Ok(())
+    }
+
+    fn create_2d_context(canvas: &HtmlCanvasElement) -> Result<CanvasRenderingContext2d> {
         let context = canvas
             .get_context("2d")
             .map_err(|_| SpiceError::Protocol("Failed to get 2D context".to_string()))?
@@ -74,21 +122,12 @@ impl CanvasManager {
         // Configure context for better performance
         context.set_image_smoothing_enabled(false);
 
-        self.canvases.insert(
-            surface_id,
-            CanvasDisplay {
-                canvas,
-                context,
-                surface_id,
-            },
-        );
-
-        Ok(())
+        Ok(context)
     }
 
     /// Updates a canvas with surface data
-    pub fn update_canvas(&self, surface_id: u32, surface: &DisplaySurface) -> Result<()> {
-        let display = self.canvases.get(&surface_id).ok_or_else(|| {
+    pub fn update_canvas(&mut self, surface_id: u32, surface: &DisplaySurface) -> Result<()> {
+        let display = self.canvases.get_mut(&surface_id).ok_or_else(|| {
             SpiceError::Protocol(format!("Canvas not found for surface {surface_id}"))
         })?;
 
@@ -98,17 +137,24 @@ impl CanvasManager {
             display.canvas.set_height(surface.height);
         }
 
+        let rgba = surface.to_rgba();
+        let context = match &mut display.backend {
+            CanvasBackend::WebGl(renderer) => {
+                return renderer.draw_frame(surface.width, surface.height, &rgba)
+            }
+            CanvasBackend::Canvas2d(context) => context,
+        };
+
         // Create ImageData from surface data
         let image_data = ImageData::new_with_u8_clamped_array_and_sh(
-            wasm_bindgen::Clamped(&surface.data),
+            wasm_bindgen::Clamped(&rgba),
             surface.width,
             surface.height,
         )
         .map_err(|_| SpiceError::Protocol("Failed to create ImageData".to_string()))?;
 
         // Draw to canvas
-        display
-            .context
+        context
             .put_image_data(&image_data, 0.0, 0.0)
             .map_err(|_| SpiceError::Protocol("Failed to put image data".to_string()))?;
 
@@ -117,7 +163,7 @@ impl CanvasManager {
 
     /// Updates a region of the canvas
     pub fn update_canvas_region(
-        &self,
+        &mut self,
         surface_id: u32,
         surface: &DisplaySurface,
         x: i32,
@@ -125,10 +171,21 @@ impl CanvasManager {
         width: u32,
         height: u32,
     ) -> Result<()> {
-        let display = self.canvases.get(&surface_id).ok_or_else(|| {
+        let display = self.canvases.get_mut(&surface_id).ok_or_else(|| {
             SpiceError::Protocol(format!("Canvas not found for surface {surface_id}"))
         })?;
 
+        // Only whole rows and columns inside the surface can be uploaded
+        let left = x.clamp(0, surface.width as i32);
+        let top = y.clamp(0, surface.height as i32);
+        let right = (x + width as i32).clamp(left, surface.width as i32);
+        let bottom = (y + height as i32).clamp(top, surface.height as i32);
+        let (x, y) = (left, top);
+        let (width, height) = ((right - left) as u32, (bottom - top) as u32);
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+
         // Extract the region data
         let mut region_data = Vec::with_capacity((width * height * 4) as usize);
 
@@ -142,13 +199,23 @@ impl CanvasManager {
                     continue;
                 }
 
-                let pixel_offset = ((row as u32 * surface.width + col as u32) * 4) as usize;
-                if pixel_offset + 4 <= surface.data.len() {
-                    region_data.extend_from_slice(&surface.data[pixel_offset..pixel_offset + 4]);
+                if let Some(pixel) = surface.pixel(col as usize, row as usize) {
+                    region_data.extend_from_slice(&pixel);
                 }
             }
         }
 
+        let context = match &mut display.backend {
+            CanvasBackend::WebGl(renderer) => {
+                // The texture must hold a full frame before regions can be patched in
+                if !renderer.has_frame(surface.width, surface.height) {
+                    return renderer.draw_frame(surface.width, surface.height, &surface.to_rgba());
+                }
+                return renderer.draw_region(x as u32, y as u32, width, height, &region_data);
+            }
+            CanvasBackend::Canvas2d(context) => context,
+        };
+
         // Create ImageData for the region
         let image_data = ImageData::new_with_u8_clamped_array_and_sh(
             wasm_bindgen::Clamped(&region_data),
@@ -158,8 +225,7 @@ impl CanvasManager {
         .map_err(|_| SpiceError::Protocol("Failed to create region ImageData".to_string()))?;
 
         // Draw the region to canvas
-        display
-            .context
+        context
             .put_image_data(&image_data, x as f64, y as f64)
             .map_err(|_| SpiceError::Protocol("Failed to put region image data".to_string()))?;
 
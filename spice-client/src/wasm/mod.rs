@@ -8,3 +8,6 @@ pub mod canvas;
 
 #[cfg(target_arch = "wasm32")]
 pub mod cursor;
+
+#[cfg(target_arch = "wasm32")]
+pub mod webgl;
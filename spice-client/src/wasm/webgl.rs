@@ -0,0 +1,180 @@
+//! WebGL2 rendering for display canvases
+//!
+//! The surface is kept in a texture and drawn as a single quad, so a frame
+//! costs one texture upload instead of a `putImageData` pass over the whole
+//! canvas. Dirty rectangles are uploaded with `texSubImage2D`.
+
+use crate::error::{Result, SpiceError};
+use wasm_bindgen::JsCast;
+use web_sys::{HtmlCanvasElement, WebGl2RenderingContext as Gl, WebGlProgram, WebGlShader};
+
+// Draws a quad from gl_VertexID, so no vertex buffers are needed
+const VERTEX_SHADER: &str = r#"#version 300 es
+out vec2 v_uv;
+void main() {
+    vec2 corner = vec2(float(gl_VertexID & 1), float(gl_VertexID >> 1));
+    v_uv = corner;
+    gl_Position = vec4(corner.x * 2.0 - 1.0, 1.0 - corner.y * 2.0, 0.0, 1.0);
+}
+"#;
+
+const FRAGMENT_SHADER: &str = r#"#version 300 es
+precision mediump float;
+in vec2 v_uv;
+uniform sampler2D u_frame;
+out vec4 color;
+void main() {
+    color = texture(u_frame, v_uv);
+}
+"#;
+
+/// Draws RGBA frames to a canvas through a WebGL2 texture
+pub struct WebGlRenderer {
+    gl: Gl,
+    program: WebGlProgram,
+    texture_width: u32,
+    texture_height: u32,
+}
+
+impl WebGlRenderer {
+    /// Sets up WebGL2 on the canvas, or returns `None` if the browser
+    /// doesn't provide a WebGL2 context for it
+    pub fn new(canvas: &HtmlCanvasElement) -> Result<Option<Self>> {
+        let Some(context) = canvas
+            .get_context("webgl2")
+            .map_err(|_| SpiceError::Protocol("Failed to get WebGL2 context".to_string()))?
+        else {
+            return Ok(None);
+        };
+        let gl = context.dyn_into::<Gl>().map_err(|_| {
+            SpiceError::Protocol("Failed to cast to WebGl2RenderingContext".to_string())
+        })?;
+
+        let vertex = compile_shader(&gl, Gl::VERTEX_SHADER, VERTEX_SHADER)?;
+        let fragment = compile_shader(&gl, Gl::FRAGMENT_SHADER, FRAGMENT_SHADER)?;
+        let program = link_program(&gl, &vertex, &fragment)?;
+
+        let texture = gl
+            .create_texture()
+            .ok_or_else(|| SpiceError::Protocol("Failed to create texture".to_string()))?;
+        gl.bind_texture(Gl::TEXTURE_2D, Some(&texture));
+        // Pixels map 1:1 to the canvas, so no filtering or mipmaps
+        gl.tex_parameteri(Gl::TEXTURE_2D, Gl::TEXTURE_MIN_FILTER, Gl::NEAREST as i32);
+        gl.tex_parameteri(Gl::TEXTURE_2D, Gl::TEXTURE_MAG_FILTER, Gl::NEAREST as i32);
+        gl.tex_parameteri(Gl::TEXTURE_2D, Gl::TEXTURE_WRAP_S, Gl::CLAMP_TO_EDGE as i32);
+        gl.tex_parameteri(Gl::TEXTURE_2D, Gl::TEXTURE_WRAP_T, Gl::CLAMP_TO_EDGE as i32);
+
+        gl.use_program(Some(&program));
+        gl.uniform1i(gl.get_uniform_location(&program, "u_frame").as_ref(), 0);
+
+        Ok(Some(Self {
+            gl,
+            program,
+            texture_width: 0,
+            texture_height: 0,
+        }))
+    }
+
+    /// Replaces the whole texture with a `width` x `height` RGBA frame and draws it
+    pub fn draw_frame(&mut self, width: u32, height: u32, rgba: &[u8]) -> Result<()> {
+        self.gl
+            .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                Gl::TEXTURE_2D,
+                0,
+                Gl::RGBA8 as i32,
+                width as i32,
+                height as i32,
+                0,
+                Gl::RGBA,
+                Gl::UNSIGNED_BYTE,
+                Some(rgba),
+            )
+            .map_err(|_| SpiceError::Protocol("Failed to upload frame texture".to_string()))?;
+        self.texture_width = width;
+        self.texture_height = height;
+        self.gl.viewport(0, 0, width as i32, height as i32);
+
+        self.draw();
+        Ok(())
+    }
+
+    /// Uploads an RGBA region at (`x`, `y`) and redraws
+    pub fn draw_region(&self, x: u32, y: u32, width: u32, height: u32, rgba: &[u8]) -> Result<()> {
+        if x + width > self.texture_width || y + height > self.texture_height {
+            return Err(SpiceError::Protocol(format!(
+                "Region {width}x{height} at ({x},{y}) is outside the {}x{} texture",
+                self.texture_width, self.texture_height
+            )));
+        }
+
+        self.gl
+            .tex_sub_image_2d_with_i32_and_i32_and_u32_and_type_and_opt_u8_array(
+                Gl::TEXTURE_2D,
+                0,
+                x as i32,
+                y as i32,
+                width as i32,
+                height as i32,
+                Gl::RGBA,
+                Gl::UNSIGNED_BYTE,
+                Some(rgba),
+            )
+            .map_err(|_| SpiceError::Protocol("Failed to upload region texture".to_string()))?;
+
+        self.draw();
+        Ok(())
+    }
+
+    /// Whether a full frame has been uploaded at this size
+    pub fn has_frame(&self, width: u32, height: u32) -> bool {
+        self.texture_width == width && self.texture_height == height
+    }
+
+    fn draw(&self) {
+        self.gl.use_program(Some(&self.program));
+        self.gl.draw_arrays(Gl::TRIANGLE_STRIP, 0, 4);
+    }
+}
+
+fn compile_shader(gl: &Gl, shader_type: u32, source: &str) -> Result<WebGlShader> {
+    let shader = gl
+        .create_shader(shader_type)
+        .ok_or_else(|| SpiceError::Protocol("Failed to create shader".to_string()))?;
+    gl.shader_source(&shader, source);
+    gl.compile_shader(&shader);
+
+    if gl
+        .get_shader_parameter(&shader, Gl::COMPILE_STATUS)
+        .as_bool()
+        .unwrap_or(false)
+    {
+        Ok(shader)
+    } else {
+        Err(SpiceError::Protocol(format!(
+            "Failed to compile shader: {}",
+            gl.get_shader_info_log(&shader).unwrap_or_default()
+        )))
+    }
+}
+
+fn link_program(gl: &Gl, vertex: &WebGlShader, fragment: &WebGlShader) -> Result<WebGlProgram> {
+    let program = gl
+        .create_program()
+        .ok_or_else(|| SpiceError::Protocol("Failed to create program".to_string()))?;
+    gl.attach_shader(&program, vertex);
+    gl.attach_shader(&program, fragment);
+    gl.link_program(&program);
+
+    if gl
+        .get_program_parameter(&program, Gl::LINK_STATUS)
+        .as_bool()
+        .unwrap_or(false)
+    {
+        Ok(program)
+    } else {
+        Err(SpiceError::Protocol(format!(
+            "Failed to link program: {}",
+            gl.get_program_info_log(&program).unwrap_or_default()
+        )))
+    }
+}
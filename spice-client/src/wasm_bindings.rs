@@ -2,6 +2,9 @@
 //!
 //! This module provides the JavaScript API for the SPICE client when compiled to WebAssembly.
 
+use crate::channels::display::DisplaySurface;
+use crate::channels::{ConnectOptions, ConnectPhase, ConnectProgress};
+use crate::protocol::{ChannelType, SpiceRect};
 use crate::{SpiceClientShared, SpiceError};
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -17,6 +20,86 @@ pub struct SpiceClient {
     websocket_url: String,
     canvas: Option<HtmlCanvasElement>,
     password: Option<String>,
+    keepalive_ms: Option<u32>,
+    connect_options: ConnectOptions,
+    frame_callback: Option<js_sys::Function>,
+}
+
+/// A JS function handed to the display channels.
+///
+/// The channels require `Send + Sync` callbacks, which JS values are not.
+/// WebAssembly without threads runs everything on the one browser thread,
+/// so the function is never actually shared between threads.
+struct JsFrameCallback(js_sys::Function);
+
+unsafe impl Send for JsFrameCallback {}
+unsafe impl Sync for JsFrameCallback {}
+
+impl JsFrameCallback {
+    fn call(&self, surface_id: u32, surface: &DisplaySurface, rect: &SpiceRect) {
+        let update = js_sys::Object::new();
+        let fields: [(&str, JsValue); 7] = [
+            ("surfaceId", surface_id.into()),
+            ("x", rect.left.into()),
+            ("y", rect.top.into()),
+            ("width", (rect.right - rect.left).into()),
+            ("height", (rect.bottom - rect.top).into()),
+            ("surfaceWidth", surface.width.into()),
+            ("surfaceHeight", surface.height.into()),
+        ];
+        for (name, value) in fields {
+            let _ = js_sys::Reflect::set(&update, &name.into(), &value);
+        }
+
+        // 32-bit surfaces are already RGBA and are passed without copying
+        let rgba = surface.to_rgba();
+        // SAFETY: the view is only handed to JS for the synchronous call
+        // below, during which `rgba` is alive and nothing on the Rust side
+        // allocates. Embedders are told not to keep it past the call.
+        let pixels = unsafe { js_sys::Uint8Array::view(&rgba) };
+        let _ = js_sys::Reflect::set(&update, &"pixels".into(), &pixels);
+
+        if let Err(e) = self.0.call1(&JsValue::NULL, &update) {
+            console::error_2(&"Frame callback failed:".into(), &e);
+        }
+    }
+}
+
+/// A JS function told about connection phases; see [`JsFrameCallback`] for
+/// why it is `Send + Sync`
+struct JsProgressCallback(js_sys::Function);
+
+unsafe impl Send for JsProgressCallback {}
+unsafe impl Sync for JsProgressCallback {}
+
+impl JsProgressCallback {
+    fn call(&self, channel_type: ChannelType, channel_id: u8, phase: ConnectPhase) {
+        let channel = format!("{channel_type:?}");
+        let phase = format!("{phase:?}");
+        if let Err(e) = self.0.call3(
+            &JsValue::NULL,
+            &channel.into(),
+            &channel_id.into(),
+            &phase.into(),
+        ) {
+            console::error_2(&"Connect progress callback failed:".into(), &e);
+        }
+    }
+}
+
+/// Install a JS frame callback on a client, or remove it
+async fn apply_frame_callback(client: &SpiceClientShared, callback: Option<js_sys::Function>) {
+    match callback {
+        Some(callback) => {
+            let callback = JsFrameCallback(callback);
+            client
+                .set_frame_callback(move |_channel_id, surface_id, surface, rect| {
+                    callback.call(surface_id, surface, rect)
+                })
+                .await;
+        }
+        None => client.clear_frame_callback().await,
+    }
 }
 
 #[wasm_bindgen]
@@ -38,6 +121,9 @@ impl SpiceClient {
             websocket_url,
             canvas: Some(canvas),
             password: None,
+            keepalive_ms: None,
+            connect_options: ConnectOptions::default(),
+            frame_callback: None,
         }
     }
 
@@ -68,9 +154,75 @@ impl SpiceClient {
             websocket_url,
             canvas: Some(canvas),
             password: Some(password),
+            keepalive_ms: None,
+            connect_options: ConnectOptions::default(),
+            frame_callback: None,
         }
     }
 
+    /// Send a keepalive message whenever the connection has been idle for
+    /// `interval_ms` milliseconds, so proxies and load balancers don't drop it.
+    /// Pass `undefined` to disable. Takes effect on the next `connect()`.
+    #[wasm_bindgen(js_name = "setKeepalive")]
+    pub fn set_keepalive(&mut self, interval_ms: Option<u32>) {
+        self.keepalive_ms = interval_ms;
+    }
+
+    /// How long, in milliseconds, each channel's WebSocket may take to open
+    /// and to be accepted by the proxy. Takes effect on the next `connect()`.
+    #[wasm_bindgen(js_name = "setConnectTimeouts")]
+    pub fn set_connect_timeouts(&mut self, open_ms: u32, auth_ms: u32) {
+        self.connect_options.open_timeout = instant::Duration::from_millis(open_ms as u64);
+        self.connect_options.auth_timeout = instant::Duration::from_millis(auth_ms as u64);
+    }
+
+    /// Call `callback(channel, id, phase)` as each channel connects, where
+    /// `channel` is e.g. `"Main"` or `"Display"` and `phase` is `"Opening"`,
+    /// `"Authenticating"` or `"Linking"`. Pass `undefined` to remove it.
+    /// Takes effect on the next `connect()`.
+    #[wasm_bindgen(js_name = "setConnectProgressCallback")]
+    pub fn set_connect_progress_callback(&mut self, callback: Option<js_sys::Function>) {
+        self.connect_options.progress = callback.map(|callback| {
+            let callback = JsProgressCallback(callback);
+            Arc::new(move |channel_type, channel_id, phase| {
+                callback.call(channel_type, channel_id, phase)
+            }) as ConnectProgress
+        });
+    }
+
+    /// Call `callback` after every display update, to render the guest screen
+    /// in your own pipeline (e.g. an existing WebGL scene). Pass `undefined`
+    /// to remove it.
+    ///
+    /// The callback gets one object:
+    ///
+    /// ```text
+    /// { surfaceId, x, y, width, height, surfaceWidth, surfaceHeight, pixels }
+    /// ```
+    ///
+    /// `x`, `y`, `width` and `height` describe the area that changed.
+    /// `pixels` is a `Uint8Array` with the RGBA pixels of the whole surface,
+    /// `surfaceWidth * 4` bytes per row, so the changed area can be copied
+    /// out or uploaded with `texSubImage2D`.
+    ///
+    /// **`pixels` is only valid until the callback returns.** It is a view
+    /// into WebAssembly memory, not a copy: the client keeps drawing into the
+    /// same memory afterwards, and the view is detached when that memory
+    /// grows. Copy what you need (`pixels.slice()`) or upload it before
+    /// returning, never keep a reference to the array, and don't call back
+    /// into the client from inside the callback.
+    #[wasm_bindgen(js_name = "setFrameCallback")]
+    pub fn set_frame_callback(&mut self, callback: Option<js_sys::Function>) {
+        self.frame_callback = callback.clone();
+
+        let inner = self.inner.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Some(client) = inner.lock().await.as_ref() {
+                apply_frame_callback(client, callback).await;
+            }
+        });
+    }
+
     /// Connect to the SPICE server
     #[wasm_bindgen]
     pub async fn connect(&mut self) -> Result<(), JsValue> {
@@ -89,6 +241,20 @@ impl SpiceClient {
             client.set_password(password.clone()).await;
         }
 
+        if let Some(interval_ms) = self.keepalive_ms {
+            client
+                .set_keepalive(Some(instant::Duration::from_millis(interval_ms as u64)))
+                .await;
+        }
+
+        client
+            .set_connect_options(self.connect_options.clone())
+            .await;
+
+        if self.frame_callback.is_some() {
+            apply_frame_callback(&client, self.frame_callback.clone()).await;
+        }
+
         match client.connect().await {
             Ok(()) => {
                 console::log_1(&"Connected successfully".into());
@@ -136,6 +302,31 @@ impl SpiceClient {
         }
     }
 
+    /// Get why the connection ended, if it has
+    ///
+    /// Returns `null` while connected, otherwise an object with `reason`
+    /// (e.g. `"ticket-expired"`), `code` (the SPICE link error code, or
+    /// `null`), `recoverable` (whether reconnecting as-is may work) and
+    /// `message`.
+    #[wasm_bindgen]
+    pub async fn get_disconnect_info(&self) -> Result<JsValue, JsValue> {
+        let info = match self.inner.lock().await.as_ref() {
+            Some(client) => client.get_disconnect_info().await,
+            None => None,
+        };
+        let Some(info) = info else {
+            return Ok(JsValue::NULL);
+        };
+
+        let result = js_sys::Object::new();
+        let code = info.code.map_or(JsValue::NULL, JsValue::from);
+        js_sys::Reflect::set(&result, &"reason".into(), &info.reason.as_str().into())?;
+        js_sys::Reflect::set(&result, &"code".into(), &code)?;
+        js_sys::Reflect::set(&result, &"recoverable".into(), &info.recoverable.into())?;
+        js_sys::Reflect::set(&result, &"message".into(), &info.message.into())?;
+        Ok(result.into())
+    }
+
     /// Send a key event to the server
     ///
     /// This method spawns the actual work to avoid blocking and prevent recursive use errors
@@ -220,6 +411,55 @@ impl SpiceClient {
     }
 }
 
+/// Render `frames` synthetic full-screen frames into a canvas appended to
+/// `container` and report the frame rate.
+///
+/// Returns `{ backend: "webgl2" | "2d", fps }`. Pass `force_2d` to measure
+/// the `putImageData` path on a browser that supports WebGL2. Used by
+/// `examples/canvas-benchmark.html`.
+#[wasm_bindgen(js_name = "benchmarkCanvas")]
+pub fn benchmark_canvas(
+    container: web_sys::Element,
+    width: u32,
+    height: u32,
+    frames: u32,
+    force_2d: bool,
+) -> Result<JsValue, JsValue> {
+    use crate::channels::display::DisplaySurface;
+    use crate::protocol::SurfaceFormat;
+    use crate::wasm::canvas::CanvasManager;
+
+    let mut manager = CanvasManager::new();
+    manager.set_force_2d(force_2d);
+    let canvas = manager
+        .get_or_create_canvas(0, width, height)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    container.append_child(canvas)?;
+
+    let performance = web_sys::window()
+        .and_then(|window| window.performance())
+        .ok_or_else(|| JsValue::from_str("Performance API not available"))?;
+
+    let mut surface = DisplaySurface::new(width, height, SurfaceFormat::Xrgb32);
+    let start = performance.now();
+    for frame in 0..frames {
+        // Change every pixel so neither path can skip work
+        surface.data.fill(frame as u8);
+        manager
+            .update_canvas(0, &surface)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    }
+    let elapsed_ms = performance.now() - start;
+
+    let backend = if manager.is_webgl(0) { "webgl2" } else { "2d" };
+    let fps = frames as f64 * 1000.0 / elapsed_ms.max(1.0);
+
+    let result = js_sys::Object::new();
+    js_sys::Reflect::set(&result, &"backend".into(), &backend.into())?;
+    js_sys::Reflect::set(&result, &"fps".into(), &fps.into())?;
+    Ok(result.into())
+}
+
 /// Initialize the WASM module
 ///
 /// This should be called once when the module is loaded.
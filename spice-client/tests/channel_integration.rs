@@ -1,6 +1,9 @@
-use spice_client::channels::ChannelConnection;
+use spice_client::channels::{AdvertisedCapabilities, CapabilitySet, ChannelConnection};
 use spice_client::error::SpiceError;
 use spice_client::protocol::*;
+use spice_client::test_utils::{
+    encode_mock_public_key, hold_open, mock_key, MockKeyFormat, MockLink,
+};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpListener;
 
@@ -48,16 +51,77 @@ async fn test_channel_handshake_invalid_magic() {
     assert!(result.is_err());
     if let Err(e) = result {
         match e {
-            SpiceError::Protocol(msg) => {
-                assert!(msg.contains("Invalid") || msg.contains("magic"));
+            SpiceError::NotSpiceServer(msg) => {
+                assert!(msg.contains("ef be ad de"), "{msg}");
             }
-            _ => panic!("Expected Protocol error, got {:?}", e),
+            _ => panic!("Expected NotSpiceServer error, got {:?}", e),
         }
     }
 
     server_task.await.unwrap();
 }
 
+/// Link against a server that answers with `reply`, or with the link header
+/// it received if `None`, and keeps the connection open without sending
+/// anything else
+async fn link_against_non_spice_server(reply: Option<&'static [u8]>) -> SpiceError {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_task = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        match reply {
+            Some(reply) => socket.write_all(reply).await.unwrap(),
+            None => {
+                let mut header_buf = [0u8; 16];
+                socket.read_exact(&mut header_buf).await.unwrap();
+                socket.write_all(&header_buf).await.unwrap();
+            }
+        }
+        // Hold the connection open; only the client gives up
+        let mut buf = [0u8; 256];
+        while socket.read(&mut buf).await.unwrap_or(0) > 0 {}
+    });
+
+    let mut connection =
+        ChannelConnection::new(&addr.ip().to_string(), addr.port(), ChannelType::Main, 0)
+            .await
+            .unwrap();
+    let result = tokio::time::timeout(std::time::Duration::from_secs(1), connection.handshake())
+        .await
+        .expect("handshake waited for a reply that never comes");
+    drop(connection);
+    server_task.await.unwrap();
+    result.unwrap_err()
+}
+
+#[tokio::test]
+async fn test_handshake_fails_fast_against_vnc_server() {
+    let err = link_against_non_spice_server(Some(b"RFB 003.008\n")).await;
+    match err {
+        SpiceError::NotSpiceServer(msg) => assert!(msg.contains("VNC"), "{msg}"),
+        other => panic!("Expected NotSpiceServer error, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_handshake_fails_fast_on_text_reply() {
+    let err = link_against_non_spice_server(Some(b"SSH-2.0-OpenSSH_9.6\r\n")).await;
+    match err {
+        SpiceError::NotSpiceServer(msg) => assert!(msg.contains("\"SSH-\""), "{msg}"),
+        other => panic!("Expected NotSpiceServer error, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_handshake_detects_echoed_link_header() {
+    let err = link_against_non_spice_server(None).await;
+    match err {
+        SpiceError::NotSpiceServer(msg) => assert!(msg.contains("echoed"), "{msg}"),
+        other => panic!("Expected NotSpiceServer error, got {other:?}"),
+    }
+}
+
 #[tokio::test]
 async fn test_channel_handshake_version_mismatch() {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
@@ -266,3 +330,3462 @@ async fn test_read_message_invalid_header() {
 
     server_task.await.unwrap();
 }
+
+async fn run_ticket_auth(
+    format: MockKeyFormat,
+    accepted: spice_client::OaepHash,
+    fallback: bool,
+    password: &str,
+) -> (Result<(), SpiceError>, spice_client::OaepHash, Vec<bool>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_task = tokio::spawn(async move {
+        let mut attempts = Vec::new();
+        while let Ok(Ok((mut socket, _))) =
+            tokio::time::timeout(std::time::Duration::from_millis(500), listener.accept()).await
+        {
+            let link = MockLink {
+                key_format: format,
+                oaep_hash: accepted,
+                password: "secret".to_string(),
+                ..Default::default()
+            };
+            let accepted = link.serve(&mut socket).await.unwrap();
+            attempts.push(accepted);
+            if accepted {
+                break;
+            }
+        }
+        attempts
+    });
+
+    let mut channel =
+        ChannelConnection::new(&addr.ip().to_string(), addr.port(), ChannelType::Main, 0)
+            .await
+            .unwrap();
+    channel.set_password(password.to_string());
+    channel.set_oaep_fallback(fallback);
+    let result = channel.handshake().await;
+    let hash = channel.oaep_hash();
+
+    (result, hash, server_task.await.unwrap())
+}
+
+#[tokio::test]
+async fn test_ticket_auth_sha1() {
+    let (result, hash, attempts) = run_ticket_auth(
+        MockKeyFormat::Spki,
+        spice_client::OaepHash::Sha1,
+        true,
+        "secret",
+    )
+    .await;
+
+    assert!(result.is_ok(), "handshake failed: {:?}", result);
+    assert_eq!(hash, spice_client::OaepHash::Sha1);
+    assert_eq!(attempts, vec![true]);
+}
+
+#[tokio::test]
+async fn test_ticket_auth_falls_back_to_alternate_oaep_hash() {
+    let (result, hash, attempts) = run_ticket_auth(
+        MockKeyFormat::Spki,
+        spice_client::OaepHash::Sha256,
+        true,
+        "secret",
+    )
+    .await;
+
+    assert!(result.is_ok(), "handshake failed: {:?}", result);
+    assert_eq!(hash, spice_client::OaepHash::Sha256);
+    assert_eq!(attempts, vec![false, true]);
+}
+
+#[tokio::test]
+async fn test_ticket_auth_permission_denied_without_fallback() {
+    let (result, hash, attempts) = run_ticket_auth(
+        MockKeyFormat::Spki,
+        spice_client::OaepHash::Sha256,
+        false,
+        "secret",
+    )
+    .await;
+
+    assert!(matches!(
+        result,
+        Err(SpiceError::AuthenticationFailed {
+            reason: spice_client::AuthFailureReason::BadPassword
+        })
+    ));
+    assert_eq!(hash, spice_client::OaepHash::Sha1);
+    assert_eq!(attempts, vec![false]);
+}
+
+#[tokio::test]
+async fn test_ticket_auth_failed_fallback_keeps_oaep_hash() {
+    let (result, hash, attempts) = run_ticket_auth(
+        MockKeyFormat::Spki,
+        spice_client::OaepHash::Sha1,
+        true,
+        "wrong",
+    )
+    .await;
+
+    assert!(matches!(
+        result,
+        Err(SpiceError::AuthenticationFailed { .. })
+    ));
+    assert_eq!(hash, spice_client::OaepHash::Sha1);
+    assert_eq!(attempts, vec![false, false]);
+}
+
+#[tokio::test]
+async fn test_ticket_auth_no_fallback_over_supplied_stream() {
+    let (client_end, mut server_end) = tokio::io::duplex(64 * 1024);
+
+    let server_task = tokio::spawn(async move {
+        MockLink {
+            oaep_hash: spice_client::OaepHash::Sha256,
+            password: "secret".to_string(),
+            ..Default::default()
+        }
+        .serve(&mut server_end)
+        .await
+        .unwrap()
+    });
+
+    let mut channel = ChannelConnection::from_stream(client_end, ChannelType::Main, 0);
+    channel.set_password("secret".to_string());
+    let result = channel.handshake().await;
+
+    // The stream can't be reopened, so the server's answer stands
+    assert!(matches!(
+        result,
+        Err(SpiceError::AuthenticationFailed {
+            reason: spice_client::AuthFailureReason::BadPassword
+        })
+    ));
+    assert_eq!(channel.oaep_hash(), spice_client::OaepHash::Sha1);
+    assert!(!server_task.await.unwrap());
+}
+
+#[tokio::test]
+async fn test_ticket_auth_raw_modulus_exponent_key() {
+    let (result, _, attempts) = run_ticket_auth(
+        MockKeyFormat::RawModulusExponent,
+        spice_client::OaepHash::Sha1,
+        true,
+        "secret",
+    )
+    .await;
+
+    assert!(result.is_ok(), "handshake failed: {:?}", result);
+    assert_eq!(attempts, vec![true]);
+}
+
+#[tokio::test]
+async fn test_set_keepalive() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_task = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        // Keep the socket open until the client is done
+        hold_open(&mut socket).await.unwrap();
+    });
+
+    let mut channel =
+        ChannelConnection::new(&addr.ip().to_string(), addr.port(), ChannelType::Main, 0)
+            .await
+            .unwrap();
+    assert_eq!(channel.keepalive(), None);
+
+    let interval = std::time::Duration::from_secs(30);
+    channel.set_keepalive(Some(interval)).unwrap();
+    assert_eq!(channel.keepalive(), Some(interval));
+
+    channel.set_keepalive(None).unwrap();
+    assert_eq!(channel.keepalive(), None);
+
+    drop(channel);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_server_version_captured_from_link_reply() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_task = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        MockLink {
+            version: (2, 1),
+            ..Default::default()
+        }
+        .serve(&mut socket)
+        .await
+        .unwrap()
+    });
+
+    let mut channel =
+        ChannelConnection::new(&addr.ip().to_string(), addr.port(), ChannelType::Main, 0)
+            .await
+            .unwrap();
+    assert_eq!(channel.server_version(), None);
+
+    let result = channel.handshake().await;
+    assert!(result.is_ok(), "handshake failed: {:?}", result);
+    assert!(server_task.await.unwrap());
+    assert_eq!(channel.server_version(), Some((2, 1)));
+}
+
+#[tokio::test]
+async fn test_trace_hook_captures_handshake() {
+    use spice_client::Direction;
+    use std::sync::{Arc, Mutex};
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            // Swallow the message sent after the hook is cleared
+            let mut buf = [0u8; 18];
+            socket.read_exact(&mut buf).await.unwrap();
+        })
+        .await;
+
+    let trace = Arc::new(Mutex::new(Vec::new()));
+    let mut channel =
+        ChannelConnection::new(&addr.ip().to_string(), addr.port(), ChannelType::Main, 0)
+            .await
+            .unwrap();
+    let trace_clone = trace.clone();
+    let hook: spice_client::TraceHook = Arc::new(
+        move |channel_type: ChannelType, channel_id: u8, direction: Direction, data: &[u8]| {
+            trace_clone
+                .lock()
+                .unwrap()
+                .push((channel_type, channel_id, direction, data.to_vec()));
+        },
+    );
+    channel.set_trace_hook(Some(hook));
+
+    let result = channel.handshake().await;
+    assert!(result.is_ok(), "handshake failed: {:?}", result);
+
+    channel.set_trace_hook(None);
+    channel
+        .send_message(SPICE_MSGC_ACK_SYNC, &[])
+        .await
+        .unwrap();
+    server_task.await.unwrap();
+
+    let trace = trace.lock().unwrap();
+    assert!(trace
+        .iter()
+        .all(|entry| (entry.0, entry.1) == (ChannelType::Main, 0)));
+
+    let collect = |wanted: Direction| -> Vec<u8> {
+        trace
+            .iter()
+            .filter(|(_, _, direction, _)| *direction == wanted)
+            .flat_map(|(_, _, _, data)| data.iter().copied())
+            .collect()
+    };
+    let sent = collect(Direction::Sent);
+    let received = collect(Direction::Received);
+
+    // Link header, link message, then the 128-byte encrypted ticket
+    assert_eq!(&sent[..4], &SPICE_MAGIC.to_le_bytes());
+    let link_size = u32::from_le_bytes(sent[12..16].try_into().unwrap()) as usize;
+    assert_eq!(sent.len(), 16 + link_size + 128);
+
+    // Link reply header, reply data, then the link result
+    assert_eq!(&received[..4], &SPICE_MAGIC.to_le_bytes());
+    assert_eq!(received.len(), 16 + 178 + 4);
+    assert_eq!(&received[received.len() - 4..], &[0, 0, 0, 0]);
+}
+
+/// Log sink that keeps everything written to it
+#[derive(Clone, Default)]
+struct CapturedLogs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_info_logging_does_not_dump_payloads() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    const MESSAGES: usize = 100;
+    const PAYLOAD_SIZE: usize = 64 * 1024;
+
+    let server_task = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = vec![0u8; MESSAGES * (18 + PAYLOAD_SIZE)];
+        socket.read_exact(&mut buf).await.unwrap();
+    });
+
+    let logs = CapturedLogs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let mut channel =
+        ChannelConnection::new(&addr.ip().to_string(), addr.port(), ChannelType::Display, 0)
+            .await
+            .unwrap();
+    let payload = vec![0xabu8; PAYLOAD_SIZE];
+    for _ in 0..MESSAGES {
+        channel
+            .send_message(SPICE_MSGC_ACK, &payload)
+            .await
+            .unwrap();
+    }
+    server_task.await.unwrap();
+
+    // Sending several megabytes must not produce even one payload's worth of
+    // log output at info level
+    let logged = logs.0.lock().unwrap().len();
+    assert!(logged < PAYLOAD_SIZE, "{logged} bytes logged at info level");
+}
+
+/// Frame `(msg_type, body)` pairs with full data headers, numbering them from 1
+fn encode_data_messages(messages: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    use binrw::BinWrite;
+
+    let mut out = std::io::Cursor::new(Vec::new());
+    for (serial, (msg_type, body)) in messages.iter().enumerate() {
+        SpiceDataHeader {
+            serial: serial as u64 + 1,
+            msg_type: *msg_type,
+            msg_size: body.len() as u32,
+            sub_list: 0,
+        }
+        .write(&mut out)
+        .unwrap();
+        std::io::Write::write_all(&mut out, body).unwrap();
+    }
+    out.into_inner()
+}
+
+#[tokio::test]
+async fn test_display_gl_messages_emit_gl_events() {
+    use binrw::BinWrite;
+    use spice_client::channels::display::DisplayChannel;
+    use spice_client::DisplayEvent;
+    use std::sync::{Arc, Mutex};
+
+    let scanout = SpiceMsgDisplayGlScanoutUnix {
+        width: 1280,
+        height: 800,
+        stride: 5120,
+        drm_fourcc_format: 0x34325258, // XR24
+        flags: SPICE_GL_SCANOUT_FLAGS_Y0TOP,
+    };
+    let draw = SpiceMsgDisplayGlDraw {
+        x: 0,
+        y: 0,
+        w: 1280,
+        h: 800,
+    };
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            let messages = encode_data_messages(&[
+                (SPICE_MSG_DISPLAY_GL_SCANOUT_UNIX, {
+                    let mut body = std::io::Cursor::new(Vec::new());
+                    scanout.write(&mut body).unwrap();
+                    body.into_inner()
+                }),
+                (SPICE_MSG_DISPLAY_GL_DRAW, {
+                    let mut body = std::io::Cursor::new(Vec::new());
+                    draw.write(&mut body).unwrap();
+                    body.into_inner()
+                }),
+            ]);
+            socket.write_all(&messages).await.unwrap();
+
+            // Keep the socket open until the client is done
+            hold_open(&mut socket).await.unwrap();
+        })
+        .await;
+
+    let mut channel =
+        DisplayChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+            .await
+            .unwrap();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    channel.set_event_callback(move |event| recorded.lock().unwrap().push(event.clone()));
+
+    assert!(!channel.is_gl_active());
+    channel.process_next_message().await.unwrap();
+    channel.process_next_message().await.unwrap();
+
+    assert!(channel.is_gl_active());
+    assert_eq!(channel.gl_scanout(), Some(&scanout));
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![DisplayEvent::GlScanout(scanout), DisplayEvent::GlDraw(draw)]
+    );
+
+    drop(channel);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_surface_monitor_lookups_follow_monitors_config() {
+    use binrw::BinWrite;
+    use spice_client::channels::display::DisplayChannel;
+
+    let head = |id: u32, surface_id: u32, x: i32| SpiceHead {
+        id,
+        surface_id,
+        width: 1024,
+        height: 768,
+        x,
+        y: 0,
+        flags: SPICE_HEAD_FLAGS_NONE,
+    };
+    let monitors_config = |heads: Vec<SpiceHead>| {
+        let mut body = std::io::Cursor::new(Vec::new());
+        SpiceMonitorsConfig {
+            count: heads.len() as u16,
+            max_allowed: 2,
+            heads,
+        }
+        .write(&mut body)
+        .unwrap();
+        (SPICE_MSG_DISPLAY_MONITORS_CONFIG, body.into_inner())
+    };
+    let two_heads = monitors_config(vec![head(0, 0, 0), head(1, 1, 1024)]);
+    // The guest then drops the first monitor
+    let one_head = monitors_config(vec![head(1, 1, 0)]);
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            let messages = encode_data_messages(&[two_heads, one_head]);
+            socket.write_all(&messages).await.unwrap();
+
+            // Keep the socket open until the client is done
+            hold_open(&mut socket).await.unwrap();
+        })
+        .await;
+
+    let mut channel =
+        DisplayChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+            .await
+            .unwrap();
+    assert_eq!(channel.surface_for_monitor(0), None);
+
+    channel.process_next_message().await.unwrap();
+    assert_eq!(channel.surface_for_monitor(0), Some(0));
+    assert_eq!(channel.surface_for_monitor(1), Some(1));
+    assert_eq!(channel.surface_for_monitor(2), None);
+    assert_eq!(channel.monitor_for_surface(0), Some(0));
+    assert_eq!(channel.monitor_for_surface(1), Some(1));
+    assert_eq!(channel.monitor_for_surface(7), None);
+
+    channel.process_next_message().await.unwrap();
+    assert_eq!(channel.surface_for_monitor(0), Some(1));
+    assert_eq!(channel.surface_for_monitor(1), None);
+    assert_eq!(channel.monitor_for_surface(0), None);
+    assert_eq!(channel.monitor_for_surface(1), Some(0));
+
+    drop(channel);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_damage_callback_reports_clipped_area() {
+    use binrw::BinWrite;
+    use spice_client::channels::display::DisplayChannel;
+    use std::sync::{Arc, Mutex};
+
+    let surface_create = SpiceMsgSurfaceCreate {
+        surface_id: 0,
+        width: 16,
+        height: 16,
+        format: SurfaceFormat::Xrgb32 as u32,
+        flags: 0,
+    };
+    // Extends past the right and bottom edges of the surface
+    let draw_fill = SpiceDrawFill {
+        base: SpiceDrawBase {
+            surface_id: 0,
+            box_: SpiceRect {
+                left: 8,
+                top: 4,
+                right: 24,
+                bottom: 20,
+            },
+            clip: SpiceClip {
+                clip_type: 0,
+                data: 0,
+            },
+        },
+        data: SpiceDrawFillData {
+            brush: SpiceBrush {
+                brush_type: 1,
+                color: 0x00ff0000,
+            },
+            rop_descriptor: SPICE_ROPD_OP_PUT,
+            mask: SpiceQMask {
+                flags: 0,
+                pos: SpicePoint { x: 0, y: 0 },
+                bitmap: 0,
+            },
+        },
+    };
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            let messages = encode_data_messages(&[
+                (SPICE_MSG_DISPLAY_SURFACE_CREATE, {
+                    let mut body = std::io::Cursor::new(Vec::new());
+                    surface_create.write(&mut body).unwrap();
+                    body.into_inner()
+                }),
+                (SPICE_MSG_DISPLAY_DRAW_FILL, {
+                    let mut body = std::io::Cursor::new(Vec::new());
+                    draw_fill.write(&mut body).unwrap();
+                    body.into_inner()
+                }),
+            ]);
+            socket.write_all(&messages).await.unwrap();
+
+            // Keep the socket open until the client is done
+            hold_open(&mut socket).await.unwrap();
+        })
+        .await;
+
+    let mut channel =
+        DisplayChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+            .await
+            .unwrap();
+    let damage = Arc::new(Mutex::new(Vec::new()));
+    let recorded = damage.clone();
+    channel.set_damage_callback(move |surface_id, surface, rect| {
+        recorded.lock().unwrap().push((
+            surface_id,
+            surface.width,
+            (rect.left, rect.top, rect.right, rect.bottom),
+        ));
+    });
+
+    channel.process_next_message().await.unwrap();
+    channel.process_next_message().await.unwrap();
+
+    assert_eq!(
+        *damage.lock().unwrap(),
+        vec![
+            // A new surface is damaged as a whole
+            (0, 16, (0, 0, 16, 16)),
+            (0, 16, (8, 4, 16, 16)),
+        ]
+    );
+
+    drop(channel);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_update_time_advances_only_on_changes() {
+    use binrw::BinWrite;
+    use spice_client::channels::display::DisplayChannel;
+    use spice_client::DisplayEvent;
+    use std::sync::{Arc, Mutex};
+
+    let surface_create = SpiceMsgSurfaceCreate {
+        surface_id: 0,
+        width: 16,
+        height: 16,
+        format: SurfaceFormat::Xrgb32 as u32,
+        flags: SPICE_SURFACE_FLAGS_PRIMARY,
+    };
+    let draw_fill = |left: i32, top: i32| SpiceDrawFill {
+        base: SpiceDrawBase {
+            surface_id: 0,
+            box_: SpiceRect {
+                left,
+                top,
+                right: left + 4,
+                bottom: top + 4,
+            },
+            clip: SpiceClip {
+                clip_type: 0,
+                data: 0,
+            },
+        },
+        data: SpiceDrawFillData {
+            brush: SpiceBrush {
+                brush_type: 1,
+                color: 0x00ff0000,
+            },
+            rop_descriptor: SPICE_ROPD_OP_PUT,
+            mask: SpiceQMask {
+                flags: 0,
+                pos: SpicePoint { x: 0, y: 0 },
+                bitmap: 0,
+            },
+        },
+    };
+    let encode_fill = |fill: SpiceDrawFill| {
+        let mut body = std::io::Cursor::new(Vec::new());
+        fill.write(&mut body).unwrap();
+        (SPICE_MSG_DISPLAY_DRAW_FILL, body.into_inner())
+    };
+    // Entirely outside the surface, so nothing changes
+    let offscreen_fill = encode_fill(draw_fill(32, 32));
+    let onscreen_fill = encode_fill(draw_fill(4, 4));
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            let messages = encode_data_messages(&[
+                (SPICE_MSG_DISPLAY_SURFACE_CREATE, {
+                    let mut body = std::io::Cursor::new(Vec::new());
+                    surface_create.write(&mut body).unwrap();
+                    body.into_inner()
+                }),
+                offscreen_fill,
+                onscreen_fill,
+            ]);
+            socket.write_all(&messages).await.unwrap();
+
+            // Keep the socket open until the client is done
+            hold_open(&mut socket).await.unwrap();
+        })
+        .await;
+
+    let mut channel =
+        DisplayChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+            .await
+            .unwrap();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    channel.set_event_callback(move |event| recorded.lock().unwrap().push(event.clone()));
+    assert_eq!(channel.last_update_time(0), None);
+
+    channel.process_next_message().await.unwrap();
+    let created = channel
+        .last_update_time(0)
+        .expect("new surface not timestamped");
+
+    tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;
+    channel.process_next_message().await.unwrap();
+    assert_eq!(channel.last_update_time(0), Some(created));
+
+    tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;
+    channel.process_next_message().await.unwrap();
+    let drawn = channel.last_update_time(0).unwrap();
+    assert!(drawn > created);
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            DisplayEvent::SurfaceUpdated {
+                surface_id: 0,
+                at: created
+            },
+            DisplayEvent::SurfaceUpdated {
+                surface_id: 0,
+                at: drawn
+            },
+        ]
+    );
+
+    drop(channel);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_bandwidth_target_drops_updates() {
+    use binrw::BinWrite;
+    use spice_client::channels::display::DisplayChannel;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const FILLS: i32 = 20;
+
+    let surface_create = SpiceMsgSurfaceCreate {
+        surface_id: 0,
+        width: 32,
+        height: 32,
+        format: SurfaceFormat::Xrgb32 as u32,
+        flags: SPICE_SURFACE_FLAGS_PRIMARY,
+    };
+    let mut messages = vec![(SPICE_MSG_DISPLAY_SURFACE_CREATE, {
+        let mut body = std::io::Cursor::new(Vec::new());
+        surface_create.write(&mut body).unwrap();
+        body.into_inner()
+    })];
+    for i in 0..FILLS {
+        let fill = SpiceDrawFill {
+            base: SpiceDrawBase {
+                surface_id: 0,
+                box_: SpiceRect {
+                    left: i,
+                    top: i,
+                    right: i + 2,
+                    bottom: i + 2,
+                },
+                clip: SpiceClip {
+                    clip_type: 0,
+                    data: 0,
+                },
+            },
+            data: SpiceDrawFillData {
+                brush: SpiceBrush {
+                    brush_type: 1,
+                    color: 0x00ff0000,
+                },
+                rop_descriptor: SPICE_ROPD_OP_PUT,
+                mask: SpiceQMask {
+                    flags: 0,
+                    pos: SpicePoint { x: 0, y: 0 },
+                    bitmap: 0,
+                },
+            },
+        };
+        let mut body = std::io::Cursor::new(Vec::new());
+        fill.write(&mut body).unwrap();
+        messages.push((SPICE_MSG_DISPLAY_DRAW_FILL, body.into_inner()));
+    }
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            socket
+                .write_all(&encode_data_messages(&messages))
+                .await
+                .unwrap();
+
+            // Keep the socket open until the client is done
+            hold_open(&mut socket).await.unwrap();
+        })
+        .await;
+
+    let mut channel =
+        DisplayChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+            .await
+            .unwrap();
+    let updates = Arc::new(AtomicUsize::new(0));
+    let counted = updates.clone();
+    channel.set_update_callback(move |_| {
+        counted.fetch_add(1, Ordering::SeqCst);
+    });
+    // Each update then holds the next back for well over the test's runtime
+    channel.set_bandwidth_target(Some(1));
+    assert_eq!(channel.bandwidth_target(), Some(1));
+
+    for _ in 0..=FILLS {
+        channel.process_next_message().await.unwrap();
+    }
+
+    // Only the new surface went out; the fills after it were coalesced
+    assert_eq!(updates.load(Ordering::SeqCst), 1);
+    assert!(channel.has_pending_damage());
+    channel.flush_damage();
+    assert_eq!(updates.load(Ordering::SeqCst), 2);
+
+    drop(channel);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_draw_copy_writes_configured_pixel_format() {
+    use spice_client::channels::display::DisplayChannel;
+    use spice_client::PixelFormat;
+
+    // Opaque and half-transparent pixels of the same color
+    let source = [200u8, 100, 50, 255, 200, 100, 50, 128];
+    let cases = [
+        (PixelFormat::Rgba8, [200, 100, 50, 255, 200, 100, 50, 128]),
+        (PixelFormat::Bgra8, [50, 100, 200, 255, 50, 100, 200, 128]),
+        (
+            PixelFormat::RgbaPremultiplied,
+            [200, 100, 50, 255, 100, 50, 25, 128],
+        ),
+    ];
+
+    for (pixel_format, expected) in cases {
+        let surface_create = SpiceMsgSurfaceCreate {
+            surface_id: 0,
+            width: 2,
+            height: 1,
+            format: SurfaceFormat::Argb32 as u32,
+            flags: SPICE_SURFACE_FLAGS_PRIMARY,
+        };
+        let area = SpiceRect {
+            left: 0,
+            top: 0,
+            right: 2,
+            bottom: 1,
+        };
+        let mut draw_copy = SpiceDrawCopy {
+            base: SpiceDrawBase {
+                surface_id: 0,
+                box_: area,
+                clip: SpiceClip {
+                    clip_type: 0,
+                    data: 0,
+                },
+            },
+            data: SpiceDrawCopyData {
+                src_image: 0,
+                src_area: area,
+                rop_descriptor: SPICE_ROPD_OP_PUT,
+                scale_mode: 0,
+                mask: SpiceQMask {
+                    flags: 0,
+                    pos: SpicePoint { x: 0, y: 0 },
+                    bitmap: 0,
+                },
+            },
+        };
+        // The image follows the command in the message body, and the pixels
+        // follow the image
+        let descriptor = SpiceImageDescriptor {
+            id: 1,
+            type_: SPICE_IMAGE_TYPE_BITMAP,
+            flags: 0,
+            width: 2,
+            height: 1,
+        };
+        let mut bitmap = SpiceBitmap {
+            format: SPICE_BITMAP_FMT_RGBA,
+            flags: SPICE_BITMAP_FLAGS_TOP_DOWN,
+            x: 2,
+            y: 1,
+            stride: 8,
+            palette: 0,
+            data: 0,
+        };
+        let command_len = encode_body(&draw_copy).len();
+        draw_copy.data.src_image = command_len as u64;
+        bitmap.data =
+            (command_len + encode_body(&descriptor).len() + encode_body(&bitmap).len()) as u64;
+        let mut body = encode_body(&draw_copy);
+        body.extend(encode_body(&descriptor));
+        body.extend(encode_body(&bitmap));
+        body.extend_from_slice(&source);
+
+        let (addr, server_task) = MockLink::default()
+            .spawn(move |mut socket| async move {
+                let messages = encode_data_messages(&[
+                    (
+                        SPICE_MSG_DISPLAY_SURFACE_CREATE,
+                        encode_body(&surface_create),
+                    ),
+                    (SPICE_MSG_DISPLAY_DRAW_COPY, body),
+                ]);
+                socket.write_all(&messages).await.unwrap();
+
+                // Keep the socket open until the client is done
+                hold_open(&mut socket).await.unwrap();
+            })
+            .await;
+
+        let mut channel =
+            DisplayChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+                .await
+                .unwrap();
+        channel.set_pixel_format(pixel_format);
+        channel.process_next_message().await.unwrap();
+        channel.process_next_message().await.unwrap();
+
+        let surface = channel.get_primary_surface().unwrap();
+        assert_eq!(surface.pixel_format, pixel_format);
+        assert_eq!(surface.data, expected, "{pixel_format:?}");
+        // Reading pixels back gives RGBA whatever the storage order
+        assert_eq!(surface.pixel(0, 0), Some([200, 100, 50, 255]));
+
+        drop(channel);
+        server_task.await.unwrap();
+    }
+}
+
+fn encode_body<T>(message: &T) -> Vec<u8>
+where
+    T: binrw::BinWrite,
+    for<'a> T::Args<'a>: Default,
+{
+    let mut body = std::io::Cursor::new(Vec::new());
+    message.write_le(&mut body).unwrap();
+    body.into_inner()
+}
+
+fn stream_create(id: u32, dest: SpiceRect) -> SpiceStreamCreate {
+    SpiceStreamCreate {
+        id,
+        flags: 0,
+        codec_type: 1,
+        stamp: 0,
+        stream_width: (dest.right - dest.left) as u32,
+        stream_height: (dest.bottom - dest.top) as u32,
+        src_width: (dest.right - dest.left) as u32,
+        src_height: (dest.bottom - dest.top) as u32,
+        dest,
+        clip: SpiceClip {
+            clip_type: 0,
+            data: 0,
+        },
+    }
+}
+
+#[tokio::test]
+async fn test_stream_clip_limits_frames() {
+    use spice_client::channels::display::DisplayChannel;
+
+    let surface_create = SpiceMsgSurfaceCreate {
+        surface_id: 0,
+        width: 16,
+        height: 16,
+        format: SurfaceFormat::Xrgb32 as u32,
+        flags: SPICE_SURFACE_FLAGS_PRIMARY,
+    };
+    let create = stream_create(
+        1,
+        SpiceRect {
+            left: 4,
+            top: 4,
+            right: 12,
+            bottom: 12,
+        },
+    );
+    // The clip rectangles follow the 16-byte clip message
+    let mut stream_clip = encode_body(&SpiceStreamClip {
+        id: 1,
+        clip: SpiceClip {
+            clip_type: ClipType::Rects as u8,
+            data: 16,
+        },
+    });
+    stream_clip.extend(encode_body(&SpiceClipRects {
+        num_rects: 1,
+        rects: vec![SpiceRect {
+            left: 8,
+            top: 0,
+            right: 16,
+            bottom: 8,
+        }],
+    }));
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            let messages = encode_data_messages(&[
+                (
+                    SPICE_MSG_DISPLAY_SURFACE_CREATE,
+                    encode_body(&surface_create),
+                ),
+                (SPICE_MSG_DISPLAY_STREAM_CREATE, encode_body(&create)),
+                (SPICE_MSG_DISPLAY_STREAM_CLIP, stream_clip),
+            ]);
+            socket.write_all(&messages).await.unwrap();
+
+            // Keep the socket open until the client is done
+            hold_open(&mut socket).await.unwrap();
+        })
+        .await;
+
+    let mut channel =
+        DisplayChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+            .await
+            .unwrap();
+    for _ in 0..3 {
+        channel.process_next_message().await.unwrap();
+    }
+
+    let white = vec![0xFF; 8 * 8 * 4];
+    channel.apply_stream_frame(1, &white).unwrap();
+
+    let surface = channel.get_primary_surface().unwrap();
+    for y in 0..16 {
+        for x in 0..16 {
+            let drawn = (8..12).contains(&x) && (4..8).contains(&y);
+            let expected = if drawn { 0xFF } else { 0 };
+            assert_eq!(surface.pixel(x, y).unwrap()[0], expected, "pixel ({x},{y})");
+        }
+    }
+    assert!(channel.apply_stream_frame(2, &white).is_err());
+
+    drop(channel);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_stream_activate_report_sends_reports() {
+    use binrw::BinRead;
+    use spice_client::channels::display::DisplayChannel;
+
+    let create = stream_create(
+        5,
+        SpiceRect {
+            left: 0,
+            top: 0,
+            right: 8,
+            bottom: 8,
+        },
+    );
+    let activate_report = SpiceStreamActivateReport {
+        stream_id: 5,
+        unique_id: 42,
+        max_window_size: 2,
+        timeout_ms: 60_000,
+    };
+    let frame = |multi_media_time| SpiceStreamData {
+        id: 5,
+        multi_media_time,
+        data_size: 4,
+        data: vec![0; 4],
+    };
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            let messages = encode_data_messages(&[
+                (SPICE_MSG_DISPLAY_STREAM_CREATE, encode_body(&create)),
+                // Frames before the request are not reported
+                (SPICE_MSG_DISPLAY_STREAM_DATA, encode_body(&frame(60))),
+                (
+                    SPICE_MSG_DISPLAY_STREAM_ACTIVATE_REPORT,
+                    encode_body(&activate_report),
+                ),
+                (SPICE_MSG_DISPLAY_STREAM_DATA, encode_body(&frame(100))),
+                (SPICE_MSG_DISPLAY_STREAM_DATA, encode_body(&frame(140))),
+            ]);
+            socket.write_all(&messages).await.unwrap();
+
+            let (msg_type, _) = read_client_message(&mut socket).await;
+            assert_eq!(msg_type, SPICE_MSGC_DISPLAY_INIT);
+            read_client_message(&mut socket).await
+        })
+        .await;
+
+    let mut channel =
+        DisplayChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+            .await
+            .unwrap();
+    for _ in 0..5 {
+        channel.process_next_message().await.unwrap();
+    }
+
+    let (msg_type, body) = server_task.await.unwrap();
+    assert_eq!(msg_type, SPICE_MSGC_DISPLAY_STREAM_REPORT);
+    let report = SpiceMsgcDisplayStreamReport::read(&mut std::io::Cursor::new(body)).unwrap();
+    assert_eq!(report.stream_id, 5);
+    assert_eq!(report.unique_id, 42);
+    assert_eq!(report.start_frame_mm_time, 100);
+    assert_eq!(report.end_frame_mm_time, 140);
+    assert_eq!(report.num_frames, 2);
+    assert_eq!(report.num_drops, 0);
+}
+
+#[tokio::test]
+async fn test_primary_surface_follows_surface_flags() {
+    use binrw::BinWrite;
+    use spice_client::channels::display::DisplayChannel;
+    use std::sync::{Arc, Mutex};
+
+    // A streaming primary surface 3 and an off-screen surface 0
+    let surfaces = [
+        SpiceMsgSurfaceCreate {
+            surface_id: 3,
+            width: 32,
+            height: 24,
+            format: SurfaceFormat::Xrgb32 as u32,
+            flags: SPICE_SURFACE_FLAGS_PRIMARY | SPICE_SURFACE_FLAGS_STREAMING_MODE,
+        },
+        SpiceMsgSurfaceCreate {
+            surface_id: 0,
+            width: 8,
+            height: 8,
+            format: SurfaceFormat::Xrgb32 as u32,
+            flags: 0,
+        },
+    ];
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            let bodies: Vec<(u16, Vec<u8>)> = surfaces
+                .iter()
+                .map(|surface| {
+                    let mut body = std::io::Cursor::new(Vec::new());
+                    surface.write(&mut body).unwrap();
+                    (SPICE_MSG_DISPLAY_SURFACE_CREATE, body.into_inner())
+                })
+                .collect();
+            socket
+                .write_all(&encode_data_messages(&bodies))
+                .await
+                .unwrap();
+
+            // Keep the socket open until the client is done
+            hold_open(&mut socket).await.unwrap();
+        })
+        .await;
+
+    let mut channel =
+        DisplayChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+            .await
+            .unwrap();
+    let updates = Arc::new(Mutex::new(Vec::new()));
+    let recorded = updates.clone();
+    channel.set_update_callback(move |surface| {
+        recorded
+            .lock()
+            .unwrap()
+            .push((surface.width, surface.height));
+    });
+
+    channel.process_next_message().await.unwrap();
+    channel.process_next_message().await.unwrap();
+
+    assert_eq!(channel.primary_surface_id(), Some(3));
+    let primary = channel.get_primary_surface().unwrap();
+    assert_eq!((primary.width, primary.height), (32, 24));
+    assert!(channel.is_streaming_surface(3));
+    assert!(!channel.is_streaming_surface(0));
+    assert_eq!(channel.surface_flags(0), Some(0));
+    // Only the primary surface reaches the visible output
+    assert_eq!(*updates.lock().unwrap(), vec![(32, 24)]);
+
+    drop(channel);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_request_refresh_repaints_surface() {
+    use spice_client::channels::display::DisplayChannel;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let surface_create = encode_body(&SpiceMsgSurfaceCreate {
+        surface_id: 0,
+        width: 4,
+        height: 4,
+        format: SurfaceFormat::Xrgb32 as u32,
+        flags: SPICE_SURFACE_FLAGS_PRIMARY,
+    });
+    let draw_fill = encode_body(&SpiceDrawFill {
+        base: SpiceDrawBase {
+            surface_id: 0,
+            box_: SpiceRect {
+                left: 0,
+                top: 0,
+                right: 4,
+                bottom: 4,
+            },
+            clip: SpiceClip {
+                clip_type: 0,
+                data: 0,
+            },
+        },
+        data: SpiceDrawFillData {
+            brush: SpiceBrush {
+                brush_type: 1,
+                color: 0x00ff0000,
+            },
+            rop_descriptor: SPICE_ROPD_OP_PUT,
+            mask: SpiceQMask {
+                flags: 0,
+                pos: SpicePoint { x: 0, y: 0 },
+                bitmap: 0,
+            },
+        },
+    });
+
+    let server_task = tokio::spawn(async move {
+        // The first link only gets the mode; the draw is lost in the gap
+        let (mut first, _) = listener.accept().await.unwrap();
+        MockLink::default().serve(&mut first).await.unwrap();
+        let mode =
+            encode_data_messages(&[(SPICE_MSG_DISPLAY_SURFACE_CREATE, surface_create.clone())]);
+        first.write_all(&mode).await.unwrap();
+
+        // Relinking gets the mode and the whole screen again
+        let (mut second, _) = listener.accept().await.unwrap();
+        MockLink::default().serve(&mut second).await.unwrap();
+        let repaint = encode_data_messages(&[
+            (SPICE_MSG_DISPLAY_SURFACE_CREATE, surface_create),
+            (SPICE_MSG_DISPLAY_DRAW_FILL, draw_fill),
+        ]);
+        second.write_all(&repaint).await.unwrap();
+
+        // Keep the socket open until the client is done
+        hold_open(&mut second).await.unwrap();
+    });
+
+    let mut channel =
+        DisplayChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+            .await
+            .unwrap();
+    channel.process_next_message().await.unwrap();
+    let stale = channel.get_primary_surface().unwrap();
+    assert!(stale.data.iter().all(|&byte| byte == 0));
+
+    channel.request_refresh().await.unwrap();
+    // The old surface stays up until the server replaces it
+    assert!(channel.get_primary_surface().is_some());
+    channel.process_next_message().await.unwrap();
+    channel.process_next_message().await.unwrap();
+
+    let surface = channel.get_primary_surface().unwrap();
+    assert_eq!((surface.width, surface.height), (4, 4));
+    assert_eq!(surface.pixel(3, 3).unwrap()[..3], [255, 0, 0]);
+
+    drop(channel);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_cursor_events_follow_server_pointer() {
+    use spice_client::channels::cursor::CursorChannel;
+    use spice_client::CursorEvent;
+    use std::sync::{Arc, Mutex};
+
+    let init = SpiceMsgCursorInit {
+        position: SpicePoint16 { x: 10, y: 20 },
+        trail_length: 0,
+        trail_frequency: 0,
+        visible: 1,
+    };
+    let mut init_body = encode_body(&init);
+    init_body.extend_from_slice(&SPICE_CURSOR_FLAGS_NONE.to_le_bytes());
+    let moved = SpiceMsgCursorMove {
+        position: SpicePoint16 { x: 300, y: 150 },
+    };
+    let set = SpiceMsgCursorSet {
+        position: SpicePoint16 { x: 310, y: 160 },
+        visible: 1,
+    };
+    let mut set_body = encode_body(&set);
+    set_body.extend_from_slice(&SPICE_CURSOR_FLAGS_CACHE_ME.to_le_bytes());
+    set_body.extend_from_slice(&7u64.to_le_bytes()); // unique
+    set_body.push(0); // alpha cursor
+    set_body.extend_from_slice(&2u16.to_le_bytes()); // width
+    set_body.extend_from_slice(&2u16.to_le_bytes()); // height
+    set_body.extend_from_slice(&1u16.to_le_bytes()); // hot_spot_x
+    set_body.extend_from_slice(&1u16.to_le_bytes()); // hot_spot_y
+    set_body.extend_from_slice(&[0xFF; 2 * 2 * 4]);
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            socket
+                .write_all(&encode_data_messages(&[
+                    (SPICE_MSG_CURSOR_INIT, init_body),
+                    (SPICE_MSG_CURSOR_MOVE, encode_body(&moved)),
+                    (SPICE_MSG_CURSOR_HIDE, Vec::new()),
+                    (SPICE_MSG_CURSOR_SET, set_body),
+                ]))
+                .await
+                .unwrap();
+
+            // Keep the socket open until the client is done
+            hold_open(&mut socket).await.unwrap();
+        })
+        .await;
+
+    let mut channel =
+        CursorChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+            .await
+            .unwrap();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    channel.set_event_callback(move |event| recorded.lock().unwrap().push(*event));
+
+    // The cursor starts out visible, so init only places it
+    channel.process_next_message().await.unwrap();
+    assert_eq!(
+        events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        vec![CursorEvent::Moved { x: 10, y: 20 }]
+    );
+
+    channel.process_next_message().await.unwrap();
+    assert_eq!(channel.get_cursor_position(), (300, 150));
+    assert_eq!(
+        events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        vec![CursorEvent::Moved { x: 300, y: 150 }]
+    );
+
+    channel.process_next_message().await.unwrap();
+    assert!(!channel.is_cursor_visible());
+
+    channel.process_next_message().await.unwrap();
+    assert!(channel.is_cursor_visible());
+    assert_eq!(channel.get_cursor_position(), (310, 160));
+    let shape = channel.get_current_cursor().unwrap();
+    assert_eq!((shape.width, shape.height), (2, 2));
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            CursorEvent::Hidden,
+            CursorEvent::Shown,
+            CursorEvent::Moved { x: 310, y: 160 },
+        ]
+    );
+
+    drop(channel);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_agent_connection_state_and_events() {
+    use spice_client::channels::MainChannel;
+    use spice_client::MainEvent;
+    use std::sync::{Arc, Mutex};
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            let messages = encode_data_messages(&[
+                (MainChannelMessage::AgentConnected as u16, Vec::new()),
+                // A repeated notification doesn't change the state
+                (MainChannelMessage::AgentConnected as u16, Vec::new()),
+                (
+                    MainChannelMessage::AgentDisconnected as u16,
+                    0u32.to_le_bytes().to_vec(),
+                ),
+                (
+                    MainChannelMessage::AgentConnectedTokens as u16,
+                    10u32.to_le_bytes().to_vec(),
+                ),
+            ]);
+            socket.write_all(&messages).await.unwrap();
+
+            // Keep the socket open until the client is done
+            hold_open(&mut socket).await.unwrap();
+        })
+        .await;
+
+    let mut channel = MainChannel::new(&addr.ip().to_string(), addr.port())
+        .await
+        .unwrap();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    channel.set_event_callback(move |event| recorded.lock().unwrap().push(event.clone()));
+    assert!(!channel.is_agent_connected());
+
+    channel.process_next_message().await.unwrap();
+    assert!(channel.is_agent_connected());
+    channel.process_next_message().await.unwrap();
+    assert!(channel.is_agent_connected());
+    channel.process_next_message().await.unwrap();
+    assert!(!channel.is_agent_connected());
+    channel.process_next_message().await.unwrap();
+    assert!(channel.is_agent_connected());
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            MainEvent::AgentConnected,
+            MainEvent::AgentDisconnected,
+            MainEvent::AgentConnected,
+        ]
+    );
+
+    drop(channel);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_agent_monitors_config_lists_guest_monitors() {
+    use binrw::BinWrite;
+    use spice_client::channels::MainChannel;
+    use spice_client::{MainEvent, MonitorInfo};
+    use std::sync::{Arc, Mutex};
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            let config = VDAgentMonitorsConfig {
+                num_of_monitors: 2,
+                flags: VD_AGENT_CONFIG_MONITORS_FLAG_USE_POS,
+                monitors: vec![
+                    VDAgentMonConfig {
+                        height: 1080,
+                        width: 1920,
+                        depth: 32,
+                        x: 0,
+                        y: 0,
+                    },
+                    VDAgentMonConfig {
+                        height: 1024,
+                        width: 1280,
+                        depth: 32,
+                        x: 1920,
+                        y: 0,
+                    },
+                ],
+            };
+            let mut config_bytes = std::io::Cursor::new(Vec::new());
+            config.write(&mut config_bytes).unwrap();
+            let config_bytes = config_bytes.into_inner();
+
+            let agent_data = SpiceMsgMainAgentData {
+                protocol: VD_AGENT_PROTOCOL,
+                type_: VD_AGENT_MONITORS_CONFIG,
+                opaque: 0,
+                size: config_bytes.len() as u32,
+                data: config_bytes,
+            };
+            let mut body = std::io::Cursor::new(Vec::new());
+            agent_data.write(&mut body).unwrap();
+            let messages =
+                encode_data_messages(&[(MainChannelMessage::AgentData as u16, body.into_inner())]);
+            socket.write_all(&messages).await.unwrap();
+
+            // Keep the socket open until the client is done
+            hold_open(&mut socket).await.unwrap();
+        })
+        .await;
+
+    let mut channel = MainChannel::new(&addr.ip().to_string(), addr.port())
+        .await
+        .unwrap();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    channel.set_event_callback(move |event| recorded.lock().unwrap().push(event.clone()));
+    assert!(channel.guest_monitors().is_empty());
+
+    channel.process_next_message().await.unwrap();
+
+    let expected = vec![
+        MonitorInfo {
+            index: 0,
+            width: 1920,
+            height: 1080,
+            x: 0,
+            y: 0,
+        },
+        MonitorInfo {
+            index: 1,
+            width: 1280,
+            height: 1024,
+            x: 1920,
+            y: 0,
+        },
+    ];
+    assert_eq!(channel.guest_monitors(), expected);
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![MainEvent::GuestMonitors(expected)]
+    );
+
+    drop(channel);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_agent_message_split_across_agent_data_frames() {
+    use binrw::BinWrite;
+    use spice_client::channels::MainChannel;
+    use spice_client::MonitorInfo;
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            let config = VDAgentMonitorsConfig {
+                num_of_monitors: 3,
+                flags: VD_AGENT_CONFIG_MONITORS_FLAG_USE_POS,
+                monitors: (0..3)
+                    .map(|i| VDAgentMonConfig {
+                        height: 1080,
+                        width: 1920,
+                        depth: 32,
+                        x: 1920 * i,
+                        y: 0,
+                    })
+                    .collect(),
+            };
+            let mut config_bytes = std::io::Cursor::new(Vec::new());
+            config.write(&mut config_bytes).unwrap();
+            let config_bytes = config_bytes.into_inner();
+
+            let agent_data = SpiceMsgMainAgentData {
+                protocol: VD_AGENT_PROTOCOL,
+                type_: VD_AGENT_MONITORS_CONFIG,
+                opaque: 0,
+                size: config_bytes.len() as u32,
+                data: config_bytes,
+            };
+            let mut body = std::io::Cursor::new(Vec::new());
+            agent_data.write(&mut body).unwrap();
+            let body = body.into_inner();
+
+            // Only the first frame carries the agent message header
+            let frames: Vec<(u16, Vec<u8>)> = [&body[..28], &body[28..50], &body[50..]]
+                .iter()
+                .map(|chunk| (MainChannelMessage::AgentData as u16, chunk.to_vec()))
+                .collect();
+            socket
+                .write_all(&encode_data_messages(&frames))
+                .await
+                .unwrap();
+
+            // Keep the socket open until the client is done
+            hold_open(&mut socket).await.unwrap();
+        })
+        .await;
+
+    let mut channel = MainChannel::new(&addr.ip().to_string(), addr.port())
+        .await
+        .unwrap();
+
+    channel.process_next_message().await.unwrap();
+    channel.process_next_message().await.unwrap();
+    assert!(channel.guest_monitors().is_empty());
+    channel.process_next_message().await.unwrap();
+
+    let expected: Vec<MonitorInfo> = (0..3)
+        .map(|i| MonitorInfo {
+            index: i as u32,
+            width: 1920,
+            height: 1080,
+            x: 1920 * i,
+            y: 0,
+        })
+        .collect();
+    assert_eq!(channel.guest_monitors(), expected);
+
+    drop(channel);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_server_notify_events() {
+    use binrw::BinWrite;
+    use spice_client::channels::MainChannel;
+    use spice_client::MainEvent;
+    use std::sync::{Arc, Mutex};
+
+    let notifications = [
+        (
+            NotifySeverity::Info,
+            NotifyVisibility::Low,
+            "Guest agent started",
+        ),
+        (
+            NotifySeverity::Warn,
+            NotifyVisibility::Medium,
+            "Disk almost full",
+        ),
+        (NotifySeverity::Error, NotifyVisibility::High, "Disk full"),
+    ];
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            let messages: Vec<(u16, Vec<u8>)> = notifications
+                .iter()
+                .map(|(severity, visibility, message)| {
+                    let notify = SpiceMsgMainNotify {
+                        time_stamp: 0,
+                        severity: *severity as u32,
+                        visibility: *visibility as u32,
+                        what: 0,
+                        message_len: message.len() as u32,
+                        message: message.as_bytes().to_vec(),
+                    };
+                    let mut body = std::io::Cursor::new(Vec::new());
+                    notify.write(&mut body).unwrap();
+                    (SPICE_MSG_NOTIFY, body.into_inner())
+                })
+                .collect();
+            socket
+                .write_all(&encode_data_messages(&messages))
+                .await
+                .unwrap();
+
+            // Keep the socket open until the client is done
+            hold_open(&mut socket).await.unwrap();
+        })
+        .await;
+
+    let mut channel = MainChannel::new(&addr.ip().to_string(), addr.port())
+        .await
+        .unwrap();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    channel.set_event_callback(move |event| recorded.lock().unwrap().push(event.clone()));
+
+    for _ in 0..notifications.len() {
+        channel.process_next_message().await.unwrap();
+    }
+
+    let expected: Vec<MainEvent> = notifications
+        .iter()
+        .map(|(severity, visibility, message)| MainEvent::ServerNotify {
+            severity: *severity,
+            visibility: *visibility,
+            message: message.to_string(),
+        })
+        .collect();
+    assert_eq!(*events.lock().unwrap(), expected);
+
+    drop(channel);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_multi_media_time_drives_media_clock() {
+    use spice_client::channels::MainChannel;
+
+    let (next_tx, mut next_rx) = tokio::sync::mpsc::channel::<u32>(1);
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            // Send each time update only when the test asks for it
+            while let Some(time) = next_rx.recv().await {
+                let message = (SPICE_MSG_MAIN_MULTI_MEDIA_TIME, time.to_le_bytes().to_vec());
+                socket
+                    .write_all(&encode_data_messages(&[message]))
+                    .await
+                    .unwrap();
+            }
+        })
+        .await;
+
+    let mut channel = MainChannel::new(&addr.ip().to_string(), addr.port())
+        .await
+        .unwrap();
+    let clock = channel.media_clock();
+    assert!(!clock.is_set());
+    assert_eq!(clock.now(), 0);
+
+    next_tx.send(5_000).await.unwrap();
+    channel.process_next_message().await.unwrap();
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    let interpolated = clock.now();
+    assert!(
+        (5_100..5_600).contains(&interpolated),
+        "clock at {interpolated} after 100ms"
+    );
+
+    // The server clock jumps ahead; the local clock follows from there
+    next_tx.send(60_000).await.unwrap();
+    channel.process_next_message().await.unwrap();
+    let first = clock.now();
+    assert!((60_000..60_500).contains(&first), "clock at {first}");
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    assert!(clock.now() >= first + 50);
+
+    drop(next_tx);
+    server_task.await.unwrap();
+}
+
+async fn spawn_garbage_agent_tokens() -> (std::net::SocketAddr, tokio::task::JoinHandle<()>) {
+    MockLink::default()
+        .spawn(|mut socket| async move {
+            let messages = encode_data_messages(&[
+                // Correctly sized, but too short to hold the token count
+                (MainChannelMessage::AgentConnectedTokens as u16, vec![0xff]),
+                (MainChannelMessage::AgentConnected as u16, Vec::new()),
+            ]);
+            socket.write_all(&messages).await.unwrap();
+
+            // Keep the socket open until the client is done
+            hold_open(&mut socket).await.unwrap();
+        })
+        .await
+}
+
+#[tokio::test]
+async fn test_malformed_message_skipped() {
+    use spice_client::channels::MainChannel;
+
+    let (addr, server_task) = spawn_garbage_agent_tokens().await;
+
+    let mut channel = MainChannel::new(&addr.ip().to_string(), addr.port())
+        .await
+        .unwrap();
+
+    channel.process_next_message().await.unwrap();
+    assert!(!channel.is_agent_connected());
+    // The channel stays in sync and handles the next message
+    channel.process_next_message().await.unwrap();
+    assert!(channel.is_agent_connected());
+
+    drop(channel);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_malformed_message_fails_in_strict_mode() {
+    use spice_client::channels::MainChannel;
+
+    let (addr, server_task) = spawn_garbage_agent_tokens().await;
+
+    let mut channel = MainChannel::new(&addr.ip().to_string(), addr.port())
+        .await
+        .unwrap();
+    channel.set_strict(true);
+
+    let result = channel.process_next_message().await;
+    assert!(matches!(result, Err(SpiceError::Protocol(_))));
+
+    drop(channel);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_mouse_position_scaled_to_guest_display() {
+    use binrw::BinRead;
+    use spice_client::channels::{InputEvent, InputsChannel, MouseMode};
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            let mut header_buf = [0u8; 18];
+            socket.read_exact(&mut header_buf).await.unwrap();
+            let header = SpiceDataHeader::read(&mut std::io::Cursor::new(&header_buf[..])).unwrap();
+            let mut body = vec![0u8; header.msg_size as usize];
+            socket.read_exact(&mut body).await.unwrap();
+            (header.msg_type, body)
+        })
+        .await;
+
+    let mut channel =
+        InputsChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+            .await
+            .unwrap();
+    channel.set_mouse_mode(MouseMode::Client);
+    channel.set_display_size(1024, 768);
+    channel.set_widget_size(2048, 1536);
+
+    // A click at the center of the 2x widget lands at the center of the guest
+    channel
+        .send_event(InputEvent::MouseMove { x: 1024, y: 768 })
+        .await
+        .unwrap();
+
+    let (msg_type, body) = server_task.await.unwrap();
+    assert_eq!(
+        msg_type,
+        spice_client::channels::inputs::SPICE_MSG_INPUTS_MOUSE_POSITION
+    );
+    assert_eq!(u32::from_le_bytes(body[0..4].try_into().unwrap()), 512);
+    assert_eq!(u32::from_le_bytes(body[4..8].try_into().unwrap()), 384);
+    assert_eq!(body[12], 0);
+}
+
+async fn read_client_message(socket: &mut tokio::net::TcpStream) -> (u16, Vec<u8>) {
+    use binrw::BinRead;
+
+    let mut header_buf = [0u8; 18];
+    socket.read_exact(&mut header_buf).await.unwrap();
+    let header = SpiceDataHeader::read(&mut std::io::Cursor::new(&header_buf[..])).unwrap();
+    let mut body = vec![0u8; header.msg_size as usize];
+    socket.read_exact(&mut body).await.unwrap();
+    (header.msg_type, body)
+}
+
+async fn run_preferred_compression(
+    server_caps: Vec<u32>,
+    compression: ImageCompression,
+) -> (Result<(), SpiceError>, Option<(u16, Vec<u8>)>) {
+    use spice_client::channels::DisplayChannel;
+
+    let (addr, server_task) = MockLink {
+        channel_caps: server_caps,
+        ..Default::default()
+    }
+    .spawn(move |mut socket| async move {
+        let (msg_type, _) = read_client_message(&mut socket).await;
+        assert_eq!(msg_type, SPICE_MSGC_DISPLAY_INIT);
+
+        tokio::time::timeout(
+            std::time::Duration::from_millis(200),
+            read_client_message(&mut socket),
+        )
+        .await
+        .ok()
+    })
+    .await;
+
+    let mut channel =
+        DisplayChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+            .await
+            .unwrap();
+    let result = channel.set_preferred_compression(compression).await;
+
+    (result, server_task.await.unwrap())
+}
+
+#[tokio::test]
+async fn test_preferred_compression_sent_to_server() {
+    let (result, message) = run_preferred_compression(
+        vec![1 << SPICE_DISPLAY_CAP_PREF_COMPRESSION_SETTING],
+        ImageCompression::Lz4,
+    )
+    .await;
+
+    assert!(result.is_ok());
+    let (msg_type, body) = message.expect("no preferred compression message sent");
+    assert_eq!(msg_type, SPICE_MSGC_DISPLAY_PREFERRED_COMPRESSION);
+    assert_eq!(body, vec![ImageCompression::Lz4 as u8]);
+}
+
+#[tokio::test]
+async fn test_preferred_compression_requires_server_support() {
+    let (result, message) = run_preferred_compression(vec![], ImageCompression::Off).await;
+
+    assert!(matches!(result, Err(SpiceError::Channel(_))));
+    assert!(message.is_none());
+}
+
+/// Connects a client advertising `features` to a server that takes a
+/// compression preference and returns what the client sent after the
+/// display init
+async fn run_automatic_compression_hint(
+    features: spice_client::ClientFeatures,
+) -> Option<(u16, Vec<u8>)> {
+    use binrw::BinWrite;
+    use spice_client::ClientBuilder;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_task = tokio::spawn(async move {
+        let (mut main_socket, _) = listener.accept().await.unwrap();
+        MockLink::default().serve(&mut main_socket).await.unwrap();
+        let mut init = std::io::Cursor::new(Vec::new());
+        SpiceMsgMainInit {
+            session_id: 42,
+            display_channels_hint: 1,
+            supported_mouse_modes: 0,
+            current_mouse_mode: 0,
+            agent_connected: 0,
+            agent_tokens: 0,
+            multi_media_time: 0,
+            ram_hint: 0,
+        }
+        .write(&mut init)
+        .unwrap();
+        let mut channels_list = 1u32.to_le_bytes().to_vec();
+        channels_list.extend_from_slice(&[ChannelType::Display as u8, 0]);
+        let messages = encode_data_messages(&[
+            (MainChannelMessage::Init as u16, init.into_inner()),
+            (MainChannelMessage::ChannelsList as u16, channels_list),
+        ]);
+        main_socket.write_all(&messages).await.unwrap();
+
+        let (mut display_socket, _) = listener.accept().await.unwrap();
+        MockLink {
+            channel_caps: vec![1 << SPICE_DISPLAY_CAP_PREF_COMPRESSION_SETTING],
+            ..Default::default()
+        }
+        .serve(&mut display_socket)
+        .await
+        .unwrap();
+        let (msg_type, _) = read_client_message(&mut display_socket).await;
+        assert_eq!(msg_type, SPICE_MSGC_DISPLAY_INIT);
+
+        tokio::time::timeout(
+            std::time::Duration::from_millis(500),
+            read_client_message(&mut display_socket),
+        )
+        .await
+        .ok()
+    });
+
+    let mut client = ClientBuilder::new(&format!("{}:{}", addr.ip(), addr.port()))
+        .with_features(features)
+        .build()
+        .unwrap();
+    client.connect().await.unwrap();
+    assert_eq!(client.preferred_compression(), None);
+
+    server_task.await.unwrap()
+}
+
+#[tokio::test]
+async fn test_automatic_compression_hint_matches_decoders() {
+    let (msg_type, body) = run_automatic_compression_hint(spice_client::ClientFeatures::default())
+        .await
+        .expect("no preferred compression message sent");
+    assert_eq!(msg_type, SPICE_MSGC_DISPLAY_PREFERRED_COMPRESSION);
+    assert_eq!(body, vec![ImageCompression::Lz4 as u8]);
+
+    // Without LZ4 only uncompressed bitmaps are asked for
+    let (msg_type, body) = run_automatic_compression_hint(spice_client::ClientFeatures::none())
+        .await
+        .expect("no preferred compression message sent");
+    assert_eq!(msg_type, SPICE_MSGC_DISPLAY_PREFERRED_COMPRESSION);
+    assert_eq!(body, vec![ImageCompression::Off as u8]);
+}
+
+#[tokio::test]
+async fn test_channel_states_report_connected_channels() {
+    use binrw::BinWrite;
+    use spice_client::{ChannelState, ClientBuilder};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_task = tokio::spawn(async move {
+        let (mut main_socket, _) = listener.accept().await.unwrap();
+        MockLink::default().serve(&mut main_socket).await.unwrap();
+        let mut init = std::io::Cursor::new(Vec::new());
+        SpiceMsgMainInit {
+            session_id: 42,
+            display_channels_hint: 1,
+            supported_mouse_modes: 0,
+            current_mouse_mode: 0,
+            agent_connected: 0,
+            agent_tokens: 0,
+            multi_media_time: 0,
+            ram_hint: 0,
+        }
+        .write(&mut init)
+        .unwrap();
+        let mut channels_list = 1u32.to_le_bytes().to_vec();
+        channels_list.extend_from_slice(&[ChannelType::Display as u8, 0]);
+        let messages = encode_data_messages(&[
+            (MainChannelMessage::Init as u16, init.into_inner()),
+            (MainChannelMessage::ChannelsList as u16, channels_list),
+        ]);
+        main_socket.write_all(&messages).await.unwrap();
+
+        let (mut display_socket, _) = listener.accept().await.unwrap();
+        MockLink::default()
+            .serve(&mut display_socket)
+            .await
+            .unwrap();
+        let (msg_type, _) = read_client_message(&mut display_socket).await;
+        assert_eq!(msg_type, SPICE_MSGC_DISPLAY_INIT);
+        (main_socket, display_socket)
+    });
+
+    let mut client = ClientBuilder::new(&format!("{}:{}", addr.ip(), addr.port()))
+        .build()
+        .unwrap();
+    assert!(client.channel_states().is_empty());
+    client.connect().await.unwrap();
+    let _sockets = server_task.await.unwrap();
+
+    assert_eq!(
+        client.channel_states(),
+        vec![
+            (ChannelType::Main, 0, ChannelState::Connected),
+            (ChannelType::Display, 0, ChannelState::Connected),
+        ]
+    );
+
+    client.disconnect();
+    assert!(client
+        .channel_states()
+        .iter()
+        .all(|(_, _, state)| *state == ChannelState::Closed));
+}
+
+#[tokio::test]
+async fn test_mouse_motion_paced_by_acks() {
+    use spice_client::channels::inputs::{
+        SPICE_INPUT_MOTION_ACK_BUNCH, SPICE_MSG_INPUTS_MOUSE_MOTION_ACK,
+        SPICE_MSG_INPUTS_MOUSE_POSITION,
+    };
+    use spice_client::channels::{InputEvent, InputsChannel, MouseMode};
+
+    let (ack_tx, ack_rx) = tokio::sync::oneshot::channel::<()>();
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            let mut received = Vec::new();
+            while let Ok(message) = tokio::time::timeout(
+                std::time::Duration::from_millis(200),
+                read_client_message(&mut socket),
+            )
+            .await
+            {
+                received.push(message);
+            }
+            let before_ack = received.len();
+
+            ack_rx.await.unwrap();
+            let ack = encode_data_messages(&[(SPICE_MSG_INPUTS_MOUSE_MOTION_ACK, Vec::new())]);
+            socket.write_all(&ack).await.unwrap();
+            received.push(read_client_message(&mut socket).await);
+
+            (before_ack, received)
+        })
+        .await;
+
+    let mut channel =
+        InputsChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+            .await
+            .unwrap();
+    channel.set_mouse_mode(MouseMode::Client);
+
+    for i in 0..12 {
+        channel
+            .send_event(InputEvent::MouseMove { x: i, y: i })
+            .await
+            .unwrap();
+    }
+    let window = SPICE_INPUT_MOTION_ACK_BUNCH * 2;
+    assert_eq!(channel.unacked_motion_count(), window);
+
+    ack_tx.send(()).unwrap();
+    channel.process_next_message().await.unwrap();
+    assert_eq!(
+        channel.unacked_motion_count(),
+        window - SPICE_INPUT_MOTION_ACK_BUNCH + 1
+    );
+
+    let (before_ack, received) = server_task.await.unwrap();
+    assert_eq!(before_ack, window as usize);
+    assert!(received
+        .iter()
+        .all(|(msg_type, _)| *msg_type == SPICE_MSG_INPUTS_MOUSE_POSITION));
+
+    // Intermediate positions are dropped; the ack releases the latest one
+    let (_, body) = received.last().unwrap();
+    assert_eq!(u32::from_le_bytes(body[0..4].try_into().unwrap()), 11);
+    assert_eq!(u32::from_le_bytes(body[4..8].try_into().unwrap()), 11);
+}
+
+#[tokio::test]
+async fn test_queued_mouse_motion_is_coalesced() {
+    use spice_client::channels::inputs::{
+        SPICE_MSG_INPUTS_MOUSE_MOTION, SPICE_MSG_INPUTS_MOUSE_PRESS, SPICE_MSG_INPUTS_MOUSE_RELEASE,
+    };
+    use spice_client::channels::{InputCommand, InputsChannel, MouseButton};
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            let mut received = Vec::new();
+            while let Ok(message) = tokio::time::timeout(
+                std::time::Duration::from_millis(200),
+                read_client_message(&mut socket),
+            )
+            .await
+            {
+                received.push(message);
+            }
+            received
+        })
+        .await;
+
+    let mut channel =
+        InputsChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+            .await
+            .unwrap();
+    let queue = channel.input_queue();
+    for _ in 0..100 {
+        queue
+            .send(InputCommand::MouseMotion { x: 1, y: -1 })
+            .unwrap();
+    }
+    for pressed in [true, false] {
+        queue
+            .send(InputCommand::MouseButton {
+                button: MouseButton::Left,
+                pressed,
+            })
+            .unwrap();
+    }
+    queue
+        .send(InputCommand::MouseMotion { x: 2, y: 3 })
+        .unwrap();
+    channel.flush_input().await.unwrap();
+
+    let received = server_task.await.unwrap();
+    let msg_types: Vec<u16> = received.iter().map(|(msg_type, _)| *msg_type).collect();
+    assert_eq!(
+        msg_types,
+        vec![
+            SPICE_MSG_INPUTS_MOUSE_MOTION,
+            SPICE_MSG_INPUTS_MOUSE_PRESS,
+            SPICE_MSG_INPUTS_MOUSE_RELEASE,
+            SPICE_MSG_INPUTS_MOUSE_MOTION,
+        ]
+    );
+
+    // No movement is lost, and motion after the click stays after it
+    let delta = |body: &[u8]| {
+        (
+            i32::from_le_bytes(body[0..4].try_into().unwrap()),
+            i32::from_le_bytes(body[4..8].try_into().unwrap()),
+        )
+    };
+    assert_eq!(delta(&received[0].1), (100, -100));
+    assert_eq!(delta(&received[3].1), (2, 3));
+}
+
+#[tokio::test]
+async fn test_keymap_provider_chooses_scancodes() {
+    use spice_client::channels::inputs::SPICE_MSG_INPUTS_KEY_DOWN;
+    use spice_client::channels::keymap::fixed_scancode;
+    use spice_client::channels::{InputEvent, InputsChannel, KeymapProvider};
+    use spice_client::KeyCode;
+    use std::sync::Arc;
+
+    /// Types every character with the key in the AZERTY 'a' position
+    struct OneKey;
+
+    impl KeymapProvider for OneKey {
+        fn scancode(&self, key: &KeyCode) -> Option<u32> {
+            match key {
+                KeyCode::Char(_) => Some(0x10),
+                key => fixed_scancode(key),
+            }
+        }
+    }
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            let mut messages = Vec::new();
+            for _ in 0..2 {
+                messages.push(read_client_message(&mut socket).await);
+            }
+            messages
+        })
+        .await;
+
+    let mut channel =
+        InputsChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+            .await
+            .unwrap();
+    channel
+        .send_event(InputEvent::KeyDown(KeyCode::Char('s')))
+        .await
+        .unwrap();
+    channel.set_keymap_provider(Some(Arc::new(OneKey)));
+    channel
+        .send_event(InputEvent::KeyDown(KeyCode::Char('s')))
+        .await
+        .unwrap();
+
+    let messages = server_task.await.unwrap();
+    let scancodes: Vec<u32> = messages
+        .iter()
+        .map(|(msg_type, body)| {
+            assert_eq!(*msg_type, SPICE_MSG_INPUTS_KEY_DOWN);
+            u32::from_le_bytes(body[0..4].try_into().unwrap())
+        })
+        .collect();
+    // US 's', then whatever the provider says
+    assert_eq!(scancodes, vec![0x1F, 0x10]);
+}
+
+#[tokio::test]
+async fn test_queued_input_sent_while_event_loop_runs() {
+    use spice_client::channels::inputs::SPICE_MSG_INPUTS_KEY_DOWN;
+    use spice_client::channels::{InputCommand, InputsChannel};
+
+    let (done_tx, done_rx) = tokio::sync::oneshot::channel::<()>();
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            // The server stays silent; input must not wait for it
+            let message = read_client_message(&mut socket).await;
+            done_rx.await.unwrap();
+            message
+        })
+        .await;
+
+    let mut channel =
+        InputsChannel::new_with_connection_id(&addr.ip().to_string(), addr.port(), 0, Some(1))
+            .await
+            .unwrap();
+    let queue = channel.input_queue();
+    let loop_task = tokio::spawn(async move { channel.run().await });
+
+    queue.send(InputCommand::KeyDown(0x1E)).unwrap();
+    tokio::time::timeout(std::time::Duration::from_secs(2), queue.flush())
+        .await
+        .expect("flush timed out")
+        .unwrap();
+
+    done_tx.send(()).unwrap();
+    let (msg_type, body) = server_task.await.unwrap();
+    assert_eq!(msg_type, SPICE_MSG_INPUTS_KEY_DOWN);
+    assert_eq!(u32::from_le_bytes(body[0..4].try_into().unwrap()), 0x1E);
+
+    // The server hung up, which ends the loop and closes the queue
+    assert!(loop_task.await.unwrap().is_err());
+    assert!(queue.send(InputCommand::KeyUp(0x1E)).is_err());
+}
+
+#[tokio::test]
+async fn test_input_ack_handled_during_display_burst() {
+    use binrw::BinWrite;
+    use spice_client::channels::display::DisplayChannel;
+    use spice_client::channels::inputs::{
+        SPICE_INPUT_MOTION_ACK_BUNCH, SPICE_MSG_INPUTS_MOUSE_MOTION_ACK,
+        SPICE_MSG_INPUTS_MOUSE_POSITION,
+    };
+    use spice_client::channels::{InputEvent, InputsChannel, MouseMode, QosGate};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const BURST: usize = 5000;
+
+    let (display_addr, display_server) = MockLink::default()
+        .spawn(|mut socket| async move {
+            let mut draw = std::io::Cursor::new(Vec::new());
+            SpiceMsgDisplayGlDraw {
+                x: 0,
+                y: 0,
+                w: 64,
+                h: 64,
+            }
+            .write(&mut draw)
+            .unwrap();
+            let burst: Vec<(u16, Vec<u8>)> = (0..BURST)
+                .map(|_| (SPICE_MSG_DISPLAY_GL_DRAW, draw.get_ref().clone()))
+                .collect();
+            socket
+                .write_all(&encode_data_messages(&burst))
+                .await
+                .unwrap();
+            hold_open(&mut socket).await.unwrap();
+        })
+        .await;
+
+    let handled = Arc::new(AtomicUsize::new(0));
+    let (ack_tx, ack_rx) = tokio::sync::oneshot::channel::<()>();
+    let inputs_handled = handled.clone();
+    let (inputs_addr, inputs_server) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            for _ in 0..SPICE_INPUT_MOTION_ACK_BUNCH * 2 {
+                read_client_message(&mut socket).await;
+            }
+
+            ack_rx.await.unwrap();
+            let sent_at = inputs_handled.load(Ordering::SeqCst);
+            let ack = encode_data_messages(&[(SPICE_MSG_INPUTS_MOUSE_MOTION_ACK, Vec::new())]);
+            socket.write_all(&ack).await.unwrap();
+            // The ack releases the held-back motion
+            let (msg_type, _) = read_client_message(&mut socket).await;
+            (msg_type, sent_at, inputs_handled.load(Ordering::SeqCst))
+        })
+        .await;
+
+    let gate = QosGate::new();
+    let mut inputs = InputsChannel::new_with_connection_id(
+        &inputs_addr.ip().to_string(),
+        inputs_addr.port(),
+        0,
+        Some(1),
+    )
+    .await
+    .unwrap();
+    inputs.set_qos_gate(Some(gate.clone()));
+    inputs.set_mouse_mode(MouseMode::Client);
+    for i in 0..12 {
+        inputs
+            .send_event(InputEvent::MouseMove { x: i, y: i })
+            .await
+            .unwrap();
+    }
+
+    let mut display = DisplayChannel::new_with_connection_id(
+        &display_addr.ip().to_string(),
+        display_addr.port(),
+        0,
+        Some(1),
+    )
+    .await
+    .unwrap();
+    display.set_qos_gate(Some(gate));
+    let counter = handled.clone();
+    display.set_event_callback(move |_| {
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+
+    let display_task = tokio::spawn(async move { display.run().await });
+    let inputs_task = tokio::spawn(async move { inputs.run().await });
+
+    // Send the ack once the burst is being worked through
+    while handled.load(Ordering::SeqCst) == 0 {
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    }
+    ack_tx.send(()).unwrap();
+
+    let (msg_type, sent_at, released_at) = inputs_server.await.unwrap();
+    assert_eq!(msg_type, SPICE_MSG_INPUTS_MOUSE_POSITION);
+    // Only a handful of draws go ahead of the ack, however many are queued
+    assert!(
+        released_at - sent_at < 100,
+        "{} draws handled before the ack",
+        released_at - sent_at
+    );
+    assert!(released_at < BURST);
+
+    display_task.abort();
+    inputs_task.abort();
+    display_server.await.unwrap();
+}
+
+/// Refuse a link with `error`, as a server does for a channel it lacks.
+async fn serve_link_error(socket: &mut tokio::net::TcpStream, error: LinkError) {
+    use binrw::BinWrite;
+
+    let mut header_buf = [0u8; 16];
+    socket.read_exact(&mut header_buf).await.unwrap();
+    let size = u32::from_le_bytes([
+        header_buf[12],
+        header_buf[13],
+        header_buf[14],
+        header_buf[15],
+    ]);
+    let mut link_msg_buf = vec![0u8; size as usize];
+    socket.read_exact(&mut link_msg_buf).await.unwrap();
+
+    let reply = SpiceLinkReply {
+        magic: SPICE_MAGIC,
+        major_version: SPICE_VERSION_MAJOR,
+        minor_version: SPICE_VERSION_MINOR,
+        size: 178,
+    };
+    let reply_data = SpiceLinkReplyData {
+        error: error as u32,
+        pub_key: [0u8; 162],
+        num_common_caps: 0,
+        num_channel_caps: 0,
+        caps_offset: 178,
+    };
+    let mut reply_cursor = std::io::Cursor::new(Vec::new());
+    reply.write(&mut reply_cursor).unwrap();
+    reply_data.write(&mut reply_cursor).unwrap();
+    socket.write_all(&reply_cursor.into_inner()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_client_continues_without_unavailable_channel() {
+    use binrw::BinWrite;
+    use spice_client::{MainEvent, SpiceClientShared};
+    use std::sync::{Arc, Mutex};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_task = tokio::spawn(async move {
+        // Main channel: init and a list with a display and a cursor channel
+        let (mut main_socket, _) = listener.accept().await.unwrap();
+        MockLink::default().serve(&mut main_socket).await.unwrap();
+        let mut init = std::io::Cursor::new(Vec::new());
+        SpiceMsgMainInit {
+            session_id: 42,
+            display_channels_hint: 1,
+            supported_mouse_modes: 0,
+            current_mouse_mode: 0,
+            agent_connected: 0,
+            agent_tokens: 0,
+            multi_media_time: 0,
+            ram_hint: 0,
+        }
+        .write(&mut init)
+        .unwrap();
+        let mut channels_list = 2u32.to_le_bytes().to_vec();
+        channels_list.extend_from_slice(&[ChannelType::Display as u8, 0]);
+        channels_list.extend_from_slice(&[ChannelType::Cursor as u8, 0]);
+        let messages = encode_data_messages(&[
+            (MainChannelMessage::Init as u16, init.into_inner()),
+            (MainChannelMessage::ChannelsList as u16, channels_list),
+        ]);
+        main_socket.write_all(&messages).await.unwrap();
+
+        // Display channel links normally
+        let (mut display_socket, _) = listener.accept().await.unwrap();
+        MockLink::default()
+            .serve(&mut display_socket)
+            .await
+            .unwrap();
+
+        // Cursor channel is refused
+        let (mut cursor_socket, _) = listener.accept().await.unwrap();
+        serve_link_error(&mut cursor_socket, LinkError::ChannelNotAvailable).await;
+
+        // Keep the sockets open until the client is done
+        hold_open(&mut main_socket).await.unwrap();
+        hold_open(&mut display_socket).await.unwrap();
+    });
+
+    let client = SpiceClientShared::new(addr.ip().to_string(), addr.port());
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    client
+        .set_event_callback(move |event| recorded.lock().unwrap().push(event.clone()))
+        .await;
+
+    client.connect().await.unwrap();
+
+    assert_eq!(
+        client.connected_channels().await,
+        vec![(ChannelType::Display, 0)]
+    );
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![MainEvent::ChannelUnavailable {
+            channel_type: ChannelType::Cursor,
+            channel_id: 0,
+        }]
+    );
+
+    client.disconnect().await;
+    drop(client);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_lost_connection_reports_disconnected() {
+    use binrw::BinWrite;
+    use spice_client::{MainEvent, SpiceClientShared};
+    use std::sync::{Arc, Mutex};
+
+    let (close_tx, close_rx) = tokio::sync::oneshot::channel::<()>();
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            let mut init = std::io::Cursor::new(Vec::new());
+            SpiceMsgMainInit {
+                session_id: 42,
+                display_channels_hint: 0,
+                supported_mouse_modes: 0,
+                current_mouse_mode: 0,
+                agent_connected: 0,
+                agent_tokens: 0,
+                multi_media_time: 0,
+                ram_hint: 0,
+            }
+            .write(&mut init)
+            .unwrap();
+            let messages = encode_data_messages(&[
+                (MainChannelMessage::Init as u16, init.into_inner()),
+                (
+                    MainChannelMessage::ChannelsList as u16,
+                    0u32.to_le_bytes().to_vec(),
+                ),
+            ]);
+            socket.write_all(&messages).await.unwrap();
+
+            // Drop the connection once the client's event loop is running
+            close_rx.await.unwrap();
+        })
+        .await;
+
+    let client = SpiceClientShared::new(addr.ip().to_string(), addr.port());
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    client
+        .set_event_callback(move |event| recorded.lock().unwrap().push(event.clone()))
+        .await;
+
+    client.connect().await.unwrap();
+    client.start_event_loop().await.unwrap();
+    assert!(events.lock().unwrap().is_empty());
+    close_tx.send(()).unwrap();
+    server_task.await.unwrap();
+
+    tokio::time::timeout(std::time::Duration::from_secs(2), async {
+        while events.lock().unwrap().is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("no event after the connection was lost");
+    assert!(matches!(
+        events.lock().unwrap().as_slice(),
+        [MainEvent::Disconnected { .. }]
+    ));
+
+    // Disconnecting on purpose is not reported
+    client.disconnect().await;
+    assert_eq!(events.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_lost_connection_sets_disconnect_info() {
+    use spice_client::{AuthFailureReason, DisconnectInfo, DisconnectReason, SpiceClientShared};
+
+    let (close_tx, close_rx) = tokio::sync::oneshot::channel::<()>();
+
+    let (addr, server_task) = MockLink::default()
+        .spawn(move |mut socket| async move {
+            serve_main_init(&mut socket, 42).await;
+            close_rx.await.unwrap();
+        })
+        .await;
+
+    let client = SpiceClientShared::new(addr.ip().to_string(), addr.port());
+    client.connect().await.unwrap();
+    client.start_event_loop().await.unwrap();
+    assert!(client.get_disconnect_info().await.is_none());
+    close_tx.send(()).unwrap();
+    server_task.await.unwrap();
+
+    let info = tokio::time::timeout(std::time::Duration::from_secs(2), async {
+        loop {
+            if let Some(info) = client.get_disconnect_info().await {
+                return info;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("no disconnect info after the connection was lost");
+    assert!(matches!(
+        info.reason,
+        DisconnectReason::ConnectionClosed | DisconnectReason::Network
+    ));
+    assert_eq!(info.code, None);
+    assert!(info.recoverable);
+    assert!(info.message.starts_with("Main channel error: "));
+    assert_eq!(client.get_error_state().await, Some(info.message));
+    client.disconnect().await;
+
+    // A refused ticket carries the link error code and needs a new password
+    let expired = DisconnectInfo::from_error(
+        "Main channel",
+        &SpiceError::AuthenticationFailed {
+            reason: AuthFailureReason::TicketExpired,
+        },
+    );
+    assert_eq!(expired.reason, DisconnectReason::TicketExpired);
+    assert_eq!(expired.code, Some(LinkError::PermissionDenied as u32));
+    assert!(!expired.recoverable);
+}
+
+#[tokio::test]
+async fn test_connect_with_channels_skips_unrequested() {
+    use binrw::BinWrite;
+    use spice_client::SpiceClientShared;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_task = tokio::spawn(async move {
+        // Main channel: init and a list with display, inputs and cursor channels
+        let (mut main_socket, _) = listener.accept().await.unwrap();
+        MockLink::default().serve(&mut main_socket).await.unwrap();
+        let mut init = std::io::Cursor::new(Vec::new());
+        SpiceMsgMainInit {
+            session_id: 42,
+            display_channels_hint: 1,
+            supported_mouse_modes: 0,
+            current_mouse_mode: 0,
+            agent_connected: 0,
+            agent_tokens: 0,
+            multi_media_time: 0,
+            ram_hint: 0,
+        }
+        .write(&mut init)
+        .unwrap();
+        let mut channels_list = 3u32.to_le_bytes().to_vec();
+        channels_list.extend_from_slice(&[ChannelType::Display as u8, 0]);
+        channels_list.extend_from_slice(&[ChannelType::Inputs as u8, 0]);
+        channels_list.extend_from_slice(&[ChannelType::Cursor as u8, 0]);
+        let messages = encode_data_messages(&[
+            (MainChannelMessage::Init as u16, init.into_inner()),
+            (MainChannelMessage::ChannelsList as u16, channels_list),
+        ]);
+        main_socket.write_all(&messages).await.unwrap();
+
+        let (mut display_socket, _) = listener.accept().await.unwrap();
+        MockLink::default()
+            .serve(&mut display_socket)
+            .await
+            .unwrap();
+
+        // Inputs and cursor were not requested, so nothing else connects
+        let extra =
+            tokio::time::timeout(tokio::time::Duration::from_millis(500), listener.accept()).await;
+        assert!(extra.is_err(), "client opened an unrequested channel");
+    });
+
+    let client = SpiceClientShared::new(addr.ip().to_string(), addr.port());
+    client
+        .connect_with_channels(&[ChannelType::Display])
+        .await
+        .unwrap();
+
+    // Only channels that are connected get an event loop task
+    assert_eq!(
+        client.connected_channels().await,
+        vec![(ChannelType::Display, 0)]
+    );
+
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_multiple_display_channels() {
+    use binrw::BinWrite;
+    use spice_client::SpiceClientShared;
+    use std::sync::{Arc, Mutex};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_task = tokio::spawn(async move {
+        let (mut main_socket, _) = listener.accept().await.unwrap();
+        MockLink::default().serve(&mut main_socket).await.unwrap();
+        let mut init = std::io::Cursor::new(Vec::new());
+        SpiceMsgMainInit {
+            session_id: 42,
+            display_channels_hint: 2,
+            supported_mouse_modes: 0,
+            current_mouse_mode: 0,
+            agent_connected: 0,
+            agent_tokens: 0,
+            multi_media_time: 0,
+            ram_hint: 0,
+        }
+        .write(&mut init)
+        .unwrap();
+        let mut channels_list = 2u32.to_le_bytes().to_vec();
+        channels_list.extend_from_slice(&[ChannelType::Display as u8, 0]);
+        channels_list.extend_from_slice(&[ChannelType::Display as u8, 1]);
+        let messages = encode_data_messages(&[
+            (MainChannelMessage::Init as u16, init.into_inner()),
+            (MainChannelMessage::ChannelsList as u16, channels_list),
+        ]);
+        main_socket.write_all(&messages).await.unwrap();
+
+        // Each head gets its own primary surface 0, of a different size
+        let mut display_sockets = Vec::new();
+        for width in [64, 32] {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            MockLink::default().serve(&mut socket).await.unwrap();
+            let surface = SpiceMsgSurfaceCreate {
+                surface_id: 0,
+                width,
+                height: 16,
+                format: SurfaceFormat::Xrgb32 as u32,
+                flags: SPICE_SURFACE_FLAGS_PRIMARY,
+            };
+            socket
+                .write_all(&encode_data_messages(&[(
+                    SPICE_MSG_DISPLAY_SURFACE_CREATE,
+                    encode_body(&surface),
+                )]))
+                .await
+                .unwrap();
+            display_sockets.push(socket);
+        }
+
+        // Keep the sockets open until the client is done
+        hold_open(&mut main_socket).await.unwrap();
+        for mut socket in display_sockets {
+            hold_open(&mut socket).await.unwrap();
+        }
+    });
+
+    let client = SpiceClientShared::new(addr.ip().to_string(), addr.port());
+    let frames = Arc::new(Mutex::new(Vec::new()));
+    let recorded = frames.clone();
+    client
+        .set_frame_callback(move |channel_id, surface_id, surface, _| {
+            recorded
+                .lock()
+                .unwrap()
+                .push((channel_id, surface_id, surface.width));
+        })
+        .await;
+
+    client
+        .connect_with_channels(&[ChannelType::Display])
+        .await
+        .unwrap();
+    assert_eq!(
+        client.connected_channels().await,
+        vec![(ChannelType::Display, 0), (ChannelType::Display, 1)]
+    );
+    assert!(client.get_surface(2, 0).await.is_none());
+
+    // Each channel has its own video output; channel 0's is the default one
+    let output_0 = client.get_channel_video_output(0).await;
+    let output_1 = client.get_channel_video_output(1).await;
+    assert!(!Arc::ptr_eq(&output_0, &output_1));
+    assert!(Arc::ptr_eq(&output_0, &client.get_video_output().await));
+
+    client.start_event_loop().await.unwrap();
+    tokio::time::timeout(std::time::Duration::from_secs(2), async {
+        while frames.lock().unwrap().len() < 2 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("not every display channel reported its surface");
+
+    // Both channels have a surface 0, each in its own id space
+    let mut frames = frames.lock().unwrap().clone();
+    frames.sort();
+    assert_eq!(frames, vec![(0, 0, 64), (1, 0, 32)]);
+
+    // Neither surface 0 shadows the other
+    let width =
+        |surface: Option<spice_client::DisplaySurface>| surface.map(|surface| surface.width);
+    assert_eq!(width(client.get_surface(0, 0).await), Some(64));
+    assert_eq!(width(client.get_surface(1, 0).await), Some(32));
+    assert_eq!(width(client.get_display_surface(1).await), Some(32));
+    // The main monitor is the first display channel
+    assert_eq!(width(client.primary_surface().await), Some(64));
+
+    client.disconnect().await;
+    drop(client);
+    server_task.await.unwrap();
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+fn test_blocking_client_reads_display_surface() {
+    use binrw::BinWrite;
+    use spice_client::blocking::SpiceClient;
+
+    // The mock server runs on a runtime of its own; the client makes another
+    let server = tokio::runtime::Runtime::new().unwrap();
+    let listener = server.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_task = server.spawn(async move {
+        let (mut main_socket, _) = listener.accept().await.unwrap();
+        MockLink::default().serve(&mut main_socket).await.unwrap();
+        let mut init = std::io::Cursor::new(Vec::new());
+        SpiceMsgMainInit {
+            session_id: 42,
+            display_channels_hint: 1,
+            supported_mouse_modes: 0,
+            current_mouse_mode: 0,
+            agent_connected: 0,
+            agent_tokens: 0,
+            multi_media_time: 0,
+            ram_hint: 0,
+        }
+        .write(&mut init)
+        .unwrap();
+        let mut channels_list = 1u32.to_le_bytes().to_vec();
+        channels_list.extend_from_slice(&[ChannelType::Display as u8, 0]);
+        let messages = encode_data_messages(&[
+            (MainChannelMessage::Init as u16, init.into_inner()),
+            (MainChannelMessage::ChannelsList as u16, channels_list),
+        ]);
+        main_socket.write_all(&messages).await.unwrap();
+
+        let (mut display_socket, _) = listener.accept().await.unwrap();
+        MockLink::default()
+            .serve(&mut display_socket)
+            .await
+            .unwrap();
+        let surface = SpiceMsgSurfaceCreate {
+            surface_id: 0,
+            width: 64,
+            height: 16,
+            format: SurfaceFormat::Xrgb32 as u32,
+            flags: SPICE_SURFACE_FLAGS_PRIMARY,
+        };
+        display_socket
+            .write_all(&encode_data_messages(&[(
+                SPICE_MSG_DISPLAY_SURFACE_CREATE,
+                encode_body(&surface),
+            )]))
+            .await
+            .unwrap();
+
+        // Keep the sockets open until the client is done
+        hold_open(&mut main_socket).await.unwrap();
+        hold_open(&mut display_socket).await.unwrap();
+    });
+
+    let client = SpiceClient::new(addr.ip().to_string(), addr.port()).unwrap();
+    client
+        .connect_with_channels(&[ChannelType::Display])
+        .unwrap();
+    assert_eq!(client.connected_channels(), vec![(ChannelType::Display, 0)]);
+
+    client.start_event_loop().unwrap();
+    let surface = client
+        .wait_for_display_surface(0, std::time::Duration::from_secs(2))
+        .expect("display channel never reported its surface");
+    assert_eq!((surface.width, surface.height), (64, 16));
+
+    client.disconnect();
+    drop(client);
+    server.block_on(server_task).unwrap();
+
+    // Blocking inside a runtime would stall it, so the client refuses
+    let err = server
+        .block_on(async { SpiceClient::new("localhost".to_string(), 5900) })
+        .err()
+        .unwrap();
+    assert!(matches!(err, SpiceError::Connection(_)));
+}
+
+/// A socket path in the temp dir that no other test uses
+#[cfg(unix)]
+fn unix_socket_path(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("spice-{}-{}.sock", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[tokio::test]
+async fn test_handshake_over_supplied_stream() {
+    let (client_end, mut server_end) = tokio::io::duplex(64 * 1024);
+
+    let server_task = tokio::spawn(async move {
+        let authenticated = MockLink::default().serve(&mut server_end).await.unwrap();
+        server_end
+            .write_all(&encode_data_messages(&[(SPICE_MSG_PING, vec![0; 12])]))
+            .await
+            .unwrap();
+        // Keep the stream open until the client is done
+        hold_open(&mut server_end).await.unwrap();
+        authenticated
+    });
+
+    let mut channel = ChannelConnection::from_stream(client_end, ChannelType::Main, 0);
+    channel.handshake().await.unwrap();
+    channel.set_keepalive(None).unwrap();
+
+    // Waiting for data must not consume it
+    channel.wait_readable().await.unwrap();
+    let (header, data) = channel.read_message().await.unwrap();
+    assert_eq!(header.msg_type, SPICE_MSG_PING);
+    assert_eq!(data.len(), 12);
+
+    drop(channel);
+    assert!(server_task.await.unwrap());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_unix_socket_handshake() {
+    let path = unix_socket_path("handshake");
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+
+    let server_task = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        MockLink::default().serve(&mut socket).await.unwrap()
+    });
+
+    let mut channel = ChannelConnection::new_unix(&path, ChannelType::Main, 0)
+        .await
+        .unwrap();
+    channel.handshake().await.unwrap();
+
+    assert!(server_task.await.unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_client_builder_connects_over_unix_socket() {
+    use binrw::BinWrite;
+    use spice_client::ClientBuilder;
+
+    let path = unix_socket_path("builder");
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+
+    let server_task = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        MockLink::default().serve(&mut socket).await.unwrap();
+
+        // Main channel init with no secondary channels
+        let mut init = std::io::Cursor::new(Vec::new());
+        SpiceMsgMainInit {
+            session_id: 42,
+            display_channels_hint: 0,
+            supported_mouse_modes: 0,
+            current_mouse_mode: 0,
+            agent_connected: 0,
+            agent_tokens: 0,
+            multi_media_time: 0,
+            ram_hint: 0,
+        }
+        .write(&mut init)
+        .unwrap();
+        let messages = encode_data_messages(&[
+            (MainChannelMessage::Init as u16, init.into_inner()),
+            (
+                MainChannelMessage::ChannelsList as u16,
+                0u32.to_le_bytes().to_vec(),
+            ),
+        ]);
+        socket.write_all(&messages).await.unwrap();
+
+        // Keep the socket open until the client is done
+        hold_open(&mut socket).await.unwrap();
+    });
+
+    let mut client = ClientBuilder::from_unix_socket(&path).build().unwrap();
+    client.connect().await.unwrap();
+    assert!(client.server_info().is_some());
+
+    drop(client);
+    server_task.await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_server_capabilities_from_link_reply() {
+    use binrw::BinWrite;
+    use spice_client::SpiceClient;
+
+    let (addr, server_task) = MockLink {
+        channel_caps: vec![1 << SPICE_MAIN_CAP_AGENT_CONNECTED_TOKENS],
+        ..Default::default()
+    }
+    .spawn(move |mut socket| async move {
+        let mut init = std::io::Cursor::new(Vec::new());
+        SpiceMsgMainInit {
+            session_id: 42,
+            display_channels_hint: 0,
+            supported_mouse_modes: 0,
+            current_mouse_mode: 0,
+            agent_connected: 0,
+            agent_tokens: 0,
+            multi_media_time: 0,
+            ram_hint: 0,
+        }
+        .write(&mut init)
+        .unwrap();
+        let messages = encode_data_messages(&[
+            (MainChannelMessage::Init as u16, init.into_inner()),
+            (
+                MainChannelMessage::ChannelsList as u16,
+                0u32.to_le_bytes().to_vec(),
+            ),
+        ]);
+        socket.write_all(&messages).await.unwrap();
+
+        // Keep the socket open until the client is done
+        hold_open(&mut socket).await.unwrap();
+    })
+    .await;
+
+    let mut client = SpiceClient::new(addr.ip().to_string(), addr.port());
+    assert!(client
+        .server_capabilities()
+        .channel(ChannelType::Main)
+        .is_none());
+    client.connect().await.unwrap();
+
+    let caps = client.server_capabilities();
+    assert!(caps.has_channel_cap(ChannelType::Main, SPICE_MAIN_CAP_AGENT_CONNECTED_TOKENS));
+    assert!(!caps.has_channel_cap(ChannelType::Main, SPICE_MAIN_CAP_NAME_AND_UUID));
+    assert!(caps.common().is_empty());
+    // No display channel was linked
+    assert!(caps.channel(ChannelType::Display).is_none());
+
+    drop(client);
+    server_task.await.unwrap();
+}
+
+/// Send a main channel init with `session_id` and an empty channels list
+async fn serve_main_init(socket: &mut tokio::net::TcpStream, session_id: u32) {
+    use binrw::BinWrite;
+
+    let mut init = std::io::Cursor::new(Vec::new());
+    SpiceMsgMainInit {
+        session_id,
+        display_channels_hint: 0,
+        supported_mouse_modes: 0,
+        current_mouse_mode: 0,
+        agent_connected: 0,
+        agent_tokens: 0,
+        multi_media_time: 0,
+        ram_hint: 0,
+    }
+    .write(&mut init)
+    .unwrap();
+    let messages = encode_data_messages(&[
+        (MainChannelMessage::Init as u16, init.into_inner()),
+        (
+            MainChannelMessage::ChannelsList as u16,
+            0u32.to_le_bytes().to_vec(),
+        ),
+    ]);
+    socket.write_all(&messages).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_retry_connect_after_wrong_password() {
+    use spice_client::ClientBuilder;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_task = tokio::spawn(async move {
+        // The wrong ticket is refused with both OAEP hashes
+        for _ in 0..2 {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let accepted = MockLink {
+                password: "secret".to_string(),
+                ..Default::default()
+            }
+            .serve(&mut socket)
+            .await
+            .unwrap();
+            assert!(!accepted);
+            // The client doesn't keep the refused socket open
+            let mut buf = [0u8; 1];
+            assert_eq!(socket.read(&mut buf).await.unwrap(), 0);
+        }
+
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let accepted = MockLink {
+            password: "secret".to_string(),
+            ..Default::default()
+        }
+        .serve(&mut socket)
+        .await
+        .unwrap();
+        assert!(accepted);
+        serve_main_init(&mut socket, 42).await;
+
+        // Keep the socket open until the client is done
+        hold_open(&mut socket).await.unwrap();
+    });
+
+    let mut client = ClientBuilder::new(&format!("spice://{addr}"))
+        .with_password("wrong".to_string())
+        .build()
+        .unwrap();
+    let err = client.connect().await.unwrap_err();
+    assert!(
+        matches!(
+            err,
+            SpiceError::AuthenticationFailed {
+                reason: spice_client::AuthFailureReason::BadPassword
+            }
+        ),
+        "{err:?}"
+    );
+    assert!(client.server_info().is_none());
+
+    client.set_password("secret".to_string());
+    client.connect().await.unwrap();
+    assert_eq!(client.server_info().unwrap().session_id, Some(42));
+
+    drop(client);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_password_required_without_password() {
+    use spice_client::{ClientBuilder, DisconnectInfo, DisconnectReason};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_task = tokio::spawn(async move {
+        // The empty ticket is refused once; another OAEP hash can't help
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let accepted = MockLink {
+            password: "secret".to_string(),
+            ..Default::default()
+        }
+        .serve(&mut socket)
+        .await
+        .unwrap();
+        assert!(!accepted);
+
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let accepted = MockLink {
+            password: "secret".to_string(),
+            ..Default::default()
+        }
+        .serve(&mut socket)
+        .await
+        .unwrap();
+        assert!(accepted);
+        serve_main_init(&mut socket, 42).await;
+
+        // Keep the socket open until the client is done
+        hold_open(&mut socket).await.unwrap();
+    });
+
+    let mut client = ClientBuilder::new(&format!("spice://{addr}"))
+        .build()
+        .unwrap();
+    let err = client.connect().await.unwrap_err();
+    assert!(matches!(err, SpiceError::PasswordRequired), "{err:?}");
+    let info = DisconnectInfo::from_error("Main channel", &err);
+    assert_eq!(info.reason, DisconnectReason::PasswordRequired);
+    assert!(!info.recoverable);
+
+    // The same client connects once the user has given the password
+    client.set_password("secret".to_string());
+    client.connect().await.unwrap();
+    assert_eq!(client.server_info().unwrap().session_id, Some(42));
+
+    drop(client);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_expired_ticket_reported_on_reconnect() {
+    use spice_client::{AuthFailureReason, ClientBuilder};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_task = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        assert!(MockLink {
+            password: "ticket".to_string(),
+            ..Default::default()
+        }
+        .serve(&mut socket)
+        .await
+        .unwrap());
+        serve_main_init(&mut socket, 1).await;
+        // The client disconnects before linking again
+        hold_open(&mut socket).await.unwrap();
+
+        // The ticket's time limit ran out, so QEMU answers error 7, once
+        // for each OAEP hash
+        for _ in 0..2 {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            assert!(!MockLink {
+                password: "fresh".to_string(),
+                ..Default::default()
+            }
+            .serve(&mut socket)
+            .await
+            .unwrap());
+        }
+
+        let (mut socket, _) = listener.accept().await.unwrap();
+        assert!(MockLink {
+            password: "fresh".to_string(),
+            ..Default::default()
+        }
+        .serve(&mut socket)
+        .await
+        .unwrap());
+        serve_main_init(&mut socket, 2).await;
+        hold_open(&mut socket).await.unwrap();
+    });
+
+    let mut client = ClientBuilder::new(&format!("spice://{addr}"))
+        .with_password("ticket".to_string())
+        .build()
+        .unwrap();
+    client.connect().await.unwrap();
+    client.disconnect();
+
+    let err = client.connect().await.unwrap_err();
+    assert!(
+        matches!(
+            err,
+            SpiceError::AuthenticationFailed {
+                reason: AuthFailureReason::TicketExpired
+            }
+        ),
+        "{err:?}"
+    );
+
+    client.set_password("fresh".to_string());
+    client.connect().await.unwrap();
+    assert_eq!(client.server_info().unwrap().session_id, Some(2));
+
+    drop(client);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_client_oaep_options_apply_to_channels() {
+    use spice_client::{ClientBuilder, OaepHash};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_task = tokio::spawn(async move {
+        let link = MockLink {
+            oaep_hash: OaepHash::Sha256,
+            password: "ticket".to_string(),
+            ..Default::default()
+        };
+        // Without the fallback the SHA-1 ticket is tried only once
+        let (mut socket, _) = listener.accept().await.unwrap();
+        assert!(!link.serve(&mut socket).await.unwrap());
+
+        let (mut socket, _) = listener.accept().await.unwrap();
+        assert!(link.serve(&mut socket).await.unwrap());
+        serve_main_init(&mut socket, 1).await;
+        hold_open(&mut socket).await.unwrap();
+    });
+
+    let mut client = ClientBuilder::new(&format!("spice://{addr}"))
+        .with_password("ticket".to_string())
+        .with_oaep_fallback(false)
+        .build()
+        .unwrap();
+    let err = client.connect().await.unwrap_err();
+    assert!(
+        matches!(err, SpiceError::AuthenticationFailed { .. }),
+        "{err:?}"
+    );
+
+    client.set_oaep_hash(OaepHash::Sha256);
+    client.connect().await.unwrap();
+
+    drop(client);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_unavailable_channel_link_error() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_task = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        serve_link_error(&mut socket, LinkError::ChannelNotAvailable).await;
+    });
+
+    let mut channel =
+        ChannelConnection::new(&addr.ip().to_string(), addr.port(), ChannelType::Cursor, 0)
+            .await
+            .unwrap();
+    channel.set_connection_id(1);
+    let result = channel.handshake().await;
+    assert!(matches!(
+        result,
+        Err(SpiceError::ChannelNotAvailable(ChannelType::Cursor))
+    ));
+
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_security_link_errors() {
+    for error in [LinkError::NeedSecured, LinkError::NeedUnsecured] {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server_task = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            serve_link_error(&mut socket, error).await;
+        });
+
+        let mut channel =
+            ChannelConnection::new(&addr.ip().to_string(), addr.port(), ChannelType::Main, 0)
+                .await
+                .unwrap();
+        let result = channel.handshake().await;
+        match error {
+            LinkError::NeedSecured => assert!(matches!(
+                result,
+                Err(SpiceError::NeedSecured(ChannelType::Main))
+            )),
+            _ => assert!(matches!(
+                result,
+                Err(SpiceError::NeedUnsecured(ChannelType::Main))
+            )),
+        }
+
+        server_task.await.unwrap();
+    }
+}
+
+/// Frame `(msg_type, body)` pairs with 6-byte mini headers
+fn encode_mini_messages(messages: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    use binrw::BinWrite;
+
+    let mut out = std::io::Cursor::new(Vec::new());
+    for (msg_type, body) in messages {
+        SpiceMiniDataHeader {
+            msg_type: *msg_type,
+            msg_size: body.len() as u32,
+        }
+        .write(&mut out)
+        .unwrap();
+        std::io::Write::write_all(&mut out, body).unwrap();
+    }
+    out.into_inner()
+}
+
+/// Read a client link message and answer it with `common_caps`, then take
+/// the ticket, expecting an auth mechanism first if the client advertised
+/// auth selection. Returns the common and channel capabilities the client
+/// advertised.
+async fn serve_link_with_common_caps(
+    socket: &mut tokio::net::TcpStream,
+    common_caps: &[u32],
+) -> (CapabilitySet, CapabilitySet) {
+    use binrw::{BinRead, BinWrite};
+
+    let mut header_buf = [0u8; 16];
+    socket.read_exact(&mut header_buf).await.unwrap();
+    let size = u32::from_le_bytes(header_buf[12..16].try_into().unwrap());
+    let mut link_msg_buf = vec![0u8; size as usize];
+    socket.read_exact(&mut link_msg_buf).await.unwrap();
+
+    let link_mess = SpiceLinkMess::read(&mut std::io::Cursor::new(&link_msg_buf)).unwrap();
+    let words: Vec<u32> = link_msg_buf[link_mess.caps_offset as usize..]
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+    let (common_words, channel_words) = words.split_at(link_mess.num_common_caps as usize);
+    let advertised_common = CapabilitySet::from_words(common_words);
+    let advertised_channel = CapabilitySet::from_words(channel_words);
+
+    let reply = SpiceLinkReply {
+        magic: SPICE_MAGIC,
+        major_version: SPICE_VERSION_MAJOR,
+        minor_version: SPICE_VERSION_MINOR,
+        size: 178 + common_caps.len() as u32 * 4,
+    };
+    let reply_data = SpiceLinkReplyData {
+        error: 0,
+        pub_key: encode_mock_public_key(mock_key(), MockKeyFormat::Spki),
+        num_common_caps: common_caps.len() as u32,
+        num_channel_caps: 0,
+        caps_offset: 178,
+    };
+    let mut reply_cursor = std::io::Cursor::new(Vec::new());
+    reply.write(&mut reply_cursor).unwrap();
+    reply_data.write(&mut reply_cursor).unwrap();
+    for cap in common_caps {
+        reply_cursor.get_mut().extend_from_slice(&cap.to_le_bytes());
+    }
+    socket.write_all(&reply_cursor.into_inner()).await.unwrap();
+
+    if advertised_common.contains(SPICE_COMMON_CAP_PROTOCOL_AUTH_SELECTION) {
+        let mut mechanism = [0u8; 4];
+        socket.read_exact(&mut mechanism).await.unwrap();
+        assert_eq!(u32::from_le_bytes(mechanism), SPICE_COMMON_CAP_AUTH_SPICE);
+    }
+    let mut encrypted = [0u8; 128];
+    socket.read_exact(&mut encrypted).await.unwrap();
+    socket.write_all(&0u32.to_le_bytes()).await.unwrap();
+
+    (advertised_common, advertised_channel)
+}
+
+#[tokio::test]
+async fn test_builder_advertises_custom_capabilities() {
+    use binrw::BinWrite;
+    use spice_client::ClientBuilder;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_task = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let (common, channel) = serve_link_with_common_caps(
+            &mut socket,
+            &[1 << SPICE_COMMON_CAP_PROTOCOL_AUTH_SELECTION | 1 << SPICE_COMMON_CAP_MINI_HEADER],
+        )
+        .await;
+
+        let mut init = std::io::Cursor::new(Vec::new());
+        SpiceMsgMainInit {
+            session_id: 42,
+            display_channels_hint: 0,
+            supported_mouse_modes: 0,
+            current_mouse_mode: 0,
+            agent_connected: 0,
+            agent_tokens: 0,
+            multi_media_time: 0,
+            ram_hint: 0,
+        }
+        .write(&mut init)
+        .unwrap();
+        // Both sides advertised the mini header, so frame with it
+        let messages = encode_mini_messages(&[
+            (MainChannelMessage::Init as u16, init.into_inner()),
+            (
+                MainChannelMessage::ChannelsList as u16,
+                0u32.to_le_bytes().to_vec(),
+            ),
+        ]);
+        socket.write_all(&messages).await.unwrap();
+
+        // Keep the socket open until the client is done
+        hold_open(&mut socket).await.unwrap();
+        (common, channel)
+    });
+
+    let mut client = ClientBuilder::new(&format!("spice://{addr}"))
+        .advertise_common_caps(&[
+            SPICE_COMMON_CAP_PROTOCOL_AUTH_SELECTION,
+            SPICE_COMMON_CAP_AUTH_SPICE,
+            SPICE_COMMON_CAP_MINI_HEADER,
+        ])
+        .advertise_channel_caps(ChannelType::Main, &[SPICE_MAIN_CAP_AGENT_CONNECTED_TOKENS])
+        .build()
+        .unwrap();
+    client.connect().await.unwrap();
+    assert_eq!(client.server_info().unwrap().session_id, Some(42));
+
+    drop(client);
+    let (common, channel) = server_task.await.unwrap();
+    assert_eq!(
+        common.iter().collect::<Vec<_>>(),
+        vec![
+            SPICE_COMMON_CAP_PROTOCOL_AUTH_SELECTION,
+            SPICE_COMMON_CAP_AUTH_SPICE,
+            SPICE_COMMON_CAP_MINI_HEADER
+        ]
+    );
+    // The override replaces the default NAME_AND_UUID capability
+    assert_eq!(
+        channel.iter().collect::<Vec<_>>(),
+        vec![SPICE_MAIN_CAP_AGENT_CONNECTED_TOKENS]
+    );
+}
+
+#[tokio::test]
+async fn test_mini_header_requires_client_capability() {
+    for advertise in [false, true] {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server_task = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            serve_link_with_common_caps(&mut socket, &[1 << SPICE_COMMON_CAP_MINI_HEADER]).await;
+
+            let header_size = if advertise { 6 } else { 18 };
+            let mut header = vec![0u8; header_size];
+            socket.read_exact(&mut header).await.unwrap();
+            let msg_type = if advertise {
+                u16::from_le_bytes([header[0], header[1]])
+            } else {
+                u16::from_le_bytes([header[8], header[9]])
+            };
+            assert_eq!(msg_type, SPICE_MSGC_MAIN_ATTACH_CHANNELS);
+
+            let reply = if advertise {
+                encode_mini_messages(&[(SPICE_MSG_PING, vec![0; 12])])
+            } else {
+                encode_data_messages(&[(SPICE_MSG_PING, vec![0; 12])])
+            };
+            socket.write_all(&reply).await.unwrap();
+        });
+
+        let mut connection =
+            ChannelConnection::new(&addr.ip().to_string(), addr.port(), ChannelType::Main, 0)
+                .await
+                .unwrap();
+        if advertise {
+            let mut caps = AdvertisedCapabilities::default();
+            caps.set_common(&[SPICE_COMMON_CAP_MINI_HEADER]);
+            connection.set_advertised_caps(caps);
+        }
+        connection.handshake().await.unwrap();
+        assert_eq!(connection.uses_mini_header(), advertise);
+
+        connection
+            .send_message(SPICE_MSGC_MAIN_ATTACH_CHANNELS, &[])
+            .await
+            .unwrap();
+        let (header, data) = connection.read_message().await.unwrap();
+        assert_eq!(header.msg_type, SPICE_MSG_PING);
+        assert_eq!(data.len(), 12);
+
+        server_task.await.unwrap();
+    }
+}
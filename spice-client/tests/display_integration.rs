@@ -66,7 +66,7 @@ async fn test_display_mode_message() {
     if let Some(surf) = surface {
         assert_eq!(surf.width, 1024);
         assert_eq!(surf.height, 768);
-        assert_eq!(surf.format, 32);
+        assert_eq!(surf.format, SurfaceFormat::Xrgb32);
     }
 
     server_task.await.unwrap();
@@ -161,7 +161,7 @@ async fn test_display_surface_create_message() {
             0x00, 0x00, 0x00, 0x00, // surface_id = 0
             0x80, 0x07, 0x00, 0x00, // width = 1920
             0x38, 0x04, 0x00, 0x00, // height = 1080
-            0x20, 0x00, 0x00, 0x00, // format = 32 (ARGB)
+            0x20, 0x00, 0x00, 0x00, // format = 32 (xRGB)
             0x00, 0x00, 0x00, 0x00, // flags = 0
             0x00, 0x00, 0x00, 0x00, // reserved
         ];
@@ -183,7 +183,7 @@ async fn test_display_surface_create_message() {
     if let Some(surf) = surface {
         assert_eq!(surf.width, 1920);
         assert_eq!(surf.height, 1080);
-        assert_eq!(surf.format, 32);
+        assert_eq!(surf.format, SurfaceFormat::Xrgb32);
     }
 
     server_task.await.unwrap();
@@ -0,0 +1,80 @@
+use async_trait::async_trait;
+use spice_client::channels::{ConnectionFactory, Stream};
+use spice_client::protocol::ChannelType;
+use spice_client::test_utils::serve_mock_channel;
+use spice_client::{Result, SpiceClient};
+use std::sync::{Arc, Mutex};
+use tokio::time::{timeout, Duration};
+
+/// Hands out in-memory streams, each served by a mock server announcing a
+/// display channel
+struct DuplexFactory {
+    opened: Mutex<Vec<(ChannelType, u8)>>,
+    served: Arc<Mutex<Vec<u8>>>,
+}
+
+#[async_trait]
+impl ConnectionFactory for DuplexFactory {
+    async fn connect(&self, channel_type: ChannelType, channel_id: u8) -> Result<Stream> {
+        self.opened.lock().unwrap().push((channel_type, channel_id));
+        let (client_end, server_end) = tokio::io::duplex(64 * 1024);
+        let served = self.served.clone();
+        tokio::spawn(async move {
+            let channels = [(ChannelType::Display, 0)];
+            let linked = serve_mock_channel(server_end, "secret", &channels).await;
+            if let Ok(channel_type) = linked {
+                served.lock().unwrap().push(channel_type);
+            }
+        });
+        Ok(Stream::custom(client_end))
+    }
+}
+
+#[tokio::test]
+async fn test_factory_opens_a_stream_per_channel() {
+    let served = Arc::new(Mutex::new(Vec::new()));
+    let factory = Arc::new(DuplexFactory {
+        opened: Mutex::new(Vec::new()),
+        served: served.clone(),
+    });
+
+    let mut client = SpiceClient::with_connection_factory(factory.clone());
+    client.set_password("secret".to_string());
+    timeout(Duration::from_secs(10), client.connect())
+        .await
+        .expect("connect timed out")
+        .unwrap();
+
+    assert_eq!(
+        *factory.opened.lock().unwrap(),
+        vec![(ChannelType::Main, 0), (ChannelType::Display, 0)]
+    );
+
+    // Each channel's server end only finishes once its stream is dropped
+    client.disconnect();
+    drop(client);
+    timeout(Duration::from_secs(5), async {
+        while served.lock().unwrap().len() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("server ends were not released");
+    let mut served = served.lock().unwrap().clone();
+    served.sort();
+    assert_eq!(
+        served,
+        vec![ChannelType::Main as u8, ChannelType::Display as u8]
+    );
+}
+
+#[tokio::test]
+async fn test_closure_factory_reports_open_failures() {
+    let factory = |_: ChannelType, _: u8| async {
+        Err::<tokio::io::DuplexStream, _>(std::io::Error::other("chardev closed"))
+    };
+
+    let mut client = SpiceClient::with_connection_factory(Arc::new(factory));
+    let err = client.connect().await.unwrap_err();
+    assert!(err.to_string().contains("chardev closed"), "{err}");
+}
@@ -45,7 +45,8 @@ async fn test_cursor_set_message() {
 
     server
         .send_cursor_message(SPICE_MSG_CURSOR_SET, cursor_data)
-        .await;
+        .await
+        .unwrap();
 
     // Process message
     tokio::time::sleep(Duration::from_millis(100)).await;
@@ -82,7 +83,8 @@ async fn test_cursor_movement() {
 
         server
             .send_cursor_message(SPICE_MSG_CURSOR_MOVE, move_data)
-            .await;
+            .await
+            .unwrap();
         tokio::time::sleep(Duration::from_millis(50)).await;
 
         // Note: We can't verify position directly without processing messages
@@ -106,7 +108,8 @@ async fn test_cursor_visibility() {
     let empty_data: Vec<u8> = Vec::new();
     server
         .send_cursor_message(SPICE_MSG_CURSOR_HIDE, empty_data)
-        .await;
+        .await
+        .unwrap();
 
     // Wait for processing
     tokio::time::sleep(Duration::from_millis(100)).await;
@@ -136,14 +139,16 @@ async fn test_cursor_cache_invalidation() {
 
         server
             .send_cursor_message(SPICE_MSG_CURSOR_SET, cursor_data)
-            .await;
+            .await
+            .unwrap();
     }
 
     // Send invalidate all message
     let empty_data: Vec<u8> = Vec::new();
     server
         .send_cursor_message(SPICE_MSG_CURSOR_INVAL_ALL, empty_data)
-        .await;
+        .await
+        .unwrap();
 
     // Wait for processing
     tokio::time::sleep(Duration::from_millis(100)).await;
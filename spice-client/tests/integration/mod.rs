@@ -2,12 +2,14 @@ use spice_client::{SpiceClient, SpiceClientShared, SpiceError};
 use std::time::Duration;
 use tokio::time::timeout;
 
+pub mod connection_factory_test;
 pub mod cursor_test;
 pub mod harness;
 pub mod inputs_test;
 pub mod multi_display_framerate_test;
 pub mod multi_display_test;
 pub mod qemu_integration_test;
+pub mod real_server_test;
 
 #[cfg(test)]
 mod connection_tests {
@@ -112,7 +114,7 @@ mod display_tests {
         // Check for display surface
         if let Some(surface) = client.get_display_surface(0).await {
             println!(
-                "Got display surface: {}x{} format: {}",
+                "Got display surface: {}x{} format: {:?}",
                 surface.width, surface.height, surface.format
             );
             assert!(surface.width > 0);
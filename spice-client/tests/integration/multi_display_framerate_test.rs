@@ -76,14 +76,16 @@ async fn test_independent_display_frame_rates() {
             SPICE_MSG_DISPLAY_SURFACE_CREATE,
             bincode::serialize(&surface1).unwrap(),
         )
-        .await;
+        .await
+        .unwrap();
     server
         .send_display_message_to_channel(
             1,
             SPICE_MSG_DISPLAY_SURFACE_CREATE,
             bincode::serialize(&surface2).unwrap(),
         )
-        .await;
+        .await
+        .unwrap();
 
     // Wait for surfaces to be created
     tokio::time::sleep(Duration::from_millis(100)).await;
@@ -117,7 +119,8 @@ async fn test_independent_display_frame_rates() {
                     DisplayChannelMessage::StreamData as u16,
                     bincode::serialize(&stream_data).unwrap(),
                 )
-                .await;
+                .await
+                .unwrap();
 
             monitor1_clone.increment();
         }
@@ -145,7 +148,8 @@ async fn test_independent_display_frame_rates() {
                     DisplayChannelMessage::StreamData as u16,
                     bincode::serialize(&stream_data).unwrap(),
                 )
-                .await;
+                .await
+                .unwrap();
 
             monitor2_clone.increment();
         }
@@ -197,7 +201,8 @@ async fn test_display_switching_during_video() {
                 SPICE_MSG_DISPLAY_SURFACE_CREATE,
                 bincode::serialize(&surface).unwrap(),
             )
-            .await;
+            .await
+            .unwrap();
     }
 
     // Start video streams on surfaces 0 and 1
@@ -229,13 +234,15 @@ async fn test_display_switching_during_video() {
             DisplayChannelMessage::StreamCreate as u16,
             bincode::serialize(&stream0).unwrap(),
         )
-        .await;
+        .await
+        .unwrap();
     server
         .send_display_message(
             DisplayChannelMessage::StreamCreate as u16,
             bincode::serialize(&stream1).unwrap(),
         )
-        .await;
+        .await
+        .unwrap();
 
     // Send video data to both streams
     for i in 0..10 {
@@ -258,13 +265,15 @@ async fn test_display_switching_during_video() {
                 DisplayChannelMessage::StreamData as u16,
                 bincode::serialize(&data0).unwrap(),
             )
-            .await;
+            .await
+            .unwrap();
         server
             .send_display_message(
                 DisplayChannelMessage::StreamData as u16,
                 bincode::serialize(&data1).unwrap(),
             )
-            .await;
+            .await
+            .unwrap();
 
         tokio::time::sleep(Duration::from_millis(33)).await;
     }
@@ -300,7 +309,8 @@ async fn test_display_switching_during_video() {
             SPICE_MSG_DISPLAY_MONITORS_CONFIG,
             bincode::serialize(&monitors_config).unwrap(),
         )
-        .await;
+        .await
+        .unwrap();
 
     // Continue streaming after switch
     for i in 10..20 {
@@ -323,13 +333,15 @@ async fn test_display_switching_during_video() {
                 DisplayChannelMessage::StreamData as u16,
                 bincode::serialize(&data0).unwrap(),
             )
-            .await;
+            .await
+            .unwrap();
         server
             .send_display_message(
                 DisplayChannelMessage::StreamData as u16,
                 bincode::serialize(&data1).unwrap(),
             )
-            .await;
+            .await
+            .unwrap();
 
         tokio::time::sleep(Duration::from_millis(33)).await;
     }
@@ -369,7 +381,8 @@ async fn test_multi_display_memory_management() {
                 SPICE_MSG_DISPLAY_SURFACE_CREATE,
                 bincode::serialize(&surface).unwrap(),
             )
-            .await;
+            .await
+            .unwrap();
     }
 
     tokio::time::sleep(Duration::from_millis(100)).await;
@@ -402,7 +415,8 @@ async fn test_multi_display_memory_management() {
                 DisplayChannelMessage::StreamCreate as u16,
                 bincode::serialize(&stream).unwrap(),
             )
-            .await;
+            .await
+            .unwrap();
     }
 
     // Stream video data to all displays
@@ -420,7 +434,8 @@ async fn test_multi_display_memory_management() {
                     DisplayChannelMessage::StreamData as u16,
                     bincode::serialize(&data).unwrap(),
                 )
-                .await;
+                .await
+                .unwrap();
         }
 
         tokio::time::sleep(Duration::from_millis(10)).await;
@@ -436,7 +451,8 @@ async fn test_multi_display_memory_management() {
                 DisplayChannelMessage::StreamDestroy as u16,
                 bincode::serialize(&destroy).unwrap(),
             )
-            .await;
+            .await
+            .unwrap();
     }
 
     // Destroy all surfaces
@@ -447,7 +463,8 @@ async fn test_multi_display_memory_management() {
                 SPICE_MSG_DISPLAY_SURFACE_DESTROY,
                 bincode::serialize(&destroy).unwrap(),
             )
-            .await;
+            .await
+            .unwrap();
     }
 
     tokio::time::sleep(Duration::from_millis(100)).await;
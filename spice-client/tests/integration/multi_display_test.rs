@@ -38,13 +38,15 @@ async fn test_multi_display_support() {
             SPICE_MSG_DISPLAY_SURFACE_CREATE,
             bincode::serialize(&surface1).unwrap(),
         )
-        .await;
+        .await
+        .unwrap();
     server
         .send_display_message(
             SPICE_MSG_DISPLAY_SURFACE_CREATE,
             bincode::serialize(&surface2).unwrap(),
         )
-        .await;
+        .await
+        .unwrap();
 
     // Wait for processing
     tokio::time::sleep(Duration::from_millis(100)).await;
@@ -103,7 +105,8 @@ async fn test_monitors_config() {
             SPICE_MSG_DISPLAY_MONITORS_CONFIG,
             bincode::serialize(&monitors_config).unwrap(),
         )
-        .await;
+        .await
+        .unwrap();
 
     // Wait for processing
     tokio::time::sleep(Duration::from_millis(100)).await;
@@ -154,13 +157,15 @@ async fn test_multi_display_video_streams() {
             SPICE_MSG_DISPLAY_SURFACE_CREATE,
             bincode::serialize(&surface1).unwrap(),
         )
-        .await;
+        .await
+        .unwrap();
     server
         .send_display_message(
             SPICE_MSG_DISPLAY_SURFACE_CREATE,
             bincode::serialize(&surface2).unwrap(),
         )
-        .await;
+        .await
+        .unwrap();
 
     // Create video streams on different surfaces
     let stream1 = SpiceStreamCreate {
@@ -210,13 +215,15 @@ async fn test_multi_display_video_streams() {
             DisplayChannelMessage::StreamCreate as u16,
             bincode::serialize(&stream1).unwrap(),
         )
-        .await;
+        .await
+        .unwrap();
     server
         .send_display_message(
             DisplayChannelMessage::StreamCreate as u16,
             bincode::serialize(&stream2).unwrap(),
         )
-        .await;
+        .await
+        .unwrap();
 
     // Wait for processing
     tokio::time::sleep(Duration::from_millis(100)).await;
@@ -248,7 +255,8 @@ async fn test_surface_cleanup_on_reset() {
                 SPICE_MSG_DISPLAY_SURFACE_CREATE,
                 bincode::serialize(&surface).unwrap(),
             )
-            .await;
+            .await
+            .unwrap();
     }
 
     // Wait for processing
@@ -262,7 +270,8 @@ async fn test_surface_cleanup_on_reset() {
     // Send reset message
     server
         .send_display_message(DisplayChannelMessage::Reset as u16, vec![])
-        .await;
+        .await
+        .unwrap();
 
     // Wait for processing
     tokio::time::sleep(Duration::from_millis(100)).await;
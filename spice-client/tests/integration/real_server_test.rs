@@ -0,0 +1,130 @@
+//! Protocol checks against a real SPICE server: QEMU in docker, started by
+//! [`DockerSpiceServer`]. The mock server only speaks the protocol the way
+//! this crate understands it, so these catch messages we encode or parse
+//! differently from spice-server.
+//!
+//! They are ignored by default; see `tests/README.md` for the setup.
+
+use spice_client::test_utils::DockerSpiceServer;
+use spice_client::{ChannelType, Direction, InputsChannelMessage, SpiceClientShared};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Long enough for the first run to build the image
+const READY_TIMEOUT: Duration = Duration::from_secs(600);
+const EVENT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Size of the data header when the mini header isn't negotiated
+const DATA_HEADER_SIZE: usize = 18;
+
+async fn connect(server: &DockerSpiceServer) -> SpiceClientShared {
+    let client = SpiceClientShared::new(server.host().to_string(), server.port());
+    tokio::time::timeout(EVENT_TIMEOUT, client.connect())
+        .await
+        .expect("connecting to the real server timed out")
+        .expect("handshake with the real server failed");
+    client
+}
+
+/// Poll `check` until it returns true, failing the test after `EVENT_TIMEOUT`
+async fn wait_until<F, Fut>(what: &str, mut check: F)
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = bool>,
+{
+    let waited = tokio::time::timeout(EVENT_TIMEOUT, async {
+        while !check().await {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await;
+    assert!(waited.is_ok(), "timed out waiting for {what}");
+}
+
+#[tokio::test]
+#[ignore = "needs docker and the spice-qemu-server image; see tests/README.md"]
+async fn test_real_server_handshake() {
+    let server = DockerSpiceServer::start(READY_TIMEOUT).await.unwrap();
+    let client = connect(&server).await;
+
+    let info = client.server_info().await.expect("no main channel init");
+    assert!(info.session_id.is_some());
+    let channels = client.connected_channels().await;
+    assert!(channels.contains(&(ChannelType::Display, 0)));
+    assert!(channels.contains(&(ChannelType::Inputs, 0)));
+
+    client.disconnect().await;
+}
+
+#[tokio::test]
+#[ignore = "needs docker and the spice-qemu-server image; see tests/README.md"]
+async fn test_real_server_display_mode_and_frame() {
+    let server = DockerSpiceServer::start(READY_TIMEOUT).await.unwrap();
+    let client = connect(&server).await;
+
+    // A frame counts once the guest has drawn something on it
+    let drawn_frames = Arc::new(AtomicU32::new(0));
+    let counter = drawn_frames.clone();
+    client
+        .set_frame_callback(move |_, _, surface, _| {
+            if surface.data.iter().any(|&byte| byte != 0) {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        })
+        .await;
+    client.start_event_loop().await.unwrap();
+
+    let client = &client;
+    wait_until("a display mode", || async move {
+        client
+            .primary_surface()
+            .await
+            .is_some_and(|surface| surface.width > 0 && surface.height > 0)
+    })
+    .await;
+    let drawn_frames = &drawn_frames;
+    wait_until("a decoded frame", || async move {
+        drawn_frames.load(Ordering::SeqCst) > 0
+    })
+    .await;
+
+    client.disconnect().await;
+}
+
+#[tokio::test]
+#[ignore = "needs docker and the spice-qemu-server image; see tests/README.md"]
+async fn test_real_server_acknowledges_mouse_motion() {
+    let server = DockerSpiceServer::start(READY_TIMEOUT).await.unwrap();
+    let client = connect(&server).await;
+
+    // spice-server acks every few motion messages, so an ack means it
+    // parsed ours
+    let acks = Arc::new(AtomicU32::new(0));
+    let counter = acks.clone();
+    client
+        .set_trace_hook(move |channel_type, _, direction, bytes| {
+            let is_header = bytes.len() == DATA_HEADER_SIZE;
+            if channel_type == ChannelType::Inputs && direction == Direction::Received && is_header
+            {
+                let msg_type = u16::from_le_bytes([bytes[8], bytes[9]]);
+                if msg_type == InputsChannelMessage::MouseMotionAck as u16 {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        })
+        .await;
+    client.start_event_loop().await.unwrap();
+
+    for step in 0..8 {
+        client.send_mouse_motion(0, step % 2, 1).await.unwrap();
+    }
+    client.flush_input(0).await.unwrap();
+
+    let acks = &acks;
+    wait_until("a mouse motion ack", || async move {
+        acks.load(Ordering::SeqCst) > 0
+    })
+    .await;
+
+    client.disconnect().await;
+}
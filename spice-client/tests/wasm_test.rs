@@ -18,12 +18,8 @@ async fn test_wasm_canvas_rendering() {
     let mut display_manager = WasmDisplayManager::new();
 
     // Simulate surface creation
-    let surface = DisplaySurface {
-        width: 800,
-        height: 600,
-        format: 32,
-        data: vec![255; 800 * 600 * 4], // White surface
-    };
+    let mut surface = DisplaySurface::new(800, 600, SurfaceFormat::Xrgb32);
+    surface.data.fill(255); // White surface
 
     // This would normally be done by the channel's message handler
     // For testing, we'll manually insert a surface
@@ -50,6 +46,7 @@ async fn test_wasm_video_stream() {
             right: 740,
             bottom: 580,
         },
+        clip: None,
     };
 
     let result = display_manager.handle_stream_create(&stream_info).await;
@@ -82,6 +79,7 @@ async fn test_wasm_multi_display() {
                 right: (i + 1) as i32 * 640,
                 bottom: 480,
             },
+            clip: None,
         };
 
         let result = display_manager.handle_stream_create(&stream_info).await;
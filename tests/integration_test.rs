@@ -1,5 +1,7 @@
 use quickemu_manager::models::{VM, VMId, VMStatus, VMConfig, DisplayProtocol, VMTemplate};
 use quickemu_manager::services::{VMManager, ConfigParser};
+use quickemu_manager::services::vm_manager::StopMode;
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use tempfile::TempDir;
 use std::fs;
@@ -140,6 +142,19 @@ async fn test_vm_lifecycle_simulation() {
             disk_size: None,
             display: DisplayProtocol::Spice { port: 5930 },
             ssh_port: None,
+            extra_args: Vec::new(),
+            autostart: false,
+            cpu_quota_percent: None,
+            mem_max: None,
+            spice_socket: false,
+            spice_password: None,
+            tags: Vec::new(),
+            firmware: None,
+            secure_boot: false,
+            boot_order: Vec::new(),
+            pre_start: None,
+            post_stop: None,
+            env: BTreeMap::new(),
             raw_config: "guest_os=\"test\"".to_string(),
         },
         cpu_cores: 2,
@@ -155,7 +170,7 @@ async fn test_vm_lifecycle_simulation() {
     assert_eq!(status, VMStatus::Stopped);
     
     // Test attempting to stop a non-running VM
-    let result = vm_manager.stop_vm(&vm.id).await;
+    let result = vm_manager.stop_vm(&vm.id, StopMode::Force).await;
     assert!(result.is_err());
 }
 